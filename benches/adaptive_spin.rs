@@ -0,0 +1,70 @@
+//! Microbenchmarks for adaptive spinning: `cargo bench --features adaptive`.
+//!
+//! Each case runs with a spin budget of 0, which parks right away like the
+//! plain blocking path, and with the default budget.
+
+use std::{hint, time::Instant};
+
+use deadlock_proof::{
+    adaptive::DEFAULT_SPIN_BUDGET, concurrent, unique_type, DeadlockProofMutex, LockOutcome,
+    OuterMutexPermission,
+};
+
+const UNCONTENDED_ITERS: u32 = 2_000_000;
+const THREADS: u32 = 4;
+const CONTENDED_ITERS: u32 = 200_000;
+/// Roughly a hundred nanoseconds of work under the lock.
+const HOLD_SPINS: u32 = 50;
+
+fn main() {
+    let mut permission = OuterMutexPermission::get();
+    for budget in [0, DEFAULT_SPIN_BUDGET] {
+        let uncontended;
+        (permission, uncontended) = uncontended_lock(permission, budget);
+        let contended = short_hold_contended(budget);
+        println!(
+            "spin budget {budget:>4}: uncontended {uncontended:>6.1} ns/lock, \
+             short-hold contended {contended:>6.1} ns/lock"
+        );
+    }
+}
+
+fn uncontended_lock(
+    mut permission: OuterMutexPermission,
+    budget: u32,
+) -> (OuterMutexPermission, f64) {
+    let mutex = DeadlockProofMutex::new(0u64, unique_type!());
+    mutex.set_spin_budget(budget);
+    let started = Instant::now();
+    for _ in 0..UNCONTENDED_ITERS {
+        let mut guard = mutex.lock(permission).guard();
+        *guard += 1;
+        permission = guard.unlock();
+    }
+    let nanos = started.elapsed().as_nanos() as f64 / f64::from(UNCONTENDED_ITERS);
+    (permission, nanos)
+}
+
+fn short_hold_contended(budget: u32) -> f64 {
+    let mutex = DeadlockProofMutex::new(0u64, unique_type!());
+    mutex.set_spin_budget(budget);
+    let workers = (0..THREADS)
+        .map(|_| -> concurrent::Worker<'_> {
+            let mutex = &mutex;
+            Box::new(move |mut permission| {
+                for _ in 0..CONTENDED_ITERS {
+                    let mut guard = mutex.lock(permission).guard();
+                    for _ in 0..HOLD_SPINS {
+                        hint::spin_loop();
+                    }
+                    *guard += 1;
+                    permission = guard.unlock();
+                }
+                permission
+            })
+        })
+        .collect();
+    let started = Instant::now();
+    concurrent::run_all(workers).unwrap();
+    started.elapsed().as_nanos() as f64 / f64::from(THREADS * CONTENDED_ITERS)
+}
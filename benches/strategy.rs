@@ -0,0 +1,37 @@
+//! Nested against sequential locking over a grid of workloads:
+//! `cargo bench --bench strategy --features strategy-bench`.
+//!
+//! Every row keeps the critical sections short and varies the work between
+//! them and the number of threads, which is where the two strategies part
+//! ways: holding the first mutex through long work in between serializes
+//! the threads, while releasing it costs a second trip through its lock.
+
+use std::time::Duration;
+
+use deadlock_proof::strategy_bench::{self, Workload};
+
+const OPERATIONS: u64 = 2_000;
+
+fn main() {
+    for threads in [1, 2, 4] {
+        for between in [
+            Duration::ZERO,
+            Duration::from_micros(2),
+            Duration::from_micros(20),
+        ] {
+            let report = strategy_bench::run(&Workload {
+                threads,
+                operations: OPERATIONS,
+                first_hold: Duration::from_micros(1),
+                between,
+                second_hold: Duration::from_micros(1),
+            });
+            print!("{report}");
+            println!(
+                "  faster: {:?}, lower p99: {:?}",
+                report.faster(),
+                report.lower_tail()
+            );
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! A TCP echo server whose per-connection state lives in the lock hierarchy
+//! of a [`NetworkStack`].
+//!
+//! Each accepted connection is registered in a
+//! [`ConnectionRegistry`](deadlock_proof::net_demo::ConnectionRegistry) and
+//! served on its own thread. Every chunk echoed back is published to the IP
+//! and device layers and to the connection's stats, in hierarchy order.
+//! Shutting down cancels the connection threads' walks, and then waits for
+//! every thread to close its connection.
+//!
+//! `cargo run --example echo_server` serves on a loopback port, talks to
+//! itself through a client, shuts down and prints what the stack counted.
+
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use deadlock_proof::{
+    concurrent::spawn_with_permission, net_demo::ConnectionRegistry, NetworkStack,
+    OuterMutexPermission,
+};
+
+/// How long accepts and reads block before looking for a shutdown.
+const POLL: Duration = Duration::from_millis(10);
+
+/// A running server.
+pub struct Server {
+    pub addr: SocketAddr,
+    pub stack: Arc<NetworkStack>,
+    pub registry: Arc<ConnectionRegistry>,
+    acceptor: JoinHandle<()>,
+}
+
+/// Starts serving on `listener`.
+pub fn serve(listener: TcpListener) -> io::Result<Server> {
+    listener.set_nonblocking(true)?;
+    let addr = listener.local_addr()?;
+    let stack = Arc::new(NetworkStack::new());
+    let registry = Arc::new(ConnectionRegistry::new());
+    let acceptor = {
+        let (stack, registry) = (stack.clone(), registry.clone());
+        spawn_with_permission(move |permission| accept_loop(listener, stack, registry, permission))
+    };
+    Ok(Server {
+        addr,
+        stack,
+        registry,
+        acceptor,
+    })
+}
+
+impl Server {
+    /// Stops accepting, cancels the connection threads and waits for all of
+    /// them to close their connections.
+    pub fn shut_down(self) -> (Arc<NetworkStack>, Arc<ConnectionRegistry>) {
+        self.registry.shut_down();
+        self.acceptor.join().expect("the accept loop panicked");
+        (self.stack, self.registry)
+    }
+}
+
+fn accept_loop(
+    listener: TcpListener,
+    stack: Arc<NetworkStack>,
+    registry: Arc<ConnectionRegistry>,
+    mut permission: OuterMutexPermission,
+) -> (OuterMutexPermission, ()) {
+    let mut connections = Vec::new();
+    while !registry.is_shut_down() {
+        let (stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL);
+                continue;
+            }
+            Err(error) => {
+                eprintln!("accept failed: {error}");
+                break;
+            }
+        };
+        let opened;
+        (permission, opened) = registry.open(&stack, permission, peer);
+        if opened.is_err() {
+            break;
+        }
+        let (stack, registry) = (stack.clone(), registry.clone());
+        connections.push(spawn_with_permission(move |permission| {
+            serve_connection(stream, peer, &stack, &registry, permission)
+        }));
+    }
+    for connection in connections {
+        connection.join().expect("a connection thread panicked");
+    }
+    (permission, ())
+}
+
+/// Echoes what `peer` sends until it hangs up or the server shuts down.
+fn serve_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    stack: &NetworkStack,
+    registry: &ConnectionRegistry,
+    mut permission: OuterMutexPermission,
+) -> (OuterMutexPermission, ()) {
+    let mut buffer = [0; 1024];
+    let ready = stream
+        .set_nonblocking(false)
+        .and_then(|()| stream.set_read_timeout(Some(POLL)));
+    while ready.is_ok() {
+        let read = match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                if registry.is_shut_down() {
+                    break;
+                }
+                continue;
+            }
+            Err(_) => break,
+        };
+        if stream.write_all(&buffer[..read]).is_err() {
+            break;
+        }
+        let recorded;
+        (permission, recorded) = registry.record(stack, permission, peer, read);
+        if recorded.is_err() {
+            break;
+        }
+    }
+    let (permission, _stats) = registry.close(stack, permission, peer);
+    (permission, ())
+}
+
+fn main() -> io::Result<()> {
+    let server = serve(TcpListener::bind("127.0.0.1:0")?)?;
+    println!("echo server listening on {}", server.addr);
+
+    let mut client = TcpStream::connect(server.addr)?;
+    for message in ["hello", "deadlock-free", "world"] {
+        client.write_all(message.as_bytes())?;
+        let mut echoed = vec![0; message.len()];
+        client.read_exact(&mut echoed)?;
+        println!("echoed {:?}", String::from_utf8_lossy(&echoed));
+    }
+    drop(client);
+
+    let (stack, registry) = server.shut_down();
+    let (_permission, views) = stack.views(OuterMutexPermission::get());
+    println!(
+        "{} packets, {} bytes, {} connections still open",
+        views.ip.packets_processed,
+        views.device.bytes_transmitted,
+        registry.len()
+    );
+    Ok(())
+}
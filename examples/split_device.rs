@@ -0,0 +1,89 @@
+//! The device layer's state as a [`SplitLock`]: its interface configuration
+//! and its transmit counters are locked apart, at one level.
+//!
+//! A configuration thread brings interfaces up and down while transmit
+//! threads count bytes. They only wait for each other when a thread takes
+//! both halves, as the report at the end does.
+//!
+//! `cargo run --example split_device` prints what was counted.
+
+use std::{thread, time::Duration};
+
+use deadlock_proof::{LockOutcome, OuterMutexPermission, SplitLock};
+
+/// The half that changes when interfaces come and go.
+#[derive(Default)]
+pub struct InterfaceConfig {
+    pub interfaces_active: u32,
+    pub reconfigurations: u32,
+}
+
+/// The half that changes with every packet sent.
+#[derive(Default)]
+pub struct TxCounters {
+    pub bytes_transmitted: u64,
+    pub packets_transmitted: u64,
+}
+
+pub struct DeviceLock;
+
+pub type Device = SplitLock<InterfaceConfig, TxCounters, OuterMutexPermission, DeviceLock>;
+
+/// Reconfigures the device `rounds` times.
+pub fn reconfigure(
+    device: &Device,
+    permission: OuterMutexPermission,
+    rounds: u32,
+) -> OuterMutexPermission {
+    (0..rounds).fold(permission, |permission, round| {
+        let mut config = device.lock_a(permission).guard();
+        config.interfaces_active = 1 + round % 4;
+        config.reconfigurations += 1;
+        // Transmitters are not held up by this.
+        thread::sleep(Duration::from_micros(50));
+        config.unlock()
+    })
+}
+
+/// Sends `packets` packets of `len` bytes.
+pub fn transmit(
+    device: &Device,
+    permission: OuterMutexPermission,
+    packets: u64,
+    len: u64,
+) -> OuterMutexPermission {
+    (0..packets).fold(permission, |permission, _| {
+        let mut counters = device.lock_b(permission).guard();
+        counters.bytes_transmitted += len;
+        counters.packets_transmitted += 1;
+        counters.unlock()
+    })
+}
+
+fn main() {
+    let device = Device::new(
+        InterfaceConfig::default(),
+        TxCounters::default(),
+        DeviceLock,
+    );
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            reconfigure(&device, OuterMutexPermission::get(), 100);
+        });
+        for _ in 0..4 {
+            scope.spawn(|| {
+                transmit(&device, OuterMutexPermission::get(), 1000, 64);
+            });
+        }
+    });
+
+    let both = device.lock_both(OuterMutexPermission::get()).guard();
+    let (config, counters) = (both.a(), both.b());
+    println!(
+        "{} interfaces after {} reconfigurations; {} packets, {} bytes sent",
+        config.interfaces_active,
+        config.reconfigurations,
+        counters.packets_transmitted,
+        counters.bytes_transmitted
+    );
+}
@@ -0,0 +1,48 @@
+/* C bindings for deadlock_proof, built with the `ffi` feature.
+ *
+ * Kept in sync with src/ffi.rs; regenerate with
+ *     cbindgen --config cbindgen.toml --output include/deadlock_proof.h
+ */
+
+#ifndef DEADLOCK_PROOF_H
+#define DEADLOCK_PROOF_H
+
+#include <stdint.h>
+
+#define DPM_OK 0
+#define DPM_ERR_NULL 1
+#define DPM_ERR_ORDER 2
+#define DPM_ERR_POISONED 3
+#define DPM_ERR_WRONG_THREAD 4
+#define DPM_ERR_NOT_HELD 5
+#define DPM_ERR_STILL_HELD 6
+
+/* A mutex shared from Rust. */
+typedef struct dpm_mutex_t dpm_mutex_t;
+
+/* A thread's permission to lock. Only usable on the thread that claimed it. */
+typedef struct dpm_permission_t dpm_permission_t;
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+void dpm_mutex_free(dpm_mutex_t *mutex);
+
+uint32_t dpm_mutex_level(const dpm_mutex_t *mutex);
+
+/* NULL if this thread's permission is already taken. */
+dpm_permission_t *dpm_claim_permission(void);
+
+int dpm_release_permission(dpm_permission_t *permission);
+
+/* On DPM_OK, *data points at the mutex's data until dpm_unlock. */
+int dpm_lock(const dpm_mutex_t *mutex, dpm_permission_t *permission, void **data);
+
+int dpm_unlock(const dpm_mutex_t *mutex, dpm_permission_t *permission);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* DEADLOCK_PROOF_H */
@@ -0,0 +1,545 @@
+//! Procedural macros for the deadlock-proof mutex crate.
+//!
+//! This crate only depends on `proc_macro`, so the parsing below is done
+//! directly on token trees and deliberately accepts a narrow input shape.
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Threads permission tokens through a function that walks a declared lock
+/// hierarchy in order.
+///
+/// `#[locks(order(IpLock, DeviceLock))]` adds a trailing parameter of type
+/// `Position<IpLock>` to the function and changes its return type `R` to
+/// `(Position<IpLock>, R)`. Inside the body, locks are taken and released with
+/// two pseudo-macros:
+///
+/// * `let guard = lock!(IpLock, mutex_expr);` locks `mutex_expr` with the
+///   current permission (panicking if the mutex is poisoned).
+/// * `unlock!(guard);` releases `guard` for the next level in the sequence.
+///
+/// This first version only supports straight-line bodies: every declared level
+/// is locked exactly once, in order, at the top level of the body, and each
+/// guard is unlocked before the next lock. Anything else is rejected at
+/// expansion time. The expansion refers to `::deadlock_proof::Position`, so the
+/// library must be a dependency under that name.
+#[proc_macro_attribute]
+pub fn locks(attr: TokenStream, item: TokenStream) -> TokenStream {
+    match expand(attr, item) {
+        Ok(tokens) => tokens,
+        Err(error) => error.into_compile_error(),
+    }
+}
+
+/// Declares a family of same-level mutex identifiers.
+///
+/// `declare_mutex_family!(pub QueueLock: Q0, Q1, Q2)` declares the family
+/// identifier `QueueLock` (the one level every member is locked at), a unit
+/// struct per member, and an enum `QueueLockId { Q0, Q1, Q2 }` implementing
+/// `::deadlock_proof::family::FamilyId` in declared order, with each variant
+/// named `"QueueLock::Q0"` and so on. Each member struct converts into its
+/// enum variant.
+#[proc_macro]
+pub fn declare_mutex_family(input: TokenStream) -> TokenStream {
+    match expand_family(input) {
+        Ok(tokens) => tokens,
+        Err(error) => error.into_compile_error(),
+    }
+}
+
+fn expand_family(input: TokenStream) -> Result<TokenStream, Error> {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    let colon = tokens
+        .iter()
+        .position(|tree| is_punct(tree, ':'))
+        .ok_or_else(|| Error::new(Span::call_site(), "expected `FamilyName: Member, ...`"))?;
+    let (family, visibility) = match tokens[..colon].split_last() {
+        Some((TokenTree::Ident(family), visibility)) => (family, render(visibility)),
+        _ => {
+            return Err(Error::new(
+                tokens[colon].span(),
+                "expected the family name before `:`",
+            ))
+        }
+    };
+
+    let mut members = Vec::new();
+    for part in split_commas(tokens[colon + 1..].to_vec()) {
+        match part.as_slice() {
+            [TokenTree::Ident(member)] => members.push(member.to_string()),
+            _ => return Err(Error::new(part[0].span(), "expected a member name")),
+        }
+    }
+    if members.is_empty() {
+        return Err(Error::new(family.span(), "a mutex family needs at least one member"));
+    }
+
+    let id = format!("{family}Id");
+    let mut out = format!(
+        "#[derive(Clone, Copy)] {visibility} struct {family};\n\
+         #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)] {visibility} enum {id} {{ {} }}\n\
+         impl ::deadlock_proof::__private::SealedFamilyId for {id} {{}}\n\
+         impl ::deadlock_proof::family::FamilyId for {id} {{\n\
+             const ALL: &'static [Self] = &[{}];\n\
+             fn index(self) -> usize {{ self as usize }}\n\
+             fn name(self) -> &'static str {{ [{}][self as usize] }}\n\
+         }}\n",
+        members.join(", "),
+        members
+            .iter()
+            .map(|member| format!("{id}::{member}"))
+            .collect::<Vec<_>>()
+            .join(", "),
+        members
+            .iter()
+            .map(|member| format!("\"{family}::{member}\""))
+            .collect::<Vec<_>>()
+            .join(", "),
+    );
+    for member in &members {
+        out.push_str(&format!(
+            "{visibility} struct {member};\n\
+             impl ::core::convert::From<{member}> for {id} {{\n\
+                 fn from(_: {member}) -> Self {{ {id}::{member} }}\n\
+             }}\n"
+        ));
+    }
+    out.parse()
+        .map_err(|_| Error::new(family.span(), "could not expand the mutex family"))
+}
+
+struct Error {
+    span: Span,
+    message: String,
+}
+
+impl Error {
+    fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+
+    /// Renders the error as `::core::compile_error!("...");` pointing at `span`.
+    fn into_compile_error(self) -> TokenStream {
+        let mut message = Literal::string(&self.message);
+        message.set_span(self.span);
+        let mut tokens = path(&["core", "compile_error"], self.span);
+        tokens.push(spanned(Punct::new('!', Spacing::Alone).into(), self.span));
+        tokens.push(spanned(
+            Group::new(Delimiter::Parenthesis, TokenTree::from(message).into()).into(),
+            self.span,
+        ));
+        tokens.push(spanned(Punct::new(';', Spacing::Alone).into(), self.span));
+        tokens.into_iter().collect()
+    }
+}
+
+/// One entry of `order(...)`: its rendered name (for comparisons) and tokens.
+struct Level {
+    name: String,
+    tokens: Vec<TokenTree>,
+}
+
+fn expand(attr: TokenStream, item: TokenStream) -> Result<TokenStream, Error> {
+    let levels = parse_order(attr)?;
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+
+    let body = match tokens.pop() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => group,
+        other => {
+            let span = other.map_or_else(Span::call_site, |tree| tree.span());
+            return Err(Error::new(span, "#[locks] must be applied to a function with a body"));
+        }
+    };
+
+    let fn_index = tokens
+        .iter()
+        .position(|tree| is_ident(tree, "fn"))
+        .ok_or_else(|| Error::new(Span::call_site(), "#[locks] must be applied to a function"))?;
+    let fn_name = match tokens.get(fn_index + 1) {
+        Some(TokenTree::Ident(ident)) => ident.clone(),
+        _ => return Err(Error::new(Span::call_site(), "expected a function name")),
+    };
+    let params_index = find_params(&tokens, fn_index + 2)
+        .ok_or_else(|| Error::new(fn_name.span(), "could not find the parameter list"))?;
+
+    // Everything after the parameter list is `-> Ret` and/or a where clause.
+    let tail = tokens.split_off(params_index + 1);
+    let where_index = tail.iter().position(|tree| is_ident(tree, "where"));
+    let (signature_tail, where_clause) = match where_index {
+        Some(index) => (&tail[..index], &tail[index..]),
+        None => (&tail[..], &[][..]),
+    };
+    let return_type: Vec<TokenTree> = match signature_tail {
+        [] => Vec::new(),
+        [TokenTree::Punct(minus), TokenTree::Punct(gt), rest @ ..]
+            if minus.as_char() == '-' && gt.as_char() == '>' =>
+        {
+            rest.to_vec()
+        }
+        [first, ..] => return Err(Error::new(first.span(), "unexpected tokens in signature")),
+    };
+
+    let permission = Ident::new("__dp_permission", Span::mixed_site());
+    let first_position = position_type(&levels[0]);
+
+    // Append `__dp_permission: Position<First>` to the parameters.
+    let params = match tokens.pop() {
+        Some(TokenTree::Group(group)) => group,
+        _ => unreachable!("find_params returned a parenthesized group"),
+    };
+    let mut param_tokens: Vec<TokenTree> = params.stream().into_iter().collect();
+    if !param_tokens.is_empty() && !is_punct(param_tokens.last().unwrap(), ',') {
+        param_tokens.push(Punct::new(',', Spacing::Alone).into());
+    }
+    param_tokens.push(permission.clone().into());
+    param_tokens.push(Punct::new(':', Spacing::Alone).into());
+    param_tokens.extend(first_position.iter().cloned());
+    let mut new_params = Group::new(Delimiter::Parenthesis, param_tokens.into_iter().collect());
+    new_params.set_span(params.span());
+    tokens.push(new_params.into());
+
+    // Return `(Position<First>, R)`.
+    let mut returned = first_position;
+    returned.push(Punct::new(',', Spacing::Alone).into());
+    if return_type.is_empty() {
+        returned.push(Group::new(Delimiter::Parenthesis, TokenStream::new()).into());
+    } else {
+        returned.extend(return_type);
+    }
+    tokens.push(Punct::new('-', Spacing::Joint).into());
+    tokens.push(Punct::new('>', Spacing::Alone).into());
+    tokens.push(Group::new(Delimiter::Parenthesis, returned.into_iter().collect()).into());
+    tokens.extend(where_clause.iter().cloned());
+
+    let mut new_body = Group::new(
+        Delimiter::Brace,
+        rewrite_body(body.stream(), &levels, &permission, &fn_name)?,
+    );
+    new_body.set_span(body.span());
+    tokens.push(new_body.into());
+
+    Ok(tokens.into_iter().collect())
+}
+
+/// Parses `order(A, B, ...)` into its levels.
+fn parse_order(attr: TokenStream) -> Result<Vec<Level>, Error> {
+    let mut iter = attr.into_iter();
+    let group = match (iter.next(), iter.next(), iter.next()) {
+        (Some(TokenTree::Ident(ident)), Some(TokenTree::Group(group)), None)
+            if ident.to_string() == "order" && group.delimiter() == Delimiter::Parenthesis =>
+        {
+            group
+        }
+        _ => {
+            return Err(Error::new(
+                Span::call_site(),
+                "expected #[locks(order(FirstLock, SecondLock, ...))]",
+            ));
+        }
+    };
+
+    let mut levels = Vec::new();
+    for tokens in split_commas(group.stream().into_iter().collect()) {
+        let name = render(&tokens);
+        if levels.iter().any(|level: &Level| level.name == name) {
+            return Err(Error::new(tokens[0].span(), format!("`{name}` is listed twice")));
+        }
+        levels.push(Level { name, tokens });
+    }
+    if levels.is_empty() {
+        return Err(Error::new(group.span(), "order(...) must name at least one lock"));
+    }
+    Ok(levels)
+}
+
+/// Finds the parameter list, skipping over any generic parameters.
+fn find_params(tokens: &[TokenTree], start: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut previous_was_minus = false;
+    for (index, tree) in tokens.iter().enumerate().skip(start) {
+        match tree {
+            TokenTree::Punct(punct) if punct.as_char() == '<' => depth += 1,
+            TokenTree::Punct(punct) if punct.as_char() == '>' && !previous_was_minus => {
+                depth = depth.saturating_sub(1)
+            }
+            TokenTree::Group(group) if depth == 0 && group.delimiter() == Delimiter::Parenthesis => {
+                return Some(index);
+            }
+            _ => {}
+        }
+        previous_was_minus = is_punct(tree, '-');
+    }
+    None
+}
+
+/// Rewrites the `lock!`/`unlock!` pseudo-calls and appends the permission
+/// hand-back to the body.
+fn rewrite_body(
+    body: TokenStream,
+    levels: &[Level],
+    permission: &Ident,
+    fn_name: &Ident,
+) -> Result<TokenStream, Error> {
+    let tokens: Vec<TokenTree> = body.into_iter().collect();
+    let mut output: Vec<TokenTree> = Vec::new();
+    let mut next_level = 0;
+    let mut held: Option<Ident> = None;
+    let mut last_statement_end = 0;
+
+    let mut index = 0;
+    while index < tokens.len() {
+        let tree = &tokens[index];
+        if is_ident(tree, "return") || is_punct(tree, '?') {
+            return Err(Error::new(
+                tree.span(),
+                "early exits are not supported inside #[locks] functions",
+            ));
+        }
+        if let TokenTree::Group(group) = tree {
+            reject_nested(group.stream())?;
+        }
+
+        let Some((name, args)) = pseudo_call(&tokens, index) else {
+            output.push(tree.clone());
+            if is_punct(tree, ';') {
+                last_statement_end = output.len();
+            }
+            index += 1;
+            continue;
+        };
+        if !tokens.get(index + 3).is_some_and(|tree| is_punct(tree, ';')) {
+            return Err(Error::new(name.span(), format!("{name}! must be used as a statement")));
+        }
+
+        if name.to_string() == "lock" {
+            let binding = let_binding(&output)
+                .ok_or_else(|| Error::new(name.span(), "expected `let guard = lock!(Level, mutex);`"))?;
+            let mut parts = split_commas(args.stream().into_iter().collect()).into_iter();
+            let (level, mutex) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(level), Some(mutex), None) => (level, mutex),
+                _ => return Err(Error::new(args.span(), "expected `lock!(Level, mutex)`")),
+            };
+            let level_name = render(&level);
+            if let Some(guard) = &held {
+                return Err(Error::new(
+                    level[0].span(),
+                    format!("cannot lock `{level_name}` while `{guard}` is still locked; unlock! it first"),
+                ));
+            }
+            match levels.get(next_level) {
+                Some(expected) if expected.name == level_name => {}
+                Some(expected) => {
+                    return Err(Error::new(
+                        level[0].span(),
+                        format!("`{level_name}` is locked out of order; expected `{}` next", expected.name),
+                    ));
+                }
+                None => {
+                    return Err(Error::new(
+                        level[0].span(),
+                        format!("`{level_name}` is not in the declared order or was already locked"),
+                    ));
+                }
+            }
+            next_level += 1;
+            held = Some(binding);
+
+            // `{ let __dp_permission: Position<Level> = __dp_permission;
+            //    LockOutcome::into_result((mutex).lock(__dp_permission)).expect("...") }`
+            // The lock result goes through `LockOutcome` so the expansion
+            // builds with and without the `no-poison` feature.
+            let mut block: Vec<TokenTree> = vec![
+                Ident::new("let", Span::mixed_site()).into(),
+                permission.clone().into(),
+                Punct::new(':', Spacing::Alone).into(),
+            ];
+            block.extend(position_type(&Level { name: level_name.clone(), tokens: level }));
+            block.push(Punct::new('=', Spacing::Alone).into());
+            block.push(permission.clone().into());
+            block.push(Punct::new(';', Spacing::Alone).into());
+            let mut locked: Vec<TokenTree> =
+                vec![Group::new(Delimiter::Parenthesis, mutex.into_iter().collect()).into()];
+            locked.extend(method_call("lock", TokenTree::from(permission.clone()).into()));
+            block.extend(path(&["deadlock_proof", "LockOutcome", "into_result"], Span::call_site()));
+            block.push(Group::new(Delimiter::Parenthesis, locked.into_iter().collect()).into());
+            block.extend(method_call(
+                "expect",
+                TokenTree::from(Literal::string(&format!(
+                    "deadlock-proof mutex `{level_name}` is poisoned"
+                )))
+                .into(),
+            ));
+            output.push(Group::new(Delimiter::Brace, block.into_iter().collect()).into());
+        } else {
+            let guard = match &args.stream().into_iter().collect::<Vec<_>>()[..] {
+                [TokenTree::Ident(guard)] => guard.clone(),
+                _ => return Err(Error::new(args.span(), "expected `unlock!(guard)`")),
+            };
+            match &held {
+                Some(current) if current.to_string() == guard.to_string() => held = None,
+                Some(current) => {
+                    return Err(Error::new(
+                        guard.span(),
+                        format!("`{guard}` is not the currently locked guard (`{current}` is)"),
+                    ));
+                }
+                None => {
+                    return Err(Error::new(guard.span(), format!("`{guard}` is not locked")));
+                }
+            }
+
+            // `let __dp_permission = guard.unlock_for_sequential()`
+            output.push(Ident::new("let", Span::mixed_site()).into());
+            output.push(permission.clone().into());
+            output.push(Punct::new('=', Spacing::Alone).into());
+            output.push(guard.into());
+            output.extend(method_call("unlock_for_sequential", TokenStream::new()));
+        }
+        index += 3;
+    }
+
+    if let Some(guard) = held {
+        return Err(Error::new(
+            guard.span(),
+            format!("`{guard}` is still locked at the end of `{fn_name}`"),
+        ));
+    }
+    if let Some(level) = levels.get(next_level) {
+        return Err(Error::new(
+            fn_name.span(),
+            format!("`{}` is declared in order(...) but never locked", level.name),
+        ));
+    }
+
+    // let __dp_result = { <tail> };
+    // (__dp_permission.to_earlier()..., __dp_result)
+    let result = Ident::new("__dp_result", Span::mixed_site());
+    let tail: TokenStream = output.split_off(last_statement_end).into_iter().collect();
+    output.push(Ident::new("let", Span::mixed_site()).into());
+    output.push(result.clone().into());
+    output.push(Punct::new('=', Spacing::Alone).into());
+    output.push(Group::new(Delimiter::Brace, tail).into());
+    output.push(Punct::new(';', Spacing::Alone).into());
+
+    let mut returned: Vec<TokenTree> = vec![permission.clone().into()];
+    for _ in levels {
+        returned.extend(method_call("to_earlier", TokenStream::new()));
+    }
+    returned.push(Punct::new(',', Spacing::Alone).into());
+    returned.push(result.into());
+    output.push(Group::new(Delimiter::Parenthesis, returned.into_iter().collect()).into());
+
+    Ok(output.into_iter().collect())
+}
+
+/// Recognizes `lock!(...)` or `unlock!(...)` starting at `index`.
+fn pseudo_call(tokens: &[TokenTree], index: usize) -> Option<(Ident, Group)> {
+    match &tokens[index..] {
+        [TokenTree::Ident(name), TokenTree::Punct(bang), TokenTree::Group(args), ..]
+            if matches!(name.to_string().as_str(), "lock" | "unlock")
+                && bang.as_char() == '!'
+                && args.delimiter() == Delimiter::Parenthesis =>
+        {
+            Some((name.clone(), args.clone()))
+        }
+        _ => None,
+    }
+}
+
+/// Rejects `lock!`/`unlock!` anywhere below the top level of the body.
+fn reject_nested(stream: TokenStream) -> Result<(), Error> {
+    let tokens: Vec<TokenTree> = stream.into_iter().collect();
+    for index in 0..tokens.len() {
+        if let Some((name, _)) = pseudo_call(&tokens, index) {
+            return Err(Error::new(
+                name.span(),
+                format!("{name}! must appear at the top level of the function body"),
+            ));
+        }
+        if let TokenTree::Group(group) = &tokens[index] {
+            reject_nested(group.stream())?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `output` ends in `let [mut] name =` and returns `name`.
+fn let_binding(output: &[TokenTree]) -> Option<Ident> {
+    let (binding, before) = match output {
+        [.., TokenTree::Ident(binding), TokenTree::Punct(eq)] if eq.as_char() == '=' => {
+            (binding, &output[..output.len() - 2])
+        }
+        _ => return None,
+    };
+    let before = match before {
+        [rest @ .., last] if is_ident(last, "mut") => rest,
+        _ => before,
+    };
+    matches!(before.last(), Some(tree) if is_ident(tree, "let")).then(|| binding.clone())
+}
+
+/// `::deadlock_proof::Position<Level>` for the given level.
+fn position_type(level: &Level) -> Vec<TokenTree> {
+    let mut tokens: Vec<TokenTree> = Vec::new();
+    for segment in ["deadlock_proof", "Position"] {
+        tokens.push(Punct::new(':', Spacing::Joint).into());
+        tokens.push(Punct::new(':', Spacing::Alone).into());
+        tokens.push(Ident::new(segment, Span::call_site()).into());
+    }
+    tokens.push(Punct::new('<', Spacing::Alone).into());
+    tokens.extend(level.tokens.iter().cloned());
+    tokens.push(Punct::new('>', Spacing::Alone).into());
+    tokens
+}
+
+/// `.method(args)`
+fn method_call(method: &str, args: TokenStream) -> Vec<TokenTree> {
+    vec![
+        Punct::new('.', Spacing::Alone).into(),
+        Ident::new(method, Span::mixed_site()).into(),
+        Group::new(Delimiter::Parenthesis, args).into(),
+    ]
+}
+
+/// `::a::b` with every token spanned at `span`.
+fn path(segments: &[&str], span: Span) -> Vec<TokenTree> {
+    let mut tokens = Vec::new();
+    for segment in segments {
+        tokens.push(spanned(Punct::new(':', Spacing::Joint).into(), span));
+        tokens.push(spanned(Punct::new(':', Spacing::Alone).into(), span));
+        tokens.push(Ident::new(segment, span).into());
+    }
+    tokens
+}
+
+fn spanned(mut tree: TokenTree, span: Span) -> TokenTree {
+    tree.set_span(span);
+    tree
+}
+
+fn split_commas(tokens: Vec<TokenTree>) -> Vec<Vec<TokenTree>> {
+    let mut parts = vec![Vec::new()];
+    for tree in tokens {
+        if is_punct(&tree, ',') {
+            parts.push(Vec::new());
+        } else {
+            parts.last_mut().unwrap().push(tree);
+        }
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+fn render(tokens: &[TokenTree]) -> String {
+    tokens.iter().cloned().collect::<TokenStream>().to_string()
+}
+
+fn is_ident(tree: &TokenTree, name: &str) -> bool {
+    matches!(tree, TokenTree::Ident(ident) if ident.to_string() == name)
+}
+
+fn is_punct(tree: &TokenTree, ch: char) -> bool {
+    matches!(tree, TokenTree::Punct(punct) if punct.as_char() == ch)
+}
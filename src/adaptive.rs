@@ -0,0 +1,90 @@
+//! Adaptive spinning before parking, enabled by the `adaptive` feature.
+//!
+//! A contended acquisition first retries the lock for a bounded number of
+//! spins, backing off exponentially between attempts, and only parks on the
+//! inner mutex once the budget is used up. Holds that last a few hundred
+//! nanoseconds are usually over before the budget is, which saves the waiter
+//! a park and wake-up.
+//!
+//! On a single CPU the holder cannot run while a waiter spins, so waiters
+//! park straight away there, and a waiter whose backoff has reached its cap
+//! yields its time slice between attempts.
+//!
+//! The retries are `try_lock`s on the inner mutex, whose state word is the
+//! only lock state there is, so a spinning waiter sees poisoning exactly like
+//! a parked one.
+
+use std::{
+    hint,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        LockResult, Mutex, MutexGuard, OnceLock, TryLockError,
+    },
+    thread,
+};
+
+use crate::{DeadlockProofMutex, MutexPermission};
+
+/// The spin budget of a new mutex.
+pub const DEFAULT_SPIN_BUDGET: u32 = 256;
+
+/// Longest single backoff, in spins.
+const MAX_BACKOFF: u32 = 64;
+
+/// Whether this process can run more than one thread at a time.
+fn parallel() -> bool {
+    static PARALLEL: OnceLock<bool> = OnceLock::new();
+    *PARALLEL.get_or_init(|| thread::available_parallelism().is_ok_and(|cpus| cpus.get() > 1))
+}
+
+/// How many spins a mutex may spend before parking.
+pub(crate) struct SpinBudget(AtomicU32);
+
+impl SpinBudget {
+    pub(crate) const fn new() -> Self {
+        Self(AtomicU32::new(DEFAULT_SPIN_BUDGET))
+    }
+
+    /// Tries `mutex` until it is free or the budget is spent. `None` means
+    /// the caller should park.
+    pub(crate) fn try_acquire<'a, T>(
+        &self,
+        mutex: &'a Mutex<T>,
+    ) -> Option<LockResult<MutexGuard<'a, T>>> {
+        let budget = if parallel() { self.0.load(Ordering::Relaxed) } else { 0 };
+        let mut spent = 0;
+        let mut backoff = 1;
+        loop {
+            match mutex.try_lock() {
+                Ok(guard) => return Some(Ok(guard)),
+                Err(TryLockError::Poisoned(poisoned)) => return Some(Err(poisoned)),
+                Err(TryLockError::WouldBlock) if spent >= budget => return None,
+                Err(TryLockError::WouldBlock) => {}
+            }
+            let spins = backoff.min(budget - spent);
+            if backoff == MAX_BACKOFF {
+                thread::yield_now();
+            } else {
+                for _ in 0..spins {
+                    hint::spin_loop();
+                }
+            }
+            spent += spins;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Sets how many spins a contended acquisition may spend retrying before
+    /// it parks. `0` parks right after the first failed attempt. Takes effect
+    /// for acquisitions that start afterwards.
+    pub fn set_spin_budget(&self, spins: u32) {
+        self.spin_budget.0.store(spins, Ordering::Relaxed);
+    }
+
+    /// The current spin budget, [`DEFAULT_SPIN_BUDGET`] unless changed.
+    pub fn spin_budget(&self) -> u32 {
+        self.spin_budget.0.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,204 @@
+//! Bounded queues inside lock-protected state, for backpressure.
+//!
+//! A [`BoundedUnderLock`] lives inside the data of a mutex, such as the
+//! transport layer's [`ingress`](crate::TransportState::ingress) queue, and
+//! is only ever touched through that mutex's guard. [`push_within`] refuses
+//! an item once the queue is full, which is the signal for a producer to stop
+//! accepting work. Producers that would rather wait use a [`WaitForSpace`]:
+//! it releases the mutex, waits until a consumer has made room, and locks
+//! again, so no lock is held while waiting.
+//!
+//! ```
+//! use deadlock_proof::{backpressure, LockOutcome, NetworkStack, OuterMutexPermission};
+//!
+//! let stack = NetworkStack::with_ingress_capacity(1);
+//! let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+//! let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+//! let mut transport = stack.transport_layer.lock(device.unlock_for_sequential()).guard();
+//! assert!(backpressure::push_within(&mut transport, vec![1]).is_ok());
+//! assert!(backpressure::push_within(&mut transport, vec![2]).is_err());
+//! assert_eq!(stack.ingress_space.pop_within(&mut transport), Some(vec![1]));
+//! ```
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{DeadlockProofMutex, DeadlockProofMutexGuard, LockOutcome, MutexPermission};
+
+/// A FIFO queue holding at most [`capacity`](Self::capacity) items.
+#[derive(Clone, Debug)]
+pub struct BoundedUnderLock<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> BoundedUnderLock<T> {
+    /// An empty queue for at most `capacity` items.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            items: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the capacity. Items beyond a lowered capacity stay queued;
+    /// pushes fail until they have been popped.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.items.len() >= self.capacity
+    }
+
+    /// Appends `item`, or hands it back if the queue is full.
+    pub fn push(&mut self, item: T) -> Result<(), Full<T>> {
+        if self.is_full() {
+            return Err(Full(item));
+        }
+        self.items.push_back(item);
+        Ok(())
+    }
+
+    /// Removes the oldest item.
+    pub fn pop(&mut self) -> Option<T> {
+        self.items.pop_front()
+    }
+}
+
+/// The item a full queue refused.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Full<T>(pub T);
+
+impl<T> fmt::Debug for Full<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Full(..)")
+    }
+}
+
+impl<T> fmt::Display for Full<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("bounded queue is full")
+    }
+}
+
+impl<T> Error for Full<T> {}
+
+/// Lock-protected state with a [`BoundedUnderLock`] in it.
+pub trait HasBoundedQueue {
+    type Item;
+
+    fn queue(&self) -> &BoundedUnderLock<Self::Item>;
+
+    fn queue_mut(&mut self) -> &mut BoundedUnderLock<Self::Item>;
+}
+
+/// Pushes `item` onto the queue in the state `guard` holds. A refused push
+/// does not count as a modification, so it bumps no version under
+/// `tracking`.
+pub fn push_within<S: HasBoundedQueue, P: MutexPermission, I: 'static>(
+    guard: &mut DeadlockProofMutexGuard<'_, S, P, I>,
+    item: S::Item,
+) -> Result<(), Full<S::Item>> {
+    if guard.queue().is_full() {
+        return Err(Full(item));
+    }
+    guard.get_mut().queue_mut().push(item)
+}
+
+/// Pops the oldest item off the queue in the state `guard` holds, without
+/// waking producers waiting in a [`WaitForSpace`]. See
+/// [`WaitForSpace::pop_within`].
+pub fn pop_within<S: HasBoundedQueue, P: MutexPermission, I: 'static>(
+    guard: &mut DeadlockProofMutexGuard<'_, S, P, I>,
+) -> Option<S::Item> {
+    if guard.queue().is_empty() {
+        return None;
+    }
+    guard.get_mut().queue_mut().pop()
+}
+
+/// Where producers wait for space in a [`BoundedUnderLock`]. Consumers pop
+/// through [`pop_within`](Self::pop_within) to wake them.
+///
+/// The condition variable's own lock is only taken with no other lock held,
+/// or while holding the queue's guard just to publish a pop, and nothing is
+/// locked under it, so it takes no part in the lock order.
+#[derive(Default)]
+pub struct WaitForSpace {
+    /// Counts the pops, so a waiter can tell whether one happened since it
+    /// found the queue full.
+    pops: Mutex<u64>,
+    space: Condvar,
+}
+
+impl WaitForSpace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn pops(&self) -> MutexGuard<'_, u64> {
+        // Nothing runs under this lock that could panic.
+        self.pops.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Pops the oldest item off the queue in the state `guard` holds and
+    /// wakes the producers waiting for space.
+    pub fn pop_within<S: HasBoundedQueue, P: MutexPermission, I: 'static>(
+        &self,
+        guard: &mut DeadlockProofMutexGuard<'_, S, P, I>,
+    ) -> Option<S::Item> {
+        let item = pop_within(guard)?;
+        *self.pops() += 1;
+        self.space.notify_all();
+        Some(item)
+    }
+
+    /// Pushes `item` onto the queue in `mutex`, waiting for space whenever
+    /// it is full. The mutex is unlocked while waiting: the permission comes
+    /// back from the guard before the wait starts, and the mutex is locked
+    /// again with it afterwards.
+    ///
+    /// Waits forever if nothing pops through [`pop_within`](Self::pop_within).
+    /// Panics if `mutex` is poisoned.
+    pub fn push<S: HasBoundedQueue, P: MutexPermission, I: 'static>(
+        &self,
+        mutex: &DeadlockProofMutex<S, P, I>,
+        mut permission: P,
+        mut item: S::Item,
+    ) -> P {
+        loop {
+            let mut guard = mutex.lock(permission).guard();
+            // Read while the queue is locked, so no pop after this point
+            // goes unnoticed.
+            let seen = *self.pops();
+            match push_within(&mut guard, item) {
+                Ok(()) => return guard.unlock(),
+                Err(Full(refused)) => item = refused,
+            }
+            permission = guard.unlock();
+            let pops = self.pops();
+            drop(
+                self.space
+                    .wait_while(pops, |pops| *pops == seen)
+                    .unwrap_or_else(PoisonError::into_inner),
+            );
+        }
+    }
+}
@@ -3,9 +3,9 @@ use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-mod lib;
-use lib::{
-    DeadlockProofMutex, NetworkStack, OuterMutexPermission
+use deadlock_proof::{
+    locks, soak, unique_type, DeadlockProofMutex, DeviceLock, IpLock, LockOutcome, NetworkStack,
+    OuterMutexPermission, TransportLock,
 };
 
 fn main() {
@@ -15,14 +15,15 @@ fn main() {
 
     loop {
         print_menu();
-        let choice = get_user_input("Enter your choice (1-5): ");
+        let choice = get_user_input("Enter your choice (1-6): ");
         
         match choice.trim() {
             "1" => demo_exclusive_mutexes(),
             "2" => demo_nested_mutexes(),
             "3" => demo_sequential_mutexes(),
             "4" => demo_network_stack(),
-            "5" => {
+            "5" => demo_soak(),
+            "6" => {
                 println!(" Goodbye!");
                 break;
             }
@@ -37,7 +38,8 @@ fn print_menu() {
     println!("2. Nested Mutexes (Ordered acquisition)");
     println!("3. Sequential Mutexes (Lock-unlock-lock pattern)");
     println!("4. Network Stack Simulation (Netstack3-style)");
-    println!("5. Exit");
+    println!("5. Soak Test (Randomized long run with invariant checks)");
+    println!("6. Exit");
 }
 
 fn get_user_input(prompt: &str) -> String {
@@ -66,14 +68,14 @@ fn demo_exclusive_mutexes() {
         let permission = OuterMutexPermission::get();
         
         println!("  Thread: Acquiring mutex1...");
-        let mut guard1 = c_mutex1.lock(permission).unwrap();
+        let mut guard1 = c_mutex1.lock(permission).guard();
         *guard1 = 42;
         println!("  Thread: Set mutex1 to {}", *guard1);
         
         let permission = guard1.unlock();
         println!("  Thread: Released mutex1, acquiring mutex2...");
         
-        let mut guard2 = c_mutex2.lock(permission).unwrap();
+        let mut guard2 = c_mutex2.lock(permission).guard();
         *guard2 = 84;
         println!("  Thread: Set mutex2 to {}", *guard2);
 
@@ -92,18 +94,18 @@ fn demo_exclusive_mutexes() {
     
     // Main thread access
     let permission = OuterMutexPermission::get();
-    let guard1 = mutex1.lock(permission).unwrap();
+    let guard1 = mutex1.lock(permission).guard();
     println!("Main: mutex1 = {}", *guard1);
     
     let permission = guard1.unlock();
-    let guard2 = mutex2.lock(permission).unwrap();
+    let guard2 = mutex2.lock(permission).guard();
     println!("Main: mutex2 = {}", *guard2);
     
     println!(" Demo completed successfully!\n");
 }
 
 fn demo_nested_mutexes() {
-/// This shows the hierarchical pattern: locking A gives you a new key that is the only key that can open B.
+// This shows the hierarchical pattern: locking A gives you a new key that is the only key that can open B.
 
 
     println!("Mutexes must be acquired in a specific nested order across all threads.");
@@ -122,17 +124,17 @@ fn demo_nested_mutexes() {
         let permission = OuterMutexPermission::get();
         
         println!("  Thread: Acquiring outermost mutex...");
-        let (mut guard1, perm1) = c_mutex1.lock_for_nested(permission).unwrap();
+        let (mut guard1, perm1) = c_mutex1.lock_for_nested(permission).guard();
         guard1.push_str(" - Modified by thread");
         println!("  Thread: Modified layer 1: {}", *guard1);
         
         println!("  Thread: Acquiring middle mutex...");
-        let (mut guard2, perm2) = c_mutex2.lock_for_nested(perm1).unwrap();
+        let (mut guard2, perm2) = c_mutex2.lock_for_nested(perm1).guard();
         guard2.push_str(" - Modified by thread");
         println!("  Thread: Modified layer 2: {}", *guard2);
         
         println!("  Thread: Acquiring innermost mutex...");
-        let mut guard3 = c_mutex3.lock(perm2).unwrap();
+        let mut guard3 = c_mutex3.lock(perm2).guard();
         guard3.push_str(" - Modified by thread");
         println!("  Thread: Modified layer 3: {}", *guard3);
         
@@ -150,17 +152,17 @@ fn demo_nested_mutexes() {
     
     // Lock mutex1, consuming `permission` and creating `perm1`
 
-    let (mut guard1, perm1) = mutex1.lock_for_nested(permission).unwrap();
+    let (guard1, perm1) = mutex1.lock_for_nested(permission).guard();
     println!("Main: Layer 1 = {}", *guard1);
     
     // Use `perm1` to lock mutex2, creating `perm2`
 
-    let (mut guard2, perm2) = mutex2.lock_for_nested(perm1).unwrap();
+    let (guard2, perm2) = mutex2.lock_for_nested(perm1).guard();
     println!("Main: Layer 2 = {}", *guard2);
    
     // Use `perm2` to lock mutex3
 
-    let mut guard3 = mutex3.lock(perm2).unwrap();
+    let guard3 = mutex3.lock(perm2).guard();
     println!("Main: Layer 3 = {}", *guard3);
     
     println!(" Demo completed successfully!\n");
@@ -186,21 +188,21 @@ fn demo_sequential_mutexes() {
         
         // Process data1
         println!("  Thread: Processing data set 1...");
-        let mut guard1 = c_data1.lock(permission).unwrap();
+        let mut guard1 = c_data1.lock(permission).guard();
         guard1.push(10);
         println!("  Thread: Added 10 to data1: {:?}", *guard1);
         let perm = guard1.unlock_for_sequential();
         
         // Process data2
         println!("  Thread: Processing data set 2...");
-        let mut guard2 = c_data2.lock(perm).unwrap();
+        let mut guard2 = c_data2.lock(perm).guard();
         guard2.push(11);
         println!("  Thread: Added 11 to data2: {:?}", *guard2);
         let perm = guard2.unlock_for_sequential();
         
         // Process data3
         println!("  Thread: Processing data set 3...");
-        let mut guard3 = c_data3.lock(perm).unwrap();
+        let mut guard3 = c_data3.lock(perm).guard();
         guard3.push(12);
         println!("  Thread: Added 12 to data3: {:?}", *guard3);
         
@@ -212,15 +214,15 @@ fn demo_sequential_mutexes() {
     // Main thread follows same sequence
     let permission = OuterMutexPermission::get();
     
-    let guard1 = data1.lock(permission).unwrap();
+    let guard1 = data1.lock(permission).guard();
     println!("Main: Data1 final state: {:?}", *guard1);
     let perm = guard1.unlock_for_sequential();
     
-    let guard2 = data2.lock(perm).unwrap();
+    let guard2 = data2.lock(perm).guard();
     println!("Main: Data2 final state: {:?}", *guard2);
     let perm = guard2.unlock_for_sequential();
     
-    let guard3 = data3.lock(perm).unwrap();
+    let guard3 = data3.lock(perm).guard();
     println!("Main: Data3 final state: {:?}", *guard3);
     
     println!(" Demo completed successfully!\n");
@@ -239,32 +241,8 @@ fn demo_network_stack() {
         let permission = OuterMutexPermission::get();
 
         // This demonstrates the lock-unlock-lock pattern, enforced by the types defined in NetworkStack. You cannot lock the device_layer without first having locked and unlocked the ip_layer.
-        
-        // Process in network stack order: IP -> Device -> Transport
-        println!("  Thread: Processing IP layer...");
-        let mut ip_guard = c_stack.ip_layer.lock(permission).unwrap();
-        ip_guard.packets_processed += 100;
-        ip_guard.routing_table_size = 50;
-        println!("  Thread: IP layer - packets: {}, routing entries: {}", 
-                ip_guard.packets_processed, ip_guard.routing_table_size);
-        
-        let device_perm = ip_guard.unlock_for_sequential();
-        
-        println!("  Thread: Processing Device layer...");
-        let mut device_guard = c_stack.device_layer.lock(device_perm).unwrap();
-        device_guard.interfaces_active = 3;
-        device_guard.bytes_transmitted += 1024;
-        println!("  Thread: Device layer - interfaces: {}, bytes: {}", 
-                device_guard.interfaces_active, device_guard.bytes_transmitted);
-        
-        let transport_perm = device_guard.unlock_for_sequential();
-        
-        println!("  Thread: Processing Transport layer...");
-        let mut transport_guard = c_stack.transport_layer.lock(transport_perm).unwrap();
-        transport_guard.tcp_connections = 5;
-        transport_guard.udp_sockets = 8;
-        println!("  Thread: Transport layer - TCP: {}, UDP: {}", 
-                transport_guard.tcp_connections, transport_guard.udp_sockets);
+        // The #[locks] attribute threads the permission through the walk for us.
+        let (_permission, ()) = process_network_layers(&c_stack, permission);
         
         println!("  Thread: Network stack processing complete");
         
@@ -278,19 +256,79 @@ fn demo_network_stack() {
     println!(" Reading final network stack state...");
     let permission = OuterMutexPermission::get();
     
-    let ip_guard = stack.ip_layer.lock(permission).unwrap();
+    let ip_guard = stack.ip_layer.lock(permission).guard();
     println!("Main: IP Layer - Packets processed: {}, Routing table size: {}", 
             ip_guard.packets_processed, ip_guard.routing_table_size);
     let device_perm = ip_guard.unlock_for_sequential();
     
-    let device_guard = stack.device_layer.lock(device_perm).unwrap();
+    let device_guard = stack.device_layer.lock(device_perm).guard();
     println!("Main: Device Layer - Active interfaces: {}, Bytes transmitted: {}", 
             device_guard.interfaces_active, device_guard.bytes_transmitted);
     let transport_perm = device_guard.unlock_for_sequential();
     
-    let transport_guard = stack.transport_layer.lock(transport_perm).unwrap();
+    let transport_guard = stack.transport_layer.lock(transport_perm).guard();
     println!("Main: Transport Layer - TCP connections: {}, UDP sockets: {}", 
             transport_guard.tcp_connections, transport_guard.udp_sockets);
     
     println!(" Network stack simulation completed successfully!\n");
-}
\ No newline at end of file
+}
+
+// Process in network stack order: IP -> Device -> Transport
+#[locks(order(IpLock, DeviceLock, TransportLock))]
+fn process_network_layers(stack: &NetworkStack) {
+    println!("  Thread: Processing IP layer...");
+    let mut ip_guard = lock!(IpLock, stack.ip_layer);
+    ip_guard.packets_processed += 100;
+    ip_guard.routing_table_size = 50;
+    println!("  Thread: IP layer - packets: {}, routing entries: {}", 
+            ip_guard.packets_processed, ip_guard.routing_table_size);
+    unlock!(ip_guard);
+
+    println!("  Thread: Processing Device layer...");
+    let mut device_guard = lock!(DeviceLock, stack.device_layer);
+    device_guard.interfaces_active = 3;
+    device_guard.bytes_transmitted += 1024;
+    println!("  Thread: Device layer - interfaces: {}, bytes: {}", 
+            device_guard.interfaces_active, device_guard.bytes_transmitted);
+    unlock!(device_guard);
+
+    println!("  Thread: Processing Transport layer...");
+    let mut transport_guard = lock!(TransportLock, stack.transport_layer);
+    transport_guard.tcp_connections = 5;
+    transport_guard.udp_sockets = 8;
+    println!("  Thread: Transport layer - TCP: {}, UDP: {}", 
+            transport_guard.tcp_connections, transport_guard.udp_sockets);
+    unlock!(transport_guard);
+}
+
+fn demo_soak() {
+    println!("\n Soak Test");
+    println!("============");
+    println!("Runs random exclusive, nested, sequential and stack-walk flows on many threads.");
+
+    let mut config = soak::SoakConfig::default();
+    let threads = get_user_input("Threads [4]: ");
+    if let Ok(threads) = threads.trim().parse() {
+        config.threads = threads;
+    }
+    let seconds = get_user_input("Duration in seconds [10]: ");
+    config.duration = Duration::from_secs(seconds.trim().parse().unwrap_or(10));
+
+    println!(" Running {} threads for {:?}...", config.threads, config.duration);
+    let report = soak::run(config);
+
+    for scenario in soak::Scenario::ALL {
+        println!("Main: {:?} - {} operations", scenario, report.operations(scenario));
+    }
+    println!("Main: {:.0} operations/s, longest wait {:?}",
+            report.throughput(), report.max_wait);
+    if report.is_clean() {
+        println!(" Soak test completed without invariant violations!\n");
+    } else {
+        println!(" {} invariant violation(s):", report.violation_count);
+        for violation in &report.violations {
+            println!("  {violation}");
+        }
+        println!();
+    }
+}
@@ -0,0 +1,232 @@
+//! Walks over the [`NetworkStack`] with a time budget, for forwarding
+//! decisions that must be made within a deadline or not at all.
+//!
+//! A [`WalkBudget`] gives the walk an overall time limit and each layer a
+//! limit on how long the walk may wait for its lock. Each layer is waited
+//! for as with [`try_lock_until`](crate::RtHandle::try_lock_until), until
+//! the earlier of the layer's own deadline and the overall one. A layer
+//! that cannot be had in time abandons the walk there: the layers after it
+//! are not visited, and the permission is walked back to the root. Time
+//! spent holding a layer cannot be cut short, but it uses up the overall
+//! budget, so a slow layer leaves less waiting time for the ones after it.
+//!
+//! The [`WalkOutcome`] says how long each layer visited was waited for and
+//! held, and where and why the walk was abandoned, if it was:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use deadlock_proof::{budget::WalkBudget, NetworkStack, OuterMutexPermission};
+//!
+//! let stack = NetworkStack::new();
+//! let budget = WalkBudget {
+//!     overall: Duration::from_micros(200),
+//!     per_layer: [Duration::from_micros(50), Duration::from_micros(100), Duration::from_micros(50)],
+//! };
+//! let (_permission, outcome) = stack.process_packet_with_budget(
+//!     OuterMutexPermission::get(),
+//!     budget,
+//!     |ip| ip.packets_processed += 1,
+//!     |device| device.bytes_transmitted += 64,
+//!     |_| {},
+//! );
+//! if let Some(abandoned) = outcome.abandoned {
+//!     eprintln!("dropped the packet at {}", abandoned.layer);
+//! }
+//! ```
+//!
+//! Times are taken from the crate's [`clock`], which is monotonic.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    clock,
+    network_stack::LOCK_ORDER, rt::TryLockError, DeadlockProofMutex, DeadlockProofMutexGuard,
+    DeviceState, IpState, MutexPermission, NetworkStack, OuterMutexPermission, TransportState,
+    WalkToken,
+};
+
+/// How long a budgeted walk may take.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WalkBudget {
+    /// The whole walk, waiting and holding included.
+    pub overall: Duration,
+    /// The longest wait for each layer's lock, in
+    /// [`LOCK_ORDER`](crate::network_stack::LOCK_ORDER).
+    pub per_layer: [Duration; LOCK_ORDER.len()],
+}
+
+/// How long a budgeted walk spent at one layer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayerTiming {
+    /// The layer's name, as in [`LOCK_ORDER`](crate::network_stack::LOCK_ORDER).
+    pub layer: &'static str,
+    /// From the start of the wait until the lock was taken.
+    pub waited: Duration,
+    /// From taking the lock until releasing it.
+    pub held: Duration,
+}
+
+/// Which limit of a [`WalkBudget`] ran out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Exhausted {
+    /// The layer's own wait limit.
+    Layer,
+    /// The overall limit.
+    Overall,
+}
+
+/// Where a budgeted walk gave up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WalkAbandoned {
+    /// The layer whose lock the walk could not get in time.
+    pub layer: &'static str,
+    pub exhausted: Exhausted,
+}
+
+/// What a budgeted walk did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WalkOutcome {
+    /// The layers locked, in order: all of them unless the walk was
+    /// abandoned.
+    pub layers: Vec<LayerTiming>,
+    /// Where the walk gave up, if it did.
+    pub abandoned: Option<WalkAbandoned>,
+}
+
+impl WalkOutcome {
+    /// Whether every layer was visited.
+    pub fn is_complete(&self) -> bool {
+        self.abandoned.is_none()
+    }
+}
+
+/// The bookkeeping of one budgeted walk.
+struct Timer {
+    budget: WalkBudget,
+    deadline: Instant,
+    outcome: WalkOutcome,
+    /// When the walk got the layer it holds, and how long it waited for it.
+    holding: Option<(Instant, Duration)>,
+}
+
+impl Timer {
+    fn start(budget: WalkBudget) -> Self {
+        Self {
+            budget,
+            deadline: clock::now() + budget.overall,
+            outcome: WalkOutcome::default(),
+            holding: None,
+        }
+    }
+
+    /// Waits for the next layer within the budget, or gives the permission
+    /// back with the walk marked abandoned.
+    ///
+    /// Panics if the layer is poisoned.
+    fn lock<'a, T, P: MutexPermission, I: 'static>(
+        &mut self,
+        mutex: &'a DeadlockProofMutex<T, P, I>,
+        permission: P,
+    ) -> Result<DeadlockProofMutexGuard<'a, T, P, I>, P> {
+        let index = self.outcome.layers.len();
+        let layer = LOCK_ORDER[index];
+        let started = clock::now();
+        let layer_deadline = started + self.budget.per_layer[index];
+        let (deadline, exhausted) = if self.deadline <= layer_deadline {
+            (self.deadline, Exhausted::Overall)
+        } else {
+            (layer_deadline, Exhausted::Layer)
+        };
+        let result = if started >= self.deadline {
+            Err(TryLockError::WouldBlock(permission))
+        } else {
+            mutex.try_guard_until(permission, deadline)
+        };
+        match result {
+            Ok(guard) => {
+                let acquired = clock::now();
+                self.holding = Some((acquired, acquired - started));
+                Ok(guard)
+            }
+            Err(TryLockError::WouldBlock(permission)) => {
+                self.outcome.abandoned = Some(WalkAbandoned { layer, exhausted });
+                Err(permission)
+            }
+            #[cfg(not(feature = "no-poison"))]
+            Err(TryLockError::Poisoned(_)) => panic!("`{layer}` is poisoned"),
+        }
+    }
+
+    /// Records the layer just released, passing on the permission it gave
+    /// back.
+    fn released<Q>(&mut self, permission: Q) -> Q {
+        let (acquired, waited) = self.holding.take().expect("a layer is held");
+        self.outcome.layers.push(LayerTiming {
+            layer: LOCK_ORDER[self.outcome.layers.len()],
+            waited,
+            held: clock::now() - acquired,
+        });
+        permission
+    }
+}
+
+impl WalkToken<'_> {
+    /// Walks the three layers in order, running the update for each, within
+    /// `budget`. See the [module docs](crate::budget).
+    ///
+    /// Panics if a layer is poisoned.
+    pub fn process_packet_with_budget(
+        &mut self,
+        budget: WalkBudget,
+        ip: impl FnOnce(&mut IpState),
+        device: impl FnOnce(&mut DeviceState),
+        transport: impl FnOnce(&mut TransportState),
+    ) -> WalkOutcome {
+        let stack = self.stack();
+        self.with_outer(|permission| {
+            let mut timer = Timer::start(budget);
+
+            let mut ip_guard = match timer.lock(&stack.ip_layer, permission) {
+                Ok(guard) => guard,
+                Err(permission) => return (permission, timer.outcome),
+            };
+            ip(&mut ip_guard);
+            let permission = timer.released(ip_guard.unlock_for_sequential());
+
+            let mut device_guard = match timer.lock(&stack.device_layer, permission) {
+                Ok(guard) => guard,
+                Err(permission) => return (permission.to_earlier(), timer.outcome),
+            };
+            device(&mut device_guard);
+            let permission = timer.released(device_guard.unlock_for_sequential());
+
+            let mut transport_guard = match timer.lock(&stack.transport_layer, permission) {
+                Ok(guard) => guard,
+                Err(permission) => return (permission.to_earlier().to_earlier(), timer.outcome),
+            };
+            transport(&mut transport_guard);
+            let permission = timer.released(transport_guard.unlock_for_sequential());
+
+            let permission = permission.to_earlier().to_earlier().to_earlier();
+            (permission, timer.outcome)
+        })
+    }
+}
+
+impl NetworkStack {
+    /// Runs [`WalkToken::process_packet_with_budget`] as a walk of its own.
+    pub fn process_packet_with_budget(
+        &self,
+        permission: OuterMutexPermission,
+        budget: WalkBudget,
+        ip: impl FnOnce(&mut IpState),
+        device: impl FnOnce(&mut DeviceState),
+        transport: impl FnOnce(&mut TransportState),
+    ) -> (OuterMutexPermission, WalkOutcome) {
+        let mut walk = self.begin_walk(permission);
+        let outcome = walk.process_packet_with_budget(budget, ip, device, transport);
+        (walk.finish(), outcome)
+    }
+}
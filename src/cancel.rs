@@ -0,0 +1,166 @@
+//! Acquisitions that another thread can call off.
+//!
+//! A thread blocked in [`lock`](DeadlockProofMutex::lock) waits until the
+//! holder lets go, however long that takes. When the holder may be wedged,
+//! say during shutdown, waiters should use
+//! [`lock_cancellable`](DeadlockProofMutex::lock_cancellable) with a
+//! [`LockCancellation`] the coordinating thread keeps a clone of. Once that
+//! clone is [cancelled](LockCancellation::cancel), every acquisition with the
+//! token gives up promptly and returns its permission in
+//! [`CancellableLockError::Cancelled`], so the thread can still lock other
+//! mutexes on its way out.
+//!
+//! A cancellable acquisition polls the mutex, pausing up to a millisecond
+//! between attempts, rather than queueing on it. Cancelling wakes the
+//! paused waiters right away.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::{self, Thread},
+    time::Duration,
+};
+
+use crate::{rt::TryLockError, DeadlockProofMutex, DeadlockProofMutexGuard, MutexPermission};
+
+/// Longest pause between two attempts of a cancellable acquisition.
+const MAX_PAUSE: Duration = Duration::from_millis(1);
+
+/// Calls off the acquisitions made with it, from any thread. Clones share
+/// the same state, and cancelling is for good. See the
+/// [module docs](self).
+#[derive(Clone, Default)]
+pub struct LockCancellation(Arc<State>);
+
+#[derive(Default)]
+struct State {
+    cancelled: AtomicBool,
+    /// Threads inside an acquisition with this token, to wake on cancel.
+    waiting: Mutex<Vec<Thread>>,
+}
+
+impl LockCancellation {
+    /// Makes every acquisition with this token, current and future, give up.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Release);
+        for thread in self.waiting().iter() {
+            thread.unpark();
+        }
+    }
+
+    /// Whether [`cancel`](Self::cancel) was called on this token or a clone.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+
+    fn waiting(&self) -> MutexGuard<'_, Vec<Thread>> {
+        self.0
+            .waiting
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for LockCancellation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockCancellation")
+            .field("cancelled", &self.is_cancelled())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Why [`DeadlockProofMutex::lock_cancellable`] did not get the lock. Either
+/// way the permission comes back.
+#[non_exhaustive]
+pub enum CancellableLockError<P> {
+    /// The token was cancelled.
+    Cancelled(P),
+    /// The mutex is poisoned, as with [`DeadlockProofMutex::lock`].
+    #[cfg(not(feature = "no-poison"))]
+    Poisoned(P),
+}
+
+impl<P> CancellableLockError<P> {
+    /// The permission that was passed in.
+    pub fn into_permission(self) -> P {
+        match self {
+            CancellableLockError::Cancelled(permission) => permission,
+            #[cfg(not(feature = "no-poison"))]
+            CancellableLockError::Poisoned(permission) => permission,
+        }
+    }
+}
+
+impl<P> fmt::Debug for CancellableLockError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CancellableLockError::Cancelled(_) => f.write_str("Cancelled(..)"),
+            #[cfg(not(feature = "no-poison"))]
+            CancellableLockError::Poisoned(_) => f.write_str("Poisoned(..)"),
+        }
+    }
+}
+
+impl<P> fmt::Display for CancellableLockError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CancellableLockError::Cancelled(_) => f.write_str("lock acquisition was cancelled"),
+            #[cfg(not(feature = "no-poison"))]
+            CancellableLockError::Poisoned(_) => f.write_str("deadlock-proof mutex is poisoned"),
+        }
+    }
+}
+
+impl<P> std::error::Error for CancellableLockError<P> {}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// A fresh token for [`lock_cancellable`](Self::lock_cancellable). It is
+    /// not tied to this mutex, so one token can call off waits on several.
+    pub fn cancellation_token(&self) -> LockCancellation {
+        LockCancellation::default()
+    }
+
+    /// Like [`lock`](Self::lock), but gives up once `cancellation` is
+    /// cancelled, or right away if it already is. Counts as a waiter while
+    /// it waits, under `tracking`.
+    #[track_caller]
+    pub fn lock_cancellable(
+        &self,
+        mut permission: P,
+        cancellation: &LockCancellation,
+    ) -> Result<DeadlockProofMutexGuard<'_, T, P, I>, CancellableLockError<P>> {
+        let current = thread::current();
+        let id = current.id();
+        cancellation.waiting().push(current);
+        #[cfg(feature = "tracking")]
+        self.waiters.fetch_add(1, Ordering::Relaxed);
+
+        let mut pause = Duration::from_micros(1);
+        let result = loop {
+            if cancellation.is_cancelled() {
+                break Err(CancellableLockError::Cancelled(permission));
+            }
+            match self.try_guard(permission) {
+                Ok(guard) => break Ok(guard),
+                Err(TryLockError::WouldBlock(returned)) => permission = returned,
+                #[cfg(not(feature = "no-poison"))]
+                Err(TryLockError::Poisoned(returned)) => {
+                    break Err(CancellableLockError::Poisoned(returned));
+                }
+            }
+            thread::park_timeout(pause);
+            pause = (pause * 2).min(MAX_PAUSE);
+        };
+
+        #[cfg(feature = "tracking")]
+        self.waiters.fetch_sub(1, Ordering::Relaxed);
+        let mut waiting = cancellation.waiting();
+        if let Some(index) = waiting.iter().position(|thread| thread.id() == id) {
+            waiting.swap_remove(index);
+        }
+        result
+    }
+}
@@ -0,0 +1,85 @@
+//! Moving data from one critical section of a sequential walk to the next.
+//!
+//! A walk often computes something under one lock that the next lock's
+//! critical section needs, e.g. the next hop found at the IP layer. Wrapping
+//! it in a [`SequentialCarry`] together with the sequential permission keeps
+//! that hand-off visible in the types instead of in a stray local variable.
+
+use std::ops::{Deref, DerefMut};
+
+use crate::{
+    poison, DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofPoisonError, LockResult,
+    MutexPermission, SequentialMutexPermission,
+};
+
+/// A payload `X` travelling along with the sequential permission past `I`.
+/// Created by [`DeadlockProofMutexGuard::unlock_for_sequential_with`] and
+/// consumed by [`DeadlockProofMutex::lock_with_carry`].
+pub struct SequentialCarry<P: MutexPermission, I: 'static, X> {
+    permission: SequentialMutexPermission<P, I>,
+    payload: X,
+}
+
+impl<P: MutexPermission, I: 'static, X> SequentialCarry<P, I, X> {
+    /// Ends the pipeline, splitting the carry into its permission and payload.
+    pub fn into_parts(self) -> (SequentialMutexPermission<P, I>, X) {
+        (self.permission, self.payload)
+    }
+}
+
+impl<P: MutexPermission, I: 'static, X> Deref for SequentialCarry<P, I, X> {
+    type Target = X;
+
+    fn deref(&self) -> &X {
+        &self.payload
+    }
+}
+
+impl<P: MutexPermission, I: 'static, X> DerefMut for SequentialCarry<P, I, X> {
+    fn deref_mut(&mut self) -> &mut X {
+        &mut self.payload
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutexGuard<'_, T, P, I> {
+    /// Like [`unlock_for_sequential`](Self::unlock_for_sequential), but also
+    /// hands `payload` on to the next mutex in the sequence.
+    pub fn unlock_for_sequential_with<X>(self, payload: X) -> SequentialCarry<P, I, X> {
+        SequentialCarry {
+            permission: self.unlock_for_sequential(),
+            payload,
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static, J: 'static>
+    DeadlockProofMutex<T, SequentialMutexPermission<P, I>, J>
+{
+    /// Locks this mutex with the permission in `carry` and runs `f` on the data
+    /// and the payload. Whatever `f` returns is carried on past this mutex,
+    /// which is unlocked again before returning. A poisoned mutex drops the
+    /// payload and fails with the permission still in the guard.
+    #[track_caller]
+    pub fn lock_with_carry<X, Y>(
+        &self,
+        carry: SequentialCarry<P, I, X>,
+        f: impl FnOnce(&mut T, X) -> Y,
+    ) -> CarryResult<'_, T, P, I, J, Y> {
+        let SequentialCarry {
+            permission,
+            payload,
+        } = carry;
+        // An ordinary guard, so the release does what every other does.
+        poison::map!(self.lock(permission), |mut guard| {
+            let payload = f(guard.get_mut(), payload);
+            guard.unlock_for_sequential_with(payload)
+        })
+    }
+}
+
+/// Result of [`DeadlockProofMutex::lock_with_carry`]: the new payload carried on
+/// past `J`, which sits right after `I`.
+pub type CarryResult<'a, T, P, I, J, Y> = LockResult<
+    SequentialCarry<SequentialMutexPermission<P, I>, J, Y>,
+    DeadlockProofPoisonError<'a, T, SequentialMutexPermission<P, I>, J>,
+>;
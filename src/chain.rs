@@ -0,0 +1,125 @@
+//! The levels a permission has been through, for logging where in its
+//! hierarchy a thread is.
+//!
+//! Every permission type is a [`PermissionChain`]: its root, then one level
+//! for each step down, named by the identifier of the mutex that step went
+//! past or inside, without the identifier's module path. Root, nested and
+//! sequential permissions render their chain as their `Display`:
+//!
+//! ```
+//! use deadlock_proof::{LockOutcome, NetworkStack, OuterMutexPermission};
+//!
+//! let stack = NetworkStack::new();
+//! let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+//! let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+//! let permission = device.unlock_for_sequential();
+//! assert_eq!(permission.to_string(), "Outer → IpLock → DeviceLock");
+//! ```
+//!
+//! With the `metrics-exporter` feature,
+//! [`Recorder::record_positioned_histogram`](crate::metrics::Recorder::record_positioned_histogram)
+//! gets each acquisition's position rendered the same way.
+
+use std::fmt;
+
+use crate::{MutexPermission, NestedMutexPermission, OuterMutexPermission, SequentialMutexPermission};
+
+/// The levels of a permission's chain, root first.
+pub trait PermissionChain {
+    /// Calls `f` with the name of each level, starting with the root.
+    fn visit_levels(f: &mut dyn FnMut(&'static str));
+}
+
+impl PermissionChain for OuterMutexPermission {
+    fn visit_levels(f: &mut dyn FnMut(&'static str)) {
+        f("Outer");
+    }
+}
+
+impl<P: MutexPermission, I: 'static> PermissionChain for NestedMutexPermission<P, I> {
+    fn visit_levels(f: &mut dyn FnMut(&'static str)) {
+        P::visit_levels(f);
+        f(identifier_name::<I>());
+    }
+}
+
+impl<P: MutexPermission, I: 'static> PermissionChain for SequentialMutexPermission<P, I> {
+    fn visit_levels(f: &mut dyn FnMut(&'static str)) {
+        P::visit_levels(f);
+        f(identifier_name::<I>());
+    }
+}
+
+/// The type name of `I`, without the module path of the type itself.
+/// Generic arguments keep theirs.
+fn identifier_name<I: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<I>();
+    let path = &name[..name.find('<').unwrap_or(name.len())];
+    path.rfind("::").map_or(name, |index| &name[index + 2..])
+}
+
+/// Writes the chain of `P` as `Outer → IpLock → DeviceLock`.
+fn write<P: PermissionChain + ?Sized>(f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut result = Ok(());
+    let mut first = true;
+    P::visit_levels(&mut |level| {
+        if result.is_ok() {
+            result = match first {
+                true => f.write_str(level),
+                false => write!(f, " → {level}"),
+            };
+        }
+        first = false;
+    });
+    result
+}
+
+/// The chain of `P`, rendered and leaked once per permission type, for
+/// recorders that want `&'static str` labels. Takes a process-wide lock, so
+/// each mutex keeps what it gets rather than call this per acquisition.
+#[cfg(feature = "metrics-exporter")]
+pub(crate) fn rendered<P: PermissionChain + 'static>() -> &'static str {
+    use std::{
+        any::TypeId,
+        collections::HashMap,
+        marker::PhantomData,
+        sync::{Mutex, OnceLock, PoisonError},
+    };
+
+    /// [`write`] as a value, since there is no permission at hand.
+    struct Chain<P: ?Sized>(PhantomData<P>);
+
+    impl<P: PermissionChain + ?Sized> fmt::Display for Chain<P> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write::<P>(f)
+        }
+    }
+
+    static RENDERED: OnceLock<Mutex<HashMap<TypeId, &'static str>>> = OnceLock::new();
+
+    let mut rendered = RENDERED
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    rendered
+        .entry(TypeId::of::<P>())
+        .or_insert_with(|| Box::leak(Chain::<P>(PhantomData).to_string().into_boxed_str()))
+}
+
+impl fmt::Display for OuterMutexPermission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write::<Self>(f)
+    }
+}
+
+impl<P: MutexPermission, I: 'static> fmt::Display for NestedMutexPermission<P, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write::<Self>(f)
+    }
+}
+
+impl<P: MutexPermission, I: 'static> fmt::Display for SequentialMutexPermission<P, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write::<Self>(f)
+    }
+}
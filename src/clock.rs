@@ -0,0 +1,181 @@
+//! The time every timed feature of the crate reads.
+//!
+//! Timed locks, walk budgets, pool leases, backoff, teardown deadlines,
+//! holder records and wait metrics take the time from [`now`] rather than
+//! from [`Instant::now`], and wait through the same clock rather than
+//! [`thread::sleep`]. Without the `test-clock` feature that is the real
+//! clock. With it, a test can [`pause`] the clock and move it on by hand,
+//! so that a five second deadline passes in no time and always at the same
+//! point:
+//!
+//! ```
+//! # #[cfg(feature = "test-clock")] {
+//! use std::time::Duration;
+//!
+//! use deadlock_proof::{clock, NetworkStack};
+//!
+//! let stack = std::sync::Arc::new(NetworkStack::new());
+//! let _paused = clock::pause();
+//! let started = clock::now();
+//! // Another reference keeps the stack shared, so this waits out its deadline.
+//! let shared = std::sync::Arc::clone(&stack);
+//! assert!(stack.teardown_when_idle(started + Duration::from_secs(5)).is_err());
+//! assert!(clock::now() >= started + Duration::from_secs(5));
+//! # drop(shared);
+//! # }
+//! ```
+//!
+//! While paused, every wait moves the clock on by its length and returns at
+//! once, and [`set_auto_advance`] makes every reading move it on as well.
+//! The clock is global, so a pause also serializes: a second `pause`
+//! blocks until the first [`PausedClock`] is dropped, which lets the tests
+//! of one binary pause it without seeing each other's time. Dropping it
+//! puts the real clock back.
+
+#[cfg(feature = "test-clock")]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex, MutexGuard, PoisonError,
+};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Where the time comes from.
+pub(crate) trait Clock: Sync {
+    fn now(&self) -> Instant;
+
+    /// Waits for `duration` as this clock measures it.
+    fn sleep(&self, duration: Duration);
+}
+
+/// [`Instant::now`] and [`thread::sleep`].
+struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// The clock in effect: the real one unless a test [`pause`]d it.
+fn clock() -> &'static dyn Clock {
+    #[cfg(feature = "test-clock")]
+    if PAUSED.load(Ordering::Acquire) {
+        return &TestClock;
+    }
+    &RealClock
+}
+
+/// The current time, as the crate's timed features see it.
+pub fn now() -> Instant {
+    clock().now()
+}
+
+/// Waits for `duration` by the crate's clock.
+pub(crate) fn sleep(duration: Duration) {
+    clock().sleep(duration);
+}
+
+/// Whether [`TestClock`] is in effect. Only read as a fast path; the time
+/// itself is under [`STATE`].
+#[cfg(feature = "test-clock")]
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "test-clock")]
+static STATE: Mutex<Option<Paused>> = Mutex::new(None);
+
+/// Held by the [`PausedClock`], so one test at a time has the clock.
+#[cfg(feature = "test-clock")]
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[cfg(feature = "test-clock")]
+struct Paused {
+    now: Instant,
+    /// How far each reading moves the clock on.
+    auto_advance: Duration,
+}
+
+#[cfg(feature = "test-clock")]
+fn state() -> MutexGuard<'static, Option<Paused>> {
+    STATE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The clock while a test has it paused.
+#[cfg(feature = "test-clock")]
+struct TestClock;
+
+#[cfg(feature = "test-clock")]
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        match state().as_mut() {
+            Some(paused) => {
+                let now = paused.now;
+                paused.now += paused.auto_advance;
+                now
+            }
+            // Resumed since `PAUSED` was read.
+            None => Instant::now(),
+        }
+    }
+
+    fn sleep(&self, duration: Duration) {
+        match state().as_mut() {
+            Some(paused) => paused.now += duration,
+            None => return thread::sleep(duration),
+        }
+        // Let whatever is being waited for run.
+        thread::yield_now();
+    }
+}
+
+/// The clock stays paused until this is dropped. Returned by [`pause`].
+#[cfg(feature = "test-clock")]
+#[must_use = "the clock resumes when this is dropped"]
+pub struct PausedClock {
+    _serial: MutexGuard<'static, ()>,
+}
+
+#[cfg(feature = "test-clock")]
+impl Drop for PausedClock {
+    fn drop(&mut self) {
+        PAUSED.store(false, Ordering::Release);
+        *state() = None;
+    }
+}
+
+/// Stops the clock at the current time, waiting first for any other
+/// [`PausedClock`] to be dropped. Needs the `test-clock` feature.
+#[cfg(feature = "test-clock")]
+pub fn pause() -> PausedClock {
+    let serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+    *state() = Some(Paused {
+        now: Instant::now(),
+        auto_advance: Duration::ZERO,
+    });
+    PAUSED.store(true, Ordering::Release);
+    PausedClock { _serial: serial }
+}
+
+/// Moves the paused clock on by `duration`.
+///
+/// Panics if the clock is not paused.
+#[cfg(feature = "test-clock")]
+pub fn advance(duration: Duration) {
+    state().as_mut().expect("the clock is not paused").now += duration;
+}
+
+/// Makes every reading of the paused clock move it on by `step` after
+/// reading it, so that code polling the time makes progress on its own.
+/// [`Duration::ZERO`], the default, turns that off again.
+///
+/// Panics if the clock is not paused.
+#[cfg(feature = "test-clock")]
+pub fn set_auto_advance(step: Duration) {
+    state().as_mut().expect("the clock is not paused").auto_advance = step;
+}
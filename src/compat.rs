@@ -0,0 +1,163 @@
+//! Taking either a raw `Mutex` or a deadlock-proof one, while migrating.
+//!
+//! A large codebase cannot switch every mutex at once. A function taking
+//! `&Mutex<Foo>` can take a [`MaybeProofed`] instead, so that callers keep
+//! passing their raw mutexes while the ones already migrated pass a
+//! [`DeadlockProofMutex`]. [`MaybeProofed::lock_compat`] locks either and
+//! hands out a [`CompatGuard`], which gives the permission back on unlock
+//! whichever mutex it locked:
+//!
+//! ```
+//! use std::sync::Mutex;
+//!
+//! use deadlock_proof::{compat::MaybeProofed, *};
+//!
+//! struct CounterLock;
+//! type Counter = DeadlockProofMutex<u64, OuterMutexPermission, CounterLock>;
+//!
+//! fn bump<'a>(
+//!     counter: impl Into<MaybeProofed<'a, u64, OuterMutexPermission, CounterLock>>,
+//!     permission: Option<OuterMutexPermission>,
+//! ) -> Option<OuterMutexPermission> {
+//!     let mut counter = counter.into().lock_compat(permission).guard();
+//!     *counter += 1;
+//!     counter.unlock()
+//! }
+//!
+//! let raw = Mutex::new(0);
+//! let proofed = Counter::new(0, CounterLock);
+//! let permission = bump(&raw, Some(OuterMutexPermission::get()));
+//! let permission = bump(&proofed, permission);
+//! assert!(permission.is_some());
+//! assert_eq!(*raw.lock().unwrap(), 1);
+//! ```
+//!
+//! Once a function only ever gets deadlock-proof mutexes, its parameter goes
+//! back to `&DeadlockProofMutex<..>` and the permission stops being optional.
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{poison, DeadlockProofMutex, DeadlockProofMutexGuard, LockResult, MutexPermission};
+
+/// A raw mutex not migrated yet, or a deadlock-proof one.
+pub enum MaybeProofed<'a, T, P: MutexPermission, I: 'static> {
+    /// A `std` mutex, locked without a permission.
+    Raw(&'a Mutex<T>),
+    /// A migrated mutex, locked with the permission passed in.
+    Proofed(&'a DeadlockProofMutex<T, P, I>),
+}
+
+impl<'a, T, P: MutexPermission, I: 'static> MaybeProofed<'a, T, P, I> {
+    /// The same as `MaybeProofed::from(mutex)`, except that every call site
+    /// gets a deprecation warning. Wrapping raw mutexes through this rather
+    /// than `From` lets the compiler list the call sites left to migrate.
+    #[deprecated(note = "migrate this mutex to `DeadlockProofMutex`")]
+    pub fn unmigrated(mutex: &'a Mutex<T>) -> Self {
+        Self::Raw(mutex)
+    }
+
+    /// Whether the mutex has been migrated.
+    pub fn is_proofed(&self) -> bool {
+        matches!(self, Self::Proofed(_))
+    }
+
+    /// Locks the mutex. A raw mutex ignores the permission and keeps it in
+    /// the guard, to be handed back on unlock; a deadlock-proof one is
+    /// locked with it, and panics if there is none.
+    #[track_caller]
+    pub fn lock_compat(self, permission: Option<P>) -> CompatLockResult<'a, T, P, I> {
+        match self {
+            Self::Raw(mutex) => {
+                #[cfg(not(feature = "no-poison"))]
+                let guard = mutex.lock();
+                // Unchecked, as for deadlock-proof mutexes in this mode.
+                #[cfg(feature = "no-poison")]
+                let guard = mutex.lock().unwrap_or_else(PoisonError::into_inner);
+                poison::map_poisoned(guard, |guard| CompatGuard::Raw(guard, permission))
+            }
+            Self::Proofed(mutex) => {
+                let permission = permission
+                    .expect("a deadlock-proof mutex cannot be locked without a permission");
+                #[cfg(not(feature = "no-poison"))]
+                return match mutex.lock(permission) {
+                    Ok(guard) => Ok(CompatGuard::Proofed(guard)),
+                    Err(poisoned) => Err(PoisonError::new(CompatGuard::Proofed(poisoned.into_guard()))),
+                };
+                #[cfg(feature = "no-poison")]
+                CompatGuard::Proofed(mutex.lock(permission))
+            }
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Clone for MaybeProofed<'_, T, P, I> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Copy for MaybeProofed<'_, T, P, I> {}
+
+impl<'a, T, P: MutexPermission, I: 'static> From<&'a Mutex<T>> for MaybeProofed<'a, T, P, I> {
+    fn from(mutex: &'a Mutex<T>) -> Self {
+        Self::Raw(mutex)
+    }
+}
+
+impl<'a, T, P: MutexPermission, I: 'static> From<&'a DeadlockProofMutex<T, P, I>>
+    for MaybeProofed<'a, T, P, I>
+{
+    fn from(mutex: &'a DeadlockProofMutex<T, P, I>) -> Self {
+        Self::Proofed(mutex)
+    }
+}
+
+/// The guard of whichever mutex [`MaybeProofed::lock_compat`] locked.
+pub enum CompatGuard<'a, T, P: MutexPermission, I: 'static> {
+    /// A raw mutex, with the permission it was handed, if any.
+    Raw(MutexGuard<'a, T>, Option<P>),
+    /// A deadlock-proof mutex.
+    Proofed(DeadlockProofMutexGuard<'a, T, P, I>),
+}
+
+impl<T, P: MutexPermission, I: 'static> CompatGuard<'_, T, P, I> {
+    /// Unlock the mutex and return the permission token it was locked with.
+    pub fn unlock(self) -> Option<P> {
+        match self {
+            Self::Raw(guard, permission) => {
+                drop(guard);
+                permission
+            }
+            Self::Proofed(guard) => Some(guard.unlock()),
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Deref for CompatGuard<'_, T, P, I> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            Self::Raw(guard, _) => guard,
+            Self::Proofed(guard) => guard,
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DerefMut for CompatGuard<'_, T, P, I> {
+    fn deref_mut(&mut self) -> &mut T {
+        match self {
+            Self::Raw(guard, _) => guard,
+            Self::Proofed(guard) => guard,
+        }
+    }
+}
+
+/// Result of [`MaybeProofed::lock_compat`]. Both kinds of mutex report
+/// poisoning with the [`CompatGuard`] they would have handed out, so a
+/// deadlock-proof one still keeps its permission behind its own guard.
+pub type CompatLockResult<'a, T, P, I> =
+    LockResult<CompatGuard<'a, T, P, I>, PoisonError<CompatGuard<'a, T, P, I>>>;
@@ -0,0 +1,126 @@
+//! Running a batch of permission-threaded workers on their own threads.
+//!
+//! Each worker runs on a fresh scoped thread, gets that thread's
+//! [`OuterMutexPermission`], and must hand it back when done, which proves it
+//! released every lock it took. Since the threads are scoped, workers may
+//! borrow from the caller, e.g. a `&NetworkStack` without an `Arc`.
+//!
+//! For threads that outlive the caller, such as a server's accept loop,
+//! [`spawn_with_permission`] does the same for a single detached thread.
+
+use std::{any::Any, fmt, thread};
+
+use crate::OuterMutexPermission;
+
+/// A worker for [`run_all`].
+pub type Worker<'a> = Box<dyn FnOnce(OuterMutexPermission) -> OuterMutexPermission + Send + 'a>;
+
+/// A worker for [`run_all_with`], producing an `R`.
+pub type WorkerWith<'a, R> =
+    Box<dyn FnOnce(OuterMutexPermission) -> (OuterMutexPermission, R) + Send + 'a>;
+
+/// The first worker, by index, that panicked.
+pub struct WorkerPanic {
+    /// Index of the worker in the input.
+    pub index: usize,
+    /// What it panicked with.
+    pub payload: Box<dyn Any + Send>,
+    /// Indices of the other workers that panicked too, in order.
+    pub also_panicked: Vec<usize>,
+}
+
+impl WorkerPanic {
+    /// The panic message, if the payload is a string.
+    pub fn message(&self) -> Option<&str> {
+        match self.payload.downcast_ref::<&'static str>() {
+            Some(message) => Some(message),
+            None => self.payload.downcast_ref::<String>().map(String::as_str),
+        }
+    }
+
+    /// Continues the panic on the current thread with the original payload.
+    pub fn resume(self) -> ! {
+        std::panic::resume_unwind(self.payload)
+    }
+}
+
+impl fmt::Debug for WorkerPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkerPanic")
+            .field("index", &self.index)
+            .field("message", &self.message())
+            .field("also_panicked", &self.also_panicked)
+            .finish()
+    }
+}
+
+impl fmt::Display for WorkerPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "worker {} panicked", self.index)?;
+        if let Some(message) = self.message() {
+            write!(f, ": {message}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for WorkerPanic {}
+
+/// Runs every worker on its own thread and waits for all of them.
+pub fn run_all(workers: Vec<Worker<'_>>) -> Result<(), WorkerPanic> {
+    run_all_with(
+        workers
+            .into_iter()
+            .map(|worker| -> WorkerWith<'_, ()> { Box::new(|permission| (worker(permission), ())) })
+            .collect(),
+    )
+    .map(|_| ())
+}
+
+/// Like [`run_all`], but collects what the workers return, in input order.
+pub fn run_all_with<R: Send>(workers: Vec<WorkerWith<'_, R>>) -> Result<Vec<R>, WorkerPanic> {
+    let outcomes: Vec<thread::Result<R>> = thread::scope(|scope| {
+        let handles: Vec<_> = workers
+            .into_iter()
+            .map(|worker| {
+                scope.spawn(move || {
+                    let (_permission, result) = worker(OuterMutexPermission::get());
+                    result
+                })
+            })
+            .collect();
+        handles.into_iter().map(|handle| handle.join()).collect()
+    });
+
+    let mut results = Vec::with_capacity(outcomes.len());
+    let mut first: Option<WorkerPanic> = None;
+    for (index, outcome) in outcomes.into_iter().enumerate() {
+        match (outcome, &mut first) {
+            (Ok(result), _) => results.push(result),
+            (Err(payload), None) => {
+                first = Some(WorkerPanic {
+                    index,
+                    payload,
+                    also_panicked: Vec::new(),
+                })
+            }
+            (Err(_), Some(first)) => first.also_panicked.push(index),
+        }
+    }
+    match first {
+        Some(panic) => Err(panic),
+        None => Ok(results),
+    }
+}
+
+/// Runs `worker` on a new thread with that thread's permission, which it
+/// hands back along with its result. Unlike [`run_all`] the thread is not
+/// scoped, so `worker` must own what it uses, e.g. an `Arc<NetworkStack>`.
+pub fn spawn_with_permission<R: Send + 'static>(
+    worker: impl FnOnce(OuterMutexPermission) -> (OuterMutexPermission, R) + Send + 'static,
+) -> thread::JoinHandle<R> {
+    thread::spawn(move || {
+        let (_permission, result) = worker(OuterMutexPermission::get());
+        result
+    })
+}
@@ -0,0 +1,113 @@
+//! Construction-time settings for a [`DeadlockProofMutex`].
+//!
+//! Per-mutex settings are gathered in a [`MutexConfig`] passed to
+//! [`DeadlockProofMutex::with_config`], rather than each getting a
+//! constructor of its own. Its fields are private and set with builder
+//! methods, so settings can be added without breaking existing callers:
+//!
+//! ```
+//! use deadlock_proof::{config::MutexConfig, DeadlockProofMutex, OuterMutexPermission};
+//!
+//! struct QueueLock;
+//!
+//! # #[cfg(feature = "tracking")] {
+//! let queue: DeadlockProofMutex<Vec<u32>, OuterMutexPermission, QueueLock> =
+//!     DeadlockProofMutex::with_config(Vec::new(), QueueLock, MutexConfig::new().name("queue"));
+//! # let _ = queue;
+//! # }
+//! ```
+
+#[cfg(feature = "tracking")]
+use crate::ContentionCallback;
+use crate::{DeadlockProofMutex, MutexPermission};
+
+/// Settings for a new mutex. [`MutexConfig::new`] gives the settings of
+/// [`DeadlockProofMutex::new`].
+#[derive(Clone, Copy, Debug)]
+pub struct MutexConfig {
+    #[cfg(feature = "tracking")]
+    name: Option<&'static str>,
+    #[cfg(feature = "tracking")]
+    contention_callback: Option<ContentionCallback>,
+    #[cfg(feature = "adaptive")]
+    spin_budget: u32,
+    #[cfg(feature = "priority")]
+    promotion_threshold: u32,
+}
+
+impl MutexConfig {
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(feature = "tracking")]
+            name: None,
+            #[cfg(feature = "tracking")]
+            contention_callback: None,
+            #[cfg(feature = "adaptive")]
+            spin_budget: crate::adaptive::DEFAULT_SPIN_BUDGET,
+            #[cfg(feature = "priority")]
+            promotion_threshold: crate::priority::DEFAULT_PROMOTION_THRESHOLD,
+        }
+    }
+
+    /// Names the mutex `name`, as
+    /// [`new_named`](DeadlockProofMutex::new_named) does.
+    #[cfg(feature = "tracking")]
+    pub const fn name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Installs `callback` from the start, as
+    /// [`set_contention_callback`](DeadlockProofMutex::set_contention_callback)
+    /// would.
+    #[cfg(feature = "tracking")]
+    pub const fn contention_callback(mut self, callback: ContentionCallback) -> Self {
+        self.contention_callback = Some(callback);
+        self
+    }
+
+    /// Starts with a spin budget of `spins`, as
+    /// [`set_spin_budget`](DeadlockProofMutex::set_spin_budget) would.
+    #[cfg(feature = "adaptive")]
+    pub const fn spin_budget(mut self, spins: u32) -> Self {
+        self.spin_budget = spins;
+        self
+    }
+
+    /// Starts with a promotion threshold of `turns`, as
+    /// [`set_promotion_threshold`](DeadlockProofMutex::set_promotion_threshold)
+    /// would.
+    #[cfg(feature = "priority")]
+    pub const fn promotion_threshold(mut self, turns: u32) -> Self {
+        self.promotion_threshold = turns;
+        self
+    }
+}
+
+impl Default for MutexConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Like [`new`](Self::new), with the settings in `config`.
+    pub fn with_config(content: T, _identifier: I, config: MutexConfig) -> Self {
+        #[cfg(feature = "tracking")]
+        let mutex = Self::unidentified(content, config.name);
+        #[cfg(not(feature = "tracking"))]
+        let mutex = Self::unidentified(content, None);
+        // Only the name is read without `adaptive` or `priority`.
+        #[cfg(not(feature = "tracking"))]
+        let _ = config;
+        #[cfg(feature = "tracking")]
+        if let Some(callback) = config.contention_callback {
+            mutex.set_contention_callback(callback);
+        }
+        #[cfg(feature = "adaptive")]
+        mutex.set_spin_budget(config.spin_budget);
+        #[cfg(feature = "priority")]
+        mutex.set_promotion_threshold(config.promotion_threshold);
+        mutex
+    }
+}
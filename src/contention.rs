@@ -0,0 +1,133 @@
+//! Per-mutex contention callbacks, for schedulers that want to know when a
+//! thread is about to block on a lock.
+//!
+//! Once a callback is installed with
+//! [`set_contention_callback`](DeadlockProofMutex::set_contention_callback),
+//! every blocking acquisition of that mutex tries the lock first. If the try
+//! fails, the callback gets [`ContentionEvent::WillBlock`] before the thread
+//! blocks and [`ContentionEvent::Acquired`] once it holds the lock.
+//! Acquisitions that find the mutex free report nothing. Timed acquisitions
+//! report the same way, except that one that times out never gets to
+//! `Acquired`. Without a callback
+//! the acquisition path is as before but for one atomic load.
+//!
+//! # Reentrancy
+//!
+//! Callbacks run synchronously on the acquiring thread, which holds no
+//! usable permission at that point, and `Acquired` runs with the mutex
+//! already locked. A callback must therefore not lock anything, this mutex
+//! least of all; it should note the event and return, as a scheduler hook
+//! would.
+
+use std::{
+    mem, ptr,
+    sync::{
+        atomic::{AtomicPtr, AtomicUsize, Ordering},
+        Mutex, MutexGuard, TryLockError,
+    },
+    time::Duration,
+};
+
+use crate::{clock, DeadlockProofMutex, MutexPermission};
+
+/// What a contention callback is told.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ContentionEvent {
+    /// The mutex is held elsewhere and the calling thread is about to block.
+    WillBlock {
+        /// The mutex's [name](crate::DeadlockProofMutex::name), or else the type
+        /// name of its identifier.
+        identifier: &'static str,
+        /// Threads inside a blocking acquisition of the mutex, the calling
+        /// one included. As racy as [`DeadlockProofMutex::waiters`].
+        waiters: usize,
+    },
+    /// The thread that reported `WillBlock` now holds the mutex.
+    Acquired {
+        /// The mutex's [name](crate::DeadlockProofMutex::name), or else the type
+        /// name of its identifier.
+        identifier: &'static str,
+        /// How long it blocked.
+        waited: Duration,
+    },
+}
+
+impl ContentionEvent {
+    /// Name of the mutex the event is about, which every event carries: its
+    /// [name](crate::DeadlockProofMutex::name), or else the type name of its
+    /// identifier.
+    pub fn identifier(&self) -> &'static str {
+        match *self {
+            Self::WillBlock { identifier, .. } | Self::Acquired { identifier, .. } => identifier,
+        }
+    }
+}
+
+/// A contention callback.
+pub type ContentionCallback = fn(ContentionEvent);
+
+/// The callback slot of one mutex. Null while none is installed.
+pub(crate) struct ContentionHook(AtomicPtr<()>);
+
+impl ContentionHook {
+    pub(crate) const fn new() -> Self {
+        Self(AtomicPtr::new(ptr::null_mut()))
+    }
+
+    /// The installed callback, for acquisitions that report on their own
+    /// rather than through [`observe`](Self::observe), like the timed ones.
+    pub(crate) fn callback(&self) -> Option<ContentionCallback> {
+        let callback = self.0.load(Ordering::Acquire);
+        // Safety: the only non-null values ever stored are
+        // `ContentionCallback`s, cast to a data pointer of the same size.
+        (!callback.is_null())
+            .then(|| unsafe { mem::transmute::<*mut (), ContentionCallback>(callback) })
+    }
+
+    /// Blocks on `mutex` with `block`, reporting to the installed callback
+    /// if the lock is not free right away.
+    pub(crate) fn observe<'a, T>(
+        &self,
+        identifier: &'static str,
+        mutex: &'a Mutex<T>,
+        waiters: &AtomicUsize,
+        block: impl FnOnce() -> std::sync::LockResult<MutexGuard<'a, T>>,
+    ) -> std::sync::LockResult<MutexGuard<'a, T>> {
+        let Some(callback) = self.callback() else {
+            return block();
+        };
+        match mutex.try_lock() {
+            Ok(guard) => return Ok(guard),
+            Err(TryLockError::Poisoned(poisoned)) => return Err(poisoned),
+            Err(TryLockError::WouldBlock) => {}
+        }
+        callback(ContentionEvent::WillBlock {
+            identifier,
+            waiters: waiters.load(Ordering::Relaxed),
+        });
+        let started = clock::now();
+        let result = block();
+        callback(ContentionEvent::Acquired {
+            identifier,
+            waited: clock::now() - started,
+        });
+        result
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Installs `callback` to be told about contended acquisitions of this
+    /// mutex, replacing any earlier one. See the [module docs](crate::contention)
+    /// for when it runs and what it must not do.
+    pub fn set_contention_callback(&self, callback: ContentionCallback) {
+        self.contention
+            .0
+            .store(callback as *mut (), Ordering::Release);
+    }
+
+    /// Removes the contention callback, if any.
+    pub fn clear_contention_callback(&self) {
+        self.contention.0.store(ptr::null_mut(), Ordering::Release);
+    }
+}
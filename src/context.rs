@@ -0,0 +1,366 @@
+//! Application context carried along with a permission.
+//!
+//! The permission already goes everywhere a lock is taken, so code that
+//! needs a request id or an allocator handle at the same places can put it
+//! in a [`PermissionWith`] rather than pass it as a second argument. The
+//! context stays with the permission while a mutex is held, and comes back
+//! out with it when the mutex is unlocked:
+//!
+//! ```
+//! use deadlock_proof::{LockOutcome, NetworkStack, OuterMutexPermission, PermissionWith};
+//!
+//! let stack = NetworkStack::new();
+//! let permission = PermissionWith::new(OuterMutexPermission::get(), "req-7");
+//! let mut ip = stack.ip_layer.lock_in_context(permission).guard();
+//! ip.packets_processed += 1;
+//! let device = stack.device_layer.lock_in_context(ip.unlock_for_sequential()).guard();
+//! assert_eq!(*device.ctx(), "req-7");
+//! ```
+//!
+//! Mutexes declared with the bare permission take it through
+//! [`lock_in_context`] and [`lock_for_nested_in_context`], which unwrap the
+//! context while the mutex is locked and put it around whatever permission
+//! comes out of it, nested tokens included.
+//!
+//! [`PermissionWith`] is itself a [`MutexPermission`], so a mutex declared
+//! with it accepts it through every lock method, and its sequential
+//! permission keeps the context inside. So does its nested token, from
+//! [`lock_for_nested_with_context`]: the mutexes inside lock with it through
+//! `lock_in_context` and see the context, and the nested guard's
+//! [`unlock_in_context`] puts the permission back together. Plain
+//! `lock_for_nested` leaves the context in the nested guard instead, and
+//! the mutexes inside lock without it:
+//!
+//! ```
+//! use deadlock_proof::*;
+//!
+//! struct TableLock;
+//! struct EntryLock;
+//! type Traced = PermissionWith<OuterMutexPermission, &'static str>;
+//! let table: DeadlockProofMutex<u32, Traced, _> = DeadlockProofMutex::new(0, TableLock);
+//! let entry: DeadlockProofMutex<u32, NestedMutexPermission<OuterMutexPermission, TableLock>, _> =
+//!     DeadlockProofMutex::new(0, EntryLock);
+//!
+//! let permission = PermissionWith::new(OuterMutexPermission::get(), "req-7");
+//! let (table_guard, inside) = table.lock_for_nested_with_context(permission).guard();
+//! let entry_guard = entry.lock_in_context(inside).guard();
+//! assert_eq!(*entry_guard.ctx(), "req-7");
+//! let _permission: Traced = table_guard.unlock_in_context(entry_guard.unlock());
+//! ```
+//!
+//! [`lock_in_context`]: DeadlockProofMutex::lock_in_context
+//! [`lock_for_nested_in_context`]: DeadlockProofMutex::lock_for_nested_in_context
+//! [`lock_for_nested_with_context`]: DeadlockProofMutex::lock_for_nested_with_context
+//! [`unlock_in_context`]: DeadlockProofNestedMutexGuard::unlock_in_context
+
+use std::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+#[cfg(feature = "origin-check")]
+use std::thread::ThreadId;
+
+use crate::{
+    poison, DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard,
+    DeadlockProofPoisonError, LockResult, LocksAs, MutexPermission, NestedMutexPermission, PermissionChain,
+    PermissionDepth, SequentialMutexPermission,
+};
+
+/// Whether a [`PermissionWith`] gives its permission back. Sealed.
+pub trait Attachment: crate::__private::SealedAttachment + 'static {}
+
+/// The permission comes back out with [`PermissionWith::into_parts`]. The
+/// default.
+pub enum Detachable {}
+
+/// The permission never comes back out of its [`PermissionWith`], only
+/// permissions derived from it, each in a `PermissionWith` of its own. This
+/// is how a [`WalkToken`](crate::WalkToken) lends out its permission:
+///
+/// ```compile_fail
+/// use deadlock_proof::*;
+///
+/// let stack = NetworkStack::new();
+/// let mut walk = stack.begin_walk(OuterMutexPermission::get());
+/// walk.with_permission(|permission| -> (WalkPermission, ()) {
+///     let (_outer, ()) = permission.into_parts();
+///     todo!()
+/// });
+/// ```
+pub enum Permanent {}
+
+impl crate::__private::SealedAttachment for Detachable {}
+impl crate::__private::SealedAttachment for Permanent {}
+impl Attachment for Detachable {}
+impl Attachment for Permanent {}
+
+/// A permission `P` together with the context `C` it travels with.
+pub struct PermissionWith<P: MutexPermission, C: 'static, S: Attachment = Detachable> {
+    permission: P,
+    context: C,
+    _attachment: PhantomData<S>,
+}
+
+impl<P: MutexPermission, C: 'static> PermissionWith<P, C> {
+    /// Attaches `context` to `permission`.
+    pub fn new(permission: P, context: C) -> Self {
+        Self::attach(permission, context)
+    }
+
+    /// Separates the permission from its context again.
+    pub fn into_parts(self) -> (P, C) {
+        self.detach()
+    }
+}
+
+impl<P: MutexPermission, C: 'static, S: Attachment> PermissionWith<P, C, S> {
+    pub(crate) fn attach(permission: P, context: C) -> Self {
+        Self {
+            permission,
+            context,
+            _attachment: PhantomData,
+        }
+    }
+
+    /// [`into_parts`](PermissionWith::into_parts) whatever the attachment,
+    /// for the crate's own code.
+    pub(crate) fn detach(self) -> (P, C) {
+        (self.permission, self.context)
+    }
+
+    /// The context.
+    pub fn ctx(&self) -> &C {
+        &self.context
+    }
+
+    /// Mutable access to the context.
+    pub fn ctx_mut(&mut self) -> &mut C {
+        &mut self.context
+    }
+}
+
+impl<P: MutexPermission, I: 'static, C: 'static, S: Attachment>
+    PermissionWith<SequentialMutexPermission<P, I>, C, S>
+{
+    /// [`to_earlier`](SequentialMutexPermission::to_earlier), keeping the
+    /// context.
+    pub fn to_earlier(self) -> PermissionWith<P, C, S> {
+        let (permission, context) = self.detach();
+        PermissionWith::attach(permission.to_earlier(), context)
+    }
+}
+
+impl<P: MutexPermission, C: 'static, S: Attachment> PermissionDepth for PermissionWith<P, C, S> {
+    const DEPTH: usize = P::DEPTH;
+}
+
+impl<P: MutexPermission, C: 'static, S: Attachment> PermissionChain for PermissionWith<P, C, S> {
+    fn visit_levels(f: &mut dyn FnMut(&'static str)) {
+        P::visit_levels(f);
+    }
+}
+
+impl<P: MutexPermission, C: 'static, S: Attachment> MutexPermission for PermissionWith<P, C, S> {
+    /// Recovers the permission; the context is dropped.
+    fn recover(self) {
+        self.permission.recover()
+    }
+
+    #[cfg(feature = "metrics")]
+    fn tag(&self) -> Option<u64> {
+        self.permission.tag()
+    }
+
+    #[cfg(feature = "origin-check")]
+    fn origin(&self) -> Option<ThreadId> {
+        self.permission.origin()
+    }
+}
+
+// The context changes nothing about where a permission stands, so a mutex
+// declared with either one takes the other at the same level.
+impl<P: MutexPermission, I: 'static, C: 'static, S: Attachment> LocksAs<P, I, I>
+    for PermissionWith<P, C, S>
+{
+}
+impl<P: MutexPermission, I: 'static, C: 'static, S: Attachment>
+    LocksAs<PermissionWith<P, C, S>, I, I> for P
+{
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// [`lock`](Self::lock) with a permission that carries a context, which
+    /// the guard holds on to. A poisoned mutex keeps the context in the
+    /// error's guard, with the permission.
+    #[track_caller]
+    pub fn lock_in_context<C: 'static, S: Attachment>(
+        &self,
+        permission: PermissionWith<P, C, S>,
+    ) -> ContextLockResult<'_, T, P, I, C, S> {
+        poison::map!(self.lock_as(permission), |guard| ContextGuard(guard))
+    }
+
+    /// [`lock_for_nested`](Self::lock_for_nested) with a permission that
+    /// carries a context. The context moves on to the nested token, so the
+    /// mutexes locked inside this one get it, and comes back out with the
+    /// token when the nested guard is
+    /// [unlocked](DeadlockProofNestedMutexGuard::unlock_in_context).
+    #[track_caller]
+    pub fn lock_for_nested_in_context<C: 'static>(
+        &self,
+        permission: PermissionWith<P, C>,
+    ) -> ContextNestedLockResult<'_, T, P, I, C> {
+        let (permission, context) = permission.into_parts();
+        poison::map!(self.lock_for_nested(permission), |(guard, nested)| {
+            (guard, PermissionWith::new(nested, context))
+        })
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static, C: 'static, S: Attachment>
+    DeadlockProofMutex<T, PermissionWith<P, C, S>, I>
+{
+    /// [`lock_for_nested`](Self::lock_for_nested) for a mutex declared with
+    /// a [`PermissionWith`], moving the context on to the nested token as
+    /// [`lock_for_nested_in_context`](Self::lock_for_nested_in_context)
+    /// does, so the mutexes locked inside this one get it. The nested guard
+    /// holds the bare permission until
+    /// [`unlock_in_context`](DeadlockProofNestedMutexGuard::unlock_in_context)
+    /// wraps it in the context again.
+    #[track_caller]
+    pub fn lock_for_nested_with_context(
+        &self,
+        permission: PermissionWith<P, C, S>,
+    ) -> ContextNestedLockResult<'_, T, P, I, C, S> {
+        let (permission, context) = permission.detach();
+        poison::map!(self.lock_for_nested_as(permission), |(guard, nested)| {
+            (guard, PermissionWith::attach(nested, context))
+        })
+    }
+}
+
+/// Result of [`DeadlockProofMutex::lock_in_context`].
+pub type ContextLockResult<'a, T, P, I, C, S = Detachable> = LockResult<
+    ContextGuard<'a, T, P, I, C, S>,
+    DeadlockProofPoisonError<'a, T, PermissionWith<P, C, S>, I>,
+>;
+
+/// Result of [`DeadlockProofMutex::lock_for_nested_in_context`] and
+/// [`lock_for_nested_with_context`](DeadlockProofMutex::lock_for_nested_with_context):
+/// the nested guard plus the token for the mutexes inside it, which has the
+/// context.
+pub type ContextNestedLockResult<'a, T, P, I, C, S = Detachable> = LockResult<
+    (
+        DeadlockProofNestedMutexGuard<'a, T, P, I>,
+        PermissionWith<NestedMutexPermission<P, I>, C, S>,
+    ),
+    DeadlockProofPoisonError<'a, T, P, I>,
+>;
+
+/// A [`DeadlockProofMutexGuard`] holding the context of the permission it
+/// was locked with. Created by [`DeadlockProofMutex::lock_in_context`].
+pub struct ContextGuard<'a, T, P: MutexPermission, I: 'static, C: 'static, S: Attachment = Detachable>(
+    DeadlockProofMutexGuard<'a, T, PermissionWith<P, C, S>, I>,
+);
+
+impl<T, P: MutexPermission, I: 'static, C: 'static, S: Attachment> ContextGuard<'_, T, P, I, C, S> {
+    /// The context.
+    pub fn ctx(&self) -> &C {
+        self.0.ctx()
+    }
+
+    /// Mutable access to the context.
+    pub fn ctx_mut(&mut self) -> &mut C {
+        self.0.ctx_mut()
+    }
+
+    /// Unlock the mutex and return the permission with its context.
+    pub fn unlock(self) -> PermissionWith<P, C, S> {
+        self.0.unlock()
+    }
+
+    /// Unlock the mutex and return a sequential permission token with the
+    /// context.
+    pub fn unlock_for_sequential(self) -> PermissionWith<SequentialMutexPermission<P, I>, C, S> {
+        let (permission, context) = self.0.unlock().detach();
+        PermissionWith::attach(SequentialMutexPermission::new(permission), context)
+    }
+
+    /// Mutable access to the data, as [`DeadlockProofMutexGuard::get_mut`].
+    pub fn get_mut(&mut self) -> &mut T {
+        self.0.get_mut()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static, C: 'static, S: Attachment> Deref
+    for ContextGuard<'_, T, P, I, C, S>
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static, C: 'static, S: Attachment> DerefMut
+    for ContextGuard<'_, T, P, I, C, S>
+{
+    fn deref_mut(&mut self) -> &mut T {
+        self.0.get_mut()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofNestedMutexGuard<'_, T, P, I> {
+    /// [`unlock`](Self::unlock) with the token from
+    /// [`lock_for_nested_in_context`](DeadlockProofMutex::lock_for_nested_in_context)
+    /// or [`lock_for_nested_with_context`](DeadlockProofMutex::lock_for_nested_with_context),
+    /// returning the permission with the context the token carried back.
+    pub fn unlock_in_context<C: 'static, S: Attachment>(
+        self,
+        token: PermissionWith<NestedMutexPermission<P, I>, C, S>,
+    ) -> PermissionWith<P, C, S> {
+        let (token, context) = token.detach();
+        PermissionWith::attach(self.unlock(token), context)
+    }
+
+    /// [`unlock_for_sequential`](Self::unlock_for_sequential) with the token
+    /// from either of those, keeping its context.
+    pub fn unlock_for_sequential_in_context<C: 'static, S: Attachment>(
+        self,
+        token: PermissionWith<NestedMutexPermission<P, I>, C, S>,
+    ) -> PermissionWith<SequentialMutexPermission<P, I>, C, S> {
+        let (token, context) = token.detach();
+        PermissionWith::attach(self.unlock_for_sequential(token), context)
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static, C: 'static, S: Attachment>
+    DeadlockProofMutexGuard<'_, T, PermissionWith<P, C, S>, I>
+{
+    /// The context of the permission this guard holds, for mutexes declared
+    /// with a [`PermissionWith`].
+    pub fn ctx(&self) -> &C {
+        self.1.ctx()
+    }
+
+    /// Mutable access to the context of the permission this guard holds.
+    pub fn ctx_mut(&mut self) -> &mut C {
+        self.1.ctx_mut()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static, C: 'static, S: Attachment>
+    DeadlockProofNestedMutexGuard<'_, T, PermissionWith<P, C, S>, I>
+{
+    /// The context of the permission this nested guard holds, which the
+    /// token from `lock_for_nested` does not carry.
+    pub fn ctx(&self) -> &C {
+        self.1.ctx()
+    }
+
+    /// Mutable access to the context of the permission this nested guard
+    /// holds.
+    pub fn ctx_mut(&mut self) -> &mut C {
+        self.1.ctx_mut()
+    }
+}
@@ -0,0 +1,175 @@
+//! A flat chain representation for deep hierarchies.
+//!
+//! A [`SequentialMutexPermission`](crate::SequentialMutexPermission) chain
+//! nests one type per level, so the permission of the tenth level of a
+//! hierarchy is ten types deep and so is every error message mentioning it.
+//! [`lock_hierarchy!`](crate::lock_hierarchy) therefore switches to
+//! [`DeepSequentialPermission`] once a hierarchy has more than
+//! [`DEEP_HIERARCHY_THRESHOLD`] levels: level 0 is locked with the root
+//! permission and level `k` with `DeepSequentialPermission<Root, k>`,
+//! whatever `k` is.
+//!
+//! Walking down is [`advance`](crate::DeadlockProofMutexGuard::advance) on
+//! the guard of the current level, which unlocks it and steps on by exactly
+//! one level. A level can only be left out explicitly, with
+//! [`skip_to`](DeepSequentialPermission::skip_to); passing the wrong
+//! permission is still a type error:
+//!
+//! ```compile_fail
+//! use deadlock_proof::*;
+//!
+//! struct L0; struct L1; struct L2; struct L3; struct L4;
+//! struct L5; struct L6; struct L7; struct L8; struct L9;
+//! lock_hierarchy!(OuterMutexPermission => L0, L1, L2, L3, L4, L5, L6, L7, L8, L9);
+//!
+//! let l0: DeadlockProofMutex<(), Position<L0>, L0> = DeadlockProofMutex::new((), L0);
+//! let l2: DeadlockProofMutex<(), Position<L2>, L2> = DeadlockProofMutex::new((), L2);
+//! let guard = l0.lock(OuterMutexPermission::get()).guard();
+//! let permission = DeepSequentialPermission::after_root(guard);
+//! l2.lock(permission);
+//! ```
+//!
+//! Chains are told apart by their root permission and depth only, so two
+//! deep hierarchies under the same root share permission types level by
+//! level. That is still deadlock-free, since a sequential permission is only
+//! ever held by one guard at a time, but it is a weaker check than the nested
+//! form's.
+
+use std::marker::PhantomData;
+
+use crate::{
+    DeadlockProofMutexGuard, LockLevel, MutexPermission, NamespacePermission, PermissionChain,
+    PermissionDepth,
+};
+
+/// Hierarchies with more levels than this use [`DeepSequentialPermission`].
+pub const DEEP_HIERARCHY_THRESHOLD: usize = 8;
+
+/// The position at depth `DEPTH` of a deep hierarchy rooted at `Root`.
+#[repr(transparent)]
+pub struct DeepSequentialPermission<Root: MutexPermission, const DEPTH: usize> {
+    root: Root,
+    _depth: PhantomData<[(); DEPTH]>,
+}
+
+impl<Root: MutexPermission, const DEPTH: usize> MutexPermission
+    for DeepSequentialPermission<Root, DEPTH>
+{
+    fn recover(self) {
+        self.root.recover();
+    }
+
+    #[cfg(feature = "metrics")]
+    fn tag(&self) -> Option<u64> {
+        self.root.tag()
+    }
+
+    #[cfg(feature = "origin-check")]
+    fn origin(&self) -> Option<std::thread::ThreadId> {
+        self.root.origin()
+    }
+}
+
+impl<Root: MutexPermission, const DEPTH: usize> PermissionDepth
+    for DeepSequentialPermission<Root, DEPTH>
+{
+    const DEPTH: usize = Root::DEPTH + DEPTH;
+}
+
+/// The root's levels, then one per level passed. Their identifiers are not
+/// part of the type, so they go by number, up to the deepest level
+/// [`advance`](crate::DeadlockProofMutexGuard::advance) reaches.
+impl<Root: MutexPermission, const DEPTH: usize> PermissionChain
+    for DeepSequentialPermission<Root, DEPTH>
+{
+    fn visit_levels(f: &mut dyn FnMut(&'static str)) {
+        const LEVELS: [&str; 32] = [
+            "level 0", "level 1", "level 2", "level 3", "level 4", "level 5", "level 6",
+            "level 7", "level 8", "level 9", "level 10", "level 11", "level 12", "level 13",
+            "level 14", "level 15", "level 16", "level 17", "level 18", "level 19", "level 20",
+            "level 21", "level 22", "level 23", "level 24", "level 25", "level 26", "level 27",
+            "level 28", "level 29", "level 30", "level 31",
+        ];
+        Root::visit_levels(f);
+        for level in 0..DEPTH {
+            f(LEVELS.get(level).copied().unwrap_or("level ?"));
+        }
+    }
+}
+
+impl<Root: NamespacePermission, const DEPTH: usize> NamespacePermission
+    for DeepSequentialPermission<Root, DEPTH>
+{
+    type In<N: 'static> = DeepSequentialPermission<Root::In<N>, DEPTH>;
+}
+
+impl<Root: MutexPermission, const DEPTH: usize> DeepSequentialPermission<Root, DEPTH> {
+    fn new(root: Root) -> Self {
+        Self {
+            root,
+            _depth: PhantomData,
+        }
+    }
+
+    /// Leaves the hierarchy altogether, returning the root permission.
+    pub fn into_root(self) -> Root {
+        self.root
+    }
+}
+
+impl<Root: NamespacePermission> DeepSequentialPermission<Root, 1> {
+    /// Unlocks level 0, which is locked with the root permission itself, and
+    /// steps on to level 1.
+    pub fn after_root<T, I: LockLevel<Permission = Root>>(
+        guard: DeadlockProofMutexGuard<'_, T, Root, I>,
+    ) -> Self {
+        Self::new(guard.unlock())
+    }
+
+    /// Steps back to level 0.
+    pub fn retreat(self) -> Root {
+        self.root
+    }
+}
+
+macro_rules! deep_steps {
+    ($($depth:literal => $next:literal),* $(,)?) => {
+        $(
+            impl<T, Root: MutexPermission, I: 'static>
+                DeadlockProofMutexGuard<'_, T, DeepSequentialPermission<Root, $depth>, I>
+            {
+                /// Unlock the mutex and step on to the next level of its
+                /// deep hierarchy.
+                pub fn advance(self) -> DeepSequentialPermission<Root, $next> {
+                    DeepSequentialPermission::new(self.unlock().root)
+                }
+            }
+
+            impl<Root: MutexPermission> DeepSequentialPermission<Root, $depth> {
+                /// Steps past the level at this position without locking
+                /// it, as
+                /// [`SequentialMutexPermission::skip_to`](crate::SequentialMutexPermission::skip_to).
+                pub fn skip_to<J: LockLevel<Permission = Self>>(
+                    self,
+                ) -> DeepSequentialPermission<Root, $next> {
+                    DeepSequentialPermission::new(self.root)
+                }
+            }
+
+            impl<Root: MutexPermission> DeepSequentialPermission<Root, $next> {
+                /// Steps back to the previous level.
+                pub fn retreat(self) -> DeepSequentialPermission<Root, $depth> {
+                    DeepSequentialPermission::new(self.root)
+                }
+            }
+        )*
+    };
+}
+
+deep_steps!(
+    1 => 2, 2 => 3, 3 => 4, 4 => 5, 5 => 6, 6 => 7, 7 => 8, 8 => 9, 9 => 10,
+    10 => 11, 11 => 12, 12 => 13, 13 => 14, 14 => 15, 15 => 16, 16 => 17,
+    17 => 18, 18 => 19, 19 => 20, 20 => 21, 21 => 22, 22 => 23, 23 => 24,
+    24 => 25, 25 => 26, 26 => 27, 27 => 28, 28 => 29, 29 => 30, 30 => 31,
+    31 => 32,
+);
@@ -0,0 +1,126 @@
+//! An opt-in cap on the depth of lock hierarchies.
+//!
+//! Every permission type knows how far down its chain it is, as
+//! [`PermissionDepth::DEPTH`]: 0 for a root permission, one more for each
+//! nested or sequential step, and `k` more for the level-`k` position of a
+//! [deep hierarchy](crate::deep). A mutex locked with a permission of depth
+//! `d` sits at depth `d + 1` of its hierarchy.
+//!
+//! With the `max-depth-8` or `max-depth-16` feature, creating a mutex any
+//! deeper than that fails to compile, with an error such as "lock hierarchy
+//! depth 9 exceeds configured maximum 8". The check runs when the
+//! constructor is instantiated for the permission type, so it catches every
+//! mutex a build creates, however its type was spelled. With both features
+//! the smaller bound applies; with neither, depth is unlimited.
+//!
+//! A mutex at depth 9 therefore only builds without `max-depth-8`:
+//!
+#![cfg_attr(feature = "max-depth-8", doc = "```compile_fail")]
+#![cfg_attr(not(feature = "max-depth-8"), doc = "```")]
+//! use deadlock_proof::*;
+//!
+//! struct L0; struct L1; struct L2; struct L3; struct L4;
+//! struct L5; struct L6; struct L7; struct L8;
+//! lock_hierarchy!(OuterMutexPermission => L0, L1, L2, L3, L4, L5, L6, L7, L8);
+//!
+//! let l8: DeadlockProofMutex<(), Position<L8>, L8> = DeadlockProofMutex::new((), L8);
+//! # let _ = l8;
+//! ```
+
+use std::marker::PhantomData;
+
+use crate::{
+    MutexPermission, NestedMutexPermission, OuterMutexPermission, SequentialMutexPermission,
+};
+
+/// How many steps a permission is from the root of its chain.
+pub trait PermissionDepth {
+    /// 0 for a root permission.
+    const DEPTH: usize;
+}
+
+impl PermissionDepth for OuterMutexPermission {
+    const DEPTH: usize = 0;
+}
+
+impl<P: MutexPermission, I: 'static> PermissionDepth for NestedMutexPermission<P, I> {
+    const DEPTH: usize = P::DEPTH + 1;
+}
+
+impl<P: MutexPermission, I: 'static> PermissionDepth for SequentialMutexPermission<P, I> {
+    const DEPTH: usize = P::DEPTH + 1;
+}
+
+/// The deepest a mutex may be in its hierarchy, as set by the `max-depth-*`
+/// features. `None` without any of them.
+pub const MAX_HIERARCHY_DEPTH: Option<usize> = if cfg!(feature = "max-depth-8") {
+    Some(8)
+} else if cfg!(feature = "max-depth-16") {
+    Some(16)
+} else {
+    None
+};
+
+/// Evaluated by the lock constructors, so that a permission chain that is
+/// too deep is a compile-time error.
+pub(crate) struct DepthCheck<P>(PhantomData<P>);
+
+impl<P: PermissionDepth> DepthCheck<P> {
+    pub(crate) const WITHIN_MAX: () = match MAX_HIERARCHY_DEPTH {
+        Some(max) if P::DEPTH >= max => {
+            panic!("{}", Message::too_deep(P::DEPTH + 1, max).as_str())
+        }
+        _ => (),
+    };
+}
+
+/// A panic message built at compile time, since const panics cannot format
+/// numbers.
+struct Message {
+    bytes: [u8; 96],
+    len: usize,
+}
+
+impl Message {
+    const fn too_deep(depth: usize, max: usize) -> Self {
+        let message = Self {
+            bytes: [0; 96],
+            len: 0,
+        };
+        let message = message.push_str("lock hierarchy depth ");
+        let message = message.push_number(depth);
+        let message = message.push_str(" exceeds configured maximum ");
+        message.push_number(max)
+    }
+
+    const fn push_str(mut self, s: &str) -> Self {
+        let s = s.as_bytes();
+        let mut i = 0;
+        while i < s.len() {
+            self.bytes[self.len] = s[i];
+            self.len += 1;
+            i += 1;
+        }
+        self
+    }
+
+    const fn push_number(mut self, n: usize) -> Self {
+        let mut divisor = 1;
+        while n / divisor >= 10 {
+            divisor *= 10;
+        }
+        while divisor > 0 {
+            self.bytes[self.len] = b'0' + (n / divisor % 10) as u8;
+            self.len += 1;
+            divisor /= 10;
+        }
+        self
+    }
+
+    const fn as_str(&self) -> &str {
+        match std::str::from_utf8(self.bytes.split_at(self.len).0) {
+            Ok(s) => s,
+            Err(_) => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Who holds which mutex right now, enabled by the `diagnostics` feature.
+//!
+//! Every guard of a [`DeadlockProofMutex`] records its holder next to the
+//! mutex as it is acquired, and clears the record right before it unlocks.
+//! [`current_holder`](DeadlockProofMutex::current_holder) reads the record
+//! of one mutex from any thread, with no permission needed. Every mutex is
+//! also registered, weakly, in a process-wide list, so that when something
+//! hangs [`dump_all_held`] can say which mutexes are held, by whom and for
+//! how long, without a debugger:
+//!
+//! ```
+//! use deadlock_proof::{diagnostics, LockOutcome, NetworkStack, OuterMutexPermission};
+//!
+//! let stack = NetworkStack::new();
+//! let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+//! let holder = stack.ip_layer.current_holder().unwrap();
+//! assert_eq!(holder.thread, std::thread::current().id());
+//! assert!(diagnostics::dump_all_held().contains("ip-layer"));
+//! ip.unlock();
+//! assert!(stack.ip_layer.current_holder().is_none());
+//! ```
+//!
+//! Mutexes that belong to a [`HierarchyGeneration`] are listed with its
+//! number, and [`dump_held_in`] lists the mutexes of one generation only.
+//! Closing the generation takes its mutexes out of the list.
+
+use std::{
+    fmt::Write,
+    panic::Location,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError, Weak,
+    },
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use crate::{DeadlockProofMutex, HierarchyGeneration, MutexPermission};
+
+/// The guard that currently holds a mutex.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HolderInfo {
+    pub thread: ThreadId,
+    pub thread_name: Option<String>,
+    /// Where the guard was acquired.
+    pub location: &'static Location<'static>,
+    /// When the guard was acquired.
+    pub since: Instant,
+}
+
+impl HolderInfo {
+    /// How long the mutex has been held so far.
+    pub fn held_for(&self) -> Duration {
+        crate::clock::now().saturating_duration_since(self.since)
+    }
+}
+
+struct Slot {
+    identifier: &'static str,
+    /// The mutex's generation, or zero if it has none.
+    generation: AtomicU64,
+    holder: Mutex<Option<HolderInfo>>,
+}
+
+impl Slot {
+    fn holder(&self) -> MutexGuard<'_, Option<HolderInfo>> {
+        // Nothing runs under this lock that could panic.
+        self.holder.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Every mutex created so far, as long as it lives, and how many of them
+/// were alive at the last sweep.
+struct Registry {
+    slots: Vec<Weak<Slot>>,
+    swept_at: usize,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    slots: Vec::new(),
+    swept_at: 0,
+});
+
+fn registry() -> MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The holder record of one mutex.
+pub(crate) struct HolderSlot(Arc<Slot>);
+
+impl HolderSlot {
+    pub(crate) fn new(identifier: &'static str) -> Self {
+        let slot = Arc::new(Slot {
+            identifier,
+            generation: AtomicU64::new(0),
+            holder: Mutex::new(None),
+        });
+        let mut registry = registry();
+        // Dropped mutexes are swept out whenever the list has doubled, so
+        // registering stays cheap on average.
+        if registry.slots.len() >= 2 * registry.swept_at.max(16) {
+            registry.slots.retain(|slot| slot.strong_count() > 0);
+            registry.swept_at = registry.slots.len();
+        }
+        registry.slots.push(Arc::downgrade(&slot));
+        Self(slot)
+    }
+
+    /// Files the record under `generation`.
+    pub(crate) fn join(&self, generation: u64) {
+        self.0.generation.store(generation, Ordering::Relaxed);
+    }
+
+    /// Records the calling thread as the holder of a guard acquired at
+    /// `location`.
+    pub(crate) fn record(&self, location: &'static Location<'static>) -> Holding<'_> {
+        let current = thread::current();
+        *self.0.holder() = Some(HolderInfo {
+            thread: current.id(),
+            thread_name: current.name().map(str::to_owned),
+            location,
+            since: crate::clock::now(),
+        });
+        Holding(&self.0)
+    }
+}
+
+/// Takes the mutexes of `generation` out of the registry.
+pub(crate) fn deregister(generation: u64) {
+    let mut registry = registry();
+    registry.slots.retain(|slot| {
+        slot.upgrade()
+            .is_some_and(|slot| slot.generation.load(Ordering::Relaxed) != generation)
+    });
+    registry.swept_at = registry.slots.len();
+}
+
+/// How many live mutexes of `generation` are registered.
+pub(crate) fn registered_in(generation: u64) -> usize {
+    live_slots()
+        .iter()
+        .filter(|slot| slot.generation.load(Ordering::Relaxed) == generation)
+        .count()
+}
+
+fn live_slots() -> Vec<Arc<Slot>> {
+    registry().slots.iter().filter_map(Weak::upgrade).collect()
+}
+
+/// The record of a guard that holds its mutex, to be cleared right before
+/// the guard unlocks.
+pub(crate) struct Holding<'a>(&'a Slot);
+
+impl Holding<'_> {
+    pub(crate) fn clear(&self) {
+        // Still locked, so the record is this guard's and nobody else can
+        // have made one yet.
+        *self.0.holder() = None;
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// The guard holding this mutex right now, if any. As racy as any
+    /// observation of another thread's locks; see the
+    /// [module docs](crate::diagnostics).
+    pub fn current_holder(&self) -> Option<HolderInfo> {
+        self.holder.0.holder().clone()
+    }
+}
+
+/// One line per mutex held right now: its [name](DeadlockProofMutex::name)
+/// or identifier, the thread holding it, for how long and where it was
+/// locked, longest held first, after a line with the totals.
+pub fn dump_all_held() -> String {
+    dump(live_slots())
+}
+
+/// [`dump_all_held`], with only the mutexes of `generation`.
+pub fn dump_held_in(generation: &HierarchyGeneration) -> String {
+    let mut slots = live_slots();
+    slots.retain(|slot| slot.generation.load(Ordering::Relaxed) == generation.id());
+    dump(slots)
+}
+
+fn dump(slots: Vec<Arc<Slot>>) -> String {
+    let mut held: Vec<_> = slots
+        .iter()
+        .filter_map(|slot| slot.holder().clone().map(|holder| (slot, holder)))
+        .collect();
+    held.sort_by_key(|(_, holder)| holder.since);
+
+    let mut dump = format!("{} of {} mutexes held\n", held.len(), slots.len());
+    for (slot, holder) in held {
+        let thread = match &holder.thread_name {
+            Some(name) => format!("`{name}`"),
+            None => format!("{:?}", holder.thread),
+        };
+        let _ = write!(dump, "{}", slot.identifier);
+        match slot.generation.load(Ordering::Relaxed) {
+            0 => {}
+            generation => {
+                let _ = write!(dump, " (generation {generation})");
+            }
+        }
+        let _ = writeln!(
+            dump,
+            ": held by {thread} for {:?}, locked at {}",
+            holder.held_for(),
+            holder.location
+        );
+    }
+    dump
+}
@@ -0,0 +1,171 @@
+//! A log of what each critical section changed, for chasing state
+//! corruption after the fact.
+//!
+//! A mutex created with
+//! [`new_with_diff_logging`](DeadlockProofMutex::new_with_diff_logging)
+//! clones its data whenever a guard of it is created, and compares the clone
+//! with the data again right before that guard releases the lock. If they
+//! differ, a [`DiffEntry`] with both states, `Debug`-formatted, goes into a
+//! global ring buffer of the last [`CAPACITY`] changes, which [`recent`]
+//! returns. Other mutexes are not logged: the clone on every acquisition is
+//! too costly to pay everywhere. A mutex with large data can make it cheaper
+//! with a [`SnapshotStrategy`] passed to
+//! [`new_with_diff_logging_by`](DeadlockProofMutex::new_with_diff_logging_by).
+//!
+//! ```
+//! use deadlock_proof::{diff_log, DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+//!
+//! struct Connections;
+//! let connections = DeadlockProofMutex::new_with_diff_logging(5u32, Connections);
+//!
+//! *connections.lock(OuterMutexPermission::get()).guard() = 0;
+//!
+//! let entry = diff_log::recent().pop().unwrap();
+//! assert!(entry.identifier.ends_with("Connections"));
+//! assert_eq!((entry.before.as_str(), entry.after.as_str()), ("5", "0"));
+//! ```
+//!
+//! Guards released while their thread panics are not logged, since
+//! formatting half-updated data could panic again.
+
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    panic::Location,
+    sync::{Mutex, MutexGuard, PoisonError},
+    thread::{self, ThreadId},
+};
+
+use crate::{
+    release::Bookkeeping, snapshot::SnapshotStrategy, DeadlockProofMutex, MutexPermission,
+};
+
+/// How many entries the log keeps. Older ones are dropped first.
+pub const CAPACITY: usize = 256;
+
+static LOG: Mutex<VecDeque<DiffEntry>> = Mutex::new(VecDeque::new());
+
+/// One critical section that changed the data of a logged mutex.
+#[derive(Clone, Debug)]
+pub struct DiffEntry {
+    /// The mutex's [name](crate::DeadlockProofMutex::name), or else the type
+    /// name of its identifier.
+    pub identifier: &'static str,
+    /// The thread that held the guard.
+    pub thread: ThreadId,
+    /// Its name, if it has one.
+    pub thread_name: Option<String>,
+    /// Where the guard was acquired.
+    pub location: &'static Location<'static>,
+    /// The data when the guard was acquired.
+    pub before: String,
+    /// The data when the guard was released.
+    pub after: String,
+}
+
+/// The logged changes, oldest first.
+pub fn recent() -> Vec<DiffEntry> {
+    log().iter().cloned().collect()
+}
+
+/// Empties the log.
+pub fn clear() {
+    log().clear();
+}
+
+fn log() -> MutexGuard<'static, VecDeque<DiffEntry>> {
+    LOG.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn record(entry: DiffEntry) {
+    let mut log = log();
+    if log.len() == CAPACITY {
+        log.pop_front();
+    }
+    log.push_back(entry);
+}
+
+/// How a logged mutex copies, compares and prints its data. Plain function
+/// pointers, so that only the constructor needs the bounds.
+pub(crate) struct Differ<T> {
+    strategy: SnapshotStrategy<T>,
+    debug: fn(&T) -> String,
+}
+
+impl<T: Debug> Differ<T> {
+    fn new(strategy: SnapshotStrategy<T>) -> Self {
+        Self {
+            strategy,
+            debug: |data| format!("{data:?}"),
+        }
+    }
+}
+
+impl<T> Differ<T> {
+    /// The data of a guard acquired at `location`, as it was then.
+    pub(crate) fn snapshot(
+        &self,
+        data: &T,
+        identifier: &'static str,
+        location: &'static Location<'static>,
+    ) -> Snapshot<'_, T> {
+        Snapshot {
+            differ: self,
+            before: self.strategy.take(data),
+            identifier,
+            location,
+        }
+    }
+}
+
+/// What every guard of a logged mutex compares the data with while it still
+/// holds the lock.
+pub(crate) struct Snapshot<'a, T> {
+    differ: &'a Differ<T>,
+    before: T,
+    identifier: &'static str,
+    location: &'static Location<'static>,
+}
+
+impl<T> Snapshot<'_, T> {
+    /// Logs the change from the snapshot to `data`, if there is one.
+    pub(crate) fn compare(self, data: &T) {
+        if thread::panicking() || self.differ.strategy.unchanged(&self.before, data) {
+            return;
+        }
+        let current = thread::current();
+        record(DiffEntry {
+            identifier: self.identifier,
+            thread: current.id(),
+            thread_name: current.name().map(str::to_owned),
+            location: self.location,
+            before: (self.differ.debug)(&self.before),
+            after: (self.differ.debug)(data),
+        });
+    }
+}
+
+impl<T: Clone + PartialEq + Debug, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Like [`new`](Self::new), but every change made under this mutex is
+    /// logged. See the [module docs](self).
+    pub fn new_with_diff_logging(content: T, identifier: I) -> Self {
+        Self::new_with_diff_logging_by(content, identifier, SnapshotStrategy::full())
+    }
+}
+
+impl<T: Debug, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Like [`new_with_diff_logging`](Self::new_with_diff_logging), with
+    /// the snapshots taken and compared by `strategy`, e.g. a
+    /// [shared](SnapshotStrategy::shared) one for large data behind an
+    /// `Arc`.
+    pub fn new_with_diff_logging_by(
+        content: T,
+        identifier: I,
+        strategy: SnapshotStrategy<T>,
+    ) -> Self {
+        let mut mutex = Self::new(content, identifier);
+        mutex.differ = Some(Differ::new(strategy));
+        mutex.versions.bookkeeping.insert(Bookkeeping::DIFF);
+        mutex
+    }
+}
@@ -0,0 +1,83 @@
+//! Formatting the crate's errors and events without allocating.
+//!
+//! The messages of every error and event type, and of the crate's own
+//! panics, are put together with [`core::fmt`] from static strings,
+//! integers, [`Location`](core::panic::Location)s and thread ids. The only
+//! other text in them is text the embedder handed over, such as a thread's
+//! name or a transaction's rejection reason, and it is written as it is.
+//! Identifiers appear as the `&'static str` of their type name. So a
+//! message can be written on a panic or abort path under memory pressure,
+//! or behind an allocator that takes locks of its own.
+//!
+//! [`LockError::write_to`] writes one to any [`fmt::Write`], such as a
+//! fixed buffer on the stack:
+//!
+//! ```
+//! use core::fmt;
+//!
+//! use deadlock_proof::{LockError, TryLockError};
+//!
+//! struct Buffer {
+//!     bytes: [u8; 64],
+//!     len: usize,
+//! }
+//!
+//! impl fmt::Write for Buffer {
+//!     fn write_str(&mut self, s: &str) -> fmt::Result {
+//!         let end = self.len + s.len();
+//!         self.bytes.get_mut(self.len..end).ok_or(fmt::Error)?.copy_from_slice(s.as_bytes());
+//!         self.len = end;
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut buffer = Buffer { bytes: [0; 64], len: 0 };
+//! TryLockError::WouldBlock(()).write_to(&mut buffer).unwrap();
+//! assert_eq!(&buffer.bytes[..buffer.len], b"deadlock-proof mutex is locked");
+//! ```
+//!
+//! The standard library's panic machinery may still allocate when a panic
+//! message has arguments: what is free of allocations is the formatting.
+
+use core::fmt;
+
+use crate::{
+    concurrent::WorkerPanic, lazy::InitPanicked, lease::Abandoned, net_demo::ShuttingDown,
+    permission::{ClaimDiagnostics, Quarantined}, pool::Retirement, soak::Violation,
+    task::DelegationAbandoned, unclaimed::ClaimError, CancellableLockError, ConfigureError,
+    DeadlockProofPoisonError, MisuseEvent, MutexPermission, PinnedLockError, TryLockError,
+    TxAborted,
+};
+
+/// An error or event of this crate, whose message can be written without
+/// allocating. See the [module docs](self).
+pub trait LockError: fmt::Display {
+    /// Writes the message, the same text as [`Display`](fmt::Display), to
+    /// `out`.
+    fn write_to(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        write!(out, "{self}")
+    }
+}
+
+impl<P> LockError for TryLockError<P> {}
+impl<P> LockError for CancellableLockError<P> {}
+impl<T, P: MutexPermission, I: 'static> LockError for PinnedLockError<'_, T, P, I> {}
+impl<T, P: MutexPermission, I: 'static> LockError for DeadlockProofPoisonError<'_, T, P, I> {}
+#[cfg(all(feature = "shared-memory", unix))]
+impl<G, P> LockError for crate::shared_memory::SharedLockError<G, P> {}
+impl<T> LockError for crate::backpressure::Full<T> {}
+impl LockError for TxAborted {}
+impl LockError for InitPanicked {}
+impl LockError for ConfigureError {}
+impl LockError for DelegationAbandoned {}
+impl LockError for ShuttingDown {}
+impl LockError for WorkerPanic {}
+impl LockError for MisuseEvent {}
+impl LockError for ClaimDiagnostics {}
+impl LockError for Quarantined {}
+impl LockError for ClaimError {}
+#[cfg(feature = "tracking")]
+impl LockError for crate::poison_watch::PoisonEvent {}
+impl LockError for Abandoned {}
+impl LockError for Retirement {}
+impl LockError for Violation {}
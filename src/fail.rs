@@ -0,0 +1,116 @@
+//! Failure injection for exercising error paths, enabled by the `test-util`
+//! feature. Never enable it outside of tests.
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{clock, DeadlockProofMutex, MutexPermission};
+
+/// A one-shot artificial delay for the next acquisition of a mutex.
+pub(crate) struct InjectedContention {
+    /// Whether a delay is injected, so that acquisitions without one skip
+    /// the lock below.
+    armed: AtomicBool,
+    pending: Mutex<Option<Pending>>,
+}
+
+enum Pending {
+    /// Injected, but no acquisition has seen it yet.
+    Delay(Duration),
+    /// Seen by an acquisition, and over at this instant.
+    Until(Instant),
+}
+
+impl InjectedContention {
+    pub(crate) const fn new() -> Self {
+        Self {
+            armed: AtomicBool::new(false),
+            pending: Mutex::new(None),
+        }
+    }
+
+    fn inject(&self, delay: Duration) {
+        *self.pending.lock().unwrap_or_else(PoisonError::into_inner) = Some(Pending::Delay(delay));
+        self.armed.store(true, Ordering::Relaxed);
+    }
+
+    /// When the injected delay is over, starting it now if no acquisition
+    /// has seen it yet.
+    fn end(&self) -> Option<Instant> {
+        if !self.armed.load(Ordering::Relaxed) {
+            return None;
+        }
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        let end = match *pending.as_ref()? {
+            Pending::Delay(delay) => clock::now() + delay,
+            Pending::Until(end) => end,
+        };
+        *pending = Some(Pending::Until(end));
+        Some(end)
+    }
+
+    fn clear(&self) {
+        self.armed.store(false, Ordering::Relaxed);
+        *self.pending.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    /// Sleeps for the rest of the injected delay, if any, and clears it.
+    pub(crate) fn wait(&self) {
+        if let Some(end) = self.end() {
+            let now = clock::now();
+            if end > now {
+                clock::sleep(end - now);
+            }
+            self.clear();
+        }
+    }
+
+    /// Whether an injected delay is still running, which the first
+    /// attempt to see it starts. Once it is over it is cleared, and the
+    /// attempt goes ahead.
+    pub(crate) fn pending(&self) -> bool {
+        match self.end() {
+            Some(end) if clock::now() < end => true,
+            Some(_) => {
+                self.clear();
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// Makes the next acquisition of `mutex` block for `delay` before it even
+/// tries the lock, as if another thread were holding it. The blocked thread
+/// counts as a waiter under `tracking`, and the delay shows up in the wait
+/// histogram under `metrics`.
+///
+/// Non-blocking and timed acquisitions see the mutex as held until the
+/// delay, started by the first of them, is over: a `try_lock` fails with
+/// `WouldBlock`, and a timed one times out if its deadline comes first.
+pub fn inject_contention<T, P: MutexPermission, I: 'static>(
+    mutex: &DeadlockProofMutex<T, P, I>,
+    delay: Duration,
+) {
+    mutex.injected_contention.inject(delay);
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Poisons this mutex as if a thread had panicked while holding it. No
+    /// permission is needed and no panic message is printed.
+    ///
+    /// Blocks while another thread holds the mutex.
+    pub fn poison_for_test(&self) {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = self.inner.lock();
+            // Unwinds without running the panic hook.
+            panic::resume_unwind(Box::new("poisoned for test"));
+        }));
+    }
+}
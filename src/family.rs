@@ -0,0 +1,226 @@
+//! Fixed sets of same-level mutexes, declared with
+//! [`declare_mutex_family!`](crate::declare_mutex_family).
+//!
+//! Every member of a family shares one identifier and therefore one
+//! permission level, and members are told apart at runtime by an id enum.
+//! Since holding any member consumes the level's permission, at most one
+//! member is held at a time; the declared order is the canonical order in
+//! which walks over the whole family visit them.
+//!
+//! The one exception is
+//! [`try_lock_available`](MutexFamily::try_lock_available), which holds
+//! every member it could get without waiting. It never blocks while holding
+//! a member, so it cannot be part of a wait cycle:
+//!
+//! ```
+//! use deadlock_proof::{declare_mutex_family, LockOutcome, MutexFamily, OuterMutexPermission};
+//!
+//! declare_mutex_family!(QueueLock: Rx, Tx, Control);
+//! let queues: MutexFamily<Vec<u8>, OuterMutexPermission, QueueLock, QueueLockId> =
+//!     MutexFamily::new(QueueLock, |_| vec![1, 2]);
+//!
+//! let mut available = queues.try_lock_available(OuterMutexPermission::get());
+//! let drained: usize = available.iter_mut().map(|(_, queue)| queue.drain(..).count()).sum();
+//! assert_eq!(drained, 6);
+//! assert!(available.skipped().is_empty());
+//! let _permission = available.unlock_all();
+//! ```
+
+use std::{
+    marker::PhantomData,
+    panic::Location,
+    sync,
+};
+
+use crate::{version, DeadlockProofMutex, Held, InnerGuard, LockOutcome, MutexPermission};
+
+/// The id enum of a mutex family. Implemented by
+/// [`declare_mutex_family!`](crate::declare_mutex_family) only; the trait is
+/// sealed.
+pub trait FamilyId: crate::__private::SealedFamilyId + Copy + 'static {
+    /// Every member, in declared order.
+    const ALL: &'static [Self];
+
+    /// Position of this member in [`ALL`](Self::ALL).
+    fn index(self) -> usize;
+
+    /// `"Family::Member"`, the [name](DeadlockProofMutex::name) of this
+    /// member's mutex.
+    fn name(self) -> &'static str;
+}
+
+/// One mutex per member of the family `Id`, all at the level of `I`.
+pub struct MutexFamily<T, P: MutexPermission, I: 'static, Id: FamilyId> {
+    members: Box<[DeadlockProofMutex<T, P, I>]>,
+    _id: PhantomData<Id>,
+}
+
+impl<T, P: MutexPermission, I: Copy + 'static, Id: FamilyId> MutexFamily<T, P, I, Id> {
+    /// Creates every member, with `init` giving each one its initial data.
+    /// With `tracking`, each member is named after its id, as in
+    /// `"QueueLock::Rx"`.
+    pub fn new(identifier: I, mut init: impl FnMut(Id) -> T) -> Self {
+        #[cfg(feature = "tracking")]
+        let member = |id: Id, data| DeadlockProofMutex::new_named(data, identifier, id.name());
+        #[cfg(not(feature = "tracking"))]
+        let member = |_: Id, data| DeadlockProofMutex::new(data, identifier);
+        Self {
+            members: Id::ALL.iter().map(|&id| member(id, init(id))).collect(),
+            _id: PhantomData,
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static, Id: FamilyId> MutexFamily<T, P, I, Id> {
+    /// The member `id`.
+    pub fn get(&self, id: Id) -> &DeadlockProofMutex<T, P, I> {
+        &self.members[id.index()]
+    }
+
+    /// Every member with its id, in declared order.
+    pub fn iter(&self) -> impl Iterator<Item = (Id, &DeadlockProofMutex<T, P, I>)> {
+        Id::ALL.iter().copied().zip(self.members.iter())
+    }
+
+    /// Locks each member in turn, in declared order, and runs `f` on it. Only
+    /// one member is locked at a time.
+    ///
+    /// Panics if any member is poisoned.
+    pub fn lock_each_in_declared_order(
+        &self,
+        mut permission: P,
+        mut f: impl FnMut(Id, &mut T),
+    ) -> P {
+        for (id, member) in self.iter() {
+            let mut guard = member.lock(permission).guard();
+            f(id, &mut guard);
+            permission = guard.unlock();
+        }
+        permission
+    }
+
+    /// Locks each member in turn, in declared order, and runs `f` on it, as
+    /// [`sweep::for_each_sequential`](crate::sweep::for_each_sequential).
+    ///
+    /// Panics if any member is poisoned.
+    #[track_caller]
+    pub fn sweep(&self, permission: P, f: impl FnMut(&mut T)) -> P {
+        crate::sweep::for_each_sequential(self.members.iter(), permission, f)
+    }
+
+    /// [`sweep`](Self::sweep), but skips the members locked elsewhere,
+    /// which it returns in declared order.
+    ///
+    /// Panics if a member it tries is poisoned.
+    #[track_caller]
+    pub fn try_sweep(&self, permission: P, f: impl FnMut(&mut T)) -> (P, Vec<Id>) {
+        let (permission, skipped) =
+            crate::sweep::try_for_each_sequential(self.members.iter(), permission, f);
+        (permission, skipped.into_iter().map(|index| Id::ALL[index]).collect())
+    }
+
+    /// Locks every member that is free right now, trying each once in
+    /// declared order, and holds them all. Members locked elsewhere are
+    /// [skipped](FamilyGuards::skipped). Acquires nothing if every member is
+    /// taken; the permission is in the returned guards either way.
+    ///
+    /// Panics if a member it tries is poisoned.
+    #[track_caller]
+    pub fn try_lock_available(&self, permission: P) -> FamilyGuards<'_, T, P, Id> {
+        crate::permission::check_origin(&permission);
+        let location = Location::caller();
+        let mut held = Vec::new();
+        let mut skipped = Vec::new();
+        for (id, member) in self.iter() {
+            let guard = match member.inner.try_lock() {
+                Ok(guard) => guard,
+                Err(sync::TryLockError::WouldBlock) => {
+                    skipped.push(id);
+                    continue;
+                }
+                #[cfg(not(feature = "no-poison"))]
+                Err(sync::TryLockError::Poisoned(_)) => {
+                    panic!("a member of a mutex family is poisoned")
+                }
+                #[cfg(feature = "no-poison")]
+                Err(sync::TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            };
+            held.push(HeldMember {
+                id,
+                guard: member.inner_guard(guard, location),
+                dirty: version::Dirty::clean(&member.versions).at(location),
+                _held: Held::new(),
+            });
+        }
+        FamilyGuards {
+            held,
+            skipped,
+            permission: Some(permission),
+        }
+    }
+}
+
+/// One member of a [`FamilyGuards`].
+struct HeldMember<'a, T, Id> {
+    id: Id,
+    guard: InnerGuard<'a, T>,
+    // Declared after the guard so it drops after the unlock.
+    dirty: version::Dirty<'a>,
+    _held: Held,
+}
+
+/// The members [`MutexFamily::try_lock_available`] got, all locked at once.
+/// Dropping it instead of calling [`unlock_all`](Self::unlock_all) hands the
+/// permission to [`MutexPermission::recover`].
+pub struct FamilyGuards<'a, T, P: MutexPermission, Id: FamilyId> {
+    /// In declared order.
+    held: Vec<HeldMember<'a, T, Id>>,
+    skipped: Vec<Id>,
+    /// Only `None` once `unlock_all` has taken it out.
+    permission: Option<P>,
+}
+
+impl<T, P: MutexPermission, Id: FamilyId> FamilyGuards<'_, T, P, Id> {
+    /// How many members are locked.
+    pub fn len(&self) -> usize {
+        self.held.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    /// The members that were locked elsewhere, in declared order.
+    pub fn skipped(&self) -> &[Id] {
+        &self.skipped
+    }
+
+    /// The locked members in declared order.
+    pub fn iter(&self) -> impl Iterator<Item = (Id, &T)> {
+        self.held.iter().map(|member| (member.id, &*member.guard))
+    }
+
+    /// The locked members in declared order, mutably. Every member counts as
+    /// modified when it is released.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Id, &mut T)> {
+        self.held.iter_mut().map(|member| {
+            member.dirty.mark();
+            (member.id, &mut *member.guard)
+        })
+    }
+
+    /// Unlocks every member and returns the permission token.
+    pub fn unlock_all(mut self) -> P {
+        self.permission.take().unwrap()
+    }
+}
+
+impl<T, P: MutexPermission, Id: FamilyId> Drop for FamilyGuards<'_, T, P, Id> {
+    fn drop(&mut self) {
+        // Unlock before the permission can be used again.
+        self.held.clear();
+        if let Some(permission) = self.permission.take() {
+            permission.recover();
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! C bindings, so C code can take part in the same locks as Rust code.
+//!
+//! C cannot carry the permission types, so the rules are checked at runtime
+//! instead. A C thread claims its permission with `dpm_claim_permission`,
+//! which takes the thread's [`OuterMutexPermission`] and therefore excludes
+//! Rust locking on that thread until the permission is released again. While
+//! holding locks through a permission, a thread may only lock mutexes whose
+//! [`LEVEL`](LockLevel::LEVEL) is above every level it already holds, which
+//! is the order every Rust walk uses too. Unlocking is allowed in any order.
+//!
+//! Mutexes are created in Rust and handed to C with [`share`]. The header is
+//! `include/deadlock_proof.h`.
+
+use std::{
+    ffi::{c_int, c_void},
+    mem,
+    sync::{Arc, MutexGuard},
+    thread::{self, ThreadId},
+};
+
+use crate::{
+    permission, poison, DeadlockProofMutex, LockLevel, MutexPermission, OuterMutexPermission,
+    Position,
+};
+
+/// Success.
+pub const DPM_OK: c_int = 0;
+/// A handle argument was null.
+pub const DPM_ERR_NULL: c_int = 1;
+/// The mutex's level is not above every level the permission already holds.
+pub const DPM_ERR_ORDER: c_int = 2;
+/// The mutex is poisoned.
+pub const DPM_ERR_POISONED: c_int = 3;
+/// The permission was claimed on a different thread.
+pub const DPM_ERR_WRONG_THREAD: c_int = 4;
+/// The permission does not hold that mutex.
+pub const DPM_ERR_NOT_HELD: c_int = 5;
+/// The permission still holds locks.
+pub const DPM_ERR_STILL_HELD: c_int = 6;
+
+/// A mutex shared with C. Called `dpm_mutex_t` on the C side.
+pub struct DpmMutex {
+    level: u32,
+    mutex: Arc<dyn ErasedMutex>,
+}
+
+/// A C thread's permission. Called `dpm_permission_t` on the C side.
+pub struct DpmPermission {
+    thread: ThreadId,
+    outer: OuterMutexPermission,
+    held: Vec<Held>,
+}
+
+struct Held {
+    mutex: *const DpmMutex,
+    level: u32,
+    guard: Box<dyn HeldGuard>,
+}
+
+trait ErasedMutex: Send + Sync {
+    /// Locks and returns the data pointer with the guard keeping it locked.
+    fn lock(self: Arc<Self>) -> Option<(*mut c_void, Box<dyn HeldGuard>)>;
+}
+
+trait HeldGuard {
+    fn release(self: Box<Self>);
+}
+
+/// A guard kept alive by the mutex it borrows from.
+struct Locked<T: 'static, P: MutexPermission, I: 'static> {
+    // Declared first so it drops before the `Arc` it borrows from.
+    guard: MutexGuard<'static, T>,
+    mutex: Arc<DeadlockProofMutex<T, P, I>>,
+}
+
+impl<T: Send + 'static, P: MutexPermission, I: Send + Sync + 'static> ErasedMutex
+    for DeadlockProofMutex<T, P, I>
+{
+    fn lock(self: Arc<Self>) -> Option<(*mut c_void, Box<dyn HeldGuard>)> {
+        let mut guard = poison::ok(self.acquire())?;
+        let data = (&mut *guard as *mut T).cast::<c_void>();
+        // Safety: the guard borrows from the mutex behind `self`, which the
+        // `Locked` keeps alive and only drops after the guard.
+        let guard = unsafe { mem::transmute::<MutexGuard<'_, T>, MutexGuard<'static, T>>(guard) };
+        let locked = Locked { guard, mutex: self };
+        Some((data, Box::new(locked)))
+    }
+}
+
+impl<T: 'static, P: MutexPermission, I: 'static> HeldGuard for Locked<T, P, I> {
+    fn release(self: Box<Self>) {
+        let Locked { guard, mutex } = *self;
+        drop(guard);
+        // C writes through a raw pointer, so every C lock counts as a change.
+        mutex.versions.bump();
+    }
+}
+
+/// Shares `mutex` with C. The handle keeps the mutex alive until it is
+/// passed to `dpm_mutex_free`. C sees the data as a `void *`, so `T` should
+/// be `#[repr(C)]`.
+pub fn share<T: Send + 'static, I: LockLevel + Send + Sync>(
+    mutex: Arc<DeadlockProofMutex<T, Position<I>, I>>,
+) -> *mut DpmMutex {
+    Box::into_raw(Box::new(DpmMutex {
+        level: I::LEVEL,
+        mutex,
+    }))
+}
+
+/// Frees a handle made by [`share`]. The mutex must not be locked through it.
+///
+/// # Safety
+///
+/// `mutex` must be null or a handle from [`share`] that is not used again.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dpm_mutex_free(mutex: *mut DpmMutex) {
+    if !mutex.is_null() {
+        // Safety: guaranteed by the caller.
+        drop(unsafe { Box::from_raw(mutex) });
+    }
+}
+
+/// The level of the mutex behind `mutex`.
+///
+/// # Safety
+///
+/// `mutex` must be a live handle from [`share`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dpm_mutex_level(mutex: *const DpmMutex) -> u32 {
+    // Safety: guaranteed by the caller.
+    unsafe { (*mutex).level }
+}
+
+/// Claims the calling thread's permission. Null if the thread's
+/// [`OuterMutexPermission`] has already been taken, by Rust or by C.
+#[unsafe(no_mangle)]
+pub extern "C" fn dpm_claim_permission() -> *mut DpmPermission {
+    match OuterMutexPermission::get_or_diagnose() {
+        Ok(outer) => Box::into_raw(Box::new(DpmPermission {
+            thread: thread::current().id(),
+            outer,
+            held: Vec::new(),
+        })),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Gives the permission back to its thread, so Rust code there can claim it
+/// again. Fails with `DPM_ERR_STILL_HELD`, keeping the permission valid, if
+/// it still holds locks.
+///
+/// # Safety
+///
+/// `permission` must be null or a permission from `dpm_claim_permission`
+/// that is not used again after `DPM_OK` is returned.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dpm_release_permission(permission: *mut DpmPermission) -> c_int {
+    // Safety: guaranteed by the caller.
+    let Some(state) = (unsafe { permission.as_ref() }) else {
+        return DPM_ERR_NULL;
+    };
+    if state.thread != thread::current().id() {
+        return DPM_ERR_WRONG_THREAD;
+    }
+    if !state.held.is_empty() {
+        return DPM_ERR_STILL_HELD;
+    }
+    // Safety: guaranteed by the caller.
+    let state = unsafe { Box::from_raw(permission) };
+    permission::return_token(state.outer);
+    DPM_OK
+}
+
+/// Locks `mutex` and stores a pointer to its data in `*data`. The pointer is
+/// valid until the matching `dpm_unlock`.
+///
+/// # Safety
+///
+/// `mutex` and `permission` must be null or live handles, and `data` must be
+/// null or valid for a write.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dpm_lock(
+    mutex: *const DpmMutex,
+    permission: *mut DpmPermission,
+    data: *mut *mut c_void,
+) -> c_int {
+    // Safety: guaranteed by the caller.
+    let (Some(handle), Some(state)) = (unsafe { mutex.as_ref() }, unsafe { permission.as_mut() })
+    else {
+        return DPM_ERR_NULL;
+    };
+    if state.thread != thread::current().id() {
+        return DPM_ERR_WRONG_THREAD;
+    }
+    if state.held.iter().any(|held| held.level >= handle.level) {
+        return DPM_ERR_ORDER;
+    }
+    let Some((pointer, guard)) = Arc::clone(&handle.mutex).lock() else {
+        return DPM_ERR_POISONED;
+    };
+    state.held.push(Held {
+        mutex,
+        level: handle.level,
+        guard,
+    });
+    if !data.is_null() {
+        // Safety: guaranteed by the caller.
+        unsafe { data.write(pointer) };
+    }
+    DPM_OK
+}
+
+/// Unlocks `mutex`, which `permission` must hold.
+///
+/// # Safety
+///
+/// `mutex` and `permission` must be null or live handles.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dpm_unlock(
+    mutex: *const DpmMutex,
+    permission: *mut DpmPermission,
+) -> c_int {
+    // Safety: guaranteed by the caller.
+    let Some(state) = (unsafe { permission.as_mut() }) else {
+        return DPM_ERR_NULL;
+    };
+    if mutex.is_null() {
+        return DPM_ERR_NULL;
+    }
+    if state.thread != thread::current().id() {
+        return DPM_ERR_WRONG_THREAD;
+    }
+    match state.held.iter().position(|held| held.mutex == mutex) {
+        Some(index) => {
+            state.held.remove(index).guard.release();
+            DPM_OK
+        }
+        None => DPM_ERR_NOT_HELD,
+    }
+}
@@ -0,0 +1,72 @@
+//! Standard traits on the guards, forwarded to the guarded data, so guards
+//! can go where generic code expects `impl AsRef<T>` and the like.
+//!
+//! The mutable forms go through `get_mut`, so handing out `&mut T` this way
+//! counts as a modification just like `DerefMut` does.
+//!
+//! `Borrow<T>` is implemented too. Its contract, that `Eq`, `Ord` and `Hash`
+//! agree between the guard and `T`, holds trivially: guards only compare
+//! against `T` itself, by forwarding, and implement neither `Hash` nor
+//! comparisons between guards.
+
+use std::{
+    borrow::{Borrow, BorrowMut},
+    cmp::Ordering,
+    fmt,
+};
+
+use crate::{DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard, MutexPermission};
+
+macro_rules! forward_to_data {
+    ($($guard:ident),*) => {
+        $(
+            impl<T, P: MutexPermission, I: 'static> AsRef<T> for $guard<'_, T, P, I> {
+                fn as_ref(&self) -> &T {
+                    self
+                }
+            }
+
+            impl<T, P: MutexPermission, I: 'static> AsMut<T> for $guard<'_, T, P, I> {
+                fn as_mut(&mut self) -> &mut T {
+                    self.get_mut()
+                }
+            }
+
+            impl<T, P: MutexPermission, I: 'static> Borrow<T> for $guard<'_, T, P, I> {
+                fn borrow(&self) -> &T {
+                    self
+                }
+            }
+
+            impl<T, P: MutexPermission, I: 'static> BorrowMut<T> for $guard<'_, T, P, I> {
+                fn borrow_mut(&mut self) -> &mut T {
+                    self.get_mut()
+                }
+            }
+
+            impl<T: PartialEq, P: MutexPermission, I: 'static> PartialEq<T> for $guard<'_, T, P, I> {
+                fn eq(&self, other: &T) -> bool {
+                    **self == *other
+                }
+            }
+
+            impl<T: PartialOrd, P: MutexPermission, I: 'static> PartialOrd<T>
+                for $guard<'_, T, P, I>
+            {
+                fn partial_cmp(&self, other: &T) -> Option<Ordering> {
+                    (**self).partial_cmp(other)
+                }
+            }
+
+            impl<T: fmt::Display, P: MutexPermission, I: 'static> fmt::Display
+                for $guard<'_, T, P, I>
+            {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    (**self).fmt(f)
+                }
+            }
+        )*
+    };
+}
+
+forward_to_data!(DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard);
@@ -0,0 +1,516 @@
+//! An interpreter for random lock programs, for fuzzing the public API.
+//!
+//! The typed API makes a wrong lock order fail to compile, so a fuzzer can
+//! only reach it through something that picks the calls at runtime.
+//! [`execute`] runs a program of [`Op`]s against a fresh arena of
+//! [`ARENA_SIZE`] mutexes, two at each level:
+//!
+//! | index | level | locked with |
+//! |-------|-------|-------------|
+//! | 0, 1  | A     | the root permission |
+//! | 2, 3  | B     | the token of a nested A guard |
+//! | 4, 5  | C     | the token of a nested B guard |
+//! | 6, 7  | S     | the sequential permission from an A guard |
+//!
+//! Each thread keeps the permission it currently has and its stack of
+//! guards, and an op only runs if the typed API would accept it in that
+//! state: locking needs exactly the mutex's permission, only the top guard
+//! can be unlocked, and a nested guard only with its token back. Any other
+//! op, including one naming a mutex outside the arena, is skipped. So every
+//! program is valid, and every program must finish.
+//!
+//! Every critical section checks that no other one overlapped it. When a
+//! thread's program ends, its remaining guards are released top first and
+//! it must be left with its root permission if, and only if, it claimed
+//! one. Once every thread is done, every mutex must be unlocked and have
+//! seen each acquisition. A program that runs for longer than
+//! [`DEADLOCK_TIMEOUT`] counts as deadlocked. Any failure panics.
+//!
+//! ```
+//! use deadlock_proof::fuzz_driver::{self, Op};
+//!
+//! let execution = fuzz_driver::execute(&[
+//!     Op::ClaimPermission,
+//!     Op::LockNested(0),
+//!     Op::SpawnThread(vec![Op::ClaimPermission, Op::Lock(1)]),
+//!     Op::Lock(4),
+//!     Op::Lock(2),
+//! ]);
+//! // Level C needs a B token, not an A one.
+//! assert_eq!(execution.skipped, 1);
+//! ```
+//!
+//! Fuzzers that hand out raw bytes go through [`decode`], e.g. as a
+//! `cargo fuzz` target:
+//!
+//! ```ignore
+//! #![no_main]
+//!
+//! use deadlock_proof::fuzz_driver;
+//!
+//! libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+//!     fuzz_driver::execute(&fuzz_driver::decode(data));
+//! });
+//! ```
+
+use std::{
+    panic, slice,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard, LockOutcome,
+    NestedMutexPermission, OuterMutexPermission, SequentialMutexPermission,
+};
+
+/// One step of a program. Mutexes are named by their arena index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Claims the thread's root permission with
+    /// [`OuterMutexPermission::get_or_diagnose`]. Skipped if the thread
+    /// already did.
+    ClaimPermission,
+    /// Locks a mutex with `lock`.
+    Lock(usize),
+    /// Locks a mutex with `lock_for_nested`. Levels C and S have nothing
+    /// nested in them.
+    LockNested(usize),
+    /// Unlocks a mutex, which has to be the top guard.
+    Unlock(usize),
+    /// Unlocks the top guard, a plain A guard, with `unlock_for_sequential`.
+    ToSequential,
+    /// Turns a sequential permission back into the root permission with
+    /// `to_earlier`.
+    ToEarlier,
+    /// Yields the thread, to shake up interleavings.
+    Yield,
+    /// Runs a program on a new thread. It is joined once the spawning
+    /// thread's own program is done and its guards released.
+    SpawnThread(Vec<Op>),
+}
+
+/// What [`execute`] did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Execution {
+    /// Ops that ran, on every thread.
+    pub executed: usize,
+    /// Ops the typed API would have rejected.
+    pub skipped: usize,
+    /// Threads that ran a program, the first one included.
+    pub threads: usize,
+}
+
+impl Execution {
+    fn merge(&mut self, other: Execution) {
+        self.executed += other.executed;
+        self.skipped += other.skipped;
+        self.threads += other.threads;
+    }
+}
+
+/// Number of mutexes in the arena.
+pub const ARENA_SIZE: usize = 4 * WIDTH;
+
+/// How long a program may run before it is taken to be deadlocked.
+pub const DEADLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Mutexes per level.
+const WIDTH: usize = 2;
+
+struct LevelA;
+struct LevelB;
+struct LevelC;
+struct LevelS;
+
+type Root = OuterMutexPermission;
+type InA = NestedMutexPermission<Root, LevelA>;
+type InB = NestedMutexPermission<InA, LevelB>;
+type AfterA = SequentialMutexPermission<Root, LevelA>;
+
+/// An arena index, split into its level and the position within it.
+#[derive(Clone, Copy)]
+enum Slot {
+    A(usize),
+    B(usize),
+    C(usize),
+    S(usize),
+}
+
+impl Slot {
+    fn of(index: usize) -> Option<Self> {
+        let position = index % WIDTH;
+        Some(match index / WIDTH {
+            0 => Slot::A(position),
+            1 => Slot::B(position),
+            2 => Slot::C(position),
+            3 => Slot::S(position),
+            _ => return None,
+        })
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Slot::A(position) => position,
+            Slot::B(position) => WIDTH + position,
+            Slot::C(position) => 2 * WIDTH + position,
+            Slot::S(position) => 3 * WIDTH + position,
+        }
+    }
+}
+
+struct Arena {
+    a: [DeadlockProofMutex<u64, Root, LevelA>; WIDTH],
+    b: [DeadlockProofMutex<u64, InA, LevelB>; WIDTH],
+    c: [DeadlockProofMutex<u64, InB, LevelC>; WIDTH],
+    s: [DeadlockProofMutex<u64, AfterA, LevelS>; WIDTH],
+    /// Acquisitions of each mutex. Only changed under the mutex, alongside
+    /// its data.
+    acquisitions: [AtomicU64; ARENA_SIZE],
+}
+
+impl Arena {
+    fn new() -> Self {
+        Self {
+            a: std::array::from_fn(|_| DeadlockProofMutex::new(0, LevelA)),
+            b: std::array::from_fn(|_| DeadlockProofMutex::new(0, LevelB)),
+            c: std::array::from_fn(|_| DeadlockProofMutex::new(0, LevelC)),
+            s: std::array::from_fn(|_| DeadlockProofMutex::new(0, LevelS)),
+            acquisitions: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    /// Called in every critical section. The data of a mutex and its count
+    /// only disagree if two critical sections overlapped.
+    fn enter(&self, slot: Slot, data: &mut u64) {
+        let acquisitions = &self.acquisitions[slot.index()];
+        let count = acquisitions.load(Ordering::Relaxed);
+        assert_eq!(*data, count, "mutex {} was entered twice", slot.index());
+        *data += 1;
+        acquisitions.store(count + 1, Ordering::Relaxed);
+    }
+
+    fn is_locked(&self, slot: Slot) -> bool {
+        match slot {
+            Slot::A(position) => self.a[position].is_locked(),
+            Slot::B(position) => self.b[position].is_locked(),
+            Slot::C(position) => self.c[position].is_locked(),
+            Slot::S(position) => self.s[position].is_locked(),
+        }
+    }
+
+    /// The checks that only hold once every thread is done. Runs on a thread
+    /// of its own, which locks every mutex once more.
+    fn check_totals(&self) {
+        for index in 0..ARENA_SIZE {
+            let slot = Slot::of(index).unwrap();
+            assert!(!self.is_locked(slot), "mutex {index} is still locked");
+        }
+        // `enter` checks that each count matches the data.
+        let mut permission = Root::get();
+        for position in 0..WIDTH {
+            let (mut a, in_a) = self.a[position].lock_for_nested(permission).guard();
+            self.enter(Slot::A(position), &mut a);
+            let (mut b, in_b) = self.b[position].lock_for_nested(in_a).guard();
+            self.enter(Slot::B(position), &mut b);
+            let mut c = self.c[position].lock(in_b).guard();
+            self.enter(Slot::C(position), &mut c);
+            permission = a.unlock(b.unlock(c.unlock()));
+        }
+        let mut a = self.a[0].lock(permission).guard();
+        self.enter(Slot::A(0), &mut a);
+        let mut after = a.unlock_for_sequential();
+        for position in 0..WIDTH {
+            let mut s = self.s[position].lock(after).guard();
+            self.enter(Slot::S(position), &mut s);
+            after = s.unlock();
+        }
+        let _permission = after.to_earlier();
+    }
+}
+
+/// The permission a thread currently has in hand.
+enum Token {
+    Root(Root),
+    InA(InA),
+    InB(InB),
+    AfterA(AfterA),
+}
+
+/// A guard a thread holds.
+enum Held<'a> {
+    A(usize, DeadlockProofMutexGuard<'a, u64, Root, LevelA>),
+    NestedA(usize, DeadlockProofNestedMutexGuard<'a, u64, Root, LevelA>),
+    B(usize, DeadlockProofMutexGuard<'a, u64, InA, LevelB>),
+    NestedB(usize, DeadlockProofNestedMutexGuard<'a, u64, InA, LevelB>),
+    C(usize, DeadlockProofMutexGuard<'a, u64, InB, LevelC>),
+    S(usize, DeadlockProofMutexGuard<'a, u64, AfterA, LevelS>),
+}
+
+impl Held<'_> {
+    fn slot(&self) -> Slot {
+        match *self {
+            Held::A(position, _) | Held::NestedA(position, _) => Slot::A(position),
+            Held::B(position, _) | Held::NestedB(position, _) => Slot::B(position),
+            Held::C(position, _) => Slot::C(position),
+            Held::S(position, _) => Slot::S(position),
+        }
+    }
+}
+
+/// One thread running its program.
+struct Interpreter<'a> {
+    arena: &'a Arc<Arena>,
+    claimed: bool,
+    token: Option<Token>,
+    held: Vec<Held<'a>>,
+    children: Vec<JoinHandle<Execution>>,
+    execution: Execution,
+}
+
+impl<'a> Interpreter<'a> {
+    fn run(arena: &'a Arc<Arena>, program: &[Op]) -> Execution {
+        let mut interpreter = Self {
+            arena,
+            claimed: false,
+            token: None,
+            held: Vec::new(),
+            children: Vec::new(),
+            execution: Execution {
+                threads: 1,
+                ..Execution::default()
+            },
+        };
+        for op in program {
+            if interpreter.step(op) {
+                interpreter.execution.executed += 1;
+            } else {
+                interpreter.execution.skipped += 1;
+            }
+        }
+        interpreter.finish()
+    }
+
+    /// Runs `op` if the typed API would allow it, and reports whether it
+    /// did.
+    fn step(&mut self, op: &Op) -> bool {
+        match op {
+            Op::ClaimPermission => self.claim(),
+            Op::Lock(index) => Slot::of(*index).is_some_and(|slot| self.lock(slot, false)),
+            Op::LockNested(index) => Slot::of(*index).is_some_and(|slot| self.lock(slot, true)),
+            Op::Unlock(index) => self.unlock(*index),
+            Op::ToSequential => self.unlock_for_sequential(),
+            Op::ToEarlier => self.back_to_root(),
+            Op::Yield => {
+                thread::yield_now();
+                true
+            }
+            Op::SpawnThread(program) => {
+                let arena = Arc::clone(self.arena);
+                let program = program.clone();
+                self.children
+                    .push(thread::spawn(move || Interpreter::run(&arena, &program)));
+                true
+            }
+        }
+    }
+
+    fn claim(&mut self) -> bool {
+        match OuterMutexPermission::get_or_diagnose() {
+            Ok(permission) => {
+                assert!(!self.claimed, "a second claim succeeded");
+                self.claimed = true;
+                self.token = Some(Token::Root(permission));
+                true
+            }
+            Err(diagnostics) => {
+                assert!(self.claimed, "the first claim failed: {diagnostics}");
+                false
+            }
+        }
+    }
+
+    fn lock(&mut self, slot: Slot, nested: bool) -> bool {
+        let arena: &'a Arena = self.arena;
+        let (held, token) = match (slot, self.token.take(), nested) {
+            (Slot::A(position), Some(Token::Root(permission)), false) => {
+                let mut guard = arena.a[position].lock(permission).guard();
+                arena.enter(slot, &mut guard);
+                (Held::A(position, guard), None)
+            }
+            (Slot::A(position), Some(Token::Root(permission)), true) => {
+                let (mut guard, token) = arena.a[position].lock_for_nested(permission).guard();
+                arena.enter(slot, &mut guard);
+                (Held::NestedA(position, guard), Some(Token::InA(token)))
+            }
+            (Slot::B(position), Some(Token::InA(permission)), false) => {
+                let mut guard = arena.b[position].lock(permission).guard();
+                arena.enter(slot, &mut guard);
+                (Held::B(position, guard), None)
+            }
+            (Slot::B(position), Some(Token::InA(permission)), true) => {
+                let (mut guard, token) = arena.b[position].lock_for_nested(permission).guard();
+                arena.enter(slot, &mut guard);
+                (Held::NestedB(position, guard), Some(Token::InB(token)))
+            }
+            (Slot::C(position), Some(Token::InB(permission)), false) => {
+                let mut guard = arena.c[position].lock(permission).guard();
+                arena.enter(slot, &mut guard);
+                (Held::C(position, guard), None)
+            }
+            (Slot::S(position), Some(Token::AfterA(permission)), false) => {
+                let mut guard = arena.s[position].lock(permission).guard();
+                arena.enter(slot, &mut guard);
+                (Held::S(position, guard), None)
+            }
+            (_, token, _) => {
+                self.token = token;
+                return false;
+            }
+        };
+        self.held.push(held);
+        self.token = token;
+        true
+    }
+
+    fn unlock(&mut self, index: usize) -> bool {
+        if self.held.last().map(|held| held.slot().index()) != Some(index) {
+            return false;
+        }
+        let held = self.held.pop().unwrap();
+        self.token = Some(match (held, self.token.take()) {
+            (Held::A(_, guard), None) => Token::Root(guard.unlock()),
+            (Held::NestedA(_, guard), Some(Token::InA(token))) => Token::Root(guard.unlock(token)),
+            (Held::B(_, guard), None) => Token::InA(guard.unlock()),
+            (Held::NestedB(_, guard), Some(Token::InB(token))) => Token::InA(guard.unlock(token)),
+            (Held::C(_, guard), None) => Token::InB(guard.unlock()),
+            (Held::S(_, guard), None) => Token::AfterA(guard.unlock()),
+            (held, token) => {
+                self.held.push(held);
+                self.token = token;
+                return false;
+            }
+        });
+        true
+    }
+
+    /// Only plain guards: a nested A guard's token would outlive it.
+    fn unlock_for_sequential(&mut self) -> bool {
+        if self.token.is_some() || !matches!(self.held.last(), Some(Held::A(..))) {
+            return false;
+        }
+        let Some(Held::A(_, guard)) = self.held.pop() else {
+            unreachable!("checked above")
+        };
+        self.token = Some(Token::AfterA(guard.unlock_for_sequential()));
+        true
+    }
+
+    fn back_to_root(&mut self) -> bool {
+        match self.token.take() {
+            Some(Token::AfterA(permission)) => {
+                self.token = Some(Token::Root(permission.to_earlier()));
+                true
+            }
+            token => {
+                self.token = token;
+                false
+            }
+        }
+    }
+
+    /// Releases whatever is still held, checks that the root permission is
+    /// back, and joins the threads this one spawned.
+    fn finish(mut self) -> Execution {
+        while let Some(held) = self.held.last() {
+            let index = held.slot().index();
+            assert!(self.unlock(index), "could not release mutex {index}");
+        }
+        self.back_to_root();
+        match (self.claimed, &self.token) {
+            (true, Some(Token::Root(_))) | (false, None) => {}
+            (true, _) => panic!("the root permission was not given back"),
+            (false, Some(_)) => panic!("a permission turned up without a claim"),
+        }
+        let mut execution = self.execution;
+        for child in self.children {
+            execution.merge(
+                child
+                    .join()
+                    .unwrap_or_else(|panic| panic::resume_unwind(panic)),
+            );
+        }
+        execution
+    }
+}
+
+/// Runs `program` on a fresh thread against a fresh arena, and checks the
+/// invariants listed in the [module docs](self). Panics if one is broken.
+pub fn execute(program: &[Op]) -> Execution {
+    let arena = Arc::new(Arena::new());
+    let (done, finished) = mpsc::channel();
+    let worker = {
+        let arena = Arc::clone(&arena);
+        let program = program.to_vec();
+        thread::spawn(move || {
+            let execution = Interpreter::run(&arena, &program);
+            let _ = done.send(());
+            execution
+        })
+    };
+    if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(DEADLOCK_TIMEOUT) {
+        panic!("program still running after {DEADLOCK_TIMEOUT:?}: {program:?}");
+    }
+    let execution = worker
+        .join()
+        .unwrap_or_else(|panic| panic::resume_unwind(panic));
+    thread::spawn(move || arena.check_totals())
+        .join()
+        .unwrap_or_else(|panic| panic::resume_unwind(panic));
+    execution
+}
+
+/// Longest program [`decode`] produces, in ops, spawned programs included.
+pub const MAX_DECODED_OPS: usize = 1024;
+
+/// Most threads a decoded program spawns.
+pub const MAX_DECODED_THREADS: usize = 16;
+
+/// Turns arbitrary bytes into a program, one op per byte, for fuzzers that
+/// hand out raw input. The low three bits pick the op and the rest its
+/// argument; indices run past the arena, so that some ops are skipped.
+/// A spawn takes the program up to the next end marker.
+pub fn decode(bytes: &[u8]) -> Vec<Op> {
+    let bytes = &bytes[..bytes.len().min(MAX_DECODED_OPS)];
+    let mut threads_left = MAX_DECODED_THREADS;
+    decode_program(&mut bytes.iter(), &mut threads_left)
+}
+
+fn decode_program(bytes: &mut slice::Iter<'_, u8>, threads_left: &mut usize) -> Vec<Op> {
+    let mut program = Vec::new();
+    while let Some(&byte) = bytes.next() {
+        let argument = usize::from(byte >> 3);
+        let index = argument % (ARENA_SIZE + WIDTH);
+        program.push(match byte & 7 {
+            0 => Op::ClaimPermission,
+            1 => Op::Lock(index),
+            2 => Op::LockNested(index),
+            3 => Op::Unlock(index),
+            4 => Op::ToSequential,
+            5 => Op::ToEarlier,
+            6 => Op::Yield,
+            _ if argument % 2 == 1 => break,
+            _ if *threads_left == 0 => continue,
+            _ => {
+                *threads_left -= 1;
+                Op::SpawnThread(decode_program(bytes, threads_left))
+            }
+        });
+    }
+    program
+}
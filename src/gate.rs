@@ -0,0 +1,153 @@
+//! A lock with no data, for sequencing alone.
+//!
+//! A `DeadlockProofMutex<(), P, I>` used as an ordering point pays for
+//! everything a mutex of data has: poisoning, versions, change listeners,
+//! holder records. An [`OrderingGate`] takes the same permissions and hands
+//! out the same ones, so it can stand at any level of a hierarchy, but is
+//! only the lock itself:
+//!
+//! ```
+//! use deadlock_proof::{
+//!     gate::OrderingGate, lock_hierarchy, DeadlockProofMutex, LockOutcome, OuterMutexPermission,
+//!     Position,
+//! };
+//!
+//! struct Config;
+//! struct Commit;
+//! lock_hierarchy!(OuterMutexPermission => Config, Commit);
+//!
+//! let config = DeadlockProofMutex::<_, Position<Config>, _>::new(0u32, Config);
+//! let commit = OrderingGate::<Position<Commit>, _>::new(Commit);
+//!
+//! let mut guard = config.lock(OuterMutexPermission::get()).guard();
+//! *guard += 1;
+//! let passed = commit.lock(guard.unlock_for_sequential());
+//! let _permission = passed.unlock().to_earlier();
+//! ```
+//!
+//! Since there is no data, a panic while the gate is held leaves nothing
+//! half-updated, so the gate is never poisoned.
+
+use std::{
+    marker::PhantomData,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    permission, MutexPermission, NestedMutexPermission, PermissionSyncSendWrapper,
+    SequentialMutexPermission, TryLockError,
+};
+
+/// A lock at level `I` that guards no data. Takes the permission `P` like a
+/// [`DeadlockProofMutex`](crate::DeadlockProofMutex) would, and hands out
+/// the same permissions as its guards.
+///
+/// The size of a `std::sync::Mutex<()>`, which on Linux is one futex word
+/// and a poison flag the gate never looks at.
+pub struct OrderingGate<P: MutexPermission, I: 'static> {
+    inner: Mutex<()>,
+    _permission: PhantomData<PermissionSyncSendWrapper<P>>,
+    _identifier: PhantomData<I>,
+}
+
+impl<P: MutexPermission, I: 'static> OrderingGate<P, I> {
+    /// Create a new gate, passable by permission `P`. Usable in a `static`.
+    pub const fn new(identifier: I) -> Self {
+        std::mem::forget(identifier);
+        Self {
+            inner: Mutex::new(()),
+            _permission: PhantomData,
+            _identifier: PhantomData,
+        }
+    }
+
+    fn acquire(&self, permission: &P) -> MutexGuard<'_, ()> {
+        permission::check_origin(permission);
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Waits until the gate is free and passes it.
+    pub fn lock(&self, permission: P) -> GateGuard<'_, P, I> {
+        GateGuard(self.acquire(&permission), permission, PhantomData)
+    }
+
+    /// Passes the gate only if it is free right now. The permission comes
+    /// back as [`TryLockError::WouldBlock`] if it is not.
+    pub fn try_lock(&self, permission: P) -> Result<GateGuard<'_, P, I>, TryLockError<P>> {
+        permission::check_origin(&permission);
+        match self.inner.try_lock() {
+            Ok(guard) => Ok(GateGuard(guard, permission, PhantomData)),
+            Err(std::sync::TryLockError::Poisoned(poisoned)) => {
+                Ok(GateGuard(poisoned.into_inner(), permission, PhantomData))
+            }
+            Err(std::sync::TryLockError::WouldBlock) => Err(TryLockError::WouldBlock(permission)),
+        }
+    }
+
+    /// Passes the gate and provides a token for claiming the mutexes nested
+    /// inside it, as [`lock_for_nested`](crate::DeadlockProofMutex::lock_for_nested).
+    pub fn lock_for_nested(
+        &self,
+        permission: P,
+    ) -> (NestedGateGuard<'_, P, I>, NestedMutexPermission<P, I>) {
+        let guard = self.acquire(&permission);
+        let nested = NestedMutexPermission::new(&permission);
+        (NestedGateGuard(guard, permission, PhantomData), nested)
+    }
+
+    /// Whether some guard currently holds the gate. As racy as
+    /// [`DeadlockProofMutex::is_locked`](crate::DeadlockProofMutex::is_locked).
+    pub fn is_locked(&self) -> bool {
+        matches!(
+            self.inner.try_lock(),
+            Err(std::sync::TryLockError::WouldBlock)
+        )
+    }
+}
+
+/// Holds an [`OrderingGate`] until it is unlocked or dropped.
+pub struct GateGuard<'a, P: MutexPermission, I: 'static>(
+    #[allow(dead_code)] // only held to keep the gate locked
+    MutexGuard<'a, ()>,
+    P,
+    PhantomData<I>,
+);
+
+impl<P: MutexPermission, I: 'static> GateGuard<'_, P, I> {
+    /// Unlock the gate and return the permission token.
+    pub fn unlock(self) -> P {
+        self.1
+    }
+
+    /// Unlock the gate and return a sequential permission token.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        SequentialMutexPermission::new(self.1)
+    }
+}
+
+/// Holds an [`OrderingGate`] passed with
+/// [`lock_for_nested`](OrderingGate::lock_for_nested). Like
+/// [`DeadlockProofNestedMutexGuard`](crate::DeadlockProofNestedMutexGuard),
+/// it only unlocks with the nested token back.
+pub struct NestedGateGuard<'a, P: MutexPermission, I: 'static>(
+    #[allow(dead_code)] // only held to keep the gate locked
+    MutexGuard<'a, ()>,
+    P,
+    PhantomData<I>,
+);
+
+impl<P: MutexPermission, I: 'static> NestedGateGuard<'_, P, I> {
+    /// Unlock the gate with the nested permission token.
+    pub fn unlock(self, _token: NestedMutexPermission<P, I>) -> P {
+        self.1
+    }
+
+    /// Unlock the gate with the nested permission token and return a
+    /// sequential permission token.
+    pub fn unlock_for_sequential(
+        self,
+        _token: NestedMutexPermission<P, I>,
+    ) -> SequentialMutexPermission<P, I> {
+        SequentialMutexPermission::new(self.1)
+    }
+}
@@ -0,0 +1,212 @@
+//! Generations of a hierarchy, for processes that tear a hierarchy down and
+//! build it again.
+//!
+//! A [`HierarchyGeneration`] stands for one instance of a hierarchy, such as
+//! one [`NetworkStack`](crate::NetworkStack). Mutexes created with
+//! [`new_in`](DeadlockProofMutex::new_in) join it, and it checks that no two
+//! of them share an identifier type: within one generation, each identifier
+//! names exactly one lock. A second mutex with an identifier the generation
+//! already has is reported as [`MisuseEvent::DuplicateIdentifier`]. The next
+//! generation starts afresh, so the same identifiers can be used again by
+//! the stack that replaces the old one.
+//!
+//! With the `diagnostics` feature the generation also namespaces the
+//! registry behind [`dump_all_held`](crate::diagnostics::dump_all_held):
+//! every line names the generation of its mutex, and
+//! [`dump_held_in`](crate::diagnostics::dump_held_in) lists only the mutexes
+//! of one generation. [`close`](HierarchyGeneration::close) takes the
+//! generation's mutexes out of the registry right away, rather than when
+//! they are dropped, and ends the generation: a mutex that joins it after
+//! that is reported as [`MisuseEvent::ClosedGeneration`].
+//!
+//! ```
+//! use deadlock_proof::{DeadlockProofMutex, HierarchyGeneration, OuterMutexPermission};
+//!
+//! struct TableLock;
+//! for _ in 0..3 {
+//!     let generation = HierarchyGeneration::new();
+//!     let table: DeadlockProofMutex<Vec<u32>, OuterMutexPermission, _> =
+//!         DeadlockProofMutex::new_in(Vec::new(), TableLock, &generation);
+//!     assert_eq!(generation.identifiers(), [std::any::type_name::<TableLock>()]);
+//!     generation.close();
+//!     drop(table);
+//! }
+//! ```
+//!
+//! Without a misuse handler both reports panic; with one, the mutex is
+//! created anyway.
+
+use std::{
+    any::{self, TypeId},
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use crate::{
+    config::MutexConfig, misuse, DeadlockProofMutex, DeadlockProofRwLock, MisuseEvent,
+    MutexPermission,
+};
+
+/// Generation numbers handed out so far. Zero is never handed out.
+static LAST: AtomicU64 = AtomicU64::new(0);
+
+/// One instance of a hierarchy. See the [module docs](self).
+pub struct HierarchyGeneration {
+    id: u64,
+    members: Mutex<Members>,
+}
+
+struct Members {
+    /// Every identifier that joined, in joining order.
+    identifiers: Vec<(TypeId, &'static str)>,
+    closed: bool,
+}
+
+impl HierarchyGeneration {
+    /// Starts a new generation, with a number no earlier one had.
+    pub fn new() -> Self {
+        Self {
+            id: LAST.fetch_add(1, Ordering::Relaxed) + 1,
+            members: Mutex::new(Members {
+                identifiers: Vec::new(),
+                closed: false,
+            }),
+        }
+    }
+
+    /// The generation's number, as shown in diagnostics.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The type names of the identifiers that joined, in joining order.
+    pub fn identifiers(&self) -> Vec<&'static str> {
+        self.members()
+            .identifiers
+            .iter()
+            .map(|&(_, name)| name)
+            .collect()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.members().closed
+    }
+
+    /// Ends the generation and takes its mutexes out of the diagnostics
+    /// registry. Closing again does nothing. Dropping the generation closes
+    /// it too.
+    pub fn close(&self) {
+        let mut members = self.members();
+        if members.closed {
+            return;
+        }
+        members.closed = true;
+        members.identifiers.clear();
+        #[cfg(feature = "diagnostics")]
+        crate::diagnostics::deregister(self.id);
+    }
+
+    /// How many live mutexes of this generation are in the diagnostics
+    /// registry.
+    #[cfg(feature = "diagnostics")]
+    pub fn registered(&self) -> usize {
+        crate::diagnostics::registered_in(self.id)
+    }
+
+    /// Enters identifier `I`, reporting a duplicate or a closed generation.
+    ///
+    /// Panics on either, unless a misuse handler is installed.
+    #[track_caller]
+    fn admit<I: 'static>(&self) {
+        let identifier = any::type_name::<I>();
+        let generation = self.id;
+        let mut members = self.members();
+        let event = if members.closed {
+            MisuseEvent::ClosedGeneration {
+                identifier,
+                generation,
+            }
+        } else if members.identifiers.iter().any(|&(id, _)| id == TypeId::of::<I>()) {
+            MisuseEvent::DuplicateIdentifier {
+                identifier,
+                generation,
+            }
+        } else {
+            members.identifiers.push((TypeId::of::<I>(), identifier));
+            return;
+        };
+        drop(members);
+        if !misuse::report(|| event.clone()) {
+            panic!("{event}");
+        }
+    }
+
+    fn members(&self) -> MutexGuard<'_, Members> {
+        // Nothing runs under this lock that could panic.
+        self.members.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for HierarchyGeneration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for HierarchyGeneration {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl fmt::Debug for HierarchyGeneration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let members = self.members();
+        f.debug_struct("HierarchyGeneration")
+            .field("id", &self.id)
+            .field("identifiers", &members.identifiers.len())
+            .field("closed", &members.closed)
+            .finish()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// [`new`](Self::new), as a member of `generation`. See the
+    /// [module docs](crate::generation).
+    ///
+    /// Panics if the generation already has a mutex identified by `I`, or is
+    /// closed, unless a misuse handler is installed.
+    #[track_caller]
+    pub fn new_in(content: T, identifier: I, generation: &HierarchyGeneration) -> Self {
+        Self::with_config_in(content, identifier, MutexConfig::new(), generation)
+    }
+
+    /// [`with_config`](Self::with_config), as a member of `generation`, as
+    /// with [`new_in`](Self::new_in).
+    #[track_caller]
+    pub fn with_config_in(
+        content: T,
+        identifier: I,
+        config: MutexConfig,
+        generation: &HierarchyGeneration,
+    ) -> Self {
+        generation.admit::<I>();
+        let mutex = Self::with_config(content, identifier, config);
+        #[cfg(feature = "diagnostics")]
+        mutex.holder.join(generation.id);
+        mutex
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofRwLock<T, P, I> {
+    /// [`new`](Self::new), as a member of `generation`, as with
+    /// [`DeadlockProofMutex::new_in`].
+    #[track_caller]
+    pub fn new_in(content: T, identifier: I, generation: &HierarchyGeneration) -> Self {
+        generation.admit::<I>();
+        Self::new(content, identifier)
+    }
+}
@@ -0,0 +1,137 @@
+//! Invariants checked whenever a guard releases its mutex, in debug builds.
+//!
+//! A mutex given an invariant with
+//! [`set_release_invariant`](DeadlockProofMutex::set_release_invariant) runs
+//! it on its data right before each of its guards unlocks, mapped and nested
+//! guards included, so a critical section that leaves the data inconsistent
+//! is caught where it ends rather than wherever the damage shows next. A
+//! violated invariant panics, naming the mutex's identifier, the invariant's
+//! message and where the offending guard was acquired, unless a
+//! [misuse handler](crate::misuse) is installed to report it to instead.
+//!
+//! The check is compiled out without `debug_assertions`: such builds accept
+//! invariants but never run them, and their mutexes and guards stay as small
+//! as before. Guards released while their
+//! thread is already panicking are not checked, as a second panic would
+//! abort.
+//!
+//! In a debug build, this panics when the guard goes; release builds run it
+//! without complaint, so it is only compiled here:
+//!
+//! ```no_run
+//! use deadlock_proof::{DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+//!
+//! struct Budget;
+//! let budget = DeadlockProofMutex::new(10i32, Budget);
+//! budget.set_release_invariant(|left| {
+//!     if *left >= 0 { Ok(()) } else { Err(format!("{left} left")) }
+//! });
+//!
+//! let mut left = budget.lock(OuterMutexPermission::get()).guard();
+//! *left -= 20;
+//! // Release invariant of `..::Budget` violated.
+//! ```
+
+#[cfg(debug_assertions)]
+use std::{
+    marker::PhantomData,
+    mem,
+    panic::Location,
+    sync::atomic::{AtomicPtr, Ordering},
+    thread,
+};
+
+#[cfg(debug_assertions)]
+use crate::MisuseEvent;
+use crate::{DeadlockProofMutex, MutexPermission};
+
+/// An invariant of a mutex's data. The error says what does not hold.
+pub type ReleaseInvariant<T> = fn(&T) -> Result<(), String>;
+
+/// The invariant slot of one mutex. Null while none is set.
+#[cfg(debug_assertions)]
+pub(crate) struct InvariantSlot<T>(AtomicPtr<()>, PhantomData<ReleaseInvariant<T>>);
+
+#[cfg(debug_assertions)]
+impl<T> InvariantSlot<T> {
+    pub(crate) const fn new() -> Self {
+        Self(AtomicPtr::new(std::ptr::null_mut()), PhantomData)
+    }
+
+    fn get(&self) -> Option<ReleaseInvariant<T>> {
+        let invariant = self.0.load(Ordering::Acquire);
+        // Safety: the only non-null values ever stored are
+        // `ReleaseInvariant<T>`s, cast to a data pointer of the same size.
+        (!invariant.is_null())
+            .then(|| unsafe { mem::transmute::<*mut (), ReleaseInvariant<T>>(invariant) })
+    }
+
+    /// What a guard acquired at `location` checks when it is released.
+    pub(crate) fn check(
+        &self,
+        identifier: &'static str,
+        location: &'static Location<'static>,
+    ) -> Option<Check<T>> {
+        self.get().map(|invariant| Check {
+            invariant,
+            identifier,
+            location,
+        })
+    }
+}
+
+/// An invariant to check on release, and what to report if it fails.
+#[cfg(debug_assertions)]
+pub(crate) struct Check<T> {
+    invariant: ReleaseInvariant<T>,
+    identifier: &'static str,
+    location: &'static Location<'static>,
+}
+
+#[cfg(debug_assertions)]
+impl<T> Check<T> {
+    /// What to report about `data`, which a guard that still holds the lock
+    /// is about to release, if anything.
+    pub(crate) fn violation(self, data: &T) -> Option<MisuseEvent> {
+        if thread::panicking() {
+            return None;
+        }
+        let message = (self.invariant)(data).err()?;
+        Some(MisuseEvent::InvariantViolated {
+            identifier: self.identifier,
+            message,
+            locked_at: self.location,
+        })
+    }
+}
+
+/// Reports a violated invariant to the misuse handler, or panics without
+/// one.
+#[cfg(debug_assertions)]
+pub(crate) fn report(event: MisuseEvent) {
+    if !crate::misuse::report(|| event.clone()) {
+        panic!("{event}");
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Sets the invariant every guard of this mutex checks before it
+    /// unlocks, replacing any earlier one. Guards already held check the one
+    /// set when they were acquired. Does nothing without
+    /// `debug_assertions`; see the [module docs](crate::invariant).
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub fn set_release_invariant(&self, invariant: ReleaseInvariant<T>) {
+        #[cfg(debug_assertions)]
+        self.release_invariant
+            .0
+            .store(invariant as *mut (), Ordering::Release);
+    }
+
+    /// Removes the release invariant, if any.
+    pub fn clear_release_invariant(&self) {
+        #[cfg(debug_assertions)]
+        self.release_invariant
+            .0
+            .store(std::ptr::null_mut(), Ordering::Release);
+    }
+}
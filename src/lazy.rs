@@ -0,0 +1,112 @@
+//! Deadlock-proof mutexes in statics, created on first use.
+//!
+//! A [`LazyDeadlockProofMutex`] is const-constructible, so it can be a
+//! `static`, and builds its [`DeadlockProofMutex`] the first time it is
+//! dereferenced, running the initializer exactly once even when many threads
+//! get there together. After that it derefs to the mutex, with every lock
+//! method, nested and sequential ones included, taking the same permissions
+//! as usual:
+//!
+//! ```
+//! use deadlock_proof::{LazyDeadlockProofMutex, LockOutcome, OuterMutexPermission};
+//!
+//! struct RegistryLock;
+//! static REGISTRY: LazyDeadlockProofMutex<Vec<&str>, OuterMutexPermission, RegistryLock> =
+//!     LazyDeadlockProofMutex::new(RegistryLock, || vec!["lo"]);
+//!
+//! let mut registry = REGISTRY.lock(OuterMutexPermission::get()).guard();
+//! registry.push("eth0");
+//! assert_eq!(*registry, ["lo", "eth0"]);
+//! ```
+//!
+//! An initializer that panics poisons the lazy mutex for good: the panic
+//! reaches the caller that ran it, the initializer is never run again, and
+//! every later dereference panics. [`try_force`](LazyDeadlockProofMutex::try_force)
+//! and [`is_poisoned`](LazyDeadlockProofMutex::is_poisoned) observe this
+//! without panicking. Like those of `LazyLock`, they are associated functions,
+//! called as `LazyDeadlockProofMutex::is_poisoned(&STATIC)`, so that they
+//! never shadow a method of the mutex.
+
+use std::{
+    error::Error,
+    fmt, mem,
+    ops::Deref,
+    panic::{self, AssertUnwindSafe},
+    sync::OnceLock,
+};
+
+use crate::{DeadlockProofMutex, MutexPermission};
+
+/// A [`DeadlockProofMutex`] created by `F` on first use. See the
+/// [module docs](crate::lazy).
+pub struct LazyDeadlockProofMutex<T, P: MutexPermission, I: 'static, F = fn() -> T> {
+    /// `None` once the initializer has panicked.
+    mutex: OnceLock<Option<DeadlockProofMutex<T, P, I>>>,
+    init: F,
+}
+
+/// The initializer of a [`LazyDeadlockProofMutex`] panicked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InitPanicked;
+
+impl fmt::Display for InitPanicked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the initializer of a lazy deadlock-proof mutex panicked")
+    }
+}
+
+impl Error for InitPanicked {}
+
+impl<T, P: MutexPermission, I: 'static, F: Fn() -> T> LazyDeadlockProofMutex<T, P, I, F> {
+    /// A lazy mutex whose data `init` creates on first use.
+    pub const fn new(identifier: I, init: F) -> Self {
+        // Like `DeadlockProofMutex::new`, only the type of the identifier
+        // matters.
+        mem::forget(identifier);
+        Self {
+            mutex: OnceLock::new(),
+            init,
+        }
+    }
+
+    /// The mutex, created now if this is the first use. Fails if the
+    /// initializer panicked, now or earlier; the panic itself still reaches
+    /// the caller that ran it.
+    pub fn try_force(this: &Self) -> Result<&DeadlockProofMutex<T, P, I>, InitPanicked> {
+        let mut panicked = None;
+        let mutex = this.mutex.get_or_init(|| {
+            let init = AssertUnwindSafe(|| DeadlockProofMutex::unidentified((this.init)(), None));
+            // Caught rather than left to unwind through the `OnceLock`, which
+            // would let the next caller run the initializer again.
+            panic::catch_unwind(init)
+                .map_err(|payload| panicked = Some(payload))
+                .ok()
+        });
+        if let Some(payload) = panicked {
+            panic::resume_unwind(payload);
+        }
+        mutex.as_ref().ok_or(InitPanicked)
+    }
+
+    /// The mutex if it has been created, without creating it.
+    pub fn get(this: &Self) -> Option<&DeadlockProofMutex<T, P, I>> {
+        this.mutex.get().and_then(Option::as_ref)
+    }
+
+    /// Whether the initializer has panicked.
+    pub fn is_poisoned(this: &Self) -> bool {
+        matches!(this.mutex.get(), Some(None))
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static, F: Fn() -> T> Deref for LazyDeadlockProofMutex<T, P, I, F> {
+    type Target = DeadlockProofMutex<T, P, I>;
+
+    /// Panics if the initializer panicked.
+    fn deref(&self) -> &DeadlockProofMutex<T, P, I> {
+        match Self::try_force(self) {
+            Ok(mutex) => mutex,
+            Err(error) => panic!("{error}"),
+        }
+    }
+}
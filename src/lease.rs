@@ -0,0 +1,238 @@
+//! Finishers for critical flows, so a forgotten permission gets noticed.
+//!
+//! Dropping or [`forget`](std::mem::forget)ting a permission or a guard
+//! loses it without a trace. For flows where that must not go unnoticed,
+//! [`PermissionLease::new`] wraps the permission in a lease and pairs it with
+//! a [`FinishToken`]. The lease is what the flow threads along; the only way
+//! to end it quietly is [`FinishToken::finish`], which takes both and hands
+//! the permission back. If either is dropped unfinished, whether the lease
+//! went out of scope early or the token was forgotten and the lease dropped
+//! later on, the pair is reported as [`Abandoned`], naming where the lease was
+//! created. Forgetting both is the one way to lose the permission unseen.
+//!
+//! By default an abandoned lease is logged to stderr, or handed to the
+//! [misuse handler](crate::misuse) if one is installed, and kept for
+//! [`abandoned`]. After [`set_strict(true)`](set_strict) it aborts the
+//! process instead, unless the thread is already panicking.
+//!
+//! ```
+//! use deadlock_proof::{lease::PermissionLease, LockOutcome, NetworkStack, OuterMutexPermission};
+//!
+//! let stack = NetworkStack::new();
+//! let (mut lease, finisher) = PermissionLease::new(OuterMutexPermission::get());
+//! lease.with_permission(|permission| {
+//!     let mut ip = stack.ip_layer.lock(permission).guard();
+//!     ip.packets_processed += 1;
+//!     (ip.unlock(), ())
+//! });
+//! let _permission = finisher.finish(lease);
+//! ```
+
+use std::{
+    cell::Cell,
+    fmt,
+    panic::Location,
+    process,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+};
+
+use crate::{MutexPermission, Namespace, NetworkStack, OuterMutexPermission, WalkToken};
+
+/// A permission on its way through a critical flow. End it with
+/// [`FinishToken::finish`]; dropping it unfinished is reported.
+pub struct PermissionLease<P: MutexPermission = OuterMutexPermission> {
+    /// Only `None` after a closure it was lent to panicked, or once
+    /// finished.
+    permission: Option<P>,
+    created_at: &'static Location<'static>,
+    /// Set once the pair is finished or reported, so it is reported once.
+    settled: Rc<Cell<bool>>,
+}
+
+/// The one way to end a [`PermissionLease`] without a report.
+pub struct FinishToken {
+    created_at: &'static Location<'static>,
+    settled: Rc<Cell<bool>>,
+}
+
+impl<P: MutexPermission> PermissionLease<P> {
+    /// Leases `permission` out to a critical flow.
+    #[track_caller]
+    pub fn new(permission: P) -> (Self, FinishToken) {
+        Self::new_at(permission, Location::caller())
+    }
+
+    pub(crate) fn new_at(
+        permission: P,
+        created_at: &'static Location<'static>,
+    ) -> (Self, FinishToken) {
+        let settled = Rc::new(Cell::new(false));
+        let token = FinishToken {
+            created_at,
+            settled: settled.clone(),
+        };
+        let lease = Self {
+            permission: Some(permission),
+            created_at,
+            settled,
+        };
+        (lease, token)
+    }
+
+    /// Where the lease was created.
+    pub fn created_at(&self) -> &'static Location<'static> {
+        self.created_at
+    }
+
+    /// Lends the permission to `f`, which hands it back along with its
+    /// result, as [`WalkToken::with_permission`] does.
+    ///
+    /// Panics if an earlier `f` panicked and so never returned the permission.
+    pub fn with_permission<R>(&mut self, f: impl FnOnce(P) -> (P, R)) -> R {
+        let permission = self
+            .permission
+            .take()
+            .expect("the leased permission was lost to a panic");
+        let (permission, result) = f(permission);
+        self.permission = Some(permission);
+        result
+    }
+}
+
+impl PermissionLease {
+    /// Runs `f` as a [walk](crate::walk) over `stack` with the leased
+    /// permission, which goes back into the lease when the walk ends.
+    ///
+    /// Panics if an earlier closure panicked and so never returned the
+    /// permission.
+    pub fn walk<N: Namespace, R>(
+        &mut self,
+        stack: &NetworkStack<N>,
+        f: impl FnOnce(&mut WalkToken<'_, N>) -> R,
+    ) -> R {
+        self.with_permission(|permission| {
+            let mut walk = stack.begin_walk(permission);
+            let result = f(&mut walk);
+            (walk.finish(), result)
+        })
+    }
+}
+
+impl<P: MutexPermission> Drop for PermissionLease<P> {
+    fn drop(&mut self) {
+        if !self.settled.replace(true) {
+            abandon(self.created_at, Dropped::Lease);
+        }
+    }
+}
+
+impl FinishToken {
+    /// Ends `lease` and returns its permission.
+    ///
+    /// Panics if `lease` was paired with another token, or if its permission
+    /// was lost to a panic in [`with_permission`](PermissionLease::with_permission).
+    pub fn finish<P: MutexPermission>(self, mut lease: PermissionLease<P>) -> P {
+        assert!(
+            Rc::ptr_eq(&self.settled, &lease.settled),
+            "finish token from {} used on the lease from {}",
+            self.created_at,
+            lease.created_at
+        );
+        self.settled.set(true);
+        lease
+            .permission
+            .take()
+            .expect("the leased permission was lost to a panic")
+    }
+}
+
+impl Drop for FinishToken {
+    fn drop(&mut self) {
+        if !self.settled.replace(true) {
+            abandon(self.created_at, Dropped::Token);
+        }
+    }
+}
+
+/// Which half of an unfinished pair was dropped first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Dropped {
+    /// The lease, taking its permission with it.
+    Lease,
+    /// The finish token, while the lease lives on, typically forgotten.
+    Token,
+}
+
+/// A lease that was never finished.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Abandoned {
+    /// Where the lease was created.
+    pub created_at: &'static Location<'static>,
+    pub dropped: Dropped,
+}
+
+impl fmt::Display for Abandoned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.dropped {
+            Dropped::Lease => "was dropped",
+            Dropped::Token => "lost its finish token",
+        };
+        write!(
+            f,
+            "permission lease created at {} {what} before being finished",
+            self.created_at
+        )
+    }
+}
+
+/// At most this many abandoned leases are kept for [`abandoned`]; the rest
+/// are only logged.
+pub const MAX_RECORDED_ABANDONED: usize = 64;
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+static ABANDONED: Mutex<Vec<Abandoned>> = Mutex::new(Vec::new());
+
+fn recorded() -> MutexGuard<'static, Vec<Abandoned>> {
+    ABANDONED.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Makes an abandoned lease abort the process rather than be logged. Meant
+/// to be set once, at startup, usually through [`configure`](crate::configure).
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// Whether an abandoned lease aborts the process.
+pub fn is_strict() -> bool {
+    STRICT.load(Ordering::Relaxed)
+}
+
+/// The first [`MAX_RECORDED_ABANDONED`] leases abandoned so far.
+pub fn abandoned() -> Vec<Abandoned> {
+    recorded().clone()
+}
+
+fn abandon(created_at: &'static Location<'static>, dropped: Dropped) {
+    let abandoned = Abandoned {
+        created_at,
+        dropped,
+    };
+    if !crate::misuse::report(|| crate::misuse::MisuseEvent::PermissionLeaked(abandoned.clone())) {
+        eprintln!("{abandoned}");
+    }
+    // A panic on its way out is already a report of its own.
+    if is_strict() && !thread::panicking() {
+        process::abort();
+    }
+    let mut recorded = recorded();
+    if recorded.len() < MAX_RECORDED_ABANDONED {
+        recorded.push(abandoned);
+    }
+}
@@ -4,11 +4,247 @@
 use std::{
     marker::PhantomData,
     ops::{Deref, DerefMut},
-    rc::Rc,
+    panic::Location,
+    ptr::NonNull,
     sync::{Mutex, MutexGuard, PoisonError},
-    cell::Cell, // used for thread-local storage (used for OuterMutexPermission)
 };
 
+#[cfg(feature = "tracking")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Lets `#[locks]` expansions name `::deadlock_proof` from inside this crate too.
+extern crate self as deadlock_proof;
+
+#[cfg(feature = "adaptive")]
+pub mod adaptive;
+pub mod backpressure;
+pub mod budget;
+pub mod cancel;
+pub mod carry;
+pub mod chain;
+pub mod clock;
+pub mod compat;
+pub mod concurrent;
+pub mod config;
+#[cfg(feature = "tracking")]
+pub mod contention;
+pub mod context;
+pub mod deep;
+pub mod depth;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+#[cfg(feature = "diff-log")]
+pub mod diff_log;
+pub mod error;
+#[cfg(feature = "test-util")]
+pub mod fail;
+pub mod family;
+mod forward;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fuzz_driver;
+pub mod gate;
+pub mod generation;
+pub mod invariant;
+pub mod lazy;
+pub mod lease;
+#[cfg(feature = "metrics")]
+pub mod lineage;
+#[cfg(feature = "diagnostics")]
+pub mod lock_stats;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod misuse;
+pub mod namespace;
+pub mod net_demo;
+pub mod network_stack;
+pub mod notify;
+pub mod optional;
+#[cfg(feature = "origin-check")]
+pub mod origin;
+pub mod ordered_guards;
+pub mod ordered_lock_map;
+pub mod owned;
+pub mod patterns;
+pub mod permission;
+pub mod permission_cell;
+pub mod phase;
+pub mod poison;
+#[cfg(feature = "tracking")]
+pub mod poison_watch;
+pub mod poll;
+pub mod pool;
+#[cfg(feature = "priority")]
+pub mod priority;
+pub mod profiling;
+pub mod rcu;
+pub mod reacquire;
+pub mod read_with;
+pub mod region;
+mod release;
+pub mod retry;
+pub mod route_cache;
+pub mod rt;
+pub mod rwlock;
+pub mod scratch;
+pub mod session;
+pub mod setup;
+#[cfg(all(feature = "shared-memory", unix))]
+pub mod shared_memory;
+pub mod signal_safe;
+#[cfg(feature = "tracking")]
+pub mod snapshot;
+pub mod soak;
+pub mod split;
+#[cfg(feature = "strategy-bench")]
+pub mod strategy_bench;
+pub mod sweep;
+pub mod task;
+pub mod teardown;
+pub mod thread_pinned;
+pub mod transaction;
+pub mod unclaimed;
+#[cfg(feature = "tracking")]
+pub mod version;
+#[cfg(not(feature = "tracking"))]
+mod version;
+pub mod view;
+pub mod walk;
+pub mod walk_cache;
+pub mod weak;
+
+pub use backpressure::{BoundedUnderLock, HasBoundedQueue, WaitForSpace};
+pub use cancel::{CancellableLockError, LockCancellation};
+pub use carry::{CarryResult, SequentialCarry};
+pub use compat::{CompatGuard, CompatLockResult, MaybeProofed};
+pub use config::MutexConfig;
+#[cfg(feature = "tracking")]
+pub use contention::{ContentionCallback, ContentionEvent};
+pub use context::{
+    Attachment, ContextGuard, ContextLockResult, ContextNestedLockResult, Detachable, Permanent,
+    PermissionWith,
+};
+pub use deep::DeepSequentialPermission;
+pub use chain::PermissionChain;
+pub use depth::{PermissionDepth, MAX_HIERARCHY_DEPTH};
+pub use error::LockError;
+pub use family::{FamilyGuards, FamilyId, MutexFamily};
+pub use generation::HierarchyGeneration;
+pub use lazy::LazyDeadlockProofMutex;
+pub use misuse::{set_misuse_handler, MisuseEvent};
+pub use namespace::{InNamespace, Namespace, NamespacePermission, RootNamespace};
+pub use network_stack::{
+    DeviceLock, DeviceState, DeviceStateView, ErrorIpLock, ErrorPathLock,
+    ErrorTransportLock, IcmpError, IpLock, IpState, IpStateView,
+    NetworkStack, OwnedStates, Route, RouteCacheLock, RouteError, RoutingTable, StackViews, TcpSocket, TransportLock,
+    TransportState, TransportStateView,
+};
+pub use optional::{EitherGuard, OptionalLockResult};
+pub use ordered_guards::OrderedGuards;
+pub use owned::{DeadlockProofOwnedMutexGuard, DeadlockProofOwnedNestedMutexGuard};
+pub use ordered_lock_map::{OrderedLockMap, RangeGuards};
+pub use permission::{
+    MutexPermission, NestedMutexPermission, OuterMutexPermission, SequentialMutexPermission,
+    MUTEX_PERMISSION_TOKEN,
+};
+pub use permission_cell::PermissionCell;
+pub use phase::{ElidedGuard, SingleThreadedPhase};
+pub use poison::{DeadlockProofPoisonError, LockOutcome, LockResult};
+pub use profiling::ScopedGuard;
+pub use region::{with_region, Region, RegionGuard};
+pub use route_cache::{RouteCache, RouteCacheStats};
+pub use rt::{BlockingHandle, RtHandle, TryLockError, TryNestedLockResult};
+pub use rwlock::{
+    DeadlockProofReadGuard, DeadlockProofRwLock, DeadlockProofWriteGuard, RwLockPolicy, RwLockResult,
+};
+pub use split::{SplitGuardA, SplitGuardB, SplitGuardBoth, SplitLock, SplitLockResult};
+pub use scratch::WithScratch;
+pub use setup::{configure, Config, ConfigureError};
+pub use signal_safe::{AtomicOps, SignalSafe, SignalSafeMutex};
+pub use task::{
+    AsyncLock, AsyncMutexGuard, AsyncPermission, AsyncPermissionSlot, BlockingDelegate,
+    DeadlockProofAsyncMutex, DelegationAbandoned, RejoinHandle,
+};
+pub use teardown::TearDown;
+pub use thread_pinned::{PinnedLockError, ThreadPinnedMutex};
+pub use transaction::{StackLayer, StackTransaction, TxAborted};
+pub use unclaimed::{AutoClaimGuard, AutoClaimResult, ClaimError, Policy};
+pub use walk::{WalkPermission, WalkToken};
+pub use walk_cache::WalkCache;
+pub use weak::{LockedOrGone, WeakDeadlockProofMutex};
+
+/// Threads permissions through a function that locks a hierarchy in order.
+/// See the macro crate for the accepted body shape.
+///
+/// ```
+/// use deadlock_proof::{locks, DeviceLock, IpLock, NetworkStack, OuterMutexPermission};
+///
+/// #[locks(order(IpLock, DeviceLock))]
+/// fn count(stack: &NetworkStack) -> u64 {
+///     let mut ip = lock!(IpLock, stack.ip_layer);
+///     ip.packets_processed += 1;
+///     let packets = ip.packets_processed;
+///     unlock!(ip);
+///     let device = lock!(DeviceLock, stack.device_layer);
+///     let bytes = device.bytes_transmitted;
+///     unlock!(device);
+///     packets + bytes
+/// }
+///
+/// let stack = NetworkStack::new();
+/// let (_permission, total) = count(&stack, OuterMutexPermission::get());
+/// assert_eq!(total, 1);
+/// ```
+///
+/// Locking out of the declared order is rejected when the macro expands:
+///
+/// ```compile_fail
+/// use deadlock_proof::{locks, DeviceLock, IpLock, NetworkStack};
+///
+/// #[locks(order(IpLock, DeviceLock))]
+/// fn backwards(stack: &NetworkStack) {
+///     let device = lock!(DeviceLock, stack.device_layer);
+///     unlock!(device);
+///     let ip = lock!(IpLock, stack.ip_layer);
+///     unlock!(ip);
+/// }
+/// ```
+///
+/// So is holding two guards at once:
+///
+/// ```compile_fail
+/// use deadlock_proof::{locks, DeviceLock, IpLock, NetworkStack};
+///
+/// #[locks(order(IpLock, DeviceLock))]
+/// fn overlapping(stack: &NetworkStack) {
+///     let ip = lock!(IpLock, stack.ip_layer);
+///     let device = lock!(DeviceLock, stack.device_layer);
+///     unlock!(device);
+///     unlock!(ip);
+/// }
+/// ```
+pub use deadlock_proof_macros::locks;
+
+/// Declares a family of same-level mutex identifiers and the id enum used to
+/// pick members out of a [`MutexFamily`].
+///
+/// ```
+/// use deadlock_proof::{declare_mutex_family, LockOutcome, MutexFamily, OuterMutexPermission};
+///
+/// declare_mutex_family!(QueueLock: Q0, Q1, Q2);
+///
+/// let queues: MutexFamily<Vec<u32>, OuterMutexPermission, QueueLock, QueueLockId> =
+///     MutexFamily::new(QueueLock, |_| Vec::new());
+/// let mut guard = queues.get(Q1.into()).lock(OuterMutexPermission::get()).guard();
+/// guard.push(1);
+/// let permission = guard.unlock();
+///
+/// let mut lengths = Vec::new();
+/// queues.lock_each_in_declared_order(permission, |id, queue| lengths.push((id, queue.len())));
+/// assert_eq!(lengths, [(QueueLockId::Q0, 0), (QueueLockId::Q1, 1), (QueueLockId::Q2, 0)]);
+/// ```
+pub use deadlock_proof_macros::declare_mutex_family;
+
 /// A macro to create a unique type for mutex identification.
 #[macro_export]
 macro_rules! unique_type {
@@ -24,66 +260,452 @@ macro_rules! declare_mutex_identifier {
         struct $mutex_name;
     };
 }
-/// This is a trait that represents the permission to claim a mutex.
-/// Some type of permission token required to claim a mutex.
-pub trait MutexPermission: 'static {} 
-
-impl MutexPermission for OuterMutexPermission {}
-
-/// Permission to claim an "outer" mutex. That is, a class of mutexes where
-/// only one can be claimed at once in each thread, thus preventing deadlock.
-pub struct OuterMutexPermission(PhantomData<Rc<()>>);
+/// Panics unless `mutex` is currently unlocked, naming the mutex and its
+/// identifier type. See [`DeadlockProofMutex::is_locked`] for the caveats.
+///
+/// ```
+/// use deadlock_proof::{assert_unlocked, NetworkStack};
+///
+/// let stack = NetworkStack::new();
+/// assert_unlocked!(stack.device_layer);
+/// ```
+#[macro_export]
+macro_rules! assert_unlocked {
+    ($mutex:expr $(,)?) => {
+        $crate::assert_unlocked(&$mutex, ::core::stringify!($mutex))
+    };
+}
 
-// Note: OuterMutexPermission is designed to be thread-local and not Send
-// We'll enforce this through usage patterns rather than negative trait bounds
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_unlocked<T, P: MutexPermission, I: 'static>(
+    mutex: &DeadlockProofMutex<T, P, I>,
+    expression: &str,
+) {
+    assert!(
+        !mutex.is_locked(),
+        "`{expression}` ({}) is still locked",
+        mutex.label()
+    );
+}
 
-/// This is a thread-local storage for the permission token.
-/// It is used to store the permission token for the current thread.
+/// Supertraits that keep the traits only this crate and its macros implement
+/// from being implemented anywhere else, so they can gain items without
+/// breaking downstream code. Public because the macro expansions name them;
+/// not part of the API.
+#[doc(hidden)]
+pub mod __private {
+    pub trait SealedLevel {}
+    pub trait SealedNamespace {}
+    pub trait SealedFamilyId {}
+    pub trait SealedFollows {}
+    pub trait SealedAttachment {}
 
-thread_local! {
-    pub static MUTEX_PERMISSION_TOKEN: Cell<Option<OuterMutexPermission>>
-        = Cell::new(Some(OuterMutexPermission(PhantomData)));
+    /// The lock order of a level extended with `extend_lock_hierarchy!`:
+    /// `base` up to the level extended, which is the last of its first
+    /// `N - extension.len()` entries, then `extension`.
+    pub const fn extend_order<const N: usize>(
+        base: &[&'static str],
+        extension: &[&'static str],
+    ) -> [&'static str; N] {
+        let kept = N - extension.len();
+        let mut order = [""; N];
+        let mut i = 0;
+        while i < N {
+            order[i] = if i < kept { base[i] } else { extension[i - kept] };
+            i += 1;
+        }
+        order
+    }
 }
 
-/// NestedMutexPermission: A key you get after locking a mutex, which lets you lock a mutex inside it.
-
-/// SequentialMutexPermission: A key you get after unlocking a mutex, which lets you lock the next one in a sequence.
+/// A lock identifier that has a fixed place in a declared lock hierarchy.
+/// Implemented by [`lock_hierarchy!`] only; the trait is sealed.
+///
+/// ```compile_fail
+/// use deadlock_proof::{LockLevel, OuterMutexPermission};
+///
+/// struct ByHand;
+/// impl LockLevel for ByHand {
+///     type Permission = OuterMutexPermission;
+///     const LEVEL: u32 = 0;
+/// }
+/// ```
+pub trait LockLevel: __private::SealedLevel + 'static {
+    /// The permission a thread must present to lock a mutex at this level.
+    type Permission: NamespacePermission;
 
-impl OuterMutexPermission {
-    /// Get the thread-local mutex claiming permission. This can be called exactly once
-    /// per thread, and will panic if it's called more than once in a thread.
-    pub fn get() -> OuterMutexPermission {
-        MUTEX_PERMISSION_TOKEN
-            .with(|token_ref| token_ref.take())
-            .expect("Mutex permission already claimed for this thread")
-    }
+    /// Depth of this level in its hierarchy, counting from 0 at the root.
+    /// Only used where the order has to be checked at runtime, as in the C
+    /// bindings.
+    const LEVEL: u32;
 }
 
-/// Permission to claim some nested mutex.
-pub struct NestedMutexPermission<P: MutexPermission, I: 'static>(
-    PhantomData<Rc<()>>,
-    PhantomData<P>,
-    PhantomData<I>,
-);
+/// The permission type needed to lock the mutex identified by `I`.
+pub type Position<I> = <I as LockLevel>::Permission;
 
-impl<P: MutexPermission, I: 'static> MutexPermission for NestedMutexPermission<P, I> {}
+/// The permission type handed out after unlocking the mutex identified by `I`
+/// with `unlock_for_sequential`, i.e. the position of the next level.
+pub type After<I> = SequentialMutexPermission<Position<I>, I>;
 
-/// Permission to claim mutexes in a specific sequence.
-pub struct SequentialMutexPermission<P: MutexPermission, I: 'static>(PhantomData<Rc<()>>, P, PhantomData<I>);
+/// The permission handed out by locking the mutex identified by `I` with
+/// [`lock_for_nested`](DeadlockProofMutex::lock_for_nested): the root of the
+/// sub-hierarchy inside that mutex.
+pub type Inside<I> = NestedMutexPermission<Position<I>, I>;
 
-impl<P: MutexPermission, I: 'static> SequentialMutexPermission<P, I> {
-    fn new(permission: P) -> Self {
-        Self(PhantomData, permission, PhantomData)
-    }
+/// Declares a sequential lock hierarchy: `lock_hierarchy!(Root => A, B, C)`
+/// means `A` is locked with `Root`, `B` with `After<A>`, and `C` with `After<B>`.
+///
+/// `lock_hierarchy!(within Parent => A, B)` declares a sub-hierarchy rooted
+/// at [`Inside<Parent>`](Inside), the token from locking `Parent` with
+/// `lock_for_nested`:
+///
+/// ```
+/// use deadlock_proof::*;
+///
+/// struct InterfaceLock;
+/// struct TxQueueLock;
+/// struct RxQueueLock;
+/// lock_hierarchy!(OuterMutexPermission => InterfaceLock);
+/// lock_hierarchy!(within InterfaceLock => TxQueueLock, RxQueueLock);
+///
+/// let interface = DeadlockProofMutex::<_, Position<InterfaceLock>, _>::new(0u64, InterfaceLock);
+/// let tx = DeadlockProofMutex::<_, Position<TxQueueLock>, _>::new(Vec::<u8>::new(), TxQueueLock);
+/// let rx = DeadlockProofMutex::<_, Position<RxQueueLock>, _>::new(Vec::<u8>::new(), RxQueueLock);
+///
+/// let (interface, inside) = interface.lock_for_nested(OuterMutexPermission::get()).guard();
+/// let tx = tx.lock(inside).guard();
+/// let rx = rx.lock(tx.unlock_for_sequential()).guard();
+/// let inside = rx.unlock_for_sequential().to_earlier().to_earlier();
+/// let _permission: OuterMutexPermission = interface.unlock(inside);
+/// ```
+///
+/// Hierarchies with more than [`deep::DEEP_HIERARCHY_THRESHOLD`] levels use
+/// the flat [`DeepSequentialPermission`] chain instead, up to 33 levels.
+///
+/// Every level also gets associated consts describing its place, documented
+/// on the level's own page: `LOCK_ORDER`, the names of all levels of its
+/// hierarchy in order, its `POSITION` in that list, the `NEXT` level, if
+/// any, and a `DESCRIPTION` numbering it and naming its neighbours:
+///
+/// ```
+/// use deadlock_proof::{DeviceLock, IpLock};
+///
+/// assert_eq!(DeviceLock::LOCK_ORDER, ["IpLock", "DeviceLock", "TransportLock"]);
+/// assert_eq!(DeviceLock::POSITION, 1);
+/// assert_eq!(IpLock::NEXT, Some("DeviceLock"));
+/// assert!(DeviceLock::DESCRIPTION.contains("level 2 of 3"));
+/// assert!(DeviceLock::DESCRIPTION.contains("after `IpLock` and before `TransportLock`"));
+/// ```
+#[macro_export]
+macro_rules! lock_hierarchy {
+    (within $parent:ty => $($levels:ident),+ $(,)?) => {
+        $crate::lock_hierarchy!(
+            @hierarchy $crate::Inside<$parent>; { "inside `", stringify!($parent), "`" };
+            $($levels),+
+        );
+    };
+    ($root:ty => $($levels:ident),+ $(,)?) => {
+        $crate::lock_hierarchy!(
+            @hierarchy $root; { "rooted at `", stringify!($root), "`" }; $($levels),+
+        );
+    };
+    (
+        @hierarchy $root:ty; $where:tt; $l0:ident, $l1:ident, $l2:ident, $l3:ident, $l4:ident,
+        $l5:ident, $l6:ident, $l7:ident, $l8:ident $(, $rest:ident)*
+    ) => {
+        impl $crate::__private::SealedLevel for $l0 {}
+        impl $crate::LockLevel for $l0 {
+            type Permission = $root;
+            const LEVEL: u32 = 0;
+        }
+        $crate::lock_hierarchy!(@deep $root; 1; $l1, $l2, $l3, $l4, $l5, $l6, $l7, $l8 $(, $rest)*);
+        $crate::lock_hierarchy!(
+            @count $where;
+            { $crate::lock_hierarchy!(@order $l0, $l1, $l2, $l3, $l4, $l5, $l6, $l7, $l8 $(, $rest)*) };
+            []; $l0, $l1, $l2, $l3, $l4, $l5, $l6, $l7, $l8 $(, $rest)*
+        );
+    };
+    (@hierarchy $root:ty; $where:tt; $first:ident $(, $rest:ident)*) => {
+        impl $crate::__private::SealedLevel for $first {}
+        impl $crate::LockLevel for $first {
+            type Permission = $root;
+            const LEVEL: u32 = 0;
+        }
+        $crate::lock_hierarchy!(@chain $first $(, $rest)*);
+        $crate::lock_hierarchy!(
+            @count $where; { $crate::lock_hierarchy!(@order $first $(, $rest)*) }; [];
+            $first $(, $rest)*
+        );
+    };
+    (@extend $base:ty; $name:expr; $first:ident $(, $rest:ident)*) => {
+        impl $crate::__private::SealedLevel for $first {}
+        impl $crate::LockLevel for $first {
+            type Permission = $crate::After<$base>;
+            const LEVEL: u32 = <$base as $crate::LockLevel>::LEVEL + 1;
+        }
+        $crate::lock_hierarchy!(@chain $first $(, $rest)*);
+        $crate::lock_hierarchy!(
+            @count { "extending `", $name, "`" };
+            {
+                // The base's own order up to it, then the new levels.
+                const EXTENSION: &[&str] = $crate::lock_hierarchy!(@order $first $(, $rest)*);
+                &$crate::__private::extend_order::<{ <$base>::POSITION + 1 + EXTENSION.len() }>(
+                    <$base>::LOCK_ORDER,
+                    EXTENSION,
+                )
+            };
+            [$name]; $first $(, $rest)*
+        );
+    };
+    (@order $($all:ident),+) => {
+        &[$(stringify!($all)),+]
+    };
+    (@chain $prev:ident, $next:ident $(, $rest:ident)*) => {
+        impl $crate::__private::SealedLevel for $next {}
+        impl $crate::LockLevel for $next {
+            type Permission =
+                $crate::SequentialMutexPermission<<$prev as $crate::LockLevel>::Permission, $prev>;
+            const LEVEL: u32 = <$prev as $crate::LockLevel>::LEVEL + 1;
+        }
+        $crate::lock_hierarchy!(@chain $next $(, $rest)*);
+    };
+    (@chain $last:ident) => {};
+    (@deep $root:ty; $depth:expr; $next:ident $(, $rest:ident)*) => {
+        impl $crate::__private::SealedLevel for $next {}
+        impl $crate::LockLevel for $next {
+            type Permission = $crate::DeepSequentialPermission<$root, { $depth }>;
+            const LEVEL: u32 = $depth as u32;
+        }
+        $crate::lock_hierarchy!(@deep $root; $depth + 1; $($rest),*);
+    };
+    (@deep $root:ty; $depth:expr;) => {};
+    // Numbers the levels for their descriptions, which `concat!` can only
+    // build from literals: both the level's own and the total are read off
+    // this list, enough for the deepest hierarchy and then some.
+    (@count $where:tt; $order:tt; $prev:tt; $($levels:ident),+) => {
+        $crate::lock_hierarchy!(
+            @count $where; $order; $prev; [
+                1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30
+                31 32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47 48 49 50 51 52 53 54 55 56 57
+                58 59 60 61 62 63 64
+            ];
+            $($levels),+
+        );
+    };
+    (@count $where:tt; $order:tt; $prev:tt; $numbers:tt; $($levels:ident),+) => {
+        $crate::lock_hierarchy!(
+            @count $where; $order; $prev; $numbers; $numbers; [$($levels),+]; $($levels),+
+        );
+    };
+    (
+        @count $where:tt; $order:tt; $prev:tt; $numbers:tt; [$counted:tt $($uncounted:tt)*];
+        $levels:tt; $level:ident, $($rest:ident),+
+    ) => {
+        $crate::lock_hierarchy!(
+            @count $where; $order; $prev; $numbers; [$($uncounted)*]; $levels; $($rest),+
+        );
+    };
+    (
+        @count $where:tt; $order:tt; $prev:tt; $numbers:tt; [$total:tt $($uncounted:tt)*];
+        [$($levels:ident),+]; $last:ident
+    ) => {
+        $crate::lock_hierarchy!(@describe $where; $order; $prev; $total; $numbers; $($levels),+);
+    };
+    (
+        @describe $where:tt; $order:tt; [$($prev:expr)?]; $total:tt; [$number:tt $($numbers:tt)*];
+        $level:ident $(, $next:ident $(, $rest:ident)*)?
+    ) => {
+        #[doc = $crate::lock_hierarchy!(
+            @sentence $level; $where; [$($prev)?]; [$($next)?]; $number; $total
+        )]
+        // Private levels need not read their own description.
+        #[allow(dead_code)]
+        impl $level {
+            /// The levels of this level's hierarchy, in the order they are
+            /// locked.
+            pub const LOCK_ORDER: &'static [&'static str] = $order;
+            /// The index of this level in [`LOCK_ORDER`](Self::LOCK_ORDER).
+            pub const POSITION: usize = <$level as $crate::LockLevel>::LEVEL as usize;
+            /// The level locked right after this one, if any.
+            pub const NEXT: ::core::option::Option<&'static str> =
+                $crate::lock_hierarchy!(@name $($next)?);
+            /// Where this level sits in its hierarchy, as in its docs.
+            pub const DESCRIPTION: &'static str = $crate::lock_hierarchy!(
+                @sentence $level; $where; [$($prev)?]; [$($next)?]; $number; $total
+            );
+        }
+        $crate::lock_hierarchy!(
+            @describe $where; $order; [stringify!($level)]; $total; [$($numbers)*];
+            $($next $(, $rest)*)?
+        );
+    };
+    (@describe $where:tt; $order:tt; [$($prev:expr)?]; $total:tt; $numbers:tt;) => {};
+    (@name $next:ident) => {
+        ::core::option::Option::Some(stringify!($next))
+    };
+    (@name) => {
+        ::core::option::Option::None
+    };
+    (@sentence $level:ident; {$($where:tt)*}; []; []; $number:tt; $total:tt) => {
+        concat!(
+            "`", stringify!($level), "` is level ", $number, " of ", $total,
+            ", the only level of the lock hierarchy ", $($where)*, "."
+        )
+    };
+    (@sentence $level:ident; {$($where:tt)*}; []; [$next:ident]; $number:tt; $total:tt) => {
+        concat!(
+            "`", stringify!($level), "` is level ", $number, " of ", $total,
+            " in the lock hierarchy ", $($where)*, "; locked first, before `",
+            stringify!($next), "`."
+        )
+    };
+    (@sentence $level:ident; {$($where:tt)*}; [$prev:expr]; [$next:ident]; $number:tt; $total:tt) => {
+        concat!(
+            "`", stringify!($level), "` is level ", $number, " of ", $total,
+            " in the lock hierarchy ", $($where)*, "; locked after `", $prev, "` and before `",
+            stringify!($next), "`."
+        )
+    };
+    (@sentence $level:ident; {$($where:tt)*}; [$prev:expr]; []; $number:tt; $total:tt) => {
+        concat!(
+            "`", stringify!($level), "` is level ", $number, " of ", $total,
+            " in the lock hierarchy ", $($where)*, "; locked last, after `", $prev, "`."
+        )
+    };
+}
 
-    /// Consumes this sequential permission to return the permission
-    /// token earlier in the sequence.
-    pub fn to_earlier(self) -> P {
-        self.1
-    }
+/// Extends a hierarchy declared elsewhere, typically in another crate:
+/// `extend_lock_hierarchy!(base::DeviceLock -> A, B)` means `A` is locked
+/// with [`After<base::DeviceLock>`](After) and `B` with `After<A>`, as if
+/// they had been declared as its next levels.
+///
+/// The base crate need not know about the extension. The new levels take
+/// the place of whatever the base declared after the level extended, so a
+/// sequential walk goes on to either, never to both, and every impl the
+/// macro emits is on the new levels. Their `LOCK_ORDER` is the base's up
+/// to the level extended, followed by them:
+///
+/// ```
+/// use deadlock_proof::*;
+///
+/// struct FilterLock;
+/// extend_lock_hierarchy!(deadlock_proof::DeviceLock -> FilterLock);
+///
+/// assert_eq!(FilterLock::LOCK_ORDER, ["IpLock", "DeviceLock", "FilterLock"]);
+///
+/// let stack = NetworkStack::new();
+/// let filter = DeadlockProofMutex::<_, Position<FilterLock>, _>::new(0u32, FilterLock);
+/// let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+/// let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+/// let filter = filter.lock(device.unlock_for_sequential()).guard();
+/// let after = filter.unlock_for_sequential();
+/// let _permission: OuterMutexPermission = after.to_earlier().to_earlier().to_earlier();
+/// ```
+///
+/// The base level must have been declared with [`lock_hierarchy!`] or this
+/// macro, and is named by a plain path without generic arguments. The new
+/// levels always form a plain sequential chain, never a
+/// [deep](crate::deep) one.
+#[macro_export]
+macro_rules! extend_lock_hierarchy {
+    ($head:ident $(:: $tail:ident)* -> $($levels:ident),+ $(,)?) => {
+        $crate::lock_hierarchy!(
+            @extend $head $(:: $tail)*; concat!(stringify!($head) $(, "::", stringify!($tail))*);
+            $($levels),+
+        );
+    };
 }
 
-impl<P: MutexPermission, I: 'static> MutexPermission for SequentialMutexPermission<P, I> {}
+/// Builds a struct of mutexes whose initial values depend on each other, by
+/// walking them in hierarchy order as they are created.
+///
+/// The fields are listed in [`lock_hierarchy!`] order, each with its level
+/// and initial value. Every value after the first is computed from a shared
+/// reference to the previous field's data, read under that field's lock with
+/// the permission a steady-state walk would hold there. The macro evaluates
+/// to the permission, walked back to where it started, and the struct:
+///
+/// ```
+/// use deadlock_proof::*;
+///
+/// struct ConfigLock;
+/// struct TableLock;
+/// lock_hierarchy!(OuterMutexPermission => ConfigLock, TableLock);
+///
+/// struct Router {
+///     ports: DeadlockProofMutex<u16, Position<ConfigLock>, ConfigLock>,
+///     table: DeadlockProofMutex<Vec<u16>, Position<TableLock>, TableLock>,
+/// }
+///
+/// let (permission, router) = construct_in_order!(OuterMutexPermission::get() => Router {
+///     ports: ConfigLock = 4,
+///     table: TableLock = |ports| vec![0; usize::from(*ports)],
+/// });
+/// let table = router.table.lock(router.ports.lock(permission).guard().unlock_for_sequential());
+/// assert_eq!(table.guard().len(), 4);
+/// ```
+///
+/// Fields out of hierarchy order fail to lock with the permission their
+/// predecessor hands on:
+///
+/// ```compile_fail
+/// use deadlock_proof::*;
+///
+/// struct ConfigLock;
+/// struct TableLock;
+/// lock_hierarchy!(OuterMutexPermission => ConfigLock, TableLock);
+///
+/// struct Router {
+///     table: DeadlockProofMutex<Vec<u16>, Position<TableLock>, TableLock>,
+///     ports: DeadlockProofMutex<u16, Position<ConfigLock>, ConfigLock>,
+/// }
+///
+/// let (permission, router) = construct_in_order!(OuterMutexPermission::get() => Router {
+///     table: TableLock = Vec::new(),
+///     ports: ConfigLock = |table| table.len() as u16,
+/// });
+/// ```
+///
+/// Only hierarchies of up to [`deep::DEEP_HIERARCHY_THRESHOLD`] levels are
+/// supported, since deeper ones step on with `advance` instead.
+#[macro_export]
+macro_rules! construct_in_order {
+    ($permission:expr => $name:ident {
+        $first:ident: $first_level:ident = $init:expr
+        $(, $field:ident: $level:ident = |$data:ident| $value:expr)* $(,)?
+    }) => {{
+        let permission = $permission;
+        let $first = $crate::DeadlockProofMutex::new($init, $first_level);
+        $crate::construct_in_order!(
+            @step permission; $name; [$first]; $first; $($field: $level = |$data| $value,)*
+        )
+    }};
+    (@step $permission:ident; $name:ident; [$($built:ident)*]; $prev:ident;
+        $field:ident: $level:ident = |$data:ident| $value:expr, $($rest:tt)*
+    ) => {{
+        let guard = $crate::LockOutcome::guard($prev.lock($permission));
+        let value = {
+            let $data = &*guard;
+            $value
+        };
+        let $permission = guard.unlock_for_sequential();
+        let $field = $crate::DeadlockProofMutex::new(value, $level);
+        $crate::construct_in_order!(@step $permission; $name; [$($built)* $field]; $field; $($rest)*)
+    }};
+    (@step $permission:ident; $name:ident; [$($built:ident)*]; $prev:ident;) => {
+        (
+            $crate::construct_in_order!(@root $permission; $($built)*),
+            $name { $($built),* },
+        )
+    };
+    (@root $permission:expr; $head:ident $($tail:ident)+) => {
+        $crate::construct_in_order!(@root $permission.to_earlier(); $($tail)+)
+    };
+    (@root $permission:expr; $last:ident) => {
+        $permission
+    };
+}
 
 /// Wrapper to make permission types Send/Sync for internal use.
 struct PermissionSyncSendWrapper<P: MutexPermission>(P);
@@ -94,58 +716,474 @@ unsafe impl<P: MutexPermission> Sync for PermissionSyncSendWrapper<P> {}
 
 /// A mutex which is compile-time guaranteed not to deadlock.
 /// Similar to the Netstack3 approach for preventing network stack deadlocks.
-
+///
 /// This is our custom mutex. The generic type P: MutexPermission. This embeds the rule "To lock me, you need a key of type P" directly into the mutex's own type.
-
-pub struct DeadlockProofMutex<T, P: MutexPermission, I: 'static>(
-    Mutex<T>,
-    PhantomData<PermissionSyncSendWrapper<P>>,
-    PhantomData<I>,
-);
+///
+/// By default a release build lays a mutex out exactly as its `Mutex<T>`:
+/// the permission and identifier types take no space. Debug builds add one
+/// word for the release invariant they check. The `tracking` feature adds a
+/// fixed few words for its waiter count, version and listeners, poison
+/// watchers, contention hook and name, and the other features whatever they
+/// need.
+pub struct DeadlockProofMutex<T, P: MutexPermission, I: 'static> {
+    inner: Mutex<T>,
+    #[cfg(feature = "tracking")]
+    name: Option<&'static str>,
+    #[cfg(feature = "tracking")]
+    waiters: AtomicUsize,
+    versions: version::Versions,
+    #[cfg(feature = "metrics")]
+    wait_histogram: metrics::WaitHistogram,
+    /// The [chain](chain) of `P`, rendered on the first exported wait.
+    #[cfg(feature = "metrics-exporter")]
+    position: std::sync::OnceLock<&'static str>,
+    #[cfg(feature = "test-util")]
+    injected_contention: fail::InjectedContention,
+    #[cfg(feature = "adaptive")]
+    spin_budget: adaptive::SpinBudget,
+    #[cfg(feature = "priority")]
+    waiter_queue: priority::WaiterQueue,
+    #[cfg(feature = "tracking")]
+    contention: contention::ContentionHook,
+    #[cfg(feature = "diff-log")]
+    differ: Option<diff_log::Differ<T>>,
+    #[cfg(debug_assertions)]
+    release_invariant: invariant::InvariantSlot<T>,
+    #[cfg(feature = "diagnostics")]
+    holder: diagnostics::HolderSlot,
+    _permission: PhantomData<PermissionSyncSendWrapper<P>>,
+    _identifier: PhantomData<I>,
+}
 
 impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
     /// Create a new deadlock-proof mutex.
     pub fn new(content: T, _identifier: I) -> Self {
-        Self(Mutex::new(content), PhantomData, PhantomData)
+        Self::unidentified(content, None)
+    }
+
+    /// [`new`](Self::new), but diagnostics, metrics, profiler scopes and
+    /// error messages show the mutex as `name` rather than by the type name
+    /// of its identifier, so that two mutexes of one identifier type, or a
+    /// type named `Inner` three modules deep, can be told apart:
+    ///
+    /// ```
+    /// use deadlock_proof::{DeadlockProofMutex, OuterMutexPermission};
+    ///
+    /// struct TableLock;
+    /// let table: DeadlockProofMutex<Vec<u32>, OuterMutexPermission, _> =
+    ///     DeadlockProofMutex::new_named(Vec::new(), TableLock, "arp-table");
+    /// assert_eq!(table.name(), Some("arp-table"));
+    /// ```
+    ///
+    /// [`MutexConfig::name`](config::MutexConfig::name) names a mutex too.
+    /// Names are kept by the `tracking` feature, which
+    /// `metrics-exporter` turns on; without it neither exists, and every
+    /// mutex is shown by its identifier.
+    #[cfg(feature = "tracking")]
+    pub fn new_named(content: T, _identifier: I, name: &'static str) -> Self {
+        Self::unidentified(content, Some(name))
+    }
+
+    /// [`new`](Self::new), for callers that already had the identifier,
+    /// named `name` if there is one. Only `None` is passed without
+    /// `tracking`, which keeps no name.
+    #[cfg_attr(not(feature = "tracking"), allow(unused_variables))]
+    fn unidentified(content: T, name: Option<&'static str>) -> Self {
+        let () = depth::DepthCheck::<P>::WITHIN_MAX;
+        #[cfg(feature = "tracking")]
+        let label = name.unwrap_or(std::any::type_name::<I>());
+        #[cfg(not(feature = "tracking"))]
+        let label = std::any::type_name::<I>();
+        #[cfg(feature = "metrics-exporter")]
+        metrics::register_histogram(label);
+        let versions = version::Versions::new(label);
+        #[cfg(feature = "diagnostics")]
+        versions.bookkeeping.insert(release::Bookkeeping::HOLDER);
+        Self {
+            inner: Mutex::new(content),
+            #[cfg(feature = "tracking")]
+            name,
+            #[cfg(feature = "tracking")]
+            waiters: AtomicUsize::new(0),
+            versions,
+            #[cfg(feature = "metrics")]
+            wait_histogram: metrics::WaitHistogram::new(),
+            #[cfg(feature = "metrics-exporter")]
+            position: std::sync::OnceLock::new(),
+            #[cfg(feature = "test-util")]
+            injected_contention: fail::InjectedContention::new(),
+            #[cfg(feature = "adaptive")]
+            spin_budget: adaptive::SpinBudget::new(),
+            #[cfg(feature = "priority")]
+            waiter_queue: priority::WaiterQueue::new(),
+            #[cfg(feature = "tracking")]
+            contention: contention::ContentionHook::new(),
+            #[cfg(feature = "diff-log")]
+            differ: None,
+            #[cfg(debug_assertions)]
+            release_invariant: invariant::InvariantSlot::new(),
+            #[cfg(feature = "diagnostics")]
+            holder: diagnostics::HolderSlot::new(label),
+            _permission: PhantomData,
+            _identifier: PhantomData,
+        }
+    }
+
+    /// Blocks on the inner mutex. Every blocking acquisition path goes
+    /// through here.
+    fn acquire(&self) -> LockResult<MutexGuard<'_, T>, PoisonError<MutexGuard<'_, T>>> {
+        self.acquire_tagged(None)
+    }
+
+    /// [`acquire`](Self::acquire) on behalf of a permission with lineage
+    /// `tag`, which is reported along with the wait.
+    fn acquire_tagged(
+        &self,
+        tag: Option<u64>,
+    ) -> LockResult<MutexGuard<'_, T>, PoisonError<MutexGuard<'_, T>>> {
+        self.acquire_prioritized(tag, Priority::Normal)
+    }
+
+    /// [`acquire_tagged`](Self::acquire_tagged), waiting for a turn with
+    /// `priority` under the `priority` feature.
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    #[cfg_attr(not(feature = "priority"), allow(unused_variables))]
+    fn acquire_prioritized(
+        &self,
+        tag: Option<u64>,
+        priority: Priority,
+    ) -> LockResult<MutexGuard<'_, T>, PoisonError<MutexGuard<'_, T>>> {
+        #[cfg(feature = "metrics")]
+        let started = clock::now();
+        #[cfg(debug_assertions)]
+        phase::assert_not_in_foreign_phase();
+        #[cfg(feature = "tracking")]
+        self.waiters.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "test-util")]
+        self.injected_contention.wait();
+        let block = || {
+            #[cfg(feature = "priority")]
+            let _turn = self.waiter_queue.turn(priority);
+            #[cfg(feature = "adaptive")]
+            return self
+                .spin_budget
+                .try_acquire(&self.inner)
+                .unwrap_or_else(|| self.inner.lock());
+            #[cfg(not(feature = "adaptive"))]
+            self.inner.lock()
+        };
+        #[cfg(feature = "tracking")]
+        let result = self
+            .contention
+            .observe(self.label(), &self.inner, &self.waiters, block);
+        #[cfg(not(feature = "tracking"))]
+        let result = block();
+        #[cfg(feature = "no-poison")]
+        let result = result.unwrap_or_else(PoisonError::into_inner);
+        #[cfg(feature = "tracking")]
+        self.waiters.fetch_sub(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        self.record_wait(clock::now() - started, tag);
+        result
+    }
+
+    /// How many threads are currently inside a blocking acquisition of this
+    /// mutex. Needs no permission.
+    ///
+    /// The value is inherently racy: it may already be stale when returned,
+    /// and it briefly counts uncontended acquisitions too. Use it as a load
+    /// signal, never for correctness. Needs the `tracking` feature.
+    #[cfg(feature = "tracking")]
+    pub fn waiters(&self) -> usize {
+        self.waiters.load(Ordering::Relaxed)
+    }
+
+    /// Whether any thread is currently waiting for this mutex. Just as racy
+    /// as [`waiters`](Self::waiters).
+    #[cfg(feature = "tracking")]
+    pub fn is_contended(&self) -> bool {
+        self.waiters() > 0
+    }
+
+    /// Whether some guard currently holds this mutex. Needs no permission.
+    ///
+    /// Probes the inner mutex with a try-lock that is released immediately,
+    /// so every kind of guard is seen without any bookkeeping of its own. The
+    /// answer is inherently racy, like a waiter count: use it for
+    /// assertions at quiescent points and for monitoring, never to decide
+    /// whether to lock.
+    pub fn is_locked(&self) -> bool {
+        matches!(self.inner.try_lock(), Err(std::sync::TryLockError::WouldBlock))
+    }
+
+    /// The name the mutex was created with, if any. See
+    /// [`new_named`](Self::new_named). Always `None` without the `tracking`
+    /// feature, which is the only way to name one.
+    pub fn name(&self) -> Option<&'static str> {
+        #[cfg(feature = "tracking")]
+        return self.name;
+        #[cfg(not(feature = "tracking"))]
+        None
+    }
+
+    /// What diagnostics show the mutex as: its [name](Self::name), or else
+    /// the type name of its identifier.
+    fn label(&self) -> &'static str {
+        self.name().unwrap_or(std::any::type_name::<I>())
+    }
+
+    /// Whether a panic unwound out of a critical section of this mutex, so
+    /// that locking it fails. Always `false` under `no-poison`, which never
+    /// fails a lock.
+    pub fn is_poisoned(&self) -> bool {
+        poison::POISONING && self.inner.is_poisoned()
+    }
+
+    #[cfg(feature = "metrics")]
+    #[cfg_attr(not(feature = "metrics-exporter"), allow(unused_variables))]
+    fn record_wait(&self, waited: std::time::Duration, tag: Option<u64>) {
+        self.wait_histogram.record(waited);
+        #[cfg(feature = "metrics-exporter")]
+        metrics::export_wait(
+            self.label(),
+            || *self.position.get_or_init(chain::rendered::<P>),
+            waited,
+            tag,
+        );
+    }
+
+    /// A snapshot of how long acquisitions of this mutex have waited so far.
+    /// Needs no permission since it never touches the lock itself.
+    #[cfg(feature = "metrics")]
+    pub fn wait_histogram(&self) -> metrics::Histogram {
+        self.wait_histogram.snapshot()
     }
 
     /// Acquires this mutex, blocking the current thread until it is able to do so.
+    #[track_caller]
     pub fn lock(
         &self,
         permission: P,
-    ) -> Result<DeadlockProofMutexGuard<T, P, I>, PoisonError<MutexGuard<T>>> {
-        self.0
-            .lock()
-            .map(|guard| DeadlockProofMutexGuard(guard, permission, PhantomData))
+    ) -> LockResult<DeadlockProofMutexGuard<'_, T, P, I>, DeadlockProofPoisonError<'_, T, P, I>> {
+        self.lock_prioritized(permission, Priority::Normal, Location::caller())
     }
 
-    /// When you successfully lock the mutex, you get this Guard. It holds two things: access to the data, and the original permission token you used to get the lock.
+    /// [`lock`](Self::lock), but a poisoned mutex hands back the very
+    /// permission passed in, as [`TryLockError::Poisoned`], rather than the
+    /// data, as the non-blocking and timed acquisitions do. A walk that
+    /// finds its next level poisoned keeps its position: it can go back to
+    /// an earlier level with
+    /// [`to_earlier`](SequentialMutexPermission::to_earlier), or step past
+    /// this one with
+    /// [`skip_poisoned`](SequentialMutexPermission::skip_poisoned).
+    ///
+    /// Never fails under `no-poison`.
+    #[track_caller]
+    pub fn lock_or_return(
+        &self,
+        permission: P,
+    ) -> Result<DeadlockProofMutexGuard<'_, T, P, I>, TryLockError<P>> {
+        permission::check_origin(&permission);
+        let tag = permission::tag_of(&permission);
+        #[cfg(not(feature = "no-poison"))]
+        let Ok(guard) = self.acquire_tagged(tag) else {
+            return Err(TryLockError::Poisoned(permission));
+        };
+        #[cfg(feature = "no-poison")]
+        let guard = self.acquire_tagged(tag);
+        Ok(self.guard_with(guard, permission, Location::caller()))
+    }
+
+    fn lock_prioritized(
+        &self,
+        permission: P,
+        priority: Priority,
+        location: &'static Location<'static>,
+    ) -> LockResult<DeadlockProofMutexGuard<'_, T, P, I>, DeadlockProofPoisonError<'_, T, P, I>> {
+        permission::check_origin(&permission);
+        let tag = permission::tag_of(&permission);
+        #[cfg(not(feature = "no-poison"))]
+        let guard = match self.acquire_prioritized(tag, priority) {
+            Ok(guard) => guard,
+            Err(poisoned) => return Err(self.poisoned(poisoned, permission, location)),
+        };
+        #[cfg(feature = "no-poison")]
+        let guard = self.acquire_prioritized(tag, priority);
+        poison::unpoisoned(self.guard_with(guard, permission, location))
+    }
+
+    /// The error of a blocking lock that found the mutex poisoned.
+    #[cfg(not(feature = "no-poison"))]
+    fn poisoned<'a, Q: MutexPermission, J: 'static>(
+        &'a self,
+        poisoned: PoisonError<MutexGuard<'a, T>>,
+        permission: Q,
+        location: &'static Location<'static>,
+    ) -> DeadlockProofPoisonError<'a, T, Q, J> {
+        DeadlockProofPoisonError::new(self.guard_with(poisoned.into_inner(), permission, location))
+    }
+
+    /// The guard of the lock `guard` was taken for, holding `permission`.
+    fn guard_with<'a, Q: MutexPermission, J: 'static>(
+        &'a self,
+        guard: MutexGuard<'a, T>,
+        permission: Q,
+        location: &'static Location<'static>,
+    ) -> DeadlockProofMutexGuard<'a, T, Q, J> {
+        DeadlockProofMutexGuard(
+            self.inner_guard(guard, location),
+            permission,
+            PhantomData,
+            version::Dirty::clean(&self.versions).at(location),
+            Held::new(),
+        )
+    }
+
+    // When you successfully lock the mutex, you get this Guard. It holds two things: access to the data, and the original permission token you used to get the lock.
 
     /// Acquires this mutex and provides a token for claiming nested mutexes.
+    #[track_caller]
     pub fn lock_for_nested(
         &self,
         permission: P,
-    ) -> Result<
-        (
-            DeadlockProofNestedMutexGuard<T, P, I>,
-            NestedMutexPermission<P, I>,
-        ),
-        PoisonError<MutexGuard<T>>,
-    > {
-        self.0.lock().map(|guard| {
-            (
-                DeadlockProofNestedMutexGuard(guard, permission, PhantomData),
-                NestedMutexPermission(PhantomData, PhantomData, PhantomData),
-            )
-        })
+    ) -> NestedLockResult<'_, T, P, I> {
+        self.lock_for_nested_holding(permission)
+    }
+
+    /// [`lock`](Self::lock) with a permission `Q` of another type, which the
+    /// guard holds, as level `J`. Only permissions that [`LocksAs`] this
+    /// mutex are accepted.
+    #[track_caller]
+    pub(crate) fn lock_as<Q: LocksAs<P, I, J>, J: 'static>(
+        &self,
+        permission: Q,
+    ) -> LockResult<DeadlockProofMutexGuard<'_, T, Q, J>, DeadlockProofPoisonError<'_, T, Q, J>>
+    {
+        permission::check_origin(&permission);
+        let tag = permission::tag_of(&permission);
+        let location = Location::caller();
+        #[cfg(not(feature = "no-poison"))]
+        let guard = match self.acquire_tagged(tag) {
+            Ok(guard) => guard,
+            Err(poisoned) => return Err(self.poisoned(poisoned, permission, location)),
+        };
+        #[cfg(feature = "no-poison")]
+        let guard = self.acquire_tagged(tag);
+        poison::unpoisoned(self.guard_with(guard, permission, location))
+    }
+
+    /// [`lock_for_nested`](Self::lock_for_nested) with a permission `Q` of
+    /// another type, as [`lock_as`](Self::lock_as).
+    #[track_caller]
+    pub(crate) fn lock_for_nested_as<Q: LocksAs<P, I, J>, J: 'static>(
+        &self,
+        permission: Q,
+    ) -> NestedLockResult<'_, T, Q, J> {
+        self.lock_for_nested_holding(permission)
+    }
+
+    /// [`lock_for_nested`](Self::lock_for_nested) with whatever permission
+    /// the callers above have checked.
+    #[track_caller]
+    fn lock_for_nested_holding<Q: MutexPermission, J: 'static>(
+        &self,
+        permission: Q,
+    ) -> NestedLockResult<'_, T, Q, J> {
+        permission::check_origin(&permission);
+        let tag = permission::tag_of(&permission);
+        let location = Location::caller();
+        #[cfg(not(feature = "no-poison"))]
+        let guard = match self.acquire_tagged(tag) {
+            Ok(guard) => guard,
+            Err(poisoned) => return Err(self.poisoned(poisoned, permission, location)),
+        };
+        #[cfg(feature = "no-poison")]
+        let guard = self.acquire_tagged(tag);
+        let nested = NestedMutexPermission::new(&permission);
+        poison::unpoisoned((
+            DeadlockProofNestedMutexGuard(
+                self.inner_guard(guard, location),
+                permission,
+                PhantomData,
+                version::Dirty::clean(&self.versions).at(location),
+                Held::new(),
+            ),
+            nested,
+        ))
+    }
+}
+
+/// A permission that may lock a mutex declared with `P` at level `I`, its
+/// guard standing at level `J` of the permission's own hierarchy, through
+/// [`lock_as`](DeadlockProofMutex::lock_as).
+///
+/// Each implementation is the proof that this keeps the lock order. A
+/// [`PermissionWith`] and the permission inside it stand in the same place,
+/// so either locks what the other does. The positions of the network
+/// stack's [error path](network_stack::ErrorPathLock) lock its layers in
+/// reverse, which is safe because only the stack's own gate hands them out
+/// and every other path that holds two layers passes that gate as well.
+pub(crate) trait LocksAs<P: MutexPermission, I: 'static, J: 'static>: MutexPermission {}
+
+/// Result of [`DeadlockProofMutex::lock_for_nested`]: the nested guard plus the
+/// token for claiming the mutexes inside it. Poisoned, it is an ordinary
+/// guard, with no token.
+pub type NestedLockResult<'a, T, P, I> = LockResult<
+    (
+        DeadlockProofNestedMutexGuard<'a, T, P, I>,
+        NestedMutexPermission<P, I>,
+    ),
+    DeadlockProofPoisonError<'a, T, P, I>,
+>;
+
+/// What the guards hold on to the inner mutex with.
+use release::InnerGuard;
+
+/// The tier of a waiter for the inner mutex.
+#[cfg(feature = "priority")]
+use priority::Priority;
+/// Every waiter is in the same tier without the `priority` feature.
+#[cfg(not(feature = "priority"))]
+#[derive(Clone, Copy)]
+enum Priority {
+    Normal,
+}
+
+/// What counts every guard towards its thread's nesting depth.
+#[cfg(feature = "diagnostics")]
+pub(crate) use lock_stats::Held;
+/// Counts nothing without the `diagnostics` feature.
+#[cfg(not(feature = "diagnostics"))]
+pub(crate) struct Held;
+
+#[cfg(not(feature = "diagnostics"))]
+impl Held {
+    pub(crate) fn new() -> Self {
+        Held
     }
 }
 
 /// Deadlock-proof equivalent to MutexGuard.
+///
+/// With the `tracking` feature, reading through `Deref` leaves the mutex's
+/// version alone; taking `&mut T`, with [`get_mut`](Self::get_mut) or
+/// `DerefMut`, makes the release count as a modification. Code written before
+/// versions existed keeps working unchanged, but every `DerefMut` use is
+/// counted, so switch read-mostly paths to `Deref` plus an explicit `get_mut`
+/// when writing.
+///
+/// Without features a release build lays the guard out exactly as its
+/// `MutexGuard`. `tracking` adds the reference and flag that track
+/// modifications, and what a poisoning is reported with, and debug builds
+/// the release invariant to check. The permission it holds only takes space for
+/// the lineage tag of the `metrics` feature.
 pub struct DeadlockProofMutexGuard<'a, T, P: MutexPermission, I: 'static>(
-    MutexGuard<'a, T>,
+    InnerGuard<'a, T>,
     P,
     PhantomData<I>,
+    // Declared after the inner guard so it drops after the unlock.
+    version::Dirty<'a>,
+    Held,
 );
 
 impl<'a, T, P: MutexPermission, I: 'static> DeadlockProofMutexGuard<'a, T, P, I> {
@@ -158,6 +1196,49 @@ impl<'a, T, P: MutexPermission, I: 'static> DeadlockProofMutexGuard<'a, T, P, I>
     pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
         SequentialMutexPermission::new(self.1)
     }
+
+    /// Mutable access to the data. Releasing the guard afterwards bumps the
+    /// mutex's version.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.3.mark();
+        self.0.deref_mut()
+    }
+
+    /// Keep the mutex locked but narrow access down to a part of the data.
+    pub fn map<U: ?Sized>(mut self, f: impl FnOnce(&mut T) -> &mut U) -> MappedGuard<'a, U, T, P, I> {
+        let target = NonNull::from(f(self.0.deref_mut()));
+        MappedGuard(self.0, target, self.1, PhantomData, self.3, self.4)
+    }
+
+    /// Like [`map`](Self::map), but the projection is looked up by `key` and may
+    /// fail. On failure the guard and the key are handed back, still locked, so
+    /// the caller can e.g. insert the missing entry and retry without relocking.
+    ///
+    /// The closure only borrows the key, which is what lets it be returned.
+    pub fn try_map_with<K, U: ?Sized>(
+        mut self,
+        key: K,
+        f: impl for<'t> FnOnce(&'t mut T, &K) -> Option<&'t mut U>,
+    ) -> Result<MappedGuard<'a, U, T, P, I>, (Self, K)> {
+        match f(self.0.deref_mut(), &key).map(NonNull::from) {
+            Some(target) => Ok(MappedGuard(self.0, target, self.1, PhantomData, self.3, self.4)),
+            None => Err((self, key)),
+        }
+    }
+
+    /// Narrows access to one variant of the data, if `f` finds it there. Unlike
+    /// [`map`](Self::map) this can be undone: [`VariantGuard::into_original`]
+    /// gives the full guard back, still locked. If `f` finds nothing the guard
+    /// is returned unchanged.
+    pub fn try_into_variant<U: ?Sized>(
+        mut self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<VariantGuard<'a, U, T, P, I>, Self> {
+        match f(self.0.deref_mut()).map(NonNull::from) {
+            Some(target) => Ok(VariantGuard(self, target, PhantomData)),
+            None => Err(self),
+        }
+    }
 }
 
 impl<T, P: MutexPermission, I: 'static> Deref for DeadlockProofMutexGuard<'_, T, P, I> {
@@ -170,95 +1251,198 @@ impl<T, P: MutexPermission, I: 'static> Deref for DeadlockProofMutexGuard<'_, T,
 
 impl<T, P: MutexPermission, I: 'static> DerefMut for DeadlockProofMutexGuard<'_, T, P, I> {
     fn deref_mut(&mut self) -> &mut T {
-        self.0.deref_mut()
+        self.get_mut()
     }
 }
 
-/// Deadlock-proof guard for nested mutex operations.
-pub struct DeadlockProofNestedMutexGuard<'a, T, P: MutexPermission, I: 'static>(
-    MutexGuard<'a, T>,
+/// A locked mutex whose access has been narrowed to a `U` inside its data.
+/// Created by [`DeadlockProofMutexGuard::map`] and
+/// [`DeadlockProofMutexGuard::try_map_with`].
+///
+/// Invariant in `U`, like the `&mut U` it stands for, so a `U` with a
+/// shorter lifetime cannot be written into data that outlives it:
+///
+/// ```compile_fail
+/// use deadlock_proof::*;
+///
+/// fn shorten<'s, 'a>(
+///     guard: MappedGuard<'a, &'static str, Vec<&'static str>, OuterMutexPermission, ()>,
+/// ) -> MappedGuard<'a, &'s str, Vec<&'static str>, OuterMutexPermission, ()> {
+///     guard
+/// }
+/// ```
+pub struct MappedGuard<'a, U: ?Sized, T, P: MutexPermission, I: 'static>(
+    #[allow(dead_code)] // only held to keep the mutex locked
+    InnerGuard<'a, T>,
+    NonNull<U>,
     P,
-    PhantomData<I>,
+    // `&'a mut U` for the variance of the pointer's target.
+    PhantomData<(I, &'a mut U)>,
+    version::Dirty<'a>,
+    Held,
 );
 
-impl<'a, T, P: MutexPermission, I: 'static> DeadlockProofNestedMutexGuard<'a, T, P, I> {
-    /// Unlock the mutex with the nested permission token.
-    pub fn unlock(self, _token: NestedMutexPermission<P, I>) -> P {
-        self.1
+impl<U: ?Sized, T, P: MutexPermission, I: 'static> MappedGuard<'_, U, T, P, I> {
+    /// Unlock the mutex and return the permission token.
+    pub fn unlock(self) -> P {
+        self.2
     }
 
     /// Unlock the mutex and return a sequential permission token.
     pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
-        SequentialMutexPermission::new(self.1)
+        SequentialMutexPermission::new(self.2)
     }
 }
 
-impl<T, P: MutexPermission, I: 'static> Deref for DeadlockProofNestedMutexGuard<'_, T, P, I> {
-    type Target = T;
+// Safety: the pointer was derived from the data behind the `MutexGuard` held in
+// the same struct, which stays locked (and in place) for as long as we exist.
+impl<U: ?Sized, T, P: MutexPermission, I: 'static> Deref for MappedGuard<'_, U, T, P, I> {
+    type Target = U;
 
-    fn deref(&self) -> &T {
-        self.0.deref()
+    fn deref(&self) -> &U {
+        unsafe { self.1.as_ref() }
     }
 }
 
-impl<T, P: MutexPermission, I: 'static> DerefMut for DeadlockProofNestedMutexGuard<'_, T, P, I> {
-    fn deref_mut(&mut self) -> &mut T {
-        self.0.deref_mut()
+impl<U: ?Sized, T, P: MutexPermission, I: 'static> DerefMut for MappedGuard<'_, U, T, P, I> {
+    fn deref_mut(&mut self) -> &mut U {
+        self.4.mark();
+        unsafe { self.1.as_mut() }
     }
 }
 
-// Netstack3-inspired network stack simulation structures
-pub struct NetworkStack {
-    pub ip_layer: DeadlockProofMutex<IpState, OuterMutexPermission, IpLock>,
-    pub device_layer: DeadlockProofMutex<DeviceState, SequentialMutexPermission<OuterMutexPermission, IpLock>, DeviceLock>,
-    pub transport_layer: DeadlockProofMutex<TransportState, SequentialMutexPermission<SequentialMutexPermission<OuterMutexPermission, IpLock>, DeviceLock>, TransportLock>,
+/// A locked mutex whose access has been narrowed to one variant `U` of its
+/// data, with the full guard kept inside. Created by
+/// [`DeadlockProofMutexGuard::try_into_variant`].
+///
+/// Invariant in `U`, as [`MappedGuard`] is:
+///
+/// ```compile_fail
+/// use deadlock_proof::*;
+///
+/// fn shorten<'s, 'a>(
+///     guard: VariantGuard<'a, &'static str, Vec<&'static str>, OuterMutexPermission, ()>,
+/// ) -> VariantGuard<'a, &'s str, Vec<&'static str>, OuterMutexPermission, ()> {
+///     guard
+/// }
+/// ```
+pub struct VariantGuard<'a, U: ?Sized, T, P: MutexPermission, I: 'static>(
+    DeadlockProofMutexGuard<'a, T, P, I>,
+    NonNull<U>,
+    PhantomData<&'a mut U>,
+);
+
+impl<'a, U: ?Sized, T, P: MutexPermission, I: 'static> VariantGuard<'a, U, T, P, I> {
+    /// Widens access back to the whole data without unlocking.
+    pub fn into_original(self) -> DeadlockProofMutexGuard<'a, T, P, I> {
+        self.0
+    }
+
+    /// Unlock the mutex and return the permission token.
+    pub fn unlock(self) -> P {
+        self.0.unlock()
+    }
+
+    /// Unlock the mutex and return a sequential permission token.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        self.0.unlock_for_sequential()
+    }
 }
 
-/// Network stack layer states
-pub struct IpState {
-    pub packets_processed: u64,
-    pub routing_table_size: usize,
+// Safety: as for `MappedGuard`; the pointer points into the data of the guard
+// held alongside it, and moving that guard does not move the data.
+impl<U: ?Sized, T, P: MutexPermission, I: 'static> Deref for VariantGuard<'_, U, T, P, I> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        unsafe { self.1.as_ref() }
+    }
 }
 
-pub struct DeviceState {
-    pub interfaces_active: u32,
-    pub bytes_transmitted: u64,
+impl<U: ?Sized, T, P: MutexPermission, I: 'static> DerefMut for VariantGuard<'_, U, T, P, I> {
+    fn deref_mut(&mut self) -> &mut U {
+        self.0.3.mark();
+        unsafe { self.1.as_mut() }
+    }
 }
 
-pub struct TransportState {
-    pub tcp_connections: u32,
-    pub udp_sockets: u32,
+/// Deadlock-proof guard for nested mutex operations. Tracks modifications
+/// like [`DeadlockProofMutexGuard`].
+pub struct DeadlockProofNestedMutexGuard<'a, T, P: MutexPermission, I: 'static>(
+    InnerGuard<'a, T>,
+    P,
+    PhantomData<I>,
+    version::Dirty<'a>,
+    Held,
+);
+
+impl<'a, T, P: MutexPermission, I: 'static> DeadlockProofNestedMutexGuard<'a, T, P, I> {
+    /// Unlock the mutex with the nested permission token.
+    ///
+    /// The token has to have come back out of whatever was locked with it,
+    /// so an outer guard cannot be unlocked while an inner one still holds:
+    ///
+    /// ```compile_fail
+    /// use deadlock_proof::*;
+    ///
+    /// let outer = DeadlockProofMutex::new(0u32, unique_type!());
+    /// let inner = DeadlockProofMutex::new(0u32, unique_type!());
+    /// let (outer_guard, token) = outer.lock_for_nested(OuterMutexPermission::get()).guard();
+    /// let (_inner_guard, inner_token) = inner.lock_for_nested(token).guard();
+    /// outer_guard.unlock(inner_token);
+    /// ```
+    pub fn unlock(self, _token: NestedMutexPermission<P, I>) -> P {
+        self.1
+    }
+
+    /// Unlock the mutex with the nested permission token and return a
+    /// sequential permission token.
+    ///
+    /// Like [`unlock`](Self::unlock), this needs the token back, so nothing
+    /// locked inside the mutex can still be held while the walk moves on to
+    /// the next level. Otherwise a thread holding an inner mutex could queue
+    /// at the next level behind one that holds it and waits for the inner
+    /// mutex:
+    ///
+    /// ```compile_fail
+    /// use deadlock_proof::*;
+    ///
+    /// struct InterfaceLock;
+    /// struct NeighborLock;
+    /// struct QueueLock;
+    /// lock_hierarchy!(OuterMutexPermission => InterfaceLock, NeighborLock);
+    /// lock_hierarchy!(within InterfaceLock => QueueLock);
+    ///
+    /// let interface = DeadlockProofMutex::<_, Position<InterfaceLock>, _>::new(0, InterfaceLock);
+    /// let queue = DeadlockProofMutex::<_, Position<QueueLock>, _>::new(0, QueueLock);
+    /// let (interface, inside) = interface.lock_for_nested(OuterMutexPermission::get()).guard();
+    /// let _queue = queue.lock(inside).guard();
+    /// let _next: After<InterfaceLock> = interface.unlock_for_sequential();
+    /// ```
+    pub fn unlock_for_sequential(
+        self,
+        _token: NestedMutexPermission<P, I>,
+    ) -> SequentialMutexPermission<P, I> {
+        SequentialMutexPermission::new(self.1)
+    }
+
+    /// Mutable access to the data, as [`DeadlockProofMutexGuard::get_mut`].
+    pub fn get_mut(&mut self) -> &mut T {
+        self.3.mark();
+        self.0.deref_mut()
+    }
 }
 
-/// Lock identifiers for the network stack layers
-pub struct IpLock;
-pub struct DeviceLock; 
-pub struct TransportLock;
+impl<T, P: MutexPermission, I: 'static> Deref for DeadlockProofNestedMutexGuard<'_, T, P, I> {
+    type Target = T;
 
-impl NetworkStack {
-    pub fn new() -> Self {
-        Self {
-            ip_layer: DeadlockProofMutex::new(
-                IpState {
-                    packets_processed: 0,
-                    routing_table_size: 0,
-                },
-                IpLock,
-            ),
-            device_layer: DeadlockProofMutex::new(
-                DeviceState {
-                    interfaces_active: 0,
-                    bytes_transmitted: 0,
-                },
-                DeviceLock,
-            ),
-            transport_layer: DeadlockProofMutex::new(
-                TransportState {
-                    tcp_connections: 0,
-                    udp_sockets: 0,
-                },
-                TransportLock,
-            ),
-        }
+    fn deref(&self) -> &T {
+        self.0.deref()
     }
-}
\ No newline at end of file
+}
+
+impl<T, P: MutexPermission, I: 'static> DerefMut for DeadlockProofNestedMutexGuard<'_, T, P, I> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.get_mut()
+    }
+}
@@ -0,0 +1,69 @@
+//! Lineage tags for correlating lock operations with a logical flow.
+//!
+//! A flow that hops between threads gets a fresh root permission on every
+//! thread it visits. Tagging each of those roots with the flow's trace id,
+//! via [`OuterMutexPermission::set_tag`], makes every permission derived from
+//! them report it: nested, sequential and deep permissions copy or forward
+//! their parent's tag, guards report the tag of the permission they hold, and
+//! with the `metrics-exporter` feature every acquisition's wait goes to
+//! `Recorder::record_tagged_histogram` with it.
+//!
+//! ```
+//! use deadlock_proof::{LockOutcome, MutexPermission, NetworkStack, OuterMutexPermission};
+//!
+//! let stack = NetworkStack::new();
+//! let mut permission = OuterMutexPermission::get();
+//! permission.set_tag(0xf10e);
+//!
+//! let ip = stack.ip_layer.lock(permission).guard();
+//! let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+//! assert_eq!(device.tag(), Some(0xf10e));
+//! assert_eq!(device.unlock().to_earlier().tag(), Some(0xf10e));
+//! ```
+//!
+//! The tag lives in the permission itself, so it costs a copy of a `u64`
+//! per derived permission and nothing without the `metrics` feature.
+
+use crate::{
+    DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard, MappedGuard, MutexPermission,
+    OuterMutexPermission, VariantGuard,
+};
+
+impl OuterMutexPermission {
+    /// Tags this root permission, and everything later derived from it,
+    /// with `trace_id`. Replaces any earlier tag.
+    ///
+    /// The tag stays with the token, so it is still there if the token goes
+    /// back into the thread's slot and is claimed again.
+    pub fn set_tag(&mut self, trace_id: u64) {
+        self.tag = Some(trace_id);
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutexGuard<'_, T, P, I> {
+    /// The lineage tag of the permission this guard was locked with.
+    pub fn tag(&self) -> Option<u64> {
+        self.1.tag()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofNestedMutexGuard<'_, T, P, I> {
+    /// The lineage tag of the permission this guard was locked with.
+    pub fn tag(&self) -> Option<u64> {
+        self.1.tag()
+    }
+}
+
+impl<U: ?Sized, T, P: MutexPermission, I: 'static> MappedGuard<'_, U, T, P, I> {
+    /// The lineage tag of the permission this guard was locked with.
+    pub fn tag(&self) -> Option<u64> {
+        self.2.tag()
+    }
+}
+
+impl<U: ?Sized, T, P: MutexPermission, I: 'static> VariantGuard<'_, U, T, P, I> {
+    /// The lineage tag of the permission this guard was locked with.
+    pub fn tag(&self) -> Option<u64> {
+        self.0.tag()
+    }
+}
@@ -0,0 +1,126 @@
+//! How deep threads nest locks, enabled by the `diagnostics` feature.
+//!
+//! Every guard of a [`DeadlockProofMutex`](crate::DeadlockProofMutex) or
+//! [`DeadlockProofRwLock`](crate::DeadlockProofRwLock) counts towards its
+//! thread's nesting depth for as long as it lives. Whenever a thread goes
+//! deeper than it has been before, its new maximum is recorded, so that after
+//! a real workload [`max_depth_observed`] and [`per_thread_max_depth`] tell
+//! whether the hierarchy is actually used as deep as it is declared.
+//! [`reset`] starts a new measurement window.
+//!
+//! ```
+//! use deadlock_proof::*;
+//!
+//! struct HostLock;
+//! struct InterfaceLock;
+//! let host = DeadlockProofMutex::<_, OuterMutexPermission, _>::new(0, HostLock);
+//! let interface = DeadlockProofMutex::new(0, InterfaceLock);
+//!
+//! let (host, nested) = host.lock_for_nested(OuterMutexPermission::get()).guard();
+//! let interface = interface.lock(nested).guard();
+//! assert_eq!(lock_stats::thread_max_depth(), 2);
+//! let _permission = host.unlock(interface.unlock());
+//! ```
+
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+    thread::{self, ThreadId},
+};
+
+/// The deepest each thread has been in the current window. Kept after the
+/// threads exit; one entry per thread that locked anything.
+static MAXIMA: Mutex<Vec<(ThreadId, usize)>> = Mutex::new(Vec::new());
+
+/// Bumped by [`reset`], so threads know to forget their maximum.
+static WINDOW: AtomicU64 = AtomicU64::new(0);
+
+fn maxima() -> MutexGuard<'static, Vec<(ThreadId, usize)>> {
+    MAXIMA.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+thread_local! {
+    /// How many guards this thread holds right now.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// The window this thread last recorded in, and its maximum in it.
+    static MAXIMUM: Cell<(u64, usize)> = const { Cell::new((0, 0)) };
+}
+
+/// Counts a guard towards its thread's depth while it lives.
+pub(crate) struct Held(());
+
+impl Held {
+    pub(crate) fn new() -> Self {
+        let _ = DEPTH.try_with(|depth| {
+            let held = depth.get() + 1;
+            depth.set(held);
+            let _ = MAXIMUM.try_with(|maximum| record(maximum, held));
+        });
+        Held(())
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        let _ = DEPTH.try_with(|depth| depth.set(depth.get().saturating_sub(1)));
+    }
+}
+
+/// How many guards this thread holds right now.
+pub(crate) fn held() -> usize {
+    DEPTH.try_with(Cell::get).unwrap_or(0)
+}
+
+/// Records `held` if it is deeper than the thread has been in this window.
+fn record(maximum: &Cell<(u64, usize)>, held: usize) {
+    let window = WINDOW.load(Ordering::Acquire);
+    let (recorded_in, deepest) = maximum.get();
+    if recorded_in == window && held <= deepest {
+        return;
+    }
+    let mut maxima = maxima();
+    // A reset in between starts the window over; record in the new one.
+    let window = WINDOW.load(Ordering::Acquire);
+    let deepest = if recorded_in == window { deepest } else { 0 };
+    let held = held.max(deepest);
+    maximum.set((window, held));
+    let id = thread::current().id();
+    match maxima.iter_mut().find(|(thread, _)| *thread == id) {
+        Some((_, recorded)) => *recorded = held,
+        None => maxima.push((id, held)),
+    }
+}
+
+/// The most guards any thread has held at once since the last [`reset`].
+pub fn max_depth_observed() -> usize {
+    maxima().iter().map(|&(_, depth)| depth).max().unwrap_or(0)
+}
+
+/// The most guards each thread has held at once since the last [`reset`], in
+/// the order the threads first locked anything.
+pub fn per_thread_max_depth() -> Vec<(ThreadId, usize)> {
+    maxima().clone()
+}
+
+/// The most guards the current thread has held at once since the last
+/// [`reset`].
+pub fn thread_max_depth() -> usize {
+    let window = WINDOW.load(Ordering::Acquire);
+    MAXIMUM
+        .try_with(|maximum| match maximum.get() {
+            (recorded_in, deepest) if recorded_in == window => deepest,
+            _ => 0,
+        })
+        .unwrap_or(0)
+}
+
+/// Forgets every maximum, starting a new measurement window. Guards held
+/// across the reset count again once their thread locks the next one.
+pub fn reset() {
+    let mut maxima = maxima();
+    WINDOW.fetch_add(1, Ordering::AcqRel);
+    maxima.clear();
+}
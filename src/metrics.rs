@@ -0,0 +1,155 @@
+//! Lock acquisition statistics, enabled by the `metrics` feature.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Number of buckets in a [`Histogram`].
+pub const BUCKETS: usize = 32;
+
+/// Allocation-free wait-time histogram stored inside each mutex. Bucket `i`
+/// counts waits of `[2^i, 2^(i+1))` nanoseconds; the last bucket also takes
+/// everything longer.
+pub(crate) struct WaitHistogram([AtomicU64; BUCKETS]);
+
+impl WaitHistogram {
+    pub(crate) const fn new() -> Self {
+        Self([const { AtomicU64::new(0) }; BUCKETS])
+    }
+
+    pub(crate) fn record(&self, waited: Duration) {
+        self.0[bucket_for(waited)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> Histogram {
+        Histogram(std::array::from_fn(|i| self.0[i].load(Ordering::Relaxed)))
+    }
+}
+
+fn bucket_for(waited: Duration) -> usize {
+    let nanos = u64::try_from(waited.as_nanos()).unwrap_or(u64::MAX);
+    (63 - (nanos | 1).leading_zeros() as usize).min(BUCKETS - 1)
+}
+
+/// A point-in-time copy of a mutex's wait-time histogram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Histogram([u64; BUCKETS]);
+
+impl Histogram {
+    /// Per-bucket acquisition counts, shortest waits first.
+    pub fn buckets(&self) -> &[u64; BUCKETS] {
+        &self.0
+    }
+
+    /// Total number of recorded acquisitions.
+    pub fn count(&self) -> u64 {
+        self.0.iter().sum()
+    }
+
+    /// The shortest wait that lands in bucket `index`.
+    pub fn bucket_floor(index: usize) -> Duration {
+        match index {
+            0 => Duration::ZERO,
+            _ => Duration::from_nanos(1 << index),
+        }
+    }
+}
+
+/// Name under which every mutex's wait histogram is exported. Mutexes are
+/// told apart by the `mutex` label, which is the mutex's
+/// [name](crate::DeadlockProofMutex::name), or else the type name of its
+/// identifier.
+#[cfg(feature = "metrics-exporter")]
+pub const WAIT_HISTOGRAM: &str = "deadlock_proof.lock_wait_ns";
+
+/// Name under which every mutex's poisonings are counted, with the same
+/// `mutex` label. See [`poison_watch`](crate::poison_watch).
+#[cfg(feature = "metrics-exporter")]
+pub const POISONED_TOTAL: &str = "deadlock_proof.poisoned_total";
+
+/// Sink for exported metrics, shaped after the `metrics` facade's recorder so
+/// a thin adapter can forward to it (or to any other backend).
+#[cfg(feature = "metrics-exporter")]
+pub trait Recorder: Sync {
+    /// Called once per mutex, when it is constructed.
+    fn register_histogram(&self, name: &'static str, mutex: &'static str);
+
+    /// Called after every acquisition with the time spent waiting.
+    fn record_histogram(&self, name: &'static str, mutex: &'static str, nanos: u64);
+
+    /// Called instead of [`record_histogram`](Self::record_histogram) when
+    /// the acquiring permission carries a [lineage](crate::lineage) tag.
+    /// Forwards to `record_histogram` unless overridden.
+    fn record_tagged_histogram(
+        &self,
+        name: &'static str,
+        mutex: &'static str,
+        nanos: u64,
+        _tag: u64,
+    ) {
+        self.record_histogram(name, mutex, nanos);
+    }
+
+    /// Called after every acquisition with the time spent waiting, the
+    /// [position](crate::chain) of the permission it was made with, such as
+    /// `Outer → IpLock`, and its lineage tag, if any. Forwards to
+    /// [`record_tagged_histogram`](Self::record_tagged_histogram) or
+    /// `record_histogram` unless overridden.
+    fn record_positioned_histogram(
+        &self,
+        name: &'static str,
+        mutex: &'static str,
+        _position: &'static str,
+        nanos: u64,
+        tag: Option<u64>,
+    ) {
+        match tag {
+            Some(tag) => self.record_tagged_histogram(name, mutex, nanos, tag),
+            None => self.record_histogram(name, mutex, nanos),
+        }
+    }
+
+    /// Called with [`POISONED_TOTAL`] every time a panic poisons a mutex.
+    /// Ignored unless overridden.
+    fn increment_counter(&self, _name: &'static str, _mutex: &'static str) {}
+}
+
+#[cfg(feature = "metrics-exporter")]
+static RECORDER: std::sync::OnceLock<&'static dyn Recorder> = std::sync::OnceLock::new();
+
+/// Installs the process-wide recorder. Mutexes constructed before this call
+/// are not registered with it. Fails if a recorder is already installed.
+/// [`configure`](crate::configure) installs it along with the other
+/// process-wide settings.
+#[cfg(feature = "metrics-exporter")]
+pub fn set_recorder(recorder: &'static dyn Recorder) -> Result<(), &'static dyn Recorder> {
+    RECORDER.set(recorder).map_err(|_| recorder)
+}
+
+#[cfg(feature = "metrics-exporter")]
+pub(crate) fn register_histogram(mutex: &'static str) {
+    if let Some(recorder) = RECORDER.get() {
+        recorder.register_histogram(WAIT_HISTOGRAM, mutex);
+    }
+}
+
+#[cfg(feature = "metrics-exporter")]
+pub(crate) fn export_wait(
+    mutex: &'static str,
+    position: impl FnOnce() -> &'static str,
+    waited: Duration,
+    tag: Option<u64>,
+) {
+    if let Some(recorder) = RECORDER.get() {
+        let nanos = u64::try_from(waited.as_nanos()).unwrap_or(u64::MAX);
+        recorder.record_positioned_histogram(WAIT_HISTOGRAM, mutex, position(), nanos, tag);
+    }
+}
+
+#[cfg(all(feature = "metrics-exporter", feature = "tracking"))]
+pub(crate) fn export_poisoned(mutex: &'static str) {
+    if let Some(recorder) = RECORDER.get() {
+        recorder.increment_counter(POISONED_TOTAL, mutex);
+    }
+}
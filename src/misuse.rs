@@ -0,0 +1,192 @@
+//! One place to hear about permission and guard misuse, for embedders that
+//! would rather log it than crash.
+//!
+//! The misuse the type system cannot rule out is detected at run time: a
+//! second claim of the root permission, a lease dropped unfinished, a
+//! [`WorkerPool`](crate::pool::WorkerPool) worker retired over its job, and,
+//! with their features or `debug_assertions`, a permission used on another
+//! thread, a violated release invariant, a lock that breaks a
+//! [`SingleThreadedPhase`](crate::SingleThreadedPhase) and a mutex joining a
+//! [`HierarchyGeneration`](crate::HierarchyGeneration) it does not fit.
+//! Without a handler each of them behaves as it always has, panicking in all
+//! but the lease and pool cases, which are printed to stderr. Once
+//! [`set_misuse_handler`] installed one, every detection site
+//! hands it a [`MisuseEvent`] first, and then:
+//!
+//! - carries on where it can: the lock with a foreign permission or in a
+//!   foreign phase goes ahead, the guard with a violated invariant unlocks,
+//!   the mutex that does not fit its generation is created anyway, and the
+//!   abandoned lease and the retired worker are recorded without printing
+//!   anything;
+//! - panics as before where it cannot, as a second claim has no permission
+//!   to return and a relocked elided mutex no guard. A handler that must
+//!   not unwind aborts instead of returning.
+//!
+//! [`OuterMutexPermission::try_get`](crate::OuterMutexPermission::try_get)
+//! and [`get_or_diagnose`](crate::OuterMutexPermission::get_or_diagnose)
+//! claim without any of this, returning the failure to the caller.
+//!
+//! ```
+//! use deadlock_proof::{misuse, OuterMutexPermission};
+//!
+//! fn log(event: misuse::MisuseEvent) {
+//!     eprintln!("misuse: {event}");
+//! }
+//! misuse::set_misuse_handler(log);
+//!
+//! let _permission = OuterMutexPermission::get();
+//! // Logged, then panics: there is no second permission to hand out.
+//! assert!(std::panic::catch_unwind(OuterMutexPermission::get).is_err());
+//! # misuse::clear_misuse_handler();
+//! ```
+//!
+//! Like a contention callback, the handler runs on the thread that misused
+//! the crate, in the middle of a lock or release, and must not lock
+//! anything.
+
+use std::{
+    fmt, mem,
+    panic::Location,
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+    thread::ThreadId,
+};
+
+use crate::{lease::Abandoned, permission::ClaimDiagnostics, pool::Retirement};
+
+/// What a misuse handler is told.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum MisuseEvent {
+    /// The root permission was claimed again on a thread that already had.
+    DoubleClaim(ClaimDiagnostics),
+    /// A permission claimed on one thread was used to lock on another. Only
+    /// detected with the `origin-check` feature.
+    WrongThread {
+        claimed_on: ThreadId,
+        used_on: ThreadId,
+    },
+    /// A lease, or its finish token, was dropped before the lease was
+    /// finished.
+    PermissionLeaked(Abandoned),
+    /// A pool worker was retired because its job kept or lost the worker's
+    /// permission.
+    WorkerRetired(Retirement),
+    /// A guard was released with the release invariant of its mutex
+    /// violated. Only detected with `debug_assertions`.
+    InvariantViolated {
+        /// The mutex's [name](crate::DeadlockProofMutex::name), or else the type
+        /// name of its identifier.
+        identifier: &'static str,
+        /// What the invariant said does not hold.
+        message: String,
+        /// Where the guard was acquired.
+        locked_at: &'static Location<'static>,
+    },
+    /// A mutex was locked while another thread holds a
+    /// [`SingleThreadedPhase`](crate::SingleThreadedPhase). Only detected
+    /// with `debug_assertions`.
+    ForeignPhaseLock,
+    /// [`lock_elided`](crate::DeadlockProofMutex::lock_elided) was called on
+    /// a mutex this thread still holds an elided guard for.
+    ElidedRelock { at: &'static Location<'static> },
+    /// A mutex joined a [`HierarchyGeneration`](crate::HierarchyGeneration)
+    /// that already has a mutex with the same identifier.
+    DuplicateIdentifier {
+        /// Type name of the identifier.
+        identifier: &'static str,
+        generation: u64,
+    },
+    /// A mutex joined a [`HierarchyGeneration`](crate::HierarchyGeneration)
+    /// that was closed.
+    ClosedGeneration {
+        /// Type name of the mutex's identifier.
+        identifier: &'static str,
+        generation: u64,
+    },
+}
+
+impl fmt::Display for MisuseEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DoubleClaim(diagnostics) => diagnostics.fmt(f),
+            Self::WrongThread {
+                claimed_on,
+                used_on,
+            } => write!(
+                f,
+                "permission claimed on thread {claimed_on:?} used to lock on thread {used_on:?}"
+            ),
+            Self::PermissionLeaked(abandoned) => abandoned.fmt(f),
+            Self::WorkerRetired(retirement) => retirement.fmt(f),
+            Self::InvariantViolated {
+                identifier,
+                message,
+                locked_at,
+            } => write!(
+                f,
+                "release invariant of `{identifier}` violated: {message}; locked at {locked_at}"
+            ),
+            Self::ForeignPhaseLock => {
+                f.write_str("a mutex was locked while another thread holds a SingleThreadedPhase")
+            }
+            Self::ElidedRelock { at } => {
+                write!(
+                    f,
+                    "lock_elided on a mutex this thread already holds, at {at}"
+                )
+            }
+            Self::DuplicateIdentifier {
+                identifier,
+                generation,
+            } => write!(
+                f,
+                "generation {generation} already has a mutex identified by `{identifier}`"
+            ),
+            Self::ClosedGeneration {
+                identifier,
+                generation,
+            } => write!(
+                f,
+                "a mutex identified by `{identifier}` joined closed generation {generation}"
+            ),
+        }
+    }
+}
+
+/// A misuse handler.
+pub type MisuseHandler = fn(MisuseEvent);
+
+/// Null while no handler is installed.
+static HANDLER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Sends every misuse detected from now on to `handler`, replacing any
+/// earlier one. See the [module docs](self) for what happens after it
+/// returns.
+pub fn set_misuse_handler(handler: MisuseHandler) {
+    HANDLER.store(handler as *mut (), Ordering::Release);
+}
+
+/// Goes back to the behaviour without a handler.
+pub fn clear_misuse_handler() {
+    HANDLER.store(ptr::null_mut(), Ordering::Release);
+}
+
+fn handler() -> Option<MisuseHandler> {
+    let handler = HANDLER.load(Ordering::Acquire);
+    // Safety: the only non-null values ever stored are `MisuseHandler`s,
+    // cast to a data pointer of the same size.
+    (!handler.is_null()).then(|| unsafe { mem::transmute::<*mut (), MisuseHandler>(handler) })
+}
+
+/// Hands the event to the handler, if one is installed, and says whether
+/// there was one. The event is only built when there is.
+pub(crate) fn report(event: impl FnOnce() -> MisuseEvent) -> bool {
+    match handler() {
+        Some(handler) => {
+            handler(event());
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,88 @@
+//! Namespaces give a whole lock hierarchy a second, independent set of types.
+//!
+//! `InNamespace<N, IpLock>` is a different identifier from `IpLock`, and its
+//! position in the hierarchy is IpLock's position with every identifier in
+//! the chain moved into `N` as well. Two stacks in different namespaces can
+//! therefore never have their permissions mixed up, even though they share
+//! the same layer definitions.
+
+use std::marker::PhantomData;
+
+use crate::{
+    LockLevel, MutexPermission, NestedMutexPermission, OuterMutexPermission,
+    SequentialMutexPermission,
+};
+
+/// A namespace for lock identifiers. Declare new ones with
+/// [`declare_namespace!`](crate::declare_namespace); the trait is sealed.
+pub trait Namespace: crate::__private::SealedNamespace + 'static {
+    /// The identifier `I` as seen from this namespace.
+    type Of<I: LockLevel>: LockLevel;
+
+    /// Maps an identifier value into this namespace.
+    fn identifier<I: LockLevel>(identifier: I) -> Self::Of<I>;
+}
+
+/// The namespace every identifier lives in unless stated otherwise.
+pub struct RootNamespace;
+
+impl crate::__private::SealedNamespace for RootNamespace {}
+
+impl Namespace for RootNamespace {
+    type Of<I: LockLevel> = I;
+
+    fn identifier<I: LockLevel>(identifier: I) -> I {
+        identifier
+    }
+}
+
+/// The identifier `I` moved into namespace `N`.
+pub struct InNamespace<N, I>(PhantomData<(N, I)>);
+
+impl<N, I> InNamespace<N, I> {
+    pub fn new(_identifier: I) -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<N: 'static, I: LockLevel> crate::__private::SealedLevel for InNamespace<N, I> {}
+
+impl<N: 'static, I: LockLevel> LockLevel for InNamespace<N, I> {
+    type Permission = <I::Permission as NamespacePermission>::In<N>;
+    const LEVEL: u32 = I::LEVEL;
+}
+
+/// Permission types whose chain of identifiers can be moved into a namespace.
+pub trait NamespacePermission: MutexPermission {
+    /// This permission with every identifier in its chain moved into `N`.
+    type In<N: 'static>: NamespacePermission;
+}
+
+impl NamespacePermission for OuterMutexPermission {
+    type In<N: 'static> = OuterMutexPermission;
+}
+
+impl<P: NamespacePermission, I: 'static> NamespacePermission for SequentialMutexPermission<P, I> {
+    type In<N: 'static> = SequentialMutexPermission<P::In<N>, InNamespace<N, I>>;
+}
+
+impl<P: NamespacePermission, I: 'static> NamespacePermission for NestedMutexPermission<P, I> {
+    type In<N: 'static> = NestedMutexPermission<P::In<N>, InNamespace<N, I>>;
+}
+
+/// Declares a new [`Namespace`]: `declare_namespace!(WhatIf);`.
+#[macro_export]
+macro_rules! declare_namespace {
+    ($vis:vis $name:ident) => {
+        $vis struct $name;
+
+        impl $crate::__private::SealedNamespace for $name {}
+        impl $crate::Namespace for $name {
+            type Of<I: $crate::LockLevel> = $crate::InNamespace<$name, I>;
+
+            fn identifier<I: $crate::LockLevel>(identifier: I) -> Self::Of<I> {
+                $crate::InNamespace::new(identifier)
+            }
+        }
+    };
+}
@@ -0,0 +1,266 @@
+//! The reusable half of a TCP echo server on top of a [`NetworkStack`].
+//! `examples/echo_server.rs` wires it to real sockets.
+//!
+//! A [`ConnectionRegistry`] keeps the stats of each open connection in an
+//! [`OrderedLockMap`] keyed by peer address. The map is ordered right after
+//! the transport layer. So every registry operation is a walk: it locks IP,
+//! device and transport in turn, publishes the stack-wide counters on the way,
+//! and only then reaches the connection's entry, with the position after the
+//! transport layer.
+//!
+//! Shutting the registry down cancels the walks of connection threads that
+//! are still sending data, using the crate's [`cancel`](crate::cancel)
+//! support. A thread stuck behind a wedged layer then gets out with its
+//! permission, and its connection is closed. Closing itself is never
+//! cancelled, so the counters add up after a shutdown.
+//!
+//! ```
+//! use deadlock_proof::{net_demo::ConnectionRegistry, NetworkStack, OuterMutexPermission};
+//!
+//! let stack = NetworkStack::new();
+//! let registry = ConnectionRegistry::new();
+//! let peer = "127.0.0.1:4000".parse().unwrap();
+//!
+//! let (permission, opened) = registry.open(&stack, OuterMutexPermission::get(), peer);
+//! opened.unwrap();
+//! let (permission, recorded) = registry.record(&stack, permission, peer, 5);
+//! recorded.unwrap();
+//! let (permission, stats) = registry.close(&stack, permission, peer);
+//! assert_eq!(stats.unwrap().bytes_echoed, 5);
+//!
+//! let (_permission, views) = stack.views(permission);
+//! assert_eq!(views.device.bytes_transmitted, 5);
+//! assert_eq!(views.transport.tcp_connections, 0);
+//! ```
+
+use std::{error::Error, fmt, net::SocketAddr};
+
+use crate::{
+    After, CancellableLockError, DeadlockProofMutex, DeadlockProofMutexGuard, DeviceState, IpState,
+    LockCancellation, LockOutcome, MutexPermission, NetworkStack, OrderedLockMap,
+    OuterMutexPermission, TransportLock, TransportState,
+};
+
+/// Identifies the connection map of a [`ConnectionRegistry`].
+pub struct ConnectionLock;
+
+/// What one connection has echoed so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// Reads that returned data.
+    pub chunks: u64,
+    pub bytes_echoed: u64,
+}
+
+/// The registry was shut down before the operation could finish. Nothing
+/// was recorded in the layers it had not reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShuttingDown;
+
+impl fmt::Display for ShuttingDown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the connection registry is shutting down")
+    }
+}
+
+impl Error for ShuttingDown {}
+
+type Connections =
+    OrderedLockMap<SocketAddr, ConnectionStats, After<TransportLock>, ConnectionLock>;
+
+/// The open connections of a server, keyed by peer address, and the stack
+/// counters they publish to. See the [module docs](self).
+pub struct ConnectionRegistry {
+    connections: Connections,
+    shutdown: LockCancellation,
+}
+
+impl Default for ConnectionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConnectionRegistry {
+    pub fn new() -> Self {
+        Self {
+            connections: OrderedLockMap::new(ConnectionLock),
+            shutdown: LockCancellation::default(),
+        }
+    }
+
+    /// Calls off [`open`](Self::open) and [`record`](Self::record), now and
+    /// from now on, including those waiting for a layer.
+    pub fn shut_down(&self) {
+        self.shutdown.cancel();
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown.is_cancelled()
+    }
+
+    /// How many connections are open.
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// Registers a connection from `peer` and counts it in the transport
+    /// layer.
+    pub fn open(
+        &self,
+        stack: &NetworkStack,
+        permission: OuterMutexPermission,
+        peer: SocketAddr,
+    ) -> (OuterMutexPermission, Result<(), ShuttingDown>) {
+        let walked = self.walk(
+            stack,
+            permission,
+            true,
+            |_| {},
+            |_| {},
+            |transport| transport.tcp_connections += 1,
+        );
+        let after = match walked {
+            Ok(after) => after,
+            Err(permission) => return (permission, Err(ShuttingDown)),
+        };
+        let (after, _) = self
+            .connections
+            .insert(after, peer, ConnectionStats::default());
+        (back_to_root(after), Ok(()))
+    }
+
+    /// Records `bytes` echoed back to `peer`: a packet in the IP layer, the
+    /// bytes in the device layer, and both in the connection's stats.
+    pub fn record(
+        &self,
+        stack: &NetworkStack,
+        permission: OuterMutexPermission,
+        peer: SocketAddr,
+        bytes: usize,
+    ) -> (OuterMutexPermission, Result<(), ShuttingDown>) {
+        let bytes = bytes as u64;
+        let walked = self.walk(
+            stack,
+            permission,
+            true,
+            |ip| ip.packets_processed += 1,
+            |device| device.bytes_transmitted += bytes,
+            |_| {},
+        );
+        let after = match walked {
+            Ok(after) => after,
+            Err(permission) => return (permission, Err(ShuttingDown)),
+        };
+        let mut entry = self.connections.lock_range(after, peer..=peer);
+        for (_, stats) in entry.iter_mut() {
+            stats.chunks += 1;
+            stats.bytes_echoed += bytes;
+        }
+        (back_to_root(entry.unlock_all()), Ok(()))
+    }
+
+    /// Unregisters the connection from `peer` and returns its stats, if it
+    /// was open. Works after a shutdown too.
+    pub fn close(
+        &self,
+        stack: &NetworkStack,
+        permission: OuterMutexPermission,
+        peer: SocketAddr,
+    ) -> (OuterMutexPermission, Option<ConnectionStats>) {
+        let Ok(after) = self.walk(stack, permission, false, |_| {}, |_| {}, |_| {}) else {
+            unreachable!("only cancellable walks give up");
+        };
+        let (after, stats) = self.connections.remove(after, &peer);
+        let after = match stats {
+            Some(_) => {
+                // Whether it was open is only known at the map, so the
+                // transport layer is taken again, from its own position.
+                let mut transport = stack.transport_layer.lock(after.to_earlier()).guard();
+                transport.tcp_connections = transport.tcp_connections.saturating_sub(1);
+                transport.unlock_for_sequential()
+            }
+            None => after,
+        };
+        (back_to_root(after), stats)
+    }
+
+    /// The stats of every open connection, in peer address order.
+    pub fn connections(
+        &self,
+        stack: &NetworkStack,
+        permission: OuterMutexPermission,
+    ) -> (OuterMutexPermission, Vec<(SocketAddr, ConnectionStats)>) {
+        let Ok(after) = self.walk(stack, permission, false, |_| {}, |_| {}, |_| {}) else {
+            unreachable!("only cancellable walks give up");
+        };
+        let entries = self.connections.lock_range(after, ..);
+        let connections = entries
+            .iter()
+            .map(|(peer, stats)| (*peer, *stats))
+            .collect();
+        (back_to_root(entries.unlock_all()), connections)
+    }
+
+    /// Walks the three layers in order, running the update for each, to the
+    /// position of the connection map. A cancellable walk gives up once the
+    /// registry is shut down, with the permission back at the root.
+    fn walk(
+        &self,
+        stack: &NetworkStack,
+        permission: OuterMutexPermission,
+        cancellable: bool,
+        ip: impl FnOnce(&mut IpState),
+        device: impl FnOnce(&mut DeviceState),
+        transport: impl FnOnce(&mut TransportState),
+    ) -> Result<After<TransportLock>, OuterMutexPermission> {
+        let mut ip_guard = self.lock_layer(&stack.ip_layer, permission, cancellable)?;
+        ip(&mut ip_guard);
+        let mut device_guard = self
+            .lock_layer(
+                &stack.device_layer,
+                ip_guard.unlock_for_sequential(),
+                cancellable,
+            )
+            .map_err(|permission| permission.to_earlier())?;
+        device(&mut device_guard);
+        let mut transport_guard = self
+            .lock_layer(
+                &stack.transport_layer,
+                device_guard.unlock_for_sequential(),
+                cancellable,
+            )
+            .map_err(|permission| permission.to_earlier().to_earlier())?;
+        transport(&mut transport_guard);
+        Ok(transport_guard.unlock_for_sequential())
+    }
+
+    /// Locks one layer, giving up on shutdown if `cancellable`.
+    ///
+    /// Panics if the layer is poisoned.
+    fn lock_layer<'a, T, P: MutexPermission, I: 'static>(
+        &self,
+        layer: &'a DeadlockProofMutex<T, P, I>,
+        permission: P,
+        cancellable: bool,
+    ) -> Result<DeadlockProofMutexGuard<'a, T, P, I>, P> {
+        if !cancellable {
+            return Ok(layer.lock(permission).guard());
+        }
+        layer
+            .lock_cancellable(permission, &self.shutdown)
+            .map_err(|error| match error {
+                CancellableLockError::Cancelled(permission) => permission,
+                #[cfg(not(feature = "no-poison"))]
+                poisoned @ CancellableLockError::Poisoned(_) => panic!("{poisoned}"),
+            })
+    }
+}
+
+fn back_to_root(after: After<TransportLock>) -> OuterMutexPermission {
+    after.to_earlier().to_earlier().to_earlier()
+}
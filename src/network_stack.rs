@@ -0,0 +1,616 @@
+//! Netstack3-inspired network stack simulation structures
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt,
+    net::Ipv4Addr,
+    sync::Arc,
+    time::Instant,
+};
+
+use crate::{
+    backpressure::{BoundedUnderLock, HasBoundedQueue, WaitForSpace},
+    config::MutexConfig,
+    gate::OrderingGate,
+    impl_state_view, lock_hierarchy, route_cache::RouteCache,
+    session::{StateCell, TcpState},
+    teardown::{self, TearDown},
+    DeadlockProofMutex,
+    DeadlockProofRwLock, HierarchyGeneration, LockOutcome, LocksAs, Namespace, OuterMutexPermission, Position, RootNamespace,
+    WalkToken,
+};
+
+/// The layer identifier `I` as seen from namespace `N`.
+type Layer<N, I> = <N as Namespace>::Of<I>;
+
+/// A three-layer stack locked in the order IP -> Device -> Transport. Stacks
+/// in different namespaces `N` have distinct lock types, so their walks are
+/// independent of each other.
+pub struct NetworkStack<N: Namespace = RootNamespace> {
+    /// Recent routing lookups. See [`WalkToken::cached_route_lookup`].
+    pub route_cache:
+        DeadlockProofRwLock<RouteCache, Position<Layer<N, RouteCacheLock>>, Layer<N, RouteCacheLock>>,
+    pub ip_layer: DeadlockProofMutex<IpState, Position<Layer<N, IpLock>>, Layer<N, IpLock>>,
+    pub device_layer:
+        DeadlockProofMutex<DeviceState, Position<Layer<N, DeviceLock>>, Layer<N, DeviceLock>>,
+    pub transport_layer: DeadlockProofMutex<
+        TransportState,
+        Position<Layer<N, TransportLock>>,
+        Layer<N, TransportLock>,
+    >,
+    /// Where producers wait for space in the transport layer's
+    /// [`ingress`](TransportState::ingress) queue.
+    pub ingress_space: WaitForSpace,
+    /// The generation every layer belongs to. See [`shutdown`](Self::shutdown).
+    generation: HierarchyGeneration,
+    /// The gate in front of the error path's hierarchy. See [`ErrorPathLock`].
+    pub(crate) error_path: OrderingGate<OuterMutexPermission, ErrorPathLock>,
+}
+
+/// Network stack layer states
+#[derive(Clone)]
+pub struct IpState {
+    pub packets_processed: u64,
+    pub routing_table_size: usize,
+    pub routes: RoutingTable,
+    pub icmp_errors_sent: u64,
+    pub last_icmp_error: Option<IcmpError>,
+}
+
+impl IpState {
+    /// What must hold whenever the IP layer is unlocked: an ICMP error is
+    /// only on record once one was counted. The routes need no check, as
+    /// [`RoutingTable::add`] rejects invalid prefixes.
+    /// [`NetworkStack::from_states`] sets this as the
+    /// [release invariant](crate::invariant) of the IP layer.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        if self.last_icmp_error.is_some() && self.icmp_errors_sent == 0 {
+            return Err("an ICMP error is on record but none was counted".to_owned());
+        }
+        Ok(())
+    }
+}
+
+/// A route to `destination/prefix_len` via `next_hop`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Route {
+    pub destination: Ipv4Addr,
+    pub prefix_len: u8,
+    pub next_hop: Ipv4Addr,
+}
+
+/// Why a route was not added.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RouteError {
+    /// The prefix is longer than the 32 bits of an IPv4 address.
+    PrefixTooLong(u8),
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PrefixTooLong(prefix_len) => {
+                write!(f, "route prefix /{prefix_len} is longer than 32 bits")
+            }
+        }
+    }
+}
+
+impl Error for RouteError {}
+
+impl Route {
+    /// Only called on routes in a [`RoutingTable`], whose prefixes are at
+    /// most 32 bits long.
+    fn matches(&self, addr: Ipv4Addr) -> bool {
+        let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0);
+        u32::from(addr) & mask == u32::from(self.destination) & mask
+    }
+}
+
+/// The IP layer's routes. Every change bumps the generation, which lets
+/// caches tell lookups made against an older table apart.
+#[derive(Clone, Debug, Default)]
+pub struct RoutingTable {
+    routes: Vec<Route>,
+    generation: u64,
+}
+
+impl RoutingTable {
+    /// Longest-prefix match for `addr`.
+    pub fn lookup(&self, addr: Ipv4Addr) -> Option<Ipv4Addr> {
+        self.routes
+            .iter()
+            .filter(|route| route.matches(addr))
+            .max_by_key(|route| route.prefix_len)
+            .map(|route| route.next_hop)
+    }
+
+    /// Adds `route`, replacing any route to the same prefix. A prefix longer
+    /// than 32 bits is rejected and leaves the table as it was.
+    pub fn add(&mut self, route: Route) -> Result<(), RouteError> {
+        if route.prefix_len > 32 {
+            return Err(RouteError::PrefixTooLong(route.prefix_len));
+        }
+        self.remove(route.destination, route.prefix_len);
+        self.routes.push(route);
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Removes the route to `destination/prefix_len`, if there is one.
+    pub fn remove(&mut self, destination: Ipv4Addr, prefix_len: u8) -> Option<Route> {
+        let index = self
+            .routes
+            .iter()
+            .position(|route| route.destination == destination && route.prefix_len == prefix_len)?;
+        self.generation += 1;
+        Some(self.routes.remove(index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Incremented by every change to the table.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+#[derive(Clone)]
+pub struct DeviceState {
+    pub interfaces_active: u32,
+    pub bytes_transmitted: u64,
+}
+
+#[derive(Clone)]
+pub struct TransportState {
+    pub tcp_connections: u32,
+    pub udp_sockets: u32,
+    pub icmp_errors_sent: u64,
+    /// Packets accepted for delivery. Full means the layer is overloaded;
+    /// see [`backpressure`](crate::backpressure).
+    pub ingress: BoundedUnderLock<Vec<u8>>,
+    /// TCP sockets by local port.
+    pub sockets: BTreeMap<u16, TcpSocket>,
+}
+
+/// One TCP socket of the transport layer. Its state only changes through
+/// the [session handles](crate::session).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TcpSocket {
+    pub state: StateCell<TcpState>,
+}
+
+impl TcpSocket {
+    /// A socket waiting for connections.
+    pub fn listening() -> Self {
+        Self {
+            state: StateCell::new(TcpState::Listen),
+        }
+    }
+}
+
+/// The capacity of the ingress queue of a [`NetworkStack::new`] stack.
+pub const DEFAULT_INGRESS_CAPACITY: usize = 1024;
+
+impl HasBoundedQueue for TransportState {
+    type Item = Vec<u8>;
+
+    fn queue(&self) -> &BoundedUnderLock<Vec<u8>> {
+        &self.ingress
+    }
+
+    fn queue_mut(&mut self) -> &mut BoundedUnderLock<Vec<u8>> {
+        &mut self.ingress
+    }
+}
+
+/// Why an ICMP error is being generated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IcmpError {
+    HostUnreachable,
+    PortUnreachable,
+    TimeExceeded,
+}
+
+impl_state_view!(
+    /// The IP layer counters, without the routes themselves.
+    pub IpStateView from IpState {
+        packets_processed: u64,
+        routing_table_size: usize,
+        icmp_errors_sent: u64,
+        last_icmp_error: Option<IcmpError>,
+    }
+);
+
+impl_state_view!(
+    /// The device layer counters.
+    pub DeviceStateView from DeviceState {
+        interfaces_active: u32,
+        bytes_transmitted: u64,
+    }
+);
+
+impl_state_view!(
+    /// The transport layer counters.
+    pub TransportStateView from TransportState {
+        tcp_connections: u32,
+        udp_sockets: u32,
+        icmp_errors_sent: u64,
+    }
+);
+
+/// Views of all three layers, as taken by [`NetworkStack::views`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct StackViews {
+    pub ip: IpStateView,
+    pub device: DeviceStateView,
+    pub transport: TransportStateView,
+}
+
+/// Everything a [`NetworkStack`] held, as handed out by
+/// [`NetworkStack::try_teardown`].
+pub struct OwnedStates {
+    pub route_cache: RouteCache,
+    pub ip: IpState,
+    pub device: DeviceState,
+    pub transport: TransportState,
+}
+
+/// Lock identifiers for the network stack layers
+pub struct IpLock;
+pub struct DeviceLock; 
+pub struct TransportLock;
+pub struct RouteCacheLock;
+
+lock_hierarchy!(OuterMutexPermission => IpLock, DeviceLock, TransportLock);
+
+/// The names of the stack's layers, in the order they are locked.
+pub const LOCK_ORDER: &[&str] = IpLock::LOCK_ORDER;
+
+// The route cache is consulted before the IP layer, but as a hierarchy of
+// its own rather than a level in front of `IpLock`; see `route_cache`. Both
+// take the outer permission, so a thread holding either one cannot lock the
+// other: a lookup must release the cache before it can reach the routing
+// table, and the table before it can go back to populate the cache.
+lock_hierarchy!(OuterMutexPermission => RouteCacheLock);
+
+/// Levels of the error path's hierarchy, which takes the layers in the
+/// reverse order: the transport layer, and the IP layer inside it. The root
+/// level is each stack's own gate in front of that hierarchy.
+///
+/// The error path holds the transport layer while it takes the IP layer, and
+/// a [`StackTransaction`](crate::StackTransaction) commit holds the IP layer
+/// while it takes the transport layer. Both pass the stack's gate first, so
+/// they never wait for a layer at the same time. Every other walk holds at
+/// most one layer at a time, so it cannot close a cycle with either.
+///
+/// The levels cannot be created outside this crate, so no gate or mutex
+/// but the stack's own stands at them, and the tokens the gate hands out
+/// never leave [`icmp_error_path`](NetworkStack::icmp_error_path): a token
+/// from one stack's gate cannot reach another stack's layers:
+///
+/// ```compile_fail
+/// use deadlock_proof::{gate::OrderingGate, ErrorPathLock, OuterMutexPermission};
+///
+/// let gate = OrderingGate::<OuterMutexPermission, _>::new(ErrorPathLock(()));
+/// ```
+pub struct ErrorPathLock(());
+/// The transport layer, locked first on the error path.
+pub struct ErrorTransportLock(());
+/// The IP layer, locked inside the transport layer on the error path.
+pub struct ErrorIpLock(());
+
+lock_hierarchy!(OuterMutexPermission => ErrorPathLock);
+lock_hierarchy!(within ErrorPathLock => ErrorTransportLock);
+lock_hierarchy!(within ErrorTransportLock => ErrorIpLock);
+
+// The error path's positions lock the layers of a stack whose gate they
+// passed; see `ErrorPathLock`.
+impl LocksAs<Position<TransportLock>, TransportLock, ErrorTransportLock>
+    for Position<ErrorTransportLock>
+{
+}
+impl LocksAs<Position<IpLock>, IpLock, ErrorIpLock> for Position<ErrorIpLock> {}
+
+impl NetworkStack {
+    pub fn new() -> Self {
+        Self::with_ingress_capacity(DEFAULT_INGRESS_CAPACITY)
+    }
+
+    /// Like [`new`](Self::new), with room for `capacity` packets in the
+    /// transport layer's ingress queue.
+    pub fn with_ingress_capacity(capacity: usize) -> Self {
+        Self::from_states(
+            IpState {
+                packets_processed: 0,
+                routing_table_size: 0,
+                routes: RoutingTable::default(),
+                icmp_errors_sent: 0,
+                last_icmp_error: None,
+            },
+            DeviceState {
+                interfaces_active: 0,
+                bytes_transmitted: 0,
+            },
+            TransportState {
+                tcp_connections: 0,
+                udp_sockets: 0,
+                icmp_errors_sent: 0,
+                ingress: BoundedUnderLock::with_capacity(capacity),
+                sockets: BTreeMap::new(),
+            },
+        )
+    }
+
+    /// Creates a stack whose initial layer states depend on each other.
+    ///
+    /// Every layer starts out as in [`new`](Self::new), already in its mutex,
+    /// and `init` sets them up with a walk like any other, so initialization
+    /// follows the same lock order as the code that runs later:
+    ///
+    /// ```
+    /// use deadlock_proof::*;
+    ///
+    /// let (permission, stack) = NetworkStack::new_with(OuterMutexPermission::get(), |walk| {
+    ///     let stack = walk.stack();
+    ///     walk.with_permission(|permission| {
+    ///         let ip = stack.ip_layer.lock_in_context(permission).guard();
+    ///         let mut device = stack.device_layer.lock_in_context(ip.unlock_for_sequential()).guard();
+    ///         device.interfaces_active = 2;
+    ///         let interfaces = device.interfaces_active;
+    ///         let mut transport = stack.transport_layer.lock_in_context(device.unlock_for_sequential()).guard();
+    ///         transport.udp_sockets = interfaces;
+    ///         (transport.unlock().to_earlier().to_earlier(), ())
+    ///     })
+    /// });
+    /// assert_eq!(stack.views(permission).1.transport.udp_sockets, 2);
+    /// ```
+    pub fn new_with(
+        permission: OuterMutexPermission,
+        init: impl FnOnce(&mut WalkToken<'_>),
+    ) -> (OuterMutexPermission, Self) {
+        let stack = Self::new();
+        let mut walk = stack.begin_walk(permission);
+        init(&mut walk);
+        let permission = walk.finish();
+        (permission, stack)
+    }
+
+    /// Records `err` in the transport layer and then in the IP layer, the
+    /// direction an error travels, and returns the root of the error path's
+    /// hierarchy.
+    ///
+    /// The stack's gate is passed first, then both layers are held together,
+    /// the IP layer locked inside the transport layer, so no walk sees the
+    /// error counted in one layer but not the other:
+    ///
+    /// ```
+    /// use deadlock_proof::*;
+    ///
+    /// let stack = NetworkStack::new();
+    /// let permission = stack.icmp_error_path(OuterMutexPermission::get(), IcmpError::PortUnreachable);
+    /// let ip = stack.ip_layer.lock(permission).guard();
+    /// assert_eq!(ip.icmp_errors_sent, 1);
+    /// ```
+    ///
+    /// Panics if either layer is poisoned.
+    pub fn icmp_error_path(
+        &self,
+        permission: OuterMutexPermission,
+        err: IcmpError,
+    ) -> OuterMutexPermission {
+        let (gate, permission) = self.error_path.lock_for_nested(permission);
+        let (mut transport, inside) = self
+            .transport_layer
+            .lock_for_nested_as::<_, ErrorTransportLock>(permission)
+            .guard();
+        let mut ip = self.ip_layer.lock_as::<_, ErrorIpLock>(inside).guard();
+        transport.icmp_errors_sent += 1;
+        ip.icmp_errors_sent += 1;
+        ip.last_icmp_error = Some(err);
+        gate.unlock(transport.unlock(ip.unlock()))
+    }
+
+    /// Compatibility shim running [`WalkToken::fork`] as a walk of its own.
+    #[deprecated(note = "use `begin_walk` and `WalkToken::fork`")]
+    pub fn fork<M: Namespace>(
+        &self,
+        permission: OuterMutexPermission,
+    ) -> (OuterMutexPermission, NetworkStack<M>) {
+        let mut walk = self.begin_walk(permission);
+        let fork = walk.fork();
+        (walk.finish(), fork)
+    }
+
+    /// Runs [`WalkToken::views`] as a walk of its own.
+    pub fn views(&self, permission: OuterMutexPermission) -> (OuterMutexPermission, StackViews) {
+        let mut walk = self.begin_walk(permission);
+        let views = walk.views();
+        (walk.finish(), views)
+    }
+}
+
+impl WalkToken<'_> {
+    /// Runs [`NetworkStack::icmp_error_path`] from the walk's permission.
+    pub fn icmp_error_path(&mut self, err: IcmpError) {
+        let stack = self.stack();
+        self.with_outer(|permission| (stack.icmp_error_path(permission, err), ()))
+    }
+
+    /// Copies the current state into an independent stack in namespace `M`.
+    ///
+    /// The layers are visited with the usual sequential walk, so only one
+    /// layer is locked at a time and only for as long as it takes to clone
+    /// it. The copy is therefore not an atomic snapshot across layers.
+    ///
+    /// Panics if any layer is poisoned.
+    ///
+    /// The fork's permission chain is its own, so a position reached in one
+    /// stack cannot be used to lock the other:
+    ///
+    /// ```compile_fail
+    /// use deadlock_proof::*;
+    ///
+    /// declare_namespace!(WhatIf);
+    ///
+    /// let stack = NetworkStack::new();
+    /// let mut walk = stack.begin_walk(OuterMutexPermission::get());
+    /// let fork = walk.fork::<WhatIf>();
+    /// let past_ip = stack.ip_layer.lock(walk.finish()).guard().unlock_for_sequential();
+    /// fork.device_layer.lock(past_ip);
+    /// ```
+    pub fn fork<M: Namespace>(&mut self) -> NetworkStack<M> {
+        let stack = self.stack();
+        self.with_outer(|permission| {
+            let ip_guard = stack.ip_layer.lock(permission).guard();
+            let ip = ip_guard.clone();
+            let permission = ip_guard.unlock_for_sequential();
+
+            let device_guard = stack.device_layer.lock(permission).guard();
+            let device = device_guard.clone();
+            let permission = device_guard.unlock_for_sequential();
+
+            let transport_guard = stack.transport_layer.lock(permission).guard();
+            let transport = transport_guard.clone();
+            let permission = transport_guard.unlock_for_sequential();
+
+            let permission = permission.to_earlier().to_earlier().to_earlier();
+            (permission, NetworkStack::from_states(ip, device, transport))
+        })
+    }
+
+    /// Takes a view of every layer, with the usual sequential walk. Like
+    /// [`fork`](Self::fork), each layer is only locked while its view is
+    /// taken, so the views are not an atomic snapshot across layers.
+    ///
+    /// Panics if any layer is poisoned.
+    pub fn views(&mut self) -> StackViews {
+        let stack = self.stack();
+        self.with_outer(|permission| {
+            let ip_guard = stack.ip_layer.lock(permission).guard();
+            let ip = ip_guard.view();
+            let permission = ip_guard.unlock_for_sequential();
+
+            let device_guard = stack.device_layer.lock(permission).guard();
+            let device = device_guard.view();
+            let permission = device_guard.unlock_for_sequential();
+
+            let transport_guard = stack.transport_layer.lock(permission).guard();
+            let transport = transport_guard.view();
+            let permission = transport_guard.unlock_for_sequential();
+
+            let permission = permission.to_earlier().to_earlier().to_earlier();
+            (permission, StackViews { ip, device, transport })
+        })
+    }
+}
+
+impl<N: Namespace> NetworkStack<N> {
+    /// Creates a stack in namespace `N` with the given initial layer states.
+    ///
+    /// Every stack is a [generation](crate::generation) of its own, so
+    /// stacks can be built and torn down any number of times. The layers
+    /// are [named](DeadlockProofMutex::name) `ip-layer`, `device-layer` and
+    /// `transport-layer`, whatever the namespace, under `tracking`.
+    pub fn from_states(ip: IpState, device: DeviceState, transport: TransportState) -> Self {
+        let generation = HierarchyGeneration::new();
+        #[cfg(feature = "tracking")]
+        let named = |name| MutexConfig::new().name(name);
+        #[cfg(not(feature = "tracking"))]
+        let named = |_| MutexConfig::new();
+        let ip_layer = DeadlockProofMutex::with_config_in(
+            ip,
+            N::identifier(IpLock),
+            named("ip-layer"),
+            &generation,
+        );
+        ip_layer.set_release_invariant(IpState::check_invariants);
+        Self {
+            route_cache: DeadlockProofRwLock::new_in(
+                RouteCache::new(),
+                N::identifier(RouteCacheLock),
+                &generation,
+            ),
+            ip_layer,
+            device_layer: DeadlockProofMutex::with_config_in(
+                device,
+                N::identifier(DeviceLock),
+                named("device-layer"),
+                &generation,
+            ),
+            transport_layer: DeadlockProofMutex::with_config_in(
+                transport,
+                N::identifier(TransportLock),
+                named("transport-layer"),
+                &generation,
+            ),
+            ingress_space: WaitForSpace::new(),
+            generation,
+            error_path: OrderingGate::new(ErrorPathLock(())),
+        }
+    }
+
+    /// The generation the stack's layers belong to.
+    pub fn generation(&self) -> &HierarchyGeneration {
+        &self.generation
+    }
+
+    /// Closes the stack's generation, taking its layers out of the
+    /// diagnostics registry, when the stack is being torn down but may
+    /// outlive the teardown, say in an `Arc` a straggling thread still has.
+    /// Dropping the stack does the same. The layers still lock as before.
+    pub fn shutdown(&self) {
+        self.generation.close();
+    }
+
+    /// Takes the stack apart if this is the last reference to it and no
+    /// layer is locked, or hands it back. See [`teardown`].
+    pub fn try_teardown(self: Arc<Self>) -> Result<OwnedStates, Arc<Self>> {
+        teardown::try_teardown(self)
+    }
+
+    /// [`try_teardown`](Self::try_teardown), attempted again until
+    /// `deadline`.
+    pub fn teardown_when_idle(self: Arc<Self>, deadline: Instant) -> Result<OwnedStates, Arc<Self>> {
+        teardown::teardown_when_idle(self, deadline)
+    }
+}
+
+impl<N: Namespace> TearDown for NetworkStack<N> {
+    type States = OwnedStates;
+
+    fn is_idle(&self) -> bool {
+        self.route_cache.is_idle()
+            && self.ip_layer.is_idle()
+            && self.device_layer.is_idle()
+            && self.transport_layer.is_idle()
+    }
+
+    /// Closes the stack's generation too.
+    fn into_states(self) -> OwnedStates {
+        let (route_cache, ip, device, transport) =
+            (self.route_cache, self.ip_layer, self.device_layer, self.transport_layer)
+                .into_states();
+        OwnedStates {
+            route_cache,
+            ip,
+            device,
+            transport,
+        }
+    }
+}
+
+impl Default for NetworkStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
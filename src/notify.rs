@@ -0,0 +1,197 @@
+//! Waiting for a state change next to the async mutex.
+//!
+//! [`AsyncNotify`] wakes tasks waiting for "something changed", like
+//! tokio's `Notify`. Waiting is not locking, so
+//! [`notified`](AsyncNotify::notified) takes no permission. It is a hazard
+//! all the same while the waiting task holds an
+//! [`AsyncMutexGuard`](crate::AsyncMutexGuard): the notifier may need that
+//! very lock before it can notify, and both tasks then wait forever. Debug
+//! builds catch this by panicking when a task awaits a notification with an
+//! async guard held. Release the guard first, and lock again once notified:
+//!
+//! ```
+//! use std::{future::Future, pin::pin, task::{Context, Waker}};
+//!
+//! use deadlock_proof::notify::AsyncNotify;
+//!
+//! let changed = AsyncNotify::new();
+//! changed.notify_one();
+//! // Nobody was waiting, so the next wait finds the permit.
+//! let mut cx = Context::from_waker(Waker::noop());
+//! assert!(pin!(changed.notified()).poll(&mut cx).is_ready());
+//! assert!(pin!(changed.notified()).poll(&mut cx).is_pending());
+//! ```
+//!
+//! Guards are attributed to the waker of the task that acquired them, so the
+//! check never fires for a different task, but it can miss a task whose
+//! futures poll with wakers of their own, as some combinators do. Tasks that
+//! share one waker, such as `Waker::noop`, count as one task.
+
+use std::{
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::{Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll, Waker},
+};
+
+/// A notification a waiter has received but not yet observed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Notification {
+    One,
+    All,
+}
+
+struct Waiter {
+    id: u64,
+    waker: Waker,
+    notified: Option<Notification>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Left by a `notify_one` that found nobody waiting.
+    permit: bool,
+    /// Registered waiters, oldest first.
+    waiters: Vec<Waiter>,
+    next_id: u64,
+}
+
+impl State {
+    fn notify_one(&mut self) {
+        match self
+            .waiters
+            .iter_mut()
+            .find(|waiter| waiter.notified.is_none())
+        {
+            Some(waiter) => {
+                waiter.notified = Some(Notification::One);
+                waiter.waker.wake_by_ref();
+            }
+            None => self.permit = true,
+        }
+    }
+}
+
+/// Wakes tasks waiting for a state change. Needs no permission on either
+/// side.
+#[derive(Default)]
+pub struct AsyncNotify {
+    state: Mutex<State>,
+}
+
+impl AsyncNotify {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // No user code runs under this lock.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Waits for a notification. The future starts waiting when first
+    /// polled; a permit left by [`notify_one`](Self::notify_one) completes it
+    /// straight away.
+    ///
+    /// In debug builds, polling it panics if the polling task holds an
+    /// [`AsyncMutexGuard`](crate::AsyncMutexGuard). See the
+    /// [module docs](self).
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            id: None,
+            done: false,
+        }
+    }
+
+    /// Wakes the oldest waiter, or leaves a permit for the next one if
+    /// nobody is waiting. Permits do not add up.
+    pub fn notify_one(&self) {
+        self.state().notify_one();
+    }
+
+    /// Wakes every task currently waiting, without leaving a permit.
+    pub fn notify_waiters(&self) {
+        let mut state = self.state();
+        for waiter in &mut state.waiters {
+            if waiter.notified.is_none() {
+                waiter.notified = Some(Notification::All);
+                waiter.waker.wake_by_ref();
+            }
+        }
+    }
+}
+
+/// Future returned by [`AsyncNotify::notified`]. Dropping it after it was
+/// picked by `notify_one` passes the notification on.
+pub struct Notified<'a> {
+    notify: &'a AsyncNotify,
+    /// Set while registered as a waiter.
+    id: Option<u64>,
+    done: bool,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        #[cfg(debug_assertions)]
+        assert!(
+            !crate::task::held::by(cx.waker()),
+            "awaited an AsyncNotify while holding an AsyncMutexGuard; \
+             the notifier may need that lock first"
+        );
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(());
+        }
+        let mut state = this.notify.state();
+        let Some(id) = this.id else {
+            if mem::take(&mut state.permit) {
+                this.done = true;
+                return Poll::Ready(());
+            }
+            let id = state.next_id;
+            state.next_id += 1;
+            state.waiters.push(Waiter {
+                id,
+                waker: cx.waker().clone(),
+                notified: None,
+            });
+            this.id = Some(id);
+            return Poll::Pending;
+        };
+        let index = state
+            .waiters
+            .iter()
+            .position(|waiter| waiter.id == id)
+            .expect("registered waiters stay until they are done");
+        let waiter = &mut state.waiters[index];
+        if waiter.notified.is_some() {
+            state.waiters.remove(index);
+            this.id = None;
+            this.done = true;
+            return Poll::Ready(());
+        }
+        if !waiter.waker.will_wake(cx.waker()) {
+            waiter.waker = cx.waker().clone();
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else {
+            return;
+        };
+        let mut state = self.notify.state();
+        if let Some(index) = state.waiters.iter().position(|waiter| waiter.id == id) {
+            let waiter = state.waiters.remove(index);
+            if waiter.notified == Some(Notification::One) {
+                state.notify_one();
+            }
+        }
+    }
+}
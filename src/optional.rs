@@ -0,0 +1,116 @@
+//! Locking a mutex that may not exist any more.
+//!
+//! Per-object mutexes, such as one per network interface, can be removed
+//! between looking them up and locking them. Matching on an
+//! `Option<&DeadlockProofMutex<..>>` by hand makes it easy to drop the
+//! permission in the `None` arm. [`DeadlockProofMutex::lock_opt`] instead
+//! returns an [`EitherGuard`] whose [`Absent`](EitherGuard::Absent) arm
+//! holds the permission, so unlocking works the same in both cases:
+//!
+//! ```
+//! use std::collections::HashMap;
+//!
+//! use deadlock_proof::*;
+//!
+//! struct InterfaceLock;
+//! type Interface = DeadlockProofMutex<u64, OuterMutexPermission, InterfaceLock>;
+//!
+//! let mut interfaces = HashMap::from([(1, Interface::new(0, InterfaceLock))]);
+//!
+//! let mut permission = OuterMutexPermission::get();
+//! for id in [1, 2, 1] {
+//!     let mut interface = DeadlockProofMutex::lock_opt(interfaces.get(&id), permission).guard();
+//!     if let Some(bytes) = interface.get_mut() {
+//!         *bytes += 1500;
+//!     }
+//!     permission = interface.unlock();
+//! }
+//! interfaces.remove(&1);
+//!
+//! let interface = DeadlockProofMutex::lock_opt(interfaces.get(&1), permission).guard();
+//! assert!(!interface.is_present());
+//! ```
+
+use crate::{
+    poison, DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofPoisonError, LockResult,
+    MutexPermission, SequentialMutexPermission,
+};
+
+/// The guard of a mutex that was there to lock, or the permission back if it
+/// was not. Returned by [`DeadlockProofMutex::lock_opt`].
+pub enum EitherGuard<'a, T, P: MutexPermission, I: 'static> {
+    /// The mutex existed and is now locked.
+    Present(DeadlockProofMutexGuard<'a, T, P, I>),
+    /// There was no mutex; the permission is unused.
+    Absent(P),
+}
+
+impl<'a, T, P: MutexPermission, I: 'static> EitherGuard<'a, T, P, I> {
+    /// Whether there was a mutex to lock.
+    pub fn is_present(&self) -> bool {
+        matches!(self, Self::Present(_))
+    }
+
+    /// The data, if there was a mutex.
+    pub fn get(&self) -> Option<&T> {
+        match self {
+            Self::Present(guard) => Some(guard),
+            Self::Absent(_) => None,
+        }
+    }
+
+    /// Mutable access to the data, if there was a mutex. Counts as a
+    /// modification, as [`DeadlockProofMutexGuard::get_mut`] does.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        match self {
+            Self::Present(guard) => Some(guard.get_mut()),
+            Self::Absent(_) => None,
+        }
+    }
+
+    /// The guard, or the permission if there was no mutex.
+    pub fn into_guard(self) -> Result<DeadlockProofMutexGuard<'a, T, P, I>, P> {
+        match self {
+            Self::Present(guard) => Ok(guard),
+            Self::Absent(permission) => Err(permission),
+        }
+    }
+
+    /// Unlock the mutex, if any, and return the permission token.
+    pub fn unlock(self) -> P {
+        match self {
+            Self::Present(guard) => guard.unlock(),
+            Self::Absent(permission) => permission,
+        }
+    }
+
+    /// Unlock the mutex, if any, and return a sequential permission token.
+    /// A missing mutex is stepped past as if it had been locked and released,
+    /// so a walk goes on to the next level either way.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        match self {
+            Self::Present(guard) => guard.unlock_for_sequential(),
+            Self::Absent(permission) => SequentialMutexPermission::new(permission),
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Locks `mutex` if there is one. Otherwise the permission comes back
+    /// inside [`EitherGuard::Absent`], ready to be unlocked like a guard.
+    #[track_caller]
+    pub fn lock_opt(mutex: Option<&Self>, permission: P) -> OptionalLockResult<'_, T, P, I> {
+        match mutex {
+            Some(mutex) => poison::map!(mutex.lock(permission), EitherGuard::Present),
+            #[cfg(not(feature = "no-poison"))]
+            None => Ok(EitherGuard::Absent(permission)),
+            #[cfg(feature = "no-poison")]
+            None => EitherGuard::Absent(permission),
+        }
+    }
+}
+
+/// Result of [`DeadlockProofMutex::lock_opt`]. Only a mutex that is present
+/// can be poisoned.
+pub type OptionalLockResult<'a, T, P, I> =
+    LockResult<EitherGuard<'a, T, P, I>, DeadlockProofPoisonError<'a, T, P, I>>;
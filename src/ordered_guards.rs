@@ -0,0 +1,290 @@
+//! Holding several guards at once, released innermost first.
+//!
+//! An [`OrderedGuards`] starts out with a root permission and locks one
+//! mutex after the other through it, each with the permission the previous
+//! one handed out. The guards are kept in a typed stack, so the permission
+//! type is checked at every step. However the container goes away, be it
+//! [`unlock_all`](OrderedGuards::unlock_all), a plain drop, or unwinding,
+//! the guards are released in reverse acquisition order.
+//!
+//! ```
+//! use deadlock_proof::{
+//!     DeadlockProofMutex, LockOutcome, NestedMutexPermission, OrderedGuards,
+//!     OuterMutexPermission,
+//! };
+//!
+//! struct Table;
+//! struct Row;
+//!
+//! let table = DeadlockProofMutex::new(vec![1], Table);
+//! let row: DeadlockProofMutex<u32, NestedMutexPermission<OuterMutexPermission, Table>, Row> =
+//!     DeadlockProofMutex::new(0, Row);
+//!
+//! let mut guards = OrderedGuards::new(OuterMutexPermission::get())
+//!     .lock_nested(&table)
+//!     .guard()
+//!     .lock(&row)
+//!     .guard();
+//! *guards.get_mut() += 1;
+//! guards.below_mut().get_mut().push(2);
+//! let permission: OuterMutexPermission = guards.unlock_all();
+//! # drop(permission);
+//! ```
+//!
+//! A mutex only fits on the stack with the permission the top of the stack
+//! provides:
+//!
+//! ```compile_fail
+//! use deadlock_proof::{DeadlockProofMutex, LockOutcome, OrderedGuards, OuterMutexPermission};
+//!
+//! struct Table;
+//! struct Other;
+//!
+//! let table = DeadlockProofMutex::new(0u32, Table);
+//! let other: DeadlockProofMutex<u32, OuterMutexPermission, Other> =
+//!     DeadlockProofMutex::new(0, Other);
+//! OrderedGuards::new(OuterMutexPermission::get())
+//!     .lock_nested(&table)
+//!     .guard()
+//!     .lock(&other); // wants the root permission, not the one inside `table`
+//! ```
+
+use std::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{
+    poison, DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard,
+    DeadlockProofPoisonError, LockResult, MutexPermission, NestedMutexPermission,
+};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// The typed stack inside an [`OrderedGuards`]: [`Root`] at the bottom, then
+/// one [`Nested`] per nested guard, possibly topped off by a [`Leaf`].
+pub trait GuardStack: sealed::Sealed {
+    /// The permission the top of the stack hands out for the next lock;
+    /// `()` once a [`Leaf`] is on top.
+    type Token;
+    /// The permission the stack was started with.
+    type Root: MutexPermission;
+
+    /// Releases every guard, top first, and returns the root permission.
+    fn release(self, token: Self::Token) -> Self::Root;
+}
+
+/// The bottom of a stack, holding no guard.
+pub struct Root<R>(PhantomData<R>);
+
+impl<R> sealed::Sealed for Root<R> {}
+
+impl<R: MutexPermission> GuardStack for Root<R> {
+    type Token = R;
+    type Root = R;
+
+    fn release(self, token: R) -> R {
+        token
+    }
+}
+
+/// A nested guard on top of the stack `S`.
+pub struct Nested<'a, S: GuardStack, T, I: 'static>
+where
+    S::Token: MutexPermission,
+{
+    // Declared before `below` so that even a bare stack drops top first.
+    guard: DeadlockProofNestedMutexGuard<'a, T, S::Token, I>,
+    below: S,
+}
+
+impl<S: GuardStack, T, I: 'static> sealed::Sealed for Nested<'_, S, T, I> where
+    S::Token: MutexPermission
+{
+}
+
+impl<S: GuardStack, T, I: 'static> GuardStack for Nested<'_, S, T, I>
+where
+    S::Token: MutexPermission,
+{
+    type Token = NestedMutexPermission<S::Token, I>;
+    type Root = S::Root;
+
+    fn release(self, token: Self::Token) -> S::Root {
+        let token = self.guard.unlock(token);
+        self.below.release(token)
+    }
+}
+
+/// A plain guard on top of the stack `S`. Nothing can be locked above it.
+pub struct Leaf<'a, S: GuardStack, T, I: 'static>
+where
+    S::Token: MutexPermission,
+{
+    guard: DeadlockProofMutexGuard<'a, T, S::Token, I>,
+    below: S,
+}
+
+impl<S: GuardStack, T, I: 'static> sealed::Sealed for Leaf<'_, S, T, I> where
+    S::Token: MutexPermission
+{
+}
+
+impl<S: GuardStack, T, I: 'static> GuardStack for Leaf<'_, S, T, I>
+where
+    S::Token: MutexPermission,
+{
+    type Token = ();
+    type Root = S::Root;
+
+    fn release(self, (): ()) -> S::Root {
+        let token = self.guard.unlock();
+        self.below.release(token)
+    }
+}
+
+/// Accessors shared by [`Nested`] and [`Leaf`].
+macro_rules! stack_node {
+    ($node:ident) => {
+        impl<S: GuardStack, T, I: 'static> $node<'_, S, T, I>
+        where
+            S::Token: MutexPermission,
+        {
+            /// The data behind the guard on top.
+            pub fn get(&self) -> &T {
+                &self.guard
+            }
+
+            /// Mutable access to the data behind the guard on top, as
+            /// [`DeadlockProofMutexGuard::get_mut`].
+            pub fn get_mut(&mut self) -> &mut T {
+                self.guard.get_mut()
+            }
+
+            /// The guards below the top one.
+            pub fn below(&self) -> &S {
+                &self.below
+            }
+
+            /// The guards below the top one, mutably.
+            pub fn below_mut(&mut self) -> &mut S {
+                &mut self.below
+            }
+        }
+    };
+}
+
+stack_node!(Nested);
+stack_node!(Leaf);
+
+impl<S: GuardStack, T, I: 'static> OrderedGuards<Nested<'_, S, T, I>>
+where
+    S::Token: MutexPermission,
+{
+    /// Releases only the guard on top.
+    pub fn pop(mut self) -> OrderedGuards<S> {
+        let (Nested { guard, below }, token) = self.take();
+        OrderedGuards(Some((below, guard.unlock(token))))
+    }
+}
+
+impl<S: GuardStack, T, I: 'static> OrderedGuards<Leaf<'_, S, T, I>>
+where
+    S::Token: MutexPermission,
+{
+    /// Releases only the guard on top.
+    pub fn pop(mut self) -> OrderedGuards<S> {
+        let (Leaf { guard, below }, ()) = self.take();
+        OrderedGuards(Some((below, guard.unlock())))
+    }
+}
+
+/// Guards locked one inside the other, released in reverse order when
+/// dropped. See the [module docs](self).
+///
+/// Derefs to the stack, whose top guard's data is reached with `get` and
+/// `get_mut`, and the guards below through `below` and `below_mut`.
+pub struct OrderedGuards<S: GuardStack>(Option<(S, S::Token)>);
+
+impl<R: MutexPermission> OrderedGuards<Root<R>> {
+    /// An empty stack that will lock with `root` first.
+    pub fn new(root: R) -> Self {
+        Self(Some((Root(PhantomData), root)))
+    }
+}
+
+impl<S: GuardStack> OrderedGuards<S> {
+    fn take(&mut self) -> (S, S::Token) {
+        self.0.take().expect("only emptied on the way out")
+    }
+
+    /// Releases every guard, innermost first, and returns the root
+    /// permission.
+    pub fn unlock_all(mut self) -> S::Root {
+        let (stack, token) = self.take();
+        stack.release(token)
+    }
+}
+
+impl<S: GuardStack> OrderedGuards<S>
+where
+    S::Token: MutexPermission,
+{
+    /// Locks `mutex` for nesting with the permission on top of the stack and
+    /// pushes its guard. If the mutex is poisoned, the guards so far are
+    /// released and their permission is lost, as with a poisoned lock
+    /// outside the container.
+    pub fn lock_nested<'a, T, I: 'static>(
+        mut self,
+        mutex: &'a DeadlockProofMutex<T, S::Token, I>,
+    ) -> PushResult<'a, T, S::Token, I, Nested<'a, S, T, I>> {
+        let (below, token) = self.take();
+        poison::map!(mutex.lock_for_nested(token), |(guard, token)| {
+            OrderedGuards(Some((Nested { guard, below }, token)))
+        })
+    }
+
+    /// Locks `mutex` with the permission on top of the stack and pushes its
+    /// guard as the last one. Poisoning is handled as by
+    /// [`lock_nested`](Self::lock_nested).
+    pub fn lock<'a, T, I: 'static>(
+        mut self,
+        mutex: &'a DeadlockProofMutex<T, S::Token, I>,
+    ) -> PushResult<'a, T, S::Token, I, Leaf<'a, S, T, I>> {
+        let (below, token) = self.take();
+        poison::map!(mutex.lock(token), |guard| {
+            OrderedGuards(Some((Leaf { guard, below }, ())))
+        })
+    }
+}
+
+/// Result of [`OrderedGuards::lock_nested`] and [`OrderedGuards::lock`]: the
+/// grown stack `S`, or the poisoned mutex locked with permission `P`.
+pub type PushResult<'a, T, P, I, S> =
+    LockResult<OrderedGuards<S>, DeadlockProofPoisonError<'a, T, P, I>>;
+
+impl<S: GuardStack> Deref for OrderedGuards<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.0.as_ref().expect("only emptied on the way out").0
+    }
+}
+
+impl<S: GuardStack> DerefMut for OrderedGuards<S> {
+    fn deref_mut(&mut self) -> &mut S {
+        &mut self.0.as_mut().expect("only emptied on the way out").0
+    }
+}
+
+impl<S: GuardStack> Drop for OrderedGuards<S> {
+    /// Releases the guards innermost first and hands the root permission to
+    /// [`MutexPermission::recover`].
+    fn drop(&mut self) {
+        if let Some((stack, token)) = self.0.take() {
+            stack.release(token).recover();
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! A map whose entries are locked in key order, a range at a time.
+//!
+//! [`OrderedLockMap::lock_range`] locks every entry in a key range, one after
+//! the other in ascending key order. Since every range operation uses that
+//! same order, overlapping ranges locked from different threads cannot
+//! deadlock: whoever gets the lowest shared key first also gets the rest.
+//!
+//! A range is registered before its first entry is locked, and while it is
+//! registered no entry can be inserted into it or removed from it, so the
+//! set of entries a range lock covers is fixed from the moment it starts.
+//! Entries are not poisoned if a holder panics.
+
+use std::{
+    cell::UnsafeCell,
+    collections::BTreeMap,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    ptr::NonNull,
+    sync::{Condvar, Mutex, MutexGuard},
+};
+
+use crate::{MutexPermission, PermissionSyncSendWrapper};
+
+struct Slot<V> {
+    value: UnsafeCell<V>,
+    locked: bool,
+}
+
+struct MapState<K, V> {
+    /// Boxed so values stay in place while the map is rebalanced.
+    entries: BTreeMap<K, Box<Slot<V>>>,
+    /// Ranges with a lock in progress or held, by id.
+    ranges: Vec<(u64, Bound<K>, Bound<K>)>,
+    next_range: u64,
+}
+
+impl<K: Ord, V> MapState<K, V> {
+    fn in_registered_range(&self, key: &K) -> bool {
+        self.ranges
+            .iter()
+            .any(|(_, start, end)| (start.as_ref(), end.as_ref()).contains(key))
+    }
+}
+
+/// Whether no key can lie between `bounds`.
+fn is_empty<K: Ord>(bounds: &(Bound<K>, Bound<K>)) -> bool {
+    match bounds {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/// A map of per-entry locks, locked with `P` as a whole.
+pub struct OrderedLockMap<K, V, P: MutexPermission, I: 'static> {
+    state: Mutex<MapState<K, V>>,
+    /// Notified whenever an entry is unlocked or a range released.
+    released: Condvar,
+    _permission: PhantomData<PermissionSyncSendWrapper<P>>,
+    _identifier: PhantomData<I>,
+}
+
+// Safety: values are only reached through `RangeGuards`, and an entry's
+// `locked` flag, changed under `state`, makes its guard the only one.
+unsafe impl<K: Send + Sync, V: Send, P: MutexPermission, I: 'static> Sync
+    for OrderedLockMap<K, V, P, I>
+{
+}
+
+impl<K: Ord + Clone, V, P: MutexPermission, I: 'static> OrderedLockMap<K, V, P, I> {
+    /// Create an empty map.
+    pub fn new(_identifier: I) -> Self {
+        Self {
+            state: Mutex::new(MapState {
+                entries: BTreeMap::new(),
+                ranges: Vec::new(),
+                next_range: 0,
+            }),
+            released: Condvar::new(),
+            _permission: PhantomData,
+            _identifier: PhantomData,
+        }
+    }
+
+    /// The internal mutex only ever guards bookkeeping, never user code.
+    fn state(&self) -> MutexGuard<'_, MapState<K, V>> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    /// Waits until `key` is outside every registered range.
+    fn wait_outside_ranges<'s>(
+        &'s self,
+        mut state: MutexGuard<'s, MapState<K, V>>,
+        key: &K,
+    ) -> MutexGuard<'s, MapState<K, V>> {
+        while state.in_registered_range(key) {
+            state = self
+                .released
+                .wait(state)
+                .unwrap_or_else(|error| error.into_inner());
+        }
+        state
+    }
+
+    pub fn len(&self) -> usize {
+        self.state().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state().entries.is_empty()
+    }
+
+    /// Inserts or replaces the entry for `key`, waiting for any range lock
+    /// covering `key` to be released first.
+    pub fn insert(&self, permission: P, key: K, value: V) -> (P, Option<V>) {
+        let state = self.state();
+        let mut state = self.wait_outside_ranges(state, &key);
+        let slot = Box::new(Slot {
+            value: UnsafeCell::new(value),
+            locked: false,
+        });
+        let previous = state.entries.insert(key, slot);
+        (permission, previous.map(|slot| slot.value.into_inner()))
+    }
+
+    /// Removes the entry for `key`, waiting for any range lock covering
+    /// `key` to be released first.
+    pub fn remove(&self, permission: P, key: &K) -> (P, Option<V>) {
+        let state = self.state();
+        let mut state = self.wait_outside_ranges(state, key);
+        let removed = state.entries.remove(key);
+        (permission, removed.map(|slot| slot.value.into_inner()))
+    }
+
+    /// Locks every entry with a key in `range`, in ascending key order.
+    ///
+    /// A range that can hold no key, such as `5..3`, locks nothing.
+    pub fn lock_range(
+        &self,
+        permission: P,
+        range: impl RangeBounds<K>,
+    ) -> RangeGuards<'_, K, V, P, I> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        let mut state = self.state();
+        let id = state.next_range;
+        state.next_range += 1;
+        if is_empty(&bounds) {
+            // `BTreeMap::range` panics on these, and there is nothing to
+            // register.
+            return RangeGuards {
+                map: self,
+                range: id,
+                entries: Vec::new(),
+                permission: Some(permission),
+            };
+        }
+        state.ranges.push((id, bounds.0.clone(), bounds.1.clone()));
+
+        // Registering the range fixed the set of keys in it.
+        let keys: Vec<K> = state
+            .entries
+            .range(bounds)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut entries = Vec::with_capacity(keys.len());
+        for key in keys {
+            loop {
+                let slot = state
+                    .entries
+                    .get_mut(&key)
+                    .expect("entry in a registered range");
+                if !slot.locked {
+                    slot.locked = true;
+                    entries.push((key, NonNull::new(slot.value.get()).unwrap()));
+                    break;
+                }
+                state = self
+                    .released
+                    .wait(state)
+                    .unwrap_or_else(|error| error.into_inner());
+            }
+        }
+
+        RangeGuards {
+            map: self,
+            range: id,
+            entries,
+            permission: Some(permission),
+        }
+    }
+
+    /// Locks the entries one at a time, in ascending key order, and runs `f`
+    /// on each, unlocking it before the next. Entries inserted after the
+    /// sweep started are not visited, and removed ones are skipped.
+    pub fn sweep(&self, mut permission: P, mut f: impl FnMut(&K, &mut V)) -> P {
+        for key in self.keys() {
+            let mut entry = self.lock_range(permission, key.clone()..=key);
+            for (key, value) in entry.iter_mut() {
+                f(key, value);
+            }
+            permission = entry.unlock_all();
+        }
+        permission
+    }
+
+    /// [`sweep`](Self::sweep), but skips the entries locked by a range
+    /// right now instead of waiting for them. Returns the permission and the
+    /// keys skipped, in ascending order.
+    pub fn try_sweep(&self, mut permission: P, mut f: impl FnMut(&K, &mut V)) -> (P, Vec<K>) {
+        let mut skipped = Vec::new();
+        for key in self.keys() {
+            permission = match self.try_lock_entry(permission, &key) {
+                Ok(mut entry) => {
+                    for (key, value) in entry.iter_mut() {
+                        f(key, value);
+                    }
+                    entry.unlock_all()
+                }
+                Err(permission) => {
+                    skipped.push(key);
+                    permission
+                }
+            };
+        }
+        (permission, skipped)
+    }
+
+    /// Locks the entry for `key` as a range of its own if it is free right
+    /// now. The guards are empty if there is no such entry.
+    fn try_lock_entry(&self, permission: P, key: &K) -> Result<RangeGuards<'_, K, V, P, I>, P> {
+        let mut state = self.state();
+        let value = match state.entries.get_mut(key) {
+            Some(slot) if slot.locked => return Err(permission),
+            Some(slot) => {
+                slot.locked = true;
+                Some(NonNull::new(slot.value.get()).unwrap())
+            }
+            None => None,
+        };
+        let id = state.next_range;
+        state.next_range += 1;
+        state
+            .ranges
+            .push((id, Bound::Included(key.clone()), Bound::Included(key.clone())));
+        Ok(RangeGuards {
+            map: self,
+            range: id,
+            entries: value.map(|value| (key.clone(), value)).into_iter().collect(),
+            permission: Some(permission),
+        })
+    }
+
+    /// The keys right now, in ascending order.
+    fn keys(&self) -> Vec<K> {
+        self.state().entries.keys().cloned().collect()
+    }
+}
+
+/// Every entry of one range of an [`OrderedLockMap`], locked. Dropping it
+/// instead of calling [`unlock_all`](Self::unlock_all) hands the permission
+/// to [`MutexPermission::recover`].
+pub struct RangeGuards<'a, K: Ord + Clone, V, P: MutexPermission, I: 'static> {
+    map: &'a OrderedLockMap<K, V, P, I>,
+    range: u64,
+    /// In key order.
+    entries: Vec<(K, NonNull<V>)>,
+    /// Only `None` once `unlock_all` has taken it out.
+    permission: Option<P>,
+}
+
+impl<K: Ord + Clone, V, P: MutexPermission, I: 'static> RangeGuards<'_, K, V, P, I> {
+    /// How many entries are locked.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The locked entries in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        // Safety: every entry is locked by this guard.
+        self.entries
+            .iter()
+            .map(|(key, value)| (key, unsafe { value.as_ref() }))
+    }
+
+    /// The locked entries in key order, mutably.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        // Safety: every entry is locked by this guard, and `&mut self` makes
+        // this the only reference through it.
+        self.entries
+            .iter_mut()
+            .map(|(key, value)| (&*key, unsafe { value.as_mut() }))
+    }
+
+    /// Unlock every entry and return the permission token.
+    pub fn unlock_all(mut self) -> P {
+        self.permission.take().unwrap()
+    }
+}
+
+impl<K: Ord + Clone, V, P: MutexPermission, I: 'static> Drop for RangeGuards<'_, K, V, P, I> {
+    fn drop(&mut self) {
+        let mut state = self.map.state();
+        for (key, _) in &self.entries {
+            if let Some(slot) = state.entries.get_mut(key) {
+                slot.locked = false;
+            }
+        }
+        state.ranges.retain(|(id, _, _)| *id != self.range);
+        drop(state);
+        self.map.released.notify_all();
+        if let Some(permission) = self.permission.take() {
+            permission.recover();
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Checks that permissions are only used on the thread that claimed them,
+//! enabled by the `origin-check` feature.
+//!
+//! Permissions are `!Send`, but `unsafe` plumbing can still carry one to
+//! another thread, which could then lock as if it were the thread that
+//! claimed it. With this feature every root permission records the thread
+//! that claimed it, and the permissions derived from it copy or forward
+//! that thread's id. Every lock operation then panics, naming both threads,
+//! when handed a permission claimed on another thread, or reports it to the
+//! [misuse handler](crate::misuse) and goes ahead if one is installed. Without the feature
+//! permissions stay zero-sized and nothing is checked.
+//!
+//! [Async permissions](crate::AsyncPermission) belong to a task rather than
+//! a thread and are never checked.
+
+use std::{
+    cell::Cell,
+    fmt,
+    sync::{Mutex, MutexGuard, PoisonError},
+    thread::{self, ThreadId},
+};
+
+use crate::MutexPermission;
+
+/// The names of the threads that claimed a root permission. Kept after they
+/// exit, since their permissions can outlive them; one entry per thread.
+static NAMES: Mutex<Vec<(ThreadId, Option<String>)>> = Mutex::new(Vec::new());
+
+fn names() -> MutexGuard<'static, Vec<(ThreadId, Option<String>)>> {
+    NAMES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+thread_local! {
+    /// Whether this thread is in [`NAMES`].
+    static REGISTERED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The origin of a root permission the current thread is claiming.
+pub(crate) fn claim() -> ThreadId {
+    let current = thread::current();
+    let id = current.id();
+    let _ = REGISTERED.try_with(|registered| {
+        if !registered.replace(true) {
+            names().push((id, current.name().map(str::to_owned)));
+        }
+    });
+    id
+}
+
+/// A thread as the panic message names it, formatted without allocating.
+struct Described<'a>(ThreadId, Option<&'a str>);
+
+impl fmt::Display for Described<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            Some(name) => write!(f, "thread `{name}` ({:?})", self.0),
+            None => write!(f, "unnamed thread {:?}", self.0),
+        }
+    }
+}
+
+/// Panics if `permission` was claimed on another thread than the current
+/// one, unless a misuse handler takes the report.
+#[track_caller]
+pub(crate) fn check<P: MutexPermission>(permission: &P) {
+    let Some(origin) = permission.origin() else {
+        return;
+    };
+    let current = thread::current();
+    if origin == current.id() {
+        return;
+    }
+    let reported = crate::misuse::report(|| crate::misuse::MisuseEvent::WrongThread {
+        claimed_on: origin,
+        used_on: current.id(),
+    });
+    if reported {
+        return;
+    }
+    let used_on = Described(current.id(), current.name());
+    let names = names();
+    match names.iter().find(|(id, _)| *id == origin) {
+        Some((id, name)) => panic!(
+            "permission claimed on {} used to lock on {used_on}",
+            Described(*id, name.as_deref())
+        ),
+        None => panic!("permission claimed on thread {origin:?} used to lock on {used_on}"),
+    }
+}
@@ -0,0 +1,213 @@
+//! Mutexes created already locked, for state that must be initialized
+//! under its own lock before anyone else can see it.
+//!
+//! Creating a mutex and then locking it leaves a window in which the mutex
+//! could already have been published, say into a shared map, with its data
+//! not set up yet. [`DeadlockProofMutex::new_locked`] closes it: the mutex
+//! is locked with the creator's permission before the [`Arc`] holding it is
+//! handed out, together with a guard that co-owns it. The creator can then
+//! publish the `Arc`, finish initializing through the guard, and unlock.
+//! Everyone who finds the mutex in the meantime blocks until then:
+//!
+//! ```
+//! use std::{collections::HashMap, sync::Mutex};
+//!
+//! use deadlock_proof::{DeadlockProofMutex, OuterMutexPermission};
+//!
+//! struct ConnectionLock;
+//! let connections = Mutex::new(HashMap::new());
+//!
+//! let (state, mut guard) =
+//!     DeadlockProofMutex::new_locked(Vec::new(), ConnectionLock, OuterMutexPermission::get());
+//! connections.lock().unwrap().insert(4000u16, state);
+//! // Whoever takes the entry out now waits for this.
+//! guard.push("SYN");
+//! let _permission = guard.unlock();
+//! ```
+//!
+//! [`new_locked_for_nested`](DeadlockProofMutex::new_locked_for_nested) does
+//! the same for a mutex whose sub-hierarchy should be set up too before it
+//! is unlocked.
+
+use std::{
+    mem,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+use crate::{
+    DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard, LockOutcome,
+    MutexPermission, NestedMutexPermission, SequentialMutexPermission,
+};
+
+impl<T: 'static, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// A new mutex, already locked with `permission`, and the guard holding
+    /// it. See the [module docs](crate::owned).
+    #[track_caller]
+    pub fn new_locked(
+        content: T,
+        identifier: I,
+        permission: P,
+    ) -> (Arc<Self>, DeadlockProofOwnedMutexGuard<T, P, I>) {
+        let mutex = Arc::new(Self::new(content, identifier));
+        // Nobody else has the mutex yet, so this neither waits nor finds it
+        // poisoned.
+        let guard = mutex.lock(permission).guard();
+        // Safety: the guard was just taken from this mutex.
+        let owned = unsafe { DeadlockProofOwnedMutexGuard::co_owning(guard, &mutex) };
+        (mutex, owned)
+    }
+
+    /// [`new_locked`](Self::new_locked), locked as by
+    /// [`lock_for_nested`](Self::lock_for_nested): also hands out the token
+    /// for the mutexes inside it.
+    #[track_caller]
+    pub fn new_locked_for_nested(
+        content: T,
+        identifier: I,
+        permission: P,
+    ) -> (
+        Arc<Self>,
+        DeadlockProofOwnedNestedMutexGuard<T, P, I>,
+        NestedMutexPermission<P, I>,
+    ) {
+        let mutex = Arc::new(Self::new(content, identifier));
+        let (guard, nested) = mutex.lock_for_nested(permission).guard();
+        // Safety: as in `new_locked`.
+        let guard = unsafe {
+            mem::transmute::<
+                DeadlockProofNestedMutexGuard<'_, T, P, I>,
+                DeadlockProofNestedMutexGuard<'static, T, P, I>,
+            >(guard)
+        };
+        let owned = DeadlockProofOwnedNestedMutexGuard {
+            guard,
+            mutex: Arc::clone(&mutex),
+        };
+        (mutex, owned, nested)
+    }
+}
+
+/// A [`DeadlockProofMutexGuard`] that co-owns its mutex. Created by
+/// [`DeadlockProofMutex::new_locked`] and
+/// [`WeakDeadlockProofMutex::lock_if_alive`](crate::WeakDeadlockProofMutex::lock_if_alive).
+pub struct DeadlockProofOwnedMutexGuard<T: 'static, P: MutexPermission, I: 'static> {
+    // Declared first so it drops before the `Arc` it borrows from.
+    guard: DeadlockProofMutexGuard<'static, T, P, I>,
+    mutex: Arc<DeadlockProofMutex<T, P, I>>,
+}
+
+impl<T: 'static, P: MutexPermission, I: 'static> DeadlockProofOwnedMutexGuard<T, P, I> {
+    /// Makes `guard` co-own the mutex it holds.
+    ///
+    /// # Safety
+    ///
+    /// `guard` must hold the mutex behind `mutex`.
+    pub(crate) unsafe fn co_owning(
+        guard: DeadlockProofMutexGuard<'_, T, P, I>,
+        mutex: &Arc<DeadlockProofMutex<T, P, I>>,
+    ) -> Self {
+        // Safety: the guard borrows from the mutex behind the `Arc`, as the
+        // caller promises, which the owned guard keeps alive and only drops
+        // after it.
+        let guard = unsafe {
+            mem::transmute::<
+                DeadlockProofMutexGuard<'_, T, P, I>,
+                DeadlockProofMutexGuard<'static, T, P, I>,
+            >(guard)
+        };
+        Self {
+            guard,
+            mutex: Arc::clone(mutex),
+        }
+    }
+
+    /// Unlock the mutex and return the permission token.
+    pub fn unlock(self) -> P {
+        self.guard.unlock()
+    }
+
+    /// Unlock the mutex and return a sequential permission token.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        self.guard.unlock_for_sequential()
+    }
+
+    /// Mutable access to the data, as [`DeadlockProofMutexGuard::get_mut`].
+    pub fn get_mut(&mut self) -> &mut T {
+        self.guard.get_mut()
+    }
+
+    /// The mutex this guard holds.
+    pub fn mutex(&self) -> &Arc<DeadlockProofMutex<T, P, I>> {
+        &self.mutex
+    }
+}
+
+impl<T: 'static, P: MutexPermission, I: 'static> Deref for DeadlockProofOwnedMutexGuard<T, P, I> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: 'static, P: MutexPermission, I: 'static> DerefMut
+    for DeadlockProofOwnedMutexGuard<T, P, I>
+{
+    fn deref_mut(&mut self) -> &mut T {
+        self.get_mut()
+    }
+}
+
+/// A [`DeadlockProofNestedMutexGuard`] that co-owns its mutex. Created by
+/// [`DeadlockProofMutex::new_locked_for_nested`].
+pub struct DeadlockProofOwnedNestedMutexGuard<T: 'static, P: MutexPermission, I: 'static> {
+    // Declared first so it drops before the `Arc` it borrows from.
+    guard: DeadlockProofNestedMutexGuard<'static, T, P, I>,
+    mutex: Arc<DeadlockProofMutex<T, P, I>>,
+}
+
+impl<T: 'static, P: MutexPermission, I: 'static> DeadlockProofOwnedNestedMutexGuard<T, P, I> {
+    /// Unlock the mutex with the nested permission token, as
+    /// [`DeadlockProofNestedMutexGuard::unlock`].
+    pub fn unlock(self, token: NestedMutexPermission<P, I>) -> P {
+        self.guard.unlock(token)
+    }
+
+    /// Unlock the mutex with the nested permission token and return a
+    /// sequential permission token.
+    pub fn unlock_for_sequential(
+        self,
+        token: NestedMutexPermission<P, I>,
+    ) -> SequentialMutexPermission<P, I> {
+        self.guard.unlock_for_sequential(token)
+    }
+
+    /// Mutable access to the data, as [`DeadlockProofMutexGuard::get_mut`].
+    pub fn get_mut(&mut self) -> &mut T {
+        self.guard.get_mut()
+    }
+
+    /// The mutex this guard holds.
+    pub fn mutex(&self) -> &Arc<DeadlockProofMutex<T, P, I>> {
+        &self.mutex
+    }
+}
+
+impl<T: 'static, P: MutexPermission, I: 'static> Deref
+    for DeadlockProofOwnedNestedMutexGuard<T, P, I>
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: 'static, P: MutexPermission, I: 'static> DerefMut
+    for DeadlockProofOwnedNestedMutexGuard<T, P, I>
+{
+    fn deref_mut(&mut self) -> &mut T {
+        self.get_mut()
+    }
+}
@@ -0,0 +1,464 @@
+//! A cookbook of common locking patterns, each one a compile-checked example.
+//!
+//! Nothing here is meant to be called: the submodules only exist to hold long
+//! examples built against the public API, and `compile_fail` examples for the
+//! anti-patterns the types rule out. `cargo test` builds and runs all of them,
+//! so they stay in step with the API.
+//!
+//! - [`nested_downcall`]: a lock held while calling into code that takes a
+//!   lock inside it.
+//! - [`callbacks_under_locks`]: running caller-supplied code with a lock held.
+//! - [`sequential_pipeline`]: visiting layers in order, one at a time.
+//! - [`early_abort`]: bailing out of a walk half way and releasing in order.
+//! - [`poison_recovery`]: living with a lock whose holder panicked.
+//! - [`thread_pool`]: worker threads, their tokens, and a shared job queue.
+
+pub mod nested_downcall {
+    //! Holding an outer lock while calling down into code that locks
+    //! something inside it.
+    //!
+    //! [`lock_for_nested`](crate::DeadlockProofMutex::lock_for_nested) hands
+    //! out a [`NestedMutexPermission`](crate::NestedMutexPermission) next to
+    //! the guard. The downcall takes that token rather than a root permission,
+    //! so its signature says which level it runs at, and it returns the token
+    //! so the caller can unlock:
+    //!
+    //! ```
+    //! use deadlock_proof::*;
+    //!
+    //! struct SocketTableLock;
+    //! struct SocketLock;
+    //!
+    //! type TableToken = NestedMutexPermission<OuterMutexPermission, SocketTableLock>;
+    //! type SocketTable = DeadlockProofMutex<Vec<u16>, OuterMutexPermission, SocketTableLock>;
+    //! type Socket = DeadlockProofMutex<u64, TableToken, SocketLock>;
+    //!
+    //! /// Runs with the table locked; all it can lock are sockets.
+    //! fn deliver(socket: &Socket, token: TableToken, bytes: u64) -> TableToken {
+    //!     let mut received = socket.lock(token).guard();
+    //!     *received += bytes;
+    //!     received.unlock()
+    //! }
+    //!
+    //! let table = SocketTable::new(vec![80, 443], SocketTableLock);
+    //! let socket = Socket::new(0, SocketLock);
+    //!
+    //! let (ports, token) = table.lock_for_nested(OuterMutexPermission::get()).guard();
+    //! let token = if ports.contains(&443) {
+    //!     deliver(&socket, token, 1500)
+    //! } else {
+    //!     token
+    //! };
+    //! let permission = ports.unlock(token);
+    //!
+    //! let (ports, token) = table.lock_for_nested(permission).guard();
+    //! let received = socket.lock(token).guard();
+    //! assert_eq!(*received, 1500);
+    //! ports.unlock(received.unlock());
+    //! ```
+    //!
+    //! The downcall cannot climb back up and relock the table it was called
+    //! under, since the table wants a root permission and all it has is the
+    //! table's own token:
+    //!
+    //! ```compile_fail
+    //! use deadlock_proof::*;
+    //!
+    //! struct SocketTableLock;
+    //! type TableToken = NestedMutexPermission<OuterMutexPermission, SocketTableLock>;
+    //! type SocketTable = DeadlockProofMutex<Vec<u16>, OuterMutexPermission, SocketTableLock>;
+    //!
+    //! fn deliver(table: &SocketTable, token: TableToken) -> TableToken {
+    //!     let ports = table.lock(token).guard();
+    //!     # unimplemented!()
+    //! }
+    //! ```
+    //!
+    //! Nor can the caller lock a second mutex at the table's level while the
+    //! table is held: the root permission is inside the table's guard.
+    //!
+    //! ```compile_fail
+    //! use deadlock_proof::*;
+    //!
+    //! let table = DeadlockProofMutex::new(vec![80u16], unique_type!());
+    //! let other = DeadlockProofMutex::new(0u64, unique_type!());
+    //! let permission = OuterMutexPermission::get();
+    //! let (ports, token) = table.lock_for_nested(permission).guard();
+    //! let counter = other.lock(permission).guard();
+    //! ```
+}
+
+pub mod callbacks_under_locks {
+    //! Running caller-supplied code while a lock is held.
+    //!
+    //! A callback invoked under a lock is a downcall whose body the lock's
+    //! owner does not control. Giving it the nested token and nothing else
+    //! limits it to locks below the one it runs under, whatever it does:
+    //!
+    //! ```
+    //! use deadlock_proof::*;
+    //!
+    //! struct RegistryLock;
+    //! struct AuditLock;
+    //!
+    //! type RegistryToken = NestedMutexPermission<OuterMutexPermission, RegistryLock>;
+    //!
+    //! struct Registry {
+    //!     names: DeadlockProofMutex<Vec<&'static str>, OuterMutexPermission, RegistryLock>,
+    //!     audit: DeadlockProofMutex<Vec<String>, RegistryToken, AuditLock>,
+    //! }
+    //!
+    //! impl Registry {
+    //!     /// Calls `visit` for every name with the registry locked.
+    //!     fn for_each(
+    //!         &self,
+    //!         permission: OuterMutexPermission,
+    //!         mut visit: impl FnMut(&str, &Self, RegistryToken) -> RegistryToken,
+    //!     ) -> OuterMutexPermission {
+    //!         let (names, mut token) = self.names.lock_for_nested(permission).guard();
+    //!         for name in names.iter() {
+    //!             token = visit(name, self, token);
+    //!         }
+    //!         names.unlock(token)
+    //!     }
+    //! }
+    //!
+    //! let registry = Registry {
+    //!     names: DeadlockProofMutex::new(vec!["eth0", "lo"], RegistryLock),
+    //!     audit: DeadlockProofMutex::new(Vec::new(), AuditLock),
+    //! };
+    //!
+    //! let permission = registry.for_each(OuterMutexPermission::get(), |name, registry, token| {
+    //!     let mut audit = registry.audit.lock(token).guard();
+    //!     audit.push(format!("visited {name}"));
+    //!     audit.unlock()
+    //! });
+    //!
+    //! let (names, token) = registry.names.lock_for_nested(permission).guard();
+    //! assert_eq!(registry.audit.lock(token).guard().len(), names.len());
+    //! ```
+    //!
+    //! A callback that tries to re-enter the registry does not compile,
+    //! rather than deadlocking on the first call:
+    //!
+    //! ```compile_fail
+    //! use deadlock_proof::*;
+    //!
+    //! struct RegistryLock;
+    //! type RegistryToken = NestedMutexPermission<OuterMutexPermission, RegistryLock>;
+    //! type Names = DeadlockProofMutex<Vec<&'static str>, OuterMutexPermission, RegistryLock>;
+    //!
+    //! fn for_each(names: &Names, visit: impl Fn(RegistryToken) -> RegistryToken) {
+    //!     let (guard, token) = names.lock_for_nested(OuterMutexPermission::get()).guard();
+    //!     guard.unlock(visit(token));
+    //! }
+    //!
+    //! let names = Names::new(vec!["eth0"], RegistryLock);
+    //! for_each(&names, |token| {
+    //!     let again = names.lock(token).guard();
+    //!     # unimplemented!()
+    //! });
+    //! ```
+}
+
+pub mod sequential_pipeline {
+    //! Visiting a fixed sequence of locks, one at a time.
+    //!
+    //! Each stage unlocks its layer with `unlock_for_sequential`, and the token
+    //! it gets back is exactly the permission the next layer asks for. Writing
+    //! the stages as functions from one position to the next makes the order
+    //! part of their signatures:
+    //!
+    //! ```
+    //! use deadlock_proof::*;
+    //!
+    //! fn route(stack: &NetworkStack, permission: OuterMutexPermission) -> After<IpLock> {
+    //!     let mut ip = stack.ip_layer.lock(permission).guard();
+    //!     ip.packets_processed += 1;
+    //!     ip.unlock_for_sequential()
+    //! }
+    //!
+    //! fn transmit(stack: &NetworkStack, permission: After<IpLock>) -> After<DeviceLock> {
+    //!     let mut device = stack.device_layer.lock(permission).guard();
+    //!     device.bytes_transmitted += 1500;
+    //!     device.unlock_for_sequential()
+    //! }
+    //!
+    //! fn deliver(stack: &NetworkStack, permission: After<DeviceLock>) -> OuterMutexPermission {
+    //!     let mut transport = stack.transport_layer.lock(permission).guard();
+    //!     transport.udp_sockets += 1;
+    //!     // Back up through every position to the root.
+    //!     transport.unlock().to_earlier().to_earlier()
+    //! }
+    //!
+    //! let stack = NetworkStack::new();
+    //! let mut permission = OuterMutexPermission::get();
+    //! for _ in 0..3 {
+    //!     permission = deliver(&stack, transmit(&stack, route(&stack, permission)));
+    //! }
+    //! assert_eq!(stack.ip_layer.lock(permission).guard().packets_processed, 3);
+    //! ```
+    //!
+    //! Stages cannot be run out of order. Past the device layer, the IP layer
+    //! is out of reach until the walk has backed up to the root:
+    //!
+    //! ```compile_fail
+    //! use deadlock_proof::*;
+    //!
+    //! let stack = NetworkStack::new();
+    //! let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    //! let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    //! let ip = stack.ip_layer.lock(device.unlock_for_sequential());
+    //! ```
+    //!
+    //! Skipping a stage does not compile either:
+    //!
+    //! ```compile_fail
+    //! use deadlock_proof::*;
+    //!
+    //! let stack = NetworkStack::new();
+    //! let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    //! let transport = stack.transport_layer.lock(ip.unlock_for_sequential());
+    //! ```
+}
+
+pub mod early_abort {
+    //! Giving up half way through a walk.
+    //!
+    //! An error path still has to hand the root permission back, so it
+    //! releases what it holds and backs up with `to_earlier`. Pairing the
+    //! permission with the outcome keeps that visible in the signature:
+    //!
+    //! ```
+    //! use deadlock_proof::*;
+    //!
+    //! #[derive(Debug, PartialEq)]
+    //! enum SendError {
+    //!     NoInterface,
+    //!     TooLarge,
+    //! }
+    //!
+    //! fn send(
+    //!     stack: &NetworkStack,
+    //!     permission: OuterMutexPermission,
+    //!     len: u64,
+    //! ) -> (OuterMutexPermission, Result<(), SendError>) {
+    //!     let mut ip = stack.ip_layer.lock(permission).guard();
+    //!     if len > 9000 {
+    //!         return (ip.unlock(), Err(SendError::TooLarge));
+    //!     }
+    //!     ip.packets_processed += 1;
+    //!
+    //!     let mut device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    //!     if device.interfaces_active == 0 {
+    //!         // Release the device layer, then back up past the IP layer.
+    //!         let permission = device.unlock().to_earlier();
+    //!         return (permission, Err(SendError::NoInterface));
+    //!     }
+    //!     device.bytes_transmitted += len;
+    //!     (device.unlock().to_earlier(), Ok(()))
+    //! }
+    //!
+    //! let stack = NetworkStack::new();
+    //! let (permission, sent) = send(&stack, OuterMutexPermission::get(), 10_000);
+    //! assert_eq!(sent, Err(SendError::TooLarge));
+    //! let (permission, sent) = send(&stack, permission, 1500);
+    //! assert_eq!(sent, Err(SendError::NoInterface));
+    //!
+    //! // Both aborts released everything: the whole walk is available again.
+    //! let ip = stack.ip_layer.lock(permission).guard();
+    //! assert_eq!(ip.packets_processed, 1);
+    //! let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    //! assert_eq!(device.bytes_transmitted, 0);
+    //! ```
+    //!
+    //! A panic needs no such care: unwinding drops whatever guard is live,
+    //! and since a walk only ever holds one, there is no order to get wrong.
+    //!
+    //! Returning early from the middle of the walk without backing up does not
+    //! type-check, because a position past the IP layer is not a root
+    //! permission:
+    //!
+    //! ```compile_fail
+    //! use deadlock_proof::*;
+    //!
+    //! fn send(stack: &NetworkStack, permission: OuterMutexPermission) -> OuterMutexPermission {
+    //!     let ip = stack.ip_layer.lock(permission).guard();
+    //!     let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    //!     device.unlock()
+    //! }
+    //! ```
+}
+
+#[cfg(not(feature = "no-poison"))]
+pub mod poison_recovery {
+    //! Living with a lock whose holder panicked.
+    //!
+    //! A panic that unwinds out of a critical section poisons the mutex, and
+    //! every later `lock` reports it with a
+    //! [`DeadlockProofPoisonError`](crate::DeadlockProofPoisonError), which
+    //! gives the permission back through
+    //! [`into_permission`](crate::DeadlockProofPoisonError::into_permission).
+    //! A thread that would rather not lock the layer at all can find out with
+    //! [`StackTransaction::commit`](crate::StackTransaction::commit), which
+    //! reports the poisoned layer in [`TxAborted`](crate::TxAborted):
+    //!
+    //! ```
+    //! use deadlock_proof::*;
+    //! use std::thread;
+    //!
+    //! let stack = NetworkStack::new();
+    //! thread::scope(|scope| {
+    //!     let crashed = scope.spawn(|| {
+    //!         let mut device = stack
+    //!             .device_layer
+    //!             .lock(stack.ip_layer.lock(OuterMutexPermission::get()).guard().unlock_for_sequential())
+    //!             .guard();
+    //!         device.bytes_transmitted = u64::MAX;
+    //!         panic!("driver crashed mid-update");
+    //!     });
+    //!     assert!(crashed.join().is_err());
+    //! });
+    //!
+    //! let (permission, committed) = stack
+    //!     .transaction()
+    //!     .stage_device(|device| {
+    //!         device.interfaces_active += 1;
+    //!         Ok(())
+    //!     })
+    //!     .commit(OuterMutexPermission::get());
+    //! assert_eq!(committed, Err(TxAborted::Poisoned(StackLayer::Device)));
+    //!
+    //! // The permission survived, so this thread can still use the layers the
+    //! // panic left alone.
+    //! let ip = stack.ip_layer.lock(permission).guard();
+    //! assert_eq!(ip.packets_processed, 0);
+    //! ```
+    //!
+    //! The data itself is still reachable through the error, for a holder
+    //! that can repair it. The error holds an ordinary guard, so the repair
+    //! runs under the same permission, and unlocking hands it back:
+    //!
+    //! ```
+    //! use deadlock_proof::*;
+    //! use std::thread;
+    //!
+    //! let counters = DeadlockProofMutex::new(vec![1u32, 2, 3], unique_type!());
+    //! thread::scope(|scope| {
+    //!     let crashed = scope.spawn(|| {
+    //!         let mut guard = counters.lock(OuterMutexPermission::get()).guard();
+    //!         guard.push(u32::MAX);
+    //!         panic!("half-written entry");
+    //!     });
+    //!     assert!(crashed.join().is_err());
+    //! });
+    //!
+    //! let (repaired, permission) = match counters.lock(OuterMutexPermission::get()).into_result() {
+    //!     Ok(guard) => (guard.len(), guard.unlock()),
+    //!     Err(poisoned) => {
+    //!         let mut entries = poisoned.into_guard();
+    //!         entries.retain(|&entry| entry != u32::MAX);
+    //!         (entries.len(), entries.unlock())
+    //!     }
+    //! };
+    //! assert_eq!(repaired, 3);
+    //! # drop(permission);
+    //! ```
+    //!
+    //! Builds with `panic = "abort"` never see poison; the `no-poison` feature
+    //! drops it from the API altogether. See [`poison`](crate::poison).
+    //!
+    //! The permission comes back only through the error: the one passed to
+    //! the failed lock cannot be reused.
+    //!
+    //! ```compile_fail
+    //! use deadlock_proof::*;
+    //!
+    //! let counters = DeadlockProofMutex::new(0u32, unique_type!());
+    //! let permission = OuterMutexPermission::get();
+    //! if counters.lock(permission).is_err() {
+    //!     counters.lock(permission);
+    //! }
+    //! ```
+}
+
+pub mod thread_pool {
+    //! Worker threads, their root permissions, and a job queue between them.
+    //!
+    //! A root permission never leaves its thread, so a pool does not hand
+    //! permissions to its workers: each worker claims its own once, when it
+    //! starts, and threads it through every job it runs. Jobs take the
+    //! permission and give it back, which proves they released what they
+    //! locked before the worker goes back to the queue. The queue itself is a
+    //! deadlock-proof mutex like any other, shared by producer and consumers:
+    //!
+    //! ```
+    //! use deadlock_proof::*;
+    //! use std::{
+    //!     collections::VecDeque,
+    //!     sync::atomic::{AtomicBool, Ordering},
+    //!     thread,
+    //! };
+    //!
+    //! type Job = Box<dyn FnOnce(&NetworkStack, OuterMutexPermission) -> OuterMutexPermission + Send>;
+    //!
+    //! struct QueueLock;
+    //! type Queue = DeadlockProofMutex<VecDeque<Job>, OuterMutexPermission, QueueLock>;
+    //!
+    //! fn worker(stack: &NetworkStack, queue: &Queue, closed: &AtomicBool) {
+    //!     // The worker's one claim.
+    //!     let mut permission = OuterMutexPermission::get();
+    //!     loop {
+    //!         let mut jobs = queue.lock(permission).guard();
+    //!         let job = jobs.pop_front();
+    //!         permission = jobs.unlock();
+    //!         match job {
+    //!             Some(job) => permission = job(stack, permission),
+    //!             None if closed.load(Ordering::Acquire) => break,
+    //!             None => thread::yield_now(),
+    //!         }
+    //!     }
+    //! }
+    //!
+    //! let stack = NetworkStack::new();
+    //! let queue = Queue::new(VecDeque::new(), QueueLock);
+    //! let closed = AtomicBool::new(false);
+    //!
+    //! let permission = thread::scope(|scope| {
+    //!     for _ in 0..4 {
+    //!         scope.spawn(|| worker(&stack, &queue, &closed));
+    //!     }
+    //!
+    //!     // The producer is a thread like any other, with its own claim.
+    //!     let mut permission = OuterMutexPermission::get();
+    //!     for _ in 0..100 {
+    //!         let mut jobs = queue.lock(permission).guard();
+    //!         jobs.push_back(Box::new(|stack, permission| {
+    //!             let mut ip = stack.ip_layer.lock(permission).guard();
+    //!             ip.packets_processed += 1;
+    //!             ip.unlock()
+    //!         }));
+    //!         permission = jobs.unlock();
+    //!     }
+    //!     closed.store(true, Ordering::Release);
+    //!     permission
+    //! });
+    //!
+    //! assert_eq!(stack.ip_layer.lock(permission).guard().packets_processed, 100);
+    //! ```
+    //!
+    //! For a fixed batch of work, [`concurrent::run_all`](crate::concurrent::run_all)
+    //! does the spawning and joining.
+    //!
+    //! Handing the pool a permission claimed elsewhere does not compile:
+    //!
+    //! ```compile_fail
+    //! use deadlock_proof::*;
+    //! use std::thread;
+    //!
+    //! let permission = OuterMutexPermission::get();
+    //! thread::spawn(move || {
+    //!     let _owned_here = permission;
+    //! });
+    //! ```
+}
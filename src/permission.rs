@@ -0,0 +1,652 @@
+//! Permission tokens, and the per-thread root token they all derive from.
+
+use std::{
+    cell::Cell,
+    fmt,
+    marker::PhantomData,
+    panic::Location,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread::{self, ThreadId},
+};
+
+use crate::{DeadlockProofMutex, LockLevel, PermissionChain, PermissionDepth};
+
+/// This is a trait that represents the permission to claim a mutex.
+/// Some type of permission token required to claim a mutex.
+pub trait MutexPermission: PermissionDepth + PermissionChain + 'static {
+    /// Called with a permission its holder had to abandon, e.g. when a
+    /// [`PermissionCell`](crate::PermissionCell) is dropped while full.
+    /// Permissions that can be returned to their owner do so here; by default
+    /// the permission is simply lost.
+    fn recover(self)
+    where
+        Self: Sized,
+    {
+    }
+
+    /// The [lineage](crate::lineage) tag this permission descends from, if
+    /// its root was tagged.
+    #[cfg(feature = "metrics")]
+    fn tag(&self) -> Option<u64> {
+        None
+    }
+
+    /// The thread that claimed the root permission this one derives from,
+    /// which every lock operation [checks](crate::origin) it is used on.
+    #[cfg(feature = "origin-check")]
+    fn origin(&self) -> Option<ThreadId> {
+        None
+    }
+}
+
+impl MutexPermission for OuterMutexPermission {
+    #[cfg(feature = "metrics")]
+    fn tag(&self) -> Option<u64> {
+        self.tag
+    }
+
+    #[cfg(feature = "origin-check")]
+    fn origin(&self) -> Option<ThreadId> {
+        self.origin
+    }
+}
+
+/// Permission to claim an "outer" mutex. That is, a class of mutexes where
+/// only one can be claimed at once in each thread, thus preventing deadlock.
+///
+/// Zero-sized unless the `metrics` feature gives it a lineage tag or the
+/// `origin-check` feature its claiming thread, as is every permission
+/// derived from it.
+// With both features there are two fields, which `transparent` rules out.
+#[cfg_attr(not(all(feature = "metrics", feature = "origin-check")), repr(transparent))]
+pub struct OuterMutexPermission {
+    _not_send: PhantomData<Rc<()>>,
+    #[cfg(feature = "metrics")]
+    pub(crate) tag: Option<u64>,
+    /// Stamped when the token is claimed.
+    #[cfg(feature = "origin-check")]
+    origin: Option<ThreadId>,
+}
+
+// Note: OuterMutexPermission is designed to be thread-local and not Send
+// We'll enforce this through usage patterns rather than negative trait bounds
+
+thread_local! {
+    /// This is a thread-local storage for the permission token.
+    /// It is used to store the permission token for the current thread.
+    pub static MUTEX_PERMISSION_TOKEN: Cell<Option<OuterMutexPermission>>
+        = const {
+            Cell::new(Some(OuterMutexPermission {
+                _not_send: PhantomData,
+                #[cfg(feature = "metrics")]
+                tag: None,
+                #[cfg(feature = "origin-check")]
+                origin: None,
+            }))
+        };
+}
+
+/// The number of threads holding a claimed root token, with [`PHASE_BIT`] set
+/// while a [`SingleThreadedPhase`](crate::phase::SingleThreadedPhase) is held.
+/// Claims are refused while the bit is set.
+static CLAIMS: AtomicUsize = AtomicUsize::new(0);
+
+const PHASE_BIT: usize = 1 << (usize::BITS - 1);
+
+/// Whether this thread's claim is counted in [`CLAIMS`]. Uncounted again
+/// when the thread exits, whatever became of the token itself.
+struct LiveClaim(Cell<bool>);
+
+impl Drop for LiveClaim {
+    fn drop(&mut self) {
+        if self.0.get() {
+            CLAIMS.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+thread_local! {
+    static LIVE_CLAIM: LiveClaim = const { LiveClaim(Cell::new(false)) };
+}
+
+/// Counts a claim by this thread. Fails while a phase is held.
+fn count_claim() -> bool {
+    let counted = CLAIMS
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |claims| {
+            (claims & PHASE_BIT == 0).then_some(claims + 1)
+        })
+        .is_ok();
+    if counted && LIVE_CLAIM.try_with(|live| live.0.set(true)).is_err() {
+        // The thread is tearing down and could never uncount the claim.
+        CLAIMS.fetch_sub(1, Ordering::AcqRel);
+    }
+    counted
+}
+
+/// Puts a token handed out by [`OuterMutexPermission::get`] back into this
+/// thread's slot and uncounts its claim.
+pub(crate) fn return_token(token: OuterMutexPermission) {
+    let _ = MUTEX_PERMISSION_TOKEN.try_with(|slot| slot.set(Some(token)));
+    let _ = LIVE_CLAIM.try_with(|live| {
+        if live.0.replace(false) {
+            CLAIMS.fetch_sub(1, Ordering::AcqRel);
+        }
+    });
+}
+
+/// Takes this thread's unclaimed token out of its slot without counting a
+/// claim, so [`OuterMutexPermission::get`] fails until it is put back with
+/// [`restore_token`].
+pub(crate) fn withhold_token() -> Option<OuterMutexPermission> {
+    MUTEX_PERMISSION_TOKEN.try_with(Cell::take).ok().flatten()
+}
+
+pub(crate) fn restore_token(token: OuterMutexPermission) {
+    let _ = MUTEX_PERMISSION_TOKEN.try_with(|slot| slot.set(Some(token)));
+}
+
+/// Whether this thread's token is still in its slot, that is, unclaimed.
+pub(crate) fn token_available() -> bool {
+    MUTEX_PERMISSION_TOKEN
+        .try_with(|token_ref| {
+            let token = token_ref.take();
+            let available = token.is_some();
+            token_ref.set(token);
+            available
+        })
+        .unwrap_or(false)
+}
+
+/// Marks a phase as held if the caller's claim is the only one.
+pub(crate) fn enter_phase() -> bool {
+    CLAIMS
+        .compare_exchange(1, 1 | PHASE_BIT, Ordering::AcqRel, Ordering::Acquire)
+        .is_ok()
+}
+
+pub(crate) fn exit_phase() {
+    CLAIMS.fetch_and(!PHASE_BIT, Ordering::AcqRel);
+}
+
+pub(crate) fn phase_held() -> bool {
+    CLAIMS.load(Ordering::Acquire) & PHASE_BIT != 0
+}
+
+/// How many threads currently hold a claimed root token. Racy, like every
+/// count taken without a lock.
+pub fn live_claims() -> usize {
+    CLAIMS.load(Ordering::Acquire) & !PHASE_BIT
+}
+
+/// How many guards of [`DeadlockProofMutex`](crate::DeadlockProofMutex)es
+/// and [`DeadlockProofRwLock`](crate::DeadlockProofRwLock)s the current
+/// thread holds, leaked ones included. Needs the `diagnostics` feature,
+/// which counts them.
+#[cfg(feature = "diagnostics")]
+pub fn held_lock_count() -> usize {
+    crate::lock_stats::held()
+}
+
+/// Hands out this thread's root token again after it was lost without a
+/// trace, as when C code `longjmp`s across Rust frames and skips their
+/// destructors. Returns `None` if the thread still holds a guard, since the
+/// lock it holds would then be locked again out of order. A token that was
+/// never claimed is claimed as by [`OuterMutexPermission::get_or_diagnose`],
+/// so `None` also while a single-threaded phase suspends claims or the
+/// thread is in [`quarantine`].
+///
+/// Only available with the `diagnostics` feature, whose guard count
+/// ([`held_lock_count`]) is what tells a lost token from one still in use.
+///
+/// # Safety
+///
+/// Every permission derived from the thread's token, the token itself
+/// included, must be gone for good: dropped, forgotten, or in a frame that
+/// was jumped over and will never resume. A permission still reachable
+/// would be a second root on this thread, and with two roots the thread can
+/// lock a mutex it already holds. The guard count cannot see permissions,
+/// only guards, so this is the caller's to promise. Guards of locks the
+/// crate does not count, such as [`OrderedLockMap`](crate::OrderedLockMap)
+/// ranges, must be gone too.
+#[cfg(feature = "diagnostics")]
+#[track_caller]
+pub unsafe fn force_reclaim_outer() -> Option<OuterMutexPermission> {
+    if held_lock_count() != 0 {
+        return None;
+    }
+    match OuterMutexPermission::get_or_diagnose() {
+        Ok(permission) => return Some(permission),
+        // The token is still in its slot, just not to be claimed right now.
+        Err(diagnostics) if diagnostics.blocked_by_phase || diagnostics.quarantined => {
+            return None
+        }
+        Err(_) => {}
+    }
+    // The claim that handed out the lost token is still counted; it now
+    // stands for this one.
+    record_claim(Location::caller(), true, false);
+    Some(OuterMutexPermission {
+        _not_send: PhantomData,
+        #[cfg(feature = "metrics")]
+        tag: None,
+        #[cfg(feature = "origin-check")]
+        origin: Some(crate::origin::claim()),
+    })
+}
+
+// NestedMutexPermission: A key you get after locking a mutex, which lets you lock a mutex inside it.
+
+// SequentialMutexPermission: A key you get after unlocking a mutex, which lets you lock the next one in a sequence.
+
+impl OuterMutexPermission {
+    /// Get the thread-local mutex claiming permission. This can be called exactly once
+    /// per thread, and will panic if it's called more than once in a thread.
+    /// The panic message includes the [`ClaimDiagnostics`], which an
+    /// installed [misuse handler](crate::misuse) gets first.
+    #[track_caller]
+    pub fn get() -> OuterMutexPermission {
+        match Self::get_or_diagnose() {
+            Ok(permission) => permission,
+            Err(diagnostics) => {
+                crate::misuse::report(|| {
+                    crate::misuse::MisuseEvent::DoubleClaim(diagnostics.clone())
+                });
+                panic!("{diagnostics}")
+            }
+        }
+    }
+
+    /// Like [`get`](Self::get), but `None` on a second claim, which is
+    /// then not reported as misuse.
+    #[track_caller]
+    pub fn try_get() -> Option<OuterMutexPermission> {
+        Self::get_or_diagnose().ok()
+    }
+
+    /// Like [`get`](Self::get), but reports a second claim as an error so
+    /// embedders can log it instead of crashing.
+    #[track_caller]
+    pub fn get_or_diagnose() -> Result<OuterMutexPermission, ClaimDiagnostics> {
+        claim(Location::caller(), false)
+    }
+}
+
+/// Claims this thread's token for a lock at `attempted_at` under
+/// [`Policy::AutoClaim`](crate::unclaimed::Policy::AutoClaim), which the
+/// diagnostics record as an implicit claim.
+pub(crate) fn claim_implicitly(
+    attempted_at: &'static Location<'static>,
+) -> Result<OuterMutexPermission, ClaimDiagnostics> {
+    claim(attempted_at, true)
+}
+
+fn claim(
+    attempted_at: &'static Location<'static>,
+    implicit: bool,
+) -> Result<OuterMutexPermission, ClaimDiagnostics> {
+    if quarantined() {
+        return Err(ClaimDiagnostics {
+            state: thread_debug_state(),
+            attempted_at,
+            blocked_by_phase: false,
+            quarantined: true,
+        });
+    }
+    // `try_with` rather than `with`: on threads that are tearing down
+    // their TLS this reports a failed claim instead of panicking.
+    let token = MUTEX_PERMISSION_TOKEN
+        .try_with(|token_ref| token_ref.take())
+        .ok()
+        .flatten();
+    let blocked_by_phase = match token {
+        Some(token) if !count_claim() => {
+            let _ = MUTEX_PERMISSION_TOKEN.try_with(|token_ref| token_ref.set(Some(token)));
+            true
+        }
+        #[cfg_attr(not(feature = "origin-check"), allow(unused_mut))]
+        Some(mut token) => {
+            record_claim(attempted_at, true, implicit);
+            #[cfg(feature = "origin-check")]
+            {
+                token.origin = Some(crate::origin::claim());
+            }
+            return Ok(token);
+        }
+        None => false,
+    };
+    record_claim(attempted_at, false, implicit);
+    Err(ClaimDiagnostics {
+        state: thread_debug_state(),
+        attempted_at,
+        blocked_by_phase,
+        quarantined: false,
+    })
+}
+
+#[cfg(any(debug_assertions, feature = "diagnostics"))]
+thread_local! {
+    /// Where this thread's token was handed out, how often it was asked for,
+    /// and whether it was handed out implicitly.
+    static CLAIM_TRACKING: Cell<(Option<&'static Location<'static>>, u32, bool)>
+        = const { Cell::new((None, 0, false)) };
+}
+
+#[cfg(any(debug_assertions, feature = "diagnostics"))]
+fn record_claim(location: &'static Location<'static>, succeeded: bool, implicit: bool) {
+    let _ = CLAIM_TRACKING.try_with(|tracking| {
+        let (claimed_at, claims, was_implicit) = tracking.get();
+        let (claimed_at, implicit) = if succeeded {
+            (Some(location), implicit)
+        } else {
+            (claimed_at, was_implicit)
+        };
+        tracking.set((claimed_at, claims.saturating_add(1), implicit));
+    });
+}
+
+#[cfg(not(any(debug_assertions, feature = "diagnostics")))]
+fn record_claim(_location: &'static Location<'static>, _succeeded: bool, _implicit: bool) {}
+
+/// What is known about the current thread's root permission token.
+#[derive(Clone, Debug)]
+pub struct ThreadPermissionDebug {
+    /// Where the token was handed out. Only tracked in debug builds or with
+    /// the `diagnostics` feature; `None` otherwise or if never claimed.
+    pub claimed_at: Option<&'static Location<'static>>,
+    /// How many times this thread asked for its token, failed attempts
+    /// included. Without tracking this is just 1 if the token is gone, else 0.
+    pub claims: u32,
+    /// Whether the token was last handed out implicitly, by a lock under
+    /// [`Policy::AutoClaim`](crate::unclaimed::Policy::AutoClaim). Tracked
+    /// like `claimed_at`, and `false` without tracking.
+    pub implicit: bool,
+    pub thread_id: ThreadId,
+    pub thread_name: Option<String>,
+}
+
+/// Reports the state of the current thread's root permission token.
+pub fn thread_debug_state() -> ThreadPermissionDebug {
+    #[cfg(any(debug_assertions, feature = "diagnostics"))]
+    let (claimed_at, claims, implicit) =
+        CLAIM_TRACKING.try_with(Cell::get).unwrap_or((None, 0, false));
+    #[cfg(not(any(debug_assertions, feature = "diagnostics")))]
+    let (claimed_at, claims, implicit) = (None, u32::from(!token_available()), false);
+
+    let thread = thread::current();
+    ThreadPermissionDebug {
+        claimed_at,
+        claims,
+        implicit,
+        thread_id: thread.id(),
+        thread_name: thread.name().map(str::to_owned),
+    }
+}
+
+/// Why [`OuterMutexPermission::get_or_diagnose`] could not hand out a token.
+#[derive(Clone, Debug)]
+pub struct ClaimDiagnostics {
+    /// The thread's state at the time of the failed claim.
+    pub state: ThreadPermissionDebug,
+    /// Where the failed claim was made.
+    pub attempted_at: &'static Location<'static>,
+    /// The token was still there, but claims are suspended because another
+    /// thread holds a [`SingleThreadedPhase`](crate::phase::SingleThreadedPhase).
+    pub blocked_by_phase: bool,
+    /// The claim was made inside [`quarantine`], where nothing is claimed.
+    /// The token was not looked at.
+    pub quarantined: bool,
+}
+
+impl fmt::Display for ClaimDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = &self.state;
+        if self.quarantined {
+            return write!(
+                f,
+                "Mutex permission claimed inside permission::quarantine (thread {:?}, {:?}), \
+                 claim attempted at {}",
+                state.thread_name.as_deref().unwrap_or("<unnamed>"),
+                state.thread_id,
+                self.attempted_at,
+            );
+        }
+        if self.blocked_by_phase {
+            return write!(
+                f,
+                "Mutex permission claims are suspended while a single-threaded phase is held \
+                 (thread {:?}, {:?}), claim attempted at {}",
+                state.thread_name.as_deref().unwrap_or("<unnamed>"),
+                state.thread_id,
+                self.attempted_at,
+            );
+        }
+        write!(
+            f,
+            "Mutex permission already claimed for this thread (thread {:?}, {:?}); ",
+            state.thread_name.as_deref().unwrap_or("<unnamed>"),
+            state.thread_id,
+        )?;
+        match state.claimed_at {
+            Some(location) if state.implicit => write!(f, "implicitly claimed at {location}")?,
+            Some(location) => write!(f, "first claimed at {location}")?,
+            None => write!(f, "claim site not tracked")?,
+        }
+        write!(f, ", {} claim attempt(s), this one at {}", state.claims, self.attempted_at)
+    }
+}
+
+impl std::error::Error for ClaimDiagnostics {}
+
+thread_local! {
+    /// How many [`quarantine`]s the current thread is inside.
+    static QUARANTINE: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Whether the current thread is inside [`quarantine`].
+pub(crate) fn quarantined() -> bool {
+    QUARANTINE.try_with(Cell::get).unwrap_or(0) != 0
+}
+
+/// [`Quarantined`] at `attempted_at` if the current thread is inside
+/// [`quarantine`].
+pub(crate) fn check_quarantine(attempted_at: &'static Location<'static>) -> Result<(), Quarantined> {
+    if quarantined() {
+        Err(Quarantined { attempted_at })
+    } else {
+        Ok(())
+    }
+}
+
+/// Proof that the current thread is inside [`quarantine`], handed to the
+/// closure. Not `Send`, as the quarantine is the thread's.
+pub struct QuarantineToken {
+    _not_send: PhantomData<Rc<()>>,
+}
+
+/// Leaves the quarantine it was made for, on return or unwind.
+struct QuarantineExit;
+
+impl Drop for QuarantineExit {
+    fn drop(&mut self) {
+        let _ = QUARANTINE.try_with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// Runs `f` with every lock that does not take a permission refused on this
+/// thread, e.g. around a call into plugin code from inside a critical
+/// section.
+///
+/// Inside, claiming the thread's token fails: [`OuterMutexPermission::get`]
+/// panics, [`try_get`](OuterMutexPermission::try_get) returns `None` and
+/// [`get_or_diagnose`](OuterMutexPermission::get_or_diagnose) reports
+/// [`ClaimDiagnostics::quarantined`]. Locks without a permission return
+/// [`ClaimError::Quarantined`](crate::unclaimed::ClaimError::Quarantined)
+/// whatever the [policy](crate::unclaimed::Policy), and
+/// [`lock_elided`](crate::DeadlockProofMutex::lock_elided) panics. Every
+/// other lock needs a permission in hand, which `f` only has if it was
+/// given one. Quarantines nest, and each one ends when `f` returns or
+/// unwinds.
+pub fn quarantine<R>(f: impl FnOnce(QuarantineToken) -> R) -> R {
+    QUARANTINE.with(|depth| depth.set(depth.get() + 1));
+    let _exit = QuarantineExit;
+    f(QuarantineToken {
+        _not_send: PhantomData,
+    })
+}
+
+/// A lock was attempted inside [`quarantine`].
+#[derive(Clone, Debug)]
+pub struct Quarantined {
+    /// Where the lock was attempted.
+    pub attempted_at: &'static Location<'static>,
+}
+
+impl fmt::Display for Quarantined {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "deadlock-proof lock attempted inside permission::quarantine at {}",
+            self.attempted_at
+        )
+    }
+}
+
+impl std::error::Error for Quarantined {}
+
+/// The lineage tag reported with an acquisition made with `permission`.
+#[cfg(feature = "metrics")]
+pub(crate) fn tag_of<P: MutexPermission>(permission: &P) -> Option<u64> {
+    permission.tag()
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn tag_of<P: MutexPermission>(_permission: &P) -> Option<u64> {
+    None
+}
+
+/// Panics if `permission` is used on another thread than the one that
+/// claimed its root.
+#[cfg(feature = "origin-check")]
+pub(crate) use crate::origin::check as check_origin;
+
+#[cfg(not(feature = "origin-check"))]
+pub(crate) fn check_origin<P: MutexPermission>(_permission: &P) {}
+
+/// Permission to claim some nested mutex.
+#[cfg_attr(not(all(feature = "metrics", feature = "origin-check")), repr(transparent))]
+pub struct NestedMutexPermission<P: MutexPermission, I: 'static> {
+    _not_send: PhantomData<Rc<()>>,
+    _outer: PhantomData<(P, I)>,
+    #[cfg(feature = "metrics")]
+    tag: Option<u64>,
+    #[cfg(feature = "origin-check")]
+    origin: Option<ThreadId>,
+}
+
+impl<P: MutexPermission, I: 'static> NestedMutexPermission<P, I> {
+    /// The permission for claiming mutexes inside the one `outer` locked.
+    #[cfg_attr(
+        not(any(feature = "metrics", feature = "origin-check")),
+        allow(unused_variables)
+    )]
+    pub(crate) fn new(outer: &P) -> Self {
+        Self {
+            _not_send: PhantomData,
+            _outer: PhantomData,
+            #[cfg(feature = "metrics")]
+            tag: outer.tag(),
+            #[cfg(feature = "origin-check")]
+            origin: outer.origin(),
+        }
+    }
+}
+
+impl<P: MutexPermission, I: 'static> MutexPermission for NestedMutexPermission<P, I> {
+    #[cfg(feature = "metrics")]
+    fn tag(&self) -> Option<u64> {
+        self.tag
+    }
+
+    #[cfg(feature = "origin-check")]
+    fn origin(&self) -> Option<ThreadId> {
+        self.origin
+    }
+}
+
+/// Permission to claim mutexes in a specific sequence. Laid out as the `P`
+/// it wraps, so a walk costs nothing however deep it goes.
+#[repr(transparent)]
+pub struct SequentialMutexPermission<P: MutexPermission, I: 'static>(PhantomData<Rc<()>>, P, PhantomData<I>);
+
+impl<P: MutexPermission, I: 'static> SequentialMutexPermission<P, I> {
+    pub(crate) fn new(permission: P) -> Self {
+        Self(PhantomData, permission, PhantomData)
+    }
+
+    /// Consumes this sequential permission to return the permission
+    /// token earlier in the sequence.
+    pub fn to_earlier(self) -> P {
+        self.1
+    }
+
+    /// Steps past the level `J` without locking it, for paths that have
+    /// nothing to do there. Returns what unlocking `J`'s guard with
+    /// `unlock_for_sequential` would have, so the levels below are locked
+    /// exactly as after a full walk.
+    ///
+    /// `J` has to be the level declared right after this position by
+    /// [`lock_hierarchy!`](crate::lock_hierarchy); skipping further, or
+    /// back up, does not compile:
+    ///
+    /// ```compile_fail
+    /// use deadlock_proof::*;
+    ///
+    /// let stack = NetworkStack::new();
+    /// let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    /// let permission = ip.unlock_for_sequential().skip_to::<TransportLock>();
+    /// ```
+    ///
+    /// ```compile_fail
+    /// use deadlock_proof::*;
+    ///
+    /// let stack = NetworkStack::new();
+    /// let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    /// let permission = ip.unlock_for_sequential().skip_to::<IpLock>();
+    /// ```
+    pub fn skip_to<J: LockLevel<Permission = Self>>(self) -> SequentialMutexPermission<Self, J> {
+        SequentialMutexPermission::new(self)
+    }
+
+    /// [`skip_to`](Self::skip_to) past the level `J`, but only if its mutex
+    /// is poisoned, as found by
+    /// [`lock_or_return`](crate::DeadlockProofMutex::lock_or_return). A
+    /// mutex that is not poisoned hands the permission back, so that a walk
+    /// written to step over broken levels cannot quietly skip healthy ones.
+    pub fn skip_poisoned<T, J: LockLevel<Permission = Self>>(
+        self,
+        mutex: &DeadlockProofMutex<T, Self, J>,
+    ) -> Result<SequentialMutexPermission<Self, J>, Self> {
+        if mutex.is_poisoned() {
+            Ok(self.skip_to())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl<P: MutexPermission, I: 'static> MutexPermission for SequentialMutexPermission<P, I> {
+    fn recover(self) {
+        self.1.recover()
+    }
+
+    #[cfg(feature = "metrics")]
+    fn tag(&self) -> Option<u64> {
+        self.1.tag()
+    }
+
+    #[cfg(feature = "origin-check")]
+    fn origin(&self) -> Option<ThreadId> {
+        self.1.origin()
+    }
+}
@@ -0,0 +1,70 @@
+//! A place to keep a permission between polls of a hand-written future.
+//!
+//! A manual `Future` that takes locks has to put its permission somewhere
+//! while it returns `Pending`. A [`PermissionCell`] is that place: take the
+//! permission out at the start of `poll`, and store it back (or the one the
+//! guard returned) before returning. If the future is dropped while the
+//! permission is stored, the cell hands it to [`MutexPermission::recover`],
+//! which returns an [`AsyncPermission`](crate::AsyncPermission) to its task's
+//! slot.
+//!
+//! The cell is `Unpin` and never pinned structurally, so a pinned future may
+//! take `&mut` to it without any pin projection. It is `Send` exactly when the
+//! permission is, which among the crate's permissions is only the task
+//! permission itself: thread-rooted and derived permissions stay put.
+//!
+//! ```compile_fail
+//! use deadlock_proof::{OuterMutexPermission, PermissionCell};
+//!
+//! fn assert_send<T: Send>(_: T) {}
+//! assert_send(PermissionCell::new(OuterMutexPermission::get()));
+//! ```
+
+use crate::MutexPermission;
+
+/// Holds at most one permission across `poll` calls.
+pub struct PermissionCell<P: MutexPermission> {
+    permission: Option<P>,
+}
+
+impl<P: MutexPermission> PermissionCell<P> {
+    /// A cell already holding `permission`.
+    pub fn new(permission: P) -> Self {
+        Self {
+            permission: Some(permission),
+        }
+    }
+
+    /// An empty cell.
+    pub fn empty() -> Self {
+        Self { permission: None }
+    }
+
+    /// Takes the permission out for the duration of a poll.
+    pub fn poll_take(&mut self) -> Option<P> {
+        self.permission.take()
+    }
+
+    /// Puts a permission back. A permission already in the cell is recovered
+    /// first, since a cell never holds two.
+    pub fn store(&mut self, permission: P) {
+        if let Some(previous) = self.permission.replace(permission) {
+            previous.recover();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.permission.is_none()
+    }
+}
+
+// The permission is never pinned, whatever its type.
+impl<P: MutexPermission> Unpin for PermissionCell<P> {}
+
+impl<P: MutexPermission> Drop for PermissionCell<P> {
+    fn drop(&mut self) {
+        if let Some(permission) = self.permission.take() {
+            permission.recover();
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Lock elision for phases in which only one thread can lock anything.
+//!
+//! [`SingleThreadedPhase::enter`] succeeds only while the calling thread is
+//! the only one holding a claimed [`OuterMutexPermission`], and for as long
+//! as the phase is held no other thread can claim one. Inside the phase,
+//! [`DeadlockProofMutex::lock_elided`] hands out guards without threading a
+//! permission and without going through the blocking acquisition path.
+//!
+//! Threads that claimed their permission count until they exit, even if the
+//! permission itself was dropped, so a phase is typically entered during
+//! setup, before any workers are spawned, or after they have been joined.
+
+use std::{
+    cell::Cell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    panic::Location,
+    rc::Rc,
+    sync::{MutexGuard, TryLockError},
+};
+
+use crate::{
+    permission, version::Dirty, DeadlockProofMutex, MutexPermission, OuterMutexPermission,
+};
+
+thread_local! {
+    static IN_PHASE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Proof that the current thread is the only one that can lock. The
+/// thread's root permission stays borrowed for as long as the phase is held.
+pub struct SingleThreadedPhase<'p> {
+    _permission: PhantomData<&'p mut OuterMutexPermission>,
+    _not_send: PhantomData<Rc<()>>,
+}
+
+impl<'p> SingleThreadedPhase<'p> {
+    /// Enters a phase, or `None` if any other thread holds a claimed root
+    /// permission.
+    pub fn enter(_permission: &'p mut OuterMutexPermission) -> Option<Self> {
+        if !permission::enter_phase() {
+            return None;
+        }
+        IN_PHASE.with(|in_phase| in_phase.set(true));
+        Some(Self {
+            _permission: PhantomData,
+            _not_send: PhantomData,
+        })
+    }
+}
+
+impl Drop for SingleThreadedPhase<'_> {
+    fn drop(&mut self) {
+        IN_PHASE.with(|in_phase| in_phase.set(false));
+        permission::exit_phase();
+    }
+}
+
+/// Panics if a phase is held by another thread, unless a misuse handler
+/// takes the report. Only checked in debug builds, on every blocking
+/// acquisition.
+#[cfg(debug_assertions)]
+pub(crate) fn assert_not_in_foreign_phase() {
+    if permission::phase_held() && !IN_PHASE.try_with(Cell::get).unwrap_or(false) {
+        let event = crate::misuse::MisuseEvent::ForeignPhaseLock;
+        if !crate::misuse::report(|| event.clone()) {
+            panic!("{event}");
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Direct access when the mutex is not shared at all. Taking the root
+    /// permission mutably keeps this out of the middle of a lock walk.
+    ///
+    /// Poisoning is ignored; the version goes up as for any mutable access.
+    pub fn get_mut_exclusive(&mut self, _permission: &mut OuterMutexPermission) -> &mut T {
+        self.versions.bump();
+        self.inner
+            .get_mut()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+
+    /// Locks without a permission during a single-threaded phase. Since no
+    /// other thread can be locking, this never waits and skips the waiter,
+    /// metrics and contention bookkeeping of [`lock`](Self::lock).
+    ///
+    /// Panics if the mutex is poisoned, unless built with `no-poison`, or
+    /// already locked, which in a single-threaded phase means this thread
+    /// still holds a guard for it. The latter is reported to the
+    /// [misuse handler](crate::misuse) first. Also panics inside
+    /// [`permission::quarantine`].
+    #[track_caller]
+    pub fn lock_elided<'a>(&'a self, _phase: &'a SingleThreadedPhase<'_>) -> ElidedGuard<'a, T> {
+        if let Err(quarantined) = permission::check_quarantine(Location::caller()) {
+            panic!("{quarantined}");
+        }
+        let guard = match self.inner.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                let at = Location::caller();
+                crate::misuse::report(|| crate::misuse::MisuseEvent::ElidedRelock { at });
+                panic!("lock_elided on a mutex this thread already holds")
+            }
+            #[cfg(not(feature = "no-poison"))]
+            Err(TryLockError::Poisoned(_)) => panic!("lock_elided on a poisoned mutex"),
+            #[cfg(feature = "no-poison")]
+            Err(TryLockError::Poisoned(error)) => error.into_inner(),
+        };
+        ElidedGuard {
+            guard,
+            dirty: Dirty::clean(&self.versions).at(Location::caller()),
+        }
+    }
+}
+
+/// Guard returned by [`DeadlockProofMutex::lock_elided`]. Cannot outlive the
+/// phase it was taken in.
+pub struct ElidedGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    dirty: Dirty<'a>,
+}
+
+impl<T> ElidedGuard<'_, T> {
+    /// Mutable access to the data, as
+    /// [`DeadlockProofMutexGuard::get_mut`](crate::DeadlockProofMutexGuard::get_mut).
+    pub fn get_mut(&mut self) -> &mut T {
+        self.dirty.mark();
+        &mut self.guard
+    }
+}
+
+impl<T> Deref for ElidedGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for ElidedGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.get_mut()
+    }
+}
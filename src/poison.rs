@@ -0,0 +1,328 @@
+//! Poisoning, and the `no-poison` feature that does away with it.
+//!
+//! Built with `panic = "abort"`, no panic ever unwinds out of a critical
+//! section, so no lock is ever poisoned and every `PoisonError` in the API is
+//! dead weight. Under the `no-poison` feature:
+//!
+//! - every lock method returns its guard directly instead of a `Result`;
+//! - [`TxAborted`](crate::TxAborted) loses its `Poisoned` variant, and the
+//!   one of [`PinnedLockError`](crate::PinnedLockError) becomes uninhabited,
+//!   so matches need not mention it;
+//! - poison is no longer checked anywhere. Should a panic unwind out of a
+//!   critical section after all, the next holder sees the data as it was
+//!   left.
+//!
+//! Code that has to build in both modes handles lock results through
+//! [`LockOutcome`], which is implemented by whatever the lock methods return
+//! in the mode the crate was built in:
+//!
+//! ```
+//! use deadlock_proof::{poison::LockOutcome, NetworkStack, OuterMutexPermission};
+//!
+//! let stack = NetworkStack::new();
+//! let mut ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+//! ip.packets_processed += 1;
+//! ```
+//!
+//! A blocking lock of a poisoned mutex fails with a
+//! [`DeadlockProofPoisonError`]. It holds the lock as an ordinary
+//! [`DeadlockProofMutexGuard`], with the permission in it, so the data can
+//! only be reached the way it always is, and
+//! [`into_permission`](DeadlockProofPoisonError::into_permission) unlocks
+//! without looking at the data at all:
+//!
+//! ```
+//! # #[cfg(not(feature = "no-poison"))] {
+//! use deadlock_proof::{unique_type, DeadlockProofMutex, OuterMutexPermission};
+//!
+//! let routes = DeadlockProofMutex::new(vec![1u32], unique_type!());
+//! std::thread::scope(|scope| {
+//!     scope
+//!         .spawn(|| {
+//!             let _guard = routes.lock(OuterMutexPermission::get());
+//!             panic!("poisoning on purpose");
+//!         })
+//!         .join()
+//!         .unwrap_err();
+//! });
+//! let permission = routes.lock(OuterMutexPermission::get()).err().unwrap().into_permission();
+//! # drop(permission);
+//! # }
+//! ```
+
+use std::{error::Error, fmt, sync::PoisonError};
+
+#[cfg(feature = "no-poison")]
+use std::convert::Infallible;
+
+use crate::{DeadlockProofMutexGuard, MutexPermission};
+#[cfg(feature = "no-poison")]
+use crate::{
+    carry::SequentialCarry, compat::CompatGuard, Attachment, ContextGuard,
+    DeadlockProofNestedMutexGuard, DeadlockProofReadGuard, DeadlockProofWriteGuard, EitherGuard,
+    NestedMutexPermission, ordered_guards::{GuardStack, OrderedGuards},
+    PermissionWith, profiling::ScopedGuard, unclaimed::AutoClaimGuard, SplitGuardA, SplitGuardB, SplitGuardBoth,
+};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Whether this build reports poisoning, that is, whether `no-poison` is off.
+pub const POISONING: bool = cfg!(not(feature = "no-poison"));
+
+/// How lock results are shaped in one of the two modes.
+pub trait PoisonMode: sealed::Sealed {
+    /// What a lock method handing out `G`, or failing with `E` when
+    /// poisoned, returns.
+    type LockResult<G, E>;
+}
+
+/// The default mode: locks report poisoning.
+pub enum Poisoning {}
+
+/// The `no-poison` mode: locks hand out their guard directly.
+pub enum NoPoison {}
+
+impl sealed::Sealed for Poisoning {}
+impl sealed::Sealed for NoPoison {}
+
+impl PoisonMode for Poisoning {
+    type LockResult<G, E> = Result<G, E>;
+}
+
+impl PoisonMode for NoPoison {
+    type LockResult<G, E> = G;
+}
+
+/// The mode this build uses.
+#[cfg(not(feature = "no-poison"))]
+pub type Mode = Poisoning;
+/// The mode this build uses.
+#[cfg(feature = "no-poison")]
+pub type Mode = NoPoison;
+
+/// What a lock method handing out `G` returns in this build: `Result<G, E>`
+/// by default, plain `G` under `no-poison`.
+pub type LockResult<G, E> = <Mode as PoisonMode>::LockResult<G, E>;
+
+/// The result of a lock method, whichever mode the crate was built in.
+pub trait LockOutcome: sealed::Sealed {
+    type Guard;
+    /// [`Infallible`](std::convert::Infallible) under `no-poison`.
+    type Error;
+
+    /// The guard, or the poison error.
+    fn into_result(self) -> Result<Self::Guard, Self::Error>;
+
+    /// The guard. Panics if the lock is poisoned.
+    #[track_caller]
+    fn guard(self) -> Self::Guard
+    where
+        Self: Sized,
+    {
+        match self.into_result() {
+            Ok(guard) => guard,
+            Err(_) => panic!("deadlock-proof lock is poisoned"),
+        }
+    }
+}
+
+#[cfg(not(feature = "no-poison"))]
+impl<G, X> sealed::Sealed for Result<G, PoisonError<X>> {}
+
+#[cfg(not(feature = "no-poison"))]
+impl<G, X> LockOutcome for Result<G, PoisonError<X>> {
+    type Guard = G;
+    type Error = PoisonError<X>;
+
+    fn into_result(self) -> Self {
+        self
+    }
+}
+
+#[cfg(not(feature = "no-poison"))]
+impl<G, T, P: MutexPermission, I: 'static> sealed::Sealed
+    for Result<G, DeadlockProofPoisonError<'_, T, P, I>>
+{
+}
+
+#[cfg(not(feature = "no-poison"))]
+impl<'a, G, T, P: MutexPermission, I: 'static> LockOutcome
+    for Result<G, DeadlockProofPoisonError<'a, T, P, I>>
+{
+    type Guard = G;
+    type Error = DeadlockProofPoisonError<'a, T, P, I>;
+
+    fn into_result(self) -> Self {
+        self
+    }
+}
+
+/// The error of a blocking lock of a poisoned mutex. Unlike a
+/// [`PoisonError`] of the inner guard, it keeps the lock behind a
+/// [`DeadlockProofMutexGuard`], which also holds the permission passed in.
+/// See the [module docs](self).
+pub struct DeadlockProofPoisonError<'a, T, P: MutexPermission, I: 'static>(
+    DeadlockProofMutexGuard<'a, T, P, I>,
+);
+
+impl<'a, T, P: MutexPermission, I: 'static> DeadlockProofPoisonError<'a, T, P, I> {
+    #[cfg(not(feature = "no-poison"))]
+    pub(crate) fn new(guard: DeadlockProofMutexGuard<'a, T, P, I>) -> Self {
+        Self(guard)
+    }
+
+    /// The guard, to use the data as the panicking holder left it.
+    pub fn into_guard(self) -> DeadlockProofMutexGuard<'a, T, P, I> {
+        self.0
+    }
+
+    /// Unlocks the mutex without touching the data, and returns the
+    /// permission. The mutex stays poisoned.
+    pub fn into_permission(self) -> P {
+        self.0.unlock()
+    }
+
+    /// The guard, borrowed.
+    pub fn get_ref(&self) -> &DeadlockProofMutexGuard<'a, T, P, I> {
+        &self.0
+    }
+
+    /// The guard, borrowed mutably.
+    pub fn get_mut(&mut self) -> &mut DeadlockProofMutexGuard<'a, T, P, I> {
+        &mut self.0
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> fmt::Debug for DeadlockProofPoisonError<'_, T, P, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadlockProofPoisonError").finish_non_exhaustive()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> fmt::Display for DeadlockProofPoisonError<'_, T, P, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("deadlock-proof mutex is poisoned")
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Error for DeadlockProofPoisonError<'_, T, P, I> {}
+
+/// Makes each guard type its own outcome under `no-poison`.
+macro_rules! unpoisoned_outcome {
+    ($([$($generics:tt)*] $guard:ty;)*) => {
+        $(
+            #[cfg(feature = "no-poison")]
+            impl<$($generics)*> sealed::Sealed for $guard {}
+
+            #[cfg(feature = "no-poison")]
+            impl<$($generics)*> LockOutcome for $guard {
+                type Guard = Self;
+                type Error = Infallible;
+
+                fn into_result(self) -> Result<Self, Infallible> {
+                    Ok(self)
+                }
+            }
+        )*
+    };
+}
+
+unpoisoned_outcome! {
+    ['a, T, P: MutexPermission, I: 'static] DeadlockProofMutexGuard<'a, T, P, I>;
+    ['a, T, P: MutexPermission, I: 'static]
+        (DeadlockProofNestedMutexGuard<'a, T, P, I>, NestedMutexPermission<P, I>);
+    ['a, T, P: MutexPermission, I: 'static] EitherGuard<'a, T, P, I>;
+    ['a, T, P: MutexPermission, I: 'static] CompatGuard<'a, T, P, I>;
+    ['a, T, P: MutexPermission, I: 'static] DeadlockProofReadGuard<'a, T, P, I>;
+    ['a, T, P: MutexPermission, I: 'static] DeadlockProofWriteGuard<'a, T, P, I>;
+    [P: MutexPermission, J: 'static, Y] SequentialCarry<P, J, Y>;
+    ['a, T, P: MutexPermission, I: 'static] ScopedGuard<'a, T, P, I>;
+    [S: GuardStack] OrderedGuards<S>;
+    ['a, A, B, P: MutexPermission, I: 'static] SplitGuardA<'a, A, B, P, I>;
+    ['a, B, P: MutexPermission, I: 'static] SplitGuardB<'a, B, P, I>;
+    ['a, A, B, P: MutexPermission, I: 'static] SplitGuardBoth<'a, A, B, P, I>;
+    ['a, T, I: 'static] AutoClaimGuard<'a, T, I>;
+    ['a, T, P: MutexPermission, I: 'static, C: 'static, S: Attachment]
+        ContextGuard<'a, T, P, I, C, S>;
+    ['a, T, P: MutexPermission, I: 'static, C: 'static]
+        (DeadlockProofNestedMutexGuard<'a, T, P, I>, PermissionWith<NestedMutexPermission<P, I>, C>);
+}
+
+/// `Ok(guard)`, or just `guard` under `no-poison`.
+#[cfg(not(feature = "no-poison"))]
+pub(crate) fn unpoisoned<G, E>(guard: G) -> LockResult<G, E> {
+    Ok(guard)
+}
+#[cfg(feature = "no-poison")]
+pub(crate) fn unpoisoned<G>(guard: G) -> G {
+    guard
+}
+
+/// `result.map(f)`, or just `f(result)` under `no-poison`.
+#[cfg(not(feature = "no-poison"))]
+macro_rules! map {
+    ($result:expr, $f:expr) => {
+        $result.map($f)
+    };
+}
+#[cfg(feature = "no-poison")]
+macro_rules! map {
+    ($result:expr, $f:expr) => {
+        $crate::poison::apply($result, $f)
+    };
+}
+pub(crate) use map;
+
+/// `f` applied to the guard, whether or not it is poisoned: [`map!`] for
+/// results whose poison error carries the same guard.
+#[cfg(not(feature = "no-poison"))]
+pub(crate) fn map_poisoned<G, H>(
+    result: LockResult<G, PoisonError<G>>,
+    f: impl FnOnce(G) -> H,
+) -> LockResult<H, PoisonError<H>> {
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(poisoned) => Err(PoisonError::new(f(poisoned.into_inner()))),
+    }
+}
+#[cfg(feature = "no-poison")]
+pub(crate) fn map_poisoned<G, H>(guard: G, f: impl FnOnce(G) -> H) -> H {
+    f(guard)
+}
+
+/// Calls `f` through a signature, so closures passed to [`map!`] get their
+/// argument type from `guard` as they would from `Result::map`.
+#[cfg(feature = "no-poison")]
+pub(crate) fn apply<G, H>(guard: G, f: impl FnOnce(G) -> H) -> H {
+    f(guard)
+}
+
+/// The guard, poisoned or not.
+#[cfg(not(feature = "no-poison"))]
+pub(crate) fn ignore<G>(result: LockResult<G, PoisonError<G>>) -> G {
+    result.unwrap_or_else(PoisonError::into_inner)
+}
+#[cfg(feature = "no-poison")]
+pub(crate) fn ignore<G>(guard: LockResult<G, PoisonError<G>>) -> G {
+    guard
+}
+
+/// What `result` holds, poisoned or not, in either mode: for results that
+/// are not guards, such as those of
+/// [`read_with`](crate::DeadlockProofMutex::read_with), and so have no
+/// [`LockOutcome`].
+pub fn into_inner<G>(result: LockResult<G, PoisonError<G>>) -> G {
+    ignore(result)
+}
+
+/// The guard, or `None` if poisoned.
+#[cfg(all(feature = "ffi", not(feature = "no-poison")))]
+pub(crate) fn ok<G>(result: LockResult<G, PoisonError<G>>) -> Option<G> {
+    result.ok()
+}
+#[cfg(all(feature = "ffi", feature = "no-poison"))]
+pub(crate) fn ok<G>(guard: LockResult<G, PoisonError<G>>) -> Option<G> {
+    Some(guard)
+}
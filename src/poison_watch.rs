@@ -0,0 +1,189 @@
+//! Notifications for mutexes poisoned by a panic, as it happens rather than
+//! on the next lock that fails.
+//!
+//! Every guard of a [`DeadlockProofMutex`] notes whether its thread was
+//! panicking when it locked. If a guard that locked normally is released
+//! while its thread panics, the panic unwound out of the critical section
+//! and has poisoned the mutex. The release then reports a [`PoisonEvent`]:
+//!
+//! - to the mutex's own watchers, registered with
+//!   [`on_poison`](DeadlockProofMutex::on_poison) next to its
+//!   [`on_change`](DeadlockProofMutex::on_change) listeners;
+//! - then to the process-wide observer installed with
+//!   [`set_poison_observer`], to alert on any mutex;
+//! - with the `metrics` feature, to the mutex's `poisoned_total()` counter,
+//!   and under `metrics-exporter` to the recorder's
+//!   `metrics::POISONED_TOTAL` counter.
+//!
+//! ```
+//! use std::thread;
+//!
+//! use deadlock_proof::{
+//!     poison_watch, unique_type, DeadlockProofMutex, LockOutcome, OuterMutexPermission,
+//! };
+//!
+//! fn alert(event: poison_watch::PoisonEvent) {
+//!     eprintln!("{event}");
+//! }
+//! poison_watch::set_poison_observer(alert);
+//!
+//! let routes = DeadlockProofMutex::new(Vec::<u32>::new(), unique_type!());
+//! routes.on_poison(|event| assert!(event.locked_at.is_some()));
+//! thread::scope(|scope| {
+//!     let writer = scope.spawn(|| {
+//!         let _routes = routes.lock(OuterMutexPermission::get()).guard();
+//!         panic!("bad route");
+//!     });
+//!     assert!(writer.join().is_err());
+//! });
+//! # poison_watch::clear_poison_observer();
+//! ```
+//!
+//! Poisonings are reported under `no-poison` too: the next holder does not
+//! see them, but the data was still left mid-update. The plain `MutexGuard`
+//! inside a poison error reports nothing, since its mutex is poisoned
+//! already. Observers and watchers run on the panicking thread, during the
+//! unwind and after the mutex was unlocked. They must not lock anything or
+//! panic, as a panic during an unwind aborts the process.
+
+use std::{
+    fmt, mem,
+    panic::Location,
+    ptr,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Mutex,
+    },
+    thread::{self, ThreadId},
+    time::SystemTime,
+};
+
+#[cfg(feature = "metrics")]
+use std::sync::atomic::AtomicU64;
+
+use crate::{DeadlockProofMutex, MutexPermission};
+
+/// What a poison observer or watcher is told.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct PoisonEvent {
+    /// The mutex's [name](crate::DeadlockProofMutex::name), or else the type
+    /// name of its identifier.
+    pub identifier: &'static str,
+    /// The thread that panicked.
+    pub thread: ThreadId,
+    /// Its name, if it has one.
+    pub thread_name: Option<String>,
+    /// Where the guard was acquired, for the acquisitions that track it.
+    pub locked_at: Option<&'static Location<'static>>,
+    /// When the guard was released.
+    pub at: SystemTime,
+}
+
+impl fmt::Display for PoisonEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` poisoned by a panic on thread ", self.identifier)?;
+        match &self.thread_name {
+            Some(name) => write!(f, "{name:?}")?,
+            None => write!(f, "{:?}", self.thread)?,
+        }
+        if let Some(locked_at) = self.locked_at {
+            write!(f, "; locked at {locked_at}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A process-wide poison observer.
+pub type PoisonObserver = fn(PoisonEvent);
+
+/// Null while no observer is installed.
+static OBSERVER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Sends every poisoning from now on to `observer`, replacing any earlier
+/// one.
+pub fn set_poison_observer(observer: PoisonObserver) {
+    OBSERVER.store(observer as *mut (), Ordering::Release);
+}
+
+/// Stops sending poisonings to the observer.
+pub fn clear_poison_observer() {
+    OBSERVER.store(ptr::null_mut(), Ordering::Release);
+}
+
+fn observer() -> Option<PoisonObserver> {
+    let observer = OBSERVER.load(Ordering::Acquire);
+    // Safety: the only non-null values ever stored are `PoisonObserver`s,
+    // cast to a data pointer of the same size.
+    (!observer.is_null()).then(|| unsafe { mem::transmute::<*mut (), PoisonObserver>(observer) })
+}
+
+type Watcher = Box<dyn Fn(&PoisonEvent) + Send + Sync>;
+
+/// The poison watchers and counter of one mutex.
+pub(crate) struct Watch {
+    identifier: &'static str,
+    watchers: Mutex<Vec<Watcher>>,
+    #[cfg(feature = "metrics")]
+    total: AtomicU64,
+}
+
+impl Watch {
+    pub(crate) fn new(identifier: &'static str) -> Self {
+        Self {
+            identifier,
+            watchers: Mutex::new(Vec::new()),
+            #[cfg(feature = "metrics")]
+            total: AtomicU64::new(0),
+        }
+    }
+
+    /// Reports that a guard acquired at `locked_at` was released by a panic.
+    pub(crate) fn poisoned(&self, locked_at: Option<&'static Location<'static>>) {
+        #[cfg(feature = "metrics")]
+        self.total.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics-exporter")]
+        crate::metrics::export_poisoned(self.identifier);
+        let watchers = self
+            .watchers
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        let observer = observer();
+        if observer.is_none() && watchers.is_empty() {
+            return;
+        }
+        let current = thread::current();
+        let event = PoisonEvent {
+            identifier: self.identifier,
+            thread: current.id(),
+            thread_name: current.name().map(str::to_owned),
+            locked_at,
+            at: SystemTime::now(),
+        };
+        for watcher in watchers.iter() {
+            watcher(&event);
+        }
+        if let Some(observer) = observer {
+            observer(event);
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Calls `watcher` every time a panic poisons this mutex. See the
+    /// [module docs](crate::poison_watch).
+    pub fn on_poison(&self, watcher: impl Fn(&PoisonEvent) + Send + Sync + 'static) {
+        self.versions
+            .poison
+            .watchers
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .push(Box::new(watcher));
+    }
+
+    /// How many guards of this mutex were released by a panic.
+    #[cfg(feature = "metrics")]
+    pub fn poisoned_total(&self) -> u64 {
+        self.versions.poison.total.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,121 @@
+//! Acquisition by polling, for event loops that neither block nor run
+//! futures.
+//!
+//! [`DeadlockProofMutex::poll_lock`] starts an acquisition without trying
+//! anything yet. Each [`PollLock::poll`] then makes one attempt and returns
+//! [`Poll::Pending`] if the mutex is taken, so the loop can go on with other
+//! work and poll again on its next round.
+//!
+//! The permission stays in a slot the caller owns and is only taken out by
+//! the attempt that gets the lock. Dropping a `PollLock` half way, e.g. when
+//! the loop gives up on a task, leaves the permission where it was. From its
+//! first miss until it gets the lock or is dropped, a poll counts towards the
+//! mutex's `waiters`, and `others_waiting` tells the loop whether it is
+//! queued behind anyone else, both under the `tracking` feature.
+//!
+//! ```
+//! use std::task::Poll;
+//!
+//! use deadlock_proof::{task::AsyncPermissionSlot, DeadlockProofMutex};
+//!
+//! struct CounterLock;
+//! let counter = DeadlockProofMutex::new(0u32, CounterLock);
+//! let mut slot = AsyncPermissionSlot::new().claim();
+//!
+//! let mut poll = counter.poll_lock(&mut slot);
+//! let Poll::Ready(mut guard) = poll.poll() else {
+//!     unreachable!("nothing else holds the counter");
+//! };
+//! drop(poll);
+//! *guard += 1;
+//! slot = Some(guard.unlock());
+//! assert!(slot.is_some());
+//! ```
+
+#[cfg(feature = "tracking")]
+use std::sync::atomic::Ordering;
+use std::{panic::Location, task::Poll};
+
+use crate::{rt::TryLockError, DeadlockProofMutex, DeadlockProofMutexGuard, MutexPermission};
+
+/// An acquisition in progress, made one attempt at a time. Created by
+/// [`DeadlockProofMutex::poll_lock`].
+pub struct PollLock<'a, 's, T, P: MutexPermission, I: 'static> {
+    mutex: &'a DeadlockProofMutex<T, P, I>,
+    slot: &'s mut Option<P>,
+    location: &'static Location<'static>,
+    /// Whether the poll has missed and counts as a waiter.
+    waiting: bool,
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Starts a polled acquisition with the permission in `slot`. See the
+    /// [module docs](crate::poll).
+    #[track_caller]
+    pub fn poll_lock<'s>(&self, slot: &'s mut Option<P>) -> PollLock<'_, 's, T, P, I> {
+        PollLock {
+            mutex: self,
+            slot,
+            location: Location::caller(),
+            waiting: false,
+        }
+    }
+}
+
+impl<'a, T, P: MutexPermission, I: 'static> PollLock<'a, '_, T, P, I> {
+    /// Makes one attempt. On success the permission moves from the slot into
+    /// the guard; otherwise it stays in the slot.
+    ///
+    /// Panics if the slot is empty, or if the mutex is poisoned, in which
+    /// case the permission is back in the slot.
+    pub fn poll(&mut self) -> Poll<DeadlockProofMutexGuard<'a, T, P, I>> {
+        let permission = self
+            .slot
+            .take()
+            .expect("polled for a lock with an empty permission slot");
+        match self.mutex.try_guard_at(permission, self.location) {
+            Ok(guard) => {
+                self.stop_waiting();
+                Poll::Ready(guard)
+            }
+            Err(TryLockError::WouldBlock(permission)) => {
+                *self.slot = Some(permission);
+                if !self.waiting {
+                    self.waiting = true;
+                    #[cfg(feature = "tracking")]
+                    self.mutex.waiters.fetch_add(1, Ordering::Relaxed);
+                }
+                Poll::Pending
+            }
+            #[cfg(not(feature = "no-poison"))]
+            Err(TryLockError::Poisoned(permission)) => {
+                *self.slot = Some(permission);
+                panic!("polled for a poisoned deadlock-proof mutex");
+            }
+        }
+    }
+
+    /// How many others are waiting for the mutex, not counting this poll. As
+    /// racy as [`DeadlockProofMutex::waiters`]; a high count means the next
+    /// poll is unlikely to succeed. Needs the `tracking` feature.
+    #[cfg(feature = "tracking")]
+    pub fn others_waiting(&self) -> usize {
+        self.mutex
+            .waiters()
+            .saturating_sub(usize::from(self.waiting))
+    }
+
+    fn stop_waiting(&mut self) {
+        if self.waiting {
+            self.waiting = false;
+            #[cfg(feature = "tracking")]
+            self.mutex.waiters.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Drop for PollLock<'_, '_, T, P, I> {
+    fn drop(&mut self) {
+        self.stop_waiting();
+    }
+}
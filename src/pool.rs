@@ -0,0 +1,416 @@
+//! A worker pool whose jobs lease their worker's root permission.
+//!
+//! As in the [`thread_pool`](crate::patterns::thread_pool) pattern, each
+//! worker claims its root permission once, when it starts, and threads it
+//! through every job it runs. Here the job gets it as a [`LeasedPermission`]
+//! with a deadline. A job that has not handed the permission back by then,
+//! typically because a guard holding it was leaked and the job now waits
+//! forever, cannot be made to: the permission belongs to the worker's thread.
+//! The pool retires that worker instead, reports a [`Retirement`] naming the
+//! job and where it was submitted, and starts a replacement thread, which
+//! claims a fresh permission of its own. A job that panics loses the
+//! permission just the same, and its worker is retired straight away.
+//!
+//! ```
+//! use std::{sync::{mpsc, Arc}, time::Duration};
+//!
+//! use deadlock_proof::{pool::WorkerPool, LockOutcome, NetworkStack};
+//!
+//! let stack = Arc::new(NetworkStack::new());
+//! let pool = WorkerPool::new(2, Duration::from_secs(5));
+//! let (done, finished) = mpsc::channel();
+//! for _ in 0..10 {
+//!     let (stack, done) = (stack.clone(), done.clone());
+//!     pool.submit("count packet", move |lease| {
+//!         lease.with_permission(|permission| {
+//!             let mut ip = stack.ip_layer.lock(permission).guard();
+//!             ip.packets_processed += 1;
+//!             (ip.unlock(), ())
+//!         });
+//!         done.send(()).unwrap();
+//!     });
+//! }
+//! finished.iter().take(10).for_each(drop);
+//! assert!(pool.retirements().is_empty());
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    panic::{self, AssertUnwindSafe, Location},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock,
+    lease::{FinishToken, PermissionLease},
+    OuterMutexPermission,
+};
+
+/// A job run by a [`WorkerPool`] worker, with that worker's permission on
+/// lease.
+pub type Job = Box<dyn FnOnce(&mut LeasedPermission) + Send>;
+
+/// A worker's root permission, lent to one job until its deadline.
+pub struct LeasedPermission {
+    /// Only `None` once a job panicked while it had the permission out.
+    permission: Option<OuterMutexPermission>,
+    deadline: Instant,
+}
+
+impl LeasedPermission {
+    /// When the pool gives up on the job and retires its worker.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Lends the permission to `f`, which hands it back along with its
+    /// result, as [`WalkToken::with_permission`](crate::WalkToken::with_permission).
+    ///
+    /// Panics if an earlier `f` panicked and so never returned the permission.
+    pub fn with_permission<R>(
+        &mut self,
+        f: impl FnOnce(OuterMutexPermission) -> (OuterMutexPermission, R),
+    ) -> R {
+        let permission = self
+            .permission
+            .take()
+            .expect("the leased permission was lost to a panic");
+        let (permission, result) = f(permission);
+        self.permission = Some(permission);
+        result
+    }
+
+    /// Like [`with_permission`](Self::with_permission), but lends `f` the
+    /// permission as a [`PermissionLease`] with its [`FinishToken`], so the
+    /// only way for `f` to hand it back is to finish the lease.
+    #[track_caller]
+    pub fn with_lease<R>(
+        &mut self,
+        f: impl FnOnce(PermissionLease, FinishToken) -> (OuterMutexPermission, R),
+    ) -> R {
+        let created_at = Location::caller();
+        self.with_permission(|permission| {
+            let (lease, finisher) = PermissionLease::new_at(permission, created_at);
+            f(lease, finisher)
+        })
+    }
+}
+
+/// Why a worker was retired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RetireReason {
+    /// The job still had the permission when its lease ran out.
+    LeaseExpired,
+    /// The job panicked, taking the permission with it.
+    Panicked,
+}
+
+/// A worker the pool gave up on, and the job that made it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Retirement {
+    /// Index of the worker, counting every worker the pool started.
+    pub worker: usize,
+    /// The name the job was submitted under.
+    pub job: &'static str,
+    /// Where the job was submitted.
+    pub submitted_at: &'static Location<'static>,
+    pub reason: RetireReason,
+}
+
+impl fmt::Display for Retirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.reason {
+            RetireReason::LeaseExpired => "kept its permission past the lease",
+            RetireReason::Panicked => "panicked and lost its permission",
+        };
+        write!(
+            f,
+            "worker {} retired: job `{}` submitted at {} {what}",
+            self.worker, self.job, self.submitted_at
+        )
+    }
+}
+
+/// At most this many retirements are kept for
+/// [`retirements`](WorkerPool::retirements); the rest are only reported.
+pub const MAX_RECORDED_RETIREMENTS: usize = 64;
+
+/// Hands `retirement` to the [misuse handler](crate::misuse), or prints it
+/// if there is none. Never called under the pool's state lock, as the
+/// handler may take its time.
+fn report(retirement: &Retirement) {
+    if !crate::misuse::report(|| crate::misuse::MisuseEvent::WorkerRetired(retirement.clone())) {
+        eprintln!("{retirement}");
+    }
+}
+
+struct Queued {
+    name: &'static str,
+    submitted_at: &'static Location<'static>,
+    job: Job,
+}
+
+/// The job a worker is running.
+struct Running {
+    name: &'static str,
+    submitted_at: &'static Location<'static>,
+    deadline: Instant,
+}
+
+#[derive(Default)]
+struct Slot {
+    running: Option<Running>,
+    retired: bool,
+    exited: bool,
+    handle: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<Queued>,
+    slots: Vec<Slot>,
+    retirements: Vec<Retirement>,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when a job is queued or the pool closes.
+    work: Condvar,
+    /// Signalled when a worker exits or the pool closes.
+    supervisor: Condvar,
+    lease: Duration,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        // Jobs never run under this lock, so it is never poisoned by them.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Starts worker `worker`, whose slot is already in `state`.
+    fn spawn(self: &Arc<Self>, state: &mut State, worker: usize) {
+        let shared = self.clone();
+        let handle = thread::Builder::new()
+            .name(format!("pool-worker-{worker}"))
+            .spawn(move || shared.work(worker))
+            .expect("failed to spawn a pool worker");
+        state.slots[worker].handle = Some(handle);
+    }
+
+    /// Takes the next job for `worker`, or `None` once the pool is closed and
+    /// drained.
+    fn next_job(&self, worker: usize) -> Option<(Job, Instant)> {
+        let mut state = self.state();
+        loop {
+            if let Some(queued) = state.queue.pop_front() {
+                let deadline = clock::now() + self.lease;
+                state.slots[worker].running = Some(Running {
+                    name: queued.name,
+                    submitted_at: queued.submitted_at,
+                    deadline,
+                });
+                return Some((queued.job, deadline));
+            }
+            if state.closed {
+                return None;
+            }
+            state = self
+                .work
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    fn work(self: Arc<Self>, worker: usize) {
+        let mut permission = OuterMutexPermission::get();
+        while let Some((job, deadline)) = self.next_job(worker) {
+            let mut lease = LeasedPermission {
+                permission: Some(permission),
+                deadline,
+            };
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| job(&mut lease)));
+            let mut state = self.state();
+            if state.slots[worker].retired {
+                // Came back after the lease ran out; the replacement runs on.
+                return;
+            }
+            match (outcome, lease.permission) {
+                (Ok(()), Some(returned)) => permission = returned,
+                _ => {
+                    let retirement = self.retire(&mut state, worker, RetireReason::Panicked);
+                    drop(state);
+                    report(&retirement);
+                    return;
+                }
+            }
+            state.slots[worker].running = None;
+        }
+        self.state().slots[worker].exited = true;
+        self.supervisor.notify_all();
+    }
+
+    /// Retires `worker` and starts its replacement, unless the pool is closed
+    /// and has nothing left for one to do. Returns the retirement for the
+    /// caller to report once it has let go of `state`.
+    fn retire(
+        self: &Arc<Self>,
+        state: &mut State,
+        worker: usize,
+        reason: RetireReason,
+    ) -> Retirement {
+        let slot = &mut state.slots[worker];
+        slot.retired = true;
+        // Nobody joins a retired worker; it may never finish.
+        slot.handle = None;
+        let running = slot.running.take().expect("only busy workers are retired");
+        let retirement = Retirement {
+            worker,
+            job: running.name,
+            submitted_at: running.submitted_at,
+            reason,
+        };
+        if state.retirements.len() < MAX_RECORDED_RETIREMENTS {
+            state.retirements.push(retirement.clone());
+        }
+        if !state.closed || !state.queue.is_empty() {
+            state.slots.push(Slot::default());
+            let replacement = state.slots.len() - 1;
+            self.spawn(state, replacement);
+        }
+        self.supervisor.notify_all();
+        retirement
+    }
+
+    /// Retires workers whose lease ran out, until every worker has exited or
+    /// been retired after the pool closed.
+    fn supervise(self: Arc<Self>) {
+        let tick = (self.lease / 4).clamp(Duration::from_millis(1), Duration::from_millis(100));
+        let mut state = self.state();
+        loop {
+            let now = clock::now();
+            let mut retired = Vec::new();
+            for worker in 0..state.slots.len() {
+                let slot = &state.slots[worker];
+                let expired = slot.running.as_ref().is_some_and(|job| job.deadline <= now);
+                if expired && !slot.retired {
+                    retired.push(self.retire(&mut state, worker, RetireReason::LeaseExpired));
+                }
+            }
+            if !retired.is_empty() {
+                drop(state);
+                retired.iter().for_each(report);
+                state = self.state();
+            }
+            if state.closed && state.slots.iter().all(|slot| slot.retired || slot.exited) {
+                return;
+            }
+            state = self
+                .supervisor
+                .wait_timeout(state, tick)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+/// A fixed number of workers running submitted jobs, each on a lease of the
+/// worker's permission. Dropping the pool runs the jobs already queued and
+/// waits for the workers, except those that were retired.
+pub struct WorkerPool {
+    shared: Arc<Shared>,
+    supervisor: Option<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// Starts `workers` workers, each job leasing its worker's permission
+    /// for `lease`.
+    ///
+    /// Panics if `workers` is 0.
+    pub fn new(workers: usize, lease: Duration) -> Self {
+        assert!(workers > 0, "a pool needs at least one worker");
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            work: Condvar::new(),
+            supervisor: Condvar::new(),
+            lease,
+        });
+        {
+            let mut state = shared.state();
+            for worker in 0..workers {
+                state.slots.push(Slot::default());
+                shared.spawn(&mut state, worker);
+            }
+        }
+        let supervisor = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("pool-supervisor".into())
+                .spawn(move || shared.supervise())
+                .expect("failed to spawn the pool supervisor")
+        };
+        Self {
+            shared,
+            supervisor: Some(supervisor),
+        }
+    }
+
+    /// Queues `job` under `name`, which is reported along with the caller's
+    /// location if the job has to be given up on.
+    #[track_caller]
+    pub fn submit(
+        &self,
+        name: &'static str,
+        job: impl FnOnce(&mut LeasedPermission) + Send + 'static,
+    ) {
+        let submitted_at = Location::caller();
+        self.shared.state().queue.push_back(Queued {
+            name,
+            submitted_at,
+            job: Box::new(job),
+        });
+        self.shared.work.notify_one();
+    }
+
+    /// How many workers are serving the queue, which retirement does not
+    /// change: every retired worker has been replaced.
+    pub fn capacity(&self) -> usize {
+        let state = self.shared.state();
+        state
+            .slots
+            .iter()
+            .filter(|slot| !slot.retired && !slot.exited)
+            .count()
+    }
+
+    /// The first [`MAX_RECORDED_RETIREMENTS`] workers retired, oldest first.
+    pub fn retirements(&self) -> Vec<Retirement> {
+        self.shared.state().retirements.clone()
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.shared.state().closed = true;
+        self.shared.work.notify_all();
+        self.shared.supervisor.notify_all();
+        if let Some(supervisor) = self.supervisor.take() {
+            let _ = supervisor.join();
+        }
+        let handles: Vec<_> = {
+            let mut state = self.shared.state();
+            state
+                .slots
+                .iter_mut()
+                .filter_map(|slot| slot.handle.take())
+                .collect()
+        };
+        for handle in handles {
+            let _ = handle.join();
+        }
+    }
+}
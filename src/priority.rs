@@ -0,0 +1,183 @@
+//! Priority tiers for the waiters of a contended mutex, enabled by the
+//! `priority` feature.
+//!
+//! Every blocking acquisition of a [`DeadlockProofMutex`] first queues for
+//! its turn to contend, with the [`Priority`] passed to
+//! [`lock_with_priority`](DeadlockProofMutex::lock_with_priority), or
+//! [`Normal`](Priority::Normal) for every other lock method. Only the waiter
+//! whose turn it is goes on to block on the inner mutex, and the next turn is
+//! handed out once it has the lock: to the highest tier waiting, first come
+//! first served within a tier. A waiter therefore waits for the holder and
+//! at most one waiter picked before it arrived, plus the waiters of its own
+//! tier or above that are ahead of it.
+//!
+//! So that sustained high-priority load cannot starve the others, a waiter
+//! moves up a tier for every [`promotion_threshold`] turns handed out while
+//! it waits.
+//!
+//! [`promotion_threshold`]: DeadlockProofMutex::promotion_threshold
+//!
+//! ```
+//! use deadlock_proof::{priority::Priority, LockOutcome, NetworkStack, OuterMutexPermission};
+//!
+//! let stack = NetworkStack::new();
+//! let permission = OuterMutexPermission::get();
+//! let mut ip = stack.ip_layer.lock_with_priority(permission, Priority::High).guard();
+//! ip.packets_processed += 1;
+//! ```
+
+use std::{
+    panic::Location,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Condvar, Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use crate::{
+    DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofPoisonError, LockResult,
+    MutexPermission,
+};
+
+/// How soon a waiter gets its turn, relative to the others.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Background work that can wait.
+    Low,
+    /// Every lock method but `lock_with_priority`.
+    #[default]
+    Normal,
+    /// Latency-sensitive work, such as a control plane.
+    High,
+}
+
+impl Priority {
+    /// `self` moved up by `tiers`, but no higher than [`High`](Self::High).
+    fn promoted(self, tiers: u64) -> Self {
+        match (self as u64).saturating_add(tiers) {
+            0 => Self::Low,
+            1 => Self::Normal,
+            _ => Self::High,
+        }
+    }
+}
+
+/// The promotion threshold of a new mutex.
+pub const DEFAULT_PROMOTION_THRESHOLD: u32 = 16;
+
+struct Waiter {
+    /// Arrival order, for first come first served within a tier.
+    ticket: u64,
+    priority: Priority,
+    /// [`Turns::handed_out`] when the waiter arrived.
+    arrived_at_turn: u64,
+}
+
+#[derive(Default)]
+struct Turns {
+    waiting: Vec<Waiter>,
+    next_ticket: u64,
+    handed_out: u64,
+    /// Whether a waiter has its turn and is still getting the lock.
+    taken: bool,
+}
+
+impl Turns {
+    /// The ticket of the waiter whose turn is next.
+    fn next(&self, threshold: u32) -> Option<u64> {
+        let threshold = u64::from(threshold.max(1));
+        let promoted = |waiter: &Waiter| {
+            let waited = self.handed_out - waiter.arrived_at_turn;
+            waiter.priority.promoted(waited / threshold)
+        };
+        self.waiting
+            .iter()
+            .max_by(|a, b| promoted(a).cmp(&promoted(b)).then(b.ticket.cmp(&a.ticket)))
+            .map(|waiter| waiter.ticket)
+    }
+}
+
+/// Who contends for the inner mutex next.
+pub(crate) struct WaiterQueue {
+    turns: Mutex<Turns>,
+    changed: Condvar,
+    threshold: AtomicU32,
+}
+
+impl WaiterQueue {
+    pub(crate) fn new() -> Self {
+        Self {
+            turns: Mutex::new(Turns::default()),
+            changed: Condvar::new(),
+            threshold: AtomicU32::new(DEFAULT_PROMOTION_THRESHOLD),
+        }
+    }
+
+    fn turns(&self) -> MutexGuard<'_, Turns> {
+        // Nothing runs under this lock that could panic.
+        self.turns.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Waits until it is the caller's turn to contend. The turn passes on
+    /// when the returned value is dropped, which should be right after the
+    /// inner mutex is locked.
+    pub(crate) fn turn(&self, priority: Priority) -> Turn<'_> {
+        let mut turns = self.turns();
+        let ticket = turns.next_ticket;
+        turns.next_ticket += 1;
+        let arrived_at_turn = turns.handed_out;
+        turns.waiting.push(Waiter {
+            ticket,
+            priority,
+            arrived_at_turn,
+        });
+        loop {
+            let threshold = self.threshold.load(Ordering::Relaxed);
+            if !turns.taken && turns.next(threshold) == Some(ticket) {
+                turns.waiting.retain(|waiter| waiter.ticket != ticket);
+                turns.taken = true;
+                turns.handed_out += 1;
+                return Turn(self);
+            }
+            turns = self
+                .changed
+                .wait(turns)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+/// A waiter's turn to contend for the inner mutex.
+pub(crate) struct Turn<'a>(&'a WaiterQueue);
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        self.0.turns().taken = false;
+        self.0.changed.notify_all();
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Like [`lock`](Self::lock), but waits for its turn with `priority`.
+    #[track_caller]
+    pub fn lock_with_priority(
+        &self,
+        permission: P,
+        priority: Priority,
+    ) -> LockResult<DeadlockProofMutexGuard<'_, T, P, I>, DeadlockProofPoisonError<'_, T, P, I>> {
+        self.lock_prioritized(permission, priority, Location::caller())
+    }
+
+    /// Sets after how many turns handed out to others a waiter moves up a
+    /// tier. `0` is taken as `1`. Applies to waiters already queued too.
+    pub fn set_promotion_threshold(&self, turns: u32) {
+        self.waiter_queue.threshold.store(turns, Ordering::Relaxed);
+        self.waiter_queue.changed.notify_all();
+    }
+
+    /// The current promotion threshold, [`DEFAULT_PROMOTION_THRESHOLD`]
+    /// unless changed.
+    pub fn promotion_threshold(&self) -> u32 {
+        self.waiter_queue.threshold.load(Ordering::Relaxed)
+    }
+}
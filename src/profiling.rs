@@ -0,0 +1,175 @@
+//! Profiler scopes around critical sections.
+//!
+//! [`DeadlockProofMutex::lock_scoped`] marks the time a guard is held as a
+//! scope named `"{identifier}::{scope_name}"`, where the identifier is the
+//! mutex's [name](DeadlockProofMutex::name), or else the type name of its
+//! `I`, so flamegraphs show it per lock rather than folded into whatever
+//! function held it.
+//!
+//! Scopes are only reported with the `profiling` feature, and only to the
+//! [`Profiler`] installed with [`set_profiler`]. The trait has the shape of
+//! the begin/end scope calls `puffin` and `tracy` expose, so supporting one
+//! of them is a small adapter outside this crate. Without the feature,
+//! `lock_scoped` is a plain `lock`.
+
+use std::ops::{Deref, DerefMut};
+
+use crate::{
+    poison, DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofPoisonError, LockResult,
+    MutexPermission, SequentialMutexPermission,
+};
+
+/// Receives the scopes opened by [`DeadlockProofMutex::lock_scoped`].
+///
+/// Scopes on one thread nest properly: every `end_scope` closes the most
+/// recent open scope of the same thread.
+#[cfg(feature = "profiling")]
+pub trait Profiler: Sync {
+    /// A critical section named `name` starts on the current thread.
+    fn begin_scope(&self, name: &'static str);
+
+    /// The critical section named `name` has ended.
+    fn end_scope(&self, name: &'static str);
+}
+
+#[cfg(feature = "profiling")]
+static PROFILER: std::sync::OnceLock<&'static dyn Profiler> = std::sync::OnceLock::new();
+
+/// Installs the process-wide profiler. Fails if one is already installed.
+/// [`configure`](crate::configure) installs it along with the other
+/// process-wide settings.
+#[cfg(feature = "profiling")]
+pub fn set_profiler(profiler: &'static dyn Profiler) -> Result<(), &'static dyn Profiler> {
+    PROFILER.set(profiler).map_err(|_| profiler)
+}
+
+/// The name a scope for an unnamed mutex of identifier `I` is reported
+/// under. [`DeadlockProofMutex::scope_name`] gives it for any mutex.
+///
+/// Each distinct name is built and leaked once, so profilers that want
+/// `&'static str` names get them without an allocation per lock.
+#[cfg(feature = "profiling")]
+pub fn scope_name<I: 'static>(scope_name: &'static str) -> &'static str {
+    combined(std::any::type_name::<I>(), scope_name)
+}
+
+/// `"{identifier}::{scope_name}"`, built and leaked once.
+#[cfg(feature = "profiling")]
+fn combined(identifier: &'static str, scope_name: &'static str) -> &'static str {
+    use std::{
+        collections::HashMap,
+        sync::{Mutex, OnceLock, PoisonError},
+    };
+
+    type Names = HashMap<(&'static str, &'static str), &'static str>;
+    static NAMES: OnceLock<Mutex<Names>> = OnceLock::new();
+
+    let mut names = NAMES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    names
+        .entry((identifier, scope_name))
+        .or_insert_with(|| Box::leak(format!("{identifier}::{scope_name}").into_boxed_str()))
+}
+
+/// An open profiler scope, closed on drop.
+struct Scope {
+    #[cfg(feature = "profiling")]
+    open: Option<(&'static dyn Profiler, &'static str)>,
+}
+
+impl Scope {
+    #[cfg(feature = "profiling")]
+    fn enter(identifier: &'static str, name: &'static str) -> Self {
+        let open = PROFILER.get().map(|&profiler| {
+            let name = combined(identifier, name);
+            profiler.begin_scope(name);
+            (profiler, name)
+        });
+        Self { open }
+    }
+
+    #[cfg(not(feature = "profiling"))]
+    fn enter(_identifier: &'static str, _name: &'static str) -> Self {
+        Self {}
+    }
+}
+
+#[cfg(feature = "profiling")]
+impl Drop for Scope {
+    fn drop(&mut self) {
+        if let Some((profiler, name)) = self.open {
+            profiler.end_scope(name);
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Like [`lock`](Self::lock), but the time the guard is held is reported
+    /// to the profiler as a scope named `"{identifier}::{scope_name}"`. The
+    /// wait for the lock is not part of the scope.
+    pub fn lock_scoped(
+        &self,
+        permission: P,
+        scope_name: &'static str,
+    ) -> LockResult<ScopedGuard<'_, T, P, I>, DeadlockProofPoisonError<'_, T, P, I>> {
+        poison::map!(self.lock(permission), |guard| {
+            ScopedGuard(guard, Scope::enter(self.label(), scope_name))
+        })
+    }
+
+    /// The name [`lock_scoped`](Self::lock_scoped) reports a scope of this
+    /// mutex under, as [`scope_name`] does for unnamed mutexes.
+    #[cfg(feature = "profiling")]
+    pub fn scope_name(&self, scope_name: &'static str) -> &'static str {
+        combined(self.label(), scope_name)
+    }
+}
+
+/// A [`DeadlockProofMutexGuard`] whose critical section is a profiler scope.
+/// The scope closes once the mutex is released.
+pub struct ScopedGuard<'a, T, P: MutexPermission, I: 'static>(
+    DeadlockProofMutexGuard<'a, T, P, I>,
+    // Declared after the guard so it closes after the unlock.
+    #[allow(dead_code)] // only held for its drop
+    Scope,
+);
+
+impl<T, P: MutexPermission, I: 'static> ScopedGuard<'_, T, P, I> {
+    /// Unlock the mutex and return the permission token.
+    pub fn unlock(self) -> P {
+        self.0.unlock()
+    }
+
+    /// Unlock the mutex and return a sequential permission token.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        self.0.unlock_for_sequential()
+    }
+
+    /// Mutable access to the data, as [`DeadlockProofMutexGuard::get_mut`].
+    pub fn get_mut(&mut self) -> &mut T {
+        self.0.get_mut()
+    }
+
+    /// The [lineage](crate::lineage) tag of the permission this guard was
+    /// locked with.
+    #[cfg(feature = "metrics")]
+    pub fn tag(&self) -> Option<u64> {
+        self.0.tag()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Deref for ScopedGuard<'_, T, P, I> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DerefMut for ScopedGuard<'_, T, P, I> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.get_mut()
+    }
+}
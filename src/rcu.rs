@@ -0,0 +1,33 @@
+//! Read-copy-update over a mutex holding an `Arc`.
+//!
+//! Readers clone the `Arc` under the lock and read the value after releasing
+//! it; writers build a new value off to the side and swap it in. The lock is
+//! only ever held for a pointer copy, and readers never see a half-written
+//! value because the value itself is never written in place.
+
+use std::{mem, sync::Arc};
+
+use crate::{poison, version::Dirty, DeadlockProofMutex, MutexPermission};
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<Arc<T>, P, I> {
+    /// Locks, clones the `Arc`, and unlocks again.
+    ///
+    /// Poisoning is ignored: a panic elsewhere cannot have left the `Arc`
+    /// half-updated.
+    pub fn clone_inner(&self, permission: P) -> (P, Arc<T>) {
+        let guard = poison::ignore(self.acquire());
+        let inner = Arc::clone(&guard);
+        drop(guard);
+        (permission, inner)
+    }
+
+    /// Locks, replaces the `Arc` with `new`, and unlocks again, returning the
+    /// previous value. Readers that cloned it earlier keep it alive.
+    ///
+    /// Poisoning is ignored, as for [`clone_inner`](Self::clone_inner).
+    pub fn swap_inner(&self, permission: P, new: Arc<T>) -> (P, Arc<T>) {
+        let _dirty = Dirty::modified(&self.versions);
+        let previous = mem::replace(&mut *poison::ignore(self.acquire()), new);
+        (permission, previous)
+    }
+}
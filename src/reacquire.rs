@@ -0,0 +1,108 @@
+//! Going back to an earlier level of a sequential walk to hold it nested.
+//!
+//! A sequential walk releases each level before it takes the next. When a
+//! walk finds out half way that it needs an earlier level held after all,
+//! [`reacquire_nested`](SequentialMutexPermission::reacquire_nested) locks
+//! that level again with [`lock_for_nested`](DeadlockProofMutex::lock_for_nested)
+//! and hands back its guard plus the permission for the levels inside it, as
+//! if the walk had gone down the nested path from there.
+//!
+//! Only levels the walk has already passed can be reacquired. The position
+//! records every level it stepped past, so [`Follows`] holds,
+//! at compile time, for exactly those levels; everything the walk has not
+//! reached yet is rejected:
+//!
+//! ```
+//! use deadlock_proof::*;
+//!
+//! let stack = NetworkStack::new();
+//! let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+//! let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+//! let at_transport = device.unlock_for_sequential();
+//!
+//! // Back past the device layer to the IP layer.
+//! let (mut ip, inside) = at_transport.reacquire_nested(&stack.ip_layer).guard();
+//! ip.packets_processed += 1;
+//! let _permission: OuterMutexPermission = ip.unlock(inside);
+//! ```
+//!
+//! ```compile_fail
+//! use deadlock_proof::*;
+//!
+//! let stack = NetworkStack::new();
+//! let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+//! let at_device = ip.unlock_for_sequential();
+//! // The transport layer comes after the current position.
+//! let _ = at_device.reacquire_nested(&stack.transport_layer);
+//! ```
+//!
+//! ```compile_fail
+//! use deadlock_proof::*;
+//!
+//! let stack = NetworkStack::new();
+//! let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+//! let at_device = ip.unlock_for_sequential();
+//! // The device layer is the current position, not yet passed.
+//! let _ = at_device.reacquire_nested(&stack.device_layer);
+//! ```
+//!
+//! Positions in a [deep](crate::deep) hierarchy do not record their levels
+//! and cannot reacquire them.
+
+use std::marker::PhantomData;
+
+use crate::{DeadlockProofMutex, MutexPermission, NestedLockResult, SequentialMutexPermission};
+
+/// The level a [`Follows`] bound found is the one just passed.
+pub struct Here;
+
+/// The level a [`Follows`] bound found is further back than `N`.
+pub struct There<N>(PhantomData<N>);
+
+/// A position past the level `I`, which is locked with `P`. `Index` says how
+/// far back `I` is and is left to inference.
+///
+/// Implemented for sequential positions only; the trait is sealed.
+pub trait Follows<I: 'static, P: MutexPermission, Index>: crate::__private::SealedFollows {
+    /// Walks back to the position of `I`.
+    fn back_to(self) -> P;
+}
+
+impl<P: MutexPermission, I: 'static> crate::__private::SealedFollows
+    for SequentialMutexPermission<P, I>
+{
+}
+
+impl<P: MutexPermission, I: 'static> Follows<I, P, Here> for SequentialMutexPermission<P, I> {
+    fn back_to(self) -> P {
+        self.to_earlier()
+    }
+}
+
+impl<Q, J, P, I, N> Follows<I, P, There<N>> for SequentialMutexPermission<Q, J>
+where
+    Q: MutexPermission + Follows<I, P, N>,
+    J: 'static,
+    P: MutexPermission,
+    I: 'static,
+{
+    fn back_to(self) -> P {
+        self.to_earlier().back_to()
+    }
+}
+
+impl<Q: MutexPermission, J: 'static> SequentialMutexPermission<Q, J> {
+    /// Locks `mutex`, a level this walk has already passed, with
+    /// [`lock_for_nested`](DeadlockProofMutex::lock_for_nested). See the
+    /// [module docs](crate::reacquire).
+    #[track_caller]
+    pub fn reacquire_nested<T, P: MutexPermission, I: 'static, Index>(
+        self,
+        mutex: &DeadlockProofMutex<T, P, I>,
+    ) -> NestedLockResult<'_, T, P, I>
+    where
+        Self: Follows<I, P, Index>,
+    {
+        mutex.lock_for_nested(self.back_to())
+    }
+}
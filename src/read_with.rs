@@ -0,0 +1,81 @@
+//! Reads that borrow the permission instead of threading it through a
+//! guard.
+//!
+//! A read that only looks at the data and is done still has to move the
+//! permission into a guard and take it back out on unlock.
+//! [`DeadlockProofMutex::read_with`] and
+//! [`DeadlockProofRwLock::read_with`](crate::DeadlockProofRwLock::read_with)
+//! lock, run a closure on a shared reference to the data and unlock, with
+//! the permission only borrowed:
+//!
+//! ```
+//! use deadlock_proof::{poison, LockOutcome, NetworkStack, OuterMutexPermission};
+//!
+//! let stack = NetworkStack::new();
+//! let mut permission = OuterMutexPermission::get();
+//! let packets = stack.ip_layer.read_with(&mut permission, |ip| ip.packets_processed);
+//! assert_eq!(poison::into_inner(packets), 0);
+//! // Still at hand for the next lock.
+//! let _ip = stack.ip_layer.lock(permission).guard();
+//! ```
+//!
+//! This is as deadlock-proof as locking with the permission. A thread only
+//! has one permission to lock the mutex with, and it stays borrowed for the
+//! whole call. The permission to lock anything else is either that
+//! permission itself, or held by a guard that cannot give it up without
+//! that very permission. So the closure cannot acquire another
+//! deadlock-proof lock, and the read is as short as a lock with nothing
+//! nested inside. The borrow is mutable because a shared one would not be
+//! enough: the closure could capture the same reference and lock with it
+//! again, in whatever order it liked:
+//!
+//! ```compile_fail
+//! use deadlock_proof::{NetworkStack, OuterMutexPermission};
+//!
+//! let stack = NetworkStack::new();
+//! let mut permission = OuterMutexPermission::get();
+//! stack.ip_layer.read_with(&mut permission, |_| {
+//!     // The permission is still borrowed for the read.
+//!     stack.ip_layer.lock(permission)
+//! });
+//! ```
+//!
+//! The data of a poisoned mutex is still read, and the result comes back as
+//! the poison error, as a guard would with `lock`. Under `no-poison` the
+//! result is returned as is; [`poison::into_inner`](crate::poison::into_inner)
+//! takes it out of either.
+
+use std::{panic::Location, sync::PoisonError};
+
+use crate::{
+    permission, version, DeadlockProofMutex, Held, LockResult, MutexPermission, Priority,
+};
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Locks, runs `f` on the data and unlocks, blocking like
+    /// [`lock`](Self::lock) but leaving `permission` with the caller. See the
+    /// [module docs](crate::read_with) for why this needs no ordering of its
+    /// own.
+    #[track_caller]
+    pub fn read_with<R>(
+        &self,
+        permission: &mut P,
+        f: impl FnOnce(&T) -> R,
+    ) -> LockResult<R, PoisonError<R>> {
+        let location = Location::caller();
+        permission::check_origin(permission);
+        let tag = permission::tag_of(permission);
+        let read = |guard| {
+            let _dirty = version::Dirty::clean(&self.versions).at(location);
+            let _held = Held::new();
+            f(&self.inner_guard(guard, location))
+        };
+        #[cfg(not(feature = "no-poison"))]
+        return match self.acquire_prioritized(tag, Priority::Normal) {
+            Ok(guard) => Ok(read(guard)),
+            Err(poisoned) => Err(PoisonError::new(read(poisoned.into_inner()))),
+        };
+        #[cfg(feature = "no-poison")]
+        read(self.acquire_prioritized(tag, Priority::Normal))
+    }
+}
@@ -0,0 +1,119 @@
+//! Guards that can be stored in plain structs for the duration of a region.
+//!
+//! A guard's lifetime is that of the borrow of its mutex, so a context struct
+//! holding guards from several mutexes needs a lifetime per borrow. Inside
+//! [`with_region`] every guard can instead be re-tagged with the region's one
+//! brand lifetime `'r`, so the struct only ever needs `'r`:
+//!
+//! ```
+//! use deadlock_proof::{region::with_region, region::RegionGuard, *};
+//!
+//! struct Ctx<'r> {
+//!     ip: RegionGuard<'r, IpState, Position<IpLock>, IpLock>,
+//! }
+//!
+//! let stack = NetworkStack::new();
+//! let permission = with_region(|region| {
+//!     let mut ctx = Ctx { ip: region.hold(stack.ip_layer.lock(OuterMutexPermission::get()).guard()) };
+//!     ctx.ip.packets_processed += 1;
+//!     ctx.ip.unlock()
+//! });
+//! # let _ = permission;
+//! ```
+//!
+//! The brand is chosen by [`with_region`] itself, so no handle can outlive the
+//! closure. Returning one is rejected:
+//!
+//! ```compile_fail
+//! use deadlock_proof::{region::with_region, *};
+//!
+//! let stack = NetworkStack::new();
+//! let escaped = with_region(|region| {
+//!     region.hold(stack.ip_layer.lock(OuterMutexPermission::get()).guard())
+//! });
+//! ```
+//!
+//! and so is smuggling one out through a captured variable:
+//!
+//! ```compile_fail
+//! use deadlock_proof::{region::with_region, *};
+//!
+//! let stack = NetworkStack::new();
+//! let mut slot = None;
+//! with_region(|region| {
+//!     slot = Some(region.hold(stack.ip_layer.lock(OuterMutexPermission::get()).guard()));
+//! });
+//! ```
+
+use std::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{DeadlockProofMutexGuard, MutexPermission, SequentialMutexPermission};
+
+/// An invariant lifetime, so brands of different regions never unify.
+type Brand<'r> = PhantomData<fn(&'r ()) -> &'r ()>;
+
+/// A region in which guards borrowed for `'a` may be held as
+/// [`RegionGuard`]s tagged with the brand `'r`.
+pub struct Region<'r, 'a> {
+    _brand: Brand<'r>,
+    /// Implies `'a: 'r`, which is what makes re-tagging a guard sound.
+    _outlives: PhantomData<&'r &'a ()>,
+}
+
+/// Runs `f` with a fresh region. Guards of mutexes borrowed for `'a` can be
+/// held in it; whatever `f` returns cannot mention the region's brand.
+pub fn with_region<'a, R>(f: impl for<'r> FnOnce(Region<'r, 'a>) -> R) -> R {
+    f(Region {
+        _brand: PhantomData,
+        _outlives: PhantomData,
+    })
+}
+
+impl<'r, 'a> Region<'r, 'a> {
+    /// Re-tags `guard` with this region's brand.
+    pub fn hold<T, P: MutexPermission, I: 'static>(
+        &self,
+        guard: DeadlockProofMutexGuard<'a, T, P, I>,
+    ) -> RegionGuard<'r, T, P, I> {
+        RegionGuard {
+            guard,
+            _brand: PhantomData,
+        }
+    }
+}
+
+/// A [`DeadlockProofMutexGuard`] held in a region. Usable only inside the
+/// [`with_region`] closure that created it.
+pub struct RegionGuard<'r, T, P: MutexPermission, I: 'static> {
+    guard: DeadlockProofMutexGuard<'r, T, P, I>,
+    _brand: Brand<'r>,
+}
+
+impl<T, P: MutexPermission, I: 'static> RegionGuard<'_, T, P, I> {
+    /// Unlock the mutex and return the permission token.
+    pub fn unlock(self) -> P {
+        self.guard.unlock()
+    }
+
+    /// Unlock the mutex and return a sequential permission token.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        self.guard.unlock_for_sequential()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Deref for RegionGuard<'_, T, P, I> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DerefMut for RegionGuard<'_, T, P, I> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
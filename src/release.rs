@@ -0,0 +1,206 @@
+//! What a guard of a [`DeadlockProofMutex`] does as it unlocks.
+//!
+//! Holder tracking, the diff log and, in debug builds, the release
+//! invariant each have work to do right before a guard releases the
+//! lock. The guard keeps what they need next to its `MutexGuard`, along with
+//! one [`Bookkeeping`] byte saying which of them have anything to do for it.
+//! The byte is taken from the mutex's own when the guard is created, so an
+//! unlock with nothing to do tests that byte and releases the lock. The work
+//! itself is outlined into one cold function, out of the way of the unlock.
+//!
+//! Version bumps, change listeners and poison reports come after the unlock,
+//! in [`Dirty`](crate::version::Dirty), which funnels them the same way.
+
+#[cfg(any(feature = "diagnostics", feature = "diff-log", debug_assertions))]
+use std::marker::PhantomData;
+#[cfg(feature = "tracking")]
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::{
+    ops::{Deref, DerefMut},
+    panic::Location,
+    sync::MutexGuard,
+};
+
+use crate::{DeadlockProofMutex, MutexPermission};
+
+/// Which kinds of bookkeeping a mutex, or one of its guards, has to do on
+/// release.
+#[cfg(any(feature = "tracking", debug_assertions))]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Bookkeeping(u8);
+
+// Which of these are used depends on the features and the build.
+#[cfg(any(feature = "tracking", debug_assertions))]
+#[allow(dead_code)]
+impl Bookkeeping {
+    pub(crate) const NONE: Self = Self(0);
+    /// Recording the holder, under `diagnostics`.
+    pub(crate) const HOLDER: Self = Self(1);
+    /// Comparing the data with a snapshot, under `diff-log`.
+    pub(crate) const DIFF: Self = Self(1 << 1);
+    /// Checking the release invariant, in debug builds.
+    pub(crate) const INVARIANT: Self = Self(1 << 2);
+    /// Calling change listeners, after the unlock.
+    pub(crate) const LISTENERS: Self = Self(1 << 3);
+
+    pub(crate) fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// The part of it an [`InnerGuard`] does.
+    fn before_unlock(self) -> Self {
+        Self(self.0 & (Self::HOLDER.0 | Self::DIFF.0 | Self::INVARIANT.0))
+    }
+
+    pub(crate) fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// The [`Bookkeeping`] of one mutex. Set as the mutex is built, and as
+/// listeners are registered.
+#[cfg(feature = "tracking")]
+pub(crate) struct MutexBookkeeping(AtomicU8);
+
+#[cfg(feature = "tracking")]
+impl MutexBookkeeping {
+    pub(crate) const fn new() -> Self {
+        Self(AtomicU8::new(Bookkeeping::NONE.0))
+    }
+
+    pub(crate) fn get(&self) -> Bookkeeping {
+        Bookkeeping(self.0.load(Ordering::Acquire))
+    }
+
+    pub(crate) fn insert(&self, bookkeeping: Bookkeeping) {
+        self.0.fetch_or(bookkeeping.0, Ordering::AcqRel);
+    }
+}
+
+/// What every full guard holds on to its mutex with.
+pub(crate) struct InnerGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    #[cfg(any(feature = "diagnostics", feature = "diff-log", debug_assertions))]
+    pending: Pending<'a, T>,
+}
+
+/// What a guard has left to do before it unlocks.
+#[cfg(any(feature = "diagnostics", feature = "diff-log", debug_assertions))]
+struct Pending<'a, T> {
+    bookkeeping: Bookkeeping,
+    #[cfg(feature = "diagnostics")]
+    holding: crate::diagnostics::Holding<'a>,
+    #[cfg(feature = "diff-log")]
+    snapshot: Option<crate::diff_log::Snapshot<'a, T>>,
+    #[cfg(debug_assertions)]
+    check: Option<crate::invariant::Check<T>>,
+    _data: PhantomData<&'a T>,
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Wraps a freshly acquired inner guard for a guard acquired at
+    /// `location`, doing on acquisition whatever of the mutex's bookkeeping
+    /// has to: with `diff-log` this is where a logged mutex takes its
+    /// snapshot, with `diagnostics` where the guard records itself as the
+    /// holder, and in debug builds where it picks up the release invariant.
+    #[cfg_attr(
+        not(any(feature = "diff-log", feature = "diagnostics", debug_assertions)),
+        allow(unused_variables)
+    )]
+    pub(crate) fn inner_guard<'a>(
+        &'a self,
+        guard: MutexGuard<'a, T>,
+        location: &'static Location<'static>,
+    ) -> InnerGuard<'a, T> {
+        #[cfg(any(feature = "diagnostics", feature = "diff-log", debug_assertions))]
+        let pending = {
+            #[cfg(feature = "tracking")]
+            let bookkeeping = self.versions.bookkeeping.get().before_unlock();
+            // Only the invariant is left to check, and it says so below.
+            #[cfg(not(feature = "tracking"))]
+            let bookkeeping = Bookkeeping::NONE;
+            #[cfg(feature = "diagnostics")]
+            let holding = self.holder.record(location);
+            #[cfg(feature = "diff-log")]
+            let snapshot = self
+                .differ
+                .as_ref()
+                .map(|differ| differ.snapshot(&guard, self.label(), location));
+            #[cfg(debug_assertions)]
+            let check = self.release_invariant.check(self.label(), location);
+            #[cfg(debug_assertions)]
+            let bookkeeping = match check {
+                Some(_) => bookkeeping.with(Bookkeeping::INVARIANT),
+                None => bookkeeping,
+            };
+            Pending {
+                bookkeeping,
+                #[cfg(feature = "diagnostics")]
+                holding,
+                #[cfg(feature = "diff-log")]
+                snapshot,
+                #[cfg(debug_assertions)]
+                check,
+                _data: PhantomData,
+            }
+        };
+        InnerGuard {
+            guard,
+            #[cfg(any(feature = "diagnostics", feature = "diff-log", debug_assertions))]
+            pending,
+        }
+    }
+}
+
+impl<T> Deref for InnerGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for InnerGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+#[cfg(any(feature = "diagnostics", feature = "diff-log", debug_assertions))]
+impl<T> Drop for InnerGuard<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.pending.bookkeeping != Bookkeeping::NONE {
+            settle(&mut self.pending, &self.guard);
+        }
+    }
+}
+
+/// Does the bookkeeping of a guard about to unlock, with the data as its
+/// critical section left it: checks the invariant, clears the holder record
+/// and logs the diff. A violated invariant is reported once the record is
+/// cleared, so a panic it raises leaves no stale holder behind, and logs no
+/// diff.
+#[cfg(any(feature = "diagnostics", feature = "diff-log", debug_assertions))]
+#[cfg_attr(
+    not(any(feature = "diff-log", debug_assertions)),
+    allow(unused_variables)
+)]
+#[cold]
+#[inline(never)]
+fn settle<T>(pending: &mut Pending<'_, T>, data: &T) {
+    #[cfg(debug_assertions)]
+    let violation = pending.check.take().and_then(|check| check.violation(data));
+    #[cfg(feature = "diagnostics")]
+    if pending.bookkeeping.contains(Bookkeeping::HOLDER) {
+        pending.holding.clear();
+    }
+    #[cfg(debug_assertions)]
+    if let Some(event) = violation {
+        crate::invariant::report(event);
+    }
+    #[cfg(feature = "diff-log")]
+    if let Some(snapshot) = pending.snapshot.take() {
+        snapshot.compare(data);
+    }
+}
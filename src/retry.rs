@@ -0,0 +1,198 @@
+//! Optimistic retries with backoff, for flows that would rather come back
+//! later than wait for a lock.
+//!
+//! [`with_backoff`] tries the lock without waiting and runs the attempt on
+//! the data when it gets it. A miss, or an attempt returning
+//! [`ControlFlow::Continue`] because the data is not ready yet, unlocks,
+//! sleeps and tries again, each pause longer than the one before, until the
+//! attempt breaks or the [`BackoffPolicy`] runs out of attempts or time. The
+//! permission comes back in every case, and an attempt that panics hands it
+//! to [`MutexPermission::recover`] as it unwinds.
+//!
+//! ```
+//! use std::ops::ControlFlow;
+//!
+//! use deadlock_proof::{
+//!     retry::{with_backoff, BackoffPolicy},
+//!     DeadlockProofMutex, OuterMutexPermission,
+//! };
+//!
+//! struct InboxLock;
+//! let inbox = DeadlockProofMutex::new(vec![7u32], InboxLock);
+//!
+//! let (_permission, message) = with_backoff(
+//!     &inbox,
+//!     OuterMutexPermission::get(),
+//!     BackoffPolicy::default(),
+//!     |inbox| match inbox.pop() {
+//!         Some(message) => ControlFlow::Break(message),
+//!         None => ControlFlow::Continue(()),
+//!     },
+//! );
+//! assert_eq!(message, Some(7));
+//! ```
+
+use std::{
+    hash::{BuildHasher, RandomState},
+    ops::ControlFlow,
+    panic::Location,
+    time::{Duration, Instant},
+};
+
+use crate::{clock, rt::TryLockError, DeadlockProofMutex, DeadlockProofMutexGuard, MutexPermission};
+
+/// How [`with_backoff`] spaces out its attempts and when it gives up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackoffPolicy {
+    /// The pause after the first failed attempt.
+    pub initial: Duration,
+    /// The longest pause, however many attempts have failed.
+    pub max: Duration,
+    /// What each pause is multiplied by for the next one.
+    pub multiplier: u32,
+    /// Attempts before giving up, the first one included. `0` is taken as
+    /// `1`.
+    pub max_attempts: u32,
+    /// How long after the call no further attempt starts, if at all.
+    pub timeout: Option<Duration>,
+    /// Whether each pause is shortened by a random amount of up to half, so
+    /// that threads that missed together do not retry together.
+    pub jitter: bool,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_micros(10),
+            max: Duration::from_millis(10),
+            multiplier: 2,
+            max_attempts: 16,
+            timeout: None,
+            jitter: true,
+        }
+    }
+}
+
+/// Runs `attempt` on the data of `mutex` until it breaks, retrying with
+/// backoff as `policy` says. Returns the permission, and the value the
+/// attempt broke with unless the policy ran out first. See the
+/// [module docs](self).
+///
+/// Panics if the mutex is poisoned, with the permission recovered.
+#[track_caller]
+pub fn with_backoff<T, P: MutexPermission, I: 'static, R>(
+    mutex: &DeadlockProofMutex<T, P, I>,
+    permission: P,
+    policy: BackoffPolicy,
+    mut attempt: impl FnMut(&mut T) -> ControlFlow<R>,
+) -> (P, Option<R>) {
+    let location = Location::caller();
+    let deadline = policy.timeout.map(|timeout| clock::now() + timeout);
+    let mut jitter = Jitter::new(policy.jitter);
+    let mut pause = policy.initial;
+    let mut permission = Recovering(Some(permission));
+    for attempts in 1.. {
+        match mutex.try_guard_at(permission.take(), location) {
+            Ok(guard) => {
+                let mut guard = Recovering(Some(guard));
+                let flow = attempt(guard.get_mut());
+                permission = Recovering(Some(guard.take().unlock()));
+                if let ControlFlow::Break(value) = flow {
+                    return (permission.take(), Some(value));
+                }
+            }
+            Err(TryLockError::WouldBlock(returned)) => permission = Recovering(Some(returned)),
+            #[cfg(not(feature = "no-poison"))]
+            Err(TryLockError::Poisoned(returned)) => {
+                returned.recover();
+                panic!("retried a poisoned deadlock-proof mutex");
+            }
+        }
+        if attempts >= policy.max_attempts.max(1) {
+            break;
+        }
+        let sleep = jitter.shorten(pause);
+        if let Some(deadline) = deadline {
+            let Some(left) = deadline.checked_duration_since(clock::now()) else {
+                break;
+            };
+            if sleep >= left {
+                // The next attempt would start after the deadline.
+                break;
+            }
+        }
+        clock::sleep(sleep);
+        pause = pause.saturating_mul(policy.multiplier).min(policy.max);
+    }
+    (permission.take(), None)
+}
+
+/// Holds a permission, or a guard holding one, and recovers the permission
+/// if it is dropped on the way out of a panic.
+struct Recovering<G: Unlock>(Option<G>);
+
+impl<G: Unlock> Recovering<G> {
+    fn take(&mut self) -> G {
+        self.0.take().expect("taken once per attempt")
+    }
+}
+
+impl<'a, T, P: MutexPermission, I: 'static> Recovering<DeadlockProofMutexGuard<'a, T, P, I>> {
+    fn get_mut(&mut self) -> &mut T {
+        self.0.as_mut().expect("held for the attempt")
+    }
+}
+
+impl<G: Unlock> Drop for Recovering<G> {
+    fn drop(&mut self) {
+        if let Some(held) = self.0.take() {
+            held.into_permission().recover();
+        }
+    }
+}
+
+/// What [`Recovering`] can get a permission back from.
+trait Unlock {
+    type Permission: MutexPermission;
+
+    fn into_permission(self) -> Self::Permission;
+}
+
+impl<P: MutexPermission> Unlock for P {
+    type Permission = P;
+
+    fn into_permission(self) -> P {
+        self
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Unlock for DeadlockProofMutexGuard<'_, T, P, I> {
+    type Permission = P;
+
+    fn into_permission(self) -> P {
+        self.unlock()
+    }
+}
+
+/// xorshift64, seeded per call from the standard library's random hasher
+/// keys.
+struct Jitter(Option<u64>);
+
+impl Jitter {
+    fn new(enabled: bool) -> Self {
+        Self(enabled.then(|| RandomState::new().hash_one(Instant::now()) | 1))
+    }
+
+    /// `pause`, less a random amount of up to half of it.
+    fn shorten(&mut self, pause: Duration) -> Duration {
+        let Some(state) = &mut self.0 else {
+            return pause;
+        };
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        let nanos = u64::try_from(pause.as_nanos()).unwrap_or(u64::MAX);
+        let cut = *state % (nanos / 2 + 1);
+        pause - Duration::from_nanos(cut)
+    }
+}
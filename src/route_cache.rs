@@ -0,0 +1,245 @@
+//! An LRU of recent routing lookups in front of the IP layer.
+//!
+//! Hits are served under a shared read lock and never touch the IP layer.
+//! Misses release the cache, look the route up in the IP layer, and then
+//! retake the cache for writing to remember the answer. The cache and the
+//! IP layer are never held at the same time.
+//!
+//! The cache is not a level of the stack's hierarchy in front of
+//! [`IpLock`](crate::IpLock). It is the only level of a hierarchy of its own,
+//! [`RouteCacheLock`](crate::RouteCacheLock), rooted at the outer permission
+//! just like the IP layer. Placing it before `IpLock` would make every walk
+//! that locks the IP layer step through, or skip, the cache first. Since
+//! both take the outer permission, and locking one consumes it, holding one
+//! rules out locking the other, so neither order between them can be taken:
+//!
+//! ```compile_fail
+//! use deadlock_proof::*;
+//!
+//! let stack = NetworkStack::new();
+//! let permission = OuterMutexPermission::get();
+//! let cache = stack.route_cache.read(permission).guard();
+//! let ip = stack.ip_layer.lock(permission).guard();
+//! ```
+
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{LockOutcome, NetworkStack, OuterMutexPermission, Route, RouteError, WalkToken};
+
+/// How many lookups a [`RouteCache`] remembers.
+pub const ROUTE_CACHE_CAPACITY: usize = 64;
+
+struct CachedRoute {
+    next_hop: Option<Ipv4Addr>,
+    last_used: AtomicU64,
+}
+
+/// Recent lookups, keyed by destination address. Recency is tracked with
+/// atomics so hits can update it under a shared read lock.
+pub struct RouteCache {
+    entries: HashMap<Ipv4Addr, CachedRoute>,
+    clock: AtomicU64,
+    /// Routing table generation the entries were looked up against.
+    generation: u64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Hit and miss counts of a [`RouteCache`] since it was created.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RouteCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl RouteCache {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            clock: AtomicU64::new(0),
+            generation: 0,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> RouteCacheStats {
+        RouteCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// `Some(next_hop)` on a hit. Counts the hit or miss.
+    fn get(&self, addr: Ipv4Addr) -> Option<Option<Ipv4Addr>> {
+        match self.entries.get(&addr) {
+            Some(entry) => {
+                let now = self.clock.fetch_add(1, Ordering::Relaxed);
+                entry.last_used.store(now, Ordering::Relaxed);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.next_hop)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Remembers a lookup made against table `generation`. Lookups older than
+    /// the last invalidation are dropped, since their answer may be stale.
+    fn insert(&mut self, addr: Ipv4Addr, next_hop: Option<Ipv4Addr>, generation: u64) {
+        if generation < self.generation {
+            return;
+        }
+        if self.entries.len() >= ROUTE_CACHE_CAPACITY && !self.entries.contains_key(&addr) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed))
+                .map(|(addr, _)| *addr);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        self.entries.insert(
+            addr,
+            CachedRoute {
+                next_hop,
+                last_used: AtomicU64::new(now),
+            },
+        );
+    }
+
+    /// Forgets everything looked up before table `generation`.
+    fn invalidate(&mut self, generation: u64) {
+        self.entries.clear();
+        self.generation = self.generation.max(generation);
+    }
+}
+
+impl NetworkStack {
+    /// Compatibility shim running [`WalkToken::cached_route_lookup`] as a walk
+    /// of its own.
+    #[deprecated(note = "use `begin_walk` and `WalkToken::cached_route_lookup`")]
+    pub fn cached_route_lookup(
+        &self,
+        permission: OuterMutexPermission,
+        addr: Ipv4Addr,
+    ) -> (OuterMutexPermission, Option<Ipv4Addr>) {
+        let mut walk = self.begin_walk(permission);
+        let next_hop = walk.cached_route_lookup(addr);
+        (walk.finish(), next_hop)
+    }
+
+    /// Compatibility shim running [`WalkToken::add_route`] as a walk of its
+    /// own.
+    #[deprecated(note = "use `begin_walk` and `WalkToken::add_route`")]
+    pub fn add_route(
+        &self,
+        permission: OuterMutexPermission,
+        route: Route,
+    ) -> (OuterMutexPermission, Result<(), RouteError>) {
+        let mut walk = self.begin_walk(permission);
+        let added = walk.add_route(route);
+        (walk.finish(), added)
+    }
+
+    /// Compatibility shim running [`WalkToken::remove_route`] as a walk of its
+    /// own.
+    #[deprecated(note = "use `begin_walk` and `WalkToken::remove_route`")]
+    pub fn remove_route(
+        &self,
+        permission: OuterMutexPermission,
+        destination: Ipv4Addr,
+        prefix_len: u8,
+    ) -> (OuterMutexPermission, Option<Route>) {
+        let mut walk = self.begin_walk(permission);
+        let removed = walk.remove_route(destination, prefix_len);
+        (walk.finish(), removed)
+    }
+
+    fn invalidate_route_cache(
+        &self,
+        permission: OuterMutexPermission,
+        generation: u64,
+    ) -> OuterMutexPermission {
+        let mut cache = self.route_cache.write(permission).guard();
+        cache.invalidate(generation);
+        cache.unlock()
+    }
+}
+
+impl WalkToken<'_> {
+    /// Looks up the next hop for `addr`, serving repeated lookups from the
+    /// route cache.
+    ///
+    /// A lookup racing with [`add_route`](Self::add_route) or
+    /// [`remove_route`](Self::remove_route) may still return the old answer,
+    /// but no answer from before a change is cached after it.
+    ///
+    /// Panics if the cache or the IP layer is poisoned.
+    pub fn cached_route_lookup(&mut self, addr: Ipv4Addr) -> Option<Ipv4Addr> {
+        let stack = self.stack();
+        self.with_outer(|permission| {
+            let cache = stack.route_cache.read(permission).guard();
+            let hit = cache.get(addr);
+            let permission = cache.unlock();
+            if let Some(next_hop) = hit {
+                return (permission, next_hop);
+            }
+
+            let ip = stack.ip_layer.lock(permission).guard();
+            let next_hop = ip.routes.lookup(addr);
+            let generation = ip.routes.generation();
+            let permission = ip.unlock();
+
+            let mut cache = stack.route_cache.write(permission).guard();
+            cache.insert(addr, next_hop, generation);
+            (cache.unlock(), next_hop)
+        })
+    }
+
+    /// Adds `route` to the IP layer and invalidates the route cache. Fails
+    /// as [`RoutingTable::add`](crate::RoutingTable::add) does.
+    ///
+    /// Panics if the cache or the IP layer is poisoned.
+    pub fn add_route(&mut self, route: Route) -> Result<(), RouteError> {
+        let stack = self.stack();
+        self.with_outer(|permission| {
+            let mut ip = stack.ip_layer.lock(permission).guard();
+            let added = ip.routes.add(route);
+            ip.routing_table_size = ip.routes.len();
+            let generation = ip.routes.generation();
+            (stack.invalidate_route_cache(ip.unlock(), generation), added)
+        })
+    }
+
+    /// Removes the route to `destination/prefix_len` from the IP layer and
+    /// invalidates the route cache.
+    ///
+    /// Panics if the cache or the IP layer is poisoned.
+    pub fn remove_route(&mut self, destination: Ipv4Addr, prefix_len: u8) -> Option<Route> {
+        let stack = self.stack();
+        self.with_outer(|permission| {
+            let mut ip = stack.ip_layer.lock(permission).guard();
+            let removed = ip.routes.remove(destination, prefix_len);
+            ip.routing_table_size = ip.routes.len();
+            let generation = ip.routes.generation();
+            (stack.invalidate_route_cache(ip.unlock(), generation), removed)
+        })
+    }
+}
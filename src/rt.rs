@@ -0,0 +1,351 @@
+//! Sharing one mutex between real-time and ordinary threads.
+//!
+//! A real-time thread must never block for an unbounded time, so it should
+//! only ever try a lock, or wait for it with a deadline. Hand it an
+//! [`RtHandle`] instead of the mutex: the handle only has non-blocking and
+//! timed acquisitions, so a blocking `lock` in real-time code does not
+//! compile:
+//!
+//! ```compile_fail
+//! use deadlock_proof::*;
+//!
+//! let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+//! let rt = mutex.rt_handle();
+//! rt.lock(OuterMutexPermission::get());
+//! ```
+//!
+//! Ordinary threads use the mutex directly, which has the same
+//! [`try_lock`](DeadlockProofMutex::try_lock) and a
+//! [`try_lock_for_nested`](DeadlockProofMutex::try_lock_for_nested), or a
+//! [`BlockingHandle`] which dereferences to it and adds the timed
+//! acquisitions. Both handles are a `Copy` shared reference. A failed attempt hands the
+//! permission back in the [`TryLockError`], so the thread can go on with
+//! other work.
+
+#[cfg(feature = "tracking")]
+use std::sync::atomic::Ordering;
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::Deref,
+    panic::Location,
+    sync::{self, MutexGuard},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tracking")]
+use crate::ContentionEvent;
+use crate::{
+    clock, permission, version, DeadlockProofMutex, DeadlockProofMutexGuard,
+    DeadlockProofNestedMutexGuard, Held, MutexPermission, NestedMutexPermission,
+};
+
+/// Why a non-blocking or timed acquisition did not get the lock. Either way
+/// the permission comes back.
+#[non_exhaustive]
+pub enum TryLockError<P> {
+    /// Another guard holds the mutex.
+    WouldBlock(P),
+    /// The mutex is poisoned, as with [`DeadlockProofMutex::lock`].
+    ///
+    /// Unlike the [`DeadlockProofPoisonError`](crate::DeadlockProofPoisonError)
+    /// of a blocking `lock`, this holds no guard: the mutex is left unlocked,
+    /// so a walk can go back with the permission or
+    /// [`skip_poisoned`](crate::SequentialMutexPermission::skip_poisoned)
+    /// past the level without holding it. A `lock` with the same permission
+    /// still gets the data.
+    #[cfg(not(feature = "no-poison"))]
+    Poisoned(P),
+}
+
+impl<P> TryLockError<P> {
+    /// The permission that was passed in.
+    pub fn into_permission(self) -> P {
+        match self {
+            TryLockError::WouldBlock(permission) => permission,
+            #[cfg(not(feature = "no-poison"))]
+            TryLockError::Poisoned(permission) => permission,
+        }
+    }
+}
+
+impl<P> fmt::Debug for TryLockError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryLockError::WouldBlock(_) => f.write_str("WouldBlock(..)"),
+            #[cfg(not(feature = "no-poison"))]
+            TryLockError::Poisoned(_) => f.write_str("Poisoned(..)"),
+        }
+    }
+}
+
+impl<P> fmt::Display for TryLockError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryLockError::WouldBlock(_) => f.write_str("deadlock-proof mutex is locked"),
+            #[cfg(not(feature = "no-poison"))]
+            TryLockError::Poisoned(_) => f.write_str("deadlock-proof mutex is poisoned"),
+        }
+    }
+}
+
+impl<P> std::error::Error for TryLockError<P> {}
+
+/// Result of [`DeadlockProofMutex::try_lock_for_nested`]: the nested guard
+/// plus the token for the mutexes inside it, or the permission back.
+pub type TryNestedLockResult<'a, T, P, I> = Result<
+    (
+        DeadlockProofNestedMutexGuard<'a, T, P, I>,
+        NestedMutexPermission<P, I>,
+    ),
+    TryLockError<P>,
+>;
+
+/// Longest pause between two attempts of a timed acquisition.
+const MAX_PAUSE: Duration = Duration::from_micros(100);
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// The restricted handle for real-time code. See the [module docs](self).
+    pub fn rt_handle(&self) -> RtHandle<'_, T, P, I> {
+        RtHandle(self)
+    }
+
+    /// The handle for threads that may block, with the full API.
+    pub fn blocking_handle(&self) -> BlockingHandle<'_, T, P, I> {
+        BlockingHandle(self)
+    }
+
+    /// One attempt, never waiting.
+    #[track_caller]
+    pub(crate) fn try_guard(
+        &self,
+        permission: P,
+    ) -> Result<DeadlockProofMutexGuard<'_, T, P, I>, TryLockError<P>> {
+        self.try_guard_at(permission, Location::caller())
+    }
+
+    /// Takes the lock if it is free right now. A locked or poisoned mutex
+    /// hands the permission back in the [`TryLockError`], so the thread can
+    /// go on with other work, or lock something else.
+    #[track_caller]
+    pub fn try_lock(
+        &self,
+        permission: P,
+    ) -> Result<DeadlockProofMutexGuard<'_, T, P, I>, TryLockError<P>> {
+        self.try_guard(permission)
+    }
+
+    /// [`lock_for_nested`](Self::lock_for_nested) if the mutex is free right
+    /// now, handing the permission back as [`try_lock`](Self::try_lock)
+    /// does if it is not.
+    #[track_caller]
+    pub fn try_lock_for_nested(&self, permission: P) -> TryNestedLockResult<'_, T, P, I> {
+        let location = Location::caller();
+        let (guard, permission) = self.try_inner(permission)?;
+        let nested = NestedMutexPermission::new(&permission);
+        Ok((
+            DeadlockProofNestedMutexGuard(
+                self.inner_guard(guard, location),
+                permission,
+                PhantomData,
+                version::Dirty::clean(&self.versions).at(location),
+                Held::new(),
+            ),
+            nested,
+        ))
+    }
+
+    /// One attempt at the inner lock, keeping the permission either way.
+    fn try_inner(&self, permission: P) -> Result<(MutexGuard<'_, T>, P), TryLockError<P>> {
+        permission::check_origin(&permission);
+        #[cfg(feature = "test-util")]
+        if self.injected_contention.pending() {
+            return Err(TryLockError::WouldBlock(permission));
+        }
+        match self.inner.try_lock() {
+            Ok(guard) => Ok((guard, permission)),
+            Err(sync::TryLockError::WouldBlock) => Err(TryLockError::WouldBlock(permission)),
+            #[cfg(not(feature = "no-poison"))]
+            Err(sync::TryLockError::Poisoned(_)) => Err(TryLockError::Poisoned(permission)),
+            #[cfg(feature = "no-poison")]
+            Err(sync::TryLockError::Poisoned(poisoned)) => Ok((poisoned.into_inner(), permission)),
+        }
+    }
+
+    /// [`try_guard`](Self::try_guard) for a guard acquired at `location`.
+    pub(crate) fn try_guard_at(
+        &self,
+        permission: P,
+        location: &'static Location<'static>,
+    ) -> Result<DeadlockProofMutexGuard<'_, T, P, I>, TryLockError<P>> {
+        let (guard, permission) = self.try_inner(permission)?;
+        Ok(DeadlockProofMutexGuard(
+            self.inner_guard(guard, location),
+            permission,
+            PhantomData,
+            version::Dirty::clean(&self.versions).at(location),
+            Held::new(),
+        ))
+    }
+
+    /// Attempts until `deadline`, pausing a little longer after each miss.
+    /// Counts as a waiter meanwhile, and is reported to the contention
+    /// callback and the wait histogram as a blocking acquisition is, as far
+    /// as the features keep them.
+    #[track_caller]
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    pub(crate) fn try_guard_until(
+        &self,
+        permission: P,
+        deadline: Instant,
+    ) -> Result<DeadlockProofMutexGuard<'_, T, P, I>, TryLockError<P>> {
+        let started = clock::now();
+        let tag = permission::tag_of(&permission);
+        #[cfg(feature = "tracking")]
+        self.waiters.fetch_add(1, Ordering::Relaxed);
+        let result = match self.try_guard(permission) {
+            #[cfg(feature = "tracking")]
+            Err(TryLockError::WouldBlock(permission)) => {
+                let callback = self.contention.callback();
+                if let Some(callback) = callback {
+                    callback(ContentionEvent::WillBlock {
+                        identifier: self.label(),
+                        waiters: self.waiters(),
+                    });
+                }
+                let result = self.retry_until(permission, deadline);
+                if let (Some(callback), Ok(_)) = (callback, &result) {
+                    callback(ContentionEvent::Acquired {
+                        identifier: self.label(),
+                        waited: clock::now() - started,
+                    });
+                }
+                result
+            }
+            #[cfg(not(feature = "tracking"))]
+            Err(TryLockError::WouldBlock(permission)) => self.retry_until(permission, deadline),
+            result => result,
+        };
+        #[cfg(feature = "tracking")]
+        self.waiters.fetch_sub(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        self.record_wait(clock::now() - started, tag);
+        result
+    }
+
+    /// The attempts of [`try_guard_until`](Self::try_guard_until) after the
+    /// first one missed.
+    #[track_caller]
+    fn retry_until(
+        &self,
+        mut permission: P,
+        deadline: Instant,
+    ) -> Result<DeadlockProofMutexGuard<'_, T, P, I>, TryLockError<P>> {
+        let mut pause = Duration::from_micros(1);
+        loop {
+            let now = clock::now();
+            if now >= deadline {
+                return Err(TryLockError::WouldBlock(permission));
+            }
+            clock::sleep(pause.min(deadline - now));
+            pause = (pause * 2).min(MAX_PAUSE);
+            match self.try_guard(permission) {
+                Err(TryLockError::WouldBlock(returned)) => permission = returned,
+                result => return result,
+            }
+        }
+    }
+}
+
+/// The non-blocking and timed acquisitions, shared by both handles.
+macro_rules! try_acquisitions {
+    () => {
+        /// Takes the lock if it is free right now.
+        #[track_caller]
+        pub fn try_lock(
+            &self,
+            permission: P,
+        ) -> Result<DeadlockProofMutexGuard<'a, T, P, I>, TryLockError<P>> {
+            self.0.try_guard(permission)
+        }
+
+        /// Waits for the lock for at most `timeout`.
+        #[track_caller]
+        pub fn try_lock_for(
+            &self,
+            permission: P,
+            timeout: Duration,
+        ) -> Result<DeadlockProofMutexGuard<'a, T, P, I>, TryLockError<P>> {
+            self.0.try_guard_until(permission, clock::now() + timeout)
+        }
+
+        /// Waits for the lock until `deadline` at the latest.
+        #[track_caller]
+        pub fn try_lock_until(
+            &self,
+            permission: P,
+            deadline: Instant,
+        ) -> Result<DeadlockProofMutexGuard<'a, T, P, I>, TryLockError<P>> {
+            self.0.try_guard_until(permission, deadline)
+        }
+    };
+}
+
+/// A mutex as seen by real-time code: it can be tried and waited for with a
+/// deadline, but never locked unboundedly. Created by
+/// [`DeadlockProofMutex::rt_handle`].
+pub struct RtHandle<'a, T, P: MutexPermission, I: 'static>(&'a DeadlockProofMutex<T, P, I>);
+
+impl<'a, T, P: MutexPermission, I: 'static> RtHandle<'a, T, P, I> {
+    try_acquisitions!();
+
+    /// As [`DeadlockProofMutex::is_locked`].
+    pub fn is_locked(&self) -> bool {
+        self.0.is_locked()
+    }
+
+    /// As [`DeadlockProofMutex::waiters`].
+    #[cfg(feature = "tracking")]
+    pub fn waiters(&self) -> usize {
+        self.0.waiters()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Clone for RtHandle<'_, T, P, I> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Copy for RtHandle<'_, T, P, I> {}
+
+/// A mutex as seen by threads that may block. Dereferences to the mutex, so
+/// the whole API is there, plus the acquisitions of [`RtHandle`]. Created by
+/// [`DeadlockProofMutex::blocking_handle`].
+pub struct BlockingHandle<'a, T, P: MutexPermission, I: 'static>(&'a DeadlockProofMutex<T, P, I>);
+
+impl<'a, T, P: MutexPermission, I: 'static> BlockingHandle<'a, T, P, I> {
+    try_acquisitions!();
+
+    /// The restricted handle to the same mutex, for passing on to real-time
+    /// code.
+    pub fn rt_handle(&self) -> RtHandle<'a, T, P, I> {
+        self.0.rt_handle()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Deref for BlockingHandle<'_, T, P, I> {
+    type Target = DeadlockProofMutex<T, P, I>;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Clone for BlockingHandle<'_, T, P, I> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Copy for BlockingHandle<'_, T, P, I> {}
@@ -0,0 +1,419 @@
+//! A reader-writer counterpart to [`DeadlockProofMutex`](crate::DeadlockProofMutex).
+//!
+//! Both read and write access consume the permission, exactly like a mutex
+//! lock, so the ordering guarantees are the same. Readers on different
+//! threads still share the lock.
+//!
+//! Which waiters go first when readers and writers contend is the lock's
+//! [`RwLockPolicy`], chosen at construction with
+//! [`with_policy`](DeadlockProofRwLock::with_policy). The lock keeps its own
+//! state rather than wrap `std::sync::RwLock`, whose policy is the
+//! platform's. Every policy takes the same permissions: a thread never waits
+//! for a read it holds itself, since the permission it would need is inside
+//! the guard, so no policy can deadlock a reader against its own writer.
+//!
+//! ```
+//! use deadlock_proof::{rwlock::RwLockPolicy, DeadlockProofRwLock, LockOutcome, OuterMutexPermission};
+//!
+//! struct RoutesLock;
+//!
+//! let routes = DeadlockProofRwLock::<_, OuterMutexPermission, _>::with_policy(
+//!     vec![10u32],
+//!     RoutesLock,
+//!     RwLockPolicy::ReaderPref,
+//! );
+//! let table = routes.read(OuterMutexPermission::get()).guard();
+//! assert_eq!(*table, [10]);
+//! assert_eq!(routes.policy(), RwLockPolicy::ReaderPref);
+//! ```
+
+use std::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+};
+
+use crate::{
+    depth::DepthCheck, permission, poison, Held, LockResult, MutexPermission,
+    PermissionSyncSendWrapper, SequentialMutexPermission,
+};
+
+/// Who a [`DeadlockProofRwLock`] lets in first when readers and writers are
+/// both waiting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RwLockPolicy {
+    /// A reader gets in whenever no writer holds the lock, even with writers
+    /// waiting. Readers never wait for one another, but overlapping readers
+    /// that keep coming starve the writers.
+    ReaderPref,
+    /// A waiting writer holds back the readers that come after it, so a
+    /// writer only waits for the readers already in and the writers ahead
+    /// of it. Writers that keep coming starve the readers. What
+    /// [`new`](DeadlockProofRwLock::new) picks, as `std::sync::RwLock` works
+    /// on most platforms.
+    #[default]
+    WriterPref,
+    /// A waiting writer holds back later readers, as with
+    /// [`WriterPref`](Self::WriterPref), and a writer letting go lets in
+    /// every reader waiting at that moment before the next writer. Readers
+    /// wait out at most one writer, and a writer waits for at most one batch
+    /// of readers per writer ahead of it, so neither side starves.
+    Fair,
+}
+
+/// What the lock is doing, and who is waiting for it.
+#[derive(Default)]
+struct State {
+    readers: usize,
+    writer: bool,
+    waiting_readers: usize,
+    waiting_writers: usize,
+    /// How many times a writer has let go. A reader waiting across one is
+    /// one of the batch [`Fair`](RwLockPolicy::Fair) lets in next.
+    releases: u64,
+    /// The readers of that batch not in yet.
+    released_readers: usize,
+}
+
+impl State {
+    /// Whether a reader that arrived after `arrived` releases gets in.
+    fn admits_reader(&self, policy: RwLockPolicy, arrived: u64) -> bool {
+        !self.writer
+            && match policy {
+                RwLockPolicy::ReaderPref => true,
+                RwLockPolicy::WriterPref => self.waiting_writers == 0,
+                RwLockPolicy::Fair => self.waiting_writers == 0 || arrived < self.releases,
+            }
+    }
+
+    fn admits_writer(&self, policy: RwLockPolicy) -> bool {
+        !self.writer
+            && self.readers == 0
+            && (policy != RwLockPolicy::Fair || self.released_readers == 0)
+    }
+}
+
+/// The lock itself: its state, one wait queue for readers and one for
+/// writers, and the data. Poisoned by a panic under a write guard, as
+/// `std::sync::RwLock` is.
+pub(crate) struct RawRwLock<T> {
+    state: Mutex<State>,
+    readers: Condvar,
+    writers: Condvar,
+    policy: RwLockPolicy,
+    poisoned: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+/// Safety: the state only hands out shared access to readers, and exclusive
+/// access to a single writer, as `std::sync::RwLock` does.
+unsafe impl<T: Send> Send for RawRwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RawRwLock<T> {}
+
+impl<T> RawRwLock<T> {
+    fn new(content: T, policy: RwLockPolicy) -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+            readers: Condvar::new(),
+            writers: Condvar::new(),
+            policy,
+            poisoned: AtomicBool::new(false),
+            data: UnsafeCell::new(content),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // Nothing runs under this lock that could panic.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// `guard`, in an error if a writer panicked.
+    fn checked<G>(&self, guard: G) -> Result<G, PoisonError<G>> {
+        match self.poisoned.load(Ordering::Acquire) {
+            true => Err(PoisonError::new(guard)),
+            false => Ok(guard),
+        }
+    }
+
+    pub(crate) fn read(&self) -> Result<RawReadGuard<'_, T>, PoisonError<RawReadGuard<'_, T>>> {
+        let mut state = self.state();
+        let arrived = state.releases;
+        if !state.admits_reader(self.policy, arrived) {
+            state.waiting_readers += 1;
+            while !state.admits_reader(self.policy, arrived) {
+                state = self.readers.wait(state).unwrap_or_else(PoisonError::into_inner);
+            }
+            state.waiting_readers -= 1;
+            if arrived < state.releases {
+                state.released_readers -= 1;
+            }
+        }
+        state.readers += 1;
+        drop(state);
+        self.checked(RawReadGuard(self, PhantomData))
+    }
+
+    pub(crate) fn write(&self) -> Result<RawWriteGuard<'_, T>, PoisonError<RawWriteGuard<'_, T>>> {
+        let mut state = self.state();
+        if !state.admits_writer(self.policy) {
+            state.waiting_writers += 1;
+            while !state.admits_writer(self.policy) {
+                state = self.writers.wait(state).unwrap_or_else(PoisonError::into_inner);
+            }
+            state.waiting_writers -= 1;
+        }
+        state.writer = true;
+        drop(state);
+        self.checked(RawWriteGuard(self, thread::panicking(), PhantomData))
+    }
+
+    /// Whether nobody holds the lock.
+    pub(crate) fn is_idle(&self) -> bool {
+        let state = self.state();
+        !state.writer && state.readers == 0
+    }
+
+    pub(crate) fn into_inner(self) -> Result<T, PoisonError<T>> {
+        let poisoned = self.poisoned.load(Ordering::Acquire);
+        let content = self.data.into_inner();
+        match poisoned {
+            true => Err(PoisonError::new(content)),
+            false => Ok(content),
+        }
+    }
+}
+
+/// Shared access to a [`RawRwLock`]. Like `std::sync::RwLockReadGuard`, not
+/// `Send`, and `Sync` with the data.
+pub(crate) struct RawReadGuard<'a, T>(&'a RawRwLock<T>, PhantomData<*const ()>);
+
+/// Safety: a shared guard only hands out `&T`.
+unsafe impl<T: Sync> Sync for RawReadGuard<'_, T> {}
+
+impl<T> Deref for RawReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: no writer is in while this reader is.
+        unsafe { &*self.0.data.get() }
+    }
+}
+
+impl<T> Drop for RawReadGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.0.state();
+        state.readers -= 1;
+        if state.readers == 0 {
+            self.0.writers.notify_all();
+        }
+    }
+}
+
+/// Exclusive access to a [`RawRwLock`], with whether the thread was already
+/// panicking when it got in.
+pub(crate) struct RawWriteGuard<'a, T>(&'a RawRwLock<T>, bool, PhantomData<*const ()>);
+
+/// Safety: a shared borrow of the guard only hands out `&T`.
+unsafe impl<T: Sync> Sync for RawWriteGuard<'_, T> {}
+
+impl<T> Deref for RawWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: this writer is the only one in.
+        unsafe { &*self.0.data.get() }
+    }
+}
+
+impl<T> DerefMut for RawWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: this writer is the only one in.
+        unsafe { &mut *self.0.data.get() }
+    }
+}
+
+impl<T> Drop for RawWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        if !self.1 && thread::panicking() {
+            self.0.poisoned.store(true, Ordering::Release);
+        }
+        let mut state = self.0.state();
+        state.writer = false;
+        state.releases += 1;
+        state.released_readers = state.waiting_readers;
+        drop(state);
+        self.0.readers.notify_all();
+        self.0.writers.notify_all();
+    }
+}
+
+/// A reader-writer lock which is compile-time guaranteed not to deadlock.
+pub struct DeadlockProofRwLock<T, P: MutexPermission, I: 'static> {
+    pub(crate) inner: RawRwLock<T>,
+    _permission: PhantomData<PermissionSyncSendWrapper<P>>,
+    _identifier: PhantomData<I>,
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofRwLock<T, P, I> {
+    /// Create a new deadlock-proof reader-writer lock, with the default
+    /// [`RwLockPolicy`].
+    pub fn new(content: T, identifier: I) -> Self {
+        Self::with_policy(content, identifier, RwLockPolicy::default())
+    }
+
+    /// [`new`](Self::new), letting waiters in by `policy`.
+    pub fn with_policy(content: T, _identifier: I, policy: RwLockPolicy) -> Self {
+        let () = DepthCheck::<P>::WITHIN_MAX;
+        Self {
+            inner: RawRwLock::new(content, policy),
+            _permission: PhantomData,
+            _identifier: PhantomData,
+        }
+    }
+
+    /// The policy the lock was created with.
+    pub fn policy(&self) -> RwLockPolicy {
+        self.inner.policy
+    }
+
+    /// Acquires shared access, blocking while a writer holds the lock.
+    pub fn read(
+        &self,
+        permission: P,
+    ) -> RwLockResult<DeadlockProofReadGuard<'_, T, P, I>> {
+        permission::check_origin(&permission);
+        let result = self.inner.read();
+        #[cfg(feature = "no-poison")]
+        let result = result.unwrap_or_else(PoisonError::into_inner);
+        poison::map_poisoned(result, |guard| DeadlockProofReadGuard(
+            guard,
+            permission,
+            PhantomData,
+            Held::new()
+        ))
+    }
+
+    /// Takes shared access, runs `f` on the data and releases it, blocking
+    /// like [`read`](Self::read) but leaving `permission` with the caller.
+    /// Readers on other threads still share the lock. See
+    /// [`read_with`](crate::read_with) for why this needs no ordering of its
+    /// own.
+    pub fn read_with<R>(
+        &self,
+        permission: &mut P,
+        f: impl FnOnce(&T) -> R,
+    ) -> LockResult<R, PoisonError<R>> {
+        permission::check_origin(permission);
+        let result = self.inner.read();
+        #[cfg(not(feature = "no-poison"))]
+        return match result {
+            Ok(guard) => Ok(f(&guard)),
+            Err(poisoned) => Err(PoisonError::new(f(&poisoned.into_inner()))),
+        };
+        #[cfg(feature = "no-poison")]
+        f(&result.unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Acquires exclusive access, blocking while anyone else holds the lock.
+    pub fn write(
+        &self,
+        permission: P,
+    ) -> RwLockResult<DeadlockProofWriteGuard<'_, T, P, I>> {
+        permission::check_origin(&permission);
+        let result = self.inner.write();
+        #[cfg(feature = "no-poison")]
+        let result = result.unwrap_or_else(PoisonError::into_inner);
+        poison::map_poisoned(result, |guard| DeadlockProofWriteGuard(
+            guard,
+            permission,
+            PhantomData,
+            Held::new()
+        ))
+    }
+}
+
+/// Result of [`DeadlockProofRwLock::read`] and
+/// [`write`](DeadlockProofRwLock::write). A lock poisoned by a writer still
+/// hands out the guard, inside the error.
+pub type RwLockResult<G> = LockResult<G, PoisonError<G>>;
+
+/// Shared access to a [`DeadlockProofRwLock`].
+pub struct DeadlockProofReadGuard<'a, T, P: MutexPermission, I: 'static>(
+    RawReadGuard<'a, T>,
+    P,
+    PhantomData<I>,
+    Held,
+);
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofReadGuard<'_, T, P, I> {
+    /// Unlock and return the permission token.
+    pub fn unlock(self) -> P {
+        self.1
+    }
+
+    /// Unlock and return a sequential permission token.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        SequentialMutexPermission::new(self.1)
+    }
+
+    /// The [lineage](crate::lineage) tag of the permission this guard was
+    /// locked with.
+    #[cfg(feature = "metrics")]
+    pub fn tag(&self) -> Option<u64> {
+        self.1.tag()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Deref for DeadlockProofReadGuard<'_, T, P, I> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.0.deref()
+    }
+}
+
+/// Exclusive access to a [`DeadlockProofRwLock`].
+pub struct DeadlockProofWriteGuard<'a, T, P: MutexPermission, I: 'static>(
+    RawWriteGuard<'a, T>,
+    P,
+    PhantomData<I>,
+    Held,
+);
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofWriteGuard<'_, T, P, I> {
+    /// Unlock and return the permission token.
+    pub fn unlock(self) -> P {
+        self.1
+    }
+
+    /// Unlock and return a sequential permission token.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        SequentialMutexPermission::new(self.1)
+    }
+
+    /// The [lineage](crate::lineage) tag of the permission this guard was
+    /// locked with.
+    #[cfg(feature = "metrics")]
+    pub fn tag(&self) -> Option<u64> {
+        self.1.tag()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Deref for DeadlockProofWriteGuard<'_, T, P, I> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.0.deref()
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DerefMut for DeadlockProofWriteGuard<'_, T, P, I> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.0.deref_mut()
+    }
+}
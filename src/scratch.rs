@@ -0,0 +1,100 @@
+//! A reusable scratch value kept inside a mutex, next to its data.
+//!
+//! A critical section that needs a temporary buffer every time, say for
+//! parsing headers, can keep one in the mutex instead of allocating it anew.
+//! [`DeadlockProofMutex::new_with_scratch`] stores a default `S` alongside
+//! the data in a [`WithScratch`], and guards of such a mutex reach it with
+//! `scratch`. The scratch is behind the same lock as the data, so it needs no
+//! synchronization of its own, and it stays there between acquisitions, with
+//! whatever capacity it grew to.
+//!
+//! ```
+//! use deadlock_proof::{DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+//!
+//! struct Parser;
+//! let packets = DeadlockProofMutex::new_with_scratch::<Vec<u8>>(0u64, Parser);
+//!
+//! let mut guard = packets.lock(OuterMutexPermission::get()).guard();
+//! let (parsed, scratch) = guard.data_and_scratch();
+//! scratch.clear();
+//! scratch.extend_from_slice(b"\x45\x00");
+//! *parsed += 1;
+//! assert_eq!(**guard, 1);
+//! ```
+//!
+//! The scratch is not part of `T`: the guards dereference to a
+//! [`WithScratch`], which dereferences to the data, and only touching the
+//! data counts as a modification for the mutex's version.
+
+use std::ops::{Deref, DerefMut};
+
+use crate::{
+    DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard, MutexPermission,
+};
+
+/// The data of a mutex created by [`DeadlockProofMutex::new_with_scratch`],
+/// with its scratch value. Dereferences to the data.
+pub struct WithScratch<T, S> {
+    data: T,
+    scratch: S,
+}
+
+impl<T, S> WithScratch<T, S> {
+    /// The data, without the scratch.
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+}
+
+impl<T, S> Deref for WithScratch<T, S> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.data
+    }
+}
+
+impl<T, S> DerefMut for WithScratch<T, S> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Creates a mutex holding `content` and a default scratch `S`. See the
+    /// [module docs](crate::scratch).
+    pub fn new_with_scratch<S: Default>(
+        content: T,
+        identifier: I,
+    ) -> DeadlockProofMutex<WithScratch<T, S>, P, I> {
+        DeadlockProofMutex::new(
+            WithScratch {
+                data: content,
+                scratch: S::default(),
+            },
+            identifier,
+        )
+    }
+}
+
+/// Scratch access for both guard types.
+macro_rules! scratch_access {
+    ($guard:ident) => {
+        impl<T, S, P: MutexPermission, I: 'static> $guard<'_, WithScratch<T, S>, P, I> {
+            /// The scratch value. Using it leaves the mutex's version alone.
+            pub fn scratch(&mut self) -> &mut S {
+                &mut self.0.scratch
+            }
+
+            /// The data and the scratch value at once. Counts as a
+            /// modification, like [`get_mut`](Self::get_mut).
+            pub fn data_and_scratch(&mut self) -> (&mut T, &mut S) {
+                let WithScratch { data, scratch } = self.get_mut();
+                (data, scratch)
+            }
+        }
+    };
+}
+
+scratch_access!(DeadlockProofMutexGuard);
+scratch_access!(DeadlockProofNestedMutexGuard);
@@ -0,0 +1,200 @@
+//! Typestate handles for state machines kept under a lock, such as the
+//! lifecycle of a TCP connection.
+//!
+//! A [`StateCell`] stores the state as a plain enum, so the data it sits in
+//! can still be cloned, compared and snapshotted like any other. Changing
+//! the state goes through a typed [`Handle`]: [`StateCell::handle`] hands
+//! out a `Handle<'_, S>` only while the cell is in state `S`, and each legal
+//! transition is a method on the handle of its source state that consumes
+//! it and returns the handle of the target. A transition the state machine
+//! does not have is a method that does not exist, so it does not compile.
+//! Other state machines declare their states with [`State`] and their
+//! transitions with [`Transition`], and move with
+//! [`Handle::transition`].
+//!
+//! The handle borrows the cell mutably, and the cell is reached through a
+//! guard, so no handle outlives the critical section it was obtained in.
+//! The sockets of the [`NetworkStack`](crate::NetworkStack)'s transport
+//! layer work this way, through
+//! [`socket_handle`](crate::DeadlockProofMutexGuard::socket_handle):
+//!
+//! ```
+//! use deadlock_proof::{
+//!     session::{Closed, Established, Listen, TcpState},
+//!     LockOutcome, NetworkStack, OuterMutexPermission, TcpSocket,
+//! };
+//!
+//! let stack = NetworkStack::new();
+//! let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+//! let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+//! let mut transport = stack.transport_layer.lock(device.unlock_for_sequential()).guard();
+//! transport.sockets.insert(80, TcpSocket::listening());
+//!
+//! let listening = transport.socket_handle::<Listen>(80).unwrap();
+//! listening.accept().establish();
+//! assert!(transport.socket_handle::<Listen>(80).is_none());
+//! transport.socket_handle::<Established>(80).unwrap().close();
+//! assert_eq!(transport.sockets[&80].state.get(), TcpState::Closed);
+//! assert!(transport.socket_handle::<Closed>(80).is_some());
+//! ```
+//!
+//! Skipping a state does not compile:
+//!
+//! ```compile_fail
+//! # use deadlock_proof::{session::Listen, LockOutcome, NetworkStack, OuterMutexPermission};
+//! # let stack = NetworkStack::new();
+//! # let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+//! # let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+//! # let mut transport = stack.transport_layer.lock(device.unlock_for_sequential()).guard();
+//! let listening = transport.socket_handle::<Listen>(80).unwrap();
+//! listening.establish();
+//! ```
+//!
+//! Nor does keeping a handle past its guard:
+//!
+//! ```compile_fail
+//! # use deadlock_proof::{session::Listen, LockOutcome, NetworkStack, OuterMutexPermission};
+//! # let stack = NetworkStack::new();
+//! # let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+//! # let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+//! # let mut transport = stack.transport_layer.lock(device.unlock_for_sequential()).guard();
+//! let listening = transport.socket_handle::<Listen>(80).unwrap();
+//! let _permission = transport.unlock();
+//! listening.accept();
+//! ```
+
+use std::marker::PhantomData;
+
+use crate::{DeadlockProofMutexGuard, MutexPermission, TransportState};
+
+/// A state machine's current state, stored as the plain enum `M`. See the
+/// [module docs](self).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StateCell<M>(M);
+
+impl<M: Copy + Eq> StateCell<M> {
+    pub fn new(state: M) -> Self {
+        Self(state)
+    }
+
+    /// The current state.
+    pub fn get(&self) -> M {
+        self.0
+    }
+
+    /// The handle for state `S`, if the cell is in it.
+    pub fn handle<S: State<Machine = M>>(&mut self) -> Option<Handle<'_, S>> {
+        (self.0 == S::STATE).then_some(Handle {
+            cell: self,
+            _state: PhantomData,
+        })
+    }
+}
+
+/// A type standing for one state of the state machine `Machine`.
+pub trait State {
+    type Machine: Copy + Eq;
+    /// The value stored for this state.
+    const STATE: Self::Machine;
+}
+
+/// Declares that the state machine may go from state `Self` to state `T`.
+pub trait Transition<T: State>: State<Machine = T::Machine> {}
+
+/// A [`StateCell`] known to be in state `S`, borrowed for as long as the
+/// guard it was reached through.
+pub struct Handle<'a, S: State> {
+    cell: &'a mut StateCell<S::Machine>,
+    _state: PhantomData<S>,
+}
+
+impl<'a, S: State> Handle<'a, S> {
+    /// Moves the cell on to state `T`.
+    pub fn transition<T: State>(self) -> Handle<'a, T>
+    where
+        S: Transition<T>,
+    {
+        self.cell.0 = T::STATE;
+        Handle {
+            cell: self.cell,
+            _state: PhantomData,
+        }
+    }
+}
+
+/// The lifecycle of a TCP connection, as stored in a [`StateCell`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TcpState {
+    Listen,
+    SynReceived,
+    Established,
+    Closed,
+}
+
+/// Waiting for a connection request.
+pub struct Listen;
+/// A SYN arrived and was answered.
+pub struct SynReceived;
+/// The handshake completed.
+pub struct Established;
+/// The connection is over.
+pub struct Closed;
+
+macro_rules! tcp_states {
+    ($($state:ident),+) => {
+        $(impl State for $state {
+            type Machine = TcpState;
+            const STATE: TcpState = TcpState::$state;
+        })+
+    };
+}
+
+tcp_states!(Listen, SynReceived, Established, Closed);
+
+impl Transition<SynReceived> for Listen {}
+impl Transition<Closed> for Listen {}
+impl Transition<Established> for SynReceived {}
+impl Transition<Closed> for SynReceived {}
+impl Transition<Closed> for Established {}
+
+impl<'a> Handle<'a, Listen> {
+    /// A SYN arrived.
+    pub fn accept(self) -> Handle<'a, SynReceived> {
+        self.transition()
+    }
+
+    /// Stops listening.
+    pub fn close(self) -> Handle<'a, Closed> {
+        self.transition()
+    }
+}
+
+impl<'a> Handle<'a, SynReceived> {
+    /// The peer acknowledged the SYN-ACK.
+    pub fn establish(self) -> Handle<'a, Established> {
+        self.transition()
+    }
+
+    /// The handshake was reset.
+    pub fn reset(self) -> Handle<'a, Closed> {
+        self.transition()
+    }
+}
+
+impl<'a> Handle<'a, Established> {
+    pub fn close(self) -> Handle<'a, Closed> {
+        self.transition()
+    }
+}
+
+impl<P: MutexPermission, I: 'static> DeadlockProofMutexGuard<'_, TransportState, P, I> {
+    /// The handle of the socket on `port`, if there is one and it is in
+    /// state `S`. Counts as a modification, like
+    /// [`get_mut`](Self::get_mut).
+    pub fn socket_handle<S: State<Machine = TcpState>>(
+        &mut self,
+        port: u16,
+    ) -> Option<Handle<'_, S>> {
+        self.get_mut().sockets.get_mut(&port)?.state.handle()
+    }
+}
@@ -0,0 +1,223 @@
+//! The process-wide settings of the crate, applied in one place.
+//!
+//! Each subsystem with global state has a setter of its own, such as
+//! [`lease::set_strict`](crate::lease::set_strict),
+//! [`misuse::set_misuse_handler`](crate::misuse::set_misuse_handler) or,
+//! with their features, `metrics::set_recorder` and
+//! `profiling::set_profiler`. Calling them one by one leaves the order they take effect in up to whoever calls them.
+//! [`configure`] takes all of them in a [`Config`] and applies its sections
+//! in a fixed order:
+//!
+//! 1. the misuse handler, so that any misuse from then on reaches it;
+//! 2. the metrics recorder, so that everything set up after it is
+//!    recorded;
+//! 3. the profiler;
+//! 4. lease strictness;
+//! 5. the [unclaimed-thread policy](crate::unclaimed).
+//!
+//! A section that fails stops the call there: the sections after it are not
+//! applied. [`configure`] succeeds at most once per process and any further
+//! call returns [`ConfigureError::AlreadyConfigured`], whether or not the
+//! first one succeeded.
+//!
+//! ```
+//! use deadlock_proof::{configure, lease, Config, ConfigureError};
+//!
+//! configure(Config::new().strict_leases(true)).unwrap();
+//! assert!(lease::is_strict());
+//! assert_eq!(configure(Config::new()), Err(ConfigureError::AlreadyConfigured));
+//! # lease::set_strict(false);
+//! ```
+//!
+//! Tests share those globals, so with the `test-util` feature
+//! `configure_for_tests` gives each test the defaults for the length of a
+//! `TestConfiguration` and resets them once it is dropped, one test at a
+//! time.
+
+use std::{
+    error::Error,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{misuse::MisuseHandler, unclaimed::Policy};
+
+#[cfg(feature = "metrics-exporter")]
+use crate::metrics::Recorder;
+#[cfg(feature = "profiling")]
+use crate::profiling::Profiler;
+
+/// The process-wide settings [`configure`] applies. [`Config::new`] leaves
+/// every subsystem as it starts out.
+#[derive(Clone, Copy)]
+pub struct Config {
+    strict_leases: bool,
+    unclaimed_policy: Policy,
+    misuse_handler: Option<MisuseHandler>,
+    #[cfg(feature = "metrics-exporter")]
+    recorder: Option<&'static dyn Recorder>,
+    #[cfg(feature = "profiling")]
+    profiler: Option<&'static dyn Profiler>,
+}
+
+impl Config {
+    pub const fn new() -> Self {
+        Self {
+            strict_leases: false,
+            unclaimed_policy: Policy::Panic,
+            misuse_handler: None,
+            #[cfg(feature = "metrics-exporter")]
+            recorder: None,
+            #[cfg(feature = "profiling")]
+            profiler: None,
+        }
+    }
+
+    /// Makes abandoned leases abort the process, as
+    /// [`lease::set_strict`](crate::lease::set_strict) would.
+    pub const fn strict_leases(mut self, strict: bool) -> Self {
+        self.strict_leases = strict;
+        self
+    }
+
+    /// Sets what locking without a permission does on a thread that never
+    /// claimed its token, as
+    /// [`unclaimed::set_policy`](crate::unclaimed::set_policy) would.
+    pub const fn unclaimed_policy(mut self, policy: Policy) -> Self {
+        self.unclaimed_policy = policy;
+        self
+    }
+
+    /// Installs `handler`, as
+    /// [`misuse::set_misuse_handler`](crate::misuse::set_misuse_handler)
+    /// would.
+    pub const fn misuse_handler(mut self, handler: MisuseHandler) -> Self {
+        self.misuse_handler = Some(handler);
+        self
+    }
+
+    /// Installs `recorder`, as
+    /// [`metrics::set_recorder`](crate::metrics::set_recorder) would.
+    #[cfg(feature = "metrics-exporter")]
+    pub const fn recorder(mut self, recorder: &'static dyn Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Installs `profiler`, as
+    /// [`profiling::set_profiler`](crate::profiling::set_profiler) would.
+    #[cfg(feature = "profiling")]
+    pub const fn profiler(mut self, profiler: &'static dyn Profiler) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Why [`configure`] failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConfigureError {
+    /// [`configure`] was called before.
+    AlreadyConfigured,
+    /// A recorder was already installed with `metrics::set_recorder`.
+    #[cfg(feature = "metrics-exporter")]
+    RecorderTaken,
+    /// A profiler was already installed with `profiling::set_profiler`.
+    #[cfg(feature = "profiling")]
+    ProfilerTaken,
+}
+
+impl fmt::Display for ConfigureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::AlreadyConfigured => "the crate was already configured",
+            #[cfg(feature = "metrics-exporter")]
+            Self::RecorderTaken => "a metrics recorder was already installed",
+            #[cfg(feature = "profiling")]
+            Self::ProfilerTaken => "a profiler was already installed",
+        })
+    }
+}
+
+impl Error for ConfigureError {}
+
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+
+/// Applies `config`, section by section in the order of the
+/// [module docs](crate::setup). Only the first call does anything.
+pub fn configure(config: Config) -> Result<(), ConfigureError> {
+    if CONFIGURED.swap(true, Ordering::AcqRel) {
+        return Err(ConfigureError::AlreadyConfigured);
+    }
+    if let Some(handler) = config.misuse_handler {
+        crate::misuse::set_misuse_handler(handler);
+    }
+    #[cfg(feature = "metrics-exporter")]
+    if let Some(recorder) = config.recorder {
+        crate::metrics::set_recorder(recorder).map_err(|_| ConfigureError::RecorderTaken)?;
+    }
+    #[cfg(feature = "profiling")]
+    if let Some(profiler) = config.profiler {
+        crate::profiling::set_profiler(profiler).map_err(|_| ConfigureError::ProfilerTaken)?;
+    }
+    crate::lease::set_strict(config.strict_leases);
+    crate::unclaimed::set_policy(config.unclaimed_policy);
+    Ok(())
+}
+
+/// Whether [`configure`] has been called.
+pub fn is_configured() -> bool {
+    CONFIGURED.load(Ordering::Acquire)
+}
+
+/// The defaults of [`Config::new`] for one test, until the returned value is
+/// dropped. Enabled by the `test-util` feature.
+///
+/// Everything a test can leave behind is reset on both ends: lease
+/// strictness, the unclaimed-thread policy, the misuse handler and, with their features, the depth maxima of `lock_stats`
+/// and the `diff_log`.
+/// Tests that hold a `TestConfiguration` run one after the other. Installed
+/// recorders and profilers cannot be taken back and stay as they are, and
+/// whether [`configure`] was called is left alone.
+#[cfg(feature = "test-util")]
+pub fn configure_for_tests() -> TestConfiguration {
+    use std::sync::{Mutex, PoisonError};
+
+    static RUNNING: Mutex<()> = Mutex::new(());
+    // A test that failed while configured still reset the globals on its way
+    // out.
+    let running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+    reset_for_tests();
+    TestConfiguration { _running: running }
+}
+
+/// The test configuration of one test. See [`configure_for_tests`].
+#[cfg(feature = "test-util")]
+#[must_use = "the configuration is torn down as soon as this is dropped"]
+pub struct TestConfiguration {
+    _running: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(feature = "test-util")]
+impl Drop for TestConfiguration {
+    fn drop(&mut self) {
+        reset_for_tests();
+    }
+}
+
+#[cfg(feature = "test-util")]
+fn reset_for_tests() {
+    crate::lease::set_strict(Config::new().strict_leases);
+    crate::unclaimed::set_policy(Config::new().unclaimed_policy);
+    crate::misuse::clear_misuse_handler();
+    #[cfg(feature = "diagnostics")]
+    crate::lock_stats::reset();
+    #[cfg(feature = "diff-log")]
+    crate::diff_log::clear();
+}
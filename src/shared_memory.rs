@@ -0,0 +1,388 @@
+//! Deadlock-proof mutexes over memory shared between processes.
+//!
+//! A [`SharedDeadlockProofMutex`] lives in memory the caller provides, usually
+//! a `MAP_SHARED` mapping, and is backed by a robust, process-shared pthread
+//! mutex at the start of that memory. The pthread mutex excludes every other
+//! thread in every process that maps it; the permission tokens order the
+//! locks taken within this process just as for [`DeadlockProofMutex`].
+//! Nothing orders locks across processes, so two processes that each take two
+//! shared mutexes in opposite orders can still deadlock.
+//!
+//! The memory holds the pthread mutex followed by the data: it must be
+//! [`SIZE`](SharedDeadlockProofMutex::SIZE) bytes, aligned to
+//! [`ALIGN`](SharedDeadlockProofMutex::ALIGN). One process sets it up with
+//! [`init`](SharedDeadlockProofMutex::init) before any process attaches to
+//! it with [`from_raw_parts`](SharedDeadlockProofMutex::from_raw_parts). The
+//! data has to be [`SharedSafe`], since the other side may be a different
+//! program.
+//!
+//! If the thread holding the lock dies, in this process or another one, the
+//! next acquisition still gets the lock but fails with
+//! [`SharedLockError::PreviousOwnerDied`]: the data may be half-updated.
+//! [`PreviousOwnerDied::recover`] marks the lock consistent again and hands
+//! out the guard, so that the data can be checked and repaired. Dropping the
+//! error instead leaves the lock unusable for good, and every later
+//! acquisition fails with [`SharedLockError::NotRecoverable`].
+//!
+//! Only available on Linux, behind the `shared-memory` feature.
+//!
+//! [`DeadlockProofMutex`]: crate::DeadlockProofMutex
+
+#[cfg(not(target_os = "linux"))]
+compile_error!(
+    "the `shared-memory` feature needs robust process-shared mutexes, which are only bound on Linux"
+);
+
+use std::{
+    cell::UnsafeCell,
+    ffi::c_int,
+    fmt, io,
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    rc::Rc,
+};
+
+use crate::{depth, MutexPermission, NestedMutexPermission, SequentialMutexPermission};
+
+mod sys {
+    use std::ffi::c_int;
+
+    pub(super) const PTHREAD_PROCESS_SHARED: c_int = 1;
+    pub(super) const PTHREAD_MUTEX_ROBUST: c_int = 1;
+    pub(super) const EOWNERDEAD: c_int = 130;
+    pub(super) const ENOTRECOVERABLE: c_int = 131;
+
+    /// Large and aligned enough for `pthread_mutex_t` on every Linux target.
+    #[repr(C, align(8))]
+    pub(super) struct RawMutex([u8; 64]);
+
+    /// Likewise for `pthread_mutexattr_t`.
+    #[repr(C, align(8))]
+    pub(super) struct RawMutexAttr([u8; 16]);
+
+    unsafe extern "C" {
+        pub(super) fn pthread_mutexattr_init(attr: *mut RawMutexAttr) -> c_int;
+        pub(super) fn pthread_mutexattr_destroy(attr: *mut RawMutexAttr) -> c_int;
+        pub(super) fn pthread_mutexattr_setpshared(attr: *mut RawMutexAttr, shared: c_int)
+            -> c_int;
+        pub(super) fn pthread_mutexattr_setrobust(attr: *mut RawMutexAttr, robust: c_int) -> c_int;
+        pub(super) fn pthread_mutex_init(mutex: *mut RawMutex, attr: *const RawMutexAttr) -> c_int;
+        pub(super) fn pthread_mutex_lock(mutex: *mut RawMutex) -> c_int;
+        pub(super) fn pthread_mutex_unlock(mutex: *mut RawMutex) -> c_int;
+        pub(super) fn pthread_mutex_consistent(mutex: *mut RawMutex) -> c_int;
+    }
+}
+
+/// Data that may live in memory shared with another process.
+///
+/// # Safety
+///
+/// The type must hold no pointers, references or other process-local
+/// handles, and every bit pattern must be a valid value of it, since the
+/// other process may be a different program writing whatever it likes.
+pub unsafe trait SharedSafe: Copy + Send + 'static {}
+
+macro_rules! impl_shared_safe {
+    ($($ty:ty),*) => {$(
+        // Safety: plain numbers, valid for every bit pattern.
+        unsafe impl SharedSafe for $ty {}
+    )*};
+}
+
+impl_shared_safe!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+impl_shared_safe!(f32, f64, ());
+
+// Safety: an array of such values is just as plain.
+unsafe impl<T: SharedSafe, const N: usize> SharedSafe for [T; N] {}
+
+/// The layout of the shared memory.
+#[repr(C)]
+struct Slot<T> {
+    mutex: UnsafeCell<sys::RawMutex>,
+    data: UnsafeCell<T>,
+}
+
+fn check(code: c_int) -> io::Result<()> {
+    match code {
+        0 => Ok(()),
+        code => Err(io::Error::from_raw_os_error(code)),
+    }
+}
+
+/// A deadlock-proof mutex whose lock and data live in shared memory. See the
+/// [module docs](self).
+pub struct SharedDeadlockProofMutex<T: SharedSafe, P: MutexPermission, I: 'static> {
+    slot: NonNull<Slot<T>>,
+    _permission: PhantomData<P>,
+    _identifier: PhantomData<I>,
+}
+
+// Safety: the data is only reached under the pthread mutex, which is
+// process-shared and therefore also excludes the threads of this process.
+// `T: SharedSafe` is plain data and `Send`; `P` and `I` are only markers.
+unsafe impl<T: SharedSafe, P: MutexPermission, I: 'static> Send
+    for SharedDeadlockProofMutex<T, P, I>
+{
+}
+unsafe impl<T: SharedSafe, P: MutexPermission, I: 'static> Sync
+    for SharedDeadlockProofMutex<T, P, I>
+{
+}
+
+impl<T: SharedSafe, P: MutexPermission, I: 'static> SharedDeadlockProofMutex<T, P, I> {
+    /// How many bytes of shared memory the mutex takes.
+    pub const SIZE: usize = mem::size_of::<Slot<T>>();
+    /// The alignment the shared memory needs.
+    pub const ALIGN: usize = mem::align_of::<Slot<T>>();
+
+    /// Sets up the mutex in `ptr`, holding `content`. Does not attach to it:
+    /// follow up with [`from_raw_parts`](Self::from_raw_parts).
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for writes of [`SIZE`](Self::SIZE) bytes, aligned
+    /// to [`ALIGN`](Self::ALIGN), and no thread in any process may use the
+    /// memory as a mutex until this returns. Initialising memory that some
+    /// thread has already attached to is undefined behavior.
+    pub unsafe fn init(ptr: *mut u8, content: T) -> io::Result<()> {
+        let slot = ptr.cast::<Slot<T>>();
+        let mut attr = MaybeUninit::<sys::RawMutexAttr>::uninit();
+        // Safety: `attr` is destroyed right after use, and the caller
+        // guarantees that `slot` is ours to write.
+        unsafe {
+            check(sys::pthread_mutexattr_init(attr.as_mut_ptr()))?;
+            let result = check(sys::pthread_mutexattr_setpshared(
+                attr.as_mut_ptr(),
+                sys::PTHREAD_PROCESS_SHARED,
+            ))
+            .and_then(|()| {
+                check(sys::pthread_mutexattr_setrobust(
+                    attr.as_mut_ptr(),
+                    sys::PTHREAD_MUTEX_ROBUST,
+                ))
+            })
+            .and_then(|()| {
+                check(sys::pthread_mutex_init(
+                    ptr::addr_of_mut!((*slot).mutex).cast(),
+                    attr.as_ptr(),
+                ))
+            });
+            sys::pthread_mutexattr_destroy(attr.as_mut_ptr());
+            result?;
+            ptr::addr_of_mut!((*slot).data).cast::<T>().write(content);
+        }
+        Ok(())
+    }
+
+    /// Attaches to a mutex set up with [`init`](Self::init), in this process
+    /// or another one. Dropping the result detaches without touching the
+    /// memory.
+    ///
+    /// Every attachment within one process should use the same `P` and `I`:
+    /// the ordering proof only relates mutexes through their types.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to memory initialised with `init` for the same `T`,
+    /// and stay valid for reads and writes of [`SIZE`](Self::SIZE) bytes for
+    /// as long as the result lives. The memory may be mapped at different
+    /// addresses, but must not be initialised again meanwhile.
+    pub unsafe fn from_raw_parts(ptr: *mut u8, _identifier: I) -> Self {
+        let () = depth::DepthCheck::<P>::WITHIN_MAX;
+        let slot = NonNull::new(ptr.cast::<Slot<T>>()).expect("shared mutex at a null pointer");
+        debug_assert!(slot.as_ptr().is_aligned(), "shared mutex is misaligned");
+        Self {
+            slot,
+            _permission: PhantomData,
+            _identifier: PhantomData,
+        }
+    }
+
+    fn raw(&self) -> *mut sys::RawMutex {
+        // Safety: the slot is valid for as long as `self` is.
+        unsafe { self.slot.as_ref() }.mutex.get()
+    }
+
+    /// Blocks on the pthread mutex. `locked` turns the permission into what
+    /// a successful acquisition returns.
+    fn acquire<G>(&self, permission: P, locked: impl FnOnce(P) -> G) -> SharedLockResult<G, P> {
+        crate::permission::check_origin(&permission);
+        #[cfg(debug_assertions)]
+        crate::phase::assert_not_in_foreign_phase();
+        // Safety: the mutex was initialised, as `from_raw_parts` requires.
+        match unsafe { sys::pthread_mutex_lock(self.raw()) } {
+            0 => Ok(locked(permission)),
+            sys::EOWNERDEAD => Err(SharedLockError::PreviousOwnerDied(PreviousOwnerDied {
+                locked: locked(permission),
+                mutex: self.raw(),
+            })),
+            sys::ENOTRECOVERABLE => Err(SharedLockError::NotRecoverable(permission)),
+            // The error code rather than its description, which would have
+            // to be allocated.
+            code => panic!("pthread_mutex_lock failed with error {code}"),
+        }
+    }
+
+    fn guard(&self, permission: P) -> SharedGuard<'_, T, P, I> {
+        SharedGuard {
+            mutex: self,
+            permission: Some(permission),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Acquires this mutex, blocking the current thread, and any thread of
+    /// another process, until it is able to do so.
+    pub fn lock(&self, permission: P) -> SharedLockResult<SharedGuard<'_, T, P, I>, P> {
+        self.acquire(permission, |permission| self.guard(permission))
+    }
+
+    /// Acquires this mutex and provides a token for claiming nested mutexes.
+    pub fn lock_for_nested(&self, permission: P) -> SharedNestedLockResult<'_, T, P, I> {
+        self.acquire(permission, |permission| {
+            let nested = NestedMutexPermission::new(&permission);
+            (SharedNestedGuard(self.guard(permission)), nested)
+        })
+    }
+}
+
+/// Result of locking a [`SharedDeadlockProofMutex`], which yields `G`.
+pub type SharedLockResult<G, P> = Result<G, SharedLockError<G, P>>;
+
+/// Result of [`SharedDeadlockProofMutex::lock_for_nested`]: the nested guard
+/// plus the token for claiming the mutexes inside it.
+pub type SharedNestedLockResult<'a, T, P, I> =
+    SharedLockResult<(SharedNestedGuard<'a, T, P, I>, NestedMutexPermission<P, I>), P>;
+
+/// Why locking a [`SharedDeadlockProofMutex`] did not simply succeed.
+#[non_exhaustive]
+pub enum SharedLockError<G, P> {
+    /// The previous holder died with the lock held. The lock is held now.
+    PreviousOwnerDied(PreviousOwnerDied<G>),
+    /// A previous holder died and nobody recovered the mutex, so it can
+    /// never be locked again. The permission is returned unused.
+    NotRecoverable(P),
+}
+
+impl<G, P> fmt::Debug for SharedLockError<G, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SharedLockError::PreviousOwnerDied(_) => f.write_str("PreviousOwnerDied(..)"),
+            SharedLockError::NotRecoverable(_) => f.write_str("NotRecoverable(..)"),
+        }
+    }
+}
+
+impl<G, P> fmt::Display for SharedLockError<G, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SharedLockError::PreviousOwnerDied(_) => {
+                f.write_str("previous owner of the shared mutex died holding it")
+            }
+            SharedLockError::NotRecoverable(_) => f.write_str("shared mutex is not recoverable"),
+        }
+    }
+}
+
+impl<G, P> std::error::Error for SharedLockError<G, P> {}
+
+/// A lock that was acquired from a holder that died. See the
+/// [module docs](self).
+pub struct PreviousOwnerDied<G> {
+    locked: G,
+    mutex: *mut sys::RawMutex,
+}
+
+impl<G> PreviousOwnerDied<G> {
+    /// Marks the mutex consistent again and returns what the acquisition
+    /// would have returned. The data is still as the dead holder left it.
+    pub fn recover(self) -> G {
+        // Safety: the mutex is held by this thread, through `locked`.
+        let code = unsafe { sys::pthread_mutex_consistent(self.mutex) };
+        assert_eq!(code, 0, "pthread_mutex_consistent failed");
+        self.locked
+    }
+}
+
+/// Guard for a [`SharedDeadlockProofMutex`]. Unlocks, in every process,
+/// when dropped.
+pub struct SharedGuard<'a, T: SharedSafe, P: MutexPermission, I: 'static> {
+    mutex: &'a SharedDeadlockProofMutex<T, P, I>,
+    /// Only `None` once `unlock` has taken it out.
+    permission: Option<P>,
+    /// A pthread mutex must be unlocked by the thread that locked it.
+    _not_send: PhantomData<Rc<()>>,
+}
+
+impl<T: SharedSafe, P: MutexPermission, I: 'static> SharedGuard<'_, T, P, I> {
+    /// Unlock the mutex and return the permission token.
+    pub fn unlock(mut self) -> P {
+        self.permission.take().expect("permission taken twice")
+    }
+
+    /// Unlock the mutex and return a sequential permission token.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        SequentialMutexPermission::new(self.unlock())
+    }
+}
+
+impl<T: SharedSafe, P: MutexPermission, I: 'static> Deref for SharedGuard<'_, T, P, I> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: the lock is held.
+        unsafe { &*self.mutex.slot.as_ref().data.get() }
+    }
+}
+
+impl<T: SharedSafe, P: MutexPermission, I: 'static> DerefMut for SharedGuard<'_, T, P, I> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the lock is held, and `&mut self` makes this the only
+        // reference.
+        unsafe { &mut *self.mutex.slot.as_ref().data.get() }
+    }
+}
+
+impl<T: SharedSafe, P: MutexPermission, I: 'static> Drop for SharedGuard<'_, T, P, I> {
+    fn drop(&mut self) {
+        // Safety: the lock is held by this thread.
+        unsafe { sys::pthread_mutex_unlock(self.mutex.raw()) };
+    }
+}
+
+/// Guard for nested operations on a [`SharedDeadlockProofMutex`].
+pub struct SharedNestedGuard<'a, T: SharedSafe, P: MutexPermission, I: 'static>(
+    SharedGuard<'a, T, P, I>,
+);
+
+impl<T: SharedSafe, P: MutexPermission, I: 'static> SharedNestedGuard<'_, T, P, I> {
+    /// Unlock the mutex with the nested permission token.
+    pub fn unlock(self, _token: NestedMutexPermission<P, I>) -> P {
+        self.0.unlock()
+    }
+
+    /// Unlock the mutex with the nested permission token and return a
+    /// sequential permission token.
+    pub fn unlock_for_sequential(
+        self,
+        _token: NestedMutexPermission<P, I>,
+    ) -> SequentialMutexPermission<P, I> {
+        self.0.unlock_for_sequential()
+    }
+}
+
+impl<T: SharedSafe, P: MutexPermission, I: 'static> Deref for SharedNestedGuard<'_, T, P, I> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: SharedSafe, P: MutexPermission, I: 'static> DerefMut for SharedNestedGuard<'_, T, P, I> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
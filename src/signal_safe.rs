@@ -0,0 +1,157 @@
+//! Lock-free side regions that signal handlers may touch without locking.
+//!
+//! A signal handler must never take a lock, so state it updates lives in a
+//! [`SignalSafe`] region next to the mutex rather than inside it. The region
+//! only holds an atomic, and its contents are folded into the locked data by
+//! a consolidation function each time the mutex is next acquired.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+use crate::{
+    poison, DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofPoisonError, LockResult,
+    MutexPermission, NestedLockResult,
+};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Value types that have a lock-free atomic counterpart. Sealed: only `u32`,
+/// `u64` and `bool` are allowed, since those are lock-free on every platform
+/// we care about and therefore safe to use from a signal handler.
+pub trait AtomicOps: sealed::Sealed + Copy + 'static {
+    /// The matching `std::sync::atomic` type.
+    type Atomic: Send + Sync;
+
+    #[doc(hidden)]
+    fn new_atomic(value: Self) -> Self::Atomic;
+    #[doc(hidden)]
+    fn load(atomic: &Self::Atomic, order: Ordering) -> Self;
+    #[doc(hidden)]
+    fn store(atomic: &Self::Atomic, value: Self, order: Ordering);
+    #[doc(hidden)]
+    fn swap(atomic: &Self::Atomic, value: Self, order: Ordering) -> Self;
+}
+
+macro_rules! impl_atomic_ops {
+    ($($value:ty => $atomic:ty),*) => {$(
+        impl sealed::Sealed for $value {}
+
+        impl AtomicOps for $value {
+            type Atomic = $atomic;
+
+            fn new_atomic(value: Self) -> Self::Atomic {
+                <$atomic>::new(value)
+            }
+            fn load(atomic: &Self::Atomic, order: Ordering) -> Self {
+                atomic.load(order)
+            }
+            fn store(atomic: &Self::Atomic, value: Self, order: Ordering) {
+                atomic.store(value, order)
+            }
+            fn swap(atomic: &Self::Atomic, value: Self, order: Ordering) -> Self {
+                atomic.swap(value, order)
+            }
+        }
+    )*};
+}
+
+impl_atomic_ops!(u32 => AtomicU32, u64 => AtomicU64, bool => AtomicBool);
+
+/// An atomic value that may be accessed from anywhere, signal handlers
+/// included, without any permission.
+pub struct SignalSafe<A: AtomicOps>(A::Atomic);
+
+impl<A: AtomicOps> SignalSafe<A> {
+    fn new(value: A) -> Self {
+        Self(A::new_atomic(value))
+    }
+
+    /// Reads the current value.
+    pub fn load(&self, order: Ordering) -> A {
+        A::load(&self.0, order)
+    }
+
+    /// Overwrites the current value.
+    pub fn store(&self, value: A, order: Ordering) {
+        A::store(&self.0, value, order)
+    }
+
+    /// Replaces the current value, returning the previous one. Consolidation
+    /// functions typically `swap` in the neutral value to take what
+    /// accumulated since the last acquisition.
+    pub fn swap(&self, value: A, order: Ordering) -> A {
+        A::swap(&self.0, value, order)
+    }
+}
+
+impl SignalSafe<u32> {
+    /// Adds to the current value, returning the previous one.
+    pub fn fetch_add(&self, value: u32, order: Ordering) -> u32 {
+        self.0.fetch_add(value, order)
+    }
+}
+
+impl SignalSafe<u64> {
+    /// Adds to the current value, returning the previous one.
+    pub fn fetch_add(&self, value: u64, order: Ordering) -> u64 {
+        self.0.fetch_add(value, order)
+    }
+}
+
+/// A [`DeadlockProofMutex`] with an attached [`SignalSafe`] region. Created by
+/// [`DeadlockProofMutex::with_signal_safe`].
+pub struct SignalSafeMutex<T, A: AtomicOps, P: MutexPermission, I: 'static> {
+    mutex: DeadlockProofMutex<T, P, I>,
+    region: SignalSafe<A>,
+    consolidate: fn(&mut T, &SignalSafe<A>),
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// Attaches a signal-safe region holding `initial`. Every later
+    /// acquisition first runs `consolidate` on the locked data and the region.
+    pub fn with_signal_safe<A: AtomicOps>(
+        self,
+        initial: A,
+        consolidate: fn(&mut T, &SignalSafe<A>),
+    ) -> SignalSafeMutex<T, A, P, I> {
+        SignalSafeMutex {
+            mutex: self,
+            region: SignalSafe::new(initial),
+            consolidate,
+        }
+    }
+}
+
+impl<T, A: AtomicOps, P: MutexPermission, I: 'static> SignalSafeMutex<T, A, P, I> {
+    /// The lock-free region. Needs no permission and never blocks.
+    pub fn signal_safe(&self) -> &SignalSafe<A> {
+        &self.region
+    }
+
+    /// Acquires the mutex and consolidates the region into the data. A
+    /// poisoned mutex is not consolidated, so nothing accumulated is lost.
+    pub fn lock(
+        &self,
+        permission: P,
+    ) -> LockResult<DeadlockProofMutexGuard<'_, T, P, I>, DeadlockProofPoisonError<'_, T, P, I>> {
+        poison::map!(self.mutex.lock(permission), |mut guard| {
+            (self.consolidate)(&mut guard, &self.region);
+            guard
+        })
+    }
+
+    /// See [`DeadlockProofMutex::poison_for_test`].
+    #[cfg(feature = "test-util")]
+    pub fn poison_for_test(&self) {
+        self.mutex.poison_for_test()
+    }
+
+    /// Like [`lock`](Self::lock), for claiming nested mutexes.
+    pub fn lock_for_nested(&self, permission: P) -> NestedLockResult<'_, T, P, I> {
+        poison::map!(self.mutex.lock_for_nested(permission), |(mut guard, nested)| {
+            (self.consolidate)(&mut guard, &self.region);
+            (guard, nested)
+        })
+    }
+}
@@ -0,0 +1,73 @@
+//! How a mutex copies its data for the features that compare it with an
+//! earlier copy.
+//!
+//! A mutex with a [diff log](crate::diff_log) copies its data whenever a
+//! guard of it is created. For most data a full clone is fine, but a
+//! multi-megabyte table would be copied on every acquisition. A
+//! [`SnapshotStrategy`] given to the mutex as it is built chooses how the
+//! copy is taken and how it is compared:
+//!
+//! - [`full`](SnapshotStrategy::full) clones the data and compares with
+//!   `PartialEq`, the default.
+//! - [`shared`](SnapshotStrategy::shared), for data behind an `Arc`, only
+//!   clones the `Arc`. A critical section that only reads costs a reference
+//!   count, and one that writes through `Arc::make_mut` copies the data once,
+//!   which is also what the comparison sees: the data counts as changed when
+//!   the `Arc` points elsewhere.
+//! - [`with`](SnapshotStrategy::with) takes both the copy and the
+//!   comparison from the caller, e.g. to copy only a header.
+
+use std::{fmt, sync::Arc};
+
+/// How to copy and compare the data of one mutex. Plain function pointers,
+/// like the rest of a mutex's per-data hooks.
+pub struct SnapshotStrategy<T> {
+    take: fn(&T) -> T,
+    unchanged: fn(&T, &T) -> bool,
+}
+
+impl<T: Clone + PartialEq> SnapshotStrategy<T> {
+    /// A full clone, compared with `PartialEq`.
+    pub fn full() -> Self {
+        Self::with(T::clone, T::eq)
+    }
+}
+
+impl<U> SnapshotStrategy<Arc<U>> {
+    /// A clone of the `Arc`, compared by pointer.
+    pub fn shared() -> Self {
+        Self::with(Arc::clone, Arc::ptr_eq)
+    }
+}
+
+impl<T> SnapshotStrategy<T> {
+    /// Copies with `take`, and has `unchanged` tell whether the data still
+    /// matches a copy.
+    pub fn with(take: fn(&T) -> T, unchanged: fn(&T, &T) -> bool) -> Self {
+        Self { take, unchanged }
+    }
+
+    /// A copy of `data`.
+    pub(crate) fn take(&self, data: &T) -> T {
+        (self.take)(data)
+    }
+
+    /// Whether `data` still matches `snapshot`.
+    pub(crate) fn unchanged(&self, snapshot: &T, data: &T) -> bool {
+        (self.unchanged)(snapshot, data)
+    }
+}
+
+impl<T> Clone for SnapshotStrategy<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for SnapshotStrategy<T> {}
+
+impl<T> fmt::Debug for SnapshotStrategy<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SnapshotStrategy").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,533 @@
+//! Long-running randomized soak runs with invariant checking.
+//!
+//! [`run`] keeps a number of threads busy for a given time with a random mix
+//! of the crate's locking flows, against one shared [`NetworkStack`] and a
+//! pool of standalone mutexes. Every flow checks what it can see of the data
+//! it touched, and once all threads are done the totals are checked against
+//! the number of operations run. The [`SoakReport`] has the throughput, the
+//! longest wait for a single lock, and every broken invariant.
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use deadlock_proof::soak::{self, SoakConfig};
+//!
+//! let report = soak::run(SoakConfig {
+//!     threads: 2,
+//!     duration: Duration::from_millis(20),
+//!     ..SoakConfig::default()
+//! });
+//! assert!(report.is_clean(), "{:?}", report.violations);
+//! ```
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "diagnostics")]
+use std::thread::{self, ThreadId};
+
+use crate::{
+    concurrent, lock_hierarchy, DeadlockProofMutex, ErrorIpLock, ErrorTransportLock, LockOutcome,
+    NestedMutexPermission, NetworkStack, OuterMutexPermission, Position,
+};
+#[cfg(feature = "diagnostics")]
+use crate::lock_stats;
+
+/// The flows a soak run picks from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scenario {
+    /// Lock one pool mutex on its own.
+    Exclusive,
+    /// Lock a pool mutex and the mutex nested inside it.
+    Nested,
+    /// Lock two mutexes one after the other with a sequential permission.
+    Sequential,
+    /// Walk the stack IP -> Device -> Transport, then read it back from
+    /// Transport to IP.
+    StackWalk,
+}
+
+impl Scenario {
+    /// Every scenario, in declaration order.
+    pub const ALL: [Scenario; 4] = [
+        Scenario::Exclusive,
+        Scenario::Nested,
+        Scenario::Sequential,
+        Scenario::StackWalk,
+    ];
+}
+
+/// Relative weights of the [`Scenario`]s. A weight of 0 leaves the scenario
+/// out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScenarioMix {
+    pub exclusive: u32,
+    pub nested: u32,
+    pub sequential: u32,
+    pub stack_walk: u32,
+}
+
+impl ScenarioMix {
+    fn weight(&self, scenario: Scenario) -> u32 {
+        match scenario {
+            Scenario::Exclusive => self.exclusive,
+            Scenario::Nested => self.nested,
+            Scenario::Sequential => self.sequential,
+            Scenario::StackWalk => self.stack_walk,
+        }
+    }
+}
+
+impl Default for ScenarioMix {
+    /// Every scenario equally often.
+    fn default() -> Self {
+        Self {
+            exclusive: 1,
+            nested: 1,
+            sequential: 1,
+            stack_walk: 1,
+        }
+    }
+}
+
+/// What to run in [`run`].
+#[derive(Clone, Debug)]
+pub struct SoakConfig {
+    /// Number of worker threads.
+    pub threads: usize,
+    /// How long the workers keep starting new operations.
+    pub duration: Duration,
+    pub mix: ScenarioMix,
+    /// Number of standalone mutexes the exclusive and nested flows pick from.
+    pub pool_size: usize,
+    /// Seed of the per-thread random choices, so a run can be repeated.
+    pub seed: u64,
+}
+
+impl Default for SoakConfig {
+    fn default() -> Self {
+        Self {
+            threads: 4,
+            duration: Duration::from_secs(1),
+            mix: ScenarioMix::default(),
+            pool_size: 8,
+            seed: 0x5eed,
+        }
+    }
+}
+
+/// A broken invariant.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// The flow that noticed it. End-of-run checks name the flow whose
+    /// totals are off.
+    pub scenario: Scenario,
+    pub detail: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.scenario, self.detail)
+    }
+}
+
+/// At most this many violations are kept in a [`SoakReport`]; the rest are
+/// only counted.
+pub const MAX_RECORDED_VIOLATIONS: usize = 64;
+
+/// The outcome of [`run`].
+#[derive(Clone, Debug)]
+pub struct SoakReport {
+    /// Time from the start of the run until every worker was done.
+    pub elapsed: Duration,
+    /// The longest any single lock call waited.
+    pub max_wait: Duration,
+    /// The first [`MAX_RECORDED_VIOLATIONS`] violations.
+    pub violations: Vec<Violation>,
+    /// How many violations there were in total.
+    pub violation_count: u64,
+    /// The most guards any worker held at once.
+    #[cfg(feature = "diagnostics")]
+    pub max_depth: usize,
+    /// The most guards each worker held at once, by worker thread.
+    #[cfg(feature = "diagnostics")]
+    pub thread_max_depths: Vec<(ThreadId, usize)>,
+    operations: [u64; Scenario::ALL.len()],
+}
+
+impl SoakReport {
+    /// How many operations of `scenario` completed.
+    pub fn operations(&self, scenario: Scenario) -> u64 {
+        self.operations[scenario as usize]
+    }
+
+    /// How many operations completed altogether.
+    pub fn total_operations(&self) -> u64 {
+        self.operations.iter().sum()
+    }
+
+    /// Completed operations per second.
+    pub fn throughput(&self) -> f64 {
+        self.total_operations() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Whether no invariant was broken.
+    pub fn is_clean(&self) -> bool {
+        self.violation_count == 0
+    }
+
+    fn violation(&mut self, scenario: Scenario, detail: String) {
+        self.violation_count += 1;
+        if self.violations.len() < MAX_RECORDED_VIOLATIONS {
+            self.violations.push(Violation { scenario, detail });
+        }
+    }
+}
+
+struct PoolLock;
+struct PoolInnerLock;
+struct FirstStage;
+struct SecondStage;
+
+lock_hierarchy!(OuterMutexPermission => FirstStage, SecondStage);
+
+/// Bytes each stack walk transmits.
+const WALK_BYTES: u64 = 64;
+
+/// The data of a pool mutex.
+#[derive(Default)]
+struct Account {
+    /// Bumped one after the other by exclusive flows, so always equal
+    /// outside of the critical section.
+    exclusive: [u64; 2],
+    /// Bumped by nested flows along with the nested mutex.
+    nested: u64,
+}
+
+struct PoolSlot {
+    account: DeadlockProofMutex<Account, OuterMutexPermission, PoolLock>,
+    nested: DeadlockProofMutex<
+        u64,
+        NestedMutexPermission<OuterMutexPermission, PoolLock>,
+        PoolInnerLock,
+    >,
+}
+
+/// Everything the workers share.
+struct Shared {
+    stack: NetworkStack,
+    pool: Vec<PoolSlot>,
+    first: DeadlockProofMutex<u64, Position<FirstStage>, FirstStage>,
+    second: DeadlockProofMutex<u64, Position<SecondStage>, SecondStage>,
+}
+
+/// xorshift64*, which is plenty for picking operations.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // A zero state would stay zero.
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+/// One worker's share of the report.
+struct Worker<'a> {
+    shared: &'a Shared,
+    rng: Rng,
+    report: SoakReport,
+}
+
+impl Worker<'_> {
+    /// Runs `lock`, counting how long it took towards the longest wait.
+    fn timed<R>(&mut self, lock: impl FnOnce() -> R) -> R {
+        let started = Instant::now();
+        let locked = lock();
+        self.report.max_wait = self.report.max_wait.max(started.elapsed());
+        locked
+    }
+
+    fn pick(&mut self, mix: &ScenarioMix, total_weight: u64) -> Scenario {
+        let mut ticket = self.rng.below(total_weight);
+        for scenario in Scenario::ALL {
+            let weight = u64::from(mix.weight(scenario));
+            if ticket < weight {
+                return scenario;
+            }
+            ticket -= weight;
+        }
+        unreachable!("ticket is below the total weight")
+    }
+
+    fn run(
+        &mut self,
+        scenario: Scenario,
+        permission: OuterMutexPermission,
+    ) -> OuterMutexPermission {
+        let permission = match scenario {
+            Scenario::Exclusive => self.exclusive(permission),
+            Scenario::Nested => self.nested(permission),
+            Scenario::Sequential => self.sequential(permission),
+            Scenario::StackWalk => self.stack_walk(permission),
+        };
+        self.report.operations[scenario as usize] += 1;
+        permission
+    }
+
+    fn exclusive(&mut self, permission: OuterMutexPermission) -> OuterMutexPermission {
+        let shared = self.shared;
+        let slot = &shared.pool[self.rng.below(shared.pool.len() as u64) as usize];
+        let mut account = self.timed(|| slot.account.lock(permission).guard());
+        let [first, second] = account.exclusive;
+        if first != second {
+            self.report.violation(
+                Scenario::Exclusive,
+                format!("torn update: {first} != {second}"),
+            );
+        }
+        account.exclusive[0] += 1;
+        std::hint::spin_loop();
+        account.exclusive[1] += 1;
+        account.unlock()
+    }
+
+    fn nested(&mut self, permission: OuterMutexPermission) -> OuterMutexPermission {
+        let shared = self.shared;
+        let slot = &shared.pool[self.rng.below(shared.pool.len() as u64) as usize];
+        let (mut account, token) = self.timed(|| slot.account.lock_for_nested(permission).guard());
+        let mut nested = self.timed(|| slot.nested.lock(token).guard());
+        if account.nested != *nested {
+            self.report.violation(
+                Scenario::Nested,
+                format!("outer count {} != nested count {}", account.nested, *nested),
+            );
+        }
+        account.nested += 1;
+        *nested += 1;
+        account.unlock(nested.unlock())
+    }
+
+    fn sequential(&mut self, permission: OuterMutexPermission) -> OuterMutexPermission {
+        let shared = self.shared;
+        let mut first = self.timed(|| shared.first.lock(permission).guard());
+        *first += 1;
+        let permission = first.unlock_for_sequential();
+        let mut second = self.timed(|| shared.second.lock(permission).guard());
+        *second += 1;
+        second.unlock().to_earlier()
+    }
+
+    /// Every walk counts a packet before it may open a connection, so the
+    /// stack never has more connections than packets. Both are then read
+    /// together, through the error path's hierarchy, to check it.
+    fn stack_walk(&mut self, permission: OuterMutexPermission) -> OuterMutexPermission {
+        let stack = &self.shared.stack;
+        let mut ip = self.timed(|| stack.ip_layer.lock(permission).guard());
+        ip.packets_processed += 1;
+        let permission = ip.unlock_for_sequential();
+
+        let mut device = self.timed(|| stack.device_layer.lock(permission).guard());
+        device.bytes_transmitted += WALK_BYTES;
+        let permission = device.unlock_for_sequential();
+
+        let open = self.rng.below(2) == 0;
+        let mut transport = self.timed(|| stack.transport_layer.lock(permission).guard());
+        if open {
+            transport.tcp_connections += 1;
+        } else {
+            transport.tcp_connections = transport.tcp_connections.saturating_sub(1);
+        }
+        let permission = transport.unlock().to_earlier().to_earlier();
+
+        let (gate, permission) = self.timed(|| stack.error_path.lock_for_nested(permission));
+        let (transport, inside) = self.timed(|| {
+            stack
+                .transport_layer
+                .lock_for_nested_as::<_, ErrorTransportLock>(permission)
+                .guard()
+        });
+        let connections = transport.tcp_connections;
+        let ip = self.timed(|| stack.ip_layer.lock_as::<_, ErrorIpLock>(inside).guard());
+        if u64::from(connections) > ip.packets_processed {
+            self.report.violation(
+                Scenario::StackWalk,
+                format!(
+                    "{connections} connections but only {} packets",
+                    ip.packets_processed
+                ),
+            );
+        }
+        gate.unlock(transport.unlock(ip.unlock()))
+    }
+}
+
+impl Shared {
+    /// The checks that only hold once every worker is done.
+    fn check_totals(
+        &self,
+        report: &mut SoakReport,
+        mut permission: OuterMutexPermission,
+    ) -> OuterMutexPermission {
+        let mut exclusive = 0;
+        let mut nested = 0;
+        for slot in &self.pool {
+            let (account, token) = slot.account.lock_for_nested(permission).guard();
+            let inner = slot.nested.lock(token).guard();
+            exclusive += account.exclusive[1];
+            nested += *inner;
+            permission = account.unlock(inner.unlock());
+        }
+        let expected = report.operations(Scenario::Exclusive);
+        if exclusive != expected {
+            report.violation(
+                Scenario::Exclusive,
+                format!("{exclusive} updates for {expected} operations"),
+            );
+        }
+        let expected = report.operations(Scenario::Nested);
+        if nested != expected {
+            report.violation(
+                Scenario::Nested,
+                format!("{nested} updates for {expected} operations"),
+            );
+        }
+
+        let first = self.first.lock(permission).guard();
+        let first_count = *first;
+        let second = self.second.lock(first.unlock_for_sequential()).guard();
+        let expected = report.operations(Scenario::Sequential);
+        if (first_count, *second) != (expected, expected) {
+            report.violation(
+                Scenario::Sequential,
+                format!(
+                    "stages at {first_count} and {} for {expected} operations",
+                    *second
+                ),
+            );
+        }
+        let permission = second.unlock().to_earlier();
+
+        let ip = self.stack.ip_layer.lock(permission).guard();
+        let packets = ip.packets_processed;
+        let device = self
+            .stack
+            .device_layer
+            .lock(ip.unlock_for_sequential())
+            .guard();
+        let expected = report.operations(Scenario::StackWalk);
+        if packets != expected || device.bytes_transmitted != expected * WALK_BYTES {
+            report.violation(
+                Scenario::StackWalk,
+                format!(
+                    "{packets} packets and {} bytes for {expected} walks",
+                    device.bytes_transmitted
+                ),
+            );
+        }
+        device.unlock().to_earlier()
+    }
+}
+
+/// Runs a soak as configured and reports on it. Blocks for about
+/// `config.duration`.
+///
+/// Panics if `config.threads` or `config.pool_size` is 0, if every weight in
+/// the mix is 0, or if a worker panics.
+pub fn run(config: SoakConfig) -> SoakReport {
+    assert!(config.threads > 0, "a soak needs at least one thread");
+    assert!(config.pool_size > 0, "a soak needs at least one pool mutex");
+    let total_weight: u64 = Scenario::ALL
+        .iter()
+        .map(|&scenario| u64::from(config.mix.weight(scenario)))
+        .sum();
+    assert!(total_weight > 0, "every scenario has weight 0");
+
+    let shared = Shared {
+        stack: NetworkStack::new(),
+        pool: (0..config.pool_size)
+            .map(|_| PoolSlot {
+                account: DeadlockProofMutex::new(Account::default(), PoolLock),
+                nested: DeadlockProofMutex::new(0, PoolInnerLock),
+            })
+            .collect(),
+        first: DeadlockProofMutex::new(0, FirstStage),
+        second: DeadlockProofMutex::new(0, SecondStage),
+    };
+    let empty = SoakReport {
+        elapsed: Duration::ZERO,
+        max_wait: Duration::ZERO,
+        violations: Vec::new(),
+        violation_count: 0,
+        #[cfg(feature = "diagnostics")]
+        max_depth: 0,
+        #[cfg(feature = "diagnostics")]
+        thread_max_depths: Vec::new(),
+        operations: [0; Scenario::ALL.len()],
+    };
+
+    let started = Instant::now();
+    let deadline = started + config.duration;
+    let workers = (0..config.threads as u64)
+        .map(|index| -> concurrent::WorkerWith<'_, SoakReport> {
+            let mut worker = Worker {
+                shared: &shared,
+                rng: Rng::new(config.seed ^ index.wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+                report: empty.clone(),
+            };
+            let mix = config.mix;
+            Box::new(move |mut permission| {
+                while Instant::now() < deadline {
+                    let scenario = worker.pick(&mix, total_weight);
+                    permission = worker.run(scenario, permission);
+                }
+                #[cfg(feature = "diagnostics")]
+                {
+                    let depth = lock_stats::thread_max_depth();
+                    worker.report.max_depth = depth;
+                    worker.report.thread_max_depths.push((thread::current().id(), depth));
+                }
+                (permission, worker.report)
+            })
+        })
+        .collect();
+    let reports = concurrent::run_all_with(workers).unwrap_or_else(|panic| panic.resume());
+
+    let mut report = empty;
+    report.elapsed = started.elapsed();
+    for worker in reports {
+        report.max_wait = report.max_wait.max(worker.max_wait);
+        for (total, count) in report.operations.iter_mut().zip(worker.operations) {
+            *total += count;
+        }
+        report.violation_count += worker.violation_count;
+        #[cfg(feature = "diagnostics")]
+        {
+            report.max_depth = report.max_depth.max(worker.max_depth);
+            report.thread_max_depths.extend(worker.thread_max_depths);
+        }
+        let room = MAX_RECORDED_VIOLATIONS - report.violations.len();
+        report
+            .violations
+            .extend(worker.violations.into_iter().take(room));
+    }
+    // The calling thread may have claimed its own permission already, so
+    // the totals are checked on a thread of their own.
+    concurrent::run_all(vec![Box::new(|permission| {
+        shared.check_totals(&mut report, permission)
+    })])
+    .unwrap_or_else(|panic| panic.resume());
+    report
+}
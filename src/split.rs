@@ -0,0 +1,239 @@
+//! One level of the hierarchy whose data is split in two halves, locked
+//! separately.
+//!
+//! State like a device's interface configuration and its transmit counters
+//! is one thing to the hierarchy, but two threads can work on the two halves
+//! without getting in each other's way. Two mutexes would need a level each.
+//! A [`SplitLock`] takes one: it has a lock of its own for each half, and
+//! every acquisition consumes the level's permission, as a mutex lock does.
+//!
+//! [`lock_a`](SplitLock::lock_a) and [`lock_b`](SplitLock::lock_b) lock one
+//! half each, so a thread on either half only waits for threads on the
+//! same one. [`lock_both`](SplitLock::lock_both) locks A, then B. That is
+//! the internal order every acquisition keeps: a guard for A goes on to B
+//! with [`also_b`](SplitGuardA::also_b), while a guard for B has no way to
+//! A. Since the guard holds the permission, the thread has none left for
+//! another lock of the same level.
+//!
+//! ```
+//! use deadlock_proof::{LockOutcome, OuterMutexPermission, SplitLock};
+//!
+//! struct LinkLock;
+//! let link = SplitLock::new(1500u32, 0u64, LinkLock);
+//!
+//! let mut bytes = link.lock_b(OuterMutexPermission::get()).guard();
+//! *bytes += 64;
+//! let permission = bytes.unlock();
+//!
+//! let mut mtu = link.lock_a(permission).guard();
+//! *mtu = 9000;
+//! let mut both = mtu.also_b().guard();
+//! let (mtu, bytes) = both.parts_mut();
+//! *bytes += u64::from(*mtu);
+//! ```
+//!
+//! A panic while holding a half poisons only that half. A lock that takes
+//! a poisoned half hands out its guard inside the poison error, as `lock`
+//! does.
+
+use std::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{self, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    depth::DepthCheck, permission, Held, LockResult, MutexPermission, PermissionSyncSendWrapper,
+    SequentialMutexPermission,
+};
+
+/// A lock at one level of the hierarchy over two halves, `A` and `B`, that
+/// can be locked apart or together. See the [module docs](self).
+pub struct SplitLock<A, B, P: MutexPermission, I: 'static> {
+    a: Mutex<A>,
+    b: Mutex<B>,
+    _permission: PhantomData<PermissionSyncSendWrapper<P>>,
+    _identifier: PhantomData<I>,
+}
+
+impl<A, B, P: MutexPermission, I: 'static> SplitLock<A, B, P, I> {
+    /// Create a new split lock over the two halves.
+    pub fn new(a: A, b: B, _identifier: I) -> Self {
+        let () = DepthCheck::<P>::WITHIN_MAX;
+        Self {
+            a: Mutex::new(a),
+            b: Mutex::new(b),
+            _permission: PhantomData,
+            _identifier: PhantomData,
+        }
+    }
+
+    /// Locks half A, blocking while another thread holds it.
+    pub fn lock_a(&self, permission: P) -> SplitLockResult<SplitGuardA<'_, A, B, P, I>> {
+        permission::check_origin(&permission);
+        let (a, poisoned) = taken(self.a.lock());
+        outcome(
+            poisoned,
+            SplitGuardA(a, &self.b, permission, PhantomData, Held::new()),
+        )
+    }
+
+    /// Locks half B, blocking while another thread holds it.
+    pub fn lock_b(&self, permission: P) -> SplitLockResult<SplitGuardB<'_, B, P, I>> {
+        permission::check_origin(&permission);
+        let (b, poisoned) = taken(self.b.lock());
+        outcome(
+            poisoned,
+            SplitGuardB(b, permission, PhantomData, Held::new()),
+        )
+    }
+
+    /// Locks half A, then half B.
+    pub fn lock_both(&self, permission: P) -> SplitLockResult<SplitGuardBoth<'_, A, B, P, I>> {
+        permission::check_origin(&permission);
+        let (a, a_poisoned) = taken(self.a.lock());
+        let (b, b_poisoned) = taken(self.b.lock());
+        outcome(
+            a_poisoned || b_poisoned,
+            SplitGuardBoth(a, b, permission, PhantomData, Held::new()),
+        )
+    }
+}
+
+/// Result of the [`SplitLock`] acquisitions. A poisoned half still hands
+/// out the guard, inside the error.
+pub type SplitLockResult<G> = LockResult<G, PoisonError<G>>;
+
+/// The guard and whether it was poisoned. Never poisoned under `no-poison`.
+fn taken<G>(result: sync::LockResult<G>) -> (G, bool) {
+    match result {
+        Ok(guard) => (guard, false),
+        Err(poisoned) => (poisoned.into_inner(), cfg!(not(feature = "no-poison"))),
+    }
+}
+
+#[cfg(not(feature = "no-poison"))]
+fn outcome<G>(poisoned: bool, guard: G) -> SplitLockResult<G> {
+    match poisoned {
+        true => Err(PoisonError::new(guard)),
+        false => Ok(guard),
+    }
+}
+#[cfg(feature = "no-poison")]
+fn outcome<G>(_poisoned: bool, guard: G) -> SplitLockResult<G> {
+    guard
+}
+
+/// Half A of a [`SplitLock`], locked.
+pub struct SplitGuardA<'a, A, B, P: MutexPermission, I: 'static>(
+    MutexGuard<'a, A>,
+    &'a Mutex<B>,
+    P,
+    PhantomData<I>,
+    Held,
+);
+
+impl<'a, A, B, P: MutexPermission, I: 'static> SplitGuardA<'a, A, B, P, I> {
+    /// Locks half B as well, keeping A.
+    pub fn also_b(self) -> SplitLockResult<SplitGuardBoth<'a, A, B, P, I>> {
+        let (b, poisoned) = taken(self.1.lock());
+        outcome(
+            poisoned,
+            SplitGuardBoth(self.0, b, self.2, PhantomData, self.4),
+        )
+    }
+
+    /// Unlock and return the permission token.
+    pub fn unlock(self) -> P {
+        self.2
+    }
+
+    /// Unlock and return a sequential permission token.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        SequentialMutexPermission::new(self.2)
+    }
+}
+
+impl<A, B, P: MutexPermission, I: 'static> Deref for SplitGuardA<'_, A, B, P, I> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        self.0.deref()
+    }
+}
+
+impl<A, B, P: MutexPermission, I: 'static> DerefMut for SplitGuardA<'_, A, B, P, I> {
+    fn deref_mut(&mut self) -> &mut A {
+        self.0.deref_mut()
+    }
+}
+
+/// Half B of a [`SplitLock`], locked.
+pub struct SplitGuardB<'a, B, P: MutexPermission, I: 'static>(
+    MutexGuard<'a, B>,
+    P,
+    PhantomData<I>,
+    Held,
+);
+
+impl<B, P: MutexPermission, I: 'static> SplitGuardB<'_, B, P, I> {
+    /// Unlock and return the permission token.
+    pub fn unlock(self) -> P {
+        self.1
+    }
+
+    /// Unlock and return a sequential permission token.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        SequentialMutexPermission::new(self.1)
+    }
+}
+
+impl<B, P: MutexPermission, I: 'static> Deref for SplitGuardB<'_, B, P, I> {
+    type Target = B;
+
+    fn deref(&self) -> &B {
+        self.0.deref()
+    }
+}
+
+impl<B, P: MutexPermission, I: 'static> DerefMut for SplitGuardB<'_, B, P, I> {
+    fn deref_mut(&mut self) -> &mut B {
+        self.0.deref_mut()
+    }
+}
+
+/// Both halves of a [`SplitLock`], locked.
+pub struct SplitGuardBoth<'a, A, B, P: MutexPermission, I: 'static>(
+    MutexGuard<'a, A>,
+    MutexGuard<'a, B>,
+    P,
+    PhantomData<I>,
+    Held,
+);
+
+impl<A, B, P: MutexPermission, I: 'static> SplitGuardBoth<'_, A, B, P, I> {
+    /// Half A.
+    pub fn a(&self) -> &A {
+        &self.0
+    }
+
+    /// Half B.
+    pub fn b(&self) -> &B {
+        &self.1
+    }
+
+    /// Both halves, mutably.
+    pub fn parts_mut(&mut self) -> (&mut A, &mut B) {
+        (&mut self.0, &mut self.1)
+    }
+
+    /// Unlock both halves and return the permission token.
+    pub fn unlock(self) -> P {
+        self.2
+    }
+
+    /// Unlock both halves and return a sequential permission token.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        SequentialMutexPermission::new(self.2)
+    }
+}
@@ -0,0 +1,310 @@
+//! Measuring whether a workload is better off nested or sequential.
+//!
+//! Code that touches two mutexes in turn can hold the first while it locks
+//! the second, with [`lock_for_nested`](crate::DeadlockProofMutex::lock_for_nested),
+//! or release it first, with
+//! [`unlock_for_sequential`](crate::DeadlockProofMutexGuard::unlock_for_sequential).
+//! Which one is faster depends on how long each critical section is, how
+//! much work there is in between, and how many threads contend. [`run`]
+//! measures both for a [`Workload`] describing those, and the
+//! [`StrategyReport`] has the throughput and latency percentiles of each:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use deadlock_proof::strategy_bench::{self, Workload};
+//!
+//! let report = strategy_bench::run(&Workload {
+//!     threads: 2,
+//!     operations: 200,
+//!     between: Duration::from_micros(5),
+//!     ..Workload::default()
+//! });
+//! assert_eq!(report.nested.operations, 400);
+//! println!("{report}");
+//! ```
+//!
+//! The work in each phase is a busy loop, so a run keeps `threads` cores
+//! busy. Both strategies run on the same pair of data, one after the other;
+//! the second mutex is declared once for each permission type.
+
+use std::{
+    fmt, hint,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    concurrent, DeadlockProofMutex, LockOutcome, NestedMutexPermission, OuterMutexPermission,
+    SequentialMutexPermission,
+};
+
+/// The two ways of locking the pair.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Strategy {
+    /// Hold the first mutex while locking the second.
+    Nested,
+    /// Release the first mutex before locking the second.
+    Sequential,
+}
+
+/// What every operation of a run does: lock the first mutex for
+/// `first_hold`, do `between` of work, then lock the second mutex for
+/// `second_hold`. Under [`Strategy::Nested`] the first mutex stays locked
+/// through `between` and the second critical section.
+#[derive(Clone, Debug)]
+pub struct Workload {
+    /// Number of threads running operations at once.
+    pub threads: usize,
+    /// Operations per thread.
+    pub operations: u64,
+    /// Work done holding the first mutex.
+    pub first_hold: Duration,
+    /// Work done between the two critical sections.
+    pub between: Duration,
+    /// Work done holding the second mutex.
+    pub second_hold: Duration,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Self {
+            threads: 4,
+            operations: 1_000,
+            first_hold: Duration::from_micros(1),
+            between: Duration::from_micros(1),
+            second_hold: Duration::from_micros(1),
+        }
+    }
+}
+
+/// How one strategy did.
+#[derive(Clone, Debug)]
+pub struct StrategyResult {
+    pub strategy: Strategy,
+    /// Operations completed, by all threads.
+    pub operations: u64,
+    /// Time from the start of the run until every thread was done.
+    pub elapsed: Duration,
+    /// Median time for one operation, including the waits for both locks.
+    pub p50: Duration,
+    /// 99th percentile time for one operation.
+    pub p99: Duration,
+    /// The slowest operation.
+    pub max: Duration,
+}
+
+impl StrategyResult {
+    /// Completed operations per second.
+    pub fn throughput(&self) -> f64 {
+        self.operations as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// The outcome of [`run`].
+#[derive(Clone, Debug)]
+pub struct StrategyReport {
+    pub workload: Workload,
+    pub nested: StrategyResult,
+    pub sequential: StrategyResult,
+}
+
+impl StrategyReport {
+    /// The strategy with the higher throughput.
+    pub fn faster(&self) -> Strategy {
+        if self.sequential.throughput() > self.nested.throughput() {
+            Strategy::Sequential
+        } else {
+            Strategy::Nested
+        }
+    }
+
+    /// The strategy with the lower 99th percentile latency.
+    pub fn lower_tail(&self) -> Strategy {
+        if self.sequential.p99 < self.nested.p99 {
+            Strategy::Sequential
+        } else {
+            Strategy::Nested
+        }
+    }
+}
+
+impl fmt::Display for StrategyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Workload {
+            threads,
+            first_hold,
+            between,
+            second_hold,
+            ..
+        } = &self.workload;
+        writeln!(
+            f,
+            "{threads} threads, holding {first_hold:?} / {between:?} between / {second_hold:?}"
+        )?;
+        for result in [&self.nested, &self.sequential] {
+            writeln!(
+                f,
+                "  {:<10} {:>10.0} ops/s  p50 {:>10?}  p99 {:>10?}  max {:>10?}",
+                format!("{:?}", result.strategy),
+                result.throughput(),
+                result.p50,
+                result.p99,
+                result.max,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct FirstLock;
+struct SecondLock;
+
+/// The pair both strategies lock, with the second mutex declared for each
+/// way of reaching it.
+struct Pair {
+    first: DeadlockProofMutex<u64, OuterMutexPermission, FirstLock>,
+    nested_second:
+        DeadlockProofMutex<u64, NestedMutexPermission<OuterMutexPermission, FirstLock>, SecondLock>,
+    sequential_second: DeadlockProofMutex<
+        u64,
+        SequentialMutexPermission<OuterMutexPermission, FirstLock>,
+        SecondLock,
+    >,
+}
+
+/// Busy for `duration`, like a computation of that length.
+fn work(duration: Duration) {
+    let started = Instant::now();
+    while started.elapsed() < duration {
+        hint::spin_loop();
+    }
+}
+
+impl Pair {
+    fn new() -> Self {
+        Self {
+            first: DeadlockProofMutex::new(0, FirstLock),
+            nested_second: DeadlockProofMutex::new(0, SecondLock),
+            sequential_second: DeadlockProofMutex::new(0, SecondLock),
+        }
+    }
+
+    fn operation(
+        &self,
+        strategy: Strategy,
+        workload: &Workload,
+        permission: OuterMutexPermission,
+    ) -> OuterMutexPermission {
+        match strategy {
+            Strategy::Nested => {
+                let (mut first, inside) = self.first.lock_for_nested(permission).guard();
+                *first += 1;
+                work(workload.first_hold);
+                work(workload.between);
+                let mut second = self.nested_second.lock(inside).guard();
+                *second += 1;
+                work(workload.second_hold);
+                first.unlock(second.unlock())
+            }
+            Strategy::Sequential => {
+                let mut first = self.first.lock(permission).guard();
+                *first += 1;
+                work(workload.first_hold);
+                let permission = first.unlock_for_sequential();
+                work(workload.between);
+                let mut second = self.sequential_second.lock(permission).guard();
+                *second += 1;
+                work(workload.second_hold);
+                second.unlock().to_earlier()
+            }
+        }
+    }
+
+    /// How many operations went through the second mutex of `strategy`.
+    fn completed(&self, strategy: Strategy) -> u64 {
+        concurrent::run_all_with(vec![Box::new(|permission| match strategy {
+            Strategy::Nested => {
+                let (first, inside) = self.first.lock_for_nested(permission).guard();
+                let second = self.nested_second.lock(inside).guard();
+                let count = *second;
+                (first.unlock(second.unlock()), count)
+            }
+            Strategy::Sequential => {
+                let first = self.first.lock(permission).guard();
+                let second = self
+                    .sequential_second
+                    .lock(first.unlock_for_sequential())
+                    .guard();
+                let count = *second;
+                (second.unlock().to_earlier(), count)
+            }
+        })])
+        .unwrap_or_else(|panic| panic.resume())[0]
+    }
+}
+
+/// The `percent`th percentile of `sorted`, which is not empty.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    sorted[(sorted.len() - 1) * percent / 100]
+}
+
+/// Runs `workload` with one strategy.
+fn run_on(pair: &Pair, strategy: Strategy, workload: &Workload) -> StrategyResult {
+    let started = Instant::now();
+    let workers = (0..workload.threads)
+        .map(|_| -> concurrent::WorkerWith<'_, Vec<Duration>> {
+            Box::new(move |mut permission| {
+                let mut latencies = Vec::with_capacity(workload.operations as usize);
+                for _ in 0..workload.operations {
+                    let began = Instant::now();
+                    permission = pair.operation(strategy, workload, permission);
+                    latencies.push(began.elapsed());
+                }
+                (permission, latencies)
+            })
+        })
+        .collect();
+    let latencies = concurrent::run_all_with(workers).unwrap_or_else(|panic| panic.resume());
+    let elapsed = started.elapsed();
+
+    let mut latencies: Vec<Duration> = latencies.into_iter().flatten().collect();
+    latencies.sort_unstable();
+    StrategyResult {
+        strategy,
+        operations: pair.completed(strategy),
+        elapsed,
+        p50: percentile(&latencies, 50),
+        p99: percentile(&latencies, 99),
+        max: latencies[latencies.len() - 1],
+    }
+}
+
+/// Runs `workload` under one strategy and reports on it. Blocks until
+/// every thread has done its operations.
+///
+/// Panics if `workload.threads` or `workload.operations` is 0.
+pub fn run_strategy(strategy: Strategy, workload: &Workload) -> StrategyResult {
+    check(workload);
+    run_on(&Pair::new(), strategy, workload)
+}
+
+/// Runs `workload` under both strategies, nested first, and compares them.
+///
+/// Panics if `workload.threads` or `workload.operations` is 0.
+pub fn run(workload: &Workload) -> StrategyReport {
+    check(workload);
+    let pair = Pair::new();
+    StrategyReport {
+        workload: workload.clone(),
+        nested: run_on(&pair, Strategy::Nested, workload),
+        sequential: run_on(&pair, Strategy::Sequential, workload),
+    }
+}
+
+fn check(workload: &Workload) {
+    assert!(workload.threads > 0, "a workload needs at least one thread");
+    assert!(
+        workload.operations > 0,
+        "a workload needs at least one operation"
+    );
+}
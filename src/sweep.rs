@@ -0,0 +1,76 @@
+//! Running one closure over many mutexes of the same level, one mutex at a
+//! time.
+//!
+//! Maintenance such as expiring idle entries visits every mutex of a level
+//! in turn. By hand, that is a loop threading the permission through each
+//! guard, and nothing stops a later edit from keeping one guard across the
+//! next lock. [`for_each_sequential`] is that loop: it locks the mutexes in
+//! iteration order, runs `f` on each, and unlocks it before locking the
+//! next, so it never holds two. [`try_for_each_sequential`] only takes the
+//! mutexes that are free right now and reports the others as skipped, for
+//! sweeps that should not wait for busy members.
+//!
+//! ```
+//! use deadlock_proof::{sweep, DeadlockProofMutex, OuterMutexPermission};
+//!
+//! struct SessionLock;
+//! let sessions: Vec<DeadlockProofMutex<Vec<u32>, OuterMutexPermission, _>> =
+//!     (0..4).map(|_| DeadlockProofMutex::new(vec![10, 200, 3000], SessionLock)).collect();
+//!
+//! let _permission = sweep::for_each_sequential(&sessions, OuterMutexPermission::get(), |idle| {
+//!     idle.retain(|&seconds| seconds < 300)
+//! });
+//! ```
+//!
+//! [`MutexFamily::sweep`](crate::MutexFamily::sweep) and
+//! [`OrderedLockMap::sweep`](crate::OrderedLockMap::sweep) do the same over
+//! their members and entries.
+
+use crate::{rt::TryLockError, DeadlockProofMutex, LockOutcome, MutexPermission};
+
+/// Locks each of `mutexes` in iteration order, runs `f` on its data and
+/// unlocks it again before going on to the next. Returns the permission.
+///
+/// Panics if a mutex is poisoned.
+#[track_caller]
+pub fn for_each_sequential<'a, T: 'a, P: MutexPermission, I: 'static>(
+    mutexes: impl IntoIterator<Item = &'a DeadlockProofMutex<T, P, I>>,
+    mut permission: P,
+    mut f: impl FnMut(&mut T),
+) -> P {
+    for mutex in mutexes {
+        let mut guard = mutex.lock(permission).guard();
+        f(&mut guard);
+        permission = guard.unlock();
+    }
+    permission
+}
+
+/// [`for_each_sequential`], but skips the mutexes that are locked elsewhere
+/// instead of waiting for them. Returns the permission and the positions of
+/// the skipped mutexes in the iteration.
+///
+/// Panics if a mutex it tries is poisoned.
+#[track_caller]
+pub fn try_for_each_sequential<'a, T: 'a, P: MutexPermission, I: 'static>(
+    mutexes: impl IntoIterator<Item = &'a DeadlockProofMutex<T, P, I>>,
+    mut permission: P,
+    mut f: impl FnMut(&mut T),
+) -> (P, Vec<usize>) {
+    let mut skipped = Vec::new();
+    for (index, mutex) in mutexes.into_iter().enumerate() {
+        permission = match mutex.try_guard(permission) {
+            Ok(mut guard) => {
+                f(&mut guard);
+                guard.unlock()
+            }
+            Err(TryLockError::WouldBlock(permission)) => {
+                skipped.push(index);
+                permission
+            }
+            #[cfg(not(feature = "no-poison"))]
+            Err(TryLockError::Poisoned(_)) => panic!("a swept mutex is poisoned"),
+        };
+    }
+    (permission, skipped)
+}
@@ -0,0 +1,432 @@
+//! Permissions and mutexes for async tasks.
+//!
+//! A task may move between threads at every `.await`, so it cannot use the
+//! thread-local [`OuterMutexPermission`](crate::OuterMutexPermission).
+//! Instead each task owns an [`AsyncPermissionSlot`] and claims its
+//! [`AsyncPermission`] from there. The permission is `Send`, and it can be
+//! put back into its slot if the code holding it is abandoned, e.g. when a
+//! future is dropped half way through an acquisition.
+//!
+//! Blocking work the task hands to another thread, e.g. with tokio's
+//! `spawn_blocking`, takes the task permission along through
+//! [`AsyncPermission::delegate_blocking`]. The blocking side then locks the
+//! same sync mutexes the task would, at the task's position in the order,
+//! and the task only gets its permission back once that work is done.
+
+use std::{
+    cell::UnsafeCell,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    depth::DepthCheck, permission, MutexPermission, NamespacePermission, PermissionCell,
+    PermissionChain, PermissionDepth, PermissionSyncSendWrapper, SequentialMutexPermission,
+};
+
+/// The root permission of an async task. Claimed from the task's
+/// [`AsyncPermissionSlot`]; create one slot per task.
+pub struct AsyncPermission {
+    parked: Arc<AtomicBool>,
+}
+
+impl MutexPermission for AsyncPermission {
+    fn recover(self) {
+        self.parked.store(true, Ordering::Release);
+    }
+}
+
+impl PermissionChain for AsyncPermission {
+    fn visit_levels(f: &mut dyn FnMut(&'static str)) {
+        f("Async");
+    }
+}
+
+impl PermissionDepth for AsyncPermission {
+    const DEPTH: usize = 0;
+}
+
+impl NamespacePermission for AsyncPermission {
+    type In<N: 'static> = AsyncPermission;
+}
+
+impl AsyncPermission {
+    /// Hands the permission to blocking work running on another thread.
+    ///
+    /// The [`BlockingDelegate`] is moved into the blocking closure and lends
+    /// it the permission there, so the sync mutexes it locks are ordered with
+    /// the task's own locks. Awaiting the [`RejoinHandle`] gives the
+    /// permission back once the delegate is done with it.
+    pub fn delegate_blocking(self) -> (BlockingDelegate, RejoinHandle) {
+        let handoff = Arc::new(Mutex::new(Handoff::Delegated(None)));
+        (
+            BlockingDelegate {
+                permission: Some(self),
+                handoff: Arc::clone(&handoff),
+            },
+            RejoinHandle { handoff },
+        )
+    }
+}
+
+/// Where a delegated permission is on its way back to the task.
+enum Handoff {
+    /// With the delegate; the waker is the awaiting task's.
+    Delegated(Option<Waker>),
+    Returned(AsyncPermission),
+    /// The delegate panicked while it had lent the permission out.
+    Abandoned,
+    /// The task stopped waiting, so a returned permission is recovered.
+    Detached,
+}
+
+/// The blocking half of [`AsyncPermission::delegate_blocking`]. `Send`, so it
+/// can be moved into a closure running on a blocking thread.
+pub struct BlockingDelegate {
+    /// Only `None` while [`run`](Self::run) has lent it out.
+    permission: Option<AsyncPermission>,
+    handoff: Arc<Mutex<Handoff>>,
+}
+
+impl BlockingDelegate {
+    /// Runs `f` with the task's permission. `f` hands the permission back
+    /// along with its result, and the [`RejoinHandle`] completes once `run`
+    /// returns.
+    ///
+    /// For the duration of `f` this thread's own root permission is withheld,
+    /// so [`OuterMutexPermission::get`](crate::OuterMutexPermission::get)
+    /// cannot claim a second, unordered root. If `f` panics, the permission is
+    /// lost and the handle reports [`DelegationAbandoned`].
+    pub fn run<R>(mut self, f: impl FnOnce(AsyncPermission) -> (AsyncPermission, R)) -> R {
+        let withheld = WithheldRoot(permission::withhold_token());
+        let (permission, result) = f(self.permission.take().unwrap());
+        drop(withheld);
+        self.permission = Some(permission);
+        result
+    }
+}
+
+impl Drop for BlockingDelegate {
+    fn drop(&mut self) {
+        let mut handoff = self.handoff.lock().unwrap_or_else(|e| e.into_inner());
+        let returned = match self.permission.take() {
+            Some(permission) => Handoff::Returned(permission),
+            None => Handoff::Abandoned,
+        };
+        match std::mem::replace(&mut *handoff, returned) {
+            Handoff::Delegated(waker) => {
+                drop(handoff);
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+            Handoff::Detached => {
+                if let Handoff::Returned(permission) =
+                    std::mem::replace(&mut *handoff, Handoff::Detached)
+                {
+                    permission.recover();
+                }
+            }
+            Handoff::Returned(_) | Handoff::Abandoned => unreachable!("delegate dropped twice"),
+        }
+    }
+}
+
+/// Puts the blocking thread's root permission back when `run` ends, panics
+/// included.
+struct WithheldRoot(Option<crate::OuterMutexPermission>);
+
+impl Drop for WithheldRoot {
+    fn drop(&mut self) {
+        if let Some(token) = self.0.take() {
+            permission::restore_token(token);
+        }
+    }
+}
+
+/// The task's half of [`AsyncPermission::delegate_blocking`]. Resolves to the
+/// permission once the [`BlockingDelegate`] has been dropped. Dropping the
+/// handle instead recovers the permission into its slot when it comes back.
+pub struct RejoinHandle {
+    handoff: Arc<Mutex<Handoff>>,
+}
+
+impl Future for RejoinHandle {
+    type Output = Result<AsyncPermission, DelegationAbandoned>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut handoff = self.handoff.lock().unwrap_or_else(|e| e.into_inner());
+        match &mut *handoff {
+            Handoff::Delegated(waker) => {
+                *waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            Handoff::Detached => panic!("RejoinHandle polled after completion"),
+            Handoff::Returned(_) | Handoff::Abandoned => {}
+        }
+        match std::mem::replace(&mut *handoff, Handoff::Detached) {
+            Handoff::Returned(permission) => Poll::Ready(Ok(permission)),
+            _ => Poll::Ready(Err(DelegationAbandoned)),
+        }
+    }
+}
+
+impl Drop for RejoinHandle {
+    fn drop(&mut self) {
+        let mut handoff = self.handoff.lock().unwrap_or_else(|e| e.into_inner());
+        if let Handoff::Returned(permission) = std::mem::replace(&mut *handoff, Handoff::Detached) {
+            permission.recover();
+        }
+    }
+}
+
+/// The blocking side lost the delegated permission by panicking inside
+/// [`BlockingDelegate::run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DelegationAbandoned;
+
+impl fmt::Display for DelegationAbandoned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the blocking delegate did not return the task permission")
+    }
+}
+
+impl std::error::Error for DelegationAbandoned {}
+
+/// Where a task's [`AsyncPermission`] lives while nobody holds it. Clones
+/// share the same permission.
+#[derive(Clone)]
+pub struct AsyncPermissionSlot {
+    parked: Arc<AtomicBool>,
+}
+
+impl AsyncPermissionSlot {
+    /// A fresh slot holding a new task permission.
+    pub fn new() -> Self {
+        Self {
+            parked: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Takes the permission out of the slot, if it is there.
+    pub fn claim(&self) -> Option<AsyncPermission> {
+        self.parked
+            .swap(false, Ordering::Acquire)
+            .then(|| AsyncPermission {
+                parked: Arc::clone(&self.parked),
+            })
+    }
+
+    /// Whether the permission is currently in the slot.
+    pub fn is_parked(&self) -> bool {
+        self.parked.load(Ordering::Acquire)
+    }
+}
+
+impl Default for AsyncPermissionSlot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A mutex for async tasks which is compile-time guaranteed not to deadlock.
+/// Waiting for it yields to the executor rather than blocking the thread.
+pub struct DeadlockProofAsyncMutex<T, P: MutexPermission, I: 'static> {
+    locked: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+    data: UnsafeCell<T>,
+    _permission: PhantomData<PermissionSyncSendWrapper<P>>,
+    _identifier: PhantomData<I>,
+}
+
+// Safety: `data` is only reached through a guard, and `locked` ensures there
+// is at most one guard at a time.
+unsafe impl<T: Send, P: MutexPermission, I: 'static> Sync for DeadlockProofAsyncMutex<T, P, I> {}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofAsyncMutex<T, P, I> {
+    /// Create a new deadlock-proof async mutex.
+    pub fn new(content: T, _identifier: I) -> Self {
+        let () = DepthCheck::<P>::WITHIN_MAX;
+        Self {
+            locked: AtomicBool::new(false),
+            wakers: Mutex::new(Vec::new()),
+            data: UnsafeCell::new(content),
+            _permission: PhantomData,
+            _identifier: PhantomData,
+        }
+    }
+
+    /// Acquires the mutex, waiting asynchronously. The permission is kept in
+    /// a [`PermissionCell`] while waiting, so dropping the future before it
+    /// completes recovers it.
+    pub fn lock(&self, permission: P) -> AsyncLock<'_, T, P, I> {
+        AsyncLock {
+            mutex: self,
+            permission: PermissionCell::new(permission),
+        }
+    }
+
+    /// One acquisition attempt for hand-written futures. If the mutex is
+    /// held, `cx`'s waker is registered for its release and the permission
+    /// is handed back so it can be stored until the next poll.
+    pub fn lock_or_register(
+        &self,
+        cx: &mut Context<'_>,
+        permission: P,
+    ) -> Result<AsyncMutexGuard<'_, T, P, I>, P> {
+        let permission = match self.try_acquire(cx.waker(), permission) {
+            Ok(guard) => return Ok(guard),
+            Err(permission) => permission,
+        };
+        self.wakers.lock().unwrap().push(cx.waker().clone());
+        // Retry in case the holder released before the waker was in place.
+        self.try_acquire(cx.waker(), permission)
+    }
+
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    fn try_acquire(&self, task: &Waker, permission: P) -> Result<AsyncMutexGuard<'_, T, P, I>, P> {
+        match self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        {
+            Ok(_) => Ok(AsyncMutexGuard {
+                mutex: self,
+                permission: Some(permission),
+                #[cfg(debug_assertions)]
+                task: held::acquired(task),
+                _data: PhantomData,
+            }),
+            Err(_) => Err(permission),
+        }
+    }
+
+    fn release(&self) {
+        self.locked.store(false, Ordering::Release);
+        // Every waiter retries; the ones that lose the race re-register.
+        for waker in self.wakers.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Future returned by [`DeadlockProofAsyncMutex::lock`].
+pub struct AsyncLock<'a, T, P: MutexPermission, I: 'static> {
+    mutex: &'a DeadlockProofAsyncMutex<T, P, I>,
+    permission: PermissionCell<P>,
+}
+
+impl<'a, T, P: MutexPermission, I: 'static> Future for AsyncLock<'a, T, P, I> {
+    type Output = AsyncMutexGuard<'a, T, P, I>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let permission = this
+            .permission
+            .poll_take()
+            .expect("AsyncLock polled after completion");
+        match this.mutex.lock_or_register(cx, permission) {
+            Ok(guard) => Poll::Ready(guard),
+            Err(permission) => {
+                this.permission.store(permission);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Guard for a [`DeadlockProofAsyncMutex`]. May be held across `.await`.
+/// Dropping it instead of calling [`unlock`](Self::unlock) recovers the
+/// permission, as with a dropped [`PermissionCell`].
+pub struct AsyncMutexGuard<'a, T, P: MutexPermission, I: 'static> {
+    mutex: &'a DeadlockProofAsyncMutex<T, P, I>,
+    /// Only `None` once `unlock` has taken it out.
+    permission: Option<P>,
+    /// The task that acquired the guard.
+    #[cfg(debug_assertions)]
+    task: Waker,
+    /// Gives the guard the auto traits of `&mut T`.
+    _data: PhantomData<&'a mut T>,
+}
+
+impl<T, P: MutexPermission, I: 'static> AsyncMutexGuard<'_, T, P, I> {
+    /// Unlock the mutex and return the permission token.
+    pub fn unlock(mut self) -> P {
+        self.permission.take().unwrap()
+    }
+
+    /// Unlock the mutex and return a sequential permission token.
+    pub fn unlock_for_sequential(self) -> SequentialMutexPermission<P, I> {
+        SequentialMutexPermission::new(self.unlock())
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Drop for AsyncMutexGuard<'_, T, P, I> {
+    fn drop(&mut self) {
+        self.mutex.release();
+        #[cfg(debug_assertions)]
+        held::released(&self.task);
+        if let Some(permission) = self.permission.take() {
+            permission.recover();
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Deref for AsyncMutexGuard<'_, T, P, I> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: the guard owns the lock.
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> DerefMut for AsyncMutexGuard<'_, T, P, I> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the guard owns the lock, and `&mut self` makes this the only
+        // reference through it.
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+/// Which tasks hold an [`AsyncMutexGuard`], in debug builds, so that
+/// [`AsyncNotify`](crate::notify::AsyncNotify) can catch a task waiting for
+/// a notification with a lock held. Tasks are told apart by their wakers.
+#[cfg(debug_assertions)]
+pub(crate) mod held {
+    use std::{
+        sync::{Mutex, MutexGuard, PoisonError},
+        task::Waker,
+    };
+
+    /// One entry per live guard.
+    static HELD: Mutex<Vec<Waker>> = Mutex::new(Vec::new());
+
+    fn held() -> MutexGuard<'static, Vec<Waker>> {
+        HELD.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn acquired(task: &Waker) -> Waker {
+        held().push(task.clone());
+        task.clone()
+    }
+
+    pub(crate) fn released(task: &Waker) {
+        let mut held = held();
+        if let Some(index) = held.iter().position(|holder| holder.will_wake(task)) {
+            held.swap_remove(index);
+        }
+    }
+
+    /// Whether the task woken by `task` holds any async guard.
+    pub(crate) fn by(task: &Waker) -> bool {
+        held().iter().any(|holder| holder.will_wake(task))
+    }
+}
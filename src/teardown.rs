@@ -0,0 +1,164 @@
+//! Taking a shared structure of locks apart once nobody uses it any more.
+//!
+//! A structure such as a [`NetworkStack`](crate::NetworkStack) is usually
+//! shared in an [`Arc`]. Dropping the last `Arc` while some thread still
+//! holds a guard cannot happen, since the guard borrows through an `Arc` of
+//! its own, but a guard that was leaked keeps its lock locked for good, and
+//! which thread ends up dropping the last `Arc` is anybody's guess.
+//! [`try_teardown`] makes it explicit: it succeeds only if the caller has the
+//! one `Arc` left, with no [`Weak`](std::sync::Weak) either, and none of the
+//! structure's locks is held, and then hands out what the locks protected.
+//! Otherwise the `Arc` comes back unchanged. [`teardown_when_idle`] tries
+//! again until a deadline, for stragglers that are about to let go.
+//!
+//! Structures opt in through [`TearDown`], which the deadlock-proof locks and
+//! tuples of them already implement, so a structure of locks implements it
+//! by asking its fields:
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use deadlock_proof::{teardown, DeadlockProofMutex, OuterMutexPermission, TearDown};
+//!
+//! struct QueueLock;
+//! struct StatsLock;
+//! struct Server {
+//!     queue: DeadlockProofMutex<Vec<u32>, OuterMutexPermission, QueueLock>,
+//!     stats: DeadlockProofMutex<u64, OuterMutexPermission, StatsLock>,
+//! }
+//!
+//! impl TearDown for Server {
+//!     type States = (Vec<u32>, u64);
+//!
+//!     fn is_idle(&self) -> bool {
+//!         self.queue.is_idle() && self.stats.is_idle()
+//!     }
+//!
+//!     fn into_states(self) -> Self::States {
+//!         (self.queue, self.stats).into_states()
+//!     }
+//! }
+//!
+//! let server = Arc::new(Server {
+//!     queue: DeadlockProofMutex::new(vec![7], QueueLock),
+//!     stats: DeadlockProofMutex::new(0, StatsLock),
+//! });
+//! let straggler = Arc::clone(&server);
+//! let server = teardown::try_teardown(server).unwrap_err();
+//! drop(straggler);
+//! assert_eq!(teardown::try_teardown(server).ok(), Some((vec![7], 0)));
+//! ```
+//!
+//! The states are handed out whether or not a lock was poisoned: nobody is
+//! left to be warned.
+
+use std::{
+    sync::{Arc, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{clock, DeadlockProofMutex, DeadlockProofRwLock, MutexPermission};
+
+/// The longest pause between two attempts of [`teardown_when_idle`].
+const MAX_PAUSE: Duration = Duration::from_millis(1);
+
+/// A structure of locks that can be taken apart into what they protect.
+/// See the [module docs](self).
+pub trait TearDown: Sized {
+    /// What the locks protected.
+    type States;
+
+    /// Whether none of the locks is held right now, by a leaked guard
+    /// either.
+    fn is_idle(&self) -> bool;
+
+    /// Takes the structure apart. Only called once [`is_idle`](Self::is_idle)
+    /// said so.
+    fn into_states(self) -> Self::States;
+}
+
+/// Takes `shared` apart if the caller has the only reference to it and it
+/// is idle, or hands it back.
+pub fn try_teardown<S: TearDown>(mut shared: Arc<S>) -> Result<S::States, Arc<S>> {
+    match Arc::get_mut(&mut shared) {
+        Some(unique) if unique.is_idle() => {}
+        _ => return Err(shared),
+    }
+    // Nobody could have cloned or upgraded a reference since: there is none.
+    Arc::try_unwrap(shared).map(TearDown::into_states)
+}
+
+/// [`try_teardown`], attempted again until `deadline`, pausing a little
+/// longer after each miss.
+pub fn teardown_when_idle<S: TearDown>(
+    mut shared: Arc<S>,
+    deadline: Instant,
+) -> Result<S::States, Arc<S>> {
+    let mut pause = Duration::from_micros(1);
+    loop {
+        shared = match try_teardown(shared) {
+            Ok(states) => return Ok(states),
+            Err(shared) => shared,
+        };
+        let now = clock::now();
+        if now >= deadline {
+            return Err(shared);
+        }
+        clock::sleep(pause.min(deadline - now));
+        pause = (pause * 2).min(MAX_PAUSE);
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> TearDown for DeadlockProofMutex<T, P, I> {
+    type States = T;
+
+    fn is_idle(&self) -> bool {
+        !self.is_locked()
+    }
+
+    fn into_states(self) -> T {
+        self.inner
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> TearDown for DeadlockProofRwLock<T, P, I> {
+    type States = T;
+
+    fn is_idle(&self) -> bool {
+        self.inner.is_idle()
+    }
+
+    fn into_states(self) -> T {
+        self.inner
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Tuples of structures, so that structures can take their fields apart
+/// together.
+macro_rules! tuple_teardown {
+    ($($name:ident),+) => {
+        impl<$($name: TearDown),+> TearDown for ($($name,)+) {
+            type States = ($($name::States,)+);
+
+            #[allow(non_snake_case)]
+            fn is_idle(&self) -> bool {
+                let ($($name,)+) = self;
+                true $(&& $name.is_idle())+
+            }
+
+            #[allow(non_snake_case)]
+            fn into_states(self) -> Self::States {
+                let ($($name,)+) = self;
+                ($($name.into_states(),)+)
+            }
+        }
+    };
+}
+
+tuple_teardown!(A, B);
+tuple_teardown!(A, B, C);
+tuple_teardown!(A, B, C, D);
@@ -0,0 +1,111 @@
+//! Mutexes whose data must only ever be touched from the thread that made them.
+//!
+//! Lock ordering says nothing about *which* thread takes a lock, so a
+//! thread-affine structure shared by mistake would still type-check. A
+//! [`ThreadPinnedMutex`] remembers its creating thread and refuses to lock
+//! anywhere else, handing the permission back so the caller can carry on.
+
+use std::{
+    error::Error,
+    fmt,
+    thread::{self, ThreadId},
+};
+
+#[cfg(feature = "no-poison")]
+use std::{convert::Infallible, marker::PhantomData};
+
+use crate::{
+    DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofPoisonError, MutexPermission,
+    OuterMutexPermission,
+};
+
+/// A [`DeadlockProofMutex`] that may only be locked on its owning thread.
+pub struct ThreadPinnedMutex<T, P: MutexPermission, I: 'static> {
+    mutex: DeadlockProofMutex<T, P, I>,
+    owner: ThreadId,
+}
+
+/// Why [`ThreadPinnedMutex::lock`] failed.
+pub enum PinnedLockError<'a, T, P: MutexPermission, I: 'static> {
+    /// The mutex belongs to another thread. The permission is returned
+    /// unused.
+    WrongThread(P),
+    /// The mutex is poisoned, as with [`DeadlockProofMutex::lock`].
+    #[cfg(not(feature = "no-poison"))]
+    Poisoned(DeadlockProofPoisonError<'a, T, P, I>),
+    /// Never constructed; only keeps `'a`, `T` and `I` in use under
+    /// `no-poison`.
+    #[cfg(feature = "no-poison")]
+    #[doc(hidden)]
+    Poisoned(Infallible, PhantomData<DeadlockProofPoisonError<'a, T, P, I>>),
+}
+
+impl<T, P: MutexPermission, I: 'static> fmt::Debug for PinnedLockError<'_, T, P, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinnedLockError::WrongThread(_) => f.write_str("WrongThread(..)"),
+            #[cfg(not(feature = "no-poison"))]
+            PinnedLockError::Poisoned(_) => f.write_str("Poisoned(..)"),
+            #[cfg(feature = "no-poison")]
+            PinnedLockError::Poisoned(never, _) => match *never {},
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> fmt::Display for PinnedLockError<'_, T, P, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinnedLockError::WrongThread(_) => {
+                f.write_str("thread-pinned mutex locked from a thread other than its owner")
+            }
+            #[cfg(not(feature = "no-poison"))]
+            PinnedLockError::Poisoned(_) => f.write_str("thread-pinned mutex is poisoned"),
+            #[cfg(feature = "no-poison")]
+            PinnedLockError::Poisoned(never, _) => match *never {},
+        }
+    }
+}
+
+impl<T, P: MutexPermission, I: 'static> Error for PinnedLockError<'_, T, P, I> {}
+
+impl<T, P: MutexPermission, I: 'static> ThreadPinnedMutex<T, P, I> {
+    /// Creates a mutex owned by the current thread. Borrowing the thread's
+    /// outer permission ties the owner to a thread that takes part in the
+    /// permission system.
+    pub fn new(content: T, identifier: I, _owner: &OuterMutexPermission) -> Self {
+        Self {
+            mutex: DeadlockProofMutex::new(content, identifier),
+            owner: thread::current().id(),
+        }
+    }
+
+    /// See [`DeadlockProofMutex::poison_for_test`]. Works from any thread.
+    #[cfg(feature = "test-util")]
+    pub fn poison_for_test(&self) {
+        self.mutex.poison_for_test()
+    }
+
+    /// The thread this mutex is pinned to.
+    pub fn owner(&self) -> ThreadId {
+        self.owner
+    }
+
+    /// Locks the mutex if called from the owning thread. From any other
+    /// thread this fails with [`PinnedLockError::WrongThread`] without
+    /// touching the mutex, in debug and release builds alike.
+    pub fn lock(
+        &self,
+        permission: P,
+    ) -> Result<DeadlockProofMutexGuard<'_, T, P, I>, PinnedLockError<'_, T, P, I>> {
+        if thread::current().id() != self.owner {
+            return Err(PinnedLockError::WrongThread(permission));
+        }
+        #[cfg(not(feature = "no-poison"))]
+        return self
+            .mutex
+            .lock(permission)
+            .map_err(PinnedLockError::Poisoned);
+        #[cfg(feature = "no-poison")]
+        Ok(self.mutex.lock(permission))
+    }
+}
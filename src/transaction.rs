@@ -0,0 +1,215 @@
+//! All-or-nothing updates across every layer of a [`NetworkStack`].
+//!
+//! Changes are staged as closures while no lock is held. [`commit`] then
+//! takes all three layers in hierarchy order, runs each layer's staged
+//! closures against a scratch copy, and only writes the copies back once
+//! every layer has accepted its changes. A rejection anywhere leaves the
+//! whole stack exactly as it was.
+//!
+//! [`commit`]: StackTransaction::commit
+
+use std::{error::Error, fmt, panic::Location};
+
+use crate::{
+    release::InnerGuard, version::Dirty, DeadlockProofMutex, Held, DeviceState, IpState, MutexPermission, Namespace,
+    NetworkStack, OuterMutexPermission, RootNamespace, TransportState,
+};
+
+/// One layer of a [`NetworkStack`], in hierarchy order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackLayer {
+    Ip,
+    Device,
+    Transport,
+}
+
+/// Why a [`StackTransaction`] was not applied. Nothing was written either way.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TxAborted {
+    /// A staged closure for `layer` returned `Err(reason)`.
+    Rejected { layer: StackLayer, reason: String },
+    /// `layer` was poisoned by a panic in another critical section.
+    #[cfg(not(feature = "no-poison"))]
+    Poisoned(StackLayer),
+}
+
+impl fmt::Display for TxAborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxAborted::Rejected { layer, reason } => {
+                write!(f, "transaction rejected at {layer:?} layer: {reason}")
+            }
+            #[cfg(not(feature = "no-poison"))]
+            TxAborted::Poisoned(layer) => write!(f, "{layer:?} layer is poisoned"),
+        }
+    }
+}
+
+impl Error for TxAborted {}
+
+type Staged<'a, S> = Vec<Box<dyn FnOnce(&mut S) -> Result<(), String> + 'a>>;
+
+/// A set of staged per-layer changes, created by
+/// [`NetworkStack::transaction`].
+///
+/// Each staged closure mutates a scratch copy of its layer and may reject the
+/// transaction by returning `Err`. Closures for the same layer run in the
+/// order they were staged, each seeing the previous ones' changes.
+pub struct StackTransaction<'a, N: Namespace = RootNamespace> {
+    stack: &'a NetworkStack<N>,
+    ip: Staged<'a, IpState>,
+    device: Staged<'a, DeviceState>,
+    transport: Staged<'a, TransportState>,
+}
+
+impl<N: Namespace> NetworkStack<N> {
+    /// Starts an empty transaction over this stack. Staging takes no locks.
+    pub fn transaction(&self) -> StackTransaction<'_, N> {
+        StackTransaction {
+            stack: self,
+            ip: Vec::new(),
+            device: Vec::new(),
+            transport: Vec::new(),
+        }
+    }
+}
+
+impl<'a, N: Namespace> StackTransaction<'a, N> {
+    /// Stages a change to the IP layer.
+    pub fn stage_ip(mut self, f: impl FnOnce(&mut IpState) -> Result<(), String> + 'a) -> Self {
+        self.ip.push(Box::new(f));
+        self
+    }
+
+    /// Stages a change to the device layer.
+    pub fn stage_device(
+        mut self,
+        f: impl FnOnce(&mut DeviceState) -> Result<(), String> + 'a,
+    ) -> Self {
+        self.device.push(Box::new(f));
+        self
+    }
+
+    /// Stages a change to the transport layer.
+    pub fn stage_transport(
+        mut self,
+        f: impl FnOnce(&mut TransportState) -> Result<(), String> + 'a,
+    ) -> Self {
+        self.transport.push(Box::new(f));
+        self
+    }
+
+    /// Applies every staged change, or none of them.
+    ///
+    /// Unlike a sequential walk, all three layers stay locked until the
+    /// write-back is done, so no other thread can observe a half-applied
+    /// transaction. This cannot deadlock: the outer permission proves the
+    /// thread holds no other deadlock-proof lock, and the layers are taken in
+    /// the same order every walker uses. The one path that takes them in
+    /// another order, [`icmp_error_path`](NetworkStack::icmp_error_path),
+    /// passes the stack's gate first, and so does the commit while it takes
+    /// the layers. Once it holds all three it waits for nothing more, so it
+    /// leaves the gate before the staged closures run.
+    ///
+    /// Each layer is released as any guard is: its
+    /// [release invariant](crate::invariant) is checked against the
+    /// written-back state, which panics here on a violation.
+    #[track_caller]
+    pub fn commit(
+        self,
+        permission: OuterMutexPermission,
+    ) -> (OuterMutexPermission, Result<(), TxAborted>) {
+        let gate = self.stack.error_path.lock(permission);
+        let layers = Layers::lock(self.stack, Location::caller());
+        let permission = gate.unlock();
+        (permission, layers.and_then(|layers| self.apply(layers)))
+    }
+
+    fn apply(self, mut layers: Layers<'_>) -> Result<(), TxAborted> {
+        let ip_draft = draft(&*layers.ip.0, self.ip, StackLayer::Ip)?;
+        let device_draft = draft(&*layers.device.0, self.device, StackLayer::Device)?;
+        let transport_draft = draft(&*layers.transport.0, self.transport, StackLayer::Transport)?;
+
+        if let Some(state) = ip_draft {
+            *layers.ip.0 = state;
+            layers.ip_dirty.mark();
+        }
+        if let Some(state) = device_draft {
+            *layers.device.0 = state;
+            layers.device_dirty.mark();
+        }
+        if let Some(state) = transport_draft {
+            *layers.transport.0 = state;
+            layers.transport_dirty.mark();
+        }
+        Ok(())
+    }
+}
+
+/// Every layer of a stack, held for a commit.
+struct Layers<'a> {
+    transport: (InnerGuard<'a, TransportState>, Held),
+    device: (InnerGuard<'a, DeviceState>, Held),
+    ip: (InnerGuard<'a, IpState>, Held),
+    // Declared after the guards so they only drop once all three layers
+    // have been released.
+    ip_dirty: Dirty<'a>,
+    device_dirty: Dirty<'a>,
+    transport_dirty: Dirty<'a>,
+}
+
+impl<'a> Layers<'a> {
+    /// Locks the layers of `stack` in hierarchy order, as guards acquired
+    /// at `location`.
+    fn lock<N: Namespace>(
+        stack: &'a NetworkStack<N>,
+        location: &'static Location<'static>,
+    ) -> Result<Self, TxAborted> {
+        let ip = acquire_layer(&stack.ip_layer, StackLayer::Ip, location)?;
+        let device = acquire_layer(&stack.device_layer, StackLayer::Device, location)?;
+        let transport = acquire_layer(&stack.transport_layer, StackLayer::Transport, location)?;
+        Ok(Self {
+            transport,
+            device,
+            ip,
+            ip_dirty: Dirty::clean(&stack.ip_layer.versions),
+            device_dirty: Dirty::clean(&stack.device_layer.versions),
+            transport_dirty: Dirty::clean(&stack.transport_layer.versions),
+        })
+    }
+}
+
+/// Locks one layer for the rest of the transaction, with the bookkeeping of
+/// a guard acquired at `location`, so that releasing it checks the release
+/// invariant, logs the diff and clears the holder record like any other.
+fn acquire_layer<'a, T, P: MutexPermission, I: 'static>(
+    mutex: &'a DeadlockProofMutex<T, P, I>,
+    layer: StackLayer,
+    location: &'static Location<'static>,
+) -> Result<(InnerGuard<'a, T>, Held), TxAborted> {
+    #[cfg(not(feature = "no-poison"))]
+    let guard = mutex.acquire().map_err(|_| TxAborted::Poisoned(layer))?;
+    #[cfg(feature = "no-poison")]
+    let guard = {
+        let _ = layer;
+        mutex.acquire()
+    };
+    Ok((mutex.inner_guard(guard, location), Held::new()))
+}
+
+/// Runs `staged` against a copy of `state`. `None` if nothing was staged.
+fn draft<S: Clone>(
+    state: &S,
+    staged: Staged<'_, S>,
+    layer: StackLayer,
+) -> Result<Option<S>, TxAborted> {
+    if staged.is_empty() {
+        return Ok(None);
+    }
+    let mut draft = state.clone();
+    for f in staged {
+        f(&mut draft).map_err(|reason| TxAborted::Rejected { layer, reason })?;
+    }
+    Ok(Some(draft))
+}
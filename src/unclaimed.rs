@@ -0,0 +1,219 @@
+//! Locking on threads that never claimed their permission token.
+//!
+//! Library code often runs on threads it did not create, such as the
+//! workers of a dependency's pool, where nobody called
+//! [`OuterMutexPermission::get`]. An outer mutex can be locked there without
+//! a permission in hand with
+//! [`lock_without_permission`](DeadlockProofMutex::lock_without_permission),
+//! which does what the crate's [`Policy`] says:
+//!
+//! - [`Policy::Panic`], the default, panics with the lock site: the thread
+//!   is expected to claim its token itself;
+//! - [`Policy::Error`] returns [`ClaimError::Unclaimed`];
+//! - [`Policy::AutoClaim`] claims the thread's token for as long as the
+//!   returned [`AutoClaimGuard`] lives, and gives it back to the thread when
+//!   the guard is dropped. The claim counts like any other, so the thread
+//!   still holds at most one root at a time, and the diagnostics show it as
+//!   implicitly claimed at the lock site.
+//!
+//! [`lock_autoclaim`](DeadlockProofMutex::lock_autoclaim) claims implicitly
+//! whatever the policy. The policy is set with [`set_policy`] or
+//! [`Config::unclaimed_policy`](crate::Config::unclaimed_policy).
+//!
+//! ```
+//! use deadlock_proof::{unclaimed, DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+//!
+//! struct CacheLock;
+//! let cache: DeadlockProofMutex<Vec<u32>, OuterMutexPermission, _> =
+//!     DeadlockProofMutex::new(Vec::new(), CacheLock);
+//!
+//! unclaimed::set_policy(unclaimed::Policy::AutoClaim);
+//! std::thread::scope(|scope| {
+//!     scope.spawn(|| {
+//!         cache.lock_without_permission().unwrap().guard().push(1);
+//!         // The token went back to the thread with the guard.
+//!         let _permission = OuterMutexPermission::get();
+//!     });
+//! });
+//! # unclaimed::set_policy(unclaimed::Policy::Panic);
+//! ```
+//!
+//! A thread whose token is out already, claimed explicitly or held by
+//! another [`AutoClaimGuard`], cannot claim it again whatever the policy,
+//! and neither can any thread while a
+//! [`SingleThreadedPhase`](crate::SingleThreadedPhase) suspends claims:
+//! both are refused with [`ClaimError::Refused`], or panic under
+//! [`Policy::Panic`] as a second [`OuterMutexPermission::get`] does.
+//! Inside [`permission::quarantine`] every lock without a permission
+//! returns [`ClaimError::Quarantined`], under every policy.
+
+use std::{
+    error::Error,
+    fmt,
+    ops::{Deref, DerefMut},
+    panic::Location,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        PoisonError,
+    },
+};
+
+use crate::{
+    permission::{self, ClaimDiagnostics, Quarantined},
+    poison, DeadlockProofMutex, DeadlockProofMutexGuard, LockResult, OuterMutexPermission,
+};
+
+/// What locking without a permission does on a thread whose token is still
+/// unclaimed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Policy {
+    /// Claim the token for the length of the guard.
+    AutoClaim,
+    /// Return [`ClaimError::Unclaimed`].
+    Error,
+    /// Panic.
+    #[default]
+    Panic,
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(Policy::Panic as u8);
+
+/// Makes every later lock without a permission follow `policy`.
+pub fn set_policy(policy: Policy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// The policy set with [`set_policy`], [`Policy::Panic`] if none was.
+pub fn policy() -> Policy {
+    match POLICY.load(Ordering::Relaxed) {
+        value if value == Policy::AutoClaim as u8 => Policy::AutoClaim,
+        value if value == Policy::Error as u8 => Policy::Error,
+        _ => Policy::Panic,
+    }
+}
+
+/// Why a mutex could not be locked without a permission.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum ClaimError {
+    /// The thread never claimed its token and the policy is
+    /// [`Policy::Error`].
+    Unclaimed {
+        attempted_at: &'static Location<'static>,
+    },
+    /// The token could not be claimed: it is out already, or claims are
+    /// suspended.
+    Refused(ClaimDiagnostics),
+    /// The lock was attempted inside
+    /// [`permission::quarantine`](crate::permission::quarantine).
+    Quarantined(Quarantined),
+}
+
+impl fmt::Display for ClaimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unclaimed { attempted_at } => write!(
+                f,
+                "mutex locked at {attempted_at} on a thread that never claimed its \
+                 permission token"
+            ),
+            Self::Refused(diagnostics) => diagnostics.fmt(f),
+            Self::Quarantined(quarantined) => quarantined.fmt(f),
+        }
+    }
+}
+
+impl Error for ClaimError {}
+
+/// The guard of a mutex locked without a permission, holding the thread's
+/// implicitly claimed token. Dropping it unlocks the mutex and gives the
+/// token back to the thread.
+pub struct AutoClaimGuard<'a, T, I: 'static>(
+    Option<DeadlockProofMutexGuard<'a, T, OuterMutexPermission, I>>,
+);
+
+impl<T, I: 'static> AutoClaimGuard<'_, T, I> {
+    /// Unlocks the mutex and gives the token back to the thread, as dropping
+    /// the guard does.
+    pub fn unlock(self) {}
+
+    fn guard(&self) -> &DeadlockProofMutexGuard<'_, T, OuterMutexPermission, I> {
+        // Only `Drop` takes the guard out.
+        self.0.as_ref().expect("guard present until dropped")
+    }
+}
+
+impl<T, I: 'static> Deref for AutoClaimGuard<'_, T, I> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard()
+    }
+}
+
+impl<T, I: 'static> DerefMut for AutoClaimGuard<'_, T, I> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.0.as_mut().expect("guard present until dropped")
+    }
+}
+
+impl<T, I: 'static> Drop for AutoClaimGuard<'_, T, I> {
+    fn drop(&mut self) {
+        if let Some(guard) = self.0.take() {
+            permission::return_token(guard.unlock());
+        }
+    }
+}
+
+/// Result of locking without a permission. The poison error carries the
+/// same guard, so the token goes back to the thread either way.
+pub type AutoClaimResult<'a, T, I> =
+    LockResult<AutoClaimGuard<'a, T, I>, PoisonError<AutoClaimGuard<'a, T, I>>>;
+
+impl<T, I: 'static> DeadlockProofMutex<T, OuterMutexPermission, I> {
+    /// Locks this outer mutex on a thread that may never have claimed its
+    /// token, following the crate's [`Policy`]. See the
+    /// [module docs](crate::unclaimed).
+    ///
+    /// Under [`Policy::Panic`] panics where the other policies return an
+    /// error, except inside
+    /// [`permission::quarantine`](crate::permission::quarantine).
+    #[track_caller]
+    pub fn lock_without_permission(&self) -> Result<AutoClaimResult<'_, T, I>, ClaimError> {
+        let attempted_at = Location::caller();
+        permission::check_quarantine(attempted_at).map_err(ClaimError::Quarantined)?;
+        let policy = policy();
+        if policy != Policy::AutoClaim && permission::token_available() {
+            let error = ClaimError::Unclaimed { attempted_at };
+            if policy == Policy::Error {
+                return Err(error);
+            }
+            panic!("{error}");
+        }
+        let result = self.autoclaim_at(attempted_at);
+        if let (Policy::Panic, Err(ClaimError::Refused(diagnostics))) = (policy, &result) {
+            crate::misuse::report(|| crate::misuse::MisuseEvent::DoubleClaim(diagnostics.clone()));
+            panic!("{diagnostics}");
+        }
+        result
+    }
+
+    /// Locks this outer mutex, claiming the thread's token for the length of
+    /// the guard, as under [`Policy::AutoClaim`] whatever the policy is.
+    #[track_caller]
+    pub fn lock_autoclaim(&self) -> Result<AutoClaimResult<'_, T, I>, ClaimError> {
+        self.autoclaim_at(Location::caller())
+    }
+
+    fn autoclaim_at(
+        &self,
+        attempted_at: &'static Location<'static>,
+    ) -> Result<AutoClaimResult<'_, T, I>, ClaimError> {
+        permission::check_quarantine(attempted_at).map_err(ClaimError::Quarantined)?;
+        let token = permission::claim_implicitly(attempted_at).map_err(ClaimError::Refused)?;
+        Ok(poison::map_poisoned(self.acquire(), |guard| {
+            AutoClaimGuard(Some(self.guard_with(guard, token, attempted_at)))
+        }))
+    }
+}
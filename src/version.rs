@@ -0,0 +1,334 @@
+//! Change tracking for mutex data, under the `tracking` feature.
+//!
+//! With it, every [`DeadlockProofMutex`] carries a version counter. A guard only counts
+//! as having modified the data once it handed out `&mut T`, through
+//! [`get_mut`](crate::DeadlockProofMutexGuard::get_mut) or `DerefMut`; when
+//! such a guard is released the version goes up by one and every listener
+//! registered with [`on_change`](DeadlockProofMutex::on_change) is called.
+//! Guards that only read leave the version alone.
+//!
+//! `DerefMut` still counts as a modification even if nothing is written,
+//! since it cannot tell; code that wants precise tracking should read through
+//! `Deref` and take `get_mut` only on the path that writes.
+//!
+//! Sections that often write back what they read can take
+//! [`get_mut_eq`](crate::DeadlockProofMutexGuard::get_mut_eq) instead. It
+//! clones the data and hands out a [`ChangeCheck`], which compares the data
+//! with the clone when dropped and only then marks the guard as modifying,
+//! so an update that changed nothing bumps no version and calls no listener.
+//! [`get_mut_eq_by`](crate::DeadlockProofMutexGuard::get_mut_eq_by) takes
+//! the comparison as a function, e.g. to ignore a field that does not
+//! matter downstream,
+//! [`get_mut_eq_with`](crate::DeadlockProofMutexGuard::get_mut_eq_with) a
+//! whole [`SnapshotStrategy`] for data too large to clone, and [`force_dirty`](crate::DeadlockProofMutexGuard::force_dirty)
+//! counts the guard as modifying whatever happens.
+//!
+//! Without the feature a mutex keeps no version and its guards track
+//! nothing, so both are laid out as the `std` types they wrap.
+//!
+//! ```
+//! # #[cfg(feature = "tracking")] {
+//! use deadlock_proof::{unique_type, DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+//!
+//! let mutex = DeadlockProofMutex::new(5u32, unique_type!());
+//! let mut guard = mutex.lock(OuterMutexPermission::get()).guard();
+//! *guard.get_mut_eq() = 5;
+//! guard.unlock();
+//! assert_eq!(mutex.version(), 0);
+//! # }
+//! ```
+
+#[cfg(not(feature = "tracking"))]
+use std::marker::PhantomData;
+use std::panic::Location;
+#[cfg(feature = "tracking")]
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+#[cfg(feature = "tracking")]
+use crate::{
+    poison_watch,
+    release::{Bookkeeping, MutexBookkeeping},
+    snapshot::SnapshotStrategy,
+    DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard,
+    MutexPermission,
+};
+
+#[cfg(feature = "tracking")]
+type Listener = Box<dyn Fn(u64) + Send + Sync>;
+
+/// The version counter and change listeners of one mutex, its poison
+/// watchers, and what its guards have to do on release.
+#[cfg(feature = "tracking")]
+pub(crate) struct Versions {
+    version: AtomicU64,
+    listeners: Mutex<Vec<Listener>>,
+    pub(crate) poison: poison_watch::Watch,
+    pub(crate) bookkeeping: MutexBookkeeping,
+}
+
+#[cfg(feature = "tracking")]
+impl Versions {
+    pub(crate) fn new(identifier: &'static str) -> Self {
+        Self {
+            version: AtomicU64::new(0),
+            listeners: Mutex::new(Vec::new()),
+            poison: poison_watch::Watch::new(identifier),
+            bookkeeping: MutexBookkeeping::new(),
+        }
+    }
+
+    /// Records one modification and notifies the listeners.
+    pub(crate) fn bump(&self) {
+        let version = self.version.fetch_add(1, Ordering::AcqRel) + 1;
+        if !self.bookkeeping.get().contains(Bookkeeping::LISTENERS) {
+            return;
+        }
+        let listeners = self
+            .listeners
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        for listener in listeners.iter() {
+            listener(version);
+        }
+    }
+}
+
+/// Held by every guard, after its inner guard so that it drops after the
+/// data has been released. Bumps the version on drop if the guard was used
+/// to modify the data, and reports the poisoning if a panic released it.
+#[cfg(feature = "tracking")]
+pub(crate) struct Dirty<'a> {
+    versions: &'a Versions,
+    dirty: bool,
+    /// Whether the thread was already panicking when the guard locked, in
+    /// which case a release during the unwind does not poison.
+    unwinding: bool,
+    locked_at: Option<&'static Location<'static>>,
+}
+
+#[cfg(feature = "tracking")]
+impl<'a> Dirty<'a> {
+    pub(crate) fn clean(versions: &'a Versions) -> Self {
+        Self {
+            versions,
+            dirty: false,
+            unwinding: thread::panicking(),
+            locked_at: None,
+        }
+    }
+
+    /// For paths that always modify the data.
+    pub(crate) fn modified(versions: &'a Versions) -> Self {
+        Self {
+            dirty: true,
+            ..Self::clean(versions)
+        }
+    }
+
+    /// For guards that know where they were acquired.
+    pub(crate) fn at(self, location: &'static Location<'static>) -> Self {
+        Self {
+            locked_at: Some(location),
+            ..self
+        }
+    }
+
+    pub(crate) fn mark(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Mutable access through `data` that only marks the guard once the
+    /// data no longer matches how it was, as `strategy` copies and compares
+    /// it.
+    fn check<'g, T>(
+        &'g mut self,
+        data: &'g mut T,
+        strategy: SnapshotStrategy<T>,
+    ) -> ChangeCheck<'g, T> {
+        ChangeCheck {
+            before: strategy.take(data),
+            data,
+            strategy,
+            dirty: &mut self.dirty,
+        }
+    }
+}
+
+/// Mutable access to a guard's data that only counts as a modification if
+/// the data changed. Created by
+/// [`get_mut_eq`](DeadlockProofMutexGuard::get_mut_eq) and
+/// [`get_mut_eq_by`](DeadlockProofMutexGuard::get_mut_eq_by); the comparison
+/// runs when it is dropped.
+#[cfg(feature = "tracking")]
+pub struct ChangeCheck<'g, T> {
+    data: &'g mut T,
+    before: T,
+    strategy: SnapshotStrategy<T>,
+    dirty: &'g mut bool,
+}
+
+#[cfg(feature = "tracking")]
+impl<T> Deref for ChangeCheck<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+#[cfg(feature = "tracking")]
+impl<T> DerefMut for ChangeCheck<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+#[cfg(feature = "tracking")]
+impl<T> Drop for ChangeCheck<'_, T> {
+    fn drop(&mut self) {
+        // A half-done write counts as one, and comparing could panic again.
+        if thread::panicking() || !self.strategy.unchanged(&self.before, self.data) {
+            *self.dirty = true;
+        }
+    }
+}
+
+#[cfg(feature = "tracking")]
+/// The change-detecting accessors, shared by both kinds of full guard.
+macro_rules! change_checks {
+    () => {
+        /// Mutable access that only makes the release count as a
+        /// modification if the data is no longer equal to how it was. See
+        /// the [module docs](crate::version).
+        pub fn get_mut_eq(&mut self) -> ChangeCheck<'_, T>
+        where
+            T: Clone + PartialEq,
+        {
+            self.get_mut_eq_by(T::eq)
+        }
+
+        /// Like [`get_mut_eq`](Self::get_mut_eq), with `eq` to tell whether
+        /// the data is unchanged.
+        pub fn get_mut_eq_by(&mut self, eq: fn(&T, &T) -> bool) -> ChangeCheck<'_, T>
+        where
+            T: Clone,
+        {
+            self.get_mut_eq_with(SnapshotStrategy::with(T::clone, eq))
+        }
+
+        /// Like [`get_mut_eq`](Self::get_mut_eq), with the copy taken and
+        /// compared by `strategy`, e.g. a
+        /// [shared](SnapshotStrategy::shared) one for large data behind an
+        /// `Arc`.
+        pub fn get_mut_eq_with(&mut self, strategy: SnapshotStrategy<T>) -> ChangeCheck<'_, T> {
+            self.3.check(self.0.deref_mut(), strategy)
+        }
+
+        /// Makes the release count as a modification, whatever the
+        /// change checks found.
+        pub fn force_dirty(&mut self) {
+            self.3.mark();
+        }
+    };
+}
+
+#[cfg(feature = "tracking")]
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutexGuard<'_, T, P, I> {
+    change_checks!();
+}
+
+#[cfg(feature = "tracking")]
+impl<T, P: MutexPermission, I: 'static> DeadlockProofNestedMutexGuard<'_, T, P, I> {
+    change_checks!();
+}
+
+#[cfg(feature = "tracking")]
+impl Drop for Dirty<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.dirty || thread::panicking() {
+            self.settle();
+        }
+    }
+}
+
+#[cfg(feature = "tracking")]
+impl Dirty<'_> {
+    /// What a release that modified the data, or happened during a panic,
+    /// has to do. Out of line, as most releases do neither.
+    #[cold]
+    #[inline(never)]
+    fn settle(&mut self) {
+        if self.dirty {
+            self.versions.bump();
+        }
+        if !self.unwinding && thread::panicking() {
+            self.versions.poison.poisoned(self.locked_at);
+        }
+    }
+}
+
+#[cfg(feature = "tracking")]
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// How many times a guard of this mutex has been released after handing
+    /// out mutable access. Needs no permission; racy in the same way as
+    /// [`waiters`](Self::waiters), but never goes down.
+    pub fn version(&self) -> u64 {
+        self.versions.version.load(Ordering::Acquire)
+    }
+
+    /// Calls `listener` with the new version every time the version goes up.
+    ///
+    /// Listeners run on the releasing thread after the mutex has been
+    /// unlocked, but they hold no permission and should be quick: a
+    /// notification, not work.
+    pub fn on_change(&self, listener: impl Fn(u64) + Send + Sync + 'static) {
+        self.versions
+            .listeners
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .push(Box::new(listener));
+        self.versions.bookkeeping.insert(Bookkeeping::LISTENERS);
+    }
+}
+
+/// Keeps nothing without the `tracking` feature.
+#[cfg(not(feature = "tracking"))]
+pub(crate) struct Versions;
+
+#[cfg(not(feature = "tracking"))]
+impl Versions {
+    pub(crate) fn new(_identifier: &'static str) -> Self {
+        Self
+    }
+
+    pub(crate) fn bump(&self) {}
+}
+
+/// Tracks nothing without the `tracking` feature.
+#[cfg(not(feature = "tracking"))]
+pub(crate) struct Dirty<'a>(PhantomData<&'a Versions>);
+
+#[cfg(not(feature = "tracking"))]
+impl<'a> Dirty<'a> {
+    pub(crate) fn clean(_versions: &'a Versions) -> Self {
+        Self(PhantomData)
+    }
+
+    pub(crate) fn modified(versions: &'a Versions) -> Self {
+        Self::clean(versions)
+    }
+
+    pub(crate) fn at(self, _location: &'static Location<'static>) -> Self {
+        self
+    }
+
+    pub(crate) fn mark(&mut self) {}
+}
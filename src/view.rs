@@ -0,0 +1,75 @@
+//! Read-only views of locked state, for exposing a chosen subset of its
+//! fields outside the lock.
+//!
+//! [`impl_state_view!`](crate::impl_state_view) declares a plain struct with
+//! copies of some fields of a state type, and a `From<&State>` impl filling
+//! it in. [`DeadlockProofMutexGuard::view`] takes one from a held guard:
+//!
+//! ```
+//! use deadlock_proof::{impl_state_view, DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+//!
+//! struct Queue {
+//!     pending: Vec<u32>,
+//!     dropped: u64,
+//! }
+//!
+//! impl_state_view!(pub QueueView from Queue { dropped: u64 });
+//!
+//! struct QueueLock;
+//! let queue = DeadlockProofMutex::new(Queue { pending: vec![1, 2], dropped: 3 }, QueueLock);
+//! let guard = queue.lock(OuterMutexPermission::get()).guard();
+//! let view: QueueView = guard.view();
+//! # assert_eq!(guard.pending.len(), 2);
+//! guard.unlock();
+//! assert_eq!(view, QueueView { dropped: 3 });
+//! ```
+//!
+//! Views of the [`NetworkStack`](crate::NetworkStack) layers are taken
+//! together with [`NetworkStack::views`](crate::NetworkStack::views).
+
+use crate::{DeadlockProofMutexGuard, MutexPermission};
+
+/// Declares a view struct holding clones of the listed fields of a state
+/// type, and `From<&State>` for it.
+///
+/// `impl_state_view!(pub IpView from IpState { packets_processed: u64 })`
+/// declares `pub struct IpView { pub packets_processed: u64 }`. The field
+/// types have to be spelled out, since a declarative macro cannot look them
+/// up; a mismatch is a type error in the generated `From` impl. Attributes,
+/// doc comments included, may go before the view and before each field. The
+/// view derives `Clone`, `Debug` and `PartialEq`.
+#[macro_export]
+macro_rules! impl_state_view {
+    (
+        $(#[$attr:meta])*
+        $vis:vis $view:ident from $state:ty {
+            $($(#[$field_attr:meta])* $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Clone, Debug, PartialEq)]
+        $vis struct $view {
+            $($(#[$field_attr])* pub $field: $ty,)*
+        }
+
+        impl ::core::convert::From<&$state> for $view {
+            fn from(state: &$state) -> Self {
+                Self {
+                    $($field: ::core::clone::Clone::clone(&state.$field),)*
+                }
+            }
+        }
+    };
+}
+
+impl<T, P: MutexPermission, I: 'static> DeadlockProofMutexGuard<'_, T, P, I> {
+    /// A view of the locked data, e.g. one declared with
+    /// [`impl_state_view!`](crate::impl_state_view). Reads only, so the
+    /// mutex's version is left alone.
+    pub fn view<V>(&self) -> V
+    where
+        V: for<'a> From<&'a T>,
+    {
+        V::from(&**self)
+    }
+}
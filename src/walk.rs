@@ -0,0 +1,154 @@
+//! Walks over a [`NetworkStack`], one per thread and stack at a time.
+//!
+//! [`NetworkStack::begin_walk`] moves the thread's outer permission into a
+//! [`WalkToken`], and the stack helpers (routing, the ICMP error path,
+//! forking) take `&mut WalkToken` instead of a raw permission. Manual walks
+//! borrow the permission with [`WalkToken::with_permission`], which borrows
+//! the token for as long as they run. A helper called from inside one would
+//! be a second walk on the same thread, and it does not compile:
+//!
+//! ```compile_fail
+//! use deadlock_proof::*;
+//! use std::net::Ipv4Addr;
+//!
+//! let stack = NetworkStack::new();
+//! let mut walk = stack.begin_walk(OuterMutexPermission::get());
+//! walk.with_permission(|permission| {
+//!     let ip = stack.ip_layer.lock_in_context(permission).guard();
+//!     let permission = ip.unlock_for_sequential().to_earlier();
+//!     // The outer permission is back early, but the walk is still going.
+//!     walk.cached_route_lookup(Ipv4Addr::new(10, 0, 0, 1));
+//!     (permission, ())
+//! });
+//! ```
+//!
+//! Nor can the permission begin a walk of its own. It is lent as a
+//! [`WalkPermission`], which locks what the outer permission locks, through
+//! [`lock_in_context`](crate::DeadlockProofMutex::lock_in_context), but
+//! steps back to another `WalkPermission`, never to an
+//! [`OuterMutexPermission`]:
+//!
+//! ```compile_fail
+//! use deadlock_proof::*;
+//!
+//! let stack = NetworkStack::new();
+//! let mut walk = stack.begin_walk(OuterMutexPermission::get());
+//! walk.with_permission(|permission| -> (WalkPermission, ()) {
+//!     let guard = stack.ip_layer.lock_in_context(permission).guard();
+//!     let _second_walk = stack.begin_walk(guard.unlock_for_sequential().to_earlier());
+//!     todo!()
+//! });
+//! ```
+//!
+//! The token holds the outer permission, so it stays on its thread, and it
+//! borrows its stack, so it cannot be used with another one.
+//! [`finish`](WalkToken::finish) hands the permission back. Dropping the token
+//! instead loses it, like dropping the permission itself. Critical walks can
+//! run inside a [`PermissionLease`](crate::lease::PermissionLease) instead:
+//! [`PermissionLease::walk`](crate::lease::PermissionLease::walk) only lends
+//! the token out, and the lease is reported if it is dropped unfinished.
+//!
+//! Values derived along the way can be kept in the token's
+//! [`WalkCache`], which goes away with it.
+
+use crate::{
+    Namespace, NetworkStack, OuterMutexPermission, Permanent, PermissionWith, RootNamespace,
+    WalkCache,
+};
+
+/// The permission [`WalkToken::with_permission`] lends out, and every
+/// permission derived from it. See the [module docs](self).
+pub type WalkPermission<P = OuterMutexPermission> = PermissionWith<P, (), Permanent>;
+
+/// An in-progress walk over one [`NetworkStack`]. See the
+/// [module docs](self).
+pub struct WalkToken<'a, N: Namespace = RootNamespace> {
+    stack: &'a NetworkStack<N>,
+    /// Only `None` while [`with_permission`](Self::with_permission) has lent
+    /// it out, or after the closure it was lent to panicked.
+    permission: Option<OuterMutexPermission>,
+    cache: WalkCache,
+}
+
+impl<N: Namespace> NetworkStack<N> {
+    /// Starts this thread's walk over the stack.
+    pub fn begin_walk(&self, permission: OuterMutexPermission) -> WalkToken<'_, N> {
+        WalkToken {
+            stack: self,
+            permission: Some(permission),
+            cache: WalkCache::default(),
+        }
+    }
+}
+
+impl<'a, N: Namespace> WalkToken<'a, N> {
+    /// The stack being walked.
+    pub fn stack(&self) -> &'a NetworkStack<N> {
+        self.stack
+    }
+
+    /// Lends the outer permission to `f` for a hand-written walk, as a
+    /// [`WalkPermission`]. `f` hands it back, usually by stepping back with
+    /// `to_earlier`, along with its result.
+    ///
+    /// Panics if an earlier `f` panicked and so never returned the permission.
+    pub fn with_permission<R>(
+        &mut self,
+        f: impl FnOnce(WalkPermission) -> (WalkPermission, R),
+    ) -> R {
+        self.with_permission_and_cache(|permission, _| f(permission))
+    }
+
+    /// Like [`with_permission`](Self::with_permission), but also lends `f`
+    /// the walk's cache.
+    pub fn with_permission_and_cache<R>(
+        &mut self,
+        f: impl FnOnce(WalkPermission, &mut WalkCache) -> (WalkPermission, R),
+    ) -> R {
+        self.lend(|permission, cache| {
+            let (permission, result) = f(PermissionWith::attach(permission, ()), cache);
+            (permission.detach().0, result)
+        })
+    }
+
+    /// [`with_permission`](Self::with_permission) with the outer permission
+    /// itself, for the stack helpers, none of which begins a walk.
+    pub(crate) fn with_outer<R>(
+        &mut self,
+        f: impl FnOnce(OuterMutexPermission) -> (OuterMutexPermission, R),
+    ) -> R {
+        self.lend(|permission, _| f(permission))
+    }
+
+    fn lend<R>(
+        &mut self,
+        f: impl FnOnce(OuterMutexPermission, &mut WalkCache) -> (OuterMutexPermission, R),
+    ) -> R {
+        let permission = self
+            .permission
+            .take()
+            .expect("the walk's permission was lost to a panic");
+        let (permission, result) = f(permission, &mut self.cache);
+        self.permission = Some(permission);
+        result
+    }
+
+    /// The values cached so far in this walk.
+    pub fn cache(&self) -> &WalkCache {
+        &self.cache
+    }
+
+    /// The values cached so far in this walk, mutably.
+    pub fn cache_mut(&mut self) -> &mut WalkCache {
+        &mut self.cache
+    }
+
+    /// Ends the walk, dropping its cache, and returns the outer permission.
+    ///
+    /// Panics if the permission was lost to a panic in
+    /// [`with_permission`](Self::with_permission).
+    pub fn finish(self) -> OuterMutexPermission {
+        self.permission
+            .expect("the walk's permission was lost to a panic")
+    }
+}
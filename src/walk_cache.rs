@@ -0,0 +1,86 @@
+//! Values derived during one walk, kept for the rest of it.
+//!
+//! Every [`WalkToken`](crate::WalkToken) carries a [`WalkCache`], a map from
+//! a type to one value of it. A step that computes something the later
+//! steps need again, say an effective MTU from the IP and device layers,
+//! puts it there, and the later steps read it instead of locking those
+//! layers once more. Key types are usually newtypes named after what they
+//! hold:
+//!
+//! ```
+//! use deadlock_proof::{LockOutcome, NetworkStack, OuterMutexPermission};
+//!
+//! struct ActiveInterfaces(u32);
+//!
+//! let stack = NetworkStack::new();
+//! let mut walk = stack.begin_walk(OuterMutexPermission::get());
+//! walk.with_permission_and_cache(|permission, cache| {
+//!     let ip = stack.ip_layer.lock_in_context(permission).guard();
+//!     let device = stack.device_layer.lock_in_context(ip.unlock_for_sequential()).guard();
+//!     cache.put(ActiveInterfaces(device.interfaces_active));
+//!     (device.unlock_for_sequential().to_earlier().to_earlier(), ())
+//! });
+//! assert_eq!(walk.cache().get::<ActiveInterfaces>().map(|n| n.0), Some(0));
+//! walk.finish();
+//! ```
+//!
+//! The cache lives and dies with its token, so a value never outlasts the
+//! walk that computed it: the next walk starts with an empty cache, and
+//! cannot see anything derived from state that may have changed since.
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+};
+
+/// One value per type, for the duration of a walk. See the
+/// [module docs](self).
+#[derive(Default)]
+pub struct WalkCache {
+    values: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl WalkCache {
+    /// Stores `value`, returning the one of the same type put earlier.
+    pub fn put<T: 'static>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .map(|previous| *previous.downcast().expect("keyed by its own type"))
+    }
+
+    /// The value of type `T`, if one was put.
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .map(|value| value.downcast_ref().expect("keyed by its own type"))
+    }
+
+    /// The value of type `T`, mutably, if one was put.
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.values
+            .get_mut(&TypeId::of::<T>())
+            .map(|value| value.downcast_mut().expect("keyed by its own type"))
+    }
+
+    /// The value of type `T`, computed with `f` and put first if there is
+    /// none yet.
+    pub fn get_or_insert_with<T: 'static>(&mut self, f: impl FnOnce() -> T) -> &mut T {
+        self.values
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(f()))
+            .downcast_mut()
+            .expect("keyed by its own type")
+    }
+
+    /// Takes the value of type `T` out, if one was put.
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .map(|value| *value.downcast().expect("keyed by its own type"))
+    }
+
+    /// Whether nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
@@ -0,0 +1,115 @@
+//! Weak references to a mutex, for subsystems that can go away.
+//!
+//! A subsystem that can be disabled at runtime, and dropped with it, is
+//! best reached through a weak reference, so that holding one does not keep
+//! the subsystem alive. Upgrading and then locking takes the permission in
+//! two steps, and the permission has to be threaded around the failed
+//! upgrade. [`WeakDeadlockProofMutex::lock_if_alive`] does both at once and
+//! hands the permission back when the mutex is gone:
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use deadlock_proof::{weak::LockedOrGone, DeadlockProofMutex, OuterMutexPermission};
+//!
+//! struct FilterLock;
+//! let filter = Arc::new(DeadlockProofMutex::new(vec!["drop 10.0.0.0/8"], FilterLock));
+//! let weak = filter.downgrade_proof();
+//!
+//! let permission = match weak.lock_if_alive(OuterMutexPermission::get()) {
+//!     LockedOrGone::Locked(rules) => {
+//!         assert_eq!(rules.len(), 1);
+//!         rules.unlock()
+//!     }
+//!     other => other.into_permission(),
+//! };
+//! // The filter is disabled.
+//! drop(filter);
+//! assert!(matches!(weak.lock_if_alive(permission), LockedOrGone::Gone(_)));
+//! ```
+//!
+//! A call that upgraded the reference keeps the mutex alive while it waits
+//! for the lock, and the [owned guard](DeadlockProofOwnedMutexGuard) keeps
+//! it alive while it is held, even if every other `Arc` was dropped in the
+//! meantime.
+
+use std::sync::{Arc, Weak};
+
+use crate::{DeadlockProofMutex, DeadlockProofOwnedMutexGuard, MutexPermission};
+
+/// A weak reference to a [`DeadlockProofMutex`] in an [`Arc`], made with
+/// [`DeadlockProofMutex::downgrade_proof`].
+pub struct WeakDeadlockProofMutex<T: 'static, P: MutexPermission, I: 'static>(
+    Weak<DeadlockProofMutex<T, P, I>>,
+);
+
+/// What [`WeakDeadlockProofMutex::lock_if_alive`] found.
+#[non_exhaustive]
+pub enum LockedOrGone<T: 'static, P: MutexPermission, I: 'static> {
+    /// The mutex was alive, and is locked. The guard has the permission and
+    /// keeps the mutex alive.
+    Locked(DeadlockProofOwnedMutexGuard<T, P, I>),
+    /// The mutex was dropped. Here is the permission back.
+    Gone(P),
+    /// The mutex is alive but poisoned, as with
+    /// [`DeadlockProofMutex::lock_or_return`].
+    #[cfg(not(feature = "no-poison"))]
+    Poisoned(P),
+}
+
+impl<T: 'static, P: MutexPermission, I: 'static> LockedOrGone<T, P, I> {
+    /// The permission, unlocking the mutex first if it was locked.
+    pub fn into_permission(self) -> P {
+        match self {
+            Self::Locked(guard) => guard.unlock(),
+            Self::Gone(permission) => permission,
+            #[cfg(not(feature = "no-poison"))]
+            Self::Poisoned(permission) => permission,
+        }
+    }
+}
+
+impl<T: 'static, P: MutexPermission, I: 'static> DeadlockProofMutex<T, P, I> {
+    /// A weak reference to this mutex. See the [module docs](crate::weak).
+    pub fn downgrade_proof(self: &Arc<Self>) -> WeakDeadlockProofMutex<T, P, I> {
+        WeakDeadlockProofMutex(Arc::downgrade(self))
+    }
+}
+
+impl<T: 'static, P: MutexPermission, I: 'static> WeakDeadlockProofMutex<T, P, I> {
+    /// Locks the mutex if it is still alive, waiting for it like
+    /// [`lock_or_return`](DeadlockProofMutex::lock_or_return). Hands the
+    /// permission back if the mutex is gone or poisoned.
+    #[track_caller]
+    pub fn lock_if_alive(&self, permission: P) -> LockedOrGone<T, P, I> {
+        let Some(mutex) = self.0.upgrade() else {
+            return LockedOrGone::Gone(permission);
+        };
+        #[cfg(not(feature = "no-poison"))]
+        let guard = match mutex.lock_or_return(permission) {
+            Ok(guard) => guard,
+            Err(error) => return LockedOrGone::Poisoned(error.into_permission()),
+        };
+        #[cfg(feature = "no-poison")]
+        let guard = mutex.lock(permission);
+        // Safety: the guard was just taken from this mutex.
+        LockedOrGone::Locked(unsafe { DeadlockProofOwnedMutexGuard::co_owning(guard, &mutex) })
+    }
+
+    /// The mutex, if it is still alive.
+    pub fn upgrade(&self) -> Option<Arc<DeadlockProofMutex<T, P, I>>> {
+        self.0.upgrade()
+    }
+
+    /// Whether the mutex is still alive. As racy as any count of references
+    /// another thread may drop.
+    pub fn is_alive(&self) -> bool {
+        self.0.strong_count() > 0
+    }
+}
+
+impl<T: 'static, P: MutexPermission, I: 'static> Clone for WeakDeadlockProofMutex<T, P, I> {
+    fn clone(&self) -> Self {
+        Self(Weak::clone(&self.0))
+    }
+}
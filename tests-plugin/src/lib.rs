@@ -0,0 +1,75 @@
+//! A downstream crate that locks a level of its own after the device layer
+//! of the [`NetworkStack`] of `deadlock_proof`, which knows nothing about it.
+
+use deadlock_proof::{
+    After, DeadlockProofMutex, LockOutcome, NetworkStack, OuterMutexPermission, Position,
+    extend_lock_hierarchy,
+};
+
+/// Identifies the packet filter, locked after
+/// [`DeviceLock`](deadlock_proof::DeviceLock).
+pub struct FilterLock;
+/// Identifies the filter's audit log, locked after [`FilterLock`].
+pub struct AuditLock;
+extend_lock_hierarchy!(deadlock_proof::DeviceLock -> FilterLock, AuditLock);
+
+/// Packets a [`PacketFilter`] let through or dropped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FilterStats {
+    pub passed: u64,
+    pub dropped: u64,
+}
+
+/// A filter on the packets the device layer hands on, with a log of the
+/// packets it dropped.
+pub struct PacketFilter {
+    pub stats: DeadlockProofMutex<FilterStats, Position<FilterLock>, FilterLock>,
+    pub audit: DeadlockProofMutex<Vec<usize>, Position<AuditLock>, AuditLock>,
+    max_len: usize,
+}
+
+impl PacketFilter {
+    /// A filter that drops packets longer than `max_len`.
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            stats: DeadlockProofMutex::new(FilterStats::default(), FilterLock),
+            audit: DeadlockProofMutex::new(Vec::new(), AuditLock),
+            max_len,
+        }
+    }
+
+    /// Sends a packet of `len` bytes through the stack: counted in the IP
+    /// layer, transmitted by the device layer if the filter lets it pass,
+    /// and logged if it does not. Walks IP, device, filter and audit in
+    /// turn.
+    pub fn send(
+        &self,
+        stack: &NetworkStack,
+        permission: OuterMutexPermission,
+        len: usize,
+    ) -> (OuterMutexPermission, bool) {
+        let mut ip = stack.ip_layer.lock(permission).guard();
+        ip.packets_processed += 1;
+        let mut device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+        let passes = len <= self.max_len;
+        if passes {
+            device.bytes_transmitted += len as u64;
+        }
+        let mut stats = self.stats.lock(device.unlock_for_sequential()).guard();
+        if passes {
+            stats.passed += 1;
+        } else {
+            stats.dropped += 1;
+        }
+        let mut audit = self.audit.lock(stats.unlock_for_sequential()).guard();
+        if !passes {
+            audit.push(len);
+        }
+        (back_to_root(audit.unlock_for_sequential()), passes)
+    }
+}
+
+/// Walks a position past the audit log back to the root of the stack.
+pub fn back_to_root(after: After<AuditLock>) -> OuterMutexPermission {
+    after.to_earlier().to_earlier().to_earlier().to_earlier()
+}
@@ -0,0 +1,88 @@
+use std::{sync::Barrier, thread};
+
+use deadlock_proof::{DeviceLock, LockOutcome, NetworkStack, OuterMutexPermission};
+use deadlock_proof_tests_plugin::{AuditLock, FilterLock, PacketFilter, back_to_root};
+
+#[test]
+fn extended_levels_continue_the_base_order() {
+    assert_eq!(
+        AuditLock::LOCK_ORDER,
+        ["IpLock", "DeviceLock", "FilterLock", "AuditLock"]
+    );
+    assert_eq!(FilterLock::POSITION, DeviceLock::POSITION + 1);
+    assert_eq!(FilterLock::NEXT, Some("AuditLock"));
+    assert!(FilterLock::DESCRIPTION.contains("after `deadlock_proof::DeviceLock`"));
+    // The base is untouched.
+    assert_eq!(DeviceLock::NEXT, Some("TransportLock"));
+}
+
+#[test]
+fn base_levels_are_reacquired_from_an_extended_position() {
+    let stack = NetworkStack::new();
+    let filter = PacketFilter::new(1500);
+    let (permission, passed) = filter.send(&stack, OuterMutexPermission::get(), 9000);
+    assert!(!passed);
+
+    let ip = stack.ip_layer.lock(permission).guard();
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    let stats = filter.stats.lock(device.unlock_for_sequential()).guard();
+    assert_eq!(stats.dropped, 1);
+    let audit = filter.audit.lock(stats.unlock_for_sequential()).guard();
+    assert_eq!(*audit, [9000]);
+
+    // Both levels of the base crate have been passed.
+    let after_audit = audit.unlock_for_sequential();
+    let (ip, inside) = after_audit.reacquire_nested(&stack.ip_layer).guard();
+    assert_eq!(ip.packets_processed, 1);
+    let _permission: OuterMutexPermission = ip.unlock(inside);
+}
+
+#[test]
+fn base_and_extended_walks_run_together_without_deadlock() {
+    const THREADS: usize = 4;
+    const ROUNDS: usize = 500;
+    let stack = NetworkStack::new();
+    let filter = PacketFilter::new(1000);
+    let start = Barrier::new(THREADS);
+    thread::scope(|scope| {
+        for thread in 0..THREADS {
+            let (stack, filter, start) = (&stack, &filter, &start);
+            scope.spawn(move || {
+                let mut permission = OuterMutexPermission::get();
+                start.wait();
+                for round in 0..ROUNDS {
+                    if thread % 2 == 0 {
+                        permission = filter.send(stack, permission, round * 4).0;
+                    } else {
+                        // The base crate's own walk, on to the transport layer.
+                        let ip = stack.ip_layer.lock(permission).guard();
+                        let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+                        let mut transport = stack
+                            .transport_layer
+                            .lock(device.unlock_for_sequential())
+                            .guard();
+                        transport.tcp_connections += 1;
+                        permission = transport
+                            .unlock_for_sequential()
+                            .to_earlier()
+                            .to_earlier()
+                            .to_earlier();
+                    }
+                }
+            });
+        }
+    });
+
+    let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(ip.packets_processed, (THREADS / 2 * ROUNDS) as u64);
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    let stats = filter.stats.lock(device.unlock_for_sequential()).guard();
+    assert_eq!(stats.passed + stats.dropped, (THREADS / 2 * ROUNDS) as u64);
+    back_to_root(
+        filter
+            .audit
+            .lock(stats.unlock_for_sequential())
+            .guard()
+            .unlock_for_sequential(),
+    );
+}
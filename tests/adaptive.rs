@@ -0,0 +1,107 @@
+#![cfg(feature = "adaptive")]
+
+use std::{sync::Barrier, thread, time::Duration};
+
+use deadlock_proof::{
+    adaptive::DEFAULT_SPIN_BUDGET, concurrent, unique_type, DeadlockProofMutex, LockOutcome,
+    OuterMutexPermission,
+};
+
+/// Eight threads hammering one counter with short holds.
+fn hammer(budget: u32) {
+    const ROUNDS: u64 = 5_000;
+    let counter = DeadlockProofMutex::new(0u64, unique_type!());
+    counter.set_spin_budget(budget);
+
+    let workers = (0..8)
+        .map(|_| -> concurrent::Worker<'_> {
+            let counter = &counter;
+            Box::new(move |mut permission| {
+                for _ in 0..ROUNDS {
+                    let mut guard = counter.lock(permission).guard();
+                    let seen = *guard;
+                    std::hint::spin_loop();
+                    *guard = seen + 1;
+                    permission = guard.unlock();
+                }
+                permission
+            })
+        })
+        .collect();
+    concurrent::run_all(workers).unwrap();
+
+    assert_eq!(
+        *counter.lock(OuterMutexPermission::get()).guard(),
+        8 * ROUNDS
+    );
+}
+
+#[test]
+fn budget_defaults_and_can_be_changed() {
+    let mutex = DeadlockProofMutex::<(), OuterMutexPermission, _>::new((), unique_type!());
+    assert_eq!(mutex.spin_budget(), DEFAULT_SPIN_BUDGET);
+    mutex.set_spin_budget(0);
+    assert_eq!(mutex.spin_budget(), 0);
+}
+
+#[test]
+fn counts_stay_exact_without_spinning() {
+    thread::spawn(|| hammer(0)).join().unwrap();
+}
+
+#[test]
+fn counts_stay_exact_with_the_default_budget() {
+    thread::spawn(|| hammer(DEFAULT_SPIN_BUDGET))
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn counts_stay_exact_when_spinning_for_long() {
+    thread::spawn(|| hammer(1 << 14)).join().unwrap();
+}
+
+#[test]
+fn spinning_waiter_gets_the_lock_once_released() {
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    // Far more than the hold below, so the waiter never parks.
+    mutex.set_spin_budget(u32::MAX);
+    let barrier = Barrier::new(2);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut guard = mutex.lock(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            thread::sleep(Duration::from_millis(5));
+            *guard = 1;
+        });
+        barrier.wait();
+        assert_eq!(*mutex.lock(OuterMutexPermission::get()).guard(), 1);
+    });
+}
+
+#[test]
+#[cfg(not(feature = "no-poison"))]
+fn spinning_waiter_sees_poison() {
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    mutex.set_spin_budget(u32::MAX);
+    let barrier = Barrier::new(2);
+
+    thread::scope(|scope| {
+        let holder = scope.spawn(|| {
+            let mut guard = mutex.lock(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            thread::sleep(Duration::from_millis(5));
+            *guard = 7;
+            panic!("holder panics mid-update");
+        });
+        barrier.wait();
+        let poisoned = mutex
+            .lock(OuterMutexPermission::get())
+            .into_result()
+            .err()
+            .expect("the holder panicked");
+        assert_eq!(*poisoned.into_guard(), 7);
+        assert!(holder.join().is_err());
+    });
+}
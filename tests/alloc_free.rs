@@ -0,0 +1,202 @@
+#![cfg(feature = "test-util")]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    fmt,
+    panic::Location,
+    thread,
+};
+
+use deadlock_proof::{
+    backpressure::Full,
+    concurrent,
+    lazy::InitPanicked,
+    lease::{Abandoned, Dropped},
+    net_demo::ShuttingDown,
+    pool::{RetireReason, Retirement},
+    soak::{Scenario, Violation},
+    task::DelegationAbandoned,
+    CancellableLockError, ClaimError, ConfigureError, LockError, MisuseEvent,
+    OuterMutexPermission, StackLayer, TryLockError, TxAborted,
+};
+
+thread_local! {
+    /// Allocations made by this thread so far.
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The system allocator, counting allocations per thread.
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// A message buffer on the stack.
+struct Buffer {
+    bytes: [u8; 512],
+    len: usize,
+}
+
+impl fmt::Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.bytes
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Writes the message of each error, asserting that it is not empty and
+/// that writing it allocated nothing.
+fn assert_formats_without_allocating(errors: &[&dyn LockError]) {
+    for error in errors {
+        let mut buffer = Buffer {
+            bytes: [0; 512],
+            len: 0,
+        };
+        let before = ALLOCATIONS.with(Cell::get);
+        error.write_to(&mut buffer).unwrap();
+        let allocations = ALLOCATIONS.with(Cell::get) - before;
+        let message = std::str::from_utf8(&buffer.bytes[..buffer.len]).unwrap();
+        assert_eq!(allocations, 0, "formatting allocated: {message}");
+        assert!(!message.is_empty());
+        assert_eq!(message, error.to_string());
+    }
+}
+
+#[test]
+fn lock_errors_format_without_allocating() {
+    let panicked = concurrent::run_all(vec![Box::new(|_| panic!("worker failed"))]).unwrap_err();
+    let rejected = TxAborted::Rejected {
+        layer: StackLayer::Device,
+        reason: "no such interface".to_owned(),
+    };
+    assert_formats_without_allocating(&[
+        &TryLockError::WouldBlock(()),
+        &CancellableLockError::Cancelled(()),
+        &rejected,
+        &InitPanicked,
+        &ConfigureError::AlreadyConfigured,
+        &DelegationAbandoned,
+        &ShuttingDown,
+        &Full(1500u32),
+        &panicked,
+    ]);
+    #[cfg(not(feature = "no-poison"))]
+    assert_formats_without_allocating(&[
+        &TryLockError::Poisoned(()),
+        &CancellableLockError::Poisoned(()),
+        &TxAborted::Poisoned(StackLayer::Ip),
+    ]);
+}
+
+#[test]
+fn misuse_events_format_without_allocating() {
+    // Claimed on a thread of its own, so the double claim is this test's.
+    thread::spawn(|| {
+        let _permission = OuterMutexPermission::get();
+        let Err(diagnostics) = OuterMutexPermission::get_or_diagnose() else {
+            panic!("claimed twice");
+        };
+        let abandoned = Abandoned {
+            created_at: Location::caller(),
+            dropped: Dropped::Token,
+        };
+        let events = [
+            MisuseEvent::DoubleClaim(diagnostics.clone()),
+            MisuseEvent::WrongThread {
+                claimed_on: thread::current().id(),
+                used_on: thread::current().id(),
+            },
+            MisuseEvent::PermissionLeaked(abandoned.clone()),
+            MisuseEvent::InvariantViolated {
+                identifier: "RouteLock",
+                message: "route to 10.0.0.0/40".to_owned(),
+                locked_at: Location::caller(),
+            },
+            MisuseEvent::ForeignPhaseLock,
+            MisuseEvent::ElidedRelock {
+                at: Location::caller(),
+            },
+            MisuseEvent::DuplicateIdentifier {
+                identifier: "DeviceLock",
+                generation: 7,
+            },
+            MisuseEvent::ClosedGeneration {
+                identifier: "DeviceLock",
+                generation: 7,
+            },
+        ];
+        for event in &events {
+            assert_formats_without_allocating(&[event]);
+        }
+        let unclaimed = ClaimError::Unclaimed {
+            attempted_at: Location::caller(),
+        };
+        let refused = ClaimError::Refused(diagnostics.clone());
+        assert_formats_without_allocating(&[&diagnostics, &abandoned, &unclaimed, &refused]);
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(feature = "tracking")]
+#[test]
+fn poison_events_format_without_allocating() {
+    use std::sync::{Arc, Mutex};
+
+    use deadlock_proof::{DeadlockProofMutex, LockOutcome};
+
+    struct TableLock;
+    let table = DeadlockProofMutex::new(0u32, TableLock);
+    let poisoned = Arc::new(Mutex::new(None));
+    let recorded = Arc::clone(&poisoned);
+    table.on_poison(move |event| *recorded.lock().unwrap() = Some(event.clone()));
+    thread::scope(|scope| {
+        let writer = thread::Builder::new()
+            .name("table-writer".into())
+            .spawn_scoped(scope, || {
+                let _table = table.lock(OuterMutexPermission::get()).guard();
+                panic!("bad entry");
+            })
+            .unwrap();
+        assert!(writer.join().is_err());
+    });
+    let poisoned = poisoned.lock().unwrap().take().unwrap();
+    assert_formats_without_allocating(&[&poisoned]);
+}
+
+#[test]
+fn event_records_format_without_allocating() {
+    let retirement = Retirement {
+        worker: 3,
+        job: "flush",
+        submitted_at: Location::caller(),
+        reason: RetireReason::LeaseExpired,
+    };
+    let violation = Violation {
+        scenario: Scenario::ALL[0],
+        detail: "torn update: 3 != 4".to_owned(),
+    };
+    assert_formats_without_allocating(&[&retirement, &violation]);
+}
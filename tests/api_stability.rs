@@ -0,0 +1,141 @@
+//! Matches the crate's non-exhaustive enums the way downstream code has to,
+//! with a catch-all arm, and builds mutexes through `MutexConfig`.
+
+#[cfg(feature = "tracking")]
+use std::sync::Mutex;
+use std::{sync::Barrier, thread};
+
+#[cfg(feature = "tracking")]
+use deadlock_proof::ContentionEvent;
+#[cfg(any(feature = "adaptive", feature = "tracking"))]
+use deadlock_proof::MutexConfig;
+use deadlock_proof::{
+    declare_mutex_family, DeadlockProofMutex, LockOutcome, MutexFamily, NetworkStack,
+    OuterMutexPermission, StackLayer, TryLockError, TxAborted,
+};
+
+#[cfg(feature = "tracking")]
+struct ConfiguredLock;
+struct PlainLock;
+
+#[cfg(feature = "tracking")]
+static EVENTS: Mutex<Vec<ContentionEvent>> = Mutex::new(Vec::new());
+
+#[cfg(feature = "tracking")]
+fn record(event: ContentionEvent) {
+    if event.identifier() == std::any::type_name::<ConfiguredLock>() {
+        EVENTS.lock().unwrap().push(event);
+    }
+}
+
+/// What a downstream caller does with a failed `try_lock`: handle the cases
+/// it knows and take the permission back from the rest.
+fn describe(error: TryLockError<OuterMutexPermission>) -> (&'static str, OuterMutexPermission) {
+    match error {
+        TryLockError::WouldBlock(permission) => ("busy", permission),
+        other => ("other", other.into_permission()),
+    }
+}
+
+#[test]
+fn try_lock_errors_match_with_a_catch_all() {
+    let mutex = DeadlockProofMutex::new(0u32, PlainLock);
+    let held = Barrier::new(2);
+    let done = Barrier::new(2);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let guard = mutex.lock(OuterMutexPermission::get()).guard();
+            held.wait();
+            done.wait();
+            drop(guard);
+        });
+        held.wait();
+        let error = mutex
+            .rt_handle()
+            .try_lock(OuterMutexPermission::get())
+            .err()
+            .expect("the mutex is held by the other thread");
+        let (kind, _permission) = describe(error);
+        assert_eq!(kind, "busy");
+        done.wait();
+    });
+}
+
+#[test]
+fn transaction_aborts_match_with_a_catch_all() {
+    let stack = NetworkStack::new();
+    let (_permission, result) = stack
+        .transaction()
+        .stage_device(|_| Err("link down".to_string()))
+        .commit(OuterMutexPermission::get());
+    let layer = match result {
+        Err(TxAborted::Rejected { layer, .. }) => Some(layer),
+        Err(_) => None,
+        Ok(()) => panic!("the rejected transaction was applied"),
+    };
+    assert_eq!(layer, Some(StackLayer::Device));
+}
+
+#[cfg(feature = "tracking")]
+#[test]
+fn configured_mutex_reports_contention_from_the_start() {
+    let mutex: DeadlockProofMutex<u32, OuterMutexPermission, ConfiguredLock> =
+        DeadlockProofMutex::with_config(
+            0,
+            ConfiguredLock,
+            MutexConfig::new().contention_callback(record),
+        );
+    let held = Barrier::new(2);
+    thread::scope(|scope| {
+        let holder = scope.spawn(|| {
+            let guard = mutex.lock(OuterMutexPermission::get()).guard();
+            held.wait();
+            while mutex.waiters() == 0 {
+                thread::yield_now();
+            }
+            drop(guard);
+        });
+        held.wait();
+        let mut guard = mutex.lock(OuterMutexPermission::get()).guard();
+        *guard += 1;
+        drop(guard);
+        holder.join().unwrap();
+    });
+
+    let blocked = EVENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|event| matches!(event, ContentionEvent::WillBlock { .. }))
+        .count();
+    assert_eq!(blocked, 1);
+}
+
+#[cfg(feature = "adaptive")]
+#[test]
+fn configured_spin_budget_applies_and_defaults_match_new() {
+    let configured = DeadlockProofMutex::<u32, OuterMutexPermission, PlainLock>::with_config(
+        0,
+        PlainLock,
+        MutexConfig::new().spin_budget(8),
+    );
+    assert_eq!(configured.spin_budget(), 8);
+    let default = DeadlockProofMutex::<u32, OuterMutexPermission, PlainLock>::with_config(
+        0,
+        PlainLock,
+        MutexConfig::default(),
+    );
+    let plain = DeadlockProofMutex::<u32, OuterMutexPermission, PlainLock>::new(0, PlainLock);
+    assert_eq!(default.spin_budget(), plain.spin_budget());
+}
+
+declare_mutex_family!(LaneLock: Lane0, Lane1);
+
+#[test]
+fn macro_declared_families_implement_the_sealed_trait() {
+    let lanes: MutexFamily<u8, OuterMutexPermission, LaneLock, LaneLockId> =
+        MutexFamily::new(LaneLock, |_| 0);
+    let permission =
+        lanes.lock_each_in_declared_order(OuterMutexPermission::get(), |_, lane| *lane += 1);
+    assert_eq!(*lanes.get(Lane1.into()).lock(permission).guard(), 1);
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Waker},
+    thread,
+    time::Duration,
+};
+
+use common::{block_on, CountingWaker};
+use deadlock_proof::{notify::AsyncNotify, AsyncPermissionSlot, DeadlockProofAsyncMutex};
+
+struct QueueLock;
+
+#[test]
+fn waiter_is_resumed_by_a_notifier() {
+    let queue = Arc::new(DeadlockProofAsyncMutex::new(Vec::new(), QueueLock));
+    let changed = Arc::new(AsyncNotify::new());
+    let slot = AsyncPermissionSlot::new();
+
+    let producer = {
+        let (queue, changed) = (queue.clone(), changed.clone());
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            let producer_slot = AsyncPermissionSlot::new();
+            let mut jobs = block_on(queue.lock(producer_slot.claim().unwrap()));
+            jobs.push(7);
+            jobs.unlock();
+            changed.notify_one();
+        })
+    };
+
+    let job = block_on(async {
+        let mut permission = slot.claim().unwrap();
+        loop {
+            let mut jobs = queue.lock(permission).await;
+            let job = jobs.pop();
+            permission = jobs.unlock();
+            match job {
+                Some(job) => break job,
+                None => changed.notified().await,
+            }
+        }
+    });
+    producer.join().unwrap();
+    assert_eq!(job, 7);
+}
+
+#[test]
+fn notify_one_leaves_a_single_permit() {
+    let changed = AsyncNotify::new();
+    let mut cx = Context::from_waker(Waker::noop());
+    changed.notify_one();
+    changed.notify_one();
+    assert!(pin!(changed.notified()).poll(&mut cx).is_ready());
+    assert!(pin!(changed.notified()).poll(&mut cx).is_pending());
+}
+
+#[test]
+fn notify_waiters_wakes_everyone_waiting_and_nobody_later() {
+    let changed = AsyncNotify::new();
+    let waker = CountingWaker::new();
+    let task = Waker::from(waker.clone());
+    let mut cx = Context::from_waker(&task);
+    let mut first = pin!(changed.notified());
+    let mut second = pin!(changed.notified());
+    assert!(first.as_mut().poll(&mut cx).is_pending());
+    assert!(second.as_mut().poll(&mut cx).is_pending());
+
+    changed.notify_waiters();
+    assert_eq!(waker.wakes(), 2);
+    assert!(first.poll(&mut cx).is_ready());
+    assert!(second.poll(&mut cx).is_ready());
+    assert!(pin!(changed.notified()).poll(&mut cx).is_pending());
+}
+
+#[test]
+fn dropped_waiter_passes_its_notification_on() {
+    let changed = AsyncNotify::new();
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut second = pin!(changed.notified());
+    {
+        let mut first = pin!(changed.notified());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        changed.notify_one();
+    }
+    assert!(second.poll(&mut cx).is_ready());
+}
+
+#[test]
+fn waiting_after_unlocking_is_fine() {
+    let queue = DeadlockProofAsyncMutex::new(0u32, QueueLock);
+    let changed = AsyncNotify::new();
+    let slot = AsyncPermissionSlot::new();
+    changed.notify_one();
+    block_on(async {
+        let guard = queue.lock(slot.claim().unwrap()).await;
+        let permission = guard.unlock();
+        changed.notified().await;
+        queue.lock(permission).await.unlock()
+    });
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "while holding an AsyncMutexGuard")]
+fn waiting_with_a_guard_held_is_caught() {
+    let queue = DeadlockProofAsyncMutex::new(0u32, QueueLock);
+    let changed = AsyncNotify::new();
+    let slot = AsyncPermissionSlot::new();
+    changed.notify_one();
+    block_on(async {
+        let guard = queue.lock(slot.claim().unwrap()).await;
+        changed.notified().await;
+        guard.unlock()
+    });
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn another_task_holding_a_guard_is_not_blamed() {
+    let queue = DeadlockProofAsyncMutex::new(0u32, QueueLock);
+    let changed = AsyncNotify::new();
+    let slot = AsyncPermissionSlot::new();
+    let holder = Waker::from(CountingWaker::new());
+    let mut lock = pin!(queue.lock(slot.claim().unwrap()));
+    let guard = match lock.as_mut().poll(&mut Context::from_waker(&holder)) {
+        std::task::Poll::Ready(guard) => guard,
+        std::task::Poll::Pending => panic!("the mutex is free"),
+    };
+
+    changed.notify_one();
+    let waiter = Waker::from(CountingWaker::new());
+    assert!(pin!(changed.notified())
+        .poll(&mut Context::from_waker(&waiter))
+        .is_ready());
+    guard.unlock();
+}
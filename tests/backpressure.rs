@@ -0,0 +1,101 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use deadlock_proof::{
+    backpressure::{self, Full},
+    LockOutcome, NetworkStack, OuterMutexPermission, Position, TransportLock,
+};
+
+/// Walks down to the transport layer without locking anything on the way
+/// back.
+fn at_transport(stack: &NetworkStack, permission: OuterMutexPermission) -> Position<TransportLock> {
+    let ip = stack.ip_layer.lock(permission).guard();
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    device.unlock_for_sequential()
+}
+
+#[test]
+fn full_queue_refuses_without_counting_a_change() {
+    let stack = NetworkStack::with_ingress_capacity(2);
+    let permission = at_transport(&stack, OuterMutexPermission::get());
+    let mut transport = stack.transport_layer.lock(permission).guard();
+    assert_eq!(transport.ingress.capacity(), 2);
+    backpressure::push_within(&mut transport, vec![1]).unwrap();
+    backpressure::push_within(&mut transport, vec![2]).unwrap();
+    let permission = transport.unlock();
+    #[cfg(feature = "tracking")]
+    let version = stack.transport_layer.version();
+
+    let mut transport = stack.transport_layer.lock(permission).guard();
+    assert_eq!(
+        backpressure::push_within(&mut transport, vec![3]),
+        Err(Full(vec![3]))
+    );
+    let permission = transport.unlock();
+    #[cfg(feature = "tracking")]
+    assert_eq!(stack.transport_layer.version(), version);
+
+    // Raising the capacity makes room at once.
+    let mut transport = stack.transport_layer.lock(permission).guard();
+    transport.ingress.set_capacity(3);
+    backpressure::push_within(&mut transport, vec![3]).unwrap();
+    assert_eq!(backpressure::pop_within(&mut transport), Some(vec![1]));
+    assert_eq!(transport.ingress.len(), 2);
+}
+
+#[test]
+fn producers_wait_for_consumers_to_make_room() {
+    const PRODUCERS: usize = 3;
+    const CONSUMERS: usize = 2;
+    const PACKETS: usize = 500;
+    const CAPACITY: usize = 4;
+    let stack = NetworkStack::with_ingress_capacity(CAPACITY);
+    let consumed = AtomicUsize::new(0);
+    let sum = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for producer in 0..PRODUCERS {
+            let stack = &stack;
+            scope.spawn(move || {
+                let mut permission = at_transport(stack, OuterMutexPermission::get());
+                for packet in 0..PACKETS {
+                    let payload = vec![(producer * PACKETS + packet) as u8];
+                    let space = &stack.ingress_space;
+                    permission = space.push(&stack.transport_layer, permission, payload);
+                }
+            });
+        }
+        for _ in 0..CONSUMERS {
+            let (stack, consumed, sum) = (&stack, &consumed, &sum);
+            scope.spawn(move || {
+                let mut permission = at_transport(stack, OuterMutexPermission::get());
+                while consumed.load(Ordering::Relaxed) < PRODUCERS * PACKETS {
+                    let mut transport = stack.transport_layer.lock(permission).guard();
+                    assert!(transport.ingress.len() <= CAPACITY);
+                    let packet = stack.ingress_space.pop_within(&mut transport);
+                    permission = transport.unlock();
+                    match packet {
+                        Some(packet) => {
+                            sum.fetch_add(usize::from(packet[0]), Ordering::Relaxed);
+                            consumed.fetch_add(1, Ordering::Relaxed);
+                        }
+                        None => thread::yield_now(),
+                    }
+                }
+            });
+        }
+    });
+
+    assert_eq!(consumed.into_inner(), PRODUCERS * PACKETS);
+    let expected: usize = (0..PRODUCERS * PACKETS).map(|n| usize::from(n as u8)).sum();
+    assert_eq!(sum.into_inner(), expected);
+    let permission = at_transport(&stack, OuterMutexPermission::get());
+    assert!(stack
+        .transport_layer
+        .lock(permission)
+        .guard()
+        .ingress
+        .is_empty());
+}
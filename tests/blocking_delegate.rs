@@ -0,0 +1,134 @@
+mod common;
+
+use std::{
+    future::Future,
+    panic,
+    pin::pin,
+    sync::mpsc,
+    task::{Context, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{block_on, CountingWaker};
+use deadlock_proof::{
+    AsyncPermission, AsyncPermissionSlot, BlockingDelegate, DeadlockProofAsyncMutex,
+    DeadlockProofMutex, DelegationAbandoned, LockOutcome, OuterMutexPermission,
+};
+
+struct QueueLock;
+struct TotalsLock;
+
+const SLOW: Duration = Duration::from_millis(50);
+
+fn assert_send<T: Send>() {}
+
+#[test]
+fn delegate_is_send() {
+    assert_send::<BlockingDelegate>();
+}
+
+#[test]
+fn rejoin_waits_for_a_slow_blocking_section() {
+    let queue =
+        DeadlockProofAsyncMutex::<Vec<u32>, AsyncPermission, _>::new(vec![1, 2, 3], QueueLock);
+    let totals = DeadlockProofMutex::<u32, AsyncPermission, _>::new(0, TotalsLock);
+    let slot = AsyncPermissionSlot::new();
+
+    thread::scope(|scope| {
+        let mut guard = block_on(queue.lock(slot.claim().unwrap()));
+        let batch: Vec<u32> = guard.drain(..).collect();
+        let (delegate, rejoin) = guard.unlock().delegate_blocking();
+
+        // Stands in for `spawn_blocking`.
+        let started = Instant::now();
+        let totals = &totals;
+        scope.spawn(move || {
+            delegate.run(|permission| {
+                let mut sum = totals.lock(permission).guard();
+                thread::sleep(SLOW);
+                *sum += batch.iter().sum::<u32>();
+                (sum.unlock(), ())
+            })
+        });
+
+        let waker = CountingWaker::new();
+        let task_waker = Waker::from(waker.clone());
+        let mut rejoin = pin!(rejoin);
+        assert!(rejoin
+            .as_mut()
+            .poll(&mut Context::from_waker(&task_waker))
+            .is_pending());
+        assert!(!slot.is_parked());
+
+        let permission = block_on(rejoin).unwrap();
+        assert!(started.elapsed() >= SLOW);
+        assert_eq!(*totals.lock(permission).guard(), 6);
+        assert!(waker.wakes() <= 1);
+    });
+}
+
+#[test]
+fn blocking_thread_cannot_claim_its_own_root_meanwhile() {
+    let slot = AsyncPermissionSlot::new();
+    let (delegate, rejoin) = slot.claim().unwrap().delegate_blocking();
+
+    thread::spawn(move || {
+        let refused = delegate
+            .run(|permission| (permission, OuterMutexPermission::get_or_diagnose().is_err()));
+        assert!(refused);
+        // The thread's own root is back once the delegate is done.
+        let _root = OuterMutexPermission::get();
+    })
+    .join()
+    .unwrap();
+
+    assert!(block_on(rejoin).is_ok());
+}
+
+#[test]
+fn panicking_blocking_section_abandons_the_permission() {
+    let slot = AsyncPermissionSlot::new();
+    let (delegate, rejoin) = slot.claim().unwrap().delegate_blocking();
+
+    let (done, finished) = mpsc::channel();
+    thread::spawn(move || {
+        let panicked = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            delegate.run(|_permission: AsyncPermission| -> (AsyncPermission, ()) {
+                panic!("blocking work failed")
+            })
+        }));
+        done.send(panicked.is_err()).unwrap();
+    });
+
+    assert!(finished.recv().unwrap());
+    assert_eq!(block_on(rejoin).err(), Some(DelegationAbandoned));
+    assert!(!slot.is_parked());
+}
+
+#[test]
+fn unused_delegate_returns_the_permission() {
+    let slot = AsyncPermissionSlot::new();
+    let (delegate, rejoin) = slot.claim().unwrap().delegate_blocking();
+    drop(delegate);
+    assert!(block_on(rejoin).is_ok());
+}
+
+#[test]
+fn dropped_rejoin_handle_parks_the_permission_in_its_slot() {
+    let slot = AsyncPermissionSlot::new();
+    let (delegate, rejoin) = slot.claim().unwrap().delegate_blocking();
+    drop(rejoin);
+
+    thread::spawn(move || {
+        delegate.run(|permission| {
+            thread::sleep(SLOW);
+            (permission, ())
+        })
+    })
+    .join()
+    .unwrap();
+
+    assert!(slot.is_parked());
+    assert!(slot.claim().is_some());
+}
@@ -0,0 +1,95 @@
+#[cfg(feature = "tracking")]
+use std::time::Instant;
+use std::{sync::Barrier, thread, time::Duration};
+
+#[cfg(feature = "tracking")]
+use deadlock_proof::CancellableLockError;
+use deadlock_proof::{DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+
+struct Transport;
+#[cfg(feature = "tracking")]
+struct Device;
+
+#[cfg(feature = "tracking")]
+#[test]
+fn cancelling_releases_a_blocked_waiter_with_its_permission() {
+    let transport = DeadlockProofMutex::new(0u32, Transport);
+    let device = DeadlockProofMutex::new(0u32, Device);
+    let cancellation = transport.cancellation_token();
+    let barrier = Barrier::new(2);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            // Wedged: holds the lock until the waiter has given up.
+            let guard = transport.lock(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            barrier.wait();
+            drop(guard);
+        });
+        barrier.wait();
+
+        let token = cancellation.clone();
+        let (transport, device) = (&transport, &device);
+        let waiter = scope.spawn(move || {
+            let permission = match transport.lock_cancellable(OuterMutexPermission::get(), &token) {
+                Err(CancellableLockError::Cancelled(permission)) => permission,
+                other => panic!("expected Cancelled, got {:?}", other.map(|_| ())),
+            };
+            // The permission is intact, so other locks can still be taken.
+            *device.lock(permission).guard() += 1;
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while transport.waiters() == 0 {
+            assert!(
+                Instant::now() < deadline,
+                "the waiter never started waiting"
+            );
+            thread::yield_now();
+        }
+        cancellation.cancel();
+        waiter.join().unwrap();
+        assert_eq!(transport.waiters(), 0);
+        barrier.wait();
+    });
+
+    assert_eq!(*device.lock(OuterMutexPermission::get()).guard(), 1);
+}
+
+#[test]
+fn uncancelled_acquisition_waits_for_the_holder() {
+    let transport = DeadlockProofMutex::new(0u32, Transport);
+    let cancellation = transport.cancellation_token();
+    let barrier = Barrier::new(2);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut guard = transport.lock(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            thread::sleep(Duration::from_millis(10));
+            *guard += 1;
+        });
+        barrier.wait();
+        let guard = transport
+            .lock_cancellable(OuterMutexPermission::get(), &cancellation)
+            .unwrap();
+        assert_eq!(*guard, 1);
+    });
+    assert!(!cancellation.is_cancelled());
+}
+
+#[test]
+fn cancelled_token_gives_up_even_on_a_free_mutex() {
+    let transport = DeadlockProofMutex::new(0u32, Transport);
+    let cancellation = transport.cancellation_token();
+    cancellation.clone().cancel();
+    assert!(cancellation.is_cancelled());
+
+    let permission = transport
+        .lock_cancellable(OuterMutexPermission::get(), &cancellation)
+        .map(|_| ())
+        .unwrap_err()
+        .into_permission();
+    assert!(!transport.is_locked());
+    assert_eq!(*transport.lock(permission).guard(), 0);
+}
@@ -0,0 +1,88 @@
+#![cfg(feature = "tracking")]
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use deadlock_proof::{unique_type, DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+
+/// Counts the change notifications of `mutex`.
+fn notifications<T, I>(mutex: &DeadlockProofMutex<T, OuterMutexPermission, I>) -> Arc<AtomicU64> {
+    let count = Arc::new(AtomicU64::new(0));
+    let counter = count.clone();
+    mutex.on_change(move |_| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+    count
+}
+
+#[test]
+fn writing_back_the_same_value_notifies_nobody() {
+    let mutex = DeadlockProofMutex::new(vec![1u32, 2], unique_type!());
+    let notified = notifications(&mutex);
+
+    let mut guard = mutex.lock(OuterMutexPermission::get()).guard();
+    let mut data = guard.get_mut_eq();
+    data.push(3);
+    data.pop();
+    drop(data);
+    let (mut guard, nested) = mutex.lock_for_nested(guard.unlock()).guard();
+    guard.get_mut_eq()[0] = 1;
+    guard.unlock(nested);
+
+    assert_eq!(mutex.version(), 0);
+    assert_eq!(notified.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn a_real_change_notifies_once() {
+    let mutex = DeadlockProofMutex::new(vec![1u32, 2], unique_type!());
+    let notified = notifications(&mutex);
+
+    let mut guard = mutex.lock(OuterMutexPermission::get()).guard();
+    guard.get_mut_eq()[0] = 10;
+    // A later no-op check does not take the change back.
+    guard.get_mut_eq()[1] = 2;
+    let permission = guard.unlock();
+
+    assert_eq!(mutex.version(), 1);
+    assert_eq!(notified.load(Ordering::Relaxed), 1);
+    assert_eq!(*mutex.lock(permission).guard(), [10, 2]);
+}
+
+#[derive(Clone)]
+struct Interface {
+    mtu: u32,
+    /// Bookkeeping nobody downstream watches.
+    polls: u64,
+}
+
+#[test]
+fn custom_comparison_and_force_dirty() {
+    let mutex = DeadlockProofMutex::new(
+        Interface {
+            mtu: 1500,
+            polls: 0,
+        },
+        unique_type!(),
+    );
+    let notified = notifications(&mutex);
+    let same_mtu = |a: &Interface, b: &Interface| a.mtu == b.mtu;
+
+    let mut guard = mutex.lock(OuterMutexPermission::get()).guard();
+    guard.get_mut_eq_by(same_mtu).polls += 1;
+    let permission = guard.unlock();
+    assert_eq!(notified.load(Ordering::Relaxed), 0);
+
+    let mut guard = mutex.lock(permission).guard();
+    guard.get_mut_eq_by(same_mtu).mtu = 9000;
+    let permission = guard.unlock();
+    assert_eq!(notified.load(Ordering::Relaxed), 1);
+
+    let mut guard = mutex.lock(permission).guard();
+    guard.force_dirty();
+    guard.unlock();
+    assert_eq!(notified.load(Ordering::Relaxed), 2);
+    assert_eq!(mutex.version(), 2);
+}
@@ -0,0 +1,64 @@
+use std::{panic, thread};
+
+use deadlock_proof::{permission, OuterMutexPermission};
+
+#[test]
+fn fresh_thread_has_no_claims() {
+    thread::Builder::new()
+        .name("fresh-worker".into())
+        .spawn(|| {
+            let state = permission::thread_debug_state();
+            assert_eq!(state.claims, 0);
+            assert!(state.claimed_at.is_none());
+            assert_eq!(state.thread_name.as_deref(), Some("fresh-worker"));
+            assert_eq!(state.thread_id, thread::current().id());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+#[cfg_attr(
+    not(any(debug_assertions, feature = "diagnostics")),
+    ignore = "claim sites are only tracked in debug builds or with the diagnostics feature"
+)]
+fn second_claim_is_diagnosed() {
+    let first_line = line!() + 1;
+    let _permission = OuterMutexPermission::get_or_diagnose().expect("first claim succeeds");
+
+    let diagnostics = match OuterMutexPermission::get_or_diagnose() {
+        Ok(_) => panic!("second claim must fail"),
+        Err(diagnostics) => diagnostics,
+    };
+    assert_eq!(diagnostics.state.claims, 2);
+    assert_eq!(diagnostics.state.thread_id, thread::current().id());
+    assert_eq!(diagnostics.attempted_at.file(), file!());
+
+    let claimed_at = diagnostics.state.claimed_at.expect("tracked in debug builds");
+    assert_eq!(claimed_at.file(), file!());
+    assert_eq!(claimed_at.line(), first_line);
+
+    let third = OuterMutexPermission::get_or_diagnose().err().unwrap();
+    assert_eq!(third.state.claims, 3);
+    assert_eq!(third.state.claimed_at, Some(claimed_at));
+}
+
+#[test]
+fn get_panics_with_the_diagnostics() {
+    thread::Builder::new()
+        .name("double-claimer".into())
+        .spawn(|| {
+            let _permission = OuterMutexPermission::get();
+            let payload = panic::catch_unwind(|| OuterMutexPermission::get()).err().unwrap();
+            let message = payload.downcast_ref::<String>().expect("formatted panic message");
+
+            assert!(message.starts_with("Mutex permission already claimed for this thread"));
+            assert!(message.contains("\"double-claimer\""));
+            assert!(message.contains("2 claim attempt(s)") || cfg!(not(debug_assertions)));
+            assert!(message.contains(file!()));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
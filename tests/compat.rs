@@ -0,0 +1,77 @@
+use std::sync::Mutex;
+
+use deadlock_proof::{
+    compat::{CompatGuard, MaybeProofed},
+    DeadlockProofMutex, LockOutcome, OuterMutexPermission,
+};
+
+struct CounterLock;
+
+type Counter = DeadlockProofMutex<u64, OuterMutexPermission, CounterLock>;
+type Either<'a> = MaybeProofed<'a, u64, OuterMutexPermission, CounterLock>;
+
+/// A function mid-migration: it takes either kind of mutex.
+fn add<'a>(
+    counter: impl Into<Either<'a>>,
+    permission: Option<OuterMutexPermission>,
+    amount: u64,
+) -> Option<OuterMutexPermission> {
+    let mut counter = counter.into().lock_compat(permission).guard();
+    *counter += amount;
+    counter.unlock()
+}
+
+#[test]
+fn raw_mutex_hands_back_whatever_permission_it_got() {
+    let raw = Mutex::new(1);
+    assert!(!Either::from(&raw).is_proofed());
+
+    let permission = add(&raw, Some(OuterMutexPermission::get()), 2);
+    assert!(permission.is_some());
+    assert!(add(&raw, None, 3).is_none());
+    assert_eq!(*raw.lock().unwrap(), 6);
+
+    // The permission was never used, so it still locks a migrated mutex.
+    let proofed = Counter::new(0, CounterLock);
+    assert!(add(&proofed, permission, 1).is_some());
+}
+
+#[test]
+fn proofed_mutex_is_locked_with_the_permission() {
+    let proofed = Counter::new(1, CounterLock);
+    let either = Either::from(&proofed);
+    assert!(either.is_proofed());
+
+    let guard = either
+        .lock_compat(Some(OuterMutexPermission::get()))
+        .guard();
+    assert!(matches!(guard, CompatGuard::Proofed(_)));
+    #[cfg(feature = "tracking")]
+    let version = proofed.version();
+    let permission = add(either, guard.unlock(), 2);
+    #[cfg(feature = "tracking")]
+    assert_eq!(proofed.version(), version + 1);
+
+    let guard = proofed.lock(permission.unwrap()).guard();
+    assert_eq!(*guard, 3);
+}
+
+#[test]
+#[should_panic = "cannot be locked without a permission"]
+fn proofed_mutex_refuses_to_lock_without_a_permission() {
+    let proofed = Counter::new(0, CounterLock);
+    add(&proofed, None, 1);
+}
+
+#[test]
+#[allow(deprecated)]
+fn unmigrated_wraps_a_raw_mutex() {
+    let raw = Mutex::new(0);
+    let permission = add(
+        Either::unmigrated(&raw),
+        Some(OuterMutexPermission::get()),
+        4,
+    );
+    assert!(permission.is_some());
+    assert_eq!(*raw.lock().unwrap(), 4);
+}
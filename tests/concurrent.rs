@@ -0,0 +1,72 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use deadlock_proof::{concurrent, LockOutcome, NetworkStack};
+
+#[test]
+fn workers_borrow_from_the_caller() {
+    let stack = NetworkStack::new();
+    let workers = (0..8)
+        .map(|_| -> concurrent::Worker<'_> {
+            Box::new(|permission| {
+                let mut ip = stack.ip_layer.lock(permission).guard();
+                ip.packets_processed += 1;
+                ip.unlock()
+            })
+        })
+        .collect();
+    concurrent::run_all(workers).unwrap();
+
+    let ip = stack
+        .ip_layer
+        .lock(deadlock_proof::OuterMutexPermission::get())
+        .guard();
+    assert_eq!(ip.packets_processed, 8);
+}
+
+#[test]
+fn results_come_back_in_input_order() {
+    let workers = (0..6u64)
+        .map(|i| -> concurrent::WorkerWith<'_, u64> {
+            Box::new(move |permission| {
+                std::thread::sleep(std::time::Duration::from_millis(6 - i));
+                (permission, i * i)
+            })
+        })
+        .collect();
+    assert_eq!(
+        concurrent::run_all_with(workers).unwrap(),
+        [0, 1, 4, 9, 16, 25]
+    );
+}
+
+#[test]
+fn first_panic_is_reported_with_its_payload() {
+    let finished = AtomicU32::new(0);
+    let workers: Vec<concurrent::Worker<'_>> = vec![
+        Box::new(|permission| {
+            finished.fetch_add(1, Ordering::Relaxed);
+            permission
+        }),
+        Box::new(|_| panic!("worker one failed")),
+        Box::new(|permission| {
+            finished.fetch_add(1, Ordering::Relaxed);
+            permission
+        }),
+        Box::new(|_| std::panic::panic_any(3u8)),
+    ];
+
+    let panic = concurrent::run_all(workers).unwrap_err();
+    assert_eq!(panic.index, 1);
+    assert_eq!(panic.message(), Some("worker one failed"));
+    assert_eq!(panic.to_string(), "worker 1 panicked: worker one failed");
+    assert_eq!(panic.also_panicked, [3]);
+    // The others still ran to completion.
+    assert_eq!(finished.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+#[should_panic(expected = "resumed")]
+fn panic_can_be_resumed_on_the_caller() {
+    let workers: Vec<concurrent::Worker<'_>> = vec![Box::new(|_| panic!("resumed"))];
+    concurrent::run_all(workers).unwrap_err().resume();
+}
@@ -0,0 +1,22 @@
+use deadlock_proof::{configure, lease, setup, unclaimed, Config, ConfigureError, Policy};
+
+#[test]
+fn configures_once() {
+    assert!(!setup::is_configured());
+    let config = Config::new()
+        .strict_leases(true)
+        .unclaimed_policy(Policy::AutoClaim);
+    assert_eq!(configure(config), Ok(()));
+    assert!(setup::is_configured());
+    assert!(lease::is_strict());
+    assert_eq!(unclaimed::policy(), Policy::AutoClaim);
+
+    // A second call changes nothing, even with different settings.
+    assert_eq!(
+        configure(Config::new().strict_leases(false)),
+        Err(ConfigureError::AlreadyConfigured)
+    );
+    assert!(lease::is_strict());
+    lease::set_strict(false);
+    unclaimed::set_policy(Policy::Panic);
+}
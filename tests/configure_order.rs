@@ -0,0 +1,44 @@
+#![cfg(all(feature = "metrics-exporter", feature = "profiling"))]
+
+use deadlock_proof::{
+    configure, lease,
+    metrics::{set_recorder, Recorder},
+    profiling::{set_profiler, Profiler},
+    setup, Config, ConfigureError,
+};
+
+struct Discard;
+
+impl Recorder for Discard {
+    fn register_histogram(&self, _name: &'static str, _mutex: &'static str) {}
+
+    fn record_histogram(&self, _name: &'static str, _mutex: &'static str, _nanos: u64) {}
+}
+
+impl Profiler for Discard {
+    fn begin_scope(&self, _name: &'static str) {}
+
+    fn end_scope(&self, _name: &'static str) {}
+}
+
+#[test]
+fn stops_at_the_first_failed_section() {
+    assert!(set_recorder(&Discard).is_ok());
+    let config = Config::new()
+        .recorder(&Discard)
+        .profiler(&Discard)
+        .strict_leases(true);
+    assert_eq!(configure(config), Err(ConfigureError::RecorderTaken));
+
+    // The profiler and lease sections come after the recorder, so neither
+    // was applied.
+    assert!(set_profiler(&Discard).is_ok());
+    assert!(!lease::is_strict());
+
+    // The failed call still counts as the one call.
+    assert!(setup::is_configured());
+    assert_eq!(
+        configure(Config::new()),
+        Err(ConfigureError::AlreadyConfigured)
+    );
+}
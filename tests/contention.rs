@@ -0,0 +1,143 @@
+#![cfg(feature = "tracking")]
+
+use std::{
+    sync::{Barrier, Mutex},
+    thread,
+    time::Duration,
+};
+
+use deadlock_proof::{
+    ContentionEvent, DeadlockProofMutex, LockOutcome, OuterMutexPermission, TryLockError,
+};
+
+static EVENTS: Mutex<Vec<ContentionEvent>> = Mutex::new(Vec::new());
+
+fn record(event: ContentionEvent) {
+    EVENTS.lock().unwrap().push(event);
+}
+
+/// The events reported for mutexes identified by `I`, since tests share the
+/// log.
+fn events_of<I>() -> Vec<ContentionEvent> {
+    let identifier = std::any::type_name::<I>();
+    EVENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .copied()
+        .filter(|event| event.identifier() == identifier)
+        .collect()
+}
+
+/// Holds `mutex` on another thread for `hold` while this thread locks it.
+fn contend<I: Send + Sync>(
+    mutex: &DeadlockProofMutex<u32, OuterMutexPermission, I>,
+    hold: Duration,
+) {
+    let barrier = Barrier::new(2);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let guard = mutex.lock(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            thread::sleep(hold);
+            drop(guard);
+        });
+        barrier.wait();
+        scope
+            .spawn(|| {
+                *mutex.lock(OuterMutexPermission::get()).guard() += 1;
+            })
+            .join()
+            .unwrap();
+    });
+}
+
+#[test]
+fn contended_acquisition_reports_both_events() {
+    struct Contended;
+    let mutex = DeadlockProofMutex::new(0u32, Contended);
+    mutex.set_contention_callback(record);
+
+    contend(&mutex, Duration::from_millis(20));
+
+    let identifier = std::any::type_name::<Contended>();
+    match events_of::<Contended>()[..] {
+        [ContentionEvent::WillBlock {
+            identifier: will_block,
+            waiters,
+        }, ContentionEvent::Acquired {
+            identifier: acquired,
+            waited,
+        }] => {
+            assert_eq!((will_block, acquired), (identifier, identifier));
+            assert!(waiters >= 1);
+            assert!(waited >= Duration::from_millis(5), "{waited:?}");
+        }
+        ref events => panic!("unexpected events {events:?}"),
+    }
+}
+
+#[test]
+fn free_mutex_reports_nothing() {
+    struct Free;
+    let mutex = DeadlockProofMutex::new(0u32, Free);
+    mutex.set_contention_callback(record);
+
+    let mut permission = OuterMutexPermission::get();
+    for _ in 0..10 {
+        permission = mutex.lock(permission).guard().unlock();
+    }
+    assert!(events_of::<Free>().is_empty());
+}
+
+#[test]
+fn cleared_callback_is_not_called() {
+    struct Cleared;
+    let mutex = DeadlockProofMutex::new(0u32, Cleared);
+    mutex.set_contention_callback(record);
+    mutex.clear_contention_callback();
+
+    contend(&mutex, Duration::from_millis(5));
+    assert!(events_of::<Cleared>().is_empty());
+}
+
+#[test]
+fn timed_acquisitions_report_like_blocking_ones() {
+    struct Timed;
+    let mutex = DeadlockProofMutex::new(0u32, Timed);
+    mutex.set_contention_callback(record);
+
+    let barrier = Barrier::new(2);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let guard = mutex.lock(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            thread::sleep(Duration::from_millis(20));
+            drop(guard);
+        });
+        barrier.wait();
+        scope
+            .spawn(|| {
+                let handle = mutex.rt_handle();
+                let permission =
+                    match handle.try_lock_for(OuterMutexPermission::get(), Duration::ZERO) {
+                        Err(TryLockError::WouldBlock(permission)) => permission,
+                        _ => panic!("the mutex is held"),
+                    };
+                let Ok(guard) = handle.try_lock_for(permission, Duration::from_secs(5)) else {
+                    panic!("the holder lets go after 20ms");
+                };
+                drop(guard);
+            })
+            .join()
+            .unwrap();
+    });
+
+    match events_of::<Timed>()[..] {
+        [ContentionEvent::WillBlock { .. }, ContentionEvent::WillBlock { .. }, ContentionEvent::Acquired { waited, .. }] =>
+        {
+            assert!(waited >= Duration::from_millis(5), "{waited:?}");
+        }
+        ref events => panic!("unexpected events {events:?}"),
+    }
+}
@@ -0,0 +1,79 @@
+#![cfg(feature = "diagnostics")]
+
+use std::{sync::mpsc, thread};
+
+use deadlock_proof::{
+    diagnostics, DeadlockProofMutex, LockOutcome, NetworkStack, OuterMutexPermission,
+};
+
+struct WedgedLock;
+
+#[test]
+fn records_the_holder_while_held() {
+    let stack = NetworkStack::new();
+    assert_eq!(stack.device_layer.current_holder(), None);
+
+    let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    let line = line!() + 1;
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    assert_eq!(stack.ip_layer.current_holder(), None);
+    let holder = thread::scope(|scope| {
+        // Readable from any thread, without a permission.
+        scope
+            .spawn(|| stack.device_layer.current_holder())
+            .join()
+            .unwrap()
+    })
+    .unwrap();
+    assert_eq!(holder.thread, thread::current().id());
+    assert_eq!(holder.location.file(), file!());
+    assert_eq!(holder.location.line(), line);
+
+    let _permission = device.unlock_for_sequential();
+    assert_eq!(stack.device_layer.current_holder(), None);
+}
+
+#[test]
+fn names_the_holding_thread() {
+    let wedged = DeadlockProofMutex::new((), WedgedLock);
+    let (held, wait_for_held) = mpsc::channel();
+    let (release, wait_for_release) = mpsc::channel();
+    thread::scope(|scope| {
+        let wedged = &wedged;
+        thread::Builder::new()
+            .name("wedged-worker".into())
+            .spawn_scoped(scope, move || {
+                let guard = wedged.lock(OuterMutexPermission::get()).guard();
+                held.send(()).unwrap();
+                wait_for_release.recv().unwrap();
+                guard.unlock();
+            })
+            .unwrap();
+        wait_for_held.recv().unwrap();
+
+        let holder = wedged.current_holder().unwrap();
+        assert_eq!(holder.thread_name.as_deref(), Some("wedged-worker"));
+        let dump = diagnostics::dump_all_held();
+        let line = dump
+            .lines()
+            .find(|line| line.contains("WedgedLock"))
+            .unwrap();
+        assert!(line.contains("held by `wedged-worker` for "));
+        assert!(line.contains(file!()));
+
+        release.send(()).unwrap();
+    });
+    assert_eq!(wedged.current_holder(), None);
+    assert!(!diagnostics::dump_all_held().contains("WedgedLock"));
+}
+
+#[test]
+fn dump_counts_registered_mutexes() {
+    let _stack = NetworkStack::new();
+    let dump = diagnostics::dump_all_held();
+    let totals = dump.lines().next().unwrap();
+    assert!(totals.ends_with(" mutexes held"));
+    // "<held> of <registered> mutexes held"
+    let registered: usize = totals.split(' ').nth(2).unwrap().parse().unwrap();
+    assert!(registered >= 3);
+}
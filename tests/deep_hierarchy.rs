@@ -0,0 +1,127 @@
+// Most levels are only created by a test that `max-depth-8` leaves out.
+#![cfg_attr(feature = "max-depth-8", allow(dead_code))]
+
+use deadlock_proof::{
+    lock_hierarchy, DeadlockProofMutex, DeepSequentialPermission, LockLevel, LockOutcome,
+    OuterMutexPermission, Position,
+};
+
+struct L0;
+struct L1;
+struct L2;
+struct L3;
+struct L4;
+struct L5;
+struct L6;
+struct L7;
+struct L8;
+struct L9;
+struct L10;
+struct L11;
+lock_hierarchy!(OuterMutexPermission => L0, L1, L2, L3, L4, L5, L6, L7, L8, L9, L10, L11);
+
+struct Short0;
+struct Short1;
+lock_hierarchy!(OuterMutexPermission => Short0, Short1);
+
+/// A mutex at the position its identifier declares.
+fn level<T, I: LockLevel>(content: T, identifier: I) -> DeadlockProofMutex<T, Position<I>, I> {
+    DeadlockProofMutex::new(content, identifier)
+}
+
+#[test]
+fn deep_positions_are_flat() {
+    let l0 = level((), L0);
+    let l1 = level((), L1);
+    let guard = l0.lock(OuterMutexPermission::get()).guard();
+    let permission: DeepSequentialPermission<OuterMutexPermission, 1> =
+        DeepSequentialPermission::after_root(guard);
+    let permission: Position<L2> = l1.lock(permission).guard().advance();
+    let _root: OuterMutexPermission = permission.into_root();
+}
+
+#[test]
+fn levels_count_from_the_root() {
+    assert_eq!(L0::LEVEL, 0);
+    assert_eq!(L1::LEVEL, 1);
+    assert_eq!(L8::LEVEL, 8);
+    assert_eq!(L11::LEVEL, 11);
+    assert_eq!(Short1::LEVEL, 1);
+}
+
+// Levels 8 and up are too deep to create under `max-depth-8`.
+#[cfg(not(feature = "max-depth-8"))]
+#[test]
+fn twelve_levels_are_walked_in_order() {
+    let l0 = level(0u32, L0);
+    let l1 = level(1u32, L1);
+    let l2 = level(2u32, L2);
+    let l3 = level(3u32, L3);
+    let l4 = level(4u32, L4);
+    let l5 = level(5u32, L5);
+    let l6 = level(6u32, L6);
+    let l7 = level(7u32, L7);
+    let l8 = level(8u32, L8);
+    let l9 = level(9u32, L9);
+    let l10 = level(10u32, L10);
+    let l11 = level(11u32, L11);
+
+    let mut sum = 0;
+    let guard = l0.lock(OuterMutexPermission::get()).guard();
+    sum += *guard;
+    let permission = DeepSequentialPermission::after_root(guard);
+    macro_rules! step {
+        ($mutex:ident, $permission:ident) => {{
+            let guard = $mutex.lock($permission).guard();
+            sum += *guard;
+            guard
+        }};
+    }
+    let permission = step!(l1, permission).advance();
+    let permission = step!(l2, permission).advance();
+    let permission = step!(l3, permission).advance();
+    let permission = step!(l4, permission).advance();
+    let permission = step!(l5, permission).advance();
+    let permission = step!(l6, permission).advance();
+    let permission = step!(l7, permission).advance();
+    let permission = step!(l8, permission).advance();
+    let permission = step!(l9, permission).advance();
+    let permission = step!(l10, permission).advance();
+    let permission = step!(l11, permission).unlock();
+    assert_eq!(sum, (0..12).sum());
+
+    // Walking back up lands on the root again.
+    let permission = permission.retreat().retreat().retreat().retreat().retreat();
+    let permission = permission.retreat().retreat().retreat().retreat().retreat();
+    let root: Position<L0> = permission.retreat();
+    assert!(l0.lock(root).into_result().is_ok());
+}
+
+#[test]
+fn short_hierarchies_keep_the_nested_form() {
+    let short0 = level((), Short0);
+    let short1 = level((), Short1);
+    let permission = short0
+        .lock(OuterMutexPermission::get())
+        .guard()
+        .unlock_for_sequential();
+    let permission: deadlock_proof::After<Short0> = permission;
+    assert!(short1.lock(permission).into_result().is_ok());
+}
+
+#[test]
+fn deep_levels_can_be_skipped() {
+    let l0 = level(0u32, L0);
+    let l1 = level(1u32, L1);
+    let l4 = level(4u32, L4);
+
+    let guard = l0.lock(OuterMutexPermission::get()).guard();
+    let permission = DeepSequentialPermission::after_root(guard);
+    let permission = l1.lock(permission).guard().advance();
+    let permission: Position<L4> = permission.skip_to::<L2>().skip_to::<L3>();
+    let guard = l4.lock(permission).guard();
+    assert_eq!(*guard, 4);
+
+    let root: Position<L0> = guard.unlock().retreat().retreat().retreat().retreat();
+    assert!(l0.lock(root).into_result().is_ok());
+}
@@ -0,0 +1,93 @@
+#![cfg(feature = "diff-log")]
+
+use std::{sync::Mutex, thread};
+
+use deadlock_proof::{
+    diff_log::{self, DiffEntry},
+    DeadlockProofMutex, LockOutcome, OuterMutexPermission,
+};
+
+/// The log is global, so tests that fill it must not overlap.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn entries_of<I>() -> Vec<DiffEntry> {
+    diff_log::recent()
+        .into_iter()
+        .filter(|entry| entry.identifier == std::any::type_name::<I>())
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Connections {
+    tcp: u32,
+}
+
+#[test]
+fn changes_are_logged_with_their_critical_section() {
+    let _serial = SERIAL.lock().unwrap_or_else(|error| error.into_inner());
+    struct Transport;
+    let transport = DeadlockProofMutex::new_with_diff_logging(Connections { tcp: 5 }, Transport);
+
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .name("resetter".into())
+            .spawn_scoped(scope, || {
+                let mut guard = transport.lock(OuterMutexPermission::get()).guard();
+                guard.tcp = 0;
+            })
+            .unwrap();
+    });
+    let mut permission = OuterMutexPermission::get();
+    // Reading, or writing the same value back, changes nothing.
+    permission = transport.lock(permission).guard().unlock();
+    let mut guard = transport.lock(permission).guard();
+    guard.tcp = 0;
+    let line = line!() + 1;
+    permission = guard.unlock();
+    let (mut guard, _token) = transport.lock_for_nested(permission).guard();
+    guard.tcp += 2;
+    drop(guard);
+
+    let entries = entries_of::<Transport>();
+    assert_eq!(entries.len(), 2, "{entries:#?}");
+    assert_eq!(entries[0].thread_name.as_deref(), Some("resetter"));
+    assert_eq!(entries[0].before, "Connections { tcp: 5 }");
+    assert_eq!(entries[0].after, "Connections { tcp: 0 }");
+    assert_eq!(entries[1].thread, thread::current().id());
+    assert_eq!(entries[1].location.file(), file!());
+    assert_eq!(entries[1].location.line(), line + 1);
+    assert_eq!(entries[1].after, "Connections { tcp: 2 }");
+}
+
+#[test]
+fn plain_mutexes_are_not_logged() {
+    let _serial = SERIAL.lock().unwrap_or_else(|error| error.into_inner());
+    struct Plain;
+    let plain = DeadlockProofMutex::new(Connections { tcp: 1 }, Plain);
+    plain.lock(OuterMutexPermission::get()).guard().tcp = 2;
+    assert!(entries_of::<Plain>().is_empty());
+}
+
+#[test]
+fn log_keeps_only_the_latest_entries() {
+    let _serial = SERIAL.lock().unwrap_or_else(|error| error.into_inner());
+    struct Counter;
+    let counter = DeadlockProofMutex::new_with_diff_logging(0usize, Counter);
+
+    let mut permission = OuterMutexPermission::get();
+    for _ in 0..diff_log::CAPACITY + 10 {
+        let mut guard = counter.lock(permission).guard();
+        *guard += 1;
+        permission = guard.unlock();
+    }
+
+    let entries = diff_log::recent();
+    assert_eq!(entries.len(), diff_log::CAPACITY);
+    let last = entries.last().unwrap();
+    assert_eq!(last.before, (diff_log::CAPACITY + 9).to_string());
+    assert_eq!(last.after, (diff_log::CAPACITY + 10).to_string());
+    assert_eq!(entries[0].after, "11");
+
+    diff_log::clear();
+    assert!(diff_log::recent().is_empty());
+}
@@ -0,0 +1,68 @@
+//! Runs the echo server example over loopback.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use deadlock_proof::OuterMutexPermission;
+
+#[path = "../examples/echo_server.rs"]
+#[allow(dead_code)]
+mod echo_server;
+
+fn echo(client: &mut TcpStream, message: &[u8]) {
+    client.write_all(message).unwrap();
+    let mut echoed = vec![0; message.len()];
+    client.read_exact(&mut echoed).unwrap();
+    assert_eq!(echoed, message);
+}
+
+#[test]
+fn clients_are_echoed_and_counted() {
+    let server = echo_server::serve(TcpListener::bind("127.0.0.1:0").unwrap()).unwrap();
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                let mut client = TcpStream::connect(server.addr).unwrap();
+                for _ in 0..10 {
+                    echo(&mut client, b"ping");
+                }
+            });
+        }
+    });
+
+    // Hung up clients are closed by their connection threads.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !server.registry.is_empty() {
+        assert!(Instant::now() < deadline, "connections were not closed");
+        thread::sleep(Duration::from_millis(1));
+    }
+    let (stack, _) = server.shut_down();
+    let (_, views) = stack.views(OuterMutexPermission::get());
+    assert_eq!(views.device.bytes_transmitted, 4 * 10 * 4);
+    // A read may have picked up more than one ping.
+    assert!((1..=40).contains(&views.ip.packets_processed));
+    assert_eq!(views.transport.tcp_connections, 0);
+}
+
+#[test]
+fn shutdown_closes_idle_connections() {
+    let server = echo_server::serve(TcpListener::bind("127.0.0.1:0").unwrap()).unwrap();
+    let mut client = TcpStream::connect(server.addr).unwrap();
+    echo(&mut client, b"hello");
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while server.registry.len() != 1 {
+        assert!(Instant::now() < deadline, "connection was not registered");
+        thread::sleep(Duration::from_millis(1));
+    }
+    // The client stays connected: shutting down must not wait for it.
+    let (stack, registry) = server.shut_down();
+    assert!(registry.is_empty());
+    let (_, views) = stack.views(OuterMutexPermission::get());
+    assert_eq!(views.transport.tcp_connections, 0);
+    drop(client);
+}
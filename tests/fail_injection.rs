@@ -0,0 +1,87 @@
+#![cfg(feature = "test-util")]
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use deadlock_proof::{
+    fail, unique_type, DeadlockProofMutex, LockOutcome, OuterMutexPermission, TryLockError,
+};
+
+#[test]
+fn rcu_reads_ignore_poison() {
+    let mutex = DeadlockProofMutex::new(std::sync::Arc::new(5u32), unique_type!());
+    mutex.poison_for_test();
+    let (permission, value) = mutex.clone_inner(OuterMutexPermission::get());
+    assert_eq!(*value, 5);
+    let (_permission, previous) = mutex.swap_inner(permission, std::sync::Arc::new(6));
+    assert_eq!(*previous, 5);
+}
+
+#[test]
+fn injected_contention_times_out_a_timed_lock() {
+    const DELAY: Duration = Duration::from_millis(50);
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    fail::inject_contention(&mutex, DELAY);
+    let handle = mutex.rt_handle();
+
+    let started = Instant::now();
+    let Err(TryLockError::WouldBlock(permission)) =
+        handle.try_lock_for(OuterMutexPermission::get(), DELAY / 5)
+    else {
+        panic!("locked through the injected contention");
+    };
+    assert!(started.elapsed() < DELAY);
+    let Err(TryLockError::WouldBlock(permission)) = mutex.try_lock(permission) else {
+        panic!("the injected delay is not over yet");
+    };
+
+    // A longer timeout waits the rest of the delay out.
+    let Ok(guard) = handle.try_lock_for(permission, DELAY * 10) else {
+        panic!("the injected delay is over");
+    };
+    assert!(started.elapsed() >= DELAY);
+    let permission = guard.unlock();
+    assert!(mutex.try_lock(permission).is_ok());
+}
+
+#[test]
+fn injected_contention_delays_the_next_acquisition_only() {
+    const DELAY: Duration = Duration::from_millis(50);
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    fail::inject_contention(&mutex, DELAY);
+
+    let _permission = thread::scope(|scope| {
+        let waiter = scope.spawn(|| {
+            let started = Instant::now();
+            let guard = mutex.lock(OuterMutexPermission::get()).guard();
+            let waited = started.elapsed();
+            guard.unlock();
+            waited
+        });
+        #[cfg(feature = "tracking")]
+        {
+            while !mutex.is_contended() {
+                thread::yield_now();
+            }
+            assert_eq!(mutex.waiters(), 1);
+        }
+        assert!(waiter.join().unwrap() >= DELAY);
+
+        let started = Instant::now();
+        let permission = mutex.lock(OuterMutexPermission::get()).guard().unlock();
+        assert!(started.elapsed() < DELAY);
+        permission
+    });
+
+    #[cfg(feature = "metrics")]
+    {
+        let histogram = mutex.wait_histogram();
+        let slow = (0..deadlock_proof::metrics::BUCKETS)
+            .filter(|&i| deadlock_proof::metrics::Histogram::bucket_floor(i) >= DELAY / 2)
+            .map(|i| histogram.buckets()[i])
+            .sum::<u64>();
+        assert_eq!(slow, 1);
+    }
+}
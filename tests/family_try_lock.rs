@@ -0,0 +1,132 @@
+use std::{
+    sync::{mpsc, Barrier},
+    thread,
+};
+
+use deadlock_proof::{
+    declare_mutex_family, FamilyId, LockOutcome, MutexFamily, OuterMutexPermission,
+};
+
+declare_mutex_family!(QueueLock: Q0, Q1, Q2, Q3);
+
+type Queues = MutexFamily<Vec<usize>, OuterMutexPermission, QueueLock, QueueLockId>;
+
+fn queues() -> Queues {
+    Queues::new(QueueLock, |id| vec![id.index()])
+}
+
+#[test]
+fn locks_every_free_member() {
+    let queues = queues();
+    let mut available = queues.try_lock_available(OuterMutexPermission::get());
+    assert_eq!(available.len(), 4);
+    assert!(available.skipped().is_empty());
+    for (id, queue) in available.iter_mut() {
+        queue.push(id.index() * 10);
+    }
+    let ids: Vec<_> = available.iter().map(|(id, _)| id).collect();
+    assert_eq!(ids, QueueLockId::ALL);
+
+    let permission = available.unlock_all();
+    let guard = queues.get(QueueLockId::Q3).lock(permission).guard();
+    assert_eq!(*guard, [3, 30]);
+    #[cfg(feature = "tracking")]
+    assert_eq!(queues.get(QueueLockId::Q0).version(), 1);
+}
+
+#[test]
+fn skips_a_member_held_by_another_thread() {
+    let queues = queues();
+    let (held, wait_for_held) = mpsc::channel();
+    let (release, wait_for_release) = mpsc::channel::<()>();
+    let queues = &queues;
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let guard = queues
+                .get(QueueLockId::Q2)
+                .lock(OuterMutexPermission::get())
+                .guard();
+            held.send(()).unwrap();
+            wait_for_release.recv().unwrap();
+            guard.unlock();
+        });
+        wait_for_held.recv().unwrap();
+
+        let available = queues.try_lock_available(OuterMutexPermission::get());
+        assert_eq!(available.skipped(), [QueueLockId::Q2]);
+        let ids: Vec<_> = available.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, [QueueLockId::Q0, QueueLockId::Q1, QueueLockId::Q3]);
+
+        // Holding the rest does not stop the other thread from finishing
+        // with its member.
+        release.send(()).unwrap();
+        let permission = available.unlock_all();
+        let guard = queues.get(QueueLockId::Q2).lock(permission).guard();
+        assert_eq!(*guard, [2]);
+    });
+}
+
+#[test]
+fn returns_the_permission_when_nothing_is_free() {
+    let queues = queues();
+    let barrier = Barrier::new(2);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let all = queues.try_lock_available(OuterMutexPermission::get());
+            assert_eq!(all.len(), 4);
+            barrier.wait();
+            barrier.wait();
+            all.unlock_all();
+        });
+        barrier.wait();
+        let none = queues.try_lock_available(OuterMutexPermission::get());
+        assert!(none.is_empty());
+        assert_eq!(none.skipped(), QueueLockId::ALL);
+        let permission = none.unlock_all();
+        barrier.wait();
+
+        let guard = queues.get(QueueLockId::Q0).lock(permission).guard();
+        assert_eq!(*guard, [0]);
+    });
+}
+
+#[test]
+fn concurrent_drains_lose_nothing() {
+    let queues = queues();
+    let batched: usize = thread::scope(|scope| {
+        let workers: Vec<_> = (0..4)
+            .map(|worker| {
+                let queues = &queues;
+                scope.spawn(move || {
+                    let mut permission = OuterMutexPermission::get();
+                    let mut batched = 0;
+                    for _ in 0..200 {
+                        // Blocking locks mixed in with the batched ones.
+                        let id = QueueLockId::ALL[worker];
+                        let mut guard = queues.get(id).lock(permission).guard();
+                        guard.push(worker);
+                        permission = guard.unlock();
+
+                        let mut available = queues.try_lock_available(permission);
+                        for (_, queue) in available.iter_mut() {
+                            queue.push(worker);
+                        }
+                        assert_eq!(available.len() + available.skipped().len(), 4);
+                        batched += available.len();
+                        permission = available.unlock_all();
+                    }
+                    batched
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .sum()
+    });
+
+    let available = queues.try_lock_available(OuterMutexPermission::get());
+    assert_eq!(available.len(), 4);
+    let pushed: usize = available.iter().map(|(_, queue)| queue.len() - 1).sum();
+    assert_eq!(pushed, 4 * 200 + batched);
+}
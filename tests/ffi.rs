@@ -0,0 +1,243 @@
+#![cfg(feature = "ffi")]
+
+use std::{
+    ffi::{c_int, c_void},
+    ptr,
+    sync::Arc,
+    thread,
+};
+
+use deadlock_proof::{
+    ffi, lock_hierarchy, DeadlockProofMutex, LockOutcome, OuterMutexPermission, Position,
+};
+
+// The handles as C sees them, declared as in the header.
+#[repr(C)]
+struct DpmMutex {
+    _opaque: [u8; 0],
+}
+
+#[repr(C)]
+struct DpmPermission {
+    _opaque: [u8; 0],
+}
+
+unsafe extern "C" {
+    fn dpm_mutex_free(mutex: *mut DpmMutex);
+    fn dpm_mutex_level(mutex: *const DpmMutex) -> u32;
+    safe fn dpm_claim_permission() -> *mut DpmPermission;
+    fn dpm_release_permission(permission: *mut DpmPermission) -> c_int;
+    fn dpm_lock(
+        mutex: *const DpmMutex,
+        permission: *mut DpmPermission,
+        data: *mut *mut c_void,
+    ) -> c_int;
+    fn dpm_unlock(mutex: *const DpmMutex, permission: *mut DpmPermission) -> c_int;
+}
+
+struct RingLock;
+struct StatsLock;
+lock_hierarchy!(OuterMutexPermission => RingLock, StatsLock);
+
+#[repr(C)]
+struct Ring {
+    head: u32,
+    tail: u32,
+}
+
+#[repr(C)]
+struct Stats {
+    enqueued: u64,
+}
+
+struct Shared {
+    ring: Arc<DeadlockProofMutex<Ring, Position<RingLock>, RingLock>>,
+    stats: Arc<DeadlockProofMutex<Stats, Position<StatsLock>, StatsLock>>,
+    ring_handle: *mut DpmMutex,
+    stats_handle: *mut DpmMutex,
+}
+
+impl Shared {
+    fn new() -> Self {
+        let ring = Arc::new(DeadlockProofMutex::new(Ring { head: 0, tail: 0 }, RingLock));
+        let stats = Arc::new(DeadlockProofMutex::new(Stats { enqueued: 0 }, StatsLock));
+        Self {
+            ring_handle: ffi::share(Arc::clone(&ring)).cast(),
+            stats_handle: ffi::share(Arc::clone(&stats)).cast(),
+            ring,
+            stats,
+        }
+    }
+}
+
+// Safety: the handles point at `DpmMutex`es, which are `Sync`.
+unsafe impl Sync for Shared {}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        unsafe {
+            dpm_mutex_free(self.ring_handle);
+            dpm_mutex_free(self.stats_handle);
+        }
+    }
+}
+
+/// What a C producer does: lock the ring, then the stats, in level order.
+unsafe fn c_enqueue(shared: &Shared, permission: *mut DpmPermission) {
+    unsafe {
+        let mut ring: *mut c_void = ptr::null_mut();
+        assert_eq!(
+            dpm_lock(shared.ring_handle, permission, &mut ring),
+            ffi::DPM_OK
+        );
+        let mut stats: *mut c_void = ptr::null_mut();
+        assert_eq!(
+            dpm_lock(shared.stats_handle, permission, &mut stats),
+            ffi::DPM_OK
+        );
+        (*ring.cast::<Ring>()).tail += 1;
+        (*stats.cast::<Stats>()).enqueued += 1;
+        assert_eq!(dpm_unlock(shared.stats_handle, permission), ffi::DPM_OK);
+        assert_eq!(dpm_unlock(shared.ring_handle, permission), ffi::DPM_OK);
+    }
+}
+
+#[test]
+fn levels_come_from_the_hierarchy() {
+    let shared = Shared::new();
+    unsafe {
+        assert_eq!(dpm_mutex_level(shared.ring_handle), 0);
+        assert_eq!(dpm_mutex_level(shared.stats_handle), 1);
+    }
+}
+
+#[test]
+fn c_writes_are_seen_by_rust() {
+    let shared = Shared::new();
+    unsafe {
+        let permission = dpm_claim_permission();
+        assert!(!permission.is_null());
+        c_enqueue(&shared, permission);
+        assert_eq!(dpm_release_permission(permission), ffi::DPM_OK);
+    }
+
+    let ring = shared.ring.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(ring.tail, 1);
+    let stats = shared.stats.lock(ring.unlock_for_sequential()).guard();
+    assert_eq!(stats.enqueued, 1);
+    #[cfg(feature = "tracking")]
+    assert_eq!(shared.ring.version(), 1);
+}
+
+#[test]
+fn out_of_order_locks_are_refused() {
+    let shared = Shared::new();
+    unsafe {
+        let permission = dpm_claim_permission();
+        assert_eq!(
+            dpm_lock(shared.stats_handle, permission, ptr::null_mut()),
+            ffi::DPM_OK
+        );
+        assert_eq!(
+            dpm_lock(shared.ring_handle, permission, ptr::null_mut()),
+            ffi::DPM_ERR_ORDER
+        );
+        assert_eq!(
+            dpm_lock(shared.stats_handle, permission, ptr::null_mut()),
+            ffi::DPM_ERR_ORDER
+        );
+        assert_eq!(dpm_release_permission(permission), ffi::DPM_ERR_STILL_HELD);
+        assert_eq!(dpm_unlock(shared.stats_handle, permission), ffi::DPM_OK);
+        assert_eq!(
+            dpm_unlock(shared.stats_handle, permission),
+            ffi::DPM_ERR_NOT_HELD
+        );
+
+        // With nothing held, any level may be locked again.
+        assert_eq!(
+            dpm_lock(shared.ring_handle, permission, ptr::null_mut()),
+            ffi::DPM_OK
+        );
+        assert_eq!(dpm_unlock(shared.ring_handle, permission), ffi::DPM_OK);
+        assert_eq!(dpm_release_permission(permission), ffi::DPM_OK);
+    }
+}
+
+#[test]
+fn c_and_rust_share_the_thread_permission() {
+    unsafe {
+        let permission = dpm_claim_permission();
+        assert!(!permission.is_null());
+        assert!(dpm_claim_permission().is_null());
+        assert!(OuterMutexPermission::get_or_diagnose().is_err());
+        assert_eq!(dpm_release_permission(permission), ffi::DPM_OK);
+    }
+    let outer = OuterMutexPermission::get();
+    assert!(dpm_claim_permission().is_null());
+    let _ = outer;
+}
+
+#[test]
+fn permissions_stay_on_their_thread() {
+    struct SendPtr(*mut DpmPermission);
+    unsafe impl Send for SendPtr {}
+
+    let shared = Shared::new();
+    let permission = SendPtr(dpm_claim_permission());
+    let shared_ref = &shared;
+    let permission = thread::scope(|scope| {
+        scope
+            .spawn(move || {
+                let permission = permission;
+                unsafe {
+                    assert_eq!(
+                        dpm_lock(shared_ref.ring_handle, permission.0, ptr::null_mut()),
+                        ffi::DPM_ERR_WRONG_THREAD
+                    );
+                    assert_eq!(
+                        dpm_release_permission(permission.0),
+                        ffi::DPM_ERR_WRONG_THREAD
+                    );
+                }
+                permission
+            })
+            .join()
+            .unwrap()
+    });
+    unsafe { assert_eq!(dpm_release_permission(permission.0), ffi::DPM_OK) };
+}
+
+#[test]
+fn c_and_rust_threads_contend_safely() {
+    const ROUNDS: u64 = 500;
+    let shared = Shared::new();
+    thread::scope(|scope| {
+        for _ in 0..2 {
+            scope.spawn(|| unsafe {
+                let permission = dpm_claim_permission();
+                for _ in 0..ROUNDS {
+                    c_enqueue(&shared, permission);
+                }
+                assert_eq!(dpm_release_permission(permission), ffi::DPM_OK);
+            });
+            scope.spawn(|| {
+                let mut permission = OuterMutexPermission::get();
+                for _ in 0..ROUNDS {
+                    let mut ring = shared.ring.lock(permission).guard();
+                    ring.head += 1;
+                    let mut stats = shared.stats.lock(ring.unlock_for_sequential()).guard();
+                    stats.enqueued += 1;
+                    permission = stats.unlock().to_earlier();
+                }
+            });
+        }
+    });
+
+    let ring = shared.ring.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(
+        (ring.head, ring.tail),
+        (2 * ROUNDS as u32, 2 * ROUNDS as u32)
+    );
+    let stats = shared.stats.lock(ring.unlock_for_sequential()).guard();
+    assert_eq!(stats.enqueued, 4 * ROUNDS);
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "diagnostics")]
+
+use std::{mem::ManuallyDrop, thread};
+
+use deadlock_proof::{
+    permission::{force_reclaim_outer, held_lock_count},
+    DeadlockProofMutex, LockOutcome, OuterMutexPermission,
+};
+
+struct CallbackStateLock;
+
+/// Runs `f` on a thread of its own, which has a token of its own.
+fn on_fresh_thread(f: impl FnOnce() + Send) {
+    thread::scope(|scope| {
+        scope.spawn(f);
+    });
+}
+
+#[test]
+fn lost_token_is_reclaimed_once_no_guard_is_held() {
+    on_fresh_thread(|| {
+        let state = DeadlockProofMutex::new(0u32, CallbackStateLock);
+        // A longjmp out of a callback skips the frame that held the guard
+        // and the token: neither destructor runs.
+        let guard = state.lock(OuterMutexPermission::get()).guard();
+        let permission = guard.unlock();
+        let _skipped = ManuallyDrop::new(permission);
+        assert!(OuterMutexPermission::get_or_diagnose().is_err());
+
+        assert_eq!(held_lock_count(), 0);
+        // Safety: the only permission of this thread is in `_skipped`,
+        // which is never used again.
+        let permission = unsafe { force_reclaim_outer() }.unwrap();
+        *state.lock(permission).guard() += 1;
+    });
+}
+
+#[test]
+fn reclaim_is_refused_while_a_guard_is_leaked() {
+    on_fresh_thread(|| {
+        let state = DeadlockProofMutex::new(0u32, CallbackStateLock);
+        let _skipped = ManuallyDrop::new(state.lock(OuterMutexPermission::get()).guard());
+        assert_eq!(held_lock_count(), 1);
+        assert!(state.is_locked());
+
+        // Safety: no permission of this thread is reachable any more.
+        assert!(unsafe { force_reclaim_outer() }.is_none());
+    });
+}
+
+#[test]
+fn unclaimed_token_is_claimed_as_usual() {
+    on_fresh_thread(|| {
+        // Safety: nothing has been claimed on this thread.
+        let permission = unsafe { force_reclaim_outer() }.unwrap();
+        assert!(OuterMutexPermission::get_or_diagnose().is_err());
+        let state = DeadlockProofMutex::new(0u32, CallbackStateLock);
+        let guard = state.lock(permission).guard();
+        assert_eq!(held_lock_count(), 1);
+        guard.unlock();
+        assert_eq!(held_lock_count(), 0);
+    });
+}
@@ -0,0 +1,86 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+use deadlock_proof::{
+    declare_namespace, LockOutcome, NetworkStack, OuterMutexPermission,
+};
+
+declare_namespace!(WhatIf);
+
+/// One full forward walk over any root-namespace stack.
+fn process_packet(stack: &NetworkStack, permission: OuterMutexPermission) -> OuterMutexPermission {
+    let mut ip = stack.ip_layer.lock(permission).guard();
+    ip.packets_processed += 1;
+    let mut device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    device.bytes_transmitted += 100;
+    let mut transport = stack.transport_layer.lock(device.unlock_for_sequential()).guard();
+    transport.tcp_connections += 1;
+    transport.unlock_for_sequential().to_earlier().to_earlier().to_earlier()
+}
+
+#[test]
+fn fork_copies_current_state() {
+    let stack = NetworkStack::new();
+    let mut walk = stack.begin_walk(process_packet(&stack, OuterMutexPermission::get()));
+    let fork = walk.fork::<WhatIf>();
+    let permission = walk.finish();
+
+    let ip = fork.ip_layer.lock(permission).guard();
+    assert_eq!(ip.packets_processed, 1);
+    let device = fork.device_layer.lock(ip.unlock_for_sequential()).guard();
+    assert_eq!(device.bytes_transmitted, 100);
+    let transport = fork.transport_layer.lock(device.unlock_for_sequential()).guard();
+    assert_eq!(transport.tcp_connections, 1);
+}
+
+#[test]
+fn fork_mid_workload_does_not_cross_contaminate() {
+    let stack = NetworkStack::new();
+    let stop = AtomicBool::new(false);
+
+    let (permission, fork, packets_at_fork) = thread::scope(|scope| {
+        for _ in 0..3 {
+            scope.spawn(|| {
+                let mut permission = OuterMutexPermission::get();
+                while !stop.load(Ordering::Relaxed) {
+                    permission = process_packet(&stack, permission);
+                }
+            });
+        }
+
+        let permission = OuterMutexPermission::get();
+        thread::sleep(std::time::Duration::from_millis(10));
+        let mut walk = stack.begin_walk(permission);
+        let fork = walk.fork::<WhatIf>();
+        let permission = walk.finish();
+        stop.store(true, Ordering::Relaxed);
+
+        let forked_ip = fork.ip_layer.lock(permission).guard();
+        let packets = forked_ip.packets_processed;
+        (forked_ip.unlock(), fork, packets)
+    });
+
+    // The original keeps its own state after the workload and fork.
+    let ip = stack.ip_layer.lock(permission).guard();
+    let original_packets = ip.packets_processed;
+    assert!(original_packets >= packets_at_fork);
+    let permission = ip.unlock();
+
+    // Mutating the fork leaves the original alone...
+    let mut forked_ip = fork.ip_layer.lock(permission).guard();
+    forked_ip.packets_processed = 0;
+    forked_ip.routing_table_size = 7;
+    let permission = forked_ip.unlock();
+
+    let ip = stack.ip_layer.lock(permission).guard();
+    assert_eq!(ip.packets_processed, original_packets);
+    assert_eq!(ip.routing_table_size, 0);
+
+    // ...and mutating the original leaves the fork alone.
+    let permission = process_packet(&stack, ip.unlock());
+    let forked_ip = fork.ip_layer.lock(permission).guard();
+    assert_eq!(forked_ip.packets_processed, 0);
+    assert_eq!(forked_ip.routing_table_size, 7);
+}
@@ -0,0 +1,118 @@
+use deadlock_proof::fuzz_driver::{self, Execution, Op, ARENA_SIZE};
+
+#[test]
+fn ops_the_types_would_reject_are_skipped() {
+    let execution = fuzz_driver::execute(&[
+        // Nothing can be locked or released before the claim.
+        Op::Lock(0),
+        Op::Unlock(0),
+        Op::ClaimPermission,
+        Op::ClaimPermission,
+        Op::Lock(ARENA_SIZE),
+        // B needs the token of a nested A guard.
+        Op::Lock(2),
+        Op::LockNested(0),
+        // The token is in use until B is released again.
+        Op::Lock(3),
+        Op::LockNested(3),
+        Op::LockNested(4),
+        // Only the top guard can be unlocked, and C has nothing nested.
+        Op::Unlock(0),
+        Op::LockNested(5),
+        Op::Unlock(3),
+        Op::Unlock(0),
+        Op::ToEarlier,
+    ]);
+
+    assert_eq!(
+        execution,
+        Execution {
+            executed: 5,
+            skipped: 10,
+            threads: 1,
+        }
+    );
+}
+
+#[test]
+fn sequential_permissions_round_trip() {
+    let execution = fuzz_driver::execute(&[
+        Op::ClaimPermission,
+        Op::Lock(1),
+        Op::ToSequential,
+        // A is released, so its level cannot be locked again yet.
+        Op::Lock(0),
+        Op::Lock(6),
+        Op::Unlock(6),
+        Op::Lock(7),
+        Op::Unlock(7),
+        Op::ToEarlier,
+        Op::Lock(0),
+    ]);
+
+    assert_eq!((execution.executed, execution.skipped), (9, 1));
+}
+
+#[test]
+fn guards_left_at_the_end_are_released() {
+    let holder = vec![
+        Op::ClaimPermission,
+        Op::LockNested(0),
+        Op::LockNested(2),
+        Op::Lock(4),
+    ];
+    let execution = fuzz_driver::execute(&[
+        Op::SpawnThread(holder.clone()),
+        Op::SpawnThread(holder.clone()),
+        Op::SpawnThread(vec![Op::SpawnThread(holder)]),
+        Op::ClaimPermission,
+        Op::Lock(0),
+        Op::ToSequential,
+        Op::Lock(6),
+    ]);
+
+    assert_eq!(execution.threads, 5);
+    assert_eq!(execution.skipped, 0);
+}
+
+#[test]
+fn bytes_decode_into_programs() {
+    assert_eq!(
+        fuzz_driver::decode(&[0, 1 | 2 << 3, 7, 0, 6, 7 | 1 << 3, 3 | 15 << 3]),
+        [
+            Op::ClaimPermission,
+            Op::Lock(2),
+            Op::SpawnThread(vec![Op::ClaimPermission, Op::Yield]),
+            Op::Unlock(15 % (ARENA_SIZE + 2)),
+        ]
+    );
+    assert!(fuzz_driver::decode(&[]).is_empty());
+}
+
+/// xorshift64, to explore a fixed set of programs.
+fn bytes(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+#[test]
+fn random_programs_finish_with_every_permission_back() {
+    let mut total = Execution::default();
+    for seed in 0..200 {
+        let program = fuzz_driver::decode(&bytes(seed, 96));
+        let execution = fuzz_driver::execute(&program);
+        total.executed += execution.executed;
+        total.skipped += execution.skipped;
+        total.threads += execution.threads;
+    }
+
+    assert!(total.executed > 1000, "{total:?}");
+    assert!(total.threads > 400, "{total:?}");
+}
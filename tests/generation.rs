@@ -0,0 +1,55 @@
+use std::panic;
+
+use deadlock_proof::{
+    DeadlockProofMutex, HierarchyGeneration, LockOutcome, NetworkStack, OuterMutexPermission,
+};
+
+struct TableLock;
+
+type Table = DeadlockProofMutex<Vec<u32>, OuterMutexPermission, TableLock>;
+
+#[test]
+fn stacks_can_be_rebuilt_any_number_of_times() {
+    let mut permission = OuterMutexPermission::get();
+    let mut last = 0;
+    for round in 0..100u64 {
+        let stack = NetworkStack::new();
+        let generation = stack.generation().id();
+        assert!(generation > last);
+        last = generation;
+        assert_eq!(stack.generation().identifiers().len(), 4);
+
+        let mut ip = stack.ip_layer.lock(permission).guard();
+        ip.packets_processed = round;
+        permission = ip.unlock();
+        stack.shutdown();
+        assert!(stack.generation().is_closed());
+        // A shut-down stack still locks.
+        permission = stack.views(permission).0;
+    }
+}
+
+#[test]
+fn stacks_alive_together_have_generations_of_their_own() {
+    let stacks: Vec<_> = (0..4).map(|_| NetworkStack::new()).collect();
+    let mut generations: Vec<_> = stacks.iter().map(|stack| stack.generation().id()).collect();
+    generations.dedup();
+    assert_eq!(generations.len(), stacks.len());
+    assert!(stacks.iter().all(|stack| !stack.generation().is_closed()));
+}
+
+#[test]
+fn duplicates_within_a_generation_panic_without_a_handler() {
+    let first = HierarchyGeneration::new();
+    let _table = Table::new_in(Vec::new(), TableLock, &first);
+    let duplicate = panic::catch_unwind(|| Table::new_in(Vec::new(), TableLock, &first));
+    let message = *duplicate.err().unwrap().downcast::<String>().unwrap();
+    assert!(message.contains("already has a mutex identified by"), "{message}");
+
+    // The same identifier in the next generation is no duplicate.
+    let second = HierarchyGeneration::new();
+    let _table = Table::new_in(Vec::new(), TableLock, &second);
+    second.close();
+    assert!(second.identifiers().is_empty());
+    assert!(panic::catch_unwind(|| Table::new_in(Vec::new(), TableLock, &second)).is_err());
+}
@@ -0,0 +1,59 @@
+#![cfg(feature = "diagnostics")]
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use deadlock_proof::{diagnostics, LockOutcome, NetworkStack, OuterMutexPermission};
+
+/// The registry is process-wide, so these tests run one at a time.
+fn serial() -> MutexGuard<'static, ()> {
+    static SERIAL: Mutex<()> = Mutex::new(());
+    SERIAL.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The number of registered mutexes, from the first line of the dump.
+fn registered(dump: &str) -> usize {
+    // "<held> of <registered> mutexes held"
+    let totals = dump.lines().next().unwrap();
+    totals.split(' ').nth(2).unwrap().parse().unwrap()
+}
+
+#[test]
+fn shut_down_stacks_leave_no_records_behind() {
+    let _serial = serial();
+    let before = registered(&diagnostics::dump_all_held());
+    let mut permission = OuterMutexPermission::get();
+    // Kept alive, so that only the shutdowns can take them out.
+    let mut stacks = Vec::new();
+    for _ in 0..100 {
+        let stack = NetworkStack::new();
+        let ip = stack.ip_layer.lock(permission).guard();
+        let dump = diagnostics::dump_held_in(stack.generation());
+        let generation = format!("ip-layer (generation {}): held by", stack.generation().id());
+        assert!(dump.starts_with("1 of 3 mutexes held\n"), "{dump}");
+        assert!(dump.contains(&generation), "{dump}");
+        permission = ip.unlock();
+
+        stack.shutdown();
+        assert_eq!(stack.generation().registered(), 0);
+        stacks.push(stack);
+    }
+    assert_eq!(registered(&diagnostics::dump_all_held()), before);
+}
+
+#[test]
+fn dumps_list_one_generation_at_a_time() {
+    let _serial = serial();
+    let (first, second) = (NetworkStack::new(), NetworkStack::new());
+    assert_eq!(first.generation().registered(), 3);
+    let ip = first.ip_layer.lock(OuterMutexPermission::get()).guard();
+    let device = second.device_layer.lock(ip.unlock_for_sequential()).guard();
+
+    let dump = diagnostics::dump_held_in(second.generation());
+    assert!(dump.starts_with("1 of 3 mutexes held\n"), "{dump}");
+    assert!(dump.contains("device-layer"), "{dump}");
+    assert!(!dump.contains("ip-layer"), "{dump}");
+    let _permission = device.unlock_for_sequential();
+
+    drop(second);
+    assert!(diagnostics::dump_held_in(first.generation()).starts_with("0 of 3 mutexes held\n"));
+}
@@ -0,0 +1,57 @@
+use std::borrow::Borrow;
+
+use deadlock_proof::{
+    unique_type, DeadlockProofMutex, IpState, LockOutcome, NetworkStack, OuterMutexPermission,
+};
+
+fn packets(state: impl AsRef<IpState>) -> u64 {
+    state.as_ref().packets_processed
+}
+
+fn reset(mut counter: impl AsMut<u32>) {
+    *counter.as_mut() = 0;
+}
+
+#[test]
+fn guard_goes_where_as_ref_is_expected() {
+    let stack = NetworkStack::new();
+    let mut ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    ip.packets_processed = 9;
+    assert_eq!(packets(&ip), 9);
+    assert_eq!(packets(ip), 9);
+}
+
+#[test]
+fn as_mut_counts_as_a_modification() {
+    let counter = DeadlockProofMutex::new(5u32, unique_type!());
+    let mut guard = counter.lock(OuterMutexPermission::get()).guard();
+    let borrowed: &u32 = guard.borrow();
+    assert_eq!(*borrowed, 5);
+    #[cfg(feature = "tracking")]
+    assert_eq!(counter.version(), 0);
+    reset(&mut guard);
+    let permission = guard.unlock();
+    #[cfg(feature = "tracking")]
+    assert_eq!(counter.version(), 1);
+    assert!(counter.lock(permission).guard() == 0);
+}
+
+#[test]
+fn payloads_of_two_guards_compare() {
+    let low = DeadlockProofMutex::new(3u32, unique_type!());
+    let high = DeadlockProofMutex::new(7u32, unique_type!());
+    let (low_guard, token) = low.lock_for_nested(OuterMutexPermission::get()).guard();
+    let high_guard = high.lock(token).guard();
+    assert!(low_guard < *high_guard);
+    assert!(high_guard > *low_guard);
+    assert!(low_guard != *high_guard);
+    assert!(low_guard == 3);
+}
+
+#[test]
+fn display_forwards_to_the_data() {
+    let name = DeadlockProofMutex::new(String::from("eth0"), unique_type!());
+    let guard = name.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(guard.to_string(), "eth0");
+    assert_eq!(format!("{guard:>6}"), "  eth0");
+}
@@ -0,0 +1,72 @@
+use deadlock_proof::{
+    lock_hierarchy, network_stack, DeviceLock, IpLock, OuterMutexPermission, RouteCacheLock,
+    TransportLock,
+};
+
+struct QueueLock;
+struct SlotLock;
+lock_hierarchy!(within DeviceLock => QueueLock, SlotLock);
+
+struct D0;
+struct D1;
+struct D2;
+struct D3;
+struct D4;
+struct D5;
+struct D6;
+struct D7;
+struct D8;
+struct D9;
+lock_hierarchy!(OuterMutexPermission => D0, D1, D2, D3, D4, D5, D6, D7, D8, D9);
+
+#[test]
+fn describes_the_network_stack() {
+    assert_eq!(
+        network_stack::LOCK_ORDER,
+        ["IpLock", "DeviceLock", "TransportLock"]
+    );
+    assert_eq!(TransportLock::LOCK_ORDER, network_stack::LOCK_ORDER);
+    assert_eq!(
+        (
+            IpLock::POSITION,
+            DeviceLock::POSITION,
+            TransportLock::POSITION
+        ),
+        (0, 1, 2)
+    );
+    assert_eq!(IpLock::NEXT, Some("DeviceLock"));
+    assert_eq!(DeviceLock::NEXT, Some("TransportLock"));
+    assert_eq!(TransportLock::NEXT, None);
+
+    assert_eq!(
+        IpLock::DESCRIPTION,
+        "`IpLock` is level 1 of 3 in the lock hierarchy rooted at \
+         `OuterMutexPermission`; locked first, before `DeviceLock`."
+    );
+    assert!(DeviceLock::DESCRIPTION.contains("level 2 of 3"));
+    assert!(DeviceLock::DESCRIPTION.contains("after `IpLock` and before `TransportLock`"));
+    assert!(TransportLock::DESCRIPTION.contains("level 3 of 3"));
+    assert!(TransportLock::DESCRIPTION.contains("last"));
+    assert!(TransportLock::DESCRIPTION.contains("after `DeviceLock`"));
+    assert!(RouteCacheLock::DESCRIPTION.contains("level 1 of 1, the only level"));
+}
+
+#[test]
+fn describes_sub_hierarchies_by_their_parent() {
+    assert_eq!(QueueLock::LOCK_ORDER, ["QueueLock", "SlotLock"]);
+    assert_eq!(SlotLock::POSITION, 1);
+    assert_eq!(
+        QueueLock::DESCRIPTION,
+        "`QueueLock` is level 1 of 2 in the lock hierarchy inside `DeviceLock`; \
+         locked first, before `SlotLock`."
+    );
+}
+
+#[test]
+fn describes_deep_hierarchies() {
+    assert_eq!(D9::LOCK_ORDER.len(), 10);
+    assert_eq!(D9::LOCK_ORDER[D9::POSITION], "D9");
+    assert_eq!(D8::NEXT, Some("D9"));
+    assert!(D5::DESCRIPTION.contains("level 6 of 10"));
+    assert!(D5::DESCRIPTION.contains("after `D4` and before `D6`"));
+}
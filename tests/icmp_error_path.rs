@@ -0,0 +1,148 @@
+use std::{sync::mpsc, thread, time::Duration};
+
+use deadlock_proof::{
+    concurrent, ErrorIpLock, IcmpError, LockOutcome, NetworkStack, OuterMutexPermission,
+    PermissionChain, Position,
+};
+
+/// One full forward walk.
+fn process_packet(stack: &NetworkStack, permission: OuterMutexPermission) -> OuterMutexPermission {
+    let mut ip = stack.ip_layer.lock(permission).guard();
+    ip.packets_processed += 1;
+    let mut device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    device.bytes_transmitted += 100;
+    let mut transport = stack
+        .transport_layer
+        .lock(device.unlock_for_sequential())
+        .guard();
+    transport.tcp_connections += 1;
+    transport
+        .unlock_for_sequential()
+        .to_earlier()
+        .to_earlier()
+        .to_earlier()
+}
+
+#[test]
+fn error_is_recorded_in_both_layers() {
+    let stack = NetworkStack::new();
+    let mut walk = stack.begin_walk(OuterMutexPermission::get());
+    walk.icmp_error_path(IcmpError::PortUnreachable);
+    let permission = walk.finish();
+
+    let ip = stack.ip_layer.lock(permission).guard();
+    assert_eq!(ip.icmp_errors_sent, 1);
+    assert_eq!(ip.last_icmp_error, Some(IcmpError::PortUnreachable));
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    let transport = stack
+        .transport_layer
+        .lock(device.unlock_for_sequential())
+        .guard();
+    assert_eq!(transport.icmp_errors_sent, 1);
+}
+
+#[test]
+fn the_error_path_has_its_own_reverse_chain() {
+    let mut levels = Vec::new();
+    Position::<ErrorIpLock>::visit_levels(&mut |level| levels.push(level));
+    assert_eq!(levels, ["Outer", "ErrorPathLock", "ErrorTransportLock"]);
+}
+
+#[test]
+fn the_error_path_returns_its_root() {
+    let stack = NetworkStack::new();
+    let permission = stack.icmp_error_path(OuterMutexPermission::get(), IcmpError::TimeExceeded);
+    let permission = stack.icmp_error_path(permission, IcmpError::PortUnreachable);
+
+    let ip = stack.ip_layer.lock(permission).guard();
+    assert_eq!(ip.icmp_errors_sent, 2);
+    assert_eq!(ip.last_icmp_error, Some(IcmpError::PortUnreachable));
+}
+
+#[test]
+fn forward_and_error_paths_run_concurrently() {
+    const ROUNDS: u64 = 2_000;
+    let stack = NetworkStack::new();
+
+    let mut workers: Vec<concurrent::Worker<'_>> = Vec::new();
+    for _ in 0..2 {
+        workers.push(Box::new(|mut permission| {
+            for _ in 0..ROUNDS {
+                permission = process_packet(&stack, permission);
+            }
+            permission
+        }));
+        workers.push(Box::new(|permission| {
+            let mut walk = stack.begin_walk(permission);
+            for _ in 0..ROUNDS {
+                walk.icmp_error_path(IcmpError::HostUnreachable);
+            }
+            walk.finish()
+        }));
+        // A commit holds the layers in the forward order all at once.
+        workers.push(Box::new(|mut permission| {
+            for _ in 0..ROUNDS {
+                let result;
+                (permission, result) = stack
+                    .transaction()
+                    .stage_device(|device| {
+                        device.interfaces_active += 1;
+                        Ok(())
+                    })
+                    .stage_transport(|transport| {
+                        transport.udp_sockets += 1;
+                        Ok(())
+                    })
+                    .commit(permission);
+                result.unwrap();
+            }
+            permission
+        }));
+    }
+    concurrent::run_all(workers).unwrap();
+
+    let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(ip.packets_processed, 2 * ROUNDS);
+    assert_eq!(ip.icmp_errors_sent, 2 * ROUNDS);
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    let transport = stack
+        .transport_layer
+        .lock(device.unlock_for_sequential())
+        .guard();
+    assert_eq!(u64::from(transport.tcp_connections), 2 * ROUNDS);
+    assert_eq!(u64::from(transport.udp_sockets), 2 * ROUNDS);
+    assert_eq!(transport.icmp_errors_sent, 2 * ROUNDS);
+}
+
+#[test]
+fn each_stack_has_its_own_gate() {
+    let busy = NetworkStack::new();
+    let other = NetworkStack::new();
+    let (done, finished) = mpsc::channel();
+
+    thread::scope(|scope| {
+        // Stays in a staged closure, holding the busy stack's layers, until
+        // the other stack's error path is through.
+        let busy = &busy;
+        let committer = scope.spawn(move || {
+            let (_permission, result) = busy
+                .transaction()
+                .stage_ip(|_| {
+                    finished
+                        .recv_timeout(Duration::from_secs(10))
+                        .map_err(|_| "the other stack's error path waited".to_owned())
+                })
+                .commit(OuterMutexPermission::get());
+            result
+        });
+        scope.spawn(|| {
+            let _permission =
+                other.icmp_error_path(OuterMutexPermission::get(), IcmpError::HostUnreachable);
+            done.send(()).unwrap();
+        });
+        committer.join().unwrap().unwrap();
+    });
+
+    let ip = other.ip_layer.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(ip.icmp_errors_sent, 1);
+}
@@ -0,0 +1,103 @@
+use std::{collections::HashMap, thread};
+
+use deadlock_proof::{
+    assert_unlocked, region::with_region, unique_type, DeadlockProofMutex, LockOutcome,
+    NetworkStack, OuterMutexPermission,
+};
+
+#[test]
+fn plain_guard_is_seen_until_unlocked() {
+    let stack = NetworkStack::new();
+    assert_unlocked!(stack.ip_layer);
+
+    let guard = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    assert!(stack.ip_layer.is_locked());
+    assert!(!stack.device_layer.is_locked());
+    let permission = guard.unlock_for_sequential();
+    assert_unlocked!(stack.ip_layer);
+
+    let guard = stack.device_layer.lock(permission).guard();
+    assert!(stack.device_layer.is_locked());
+    guard.unlock();
+    assert_unlocked!(stack.device_layer);
+}
+
+#[test]
+fn nested_guards_are_seen_at_both_levels() {
+    let outer = DeadlockProofMutex::new(0u32, unique_type!());
+    let (outer_guard, nested) = outer.lock_for_nested(OuterMutexPermission::get()).guard();
+    let inner = DeadlockProofMutex::new(0u32, unique_type!());
+    let inner_guard = inner.lock(nested).guard();
+    assert!(outer.is_locked());
+    assert!(inner.is_locked());
+
+    let nested = inner_guard.unlock();
+    assert_unlocked!(inner);
+    assert!(outer.is_locked());
+    outer_guard.unlock(nested);
+    assert_unlocked!(outer);
+}
+
+#[test]
+fn mapped_guards_keep_the_mutex_locked() {
+    let mutex = DeadlockProofMutex::new(HashMap::from([(1u8, 10u32)]), unique_type!());
+
+    let mapped = mutex
+        .lock(OuterMutexPermission::get())
+        .guard()
+        .map(|map| map.get_mut(&1).unwrap());
+    assert!(mutex.is_locked());
+    let permission = mapped.unlock();
+    assert_unlocked!(mutex);
+
+    let (guard, _key) = match mutex
+        .lock(permission)
+        .guard()
+        .try_map_with(2u8, |map, key| map.get_mut(key))
+    {
+        Ok(_) => unreachable!("key 2 is missing"),
+        Err(missing) => missing,
+    };
+    assert!(mutex.is_locked());
+    guard.unlock();
+    assert_unlocked!(mutex);
+}
+
+#[test]
+fn guards_held_elsewhere_are_seen() {
+    let stack = NetworkStack::new();
+
+    // A carry releases the mutex it came from.
+    let carry = stack
+        .ip_layer
+        .lock(OuterMutexPermission::get())
+        .guard()
+        .unlock_for_sequential_with(());
+    assert_unlocked!(stack.ip_layer);
+    let _carry = stack
+        .device_layer
+        .lock_with_carry(carry, |_, ()| assert!(stack.device_layer.is_locked()))
+        .guard();
+    assert_unlocked!(stack.device_layer);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            with_region(|region| {
+                let guard = region.hold(stack.ip_layer.lock(OuterMutexPermission::get()).guard());
+                assert!(stack.ip_layer.is_locked());
+                guard.unlock()
+            });
+            assert_unlocked!(stack.ip_layer);
+        });
+    });
+}
+
+// Without `tracking` the mutex has no name to show.
+#[cfg(feature = "tracking")]
+#[test]
+#[should_panic(expected = "`stack.ip_layer` (ip-layer) is still locked")]
+fn assertion_names_the_expression_and_the_mutex() {
+    let stack = NetworkStack::new();
+    let _guard = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    assert_unlocked!(stack.ip_layer);
+}
@@ -0,0 +1,335 @@
+//! Pins the size of every permission, lock and guard, so that a field added
+//! to one of them is a deliberate choice. With the default features, a
+//! release build lays mutexes and guards out as the `std` types they wrap,
+//! and a debug build adds only the release invariant it checks.
+
+// The levels only name positions; no mutex is ever created at them.
+#![allow(dead_code)]
+
+use std::{
+    mem::{align_of, size_of},
+    sync::Mutex,
+};
+
+use deadlock_proof::{
+    lock_hierarchy, After, DeadlockProofMutex, DeadlockProofRwLock, DeepSequentialPermission,
+    DeviceLock, IpLock, NestedMutexPermission, OuterMutexPermission, Position,
+    SequentialMutexPermission, TransportLock,
+};
+
+struct L0;
+struct L1;
+struct L2;
+struct L3;
+struct L4;
+struct L5;
+struct L6;
+struct L7;
+struct L8;
+struct L9;
+struct L10;
+struct L11;
+lock_hierarchy!(OuterMutexPermission => L0, L1, L2, L3, L4, L5, L6, L7, L8, L9, L10, L11);
+
+type Outer = OuterMutexPermission;
+type Nested<P> = NestedMutexPermission<P, L0>;
+type Sequential<P> = SequentialMutexPermission<P, L0>;
+
+/// The size of every permission type, at every depth.
+macro_rules! permission_sizes {
+    () => {
+        [
+            size_of::<Outer>(),
+            size_of::<Nested<Outer>>(),
+            size_of::<Nested<Nested<Nested<Outer>>>>(),
+            size_of::<Sequential<Outer>>(),
+            size_of::<Sequential<Sequential<Sequential<Outer>>>>(),
+            size_of::<Sequential<Nested<Sequential<Nested<Outer>>>>>(),
+            size_of::<Position<IpLock>>(),
+            size_of::<Position<DeviceLock>>(),
+            size_of::<Position<TransportLock>>(),
+            size_of::<After<TransportLock>>(),
+            size_of::<Position<L0>>(),
+            size_of::<Position<L1>>(),
+            size_of::<Position<L4>>(),
+            size_of::<Position<L8>>(),
+            size_of::<Position<L11>>(),
+            size_of::<After<L11>>(),
+            size_of::<DeepSequentialPermission<Outer, 64>>(),
+        ]
+    };
+}
+
+#[cfg(not(any(feature = "metrics", feature = "origin-check")))]
+#[test]
+fn permissions_are_zero_sized() {
+    assert_eq!(permission_sizes!(), [0; 17]);
+}
+
+#[cfg(all(feature = "metrics", not(feature = "origin-check")))]
+#[test]
+fn permissions_only_carry_the_lineage_tag() {
+    assert_eq!(permission_sizes!(), [size_of::<Option<u64>>(); 17]);
+}
+
+#[cfg(all(feature = "origin-check", not(feature = "metrics")))]
+#[test]
+fn permissions_only_carry_their_origin() {
+    use std::thread::ThreadId;
+
+    assert_eq!(permission_sizes!(), [size_of::<Option<ThreadId>>(); 17]);
+}
+
+#[cfg(all(feature = "metrics", feature = "origin-check"))]
+#[test]
+fn permissions_only_carry_the_lineage_tag_and_origin() {
+    use std::thread::ThreadId;
+
+    assert_eq!(
+        permission_sizes!(),
+        [size_of::<(Option<u64>, Option<ThreadId>)>(); 17]
+    );
+}
+
+#[test]
+fn permissions_are_laid_out_as_the_root() {
+    assert_eq!(align_of::<Position<L11>>(), align_of::<Outer>());
+    assert_eq!(align_of::<Sequential<Nested<Outer>>>(), align_of::<Outer>());
+}
+
+/// What each reader-writer lock adds to its data.
+fn rwlock_overhead<T>() -> usize {
+    size_of::<DeadlockProofRwLock<T, Outer, L0>>() - size_of::<T>()
+}
+
+#[test]
+fn rwlock_overhead_does_not_depend_on_the_data() {
+    // The same alignment as the state, so nothing is padded.
+    assert_eq!(rwlock_overhead::<u64>(), rwlock_overhead::<String>());
+    assert_eq!(rwlock_overhead::<u64>(), rwlock_overhead::<[u64; 64]>());
+    assert_eq!(rwlock_overhead::<u64>(), rwlock_overhead::<Vec<u64>>());
+}
+
+// Guards hold their permission, which these features make non-empty.
+#[cfg(not(any(feature = "metrics", feature = "origin-check")))]
+#[test]
+fn rwlock_guards_are_a_reference_to_the_lock() {
+    use deadlock_proof::{DeadlockProofReadGuard, DeadlockProofWriteGuard};
+
+    assert_eq!(
+        size_of::<DeadlockProofReadGuard<'_, u64, Position<L11>, L11>>(),
+        size_of::<&()>()
+    );
+    // And whether the writer was already panicking, for poisoning.
+    assert_eq!(
+        size_of::<DeadlockProofWriteGuard<'_, u64, Position<L11>, L11>>(),
+        size_of::<(&(), bool)>()
+    );
+}
+
+type Lock<T> = DeadlockProofMutex<T, Outer, L0>;
+
+/// What each mutex adds to its `Mutex<T>`.
+fn overhead<T>() -> usize {
+    size_of::<Lock<T>>() - size_of::<Mutex<T>>()
+}
+
+// The diff log keeps a snapshot function per data type.
+#[cfg(not(feature = "diff-log"))]
+#[test]
+fn mutex_overhead_does_not_depend_on_the_data() {
+    // The same alignment as the bookkeeping, so nothing is padded.
+    assert_eq!(overhead::<u64>(), overhead::<String>());
+    assert_eq!(overhead::<u64>(), overhead::<[u64; 64]>());
+    assert_eq!(overhead::<u64>(), overhead::<Vec<u64>>());
+}
+
+#[cfg(not(any(
+    debug_assertions,
+    feature = "adaptive",
+    feature = "diagnostics",
+    feature = "diff-log",
+    feature = "metrics",
+    feature = "priority",
+    feature = "test-util",
+    feature = "tracking"
+)))]
+#[test]
+fn mutexes_are_laid_out_as_mutex() {
+    assert_eq!(size_of::<Lock<u64>>(), size_of::<Mutex<u64>>());
+    assert_eq!(size_of::<Lock<[u64; 64]>>(), size_of::<Mutex<[u64; 64]>>());
+    assert_eq!(size_of::<Lock<()>>(), size_of::<Mutex<()>>());
+    assert_eq!(align_of::<Lock<u8>>(), align_of::<Mutex<u8>>());
+    assert_eq!(
+        size_of::<Option<Lock<String>>>(),
+        size_of::<Option<Mutex<String>>>()
+    );
+}
+
+#[cfg(all(
+    debug_assertions,
+    not(any(
+        feature = "adaptive",
+        feature = "diagnostics",
+        feature = "diff-log",
+        feature = "metrics",
+        feature = "priority",
+        feature = "test-util",
+        feature = "tracking"
+    ))
+))]
+#[test]
+fn mutexes_only_add_the_release_invariant_in_debug_builds() {
+    use std::sync::atomic::AtomicPtr;
+
+    let invariant = size_of::<AtomicPtr<()>>();
+    assert_eq!(size_of::<Lock<u64>>(), size_of::<Mutex<u64>>() + invariant);
+    assert_eq!(
+        size_of::<Lock<[u64; 64]>>(),
+        size_of::<Mutex<[u64; 64]>>() + invariant
+    );
+    assert_eq!(size_of::<Lock<()>>(), size_of::<Mutex<()>>() + invariant);
+    assert_eq!(
+        size_of::<Option<Lock<String>>>(),
+        size_of::<Option<Mutex<String>>>() + invariant
+    );
+}
+
+#[cfg(all(
+    feature = "tracking",
+    not(any(
+        feature = "adaptive",
+        feature = "diagnostics",
+        feature = "diff-log",
+        feature = "metrics",
+        feature = "priority",
+        feature = "test-util"
+    ))
+))]
+#[test]
+fn mutex_overhead_is_its_bookkeeping() {
+    use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize};
+
+    let waiters = size_of::<AtomicUsize>();
+    let name = size_of::<Option<&str>>();
+    let versions = size_of::<AtomicU64>() + size_of::<Mutex<Vec<Box<dyn Fn()>>>>();
+    // The byte saying what its guards do on release, padded.
+    let release = align_of::<u64>();
+    // What the poison events call the mutex, and the poison watchers.
+    let poison_watch = size_of::<&str>() + size_of::<Mutex<Vec<Box<dyn Fn()>>>>();
+    let contention = size_of::<AtomicPtr<()>>();
+    // The release invariant is only kept in debug builds.
+    let invariant = if cfg!(debug_assertions) {
+        size_of::<AtomicPtr<()>>()
+    } else {
+        0
+    };
+    assert_eq!(
+        overhead::<u64>(),
+        waiters + name + versions + poison_watch + release + contention + invariant
+    );
+}
+
+// These features replace or extend what a guard holds.
+#[cfg(not(any(
+    debug_assertions,
+    feature = "metrics",
+    feature = "origin-check",
+    feature = "tracking"
+)))]
+#[test]
+fn guards_are_laid_out_as_mutex_guard() {
+    use std::sync::MutexGuard;
+
+    use deadlock_proof::{DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard, MappedGuard};
+
+    type Guard<'a> = DeadlockProofMutexGuard<'a, u64, Position<L11>, L11>;
+    assert_eq!(size_of::<Guard<'_>>(), size_of::<MutexGuard<'_, u64>>());
+    assert_eq!(align_of::<Guard<'_>>(), align_of::<MutexGuard<'_, u64>>());
+    assert_eq!(
+        size_of::<DeadlockProofNestedMutexGuard<'_, u64, Outer, L0>>(),
+        size_of::<Guard<'_>>()
+    );
+    // A mapped guard also points at the part it narrowed down to.
+    assert_eq!(
+        size_of::<MappedGuard<'_, u8, u64, Outer, L0>>(),
+        size_of::<Guard<'_>>() + size_of::<&u8>()
+    );
+    assert_eq!(size_of::<Option<Guard<'_>>>(), size_of::<Guard<'_>>());
+}
+
+#[cfg(all(
+    debug_assertions,
+    not(any(feature = "metrics", feature = "origin-check", feature = "tracking"))
+))]
+#[test]
+fn guards_only_add_the_release_invariant_in_debug_builds() {
+    use std::{panic::Location, sync::MutexGuard};
+
+    use deadlock_proof::{
+        invariant::ReleaseInvariant, DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard,
+        MappedGuard,
+    };
+
+    type Guard<'a> = DeadlockProofMutexGuard<'a, u64, Position<L11>, L11>;
+    // The invariant to check on release and what to report, with the byte
+    // saying whether there is anything to do on release.
+    let invariant = size_of::<(u8, Option<(ReleaseInvariant<u64>, &str, &Location)>)>();
+    assert_eq!(
+        size_of::<Guard<'_>>(),
+        size_of::<MutexGuard<'_, u64>>() + invariant
+    );
+    assert_eq!(
+        size_of::<DeadlockProofNestedMutexGuard<'_, u64, Outer, L0>>(),
+        size_of::<Guard<'_>>()
+    );
+    // A mapped guard also points at the part it narrowed down to.
+    assert_eq!(
+        size_of::<MappedGuard<'_, u8, u64, Outer, L0>>(),
+        size_of::<Guard<'_>>() + size_of::<&u8>()
+    );
+    assert_eq!(size_of::<Option<Guard<'_>>>(), size_of::<Guard<'_>>());
+}
+
+#[cfg(all(
+    feature = "tracking",
+    not(any(
+        feature = "diagnostics",
+        feature = "diff-log",
+        feature = "metrics",
+        feature = "origin-check"
+    ))
+))]
+#[test]
+fn guards_add_only_modification_tracking() {
+    use std::{panic::Location, sync::MutexGuard};
+
+    use deadlock_proof::{
+        invariant::ReleaseInvariant, DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard,
+        MappedGuard,
+    };
+
+    type Guard<'a> = DeadlockProofMutexGuard<'a, u64, Position<L11>, L11>;
+    // The versions, the dirty flag, and what a poisoning is reported with.
+    let tracking = size_of::<(&(), Option<&Location>, bool, bool)>();
+    // In debug builds, the invariant to check on release and what to report,
+    // with the byte saying whether there is anything to do on release.
+    let invariant = if cfg!(debug_assertions) {
+        size_of::<(u8, Option<(ReleaseInvariant<u64>, &str, &Location)>)>()
+    } else {
+        0
+    };
+    assert_eq!(
+        size_of::<Guard<'_>>(),
+        size_of::<MutexGuard<'_, u64>>() + tracking + invariant
+    );
+    assert_eq!(
+        size_of::<DeadlockProofNestedMutexGuard<'_, u64, Outer, L0>>(),
+        size_of::<Guard<'_>>()
+    );
+    assert_eq!(
+        size_of::<MappedGuard<'_, u8, u64, Outer, L0>>(),
+        size_of::<Guard<'_>>() + size_of::<&u8>()
+    );
+    assert_eq!(size_of::<Option<Guard<'_>>>(), size_of::<Guard<'_>>());
+}
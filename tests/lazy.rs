@@ -0,0 +1,81 @@
+use std::{
+    panic,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Barrier,
+    },
+    thread,
+};
+
+use deadlock_proof::{
+    lazy::InitPanicked, lock_hierarchy, LazyDeadlockProofMutex, LockOutcome, OuterMutexPermission,
+    Position,
+};
+
+struct TableLock;
+struct EntryLock;
+lock_hierarchy!(OuterMutexPermission => TableLock, EntryLock);
+
+static INITIALIZED: AtomicUsize = AtomicUsize::new(0);
+
+static TABLE: LazyDeadlockProofMutex<Vec<u32>, Position<TableLock>, TableLock> =
+    LazyDeadlockProofMutex::new(TableLock, || {
+        INITIALIZED.fetch_add(1, Ordering::SeqCst);
+        vec![1, 2, 3]
+    });
+
+static ENTRY: LazyDeadlockProofMutex<u32, Position<EntryLock>, EntryLock> =
+    LazyDeadlockProofMutex::new(EntryLock, || 0);
+
+struct BrokenLock;
+static BROKEN: LazyDeadlockProofMutex<u32, OuterMutexPermission, BrokenLock> =
+    LazyDeadlockProofMutex::new(BrokenLock, || panic!("no configuration"));
+
+#[test]
+fn concurrent_first_use_initializes_once() {
+    let barrier = Barrier::new(8);
+    thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                barrier.wait();
+                let mut table = TABLE.lock(OuterMutexPermission::get()).guard();
+                table.push(4);
+            });
+        }
+    });
+    assert_eq!(INITIALIZED.load(Ordering::SeqCst), 1);
+    let table = LazyDeadlockProofMutex::get(&TABLE).unwrap();
+    assert_eq!(table.lock(OuterMutexPermission::get()).guard().len(), 3 + 8);
+}
+
+#[test]
+fn behaves_like_a_mutex_once_created() {
+    assert!(LazyDeadlockProofMutex::get(&ENTRY).is_none());
+    let table = TABLE.lock(OuterMutexPermission::get()).guard();
+    let mut entry = ENTRY.lock(table.unlock_for_sequential()).guard();
+    *entry += 1;
+    let permission = entry.unlock_for_sequential().to_earlier().to_earlier();
+
+    let (table, inside) = TABLE.lock_for_nested(permission).guard();
+    assert!(!table.is_empty());
+    let _permission: OuterMutexPermission = table.unlock(inside);
+    #[cfg(feature = "tracking")]
+    assert_eq!(ENTRY.version(), 1);
+    assert!(!LazyDeadlockProofMutex::is_poisoned(&ENTRY));
+}
+
+#[test]
+fn a_panicking_initializer_poisons() {
+    let first = panic::catch_unwind(|| LazyDeadlockProofMutex::try_force(&BROKEN).map(|_| ()));
+    // The caller that ran the initializer gets its panic.
+    assert!(first.is_err());
+    assert!(LazyDeadlockProofMutex::is_poisoned(&BROKEN));
+    assert!(LazyDeadlockProofMutex::get(&BROKEN).is_none());
+    // Later callers are told, and the initializer does not run again.
+    assert_eq!(
+        LazyDeadlockProofMutex::try_force(&BROKEN).err(),
+        Some(InitPanicked)
+    );
+    let later = panic::catch_unwind(|| BROKEN.is_locked());
+    assert!(later.is_err());
+}
@@ -0,0 +1,131 @@
+use std::{
+    env, mem,
+    net::Ipv4Addr,
+    process::Command,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use deadlock_proof::{
+    lease::{self, Dropped, PermissionLease},
+    pool::WorkerPool,
+    LockOutcome, NetworkStack, OuterMutexPermission, Route,
+};
+
+/// The record is global, so tests that read it must not overlap.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+const ROUTE: Route = Route {
+    destination: Ipv4Addr::new(10, 0, 0, 0),
+    prefix_len: 8,
+    next_hop: Ipv4Addr::new(10, 0, 0, 1),
+};
+
+/// Set in the child process of the strict-mode test.
+const STRICT_CHILD: &str = "DEADLOCK_PROOF_STRICT_LEASE_CHILD";
+
+#[test]
+fn finished_leases_are_not_reported() {
+    let _serial = SERIAL.lock().unwrap_or_else(|error| error.into_inner());
+    let before = lease::abandoned().len();
+    let stack = NetworkStack::new();
+    let (mut lease, finisher) = PermissionLease::new(OuterMutexPermission::get());
+    lease.with_permission(|permission| {
+        let mut ip = stack.ip_layer.lock(permission).guard();
+        ip.packets_processed += 1;
+        (ip.unlock(), ())
+    });
+    let route = lease.walk(&stack, |walk| {
+        walk.add_route(ROUTE).unwrap();
+        walk.cached_route_lookup(Ipv4Addr::new(10, 1, 2, 3))
+    });
+    assert_eq!(route, Some(ROUTE.next_hop));
+
+    let permission = finisher.finish(lease);
+    let ip = stack.ip_layer.lock(permission).guard();
+    assert_eq!(ip.packets_processed, 1);
+    assert_eq!(lease::abandoned().len(), before);
+}
+
+#[test]
+fn unfinished_leases_are_reported_where_they_were_created() {
+    let _serial = SERIAL.lock().unwrap_or_else(|error| error.into_inner());
+    let before = lease::abandoned().len();
+
+    let (lease, finisher) = PermissionLease::new(OuterMutexPermission::get());
+    let dropped_at = lease.created_at();
+    mem::forget(finisher);
+    drop(lease);
+
+    // The lease lives on for a while, but can no longer be finished.
+    let lost_token_at = thread::spawn(|| {
+        let (lease, finisher) = PermissionLease::new(OuterMutexPermission::get());
+        drop(finisher);
+        lease.created_at()
+    })
+    .join()
+    .unwrap();
+
+    let abandoned = &lease::abandoned()[before..];
+    assert_eq!(abandoned.len(), 2);
+    assert_eq!(abandoned[0].created_at, dropped_at);
+    assert_eq!(abandoned[0].dropped, Dropped::Lease);
+    assert_eq!(abandoned[1].created_at, lost_token_at);
+    assert_eq!(abandoned[1].dropped, Dropped::Token);
+}
+
+#[test]
+fn pool_jobs_hand_the_permission_back_by_finishing() {
+    let stack = Arc::new(NetworkStack::new());
+    let pool = WorkerPool::new(1, Duration::from_secs(10));
+    let (done, finished) = mpsc::channel();
+    for _ in 0..3 {
+        let (stack, done) = (stack.clone(), done.clone());
+        pool.submit("leased walk", move |leased| {
+            leased.with_lease(|mut lease, finisher| {
+                lease.walk(&stack, |walk| {
+                    walk.add_route(ROUTE).unwrap();
+                });
+                (finisher.finish(lease), ())
+            });
+            done.send(()).unwrap();
+        });
+    }
+    for _ in 0..3 {
+        finished.recv_timeout(Duration::from_secs(10)).unwrap();
+    }
+    assert!(pool.retirements().is_empty());
+}
+
+#[test]
+fn strict_mode_aborts_on_an_abandoned_lease() {
+    if env::var_os(STRICT_CHILD).is_some() {
+        lease::set_strict(true);
+        let (lease, _finisher) = PermissionLease::new(OuterMutexPermission::get());
+        drop(lease);
+        unreachable!("abandoning the lease should have aborted");
+    }
+
+    let output = Command::new(env::current_exe().unwrap())
+        .args([
+            "--exact",
+            "strict_mode_aborts_on_an_abandoned_lease",
+            "--nocapture",
+        ])
+        .env(STRICT_CHILD, "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        assert_eq!(output.status.signal(), Some(6), "SIGABRT");
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("was dropped before being finished"),
+        "{stderr}"
+    );
+}
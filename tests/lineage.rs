@@ -0,0 +1,153 @@
+#![cfg(feature = "metrics")]
+
+use std::{sync::mpsc, thread};
+
+use deadlock_proof::{
+    unique_type, DeadlockProofMutex, LockOutcome, MutexPermission, NetworkStack,
+    OuterMutexPermission,
+};
+
+#[test]
+fn untagged_permissions_report_no_tag() {
+    let permission = OuterMutexPermission::get();
+    assert_eq!(permission.tag(), None);
+
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    let (guard, nested) = mutex.lock_for_nested(permission).guard();
+    assert_eq!(guard.tag(), None);
+    assert_eq!(nested.tag(), None);
+    guard.unlock(nested);
+}
+
+#[test]
+fn tag_survives_a_nested_and_sequential_round_trip() {
+    let outer = DeadlockProofMutex::new(0u32, unique_type!());
+    let inner = DeadlockProofMutex::new(0u32, unique_type!());
+    let first = DeadlockProofMutex::new(0u32, unique_type!());
+    let second = DeadlockProofMutex::new(0u32, unique_type!());
+
+    let mut permission = OuterMutexPermission::get();
+    permission.set_tag(7);
+
+    let (outer_guard, nested) = outer.lock_for_nested(permission).guard();
+    assert_eq!(outer_guard.tag(), Some(7));
+    assert_eq!(nested.tag(), Some(7));
+    let inner_guard = inner.lock(nested).guard();
+    assert_eq!(inner_guard.tag(), Some(7));
+    let permission = outer_guard.unlock(inner_guard.unlock());
+
+    let sequential = first.lock(permission).guard().unlock_for_sequential();
+    assert_eq!(sequential.tag(), Some(7));
+    let mapped = second.lock(sequential).guard().map(|value| value);
+    assert_eq!(mapped.tag(), Some(7));
+    let permission = mapped.unlock().to_earlier();
+    assert_eq!(permission.tag(), Some(7));
+}
+
+#[test]
+fn tag_follows_the_stack_hierarchy() {
+    let stack = NetworkStack::new();
+    let mut permission = OuterMutexPermission::get();
+    permission.set_tag(1);
+    // A later tag replaces the earlier one.
+    permission.set_tag(2);
+
+    let ip = stack.ip_layer.lock(permission).guard();
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    let transport = stack
+        .transport_layer
+        .lock(device.unlock_for_sequential())
+        .guard();
+    assert_eq!(transport.tag(), Some(2));
+    assert_eq!(transport.unlock().to_earlier().to_earlier().tag(), Some(2));
+}
+
+#[test]
+fn handed_off_flow_is_tagged_on_each_thread() {
+    let mutex = DeadlockProofMutex::new(Vec::new(), unique_type!());
+    let (handoff, received) = mpsc::channel();
+
+    thread::scope(|scope| {
+        let mutex = &mutex;
+        scope.spawn(move || {
+            let mut permission = OuterMutexPermission::get();
+            permission.set_tag(0xabc);
+            let mut guard = mutex.lock(permission).guard();
+            let tag = guard.tag();
+            guard.push(tag);
+            handoff.send(guard.unlock().tag()).unwrap();
+        });
+        scope.spawn(move || {
+            let mut permission = OuterMutexPermission::get();
+            permission.set_tag(received.recv().unwrap().unwrap());
+            let mut guard = mutex.lock(permission).guard();
+            let tag = guard.tag();
+            guard.push(tag);
+        });
+    });
+
+    let seen = mutex.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(*seen, [Some(0xabc); 2]);
+}
+
+#[cfg(feature = "metrics-exporter")]
+mod exporter {
+    use std::sync::Mutex;
+
+    use deadlock_proof::{
+        metrics::{set_recorder, Recorder},
+        DeadlockProofMutex, LockOutcome, OuterMutexPermission,
+    };
+
+    struct TaggedLock;
+    struct UntaggedLock;
+
+    #[derive(Default)]
+    struct Captured {
+        recorded: Mutex<Vec<(&'static str, Option<u64>)>>,
+    }
+
+    impl Recorder for Captured {
+        fn register_histogram(&self, _name: &'static str, _mutex: &'static str) {}
+
+        fn record_histogram(&self, _name: &'static str, mutex: &'static str, _nanos: u64) {
+            self.recorded.lock().unwrap().push((mutex, None));
+        }
+
+        fn record_tagged_histogram(
+            &self,
+            _name: &'static str,
+            mutex: &'static str,
+            _nanos: u64,
+            tag: u64,
+        ) {
+            self.recorded.lock().unwrap().push((mutex, Some(tag)));
+        }
+    }
+
+    #[test]
+    fn acquisitions_are_recorded_with_their_tag() {
+        let captured: &'static Captured = Box::leak(Box::default());
+        assert!(set_recorder(captured).is_ok());
+
+        let tagged = DeadlockProofMutex::new((), TaggedLock);
+        let untagged = DeadlockProofMutex::new((), UntaggedLock);
+        let mut permission = untagged.lock(OuterMutexPermission::get()).guard().unlock();
+        permission.set_tag(99);
+        let (guard, nested) = tagged.lock_for_nested(permission).guard();
+        guard.unlock(nested);
+
+        let recorded = captured.recorded.lock().unwrap();
+        let ours: Vec<_> = recorded
+            .iter()
+            .filter(|(mutex, _)| mutex.contains("::exporter::"))
+            .collect();
+        assert_eq!(
+            ours,
+            [
+                &(std::any::type_name::<UntaggedLock>(), None),
+                &(std::any::type_name::<TaggedLock>(), Some(99)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use std::ops::Deref;
+
+use deadlock_proof::{poison, unique_type, DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+
+/// Written once, builds with and without `no-poison`.
+fn value_of<O>(outcome: O) -> u32
+where
+    O: LockOutcome,
+    O::Guard: Deref<Target = u32>,
+{
+    *outcome.guard()
+}
+
+#[test]
+fn lock_results_are_handled_the_same_in_either_mode() {
+    let mutex = DeadlockProofMutex::new(3u32, unique_type!());
+    let mut guard = mutex.lock(OuterMutexPermission::get()).guard();
+    *guard += 1;
+    let permission = guard.unlock();
+
+    let permission = match mutex.lock(permission).into_result() {
+        Ok(guard) => guard.unlock(),
+        Err(_) => unreachable!("never poisoned"),
+    };
+    assert_eq!(value_of(mutex.lock(permission)), 4);
+}
+
+#[test]
+fn poisoning_flag_matches_the_build() {
+    assert_eq!(poison::POISONING, cfg!(not(feature = "no-poison")));
+}
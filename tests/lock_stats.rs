@@ -0,0 +1,90 @@
+#![cfg(feature = "diagnostics")]
+
+use std::{sync::Mutex, thread};
+
+use deadlock_proof::{
+    lock_stats, DeadlockProofMutex, LockOutcome, NestedMutexPermission, OuterMutexPermission,
+};
+
+/// The maxima are global, so tests that reset them must not overlap.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+struct HostLock;
+struct InterfaceLock;
+struct QueueLock;
+
+type Host = DeadlockProofMutex<u32, OuterMutexPermission, HostLock>;
+type Interface =
+    DeadlockProofMutex<u32, NestedMutexPermission<OuterMutexPermission, HostLock>, InterfaceLock>;
+type Queue = DeadlockProofMutex<
+    u32,
+    NestedMutexPermission<NestedMutexPermission<OuterMutexPermission, HostLock>, InterfaceLock>,
+    QueueLock,
+>;
+
+/// Holds the three mutexes at once, `times` times over.
+fn nest_three_deep(mut permission: OuterMutexPermission, times: usize) -> OuterMutexPermission {
+    let host = Host::new(0, HostLock);
+    let interface = Interface::new(0, InterfaceLock);
+    let queue = Queue::new(0, QueueLock);
+    for _ in 0..times {
+        let (host, in_host) = host.lock_for_nested(permission).guard();
+        let (interface, in_interface) = interface.lock_for_nested(in_host).guard();
+        let queue = queue.lock(in_interface).guard();
+        permission = host.unlock(interface.unlock(queue.unlock()));
+    }
+    permission
+}
+
+/// Locks one mutex at a time.
+fn lock_flat(mut permission: OuterMutexPermission, times: usize) -> OuterMutexPermission {
+    let host = Host::new(0, HostLock);
+    for _ in 0..times {
+        permission = host.lock(permission).guard().unlock();
+    }
+    permission
+}
+
+#[test]
+fn every_thread_gets_its_own_maximum() {
+    let _serial = SERIAL.lock().unwrap_or_else(|error| error.into_inner());
+    lock_stats::reset();
+    let deep = thread::spawn(|| {
+        nest_three_deep(OuterMutexPermission::get(), 5);
+    });
+    let flat = thread::spawn(|| {
+        lock_flat(OuterMutexPermission::get(), 5);
+    });
+    let (deep_id, flat_id) = (deep.thread().id(), flat.thread().id());
+    deep.join().unwrap();
+    flat.join().unwrap();
+
+    let maxima = lock_stats::per_thread_max_depth();
+    assert_eq!(maxima.len(), 2);
+    assert!(maxima.contains(&(deep_id, 3)));
+    assert!(maxima.contains(&(flat_id, 1)));
+    assert_eq!(lock_stats::max_depth_observed(), 3);
+}
+
+#[test]
+fn reset_starts_a_new_window() {
+    let _serial = SERIAL.lock().unwrap_or_else(|error| error.into_inner());
+    lock_stats::reset();
+    thread::spawn(|| {
+        let permission = nest_three_deep(OuterMutexPermission::get(), 1);
+        assert_eq!(lock_stats::thread_max_depth(), 3);
+        lock_stats::reset();
+        assert_eq!(lock_stats::thread_max_depth(), 0);
+        assert_eq!(lock_stats::max_depth_observed(), 0);
+
+        // The same thread only reaches depth one in the new window.
+        lock_flat(permission, 3);
+        assert_eq!(lock_stats::thread_max_depth(), 1);
+        assert_eq!(
+            lock_stats::per_thread_max_depth(),
+            [(thread::current().id(), 1)]
+        );
+    })
+    .join()
+    .unwrap();
+}
@@ -0,0 +1,88 @@
+use deadlock_proof::{
+    lock_hierarchy, DeadlockProofMutex, DeadlockProofRwLock, LockLevel, NestedMutexPermission,
+    OuterMutexPermission, PermissionDepth, Position, SequentialMutexPermission,
+    MAX_HIERARCHY_DEPTH,
+};
+
+struct L0;
+struct L1;
+struct L2;
+struct L3;
+struct L4;
+struct L5;
+struct L6;
+struct L7;
+lock_hierarchy!(OuterMutexPermission => L0, L1, L2, L3, L4, L5, L6, L7);
+
+struct Deep0;
+struct Deep1;
+struct Deep2;
+struct Deep3;
+struct Deep4;
+struct Deep5;
+struct Deep6;
+struct Deep7;
+struct Deep8;
+lock_hierarchy!(OuterMutexPermission => Deep0, Deep1, Deep2, Deep3, Deep4, Deep5, Deep6, Deep7, Deep8);
+
+#[test]
+fn depth_counts_steps_from_the_root() {
+    type Nested = NestedMutexPermission<OuterMutexPermission, L0>;
+    assert_eq!(OuterMutexPermission::DEPTH, 0);
+    assert_eq!(Nested::DEPTH, 1);
+    assert_eq!(SequentialMutexPermission::<Nested, L1>::DEPTH, 2);
+    assert_eq!(<Position<L7>>::DEPTH, 7);
+    // The flat form of deep hierarchies counts the same way.
+    assert_eq!(<Position<Deep1>>::DEPTH, 1);
+    assert_eq!(<Position<Deep8>>::DEPTH, 8);
+}
+
+#[test]
+fn configured_maximum_follows_the_features() {
+    let expected = if cfg!(feature = "max-depth-8") {
+        Some(8)
+    } else if cfg!(feature = "max-depth-16") {
+        Some(16)
+    } else {
+        None
+    };
+    assert_eq!(MAX_HIERARCHY_DEPTH, expected);
+}
+
+fn level<I: LockLevel>(identifier: I) -> DeadlockProofMutex<(), Position<I>, I> {
+    DeadlockProofMutex::new((), identifier)
+}
+
+#[test]
+fn eight_levels_are_within_every_maximum() {
+    let sequential = [
+        level(L0).is_locked(),
+        level(L1).is_locked(),
+        level(L2).is_locked(),
+        level(L3).is_locked(),
+        level(L4).is_locked(),
+        level(L5).is_locked(),
+        level(L6).is_locked(),
+        level(L7).is_locked(),
+    ];
+    let deep = [
+        level(Deep0).is_locked(),
+        level(Deep1).is_locked(),
+        level(Deep2).is_locked(),
+        level(Deep3).is_locked(),
+        level(Deep4).is_locked(),
+        level(Deep5).is_locked(),
+        level(Deep6).is_locked(),
+        level(Deep7).is_locked(),
+    ];
+    assert_eq!(sequential, [false; 8]);
+    assert_eq!(deep, [false; 8]);
+
+    let _rw: DeadlockProofRwLock<u32, Position<Deep7>, Deep7> = DeadlockProofRwLock::new(7, Deep7);
+}
+
+#[cfg(not(feature = "max-depth-8"))]
+#[test]
+fn ninth_level_needs_a_higher_maximum() {
+    assert!(!level(Deep8).is_locked());
+}
@@ -0,0 +1,178 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    slice,
+    sync::{Mutex, MutexGuard, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use deadlock_proof::{
+    lease::{self, PermissionLease},
+    misuse,
+    pool::{RetireReason, WorkerPool},
+    DeadlockProofMutex, HierarchyGeneration, MisuseEvent, OuterMutexPermission,
+    SingleThreadedPhase,
+};
+
+/// Every event the handler got during the current test.
+static EVENTS: Mutex<Vec<MisuseEvent>> = Mutex::new(Vec::new());
+
+fn events() -> MutexGuard<'static, Vec<MisuseEvent>> {
+    EVENTS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn record(event: MisuseEvent) {
+    events().push(event);
+}
+
+/// The handler is process-wide and phases look at every claim, so these
+/// tests run one at a time, each on a fresh thread.
+fn with_handler(body: impl FnOnce() + Send) {
+    static SERIAL: Mutex<()> = Mutex::new(());
+    let _serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+    events().clear();
+    misuse::set_misuse_handler(record);
+    thread::scope(|scope| scope.spawn(body).join()).unwrap();
+    misuse::clear_misuse_handler();
+}
+
+#[test]
+fn double_claims_are_reported_before_the_panic() {
+    with_handler(|| {
+        let _permission = OuterMutexPermission::get();
+        assert!(OuterMutexPermission::try_get().is_none());
+        assert!(events().is_empty());
+
+        assert!(panic::catch_unwind(OuterMutexPermission::get).is_err());
+        let events = events();
+        assert!(
+            matches!(events.as_slice(), [MisuseEvent::DoubleClaim(_)]),
+            "expected one double claim, got {events:?}"
+        );
+    });
+}
+
+#[test]
+fn leaked_leases_are_reported_and_still_recorded() {
+    with_handler(|| {
+        let before = lease::abandoned().len();
+        let line = line!() + 1;
+        let (lease, _finisher) = PermissionLease::new(OuterMutexPermission::get());
+        drop(lease);
+
+        let events = events();
+        let [MisuseEvent::PermissionLeaked(abandoned)] = events.as_slice() else {
+            panic!("expected one leaked permission, got {events:?}");
+        };
+        assert_eq!(abandoned.created_at.line(), line);
+        assert_eq!(lease::abandoned().len(), before + 1);
+    });
+}
+
+#[test]
+fn retired_workers_are_reported_and_still_recorded() {
+    with_handler(|| {
+        let pool = WorkerPool::new(1, Duration::from_secs(10));
+        pool.submit("doomed", |lease| {
+            lease.with_permission(|_permission| panic!("dropped on the floor"))
+        });
+        // The worker reports once it has let go of the pool.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while events().is_empty() {
+            assert!(
+                Instant::now() < deadline,
+                "the retirement was never reported"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let events = events();
+        let [MisuseEvent::WorkerRetired(retirement)] = events.as_slice() else {
+            panic!("expected one retired worker, got {events:?}");
+        };
+        assert_eq!(retirement.job, "doomed");
+        assert_eq!(retirement.reason, RetireReason::Panicked);
+        assert_eq!(pool.retirements().as_slice(), slice::from_ref(retirement));
+    });
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn violated_invariants_are_reported_and_the_guard_unlocks() {
+    use deadlock_proof::LockOutcome;
+
+    struct Budget;
+    with_handler(|| {
+        let budget = DeadlockProofMutex::new(10i32, Budget);
+        budget.set_release_invariant(|left| match *left >= 0 {
+            true => Ok(()),
+            false => Err(format!("{left} left")),
+        });
+        let mut left = budget.lock(OuterMutexPermission::get()).guard();
+        *left -= 20;
+        left.unlock();
+        assert!(!budget.is_locked());
+
+        let events = events();
+        let [MisuseEvent::InvariantViolated {
+            identifier,
+            message,
+            ..
+        }] = events.as_slice()
+        else {
+            panic!("expected one violated invariant, got {events:?}");
+        };
+        assert!(identifier.ends_with("Budget"));
+        assert_eq!(message, "-10 left");
+    });
+}
+
+#[test]
+fn relocking_an_elided_mutex_is_reported_before_the_panic() {
+    struct Scratch;
+    with_handler(|| {
+        let mutex: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+            DeadlockProofMutex::new(0, Scratch);
+        let mut permission = OuterMutexPermission::get();
+        let phase = SingleThreadedPhase::enter(&mut permission).unwrap();
+        let _held = mutex.lock_elided(&phase);
+        let line = line!() + 1;
+        let relock = AssertUnwindSafe(|| drop(mutex.lock_elided(&phase)));
+        assert!(panic::catch_unwind(relock).is_err());
+
+        let events = events();
+        let [MisuseEvent::ElidedRelock { at }] = events.as_slice() else {
+            panic!("expected one elided relock, got {events:?}");
+        };
+        assert_eq!(at.line(), line);
+    });
+}
+
+#[test]
+fn misfit_generation_members_are_reported_and_created() {
+    struct Table;
+    with_handler(|| {
+        let generation = HierarchyGeneration::new();
+        let _first: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+            DeadlockProofMutex::new_in(0, Table, &generation);
+        let second: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+            DeadlockProofMutex::new_in(1, Table, &generation);
+        generation.close();
+        let _late: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+            DeadlockProofMutex::new_in(2, Table, &generation);
+        assert!(!second.is_locked());
+
+        let events = events();
+        let [MisuseEvent::DuplicateIdentifier {
+            identifier,
+            generation: duplicated_in,
+        }, MisuseEvent::ClosedGeneration {
+            generation: closed, ..
+        }] = events.as_slice()
+        else {
+            panic!("expected a duplicate and a closed generation, got {events:?}");
+        };
+        assert!(identifier.ends_with("Table"));
+        assert_eq!([*duplicated_in, *closed], [generation.id(); 2]);
+    });
+}
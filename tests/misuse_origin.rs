@@ -0,0 +1,50 @@
+#![cfg(feature = "origin-check")]
+
+use std::{
+    sync::{Mutex, PoisonError},
+    thread::{self, ThreadId},
+};
+
+use deadlock_proof::{misuse, DeadlockProofMutex, LockOutcome, MisuseEvent, OuterMutexPermission};
+
+/// Carries a permission to another thread, which the crate never does.
+struct Smuggled<P>(P);
+
+// Safety: deliberately wrong, to hand a permission to the thread that must
+// report it.
+unsafe impl<P> Send for Smuggled<P> {}
+
+static REPORTED: Mutex<Vec<(ThreadId, ThreadId)>> = Mutex::new(Vec::new());
+
+fn record(event: MisuseEvent) {
+    if let MisuseEvent::WrongThread {
+        claimed_on,
+        used_on,
+    } = event
+    {
+        let mut reported = REPORTED.lock().unwrap_or_else(PoisonError::into_inner);
+        reported.push((claimed_on, used_on));
+    }
+}
+
+#[test]
+fn wrong_thread_locks_are_reported_and_go_ahead() {
+    misuse::set_misuse_handler(record);
+    let (owner, permission) = thread::spawn(|| {
+        (
+            thread::current().id(),
+            Smuggled(OuterMutexPermission::get()),
+        )
+    })
+    .join()
+    .unwrap();
+
+    let mutex = DeadlockProofMutex::new(1u32, ());
+    let guard = mutex.lock(permission.0).guard();
+    assert_eq!(*guard, 1);
+    guard.unlock();
+    misuse::clear_misuse_handler();
+
+    let reported = REPORTED.lock().unwrap_or_else(PoisonError::into_inner);
+    assert_eq!(*reported, [(owner, thread::current().id())]);
+}
@@ -0,0 +1,79 @@
+use std::thread;
+
+use deadlock_proof::{
+    declare_mutex_family, FamilyId, LockOutcome, MutexFamily, OuterMutexPermission,
+};
+
+declare_mutex_family!(QueueLock: Q0, Q1, Q2, Q3, Q4, Q5, Q6, Q7, Q8, Q9, Q10, Q11);
+
+type Queues = MutexFamily<Vec<usize>, OuterMutexPermission, QueueLock, QueueLockId>;
+
+fn queues() -> Queues {
+    Queues::new(QueueLock, |id| vec![id.index()])
+}
+
+#[test]
+fn ids_follow_declared_order() {
+    assert_eq!(QueueLockId::ALL.len(), 12);
+    assert_eq!(QueueLockId::ALL[0], QueueLockId::Q0);
+    assert_eq!(QueueLockId::ALL[11], QueueLockId::Q11);
+    assert_eq!(QueueLockId::from(Q7).index(), 7);
+}
+
+#[test]
+fn get_returns_the_named_member() {
+    let queues = queues();
+    let guard = queues
+        .get(Q5.into())
+        .lock(OuterMutexPermission::get())
+        .guard();
+    assert_eq!(*guard, [5]);
+    let permission = guard.unlock();
+    let guard = queues.get(QueueLockId::Q11).lock(permission).guard();
+    assert_eq!(*guard, [11]);
+}
+
+#[test]
+fn walk_visits_every_member_in_order() {
+    let queues = queues();
+    let mut visited = Vec::new();
+    let mut permission =
+        queues.lock_each_in_declared_order(OuterMutexPermission::get(), |id, queue| {
+            visited.push(id);
+            queue.push(queue[0] * 2);
+        });
+    assert_eq!(visited, QueueLockId::ALL);
+
+    for (id, member) in queues.iter() {
+        let guard = member.lock(permission).guard();
+        assert_eq!(*guard, [id.index(), id.index() * 2]);
+        permission = guard.unlock();
+    }
+}
+
+#[test]
+fn family_is_shared_between_threads() {
+    let queues = queues();
+    thread::scope(|scope| {
+        for worker in 0..4 {
+            let queues = &queues;
+            scope.spawn(move || {
+                let mut permission = OuterMutexPermission::get();
+                for round in 0..50 {
+                    let id = QueueLockId::ALL[(worker + round) % QueueLockId::ALL.len()];
+                    let mut guard = queues.get(id).lock(permission).guard();
+                    guard.push(worker);
+                    permission = guard.unlock();
+                }
+                queues.lock_each_in_declared_order(permission, |_, queue| queue.push(worker));
+            });
+        }
+    });
+
+    let mut total = 0;
+    queues.lock_each_in_declared_order(OuterMutexPermission::get(), |id, queue| {
+        assert_eq!(queue[0], id.index());
+        total += queue.len() - 1;
+    });
+    assert_eq!(total, 4 * 50 + 4 * 12);
+}
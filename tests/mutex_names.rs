@@ -0,0 +1,62 @@
+#![cfg(feature = "tracking")]
+
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use deadlock_proof::{
+    config::MutexConfig, declare_mutex_family, DeadlockProofMutex, LockOutcome, MutexFamily,
+    NetworkStack, OuterMutexPermission,
+};
+
+struct TableLock;
+
+#[test]
+fn names_are_kept_and_optional() {
+    let unnamed: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+        DeadlockProofMutex::new(0, TableLock);
+    let named: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+        DeadlockProofMutex::new_named(0, TableLock, "arp-table");
+    let configured: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+        DeadlockProofMutex::with_config(0, TableLock, MutexConfig::new().name("nd-table"));
+    assert_eq!(unnamed.name(), None);
+    assert_eq!(named.name(), Some("arp-table"));
+    assert_eq!(configured.name(), Some("nd-table"));
+
+    let stack = NetworkStack::new();
+    assert_eq!(stack.ip_layer.name(), Some("ip-layer"));
+    assert_eq!(stack.device_layer.name(), Some("device-layer"));
+    assert_eq!(stack.transport_layer.name(), Some("transport-layer"));
+}
+
+#[test]
+fn the_poison_message_names_the_mutex() {
+    let table: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+        DeadlockProofMutex::new_named(0, TableLock, "arp-table");
+    let heard = Arc::new(Mutex::new(Vec::new()));
+    let watcher = Arc::clone(&heard);
+    table.on_poison(move |event| watcher.lock().unwrap().push(event.to_string()));
+    thread::scope(|scope| {
+        let writer = scope.spawn(|| {
+            let _table = table.lock(OuterMutexPermission::get()).guard();
+            panic!("bad entry");
+        });
+        assert!(writer.join().is_err());
+    });
+
+    let heard = heard.lock().unwrap();
+    let [message] = heard.as_slice() else {
+        panic!("expected one poisoning, got {heard:?}");
+    };
+    assert!(message.starts_with("`arp-table` poisoned"), "{message}");
+}
+
+#[test]
+fn family_members_are_named_after_their_ids() {
+    declare_mutex_family!(QueueLock: Rx, Tx);
+    let queues: MutexFamily<u32, OuterMutexPermission, QueueLock, QueueLockId> =
+        MutexFamily::new(QueueLock, |_| 0);
+    assert_eq!(queues.get(QueueLockId::Rx).name(), Some("QueueLock::Rx"));
+    assert_eq!(queues.get(QueueLockId::Tx).name(), Some("QueueLock::Tx"));
+}
@@ -0,0 +1,74 @@
+use std::net::SocketAddr;
+
+use deadlock_proof::{
+    net_demo::{ConnectionRegistry, ConnectionStats, ShuttingDown},
+    NetworkStack, OuterMutexPermission,
+};
+
+fn peer(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+#[test]
+fn registry_publishes_to_every_layer() {
+    let stack = NetworkStack::new();
+    let registry = ConnectionRegistry::new();
+    let mut permission = OuterMutexPermission::get();
+    for port in [2000, 1000] {
+        let opened;
+        (permission, opened) = registry.open(&stack, permission, peer(port));
+        opened.unwrap();
+    }
+    for bytes in [3, 4] {
+        let recorded;
+        (permission, recorded) = registry.record(&stack, permission, peer(2000), bytes);
+        recorded.unwrap();
+    }
+
+    let (permission, connections) = registry.connections(&stack, permission);
+    let stats = ConnectionStats {
+        chunks: 2,
+        bytes_echoed: 7,
+    };
+    assert_eq!(
+        connections,
+        [
+            (peer(1000), ConnectionStats::default()),
+            (peer(2000), stats)
+        ]
+    );
+    let (permission, views) = stack.views(permission);
+    assert_eq!(views.ip.packets_processed, 2);
+    assert_eq!(views.device.bytes_transmitted, 7);
+    assert_eq!(views.transport.tcp_connections, 2);
+
+    let (permission, closed) = registry.close(&stack, permission, peer(2000));
+    assert_eq!(closed, Some(stats));
+    let (permission, closed) = registry.close(&stack, permission, peer(2000));
+    assert_eq!(closed, None);
+    assert_eq!(registry.len(), 1);
+    let (_, views) = stack.views(permission);
+    assert_eq!(views.transport.tcp_connections, 1);
+}
+
+#[test]
+fn shutdown_calls_off_everything_but_closing() {
+    let stack = NetworkStack::new();
+    let registry = ConnectionRegistry::new();
+    let (permission, opened) = registry.open(&stack, OuterMutexPermission::get(), peer(1000));
+    opened.unwrap();
+
+    registry.shut_down();
+    let (permission, recorded) = registry.record(&stack, permission, peer(1000), 5);
+    assert_eq!(recorded, Err(ShuttingDown));
+    let (permission, opened) = registry.open(&stack, permission, peer(2000));
+    assert_eq!(opened, Err(ShuttingDown));
+
+    // The permission came back every time, and still closes the connection.
+    let (permission, closed) = registry.close(&stack, permission, peer(1000));
+    assert_eq!(closed, Some(ConnectionStats::default()));
+    assert!(registry.is_empty());
+    let (_, views) = stack.views(permission);
+    assert_eq!(views.device.bytes_transmitted, 0);
+    assert_eq!(views.transport.tcp_connections, 0);
+}
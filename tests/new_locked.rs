@@ -0,0 +1,92 @@
+use std::{
+    collections::HashMap,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use deadlock_proof::{
+    lock_hierarchy, DeadlockProofMutex, LockOutcome, OuterMutexPermission, Position,
+};
+
+struct ConnectionLock;
+struct SocketLock;
+lock_hierarchy!(OuterMutexPermission => ConnectionLock);
+lock_hierarchy!(within ConnectionLock => SocketLock);
+
+#[derive(Default)]
+struct Connection {
+    initialized: bool,
+    window: u32,
+}
+
+type Connections =
+    Mutex<HashMap<u16, Arc<DeadlockProofMutex<Connection, OuterMutexPermission, ConnectionLock>>>>;
+
+#[test]
+fn a_published_entry_blocks_until_initialization_is_done() {
+    let connections = Connections::default();
+    let (published_tx, published_rx) = mpsc::channel();
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let (state, mut guard) = DeadlockProofMutex::new_locked(
+                Connection::default(),
+                ConnectionLock,
+                OuterMutexPermission::get(),
+            );
+            assert!(Arc::ptr_eq(guard.mutex(), &state));
+            connections.lock().unwrap().insert(4000, state);
+            published_tx.send(()).unwrap();
+            // Give the reader every chance to get in early.
+            thread::sleep(Duration::from_millis(50));
+            guard.window = 65535;
+            guard.initialized = true;
+            guard.unlock();
+        });
+        let connections = &connections;
+        scope.spawn(move || {
+            published_rx.recv().unwrap();
+            let state = Arc::clone(&connections.lock().unwrap()[&4000]);
+            assert!(state.is_locked());
+            let connection = state.lock(OuterMutexPermission::get()).guard();
+            assert!(connection.initialized);
+            assert_eq!(connection.window, 65535);
+        });
+    });
+}
+
+#[test]
+fn the_guard_keeps_the_mutex_alive() {
+    let (state, mut guard) = DeadlockProofMutex::new_locked(
+        Connection::default(),
+        ConnectionLock,
+        OuterMutexPermission::get(),
+    );
+    drop(state);
+    guard.window = 1;
+    let mutex = Arc::clone(guard.mutex());
+    #[cfg(feature = "tracking")]
+    let version = mutex.version();
+    let permission = guard.unlock();
+    assert!(!mutex.is_locked());
+    #[cfg(feature = "tracking")]
+    assert_ne!(mutex.version(), version);
+    assert_eq!(mutex.lock(permission).guard().window, 1);
+}
+
+#[test]
+fn nested_construction_sets_up_the_inside_first() {
+    let sockets = DeadlockProofMutex::<_, Position<SocketLock>, _>::new(Vec::new(), SocketLock);
+    let (state, mut guard, inside) = DeadlockProofMutex::new_locked_for_nested(
+        Connection::default(),
+        ConnectionLock,
+        OuterMutexPermission::get(),
+    );
+    let mut socket = sockets.lock(inside).guard();
+    socket.push(4000u16);
+    guard.initialized = true;
+    let permission = guard.unlock(socket.unlock());
+
+    let connection = state.lock(permission).guard();
+    assert!(connection.initialized);
+}
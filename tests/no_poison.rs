@@ -0,0 +1,50 @@
+//! Poisoned locks under `no-poison`: never reported, data handed out as left.
+#![cfg(all(feature = "test-util", feature = "no-poison"))]
+
+use deadlock_proof::{
+    unique_type, DeadlockProofMutex, NetworkStack, OuterMutexPermission, PinnedLockError,
+    ThreadPinnedMutex,
+};
+
+#[test]
+fn poisoned_mutex_still_hands_out_its_guard() {
+    let mutex = DeadlockProofMutex::new(7u32, unique_type!());
+    mutex.poison_for_test();
+
+    let guard = mutex.lock(OuterMutexPermission::get());
+    assert_eq!(*guard, 7);
+    let (guard, _nested) = mutex.lock_for_nested(guard.unlock());
+    assert_eq!(*guard, 7);
+}
+
+#[test]
+fn transaction_commits_over_a_poisoned_layer() {
+    let stack = NetworkStack::new();
+    stack.device_layer.poison_for_test();
+
+    let (permission, result) = stack
+        .transaction()
+        .stage_device(|device| {
+            device.interfaces_active = 2;
+            Ok(())
+        })
+        .commit(OuterMutexPermission::get());
+    result.unwrap();
+    let ip = stack.ip_layer.lock(permission);
+    let device = stack.device_layer.lock(ip.unlock_for_sequential());
+    assert_eq!(device.interfaces_active, 2);
+}
+
+#[test]
+fn pinned_errors_need_only_match_the_wrong_thread() {
+    let permission = OuterMutexPermission::get();
+    let mutex = ThreadPinnedMutex::new(0u32, unique_type!(), &permission);
+    mutex.poison_for_test();
+    std::thread::scope(|scope| {
+        scope.spawn(|| match mutex.lock(OuterMutexPermission::get()) {
+            Ok(_) => panic!("locked from a foreign thread"),
+            Err(PinnedLockError::WrongThread(_)) => {}
+        });
+    });
+    assert_eq!(*mutex.lock(permission).unwrap(), 0);
+}
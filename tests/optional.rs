@@ -0,0 +1,90 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+};
+
+use deadlock_proof::{
+    lock_hierarchy, DeadlockProofMutex, EitherGuard, LockOutcome, OuterMutexPermission, Position,
+};
+
+struct InterfaceLock;
+struct QueueLock;
+lock_hierarchy!(OuterMutexPermission => InterfaceLock, QueueLock);
+
+type Interface = DeadlockProofMutex<u64, Position<InterfaceLock>, InterfaceLock>;
+type Queue = DeadlockProofMutex<Vec<u32>, Position<QueueLock>, QueueLock>;
+
+#[test]
+fn present_mutex_is_locked() {
+    let interface = Interface::new(0, InterfaceLock);
+    let mut guard =
+        DeadlockProofMutex::lock_opt(Some(&interface), OuterMutexPermission::get()).guard();
+    assert!(guard.is_present());
+    *guard.get_mut().unwrap() += 1500;
+    assert!(interface.is_locked());
+    let permission = guard.unlock();
+
+    assert!(!interface.is_locked());
+    #[cfg(feature = "tracking")]
+    assert_eq!(interface.version(), 1);
+    let guard = DeadlockProofMutex::lock_opt(Some(&interface), permission).guard();
+    assert_eq!(guard.get(), Some(&1500));
+    assert!(guard.into_guard().is_ok());
+}
+
+#[test]
+fn absent_mutex_hands_the_permission_back() {
+    let interfaces: HashMap<u32, Interface> = HashMap::new();
+    let mut guard =
+        DeadlockProofMutex::lock_opt(interfaces.get(&7), OuterMutexPermission::get()).guard();
+    assert!(!guard.is_present());
+    assert_eq!(guard.get(), None);
+    assert_eq!(guard.get_mut(), None);
+
+    // The permission still locks whatever is there.
+    let permission = match guard.into_guard() {
+        Err(permission) => permission,
+        Ok(_) => panic!("nothing to lock"),
+    };
+    let interface = Interface::new(0, InterfaceLock);
+    assert!(matches!(
+        DeadlockProofMutex::lock_opt(Some(&interface), permission).guard(),
+        EitherGuard::Present(_)
+    ));
+}
+
+#[test]
+fn interface_removed_while_walking_is_stepped_past() {
+    let mut interfaces: HashMap<u32, Arc<Interface>> = (0..3)
+        .map(|id| (id, Arc::new(Interface::new(0, InterfaceLock))))
+        .collect();
+    let queue = Queue::new(Vec::new(), QueueLock);
+    // The walk only keeps weak handles, so removal is not held up by it.
+    let mut walk: Vec<(u32, Weak<Interface>)> = interfaces
+        .iter()
+        .map(|(&id, interface)| (id, Arc::downgrade(interface)))
+        .collect();
+    walk.sort_by_key(|&(id, _)| id);
+
+    let mut permission = OuterMutexPermission::get();
+    for (id, interface) in walk {
+        if id == 0 {
+            interfaces.remove(&1);
+        }
+        let interface = interface.upgrade();
+        let mut guard = DeadlockProofMutex::lock_opt(interface.as_deref(), permission).guard();
+        let present = guard.is_present();
+        if let Some(bytes) = guard.get_mut() {
+            *bytes += 1;
+        }
+
+        let mut pending = queue.lock(guard.unlock_for_sequential()).guard();
+        if present {
+            pending.push(id);
+        }
+        permission = pending.unlock().to_earlier();
+    }
+
+    let nothing = DeadlockProofMutex::lock_opt(None::<&Interface>, permission).guard();
+    assert_eq!(*queue.lock(nothing.unlock_for_sequential()).guard(), [0, 2]);
+}
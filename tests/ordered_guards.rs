@@ -0,0 +1,122 @@
+#![cfg(feature = "tracking")]
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+};
+
+use deadlock_proof::{
+    DeadlockProofMutex, LockOutcome, NestedMutexPermission, OrderedGuards, OuterMutexPermission,
+};
+
+struct Outer;
+struct Middle;
+struct Inner;
+
+type MiddlePermission = NestedMutexPermission<OuterMutexPermission, Outer>;
+type InnerPermission = NestedMutexPermission<MiddlePermission, Middle>;
+
+struct Layers {
+    outer: DeadlockProofMutex<u32, OuterMutexPermission, Outer>,
+    middle: DeadlockProofMutex<u32, MiddlePermission, Middle>,
+    inner: DeadlockProofMutex<u32, InnerPermission, Inner>,
+    released: Arc<Mutex<Vec<&'static str>>>,
+}
+
+/// Three nested mutexes whose releases after a modification are logged,
+/// through the change listeners that run right after each unlock.
+fn layers() -> Layers {
+    let layers = Layers {
+        outer: DeadlockProofMutex::new(0, Outer),
+        middle: DeadlockProofMutex::new(0, Middle),
+        inner: DeadlockProofMutex::new(0, Inner),
+        released: Arc::default(),
+    };
+    let log = |name| {
+        let released = Arc::clone(&layers.released);
+        move |_| released.lock().unwrap().push(name)
+    };
+    layers.outer.on_change(log("outer"));
+    layers.middle.on_change(log("middle"));
+    layers.inner.on_change(log("inner"));
+    layers
+}
+
+#[test]
+fn dropping_releases_innermost_first() {
+    let layers = layers();
+    let mut guards = OrderedGuards::new(OuterMutexPermission::get())
+        .lock_nested(&layers.outer)
+        .guard()
+        .lock_nested(&layers.middle)
+        .guard()
+        .lock(&layers.inner)
+        .guard();
+    *guards.get_mut() += 1;
+    *guards.below_mut().get_mut() += 1;
+    *guards.below_mut().below_mut().get_mut() += 1;
+    assert!(layers.released.lock().unwrap().is_empty());
+
+    drop(guards);
+    assert_eq!(
+        *layers.released.lock().unwrap(),
+        ["inner", "middle", "outer"]
+    );
+    assert!(!layers.outer.is_locked());
+}
+
+#[test]
+fn unlock_all_hands_back_the_root_permission() {
+    let layers = layers();
+    let mut guards = OrderedGuards::new(OuterMutexPermission::get())
+        .lock_nested(&layers.outer)
+        .guard()
+        .lock_nested(&layers.middle)
+        .guard();
+    *guards.get_mut() += 1;
+    *guards.below_mut().get_mut() += 1;
+
+    let permission = guards.unlock_all();
+    assert_eq!(*layers.released.lock().unwrap(), ["middle", "outer"]);
+    assert_eq!(*layers.outer.lock(permission).guard(), 1);
+}
+
+#[test]
+fn pop_releases_only_the_top() {
+    let layers = layers();
+    let mut guards = OrderedGuards::new(OuterMutexPermission::get())
+        .lock_nested(&layers.outer)
+        .guard()
+        .lock_nested(&layers.middle)
+        .guard();
+    *guards.get_mut() += 1;
+
+    let mut guards = guards.pop();
+    assert_eq!(*layers.released.lock().unwrap(), ["middle"]);
+    assert!(layers.outer.is_locked());
+    *guards.get_mut() += 1;
+
+    // The token is back on top, so the middle layer can be locked again.
+    let guards = guards.lock_nested(&layers.middle).guard();
+    assert_eq!(*guards.get(), 1);
+    guards.unlock_all();
+    assert_eq!(*layers.released.lock().unwrap(), ["middle", "outer"]);
+}
+
+#[test]
+fn unwinding_releases_innermost_first() {
+    let layers = layers();
+    let unwound = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut guards = OrderedGuards::new(OuterMutexPermission::get())
+            .lock_nested(&layers.outer)
+            .guard()
+            .lock(&layers.middle)
+            .guard();
+        *guards.get_mut() += 1;
+        *guards.below_mut().get_mut() += 1;
+        panic!("unwind with both guards held");
+    }));
+
+    assert!(unwound.is_err());
+    assert_eq!(*layers.released.lock().unwrap(), ["middle", "outer"]);
+}
@@ -0,0 +1,139 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use deadlock_proof::{concurrent, unique_type, OrderedLockMap, OuterMutexPermission};
+
+/// An IPv4 prefix as (network, length), ordered by network first.
+type Prefix = (u32, u8);
+
+type RoutingTable<I> = OrderedLockMap<Prefix, u64, OuterMutexPermission, I>;
+
+/// A table of `prefixes` /24 routes, all with metric 0.
+fn routing_table(
+    mut permission: OuterMutexPermission,
+    prefixes: u32,
+) -> (RoutingTable<impl Sized>, OuterMutexPermission) {
+    let table = OrderedLockMap::new(unique_type!());
+    for network in 0..prefixes {
+        permission = table.insert(permission, (network << 8, 24), 0).0;
+    }
+    (table, permission)
+}
+
+#[test]
+fn locks_only_the_range_in_key_order() {
+    let (table, permission) = routing_table(OuterMutexPermission::get(), 10);
+    let mut routes = table.lock_range(permission, (2 << 8, 0)..(5 << 8, 0));
+    assert_eq!(routes.len(), 3);
+    let keys: Vec<Prefix> = routes.iter().map(|(key, _)| *key).collect();
+    assert_eq!(keys, [(2 << 8, 24), (3 << 8, 24), (4 << 8, 24)]);
+    for (_, metric) in routes.iter_mut() {
+        *metric = 7;
+    }
+    let permission = routes.unlock_all();
+
+    let routes = table.lock_range(permission, ..);
+    let metrics: Vec<u64> = routes.iter().map(|(_, metric)| *metric).collect();
+    assert_eq!(metrics, [0, 0, 7, 7, 7, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn dropping_the_guards_unlocks_every_entry() {
+    let (table, permission) = routing_table(OuterMutexPermission::get(), 3);
+    drop(table.lock_range(permission, ..));
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let routes = table.lock_range(OuterMutexPermission::get(), ..);
+            assert_eq!(routes.len(), 3);
+        });
+    });
+}
+
+#[test]
+fn empty_ranges_lock_nothing() {
+    use std::ops::Bound::Excluded;
+
+    let (table, permission) = routing_table(OuterMutexPermission::get(), 4);
+    let routes = table.lock_range(permission, (3 << 8, 0)..(1 << 8, 0));
+    assert!(routes.is_empty());
+    let key = (2 << 8, 24);
+    let routes = table.lock_range(routes.unlock_all(), (Excluded(key), Excluded(key)));
+    assert!(routes.is_empty());
+    let (permission, _) = table.insert(routes.unlock_all(), (2 << 8, 16), 1);
+    assert_eq!(table.lock_range(permission, ..).len(), 5);
+}
+
+#[test]
+fn insert_waits_for_a_range_covering_it() {
+    let (table, _) = routing_table(OuterMutexPermission::get(), 4);
+    let released = AtomicBool::new(false);
+    let (locked_tx, locked_rx) = mpsc::channel();
+    let (table, released) = (&table, &released);
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let routes = table.lock_range(OuterMutexPermission::get(), (1 << 8, 0)..(3 << 8, 0));
+            locked_tx.send(()).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            released.store(true, Ordering::SeqCst);
+            routes.unlock_all();
+        });
+        scope.spawn(move || {
+            locked_rx.recv().unwrap();
+            let permission = OuterMutexPermission::get();
+            // Outside the held range: goes straight in.
+            let permission = table.insert(permission, (9 << 8, 24), 0).0;
+            assert!(!released.load(Ordering::SeqCst));
+            // Inside it: only once the range is released.
+            table.insert(permission, (1 << 8 | 128, 25), 0);
+            assert!(released.load(Ordering::SeqCst));
+        });
+    });
+    assert_eq!(table.len(), 6);
+}
+
+#[test]
+fn overlapping_ranges_from_many_threads() {
+    const PREFIXES: u32 = 64;
+    const ROUNDS: u64 = 200;
+    let (table, permission) = routing_table(OuterMutexPermission::get(), PREFIXES);
+
+    let workers = (0..8u32)
+        .map(|worker| -> concurrent::Worker<'_> {
+            let table = &table;
+            Box::new(move |mut permission| {
+                for round in 0..ROUNDS {
+                    // Every worker's ranges overlap its neighbours'.
+                    let start = (worker * 7 + round as u32) % (PREFIXES - 16);
+                    let range = (start << 8, 0)..((start + 16) << 8, 0);
+                    let mut routes = table.lock_range(permission, range);
+                    // Another worker's churned route may be in there too.
+                    let mut covered = 0;
+                    for (_, metric) in routes.iter_mut().filter(|((_, length), _)| *length == 24) {
+                        *metric += 1;
+                        covered += 1;
+                    }
+                    assert_eq!(covered, 16);
+                    permission = routes.unlock_all();
+
+                    // Churn a more specific route inside someone's range.
+                    let churn = (start + 8) << 8 | 128;
+                    permission = table.insert(permission, (churn, 25 + worker as u8), 0).0;
+                    permission = table.remove(permission, &(churn, 25 + worker as u8)).0;
+                }
+                permission
+            })
+        })
+        .collect();
+    concurrent::run_all(workers).unwrap();
+
+    assert_eq!(table.len(), PREFIXES as usize);
+    let routes = table.lock_range(permission, ..);
+    let total: u64 = routes.iter().map(|(_, metric)| *metric).sum();
+    assert_eq!(total, 8 * ROUNDS * 16);
+}
@@ -0,0 +1,102 @@
+use std::{
+    mem::size_of,
+    sync::{atomic::AtomicU32, Mutex},
+    thread,
+};
+
+use deadlock_proof::{
+    gate::OrderingGate, lock_hierarchy, DeadlockProofMutex, LockOutcome, NestedMutexPermission,
+    OuterMutexPermission, Position, TryLockError,
+};
+
+struct Ingress;
+struct Commit;
+struct Egress;
+lock_hierarchy!(OuterMutexPermission => Ingress, Commit, Egress);
+
+struct Walk {
+    ingress: DeadlockProofMutex<Vec<u32>, Position<Ingress>, Ingress>,
+    commit: OrderingGate<Position<Commit>, Commit>,
+    egress: DeadlockProofMutex<Vec<u32>, Position<Egress>, Egress>,
+}
+
+#[test]
+fn a_gate_orders_the_levels_around_it() {
+    let walk = Walk {
+        ingress: DeadlockProofMutex::new(Vec::new(), Ingress),
+        commit: OrderingGate::new(Commit),
+        egress: DeadlockProofMutex::new(Vec::new(), Egress),
+    };
+    // Whoever passes the gate first also reaches egress first, so egress
+    // sees the packets in the order the gate let them through.
+    let passed = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for packet in 0..4 {
+            let (walk, passed) = (&walk, &passed);
+            scope.spawn(move || {
+                let mut ingress = walk.ingress.lock(OuterMutexPermission::get()).guard();
+                ingress.push(packet);
+                let gate = walk.commit.lock(ingress.unlock_for_sequential());
+                passed.lock().unwrap().push(packet);
+                let mut egress = walk.egress.lock(gate.unlock_for_sequential()).guard();
+                egress.push(packet);
+            });
+        }
+    });
+    let ingress = walk.ingress.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(ingress.len(), 4);
+    let past_commit = ingress.unlock_for_sequential().skip_to::<Commit>();
+    let egress = walk.egress.lock(past_commit).guard();
+    assert_eq!(*egress, *passed.lock().unwrap());
+    assert!(!walk.commit.is_locked());
+}
+
+#[test]
+fn a_held_gate_hands_the_permission_back() {
+    struct Flush;
+    let gate: OrderingGate<OuterMutexPermission, _> = OrderingGate::new(Flush);
+    let held = gate.lock(OuterMutexPermission::get());
+    assert!(gate.is_locked());
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let Err(TryLockError::WouldBlock(_permission)) =
+                gate.try_lock(OuterMutexPermission::get())
+            else {
+                panic!("the held gate was passed");
+            };
+        });
+    });
+    let permission = held.unlock();
+    let passed = gate.try_lock(permission).ok().unwrap();
+    drop(passed);
+}
+
+#[test]
+fn nested_locks_go_inside_a_gate() {
+    struct Barrier;
+    struct Counter;
+    let gate: OrderingGate<OuterMutexPermission, _> = OrderingGate::new(Barrier);
+    let counter: DeadlockProofMutex<u32, NestedMutexPermission<OuterMutexPermission, Barrier>, _> =
+        DeadlockProofMutex::new(0, Counter);
+    let (held, inside) = gate.lock_for_nested(OuterMutexPermission::get());
+    let mut count = counter.lock(inside).guard();
+    *count += 1;
+    let _permission: OuterMutexPermission = held.unlock(count.unlock());
+}
+
+#[test]
+fn a_gate_is_only_its_lock() {
+    struct Phase;
+    // A `std::sync::Mutex<()>` is a futex word and a poison flag.
+    assert!(size_of::<OrderingGate<OuterMutexPermission, Phase>>() <= size_of::<AtomicU32>() * 2);
+    assert_eq!(
+        size_of::<OrderingGate<OuterMutexPermission, Phase>>(),
+        size_of::<Mutex<()>>()
+    );
+    // A mutex only keeps more than its lock with `tracking`.
+    #[cfg(feature = "tracking")]
+    assert!(
+        size_of::<OrderingGate<OuterMutexPermission, Phase>>()
+            < size_of::<DeadlockProofMutex<(), OuterMutexPermission, Phase>>()
+    );
+}
@@ -0,0 +1,67 @@
+#![cfg(feature = "origin-check")]
+
+use std::thread;
+
+use deadlock_proof::{
+    DeadlockProofMutex, LockOutcome, MutexPermission, NetworkStack, OuterMutexPermission,
+};
+
+/// Carries a permission to another thread, which the crate never does.
+struct Smuggled<P>(P);
+
+// Safety: deliberately wrong, to hand a permission to the thread that must
+// refuse it.
+unsafe impl<P> Send for Smuggled<P> {}
+
+/// A permission claimed on a thread named `name`, brought back to this one.
+fn claimed_on(name: &str) -> OuterMutexPermission {
+    let permission = thread::Builder::new()
+        .name(name.into())
+        .spawn(|| Smuggled(OuterMutexPermission::get()))
+        .unwrap()
+        .join()
+        .unwrap();
+    permission.0
+}
+
+#[test]
+fn derived_permissions_keep_the_claiming_thread() {
+    let stack = NetworkStack::new();
+    let current = Some(thread::current().id());
+    let permission = OuterMutexPermission::get();
+    assert_eq!(permission.origin(), current);
+
+    let (ip, nested) = stack.ip_layer.lock_for_nested(permission).guard();
+    assert_eq!(nested.origin(), current);
+    let device = stack
+        .device_layer
+        .lock(ip.unlock_for_sequential(nested))
+        .guard();
+    let after = device.unlock_for_sequential();
+    assert_eq!(after.origin(), current);
+    assert_eq!(after.to_earlier().to_earlier().origin(), current);
+}
+
+#[test]
+#[should_panic(expected = "permission claimed on thread `owner`")]
+fn locking_with_another_threads_permission_panics() {
+    let mutex = DeadlockProofMutex::new(0u32, ());
+    let _guard = mutex.lock(claimed_on("owner"));
+}
+
+#[test]
+#[should_panic(expected = "permission claimed on thread `walker`")]
+fn sequential_permission_from_another_thread_panics() {
+    let stack = NetworkStack::new();
+    let after_ip = thread::Builder::new()
+        .name("walker".into())
+        .spawn(|| {
+            let stack = NetworkStack::new();
+            let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+            Smuggled(ip.unlock_for_sequential())
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    let _device = stack.device_layer.lock(after_ip.0);
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use std::{
+    future::Future,
+    pin::{pin, Pin},
+    task::{Context, Poll, Waker},
+    thread,
+    time::Duration,
+};
+
+use common::{block_on, CountingWaker};
+use deadlock_proof::{
+    AsyncPermission, AsyncPermissionSlot, DeadlockProofAsyncMutex, MutexPermission, PermissionCell,
+};
+
+struct CounterLock;
+
+type Counter = DeadlockProofAsyncMutex<u32, AsyncPermission, CounterLock>;
+
+/// A hand-written future that increments the counter, however many polls
+/// that takes, and then hands the task permission back.
+struct Bump<'a> {
+    counter: &'a Counter,
+    permission: PermissionCell<AsyncPermission>,
+    polls: u32,
+}
+
+impl<'a> Bump<'a> {
+    fn new(counter: &'a Counter, permission: AsyncPermission) -> Self {
+        Self {
+            counter,
+            permission: PermissionCell::new(permission),
+            polls: 0,
+        }
+    }
+}
+
+impl Future for Bump<'_> {
+    type Output = AsyncPermission;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<AsyncPermission> {
+        let this = self.get_mut();
+        this.polls += 1;
+        let permission = this
+            .permission
+            .poll_take()
+            .expect("polled after completion");
+        match this.counter.lock_or_register(cx, permission) {
+            Ok(mut guard) => {
+                *guard += 1;
+                Poll::Ready(guard.unlock())
+            }
+            Err(permission) => {
+                this.permission.store(permission);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn assert_send<T: Send>() {}
+
+#[test]
+fn task_permission_cell_is_send() {
+    assert_send::<PermissionCell<AsyncPermission>>();
+    assert_send::<Bump<'static>>();
+}
+
+#[test]
+fn manual_future_acquires_across_polls() {
+    let counter = Counter::new(0, CounterLock);
+    let holder = AsyncPermissionSlot::new();
+    let task = AsyncPermissionSlot::new();
+
+    let held = block_on(counter.lock(holder.claim().unwrap()));
+    let waker = CountingWaker::new();
+    let task_waker = Waker::from(waker.clone());
+    let mut cx = Context::from_waker(&task_waker);
+
+    let mut bump = pin!(Bump::new(&counter, task.claim().unwrap()));
+    assert!(bump.as_mut().poll(&mut cx).is_pending());
+    assert_eq!(waker.wakes(), 0);
+
+    let _holder_permission = held.unlock();
+    assert_eq!(waker.wakes(), 1);
+
+    let Poll::Ready(permission) = bump.as_mut().poll(&mut cx) else {
+        panic!("counter is free after the holder unlocked");
+    };
+    assert_eq!(bump.polls, 2);
+    assert_eq!(*block_on(counter.lock(permission)), 1);
+}
+
+#[test]
+fn dropping_mid_acquisition_recovers_the_permission() {
+    let counter = Counter::new(0, CounterLock);
+    let holder = AsyncPermissionSlot::new();
+    let task = AsyncPermissionSlot::new();
+
+    let held = block_on(counter.lock(holder.claim().unwrap()));
+    let waker = Waker::from(CountingWaker::new());
+    let mut cx = Context::from_waker(&waker);
+
+    let mut bump = Box::pin(Bump::new(&counter, task.claim().unwrap()));
+    assert!(bump.as_mut().poll(&mut cx).is_pending());
+    assert!(!task.is_parked());
+    drop(bump);
+    assert!(task.is_parked());
+
+    // The recovered permission works like the original.
+    drop(held);
+    assert!(holder.is_parked());
+    let permission = block_on(Bump::new(&counter, task.claim().unwrap()));
+    assert_eq!(*block_on(counter.lock(permission)), 1);
+}
+
+#[test]
+fn lock_future_waits_for_another_thread() {
+    let counter = Counter::new(0, CounterLock);
+    let task = AsyncPermissionSlot::new();
+
+    thread::scope(|scope| {
+        let held = block_on(counter.lock(AsyncPermissionSlot::new().claim().unwrap()));
+        scope.spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            held.unlock();
+        });
+
+        let mut guard = block_on(counter.lock(task.claim().unwrap()));
+        *guard += 1;
+        guard.unlock().recover();
+    });
+    assert!(task.is_parked());
+}
@@ -0,0 +1,114 @@
+use deadlock_proof::{
+    lock_hierarchy, LockOutcome, NetworkStack, OuterMutexPermission, PermissionChain, Position,
+    TransportLock,
+};
+
+fn levels<P: PermissionChain>() -> Vec<&'static str> {
+    let mut levels = Vec::new();
+    P::visit_levels(&mut |level| levels.push(level));
+    levels
+}
+
+#[test]
+fn the_transport_position_is_past_ip_and_device() {
+    assert_eq!(
+        levels::<Position<TransportLock>>(),
+        ["Outer", "IpLock", "DeviceLock"]
+    );
+
+    let stack = NetworkStack::new();
+    let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    let permission = ip.unlock_for_sequential();
+    assert_eq!(permission.to_string(), "Outer → IpLock");
+    let device = stack.device_layer.lock(permission).guard();
+    assert_eq!(device.unlock_for_sequential().to_string(), "Outer → IpLock → DeviceLock");
+}
+
+#[test]
+fn a_root_is_its_own_chain() {
+    assert_eq!(OuterMutexPermission::get().to_string(), "Outer");
+}
+
+#[test]
+fn nested_permissions_name_the_mutex_they_are_inside() {
+    let stack = NetworkStack::new();
+    let (_ip, inside) = stack
+        .ip_layer
+        .lock_for_nested(OuterMutexPermission::get())
+        .guard();
+    assert_eq!(inside.to_string(), "Outer → IpLock");
+}
+
+#[test]
+fn deep_levels_go_by_number() {
+    struct L0;
+    struct L1;
+    struct L2;
+    struct L3;
+    struct L4;
+    struct L5;
+    struct L6;
+    struct L7;
+    struct L8;
+    struct L9;
+    lock_hierarchy!(OuterMutexPermission => L0, L1, L2, L3, L4, L5, L6, L7, L8, L9);
+
+    assert_eq!(levels::<Position<L0>>(), ["Outer"]);
+    assert_eq!(
+        levels::<Position<L3>>(),
+        ["Outer", "level 0", "level 1", "level 2"]
+    );
+}
+
+#[cfg(feature = "metrics-exporter")]
+mod exporter {
+    use std::sync::Mutex;
+
+    use deadlock_proof::{
+        metrics::{set_recorder, Recorder},
+        LockOutcome, NetworkStack, OuterMutexPermission,
+    };
+
+    #[derive(Default)]
+    struct Captured {
+        recorded: Mutex<Vec<(&'static str, &'static str)>>,
+    }
+
+    impl Recorder for Captured {
+        fn register_histogram(&self, _name: &'static str, _mutex: &'static str) {}
+
+        fn record_histogram(&self, _name: &'static str, _mutex: &'static str, _nanos: u64) {}
+
+        fn record_positioned_histogram(
+            &self,
+            _name: &'static str,
+            mutex: &'static str,
+            position: &'static str,
+            _nanos: u64,
+            _tag: Option<u64>,
+        ) {
+            self.recorded.lock().unwrap().push((mutex, position));
+        }
+    }
+
+    #[test]
+    fn waits_are_labelled_with_the_position() {
+        let captured: &'static Captured = Box::leak(Box::default());
+        assert!(set_recorder(captured).is_ok());
+
+        let stack = NetworkStack::new();
+        let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+        let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+        let transport = stack.transport_layer.lock(device.unlock_for_sequential()).guard();
+        transport.unlock();
+
+        assert_eq!(
+            *captured.recorded.lock().unwrap(),
+            [
+                ("ip-layer", "Outer"),
+                ("device-layer", "Outer → IpLock"),
+                ("transport-layer", "Outer → IpLock → DeviceLock"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use deadlock_proof::{
+    DeadlockProofMutex, DeviceLock, LockOutcome, NestedMutexPermission, NetworkStack,
+    OuterMutexPermission, PermissionDepth, PermissionWith, Position, SequentialMutexPermission,
+};
+
+/// What a request carries through the stack.
+struct Request {
+    id: u64,
+    trace: Vec<&'static str>,
+}
+
+struct QueueLock;
+struct SlotLock;
+
+/// What the mutexes inside the device layer lock with.
+type InsideDevice = NestedMutexPermission<Position<DeviceLock>, DeviceLock>;
+
+#[test]
+fn a_request_id_travels_the_whole_walk() {
+    let stack = NetworkStack::new();
+    // A queue inside the device layer, declared with the device layer's
+    // nested token and the request, and a slot inside the queue.
+    let queue: DeadlockProofMutex<u32, PermissionWith<InsideDevice, Request>, _> =
+        DeadlockProofMutex::new(0, QueueLock);
+    let slot: DeadlockProofMutex<u32, NestedMutexPermission<InsideDevice, QueueLock>, _> =
+        DeadlockProofMutex::new(0, SlotLock);
+
+    let request = Request {
+        id: 42,
+        trace: Vec::new(),
+    };
+    let permission = PermissionWith::new(OuterMutexPermission::get(), request);
+    let mut ip = stack.ip_layer.lock_in_context(permission).guard();
+    ip.packets_processed += 1;
+    ip.ctx_mut().trace.push("ip");
+
+    let (mut device, inside) = stack
+        .device_layer
+        .lock_for_nested_in_context(ip.unlock_for_sequential())
+        .guard();
+    device.interfaces_active += 1;
+    assert_eq!(inside.ctx().id, 42);
+    let (mut queued, in_queue) = queue.lock_for_nested_with_context(inside).guard();
+    *queued += 1;
+    assert_eq!(in_queue.ctx().id, 42);
+    let mut slotted = slot.lock_in_context(in_queue).guard();
+    *slotted += 1;
+    assert_eq!(slotted.ctx().id, 42);
+    slotted.ctx_mut().trace.push("slot");
+    let inside = queued.unlock_in_context(slotted.unlock());
+
+    let mut transport = stack
+        .transport_layer
+        .lock_in_context(device.unlock_for_sequential_in_context(inside))
+        .guard();
+    transport.icmp_errors_sent += 1;
+    transport.ctx_mut().trace.push("transport");
+
+    let (permission, request) = transport.unlock().into_parts();
+    assert_eq!(request.id, 42);
+    assert_eq!(request.trace, ["ip", "slot", "transport"]);
+    // The permission underneath is the one the walk started with.
+    let _root: OuterMutexPermission = permission.to_earlier().to_earlier();
+}
+
+#[test]
+fn mutexes_declared_with_a_context_take_it_everywhere() {
+    struct FirstLock;
+    struct SecondLock;
+    type Counted = PermissionWith<OuterMutexPermission, u32>;
+    let first: DeadlockProofMutex<&str, Counted, _> = DeadlockProofMutex::new("arp", FirstLock);
+    let second: DeadlockProofMutex<&str, SequentialMutexPermission<Counted, FirstLock>, _> =
+        DeadlockProofMutex::new("nd", SecondLock);
+    assert_eq!(<Counted as PermissionDepth>::DEPTH, 0);
+
+    let mut guard = first
+        .lock(PermissionWith::new(OuterMutexPermission::get(), 0))
+        .guard();
+    *guard.ctx_mut() += 1;
+    let guard = second.lock(guard.unlock_for_sequential()).guard();
+    assert_eq!(*guard, "nd");
+    let mut permission = guard.unlock().to_earlier();
+    *permission.ctx_mut() += 1;
+
+    let (mut guard, inside) = first.lock_for_nested(permission).guard();
+    assert_eq!(*guard, "arp");
+    *guard = "arp-v2";
+    let (_root, lookups): (OuterMutexPermission, _) = guard.unlock(inside).into_parts();
+    assert_eq!(lookups, 2);
+}
+
+#[test]
+fn a_nested_lock_keeps_the_context_in_its_guard() {
+    struct TableLock;
+    struct EntryLock;
+    type Traced = PermissionWith<OuterMutexPermission, Vec<&'static str>>;
+    let table: DeadlockProofMutex<u32, Traced, _> = DeadlockProofMutex::new(0, TableLock);
+    let entry: DeadlockProofMutex<u32, NestedMutexPermission<Traced, TableLock>, _> =
+        DeadlockProofMutex::new(0, EntryLock);
+
+    let permission = PermissionWith::new(OuterMutexPermission::get(), vec!["start"]);
+    let (mut table_guard, inside) = table.lock_for_nested(permission).guard();
+    table_guard.ctx_mut().push("table");
+    // The token for the entries is a bare nested permission.
+    let inside: NestedMutexPermission<Traced, TableLock> = inside;
+    let mut entry_guard = entry.lock(inside).guard();
+    *entry_guard += 1;
+    let inside = entry_guard.unlock();
+
+    assert_eq!(*table_guard.ctx(), ["start", "table"]);
+    let (_root, trace) = table_guard.unlock(inside).into_parts();
+    assert_eq!(trace, ["start", "table"]);
+}
@@ -0,0 +1,158 @@
+//! Every permission flow end to end. Flows that must not compile are checked
+//! by the `compile_fail` doctests next to the API they exercise.
+
+use std::{panic, thread};
+
+use deadlock_proof::{
+    concurrent, unique_type, DeadlockProofMutex, LockOutcome, NetworkStack, OuterMutexPermission,
+};
+
+#[test]
+fn single_mutex_lock_unlock_and_permission_reuse() {
+    let counter = DeadlockProofMutex::new(0u32, unique_type!());
+    let mut permission = OuterMutexPermission::get();
+    for _ in 0..3 {
+        let mut guard = counter.lock(permission).guard();
+        *guard += 1;
+        permission = guard.unlock();
+    }
+    // The same permission also locks a different mutex of the same level.
+    let other = DeadlockProofMutex::new("idle", unique_type!());
+    let permission = other.lock(permission).guard().unlock();
+    assert_eq!(*counter.lock(permission).guard(), 3);
+}
+
+#[test]
+fn three_level_nested_flow() {
+    let outer = DeadlockProofMutex::new(Vec::new(), unique_type!());
+    let middle = DeadlockProofMutex::new(Vec::new(), unique_type!());
+    let inner = DeadlockProofMutex::new(Vec::new(), unique_type!());
+
+    let (mut outer_guard, outer_token) = outer.lock_for_nested(OuterMutexPermission::get()).guard();
+    let (mut middle_guard, middle_token) = middle.lock_for_nested(outer_token).guard();
+    let mut inner_guard = inner.lock(middle_token).guard();
+    outer_guard.push("outer");
+    middle_guard.push("middle");
+    inner_guard.push("inner");
+
+    // Innermost first; each token goes back to the guard that handed it out.
+    let middle_token = inner_guard.unlock();
+    let outer_token = middle_guard.unlock(middle_token);
+    let permission = outer_guard.unlock(outer_token);
+
+    let (outer_guard, outer_token) = outer.lock_for_nested(permission).guard();
+    let (middle_guard, middle_token) = middle.lock_for_nested(outer_token).guard();
+    let inner_guard = inner.lock(middle_token).guard();
+    assert_eq!(*outer_guard, ["outer"]);
+    assert_eq!(*middle_guard, ["middle"]);
+    assert_eq!(*inner_guard, ["inner"]);
+}
+
+#[test]
+fn sequential_chain_and_back_with_to_earlier() {
+    let stack = NetworkStack::new();
+    let mut permission = OuterMutexPermission::get();
+    for round in 1..=2 {
+        let mut ip = stack.ip_layer.lock(permission).guard();
+        ip.packets_processed += 1;
+        let mut device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+        device.bytes_transmitted += 100;
+        let mut transport = stack
+            .transport_layer
+            .lock(device.unlock_for_sequential())
+            .guard();
+        transport.tcp_connections += 1;
+        assert_eq!(transport.tcp_connections, round);
+        permission = transport
+            .unlock_for_sequential()
+            .to_earlier()
+            .to_earlier()
+            .to_earlier();
+    }
+
+    // Stepping back one level relocks the level before.
+    let ip = stack.ip_layer.lock(permission).guard();
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    let ip = stack
+        .ip_layer
+        .lock(device.unlock_for_sequential().to_earlier().to_earlier());
+    assert_eq!(ip.guard().packets_processed, 2);
+}
+
+#[test]
+fn network_stack_walk_from_four_threads() {
+    const WALKS: u64 = 250;
+    let stack = NetworkStack::new();
+    let workers = (0..4)
+        .map(|_| -> concurrent::Worker<'_> {
+            let stack = &stack;
+            Box::new(move |mut permission| {
+                for _ in 0..WALKS {
+                    let mut ip = stack.ip_layer.lock(permission).guard();
+                    ip.packets_processed += 1;
+                    let mut device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+                    device.bytes_transmitted += 64;
+                    let mut transport = stack
+                        .transport_layer
+                        .lock(device.unlock_for_sequential())
+                        .guard();
+                    transport.udp_sockets += 1;
+                    permission = transport
+                        .unlock_for_sequential()
+                        .to_earlier()
+                        .to_earlier()
+                        .to_earlier();
+                }
+                permission
+            })
+        })
+        .collect();
+    concurrent::run_all(workers).unwrap();
+
+    let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(ip.packets_processed, 4 * WALKS);
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    assert_eq!(device.bytes_transmitted, 4 * WALKS * 64);
+    let transport = stack
+        .transport_layer
+        .lock(device.unlock_for_sequential())
+        .guard();
+    assert_eq!(u64::from(transport.udp_sockets), 4 * WALKS);
+}
+
+#[test]
+#[cfg(not(feature = "no-poison"))]
+fn panic_while_locked_poisons_the_mutex() {
+    let mutex = DeadlockProofMutex::new(1u32, unique_type!());
+    thread::scope(|scope| {
+        let panicked = scope
+            .spawn(|| {
+                let mut guard = mutex.lock(OuterMutexPermission::get()).guard();
+                *guard = 2;
+                panic!("panicking with the lock held");
+            })
+            .join();
+        assert!(panicked.is_err());
+    });
+
+    let error = mutex
+        .lock(OuterMutexPermission::get())
+        .into_result()
+        .err()
+        .expect("poisoned by the panic");
+    assert_eq!(*error.into_guard(), 2);
+}
+
+#[test]
+fn outer_permission_is_claimed_once_per_thread() {
+    let _permission = OuterMutexPermission::get();
+    let second = panic::catch_unwind(OuterMutexPermission::get);
+    assert!(second.is_err());
+
+    // Every other thread still gets its own.
+    thread::spawn(|| {
+        let _permission = OuterMutexPermission::get();
+    })
+    .join()
+    .unwrap();
+}
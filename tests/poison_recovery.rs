@@ -0,0 +1,55 @@
+//! Carrying on after a panic poisoned one mutex.
+#![cfg(not(feature = "no-poison"))]
+
+use std::thread;
+
+use deadlock_proof::{unique_type, DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+
+fn poison<T: Send, I: Sync>(mutex: &DeadlockProofMutex<T, OuterMutexPermission, I>) {
+    thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let _guard = mutex.lock(OuterMutexPermission::get());
+                panic!("poisoning on purpose");
+            })
+            .join()
+            .unwrap_err();
+    });
+}
+
+#[test]
+fn the_permission_comes_back_for_the_other_mutexes() {
+    let routes = DeadlockProofMutex::new(vec![10u32], unique_type!());
+    let neighbours = DeadlockProofMutex::new(0u32, unique_type!());
+    let counters = DeadlockProofMutex::new(0u32, unique_type!());
+    poison(&routes);
+
+    let error = routes.lock(OuterMutexPermission::get()).err().unwrap();
+    assert_eq!(error.to_string(), "deadlock-proof mutex is poisoned");
+    let permission = error.into_permission();
+    assert!(routes.is_poisoned());
+
+    let mut neighbours = neighbours.lock(permission).guard();
+    *neighbours += 1;
+    let permission = neighbours.unlock();
+    let (mut counters, _inside) = counters.lock_for_nested(permission).unwrap();
+    *counters += 1;
+}
+
+#[test]
+fn the_data_is_reached_through_the_usual_guard() {
+    let routes = DeadlockProofMutex::new(vec![10u32], unique_type!());
+    let counters = DeadlockProofMutex::new(0u32, unique_type!());
+    poison(&routes);
+
+    let mut error = routes
+        .lock_for_nested(OuterMutexPermission::get())
+        .err()
+        .unwrap();
+    error.get_mut().push(20);
+    assert_eq!(**error.get_ref(), [10, 20]);
+    // Repaired, but still poisoned: the guard unlocks like any other.
+    let permission = error.into_guard().unlock();
+    let permission = routes.lock(permission).err().unwrap().into_permission();
+    *counters.lock(permission).guard() += 1;
+}
@@ -0,0 +1,136 @@
+#![cfg(feature = "tracking")]
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread,
+};
+
+use deadlock_proof::{
+    poison_watch::{self, PoisonEvent},
+    DeadlockProofMutex, LockOutcome, OuterMutexPermission,
+};
+
+/// Everything the observer saw. Tests run in parallel, so each one only
+/// looks at the events of its own mutex.
+static EVENTS: Mutex<Vec<PoisonEvent>> = Mutex::new(Vec::new());
+
+fn events() -> MutexGuard<'static, Vec<PoisonEvent>> {
+    EVENTS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn record(event: PoisonEvent) {
+    events().push(event);
+}
+
+fn events_for(identifier: &str) -> Vec<PoisonEvent> {
+    events()
+        .iter()
+        .filter(|event| event.identifier.ends_with(identifier))
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn the_observer_hears_who_poisoned_what_and_where() {
+    struct RouteLock;
+    poison_watch::set_poison_observer(record);
+    let routes = DeadlockProofMutex::new(0u32, RouteLock);
+    let line = line!() + 5;
+    thread::scope(|scope| {
+        let writer = thread::Builder::new()
+            .name("route-writer".into())
+            .spawn_scoped(scope, || {
+                let _routes = routes.lock(OuterMutexPermission::get()).guard();
+                panic!("bad route");
+            })
+            .unwrap();
+        assert!(writer.join().is_err());
+    });
+
+    let events = events_for("::RouteLock");
+    let [event] = events.as_slice() else {
+        panic!("expected one poisoning, got {events:?}");
+    };
+    assert_eq!(event.thread_name.as_deref(), Some("route-writer"));
+    assert_eq!(event.locked_at.unwrap().line(), line);
+    assert!(event.to_string().contains("\"route-writer\""));
+}
+
+#[test]
+fn watchers_only_hear_about_their_own_mutex() {
+    struct Watched;
+    struct Unwatched;
+    let watched = DeadlockProofMutex::new((), Watched);
+    let unwatched = DeadlockProofMutex::new((), Unwatched);
+    let heard = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&heard);
+    watched.on_poison(move |event| sink.lock().unwrap().push(event.identifier));
+
+    for_each_poisoning(|| {
+        let _guard = unwatched.lock(OuterMutexPermission::get()).guard();
+        panic!("unwatched");
+    });
+    assert!(heard.lock().unwrap().is_empty());
+    for_each_poisoning(|| {
+        let _guard = watched.lock(OuterMutexPermission::get()).guard();
+        panic!("watched");
+    });
+    let heard = heard.lock().unwrap();
+    assert_eq!(heard.len(), 1);
+    assert!(heard[0].ends_with("::Watched"));
+}
+
+#[test]
+fn guards_that_do_not_unwind_report_nothing() {
+    struct Quiet;
+    poison_watch::set_poison_observer(record);
+    let quiet = DeadlockProofMutex::new(0u32, Quiet);
+    let mut guard = quiet.lock(OuterMutexPermission::get()).guard();
+    *guard += 1;
+    let mut permission = guard.unlock();
+    let _ = quiet.read_with(&mut permission, |value| *value);
+
+    // Released by the panic, but taken while the thread was panicking
+    // already, as from a destructor, which does not poison.
+    struct LockOnDrop<'a>(&'a DeadlockProofMutex<u32, OuterMutexPermission, Quiet>);
+    impl Drop for LockOnDrop<'_> {
+        fn drop(&mut self) {
+            let permission = OuterMutexPermission::get();
+            let _guard = self.0.lock(permission).guard();
+        }
+    }
+    for_each_poisoning(|| {
+        let _lock_on_drop = LockOnDrop(&quiet);
+        panic!("not under the lock");
+    });
+
+    assert!(events_for("::Quiet").is_empty());
+    #[cfg(not(feature = "no-poison"))]
+    assert!(quiet.lock(permission).is_ok());
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn poisonings_are_counted_per_mutex() {
+    struct Counted;
+    let counted = DeadlockProofMutex::new((), Counted);
+    let _permission = counted.lock(OuterMutexPermission::get()).guard().unlock();
+    assert_eq!(counted.poisoned_total(), 0);
+    for_each_poisoning(|| {
+        let _guard = counted.lock(OuterMutexPermission::get()).guard();
+        panic!("counted");
+    });
+    assert_eq!(counted.poisoned_total(), 1);
+}
+
+/// Runs `body`, which panics, on a thread of its own.
+fn for_each_poisoning(body: impl FnOnce() + Send) {
+    thread::scope(|scope| {
+        let body = AssertUnwindSafe(body);
+        assert!(scope
+            .spawn(move || panic::catch_unwind(body).is_err())
+            .join()
+            .unwrap());
+    });
+}
@@ -0,0 +1,80 @@
+//! A walk that finds one of its levels poisoned halfway through.
+#![cfg(not(feature = "no-poison"))]
+
+use std::{thread, time::Duration};
+
+use deadlock_proof::{LockOutcome, NetworkStack, OuterMutexPermission, TryLockError};
+
+/// Poisons the device layer the way it happens in the field: a thread
+/// panics halfway through its own walk.
+fn poison_device_layer(stack: &NetworkStack) {
+    thread::scope(|scope| {
+        let walker = scope.spawn(|| {
+            let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+            let mut device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+            device.interfaces_active += 1;
+            panic!("driver crashed mid-walk");
+        });
+        assert!(walker.join().is_err());
+    });
+    assert!(stack.device_layer.is_poisoned());
+}
+
+#[test]
+fn a_walk_steps_over_a_poisoned_level() {
+    let stack = NetworkStack::new();
+    poison_device_layer(&stack);
+
+    let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    let Err(TryLockError::Poisoned(past_ip)) = stack
+        .device_layer
+        .lock_or_return(ip.unlock_for_sequential())
+    else {
+        panic!("the poisoned device layer was locked");
+    };
+    let past_device = past_ip.skip_poisoned(&stack.device_layer).ok().unwrap();
+    let mut transport = stack.transport_layer.lock(past_device).guard();
+    transport.icmp_errors_sent += 1;
+    assert_eq!(transport.icmp_errors_sent, 1);
+}
+
+#[test]
+fn a_walk_retreats_from_a_poisoned_level() {
+    let stack = NetworkStack::new();
+    poison_device_layer(&stack);
+
+    let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    let past_ip = stack
+        .device_layer
+        .lock_or_return(ip.unlock_for_sequential())
+        .err()
+        .unwrap()
+        .into_permission();
+    // Back to the start, to undo what was done before the broken level.
+    let mut ip = stack.ip_layer.lock(past_ip.to_earlier()).guard();
+    ip.packets_processed += 1;
+
+    // The timed and non-blocking attempts hand the permission back too.
+    let past_ip = ip.unlock_for_sequential();
+    let handle = stack.device_layer.rt_handle();
+    let Err(TryLockError::Poisoned(past_ip)) = handle.try_lock(past_ip) else {
+        panic!("the poisoned device layer was locked");
+    };
+    let Err(TryLockError::Poisoned(past_ip)) =
+        handle.try_lock_for(past_ip, Duration::from_millis(5))
+    else {
+        panic!("the poisoned device layer was locked");
+    };
+    let ip = stack.ip_layer.lock(past_ip.to_earlier()).guard();
+    assert_eq!(ip.packets_processed, 1);
+}
+
+#[test]
+fn healthy_levels_are_not_skipped() {
+    let stack = NetworkStack::new();
+    let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    let past_ip = ip.unlock_for_sequential();
+    let past_ip = past_ip.skip_poisoned(&stack.device_layer).err().unwrap();
+    let device = stack.device_layer.lock_or_return(past_ip).ok().unwrap();
+    assert_eq!(device.interfaces_active, 0);
+}
@@ -0,0 +1,75 @@
+//! Behaviour of poisoned locks, which only exists without `no-poison`.
+#![cfg(all(feature = "test-util", not(feature = "no-poison")))]
+
+use std::{sync::atomic::Ordering, thread};
+
+use deadlock_proof::{
+    unique_type, DeadlockProofMutex, DeviceLock, DeviceState, LockOutcome, NetworkStack,
+    OuterMutexPermission, PinnedLockError, SignalSafe, StackLayer, ThreadPinnedMutex, TxAborted,
+};
+
+#[test]
+fn poisoned_mutex_fails_both_lock_paths() {
+    let mutex = DeadlockProofMutex::new(7u32, unique_type!());
+    mutex.poison_for_test();
+
+    let error = mutex.lock(OuterMutexPermission::get()).err().unwrap();
+    assert_eq!(*error.into_guard(), 7);
+    thread::scope(|scope| {
+        scope.spawn(|| assert!(mutex.lock_for_nested(OuterMutexPermission::get()).is_err()));
+    });
+}
+
+#[test]
+fn transaction_aborts_on_poisoned_layer() {
+    let stack = NetworkStack::new();
+    stack.device_layer.poison_for_test();
+
+    let (permission, result) = stack
+        .transaction()
+        .stage_ip(|ip| {
+            ip.packets_processed += 1;
+            Ok(())
+        })
+        .commit(OuterMutexPermission::get());
+    assert_eq!(result, Err(TxAborted::Poisoned(StackLayer::Device)));
+    assert_eq!(stack.ip_layer.lock(permission).guard().packets_processed, 0);
+}
+
+#[test]
+#[should_panic]
+fn fork_panics_on_poisoned_layer() {
+    let stack = NetworkStack::new();
+    stack.transport_layer.poison_for_test();
+    let mut walk = stack.begin_walk(OuterMutexPermission::get());
+    walk.fork::<deadlock_proof::RootNamespace>();
+}
+
+fn fold_bytes(state: &mut DeviceState, pending: &SignalSafe<u64>) {
+    state.bytes_transmitted += pending.swap(0, Ordering::Relaxed);
+}
+
+#[test]
+fn poisoned_signal_safe_mutex_keeps_pending_updates() {
+    let state = DeviceState {
+        interfaces_active: 1,
+        bytes_transmitted: 0,
+    };
+    let device = DeadlockProofMutex::new(state, DeviceLock).with_signal_safe(0u64, fold_bytes);
+    device.signal_safe().fetch_add(64, Ordering::Relaxed);
+    device.poison_for_test();
+
+    assert!(device.lock(OuterMutexPermission::get()).is_err());
+    assert_eq!(device.signal_safe().load(Ordering::Relaxed), 64);
+}
+
+#[test]
+fn pinned_mutex_reports_poison_separately_from_wrong_thread() {
+    let permission = OuterMutexPermission::get();
+    let mutex = ThreadPinnedMutex::new(0u32, unique_type!(), &permission);
+    mutex.poison_for_test();
+    assert!(matches!(
+        mutex.lock(permission),
+        Err(PinnedLockError::Poisoned(_))
+    ));
+}
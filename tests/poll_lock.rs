@@ -0,0 +1,114 @@
+#[cfg(feature = "tracking")]
+use std::time::{Duration, Instant};
+use std::{sync::mpsc, task::Poll, thread};
+
+use deadlock_proof::{
+    task::{AsyncPermission, AsyncPermissionSlot},
+    DeadlockProofMutex, LockOutcome,
+};
+
+struct CounterLock;
+
+fn permission() -> Option<AsyncPermission> {
+    AsyncPermissionSlot::new().claim()
+}
+
+#[cfg(feature = "tracking")]
+fn wait_for(condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn tasks_on_one_thread_take_turns() {
+    let counter = DeadlockProofMutex::new(0u32, CounterLock);
+    let (mut first, mut second) = (permission(), permission());
+
+    // The first task gets the lock and keeps it for a few rounds of the loop.
+    let Poll::Ready(mut held) = counter.poll_lock(&mut first).poll() else {
+        panic!("the counter is free");
+    };
+    assert!(first.is_none());
+    let mut polling = counter.poll_lock(&mut second);
+    for _ in 0..3 {
+        assert!(polling.poll().is_pending());
+        *held += 1;
+    }
+    #[cfg(feature = "tracking")]
+    assert_eq!(counter.waiters(), 1);
+    first = Some(held.unlock());
+
+    let Poll::Ready(mut guard) = polling.poll() else {
+        panic!("the first task let go");
+    };
+    drop(polling);
+    #[cfg(feature = "tracking")]
+    assert_eq!(counter.waiters(), 0);
+    *guard += 1;
+    second = Some(guard.unlock());
+    assert!(first.is_some() && second.is_some());
+    assert_eq!(*counter.lock(first.unwrap()).guard(), 4);
+}
+
+#[test]
+fn abandoned_poll_keeps_the_permission() {
+    let counter = DeadlockProofMutex::new(0u32, CounterLock);
+    let (locked, locked_rx) = mpsc::channel();
+    let (release, release_rx) = mpsc::channel::<()>();
+    let mut slot = permission();
+
+    let counter = &counter;
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let _guard = counter.lock(permission().unwrap()).guard();
+            locked.send(()).unwrap();
+            release_rx.recv().unwrap();
+        });
+        locked_rx.recv().unwrap();
+
+        let mut polling = counter.poll_lock(&mut slot);
+        assert!(polling.poll().is_pending());
+        assert!(polling.poll().is_pending());
+        drop(polling);
+        release.send(()).unwrap();
+    });
+
+    assert!(slot.is_some());
+    #[cfg(feature = "tracking")]
+    assert_eq!(counter.waiters(), 0);
+    let Poll::Ready(guard) = counter.poll_lock(&mut slot).poll() else {
+        panic!("the thread let go");
+    };
+    guard.unlock();
+}
+
+#[cfg(feature = "tracking")]
+#[test]
+fn others_waiting_counts_blocked_threads() {
+    let counter = DeadlockProofMutex::new(0u32, CounterLock);
+    let mut first = permission();
+    let Poll::Ready(held) = counter.poll_lock(&mut first).poll() else {
+        panic!("the counter is free");
+    };
+
+    let mut slot = permission();
+    thread::scope(|scope| {
+        let blocked = scope.spawn(|| *counter.lock(permission().unwrap()).guard() += 1);
+        wait_for(|| counter.waiters() == 1);
+
+        let mut polling = counter.poll_lock(&mut slot);
+        assert_eq!(polling.others_waiting(), 1);
+        assert!(polling.poll().is_pending());
+        assert_eq!(counter.waiters(), 2);
+        assert_eq!(polling.others_waiting(), 1);
+        drop(polling);
+
+        held.unlock();
+        blocked.join().unwrap();
+    });
+    assert_eq!(counter.waiters(), 0);
+    assert!(slot.is_some());
+}
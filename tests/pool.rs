@@ -0,0 +1,113 @@
+use std::{
+    mem,
+    sync::{mpsc, Arc, Barrier},
+    thread,
+    time::Duration,
+};
+
+use deadlock_proof::{
+    pool::{RetireReason, WorkerPool},
+    unique_type, DeadlockProofMutex, LockOutcome, NetworkStack, OuterMutexPermission,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Queues two jobs that can only finish while both run at once, and waits for
+/// them.
+fn assert_two_workers_serve(pool: &WorkerPool) {
+    let both = Arc::new(Barrier::new(2));
+    let (done, finished) = mpsc::channel();
+    for _ in 0..2 {
+        let (both, done) = (both.clone(), done.clone());
+        pool.submit("rendezvous", move |_| {
+            both.wait();
+            done.send(()).unwrap();
+        });
+    }
+    for _ in 0..2 {
+        finished
+            .recv_timeout(TIMEOUT)
+            .expect("the pool lost a worker");
+    }
+}
+
+#[test]
+fn jobs_returning_their_lease_keep_their_worker() {
+    let stack = Arc::new(NetworkStack::new());
+    let pool = WorkerPool::new(3, TIMEOUT);
+    let (done, finished) = mpsc::channel();
+    for _ in 0..100 {
+        let (stack, done) = (stack.clone(), done.clone());
+        pool.submit("count", move |lease| {
+            lease.with_permission(|permission| {
+                let mut ip = stack.ip_layer.lock(permission).guard();
+                ip.packets_processed += 1;
+                (ip.unlock(), ())
+            });
+            done.send(()).unwrap();
+        });
+    }
+    for _ in 0..100 {
+        finished.recv_timeout(TIMEOUT).unwrap();
+    }
+    drop(pool);
+
+    // Dropping the pool joined every worker.
+    let stack = Arc::into_inner(stack).unwrap();
+    let permission = OuterMutexPermission::get();
+    assert_eq!(
+        stack.ip_layer.lock(permission).guard().packets_processed,
+        100
+    );
+}
+
+#[test]
+fn job_leaking_its_permission_is_retired_and_replaced() {
+    let pool = WorkerPool::new(2, Duration::from_millis(50));
+    let leaked = Arc::new(DeadlockProofMutex::new(0u32, unique_type!()));
+    let submitted = line!() + 1;
+    pool.submit("leaky", move |lease| {
+        lease.with_permission(|permission| {
+            mem::forget(leaked.lock(permission).guard());
+            // Without the permission there is nothing to hand back.
+            loop {
+                thread::park();
+            }
+        })
+    });
+
+    let started = std::time::Instant::now();
+    while pool.retirements().is_empty() {
+        assert!(started.elapsed() < TIMEOUT, "the leak was never noticed");
+        thread::sleep(Duration::from_millis(5));
+    }
+    let retirements = pool.retirements();
+    assert_eq!(retirements.len(), 1);
+    assert_eq!(retirements[0].job, "leaky");
+    assert_eq!(retirements[0].reason, RetireReason::LeaseExpired);
+    assert_eq!(retirements[0].submitted_at.file(), file!());
+    assert_eq!(retirements[0].submitted_at.line(), submitted);
+
+    assert_eq!(pool.capacity(), 2);
+    assert_two_workers_serve(&pool);
+}
+
+#[test]
+fn panicking_job_is_retired_and_replaced() {
+    let pool = WorkerPool::new(2, TIMEOUT);
+    for _ in 0..2 {
+        pool.submit("doomed", |lease| {
+            lease.with_permission(|_permission| panic!("dropped on the floor"))
+        });
+    }
+    assert_two_workers_serve(&pool);
+
+    let retirements = pool.retirements();
+    assert_eq!(retirements.len(), 2);
+    assert!(retirements
+        .iter()
+        .all(|retirement| retirement.reason == RetireReason::Panicked));
+    // Replacements are numbered after the original workers.
+    assert_eq!(pool.capacity(), 2);
+    assert!(retirements.iter().all(|retirement| retirement.worker < 4));
+}
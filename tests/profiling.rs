@@ -0,0 +1,136 @@
+use deadlock_proof::{DeadlockProofMutex, LockOutcome, NetworkStack, OuterMutexPermission};
+
+struct StatsLock;
+
+#[test]
+fn scoped_lock_behaves_like_lock() {
+    let stats = DeadlockProofMutex::<u32, OuterMutexPermission, _>::new(0, StatsLock);
+    let mut guard = stats
+        .lock_scoped(OuterMutexPermission::get(), "bump")
+        .guard();
+    *guard += 1;
+    #[cfg(feature = "tracking")]
+    let version = stats.version();
+    let permission = guard.unlock_for_sequential().to_earlier();
+    #[cfg(feature = "tracking")]
+    assert!(stats.version() > version);
+    assert_eq!(*stats.lock(permission).guard(), 1);
+}
+
+#[cfg(feature = "profiling")]
+mod backend {
+    use std::{sync::Mutex, thread};
+
+    use deadlock_proof::{
+        profiling::{scope_name, set_profiler, Profiler},
+        DeadlockProofMutex, IpLock, LockOutcome, NetworkStack, OuterMutexPermission,
+    };
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Begin(&'static str),
+        End(&'static str),
+    }
+
+    /// Records every scope, tagged with the thread it was on.
+    #[derive(Default)]
+    struct Captured(Mutex<Vec<(thread::ThreadId, Event)>>);
+
+    impl Captured {
+        fn events_on_this_thread(&self) -> Vec<Event> {
+            let me = thread::current().id();
+            let mut events = self.0.lock().unwrap();
+            let (mine, others) = events.drain(..).partition(|(thread, _)| *thread == me);
+            *events = others;
+            mine.into_iter().map(|(_, event)| event).collect()
+        }
+    }
+
+    impl Profiler for Captured {
+        fn begin_scope(&self, name: &'static str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((thread::current().id(), Event::Begin(name)));
+        }
+
+        fn end_scope(&self, name: &'static str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((thread::current().id(), Event::End(name)));
+        }
+    }
+
+    fn captured() -> &'static Captured {
+        static CAPTURED: std::sync::OnceLock<&'static Captured> = std::sync::OnceLock::new();
+        CAPTURED.get_or_init(|| {
+            let captured: &'static Captured = Box::leak(Box::default());
+            assert!(set_profiler(captured).is_ok());
+            captured
+        })
+    }
+
+    #[test]
+    fn names_combine_identifier_and_scope() {
+        let name = scope_name::<IpLock>("route");
+        assert_eq!(name, format!("{}::route", std::any::type_name::<IpLock>()));
+        assert!(name.ends_with("IpLock::route"));
+        // Built once, then handed out again.
+        assert!(std::ptr::eq(name, scope_name::<IpLock>("route")));
+        assert_ne!(name, scope_name::<IpLock>("forward"));
+    }
+
+    #[test]
+    fn scope_spans_the_critical_section() {
+        let captured = captured();
+        let stack = NetworkStack::new();
+        let name = stack.ip_layer.scope_name("count");
+        assert_eq!(name, "ip-layer::count");
+
+        let mut ip = stack
+            .ip_layer
+            .lock_scoped(OuterMutexPermission::get(), "count")
+            .guard();
+        ip.packets_processed += 1;
+        assert_eq!(captured.events_on_this_thread(), [Event::Begin(name)]);
+        let permission = ip.unlock();
+        assert_eq!(captured.events_on_this_thread(), [Event::End(name)]);
+
+        // Plain locks report nothing.
+        stack.ip_layer.lock(permission).guard().unlock();
+        assert_eq!(captured.events_on_this_thread(), []);
+    }
+
+    #[test]
+    fn dropped_guard_closes_its_scope() {
+        let captured = captured();
+        let mutex = DeadlockProofMutex::<(), OuterMutexPermission, _>::new((), super::StatsLock);
+        drop(
+            mutex
+                .lock_scoped(OuterMutexPermission::get(), "drop")
+                .guard(),
+        );
+
+        let name = scope_name::<super::StatsLock>("drop");
+        assert_eq!(
+            captured.events_on_this_thread(),
+            [Event::Begin(name), Event::End(name)]
+        );
+    }
+}
+
+#[test]
+fn scoped_walk_over_the_stack() {
+    let stack = NetworkStack::new();
+    let ip = stack
+        .ip_layer
+        .lock_scoped(OuterMutexPermission::get(), "walk")
+        .guard();
+    let device = stack
+        .device_layer
+        .lock_scoped(ip.unlock_for_sequential(), "walk")
+        .guard();
+    let permission = device.unlock().to_earlier();
+    stack.ip_layer.lock(permission).guard().unlock();
+}
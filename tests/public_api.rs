@@ -0,0 +1,61 @@
+//! Uses the crate the way a downstream user would: only through paths under
+//! `deadlock_proof`, with no helper traits imported for the macros' sake.
+
+use deadlock_proof::{
+    declare_namespace, lock_hierarchy, locks, DeadlockProofMutex, LockOutcome, Position,
+};
+
+struct ConfigLock;
+struct StatsLock;
+
+lock_hierarchy!(deadlock_proof::OuterMutexPermission => ConfigLock, StatsLock);
+
+struct Service {
+    config: DeadlockProofMutex<u32, Position<ConfigLock>, ConfigLock>,
+    stats: DeadlockProofMutex<u64, Position<StatsLock>, StatsLock>,
+}
+
+impl Service {
+    fn new() -> Self {
+        Self {
+            config: DeadlockProofMutex::new(3, ConfigLock),
+            stats: DeadlockProofMutex::new(0, StatsLock),
+        }
+    }
+}
+
+#[locks(order(ConfigLock, StatsLock))]
+fn record(service: &Service) -> u64 {
+    let config = lock!(ConfigLock, service.config);
+    let weight = u64::from(*config);
+    unlock!(config);
+    let mut stats = lock!(StatsLock, service.stats);
+    *stats += weight;
+    let total = *stats;
+    unlock!(stats);
+    total
+}
+
+declare_namespace!(Sandbox);
+
+#[test]
+fn downstream_hierarchy_and_locks_attribute() {
+    let service = Service::new();
+    let permission = deadlock_proof::OuterMutexPermission::get();
+    let (permission, total) = record(&service, permission);
+    assert_eq!(total, 3);
+    let (_permission, total) = record(&service, permission);
+    assert_eq!(total, 6);
+}
+
+#[test]
+fn downstream_namespace_forks_the_stack() {
+    let stack = deadlock_proof::NetworkStack::new();
+    let mut walk = stack.begin_walk(deadlock_proof::OuterMutexPermission::get());
+    let fork = walk.fork::<Sandbox>();
+    let permission = walk.finish();
+    let mut ip = fork.ip_layer.lock(permission).guard();
+    ip.packets_processed += 1;
+    let permission = ip.unlock();
+    assert_eq!(stack.ip_layer.lock(permission).guard().packets_processed, 0);
+}
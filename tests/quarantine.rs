@@ -0,0 +1,78 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    thread,
+};
+
+use deadlock_proof::{
+    permission, ClaimError, DeadlockProofMutex, LockOutcome, OuterMutexPermission,
+};
+
+struct RegistryLock;
+struct PluginLock;
+
+/// Runs `body` on a fresh thread, whose token is still unclaimed.
+fn on_fresh_thread(body: impl FnOnce() + Send) {
+    thread::scope(|scope| scope.spawn(body).join()).unwrap();
+}
+
+#[test]
+fn plugins_called_in_quarantine_cannot_lock() {
+    let registry: DeadlockProofMutex<Vec<&str>, OuterMutexPermission, _> =
+        DeadlockProofMutex::new(vec!["echo"], RegistryLock);
+    let plugin_state: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+        DeadlockProofMutex::new(0, PluginLock);
+    // What a misbehaving plugin would try.
+    let plugin = || {
+        let locked = plugin_state.lock_autoclaim().map(|result| *result.guard());
+        let claimed = OuterMutexPermission::try_get().is_some();
+        (locked, claimed)
+    };
+
+    on_fresh_thread(|| {
+        let guard = registry.lock(OuterMutexPermission::get()).guard();
+        let (locked, claimed) = permission::quarantine(|_token| {
+            assert_eq!(guard.len(), 1);
+            plugin()
+        });
+        assert!(matches!(locked, Err(ClaimError::Quarantined(_))));
+        assert!(!claimed);
+        guard.unlock();
+    });
+
+    on_fresh_thread(|| {
+        // Even with the token unclaimed, nothing is claimed inside.
+        let diagnostics = permission::quarantine(|_token| {
+            assert!(matches!(
+                plugin_state.lock_without_permission(),
+                Err(ClaimError::Quarantined(_))
+            ));
+            OuterMutexPermission::get_or_diagnose().err().unwrap()
+        });
+        assert!(diagnostics.quarantined);
+        assert!(diagnostics.to_string().contains("quarantine"));
+        assert!(matches!(plugin(), (Ok(0), true)));
+    });
+}
+
+#[test]
+fn a_panic_ends_the_quarantine() {
+    struct CountLock;
+    let count: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+        DeadlockProofMutex::new(0, CountLock);
+
+    on_fresh_thread(|| {
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            permission::quarantine(|_outer| {
+                permission::quarantine(|_inner| {});
+                // Still inside the outer one.
+                assert!(count.lock_autoclaim().is_err());
+                permission::quarantine(|_inner| panic!("plugin crashed"))
+            })
+        }));
+        assert!(outcome.is_err());
+
+        *count.lock_autoclaim().unwrap().guard() += 1;
+        let permission = OuterMutexPermission::get();
+        assert_eq!(*count.lock(permission).guard(), 1);
+    });
+}
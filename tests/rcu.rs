@@ -0,0 +1,67 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use deadlock_proof::{unique_type, DeadlockProofMutex, OuterMutexPermission};
+
+/// A table whose rows all carry the table's version, so a reader seeing mixed
+/// versions would have observed a partially written table.
+struct Table {
+    version: u64,
+    rows: Vec<u64>,
+}
+
+impl Table {
+    fn new(version: u64) -> Arc<Self> {
+        Arc::new(Self {
+            version,
+            rows: vec![version; 256],
+        })
+    }
+}
+
+#[test]
+fn swap_returns_previous_and_clone_sees_new() {
+    let mutex = DeadlockProofMutex::new(Table::new(0), unique_type!());
+    let (permission, before) = mutex.clone_inner(OuterMutexPermission::get());
+    let (permission, previous) = mutex.swap_inner(permission, Table::new(1));
+    assert!(Arc::ptr_eq(&before, &previous));
+
+    let (_permission, after) = mutex.clone_inner(permission);
+    assert_eq!(after.version, 1);
+    assert_eq!(before.version, 0);
+}
+
+#[test]
+fn readers_only_see_complete_tables() {
+    const SWAPS: u64 = 500;
+    let mutex = DeadlockProofMutex::new(Table::new(0), unique_type!());
+    let done = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        for _ in 0..3 {
+            scope.spawn(|| {
+                let mut permission = OuterMutexPermission::get();
+                let mut last_seen = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let table;
+                    (permission, table) = mutex.clone_inner(permission);
+                    // Read without holding the lock.
+                    assert!(table.rows.iter().all(|&row| row == table.version));
+                    assert!(table.version >= last_seen);
+                    last_seen = table.version;
+                }
+            });
+        }
+
+        let mut permission = OuterMutexPermission::get();
+        for version in 1..=SWAPS {
+            (permission, _) = mutex.swap_inner(permission, Table::new(version));
+        }
+        done.store(true, Ordering::Relaxed);
+    });
+}
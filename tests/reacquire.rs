@@ -0,0 +1,85 @@
+use std::thread;
+
+use deadlock_proof::{
+    lock_hierarchy, DeadlockProofMutex, DeviceLock, IpLock, LockOutcome, NetworkStack,
+    OuterMutexPermission, Position,
+};
+
+struct LinkConfigLock;
+struct RouteAuditLock;
+lock_hierarchy!(within DeviceLock => LinkConfigLock);
+lock_hierarchy!(within IpLock => RouteAuditLock);
+
+#[test]
+fn device_is_reacquired_to_update_what_is_inside_it() {
+    let stack = NetworkStack::new();
+    let link_mtu =
+        DeadlockProofMutex::<_, Position<LinkConfigLock>, _>::new(1500u32, LinkConfigLock);
+
+    let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    let mut device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    device.interfaces_active = 1;
+    let at_transport = device.unlock_for_sequential();
+
+    // Halfway to the transport layer, the device and its link config turn
+    // out to need changing together.
+    let (mut device, inside) = at_transport.reacquire_nested(&stack.device_layer).guard();
+    let mut mtu = link_mtu.lock(inside).guard();
+    *mtu = 9000;
+    device.interfaces_active += 1;
+    let at_device = device.unlock(mtu.unlock());
+
+    let (device, inside) = stack.device_layer.lock_for_nested(at_device).guard();
+    assert_eq!(device.interfaces_active, 2);
+    let mtu = link_mtu.lock(inside).guard();
+    assert_eq!(*mtu, 9000);
+    let _permission: OuterMutexPermission = device.unlock(mtu.unlock()).to_earlier();
+}
+
+#[test]
+fn reacquiring_and_walking_threads_do_not_deadlock() {
+    let stack = NetworkStack::new();
+    let audited = DeadlockProofMutex::<_, Position<RouteAuditLock>, _>::new(0u64, RouteAuditLock);
+    const ROUNDS: u64 = 1000;
+
+    thread::scope(|scope| {
+        for reacquire in [true, false, true, false] {
+            let (stack, audited) = (&stack, &audited);
+            scope.spawn(move || {
+                let mut permission = OuterMutexPermission::get();
+                for _ in 0..ROUNDS {
+                    let ip = stack.ip_layer.lock(permission).guard();
+                    let mut device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+                    device.bytes_transmitted += 1;
+                    let at_transport = device.unlock_for_sequential();
+                    permission = if reacquire {
+                        // Two levels back, past the device layer.
+                        let (mut ip, inside) =
+                            at_transport.reacquire_nested(&stack.ip_layer).guard();
+                        let mut audited = audited.lock(inside).guard();
+                        ip.packets_processed += 1;
+                        *audited += 1;
+                        thread::yield_now();
+                        ip.unlock(audited.unlock())
+                    } else {
+                        let mut transport = stack.transport_layer.lock(at_transport).guard();
+                        transport.tcp_connections += 1;
+                        thread::yield_now();
+                        transport.unlock().to_earlier().to_earlier()
+                    };
+                }
+            });
+        }
+    });
+
+    let (ip, inside) = stack
+        .ip_layer
+        .lock_for_nested(OuterMutexPermission::get())
+        .guard();
+    let audited = audited.lock(inside).guard();
+    // Every reacquisition updated the IP layer and its audit together.
+    assert_eq!((ip.packets_processed, *audited), (2 * ROUNDS, 2 * ROUNDS));
+    let ip = stack.ip_layer.lock(ip.unlock(audited.unlock())).guard();
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    assert_eq!(device.bytes_transmitted, 4 * ROUNDS);
+}
@@ -0,0 +1,105 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Barrier,
+    },
+    thread,
+};
+
+use deadlock_proof::{
+    poison, DeadlockProofMutex, DeadlockProofRwLock, LockOutcome, NetworkStack,
+    OuterMutexPermission,
+};
+
+#[test]
+fn reads_leave_the_permission_and_the_version_alone() {
+    let stack = NetworkStack::new();
+    let mut permission = OuterMutexPermission::get();
+    #[cfg(feature = "tracking")]
+    let before = stack.ip_layer.version();
+    for _ in 0..3 {
+        let packets = stack
+            .ip_layer
+            .read_with(&mut permission, |ip| ip.packets_processed);
+        assert_eq!(poison::into_inner(packets), 0);
+    }
+    #[cfg(feature = "tracking")]
+    assert_eq!(stack.ip_layer.version(), before);
+    assert!(!stack.ip_layer.is_locked());
+
+    let mut ip = stack.ip_layer.lock(permission).guard();
+    ip.packets_processed += 1;
+}
+
+#[test]
+fn reads_never_see_a_write_half_done() {
+    const WRITES: u64 = 500;
+    let pair = DeadlockProofMutex::new((0u64, 0u64), ());
+    let done = AtomicBool::new(false);
+    let start = Barrier::new(3);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut permission = OuterMutexPermission::get();
+            start.wait();
+            for _ in 0..WRITES {
+                let mut pair = pair.lock(permission).guard();
+                pair.0 += 1;
+                thread::yield_now();
+                pair.1 += 1;
+                permission = pair.unlock();
+            }
+            done.store(true, Ordering::Release);
+        });
+        for _ in 0..2 {
+            scope.spawn(|| {
+                let mut permission = OuterMutexPermission::get();
+                start.wait();
+                while !done.load(Ordering::Acquire) {
+                    let read = pair.read_with(&mut permission, |pair| *pair);
+                    let (first, second) = poison::into_inner(read);
+                    assert_eq!(first, second);
+                }
+            });
+        }
+    });
+    let mut permission = OuterMutexPermission::get();
+    let writes = pair.read_with(&mut permission, |pair| pair.0);
+    assert_eq!(poison::into_inner(writes), WRITES);
+}
+
+#[test]
+fn rwlock_readers_share_the_lock() {
+    let table = DeadlockProofRwLock::<_, OuterMutexPermission, _>::new(vec![1u32, 2, 3], ());
+    let inside = Barrier::new(2);
+    thread::scope(|scope| {
+        for _ in 0..2 {
+            scope.spawn(|| {
+                let mut permission = OuterMutexPermission::get();
+                let sum = table.read_with(&mut permission, |table| {
+                    // Both readers get here at once, or neither would.
+                    inside.wait();
+                    table.iter().sum::<u32>()
+                });
+                assert_eq!(poison::into_inner(sum), 6);
+            });
+        }
+    });
+}
+
+#[cfg(not(feature = "no-poison"))]
+#[test]
+fn poisoned_reads_come_back_as_the_error() {
+    let mutex = DeadlockProofMutex::new(7u32, ());
+    thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let _guard = mutex.lock(OuterMutexPermission::get()).guard();
+                panic!("poisoning on purpose");
+            })
+            .join()
+            .unwrap_err();
+    });
+    let mut permission = OuterMutexPermission::get();
+    let read = mutex.read_with(&mut permission, |value| *value + 1);
+    assert_eq!(read.unwrap_err().into_inner(), 8);
+}
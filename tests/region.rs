@@ -0,0 +1,83 @@
+use deadlock_proof::{
+    concurrent,
+    region::{with_region, RegionGuard},
+    After, DeviceLock, DeviceState, IpLock, IpState, LockOutcome, NetworkStack,
+    OuterMutexPermission, Position,
+};
+
+/// Per-request context carrying guards from two different borrows under one
+/// lifetime.
+struct Ctx<'r> {
+    ip: RegionGuard<'r, IpState, Position<IpLock>, IpLock>,
+    requests: u32,
+}
+
+impl Ctx<'_> {
+    fn record(&mut self) {
+        self.ip.packets_processed += 1;
+        self.requests += 1;
+    }
+}
+
+trait Handler {
+    fn handle(&mut self);
+}
+
+impl Handler for Ctx<'_> {
+    fn handle(&mut self) {
+        self.record();
+        self.record();
+    }
+}
+
+#[test]
+fn context_struct_holds_guard_for_the_request() {
+    let stack = NetworkStack::new();
+    let permission = with_region(|region| {
+        let mut ctx = Ctx {
+            ip: region.hold(stack.ip_layer.lock(OuterMutexPermission::get()).guard()),
+            requests: 0,
+        };
+        ctx.handle();
+        assert_eq!(ctx.requests, 2);
+        ctx.ip.unlock()
+    });
+
+    let ip = stack.ip_layer.lock(permission).guard();
+    assert_eq!(ip.packets_processed, 2);
+}
+
+#[test]
+fn sequential_walk_inside_a_region() {
+    let stack = NetworkStack::new();
+    let permission = with_region(|region| {
+        let ip = region.hold(stack.ip_layer.lock(OuterMutexPermission::get()).guard());
+        let mut device: RegionGuard<'_, DeviceState, After<IpLock>, DeviceLock> =
+            region.hold(stack.device_layer.lock(ip.unlock_for_sequential()).guard());
+        device.bytes_transmitted += 64;
+        device.unlock_for_sequential().to_earlier().to_earlier()
+    });
+    assert!(stack.ip_layer.lock(permission).into_result().is_ok());
+}
+
+#[test]
+fn regions_on_other_threads_are_independent() {
+    let stack = NetworkStack::new();
+    let workers = (0..4)
+        .map(|_| -> concurrent::Worker<'_> {
+            Box::new(|mut permission| {
+                for _ in 0..100 {
+                    permission = with_region(|region| {
+                        let mut ip = region.hold(stack.ip_layer.lock(permission).guard());
+                        ip.packets_processed += 1;
+                        ip.unlock()
+                    });
+                }
+                permission
+            })
+        })
+        .collect();
+    concurrent::run_all(workers).unwrap();
+    let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(ip.packets_processed, 400);
+}
@@ -0,0 +1,127 @@
+#![cfg(debug_assertions)]
+
+use std::panic::{self, AssertUnwindSafe};
+
+use deadlock_proof::{
+    DeadlockProofMutex, IcmpError, LockOutcome, NetworkStack, OuterMutexPermission,
+};
+
+struct BudgetLock;
+
+fn non_negative(left: &i32) -> Result<(), String> {
+    if *left >= 0 {
+        Ok(())
+    } else {
+        Err(format!("{left} left"))
+    }
+}
+
+/// The panic message of `f`, which must panic.
+fn panic_message(f: impl FnOnce()) -> String {
+    let payload = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_err();
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast::<&str>().unwrap().to_string(),
+    }
+}
+
+#[test]
+fn violation_names_the_lock_and_where_it_was_locked() {
+    let budget = DeadlockProofMutex::new(10, BudgetLock);
+    budget.set_release_invariant(non_negative);
+
+    let mut line = 0;
+    let message = panic_message(|| {
+        line = line!() + 1;
+        let mut left = budget.lock(OuterMutexPermission::get()).guard();
+        *left -= 20;
+    });
+    assert!(message.contains("BudgetLock"), "{message}");
+    assert!(message.contains("-10 left"), "{message}");
+    assert!(
+        message.contains(&format!("{}:{line}:", file!())),
+        "{message}"
+    );
+}
+
+#[test]
+fn invariant_is_checked_only_while_set() {
+    let budget = DeadlockProofMutex::new(10, BudgetLock);
+    budget.set_release_invariant(non_negative);
+
+    let (mut guard, nested) = budget.lock_for_nested(OuterMutexPermission::get()).guard();
+    *guard -= 5;
+    let permission = guard.unlock(nested);
+
+    budget.clear_release_invariant();
+    let mut guard = budget.lock(permission).guard();
+    *guard -= 20;
+    let permission = guard.unlock();
+
+    // An invariant set while the data breaks it only fails a later release.
+    budget.set_release_invariant(non_negative);
+    let message = panic_message(|| {
+        let _left = budget.lock(permission).guard();
+    });
+    assert!(message.contains("-15 left"), "{message}");
+}
+
+#[test]
+fn ip_layer_checks_its_icmp_record() {
+    let stack = NetworkStack::new();
+    let message = panic_message(|| {
+        let mut ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+        ip.last_icmp_error = Some(IcmpError::HostUnreachable);
+    });
+    // The layers are only named under `tracking`.
+    #[cfg(feature = "tracking")]
+    assert!(message.contains("ip-layer"), "{message}");
+    assert!(message.contains("none was counted"), "{message}");
+}
+
+#[test]
+fn commit_checks_the_layers_it_writes() {
+    let stack = NetworkStack::new();
+    let mut line = 0;
+    let message = panic_message(|| {
+        let transaction = stack.transaction().stage_ip(|ip| {
+            ip.last_icmp_error = Some(IcmpError::HostUnreachable);
+            Ok(())
+        });
+        line = line!() + 1;
+        let _ = transaction.commit(OuterMutexPermission::get());
+    });
+    #[cfg(feature = "tracking")]
+    assert!(message.contains("ip-layer"), "{message}");
+    #[cfg(not(feature = "tracking"))]
+    assert!(message.contains("IpLock"), "{message}");
+    assert!(message.contains("none was counted"), "{message}");
+    assert!(
+        message.contains(&format!("{}:{line}:", file!())),
+        "{message}"
+    );
+}
+
+#[test]
+fn carried_sections_check_the_invariant() {
+    let stack = NetworkStack::new();
+    stack.device_layer.set_release_invariant(|device| {
+        if device.interfaces_active > 0 {
+            Ok(())
+        } else {
+            Err("no interface is active".to_owned())
+        }
+    });
+    let message = panic_message(|| {
+        let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+        let carry = ip.unlock_for_sequential_with(());
+        let _ = stack.device_layer.lock_with_carry(carry, |device, ()| {
+            device.interfaces_active = 0;
+        });
+    });
+    #[cfg(feature = "tracking")]
+    assert!(message.contains("device-layer"), "{message}");
+    #[cfg(not(feature = "tracking"))]
+    assert!(message.contains("DeviceLock"), "{message}");
+    assert!(message.contains("no interface is active"), "{message}");
+}
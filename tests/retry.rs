@@ -0,0 +1,117 @@
+use std::{
+    ops::ControlFlow,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use deadlock_proof::{
+    retry::{with_backoff, BackoffPolicy},
+    task::AsyncPermissionSlot,
+    AsyncPermission, DeadlockProofMutex, LockOutcome, OuterMutexPermission,
+};
+
+struct CounterLock;
+
+fn quick(max_attempts: u32) -> BackoffPolicy {
+    BackoffPolicy {
+        initial: Duration::from_micros(50),
+        max: Duration::from_millis(1),
+        max_attempts,
+        ..BackoffPolicy::default()
+    }
+}
+
+#[test]
+fn succeeds_on_the_first_try() {
+    let counter = DeadlockProofMutex::new(1u32, CounterLock);
+    let mut attempts = 0;
+    let (permission, doubled) =
+        with_backoff(&counter, OuterMutexPermission::get(), quick(4), |value| {
+            attempts += 1;
+            *value *= 2;
+            ControlFlow::Break(*value)
+        });
+    assert_eq!((doubled, attempts), (Some(2), 1));
+    assert_eq!(*counter.lock(permission).guard(), 2);
+}
+
+#[test]
+fn succeeds_once_contention_clears() {
+    let counter = DeadlockProofMutex::new(0u32, CounterLock);
+    let (held, wait_for_held) = mpsc::channel();
+    thread::scope(|scope| {
+        let counter = &counter;
+        scope.spawn(move || {
+            let mut guard = counter.lock(OuterMutexPermission::get()).guard();
+            held.send(()).unwrap();
+            thread::sleep(Duration::from_millis(20));
+            *guard = 5;
+        });
+        wait_for_held.recv().unwrap();
+
+        let policy = BackoffPolicy {
+            timeout: Some(Duration::from_secs(5)),
+            ..quick(u32::MAX)
+        };
+        let (permission, seen) =
+            with_backoff(counter, OuterMutexPermission::get(), policy, |value| {
+                ControlFlow::Break(*value)
+            });
+        assert_eq!(seen, Some(5));
+        counter.lock(permission).guard().unlock();
+    });
+}
+
+#[test]
+fn gives_up_with_the_permission_once_exhausted() {
+    let counter = DeadlockProofMutex::new(0u32, CounterLock);
+    let mut attempts = 0;
+    // The data never becomes ready.
+    let (permission, gave) = with_backoff(
+        &counter,
+        OuterMutexPermission::get(),
+        quick(3),
+        |_| -> ControlFlow<()> {
+            attempts += 1;
+            ControlFlow::Continue(())
+        },
+    );
+    assert_eq!((gave, attempts), (None, 3));
+
+    // And the lock is never free before the deadline.
+    let guard = counter.lock(permission).guard();
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let policy = BackoffPolicy {
+                timeout: Some(Duration::from_millis(5)),
+                ..quick(u32::MAX)
+            };
+            let (permission, gave) =
+                with_backoff(&counter, OuterMutexPermission::get(), policy, |_| {
+                    ControlFlow::Break(())
+                });
+            assert_eq!(gave, None);
+            // The permission still works.
+            let other = DeadlockProofMutex::new((), CounterLock);
+            other.lock(permission).guard().unlock();
+        });
+    });
+    guard.unlock();
+}
+
+#[test]
+fn a_panicking_attempt_recovers_the_permission() {
+    let slot = AsyncPermissionSlot::new();
+    let counter: DeadlockProofMutex<u32, AsyncPermission, _> =
+        DeadlockProofMutex::new(0, CounterLock);
+    let permission = slot.claim().unwrap();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        with_backoff(&counter, permission, quick(4), |_| -> ControlFlow<()> {
+            panic!("attempt failed")
+        })
+    }));
+    assert!(result.is_err());
+    assert!(slot.is_parked());
+}
@@ -0,0 +1,124 @@
+use std::net::Ipv4Addr;
+
+use deadlock_proof::{
+    concurrent, LockOutcome, NetworkStack, OuterMutexPermission, Route, RouteCacheStats,
+    RouteError, RoutingTable,
+};
+
+fn route(destination: [u8; 4], prefix_len: u8, next_hop: [u8; 4]) -> Route {
+    Route {
+        destination: Ipv4Addr::from(destination),
+        prefix_len,
+        next_hop: Ipv4Addr::from(next_hop),
+    }
+}
+
+fn stats(
+    stack: &NetworkStack,
+    permission: OuterMutexPermission,
+) -> (OuterMutexPermission, RouteCacheStats) {
+    let cache = stack.route_cache.read(permission).guard();
+    let stats = cache.stats();
+    (cache.unlock(), stats)
+}
+
+#[test]
+fn repeated_lookups_hit_the_cache() {
+    let stack = NetworkStack::new();
+    let mut walk = stack.begin_walk(OuterMutexPermission::get());
+    walk.add_route(route([10, 0, 0, 0], 8, [192, 168, 0, 1]))
+        .unwrap();
+    walk.add_route(route([10, 1, 0, 0], 16, [192, 168, 0, 2]))
+        .unwrap();
+
+    let hop = walk.cached_route_lookup(Ipv4Addr::new(10, 1, 2, 3));
+    assert_eq!(hop, Some(Ipv4Addr::new(192, 168, 0, 2)));
+    let hop = walk.cached_route_lookup(Ipv4Addr::new(10, 1, 2, 3));
+    assert_eq!(hop, Some(Ipv4Addr::new(192, 168, 0, 2)));
+    let hop = walk.cached_route_lookup(Ipv4Addr::new(172, 16, 0, 1));
+    assert_eq!(hop, None);
+
+    let (_permission, stats) = stats(&stack, walk.finish());
+    assert_eq!(stats, RouteCacheStats { hits: 1, misses: 2 });
+}
+
+#[test]
+fn route_changes_invalidate_cached_lookups() {
+    let stack = NetworkStack::new();
+    let addr = Ipv4Addr::new(10, 1, 2, 3);
+    let mut walk = stack.begin_walk(OuterMutexPermission::get());
+    walk.add_route(route([10, 0, 0, 0], 8, [192, 168, 0, 1]))
+        .unwrap();
+    let hop = walk.cached_route_lookup(addr);
+    assert_eq!(hop, Some(Ipv4Addr::new(192, 168, 0, 1)));
+
+    walk.add_route(route([10, 1, 0, 0], 16, [192, 168, 0, 2]))
+        .unwrap();
+    let hop = walk.cached_route_lookup(addr);
+    assert_eq!(hop, Some(Ipv4Addr::new(192, 168, 0, 2)));
+
+    let removed = walk.remove_route(Ipv4Addr::new(10, 1, 0, 0), 16);
+    assert!(removed.is_some());
+    let hop = walk.cached_route_lookup(addr);
+    assert_eq!(hop, Some(Ipv4Addr::new(192, 168, 0, 1)));
+
+    let (permission, stats) = stats(&stack, walk.finish());
+    assert_eq!(stats.hits, 0);
+    let ip = stack.ip_layer.lock(permission).guard();
+    assert_eq!(ip.routing_table_size, 1);
+}
+
+#[test]
+fn prefixes_longer_than_an_address_are_rejected() {
+    let mut table = RoutingTable::default();
+    assert_eq!(
+        table.add(route([10, 0, 0, 0], 33, [192, 168, 0, 1])),
+        Err(RouteError::PrefixTooLong(33))
+    );
+    assert!(table.is_empty());
+    assert_eq!(table.generation(), 0);
+    table
+        .add(route([10, 0, 0, 1], 32, [192, 168, 0, 1]))
+        .unwrap();
+
+    let stack = NetworkStack::new();
+    let mut walk = stack.begin_walk(OuterMutexPermission::get());
+    let added = walk.add_route(route([0, 0, 0, 0], u8::MAX, [192, 168, 0, 1]));
+    assert_eq!(added, Err(RouteError::PrefixTooLong(u8::MAX)));
+    // Had it been added, it would match every address.
+    assert_eq!(walk.cached_route_lookup(Ipv4Addr::new(10, 1, 2, 3)), None);
+    let ip = stack.ip_layer.lock(walk.finish()).guard();
+    assert_eq!(ip.routing_table_size, 0);
+}
+
+#[test]
+fn synthetic_workload_is_mostly_cache_hits() {
+    const LOOKUPS: u32 = 2_000;
+    let stack = NetworkStack::new();
+    let mut walk = stack.begin_walk(OuterMutexPermission::get());
+    walk.add_route(route([10, 0, 0, 0], 8, [192, 168, 0, 1]))
+        .unwrap();
+    let permission = walk.finish();
+
+    let workers = (0..4u32)
+        .map(|worker| -> concurrent::Worker<'_> {
+            let stack = &stack;
+            Box::new(move |permission| {
+                let mut walk = stack.begin_walk(permission);
+                for i in 0..LOOKUPS {
+                    // 16 hot destinations per worker, well under capacity.
+                    let addr = Ipv4Addr::from(0x0a00_0000 | (worker << 8) | (i % 16));
+                    let hop = walk.cached_route_lookup(addr);
+                    assert_eq!(hop, Some(Ipv4Addr::new(192, 168, 0, 1)));
+                }
+                walk.finish()
+            })
+        })
+        .collect();
+    concurrent::run_all(workers).unwrap();
+
+    let (_permission, stats) = stats(&stack, permission);
+    assert_eq!(stats.hits + stats.misses, u64::from(4 * LOOKUPS));
+    // Workers use disjoint destinations, so each one misses exactly once.
+    assert_eq!(stats.misses, 4 * 16);
+}
@@ -0,0 +1,159 @@
+use std::{
+    sync::Barrier,
+    thread,
+    time::{Duration, Instant},
+};
+
+use deadlock_proof::{
+    unique_type, DeadlockProofMutex, LockOutcome, OuterMutexPermission, TryLockError,
+};
+
+#[test]
+fn rt_thread_gets_its_permission_back_while_the_lock_is_held() {
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    let rt = mutex.rt_handle();
+    let barrier = Barrier::new(2);
+
+    let permission = thread::scope(|scope| {
+        scope.spawn(|| {
+            let blocking = mutex.blocking_handle();
+            let guard = blocking.lock(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            barrier.wait();
+            drop(guard);
+        });
+        barrier.wait();
+        let permission = match rt.try_lock(OuterMutexPermission::get()) {
+            Err(TryLockError::WouldBlock(permission)) => permission,
+            other => panic!("expected WouldBlock, got {:?}", other.map(|_| ())),
+        };
+        assert!(rt.is_locked());
+        barrier.wait();
+
+        let mut guard = rt
+            .try_lock_for(permission, Duration::from_secs(10))
+            .unwrap();
+        *guard += 1;
+        guard.unlock()
+    });
+
+    assert_eq!(*mutex.lock(permission).guard(), 1);
+}
+
+#[test]
+fn timed_attempt_times_out_then_succeeds_after_release() {
+    let mutex = DeadlockProofMutex::new((), unique_type!());
+    let barrier = Barrier::new(2);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let guard = mutex.lock(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            barrier.wait();
+            drop(guard);
+        });
+        barrier.wait();
+        let rt = mutex.rt_handle();
+        let start = Instant::now();
+        let error = rt
+            .try_lock_until(
+                OuterMutexPermission::get(),
+                start + Duration::from_millis(5),
+            )
+            .err()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(5));
+        assert_eq!(error.to_string(), "deadlock-proof mutex is locked");
+        let permission = error.into_permission();
+        barrier.wait();
+
+        rt.try_lock_for(permission, Duration::from_secs(10))
+            .unwrap()
+            .unlock();
+    });
+}
+
+#[test]
+fn both_handles_share_one_counter() {
+    const ROUNDS: u32 = 1_000;
+    let counter = DeadlockProofMutex::new(0u32, unique_type!());
+
+    thread::scope(|scope| {
+        let rt = counter.blocking_handle().rt_handle();
+        scope.spawn(move || {
+            let mut permission = OuterMutexPermission::get();
+            let mut done = 0;
+            while done < ROUNDS {
+                match rt.try_lock(permission) {
+                    Ok(mut guard) => {
+                        *guard += 1;
+                        done += 1;
+                        permission = guard.unlock();
+                    }
+                    Err(error) => permission = error.into_permission(),
+                }
+            }
+        });
+        let blocking = counter.blocking_handle();
+        scope.spawn(move || {
+            let mut permission = OuterMutexPermission::get();
+            for _ in 0..ROUNDS {
+                let mut guard = blocking.lock(permission).guard();
+                *guard += 1;
+                permission = guard.unlock();
+            }
+        });
+    });
+
+    assert_eq!(
+        *counter.lock(OuterMutexPermission::get()).guard(),
+        2 * ROUNDS
+    );
+}
+
+#[test]
+#[cfg(not(feature = "no-poison"))]
+fn poisoned_mutex_returns_the_permission() {
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let _guard = mutex.lock(OuterMutexPermission::get()).guard();
+                panic!("poison the mutex");
+            })
+            .join()
+            .unwrap_err();
+    });
+
+    let rt = mutex.rt_handle();
+    match rt.try_lock_for(OuterMutexPermission::get(), Duration::from_secs(10)) {
+        Err(error @ TryLockError::Poisoned(_)) => {
+            assert_eq!(error.to_string(), "deadlock-proof mutex is poisoned");
+            error.into_permission();
+        }
+        other => panic!("expected Poisoned, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+#[cfg(feature = "no-poison")]
+fn poisoned_mutex_is_tried_like_a_healthy_one() {
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let _guard = mutex.lock(OuterMutexPermission::get()).guard();
+                panic!("poison the mutex");
+            })
+            .join()
+            .unwrap_err();
+    });
+
+    assert_eq!(
+        *mutex
+            .rt_handle()
+            .try_lock(OuterMutexPermission::get())
+            .unwrap(),
+        0
+    );
+}
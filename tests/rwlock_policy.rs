@@ -0,0 +1,204 @@
+//! Who gets in first under each [`RwLockPolicy`], with readers and writers
+//! that keep coming.
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use deadlock_proof::{DeadlockProofRwLock, LockOutcome, OuterMutexPermission, RwLockPolicy};
+
+struct RoutesLock;
+
+type Routes = DeadlockProofRwLock<Vec<u32>, OuterMutexPermission, RoutesLock>;
+
+const THREADS: usize = 4;
+const HOLD: Duration = Duration::from_millis(2);
+const SAMPLES: usize = 30;
+
+/// Readers that overlap, so the lock is never free of them while they run.
+fn sustained_readers<'a>(
+    scope: &'a thread::Scope<'a, '_>,
+    routes: &'a Routes,
+    stop: &'a AtomicBool,
+    completed: &'a AtomicUsize,
+) {
+    for started in 0..THREADS {
+        scope.spawn(move || {
+            thread::sleep(HOLD * started as u32 / THREADS as u32);
+            let mut permission = OuterMutexPermission::get();
+            while !stop.load(Ordering::Relaxed) {
+                let table = routes.read(permission).guard();
+                thread::sleep(HOLD);
+                permission = table.unlock();
+                completed.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+    }
+}
+
+/// Writers back to back, so one is always waiting while another holds.
+fn sustained_writers<'a>(
+    scope: &'a thread::Scope<'a, '_>,
+    routes: &'a Routes,
+    stop: &'a AtomicBool,
+    completed: &'a AtomicUsize,
+) {
+    for _ in 0..THREADS {
+        scope.spawn(move || {
+            let mut permission = OuterMutexPermission::get();
+            while !stop.load(Ordering::Relaxed) {
+                let mut table = routes.write(permission).guard();
+                table.push(1);
+                thread::sleep(HOLD);
+                permission = table.unlock();
+                completed.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+    }
+}
+
+/// How long each of `SAMPLES` acquisitions by `acquire` waited, sorted, and
+/// the permission back.
+fn waits(
+    mut permission: OuterMutexPermission,
+    mut acquire: impl FnMut(OuterMutexPermission) -> OuterMutexPermission,
+) -> (Vec<Duration>, OuterMutexPermission) {
+    let mut waits = Vec::with_capacity(SAMPLES);
+    for _ in 0..SAMPLES {
+        let started = Instant::now();
+        permission = acquire(permission);
+        waits.push(started.elapsed());
+        thread::sleep(HOLD);
+    }
+    waits.sort();
+    (waits, permission)
+}
+
+fn read(routes: &Routes) -> impl FnMut(OuterMutexPermission) -> OuterMutexPermission + '_ {
+    |permission| routes.read(permission).guard().unlock()
+}
+
+fn write(routes: &Routes) -> impl FnMut(OuterMutexPermission) -> OuterMutexPermission + '_ {
+    |permission| {
+        let mut table = routes.write(permission).guard();
+        table.push(2);
+        table.unlock()
+    }
+}
+
+#[test]
+fn reader_pref_lets_readers_past_a_waiting_writer() {
+    let routes = Routes::with_policy(Vec::new(), RoutesLock, RwLockPolicy::ReaderPref);
+    let (stop, completed) = (AtomicBool::new(false), AtomicUsize::new(0));
+    let (waits, permission) = thread::scope(|scope| {
+        sustained_readers(scope, &routes, &stop, &completed);
+        let writer = scope.spawn(|| {
+            write(&routes)(OuterMutexPermission::get());
+        });
+        thread::sleep(HOLD * 5);
+        let (waits, permission) = waits(OuterMutexPermission::get(), read(&routes));
+        // Once the readers stop, the writer still gets in.
+        stop.store(true, Ordering::Relaxed);
+        writer.join().unwrap();
+        (waits, permission)
+    });
+    let p90 = waits[SAMPLES * 9 / 10 - 1];
+    // No wait for the writer, which would take a hold or more.
+    assert!(p90 < HOLD, "p90 read wait {p90:?}");
+    assert_eq!(*routes.read(permission).guard(), [2]);
+}
+
+#[test]
+fn writer_pref_does_not_starve_writers_under_sustained_readers() {
+    let routes = Routes::with_policy(Vec::new(), RoutesLock, RwLockPolicy::WriterPref);
+    let (stop, completed) = (AtomicBool::new(false), AtomicUsize::new(0));
+    let (waits, permission) = thread::scope(|scope| {
+        sustained_readers(scope, &routes, &stop, &completed);
+        thread::sleep(HOLD * 5);
+        let (waits, permission) = waits(OuterMutexPermission::get(), write(&routes));
+        stop.store(true, Ordering::Relaxed);
+        (waits, permission)
+    });
+    let p90 = waits[SAMPLES * 9 / 10 - 1];
+    // The readers already in, plus scheduling slack.
+    assert!(p90 < HOLD * 5, "p90 write wait {p90:?}");
+    assert_eq!(routes.read(permission).guard().len(), SAMPLES);
+    assert!(completed.load(Ordering::Relaxed) > 0);
+}
+
+#[test]
+fn fair_does_not_starve_readers_under_sustained_writers() {
+    let routes = Routes::with_policy(Vec::new(), RoutesLock, RwLockPolicy::Fair);
+    let (stop, completed) = (AtomicBool::new(false), AtomicUsize::new(0));
+    let (waits, _) = thread::scope(|scope| {
+        sustained_writers(scope, &routes, &stop, &completed);
+        thread::sleep(HOLD * 5);
+        let (waits, permission) = waits(OuterMutexPermission::get(), read(&routes));
+        stop.store(true, Ordering::Relaxed);
+        (waits, permission)
+    });
+    let p90 = waits[SAMPLES * 9 / 10 - 1];
+    // The writer in, plus scheduling slack; served after all `THREADS`
+    // writers it would take more.
+    assert!(p90 < HOLD * 3, "p90 read wait {p90:?}");
+    assert!(completed.load(Ordering::Relaxed) > 0);
+}
+
+#[test]
+fn fair_does_not_starve_writers_under_sustained_readers() {
+    let routes = Routes::with_policy(Vec::new(), RoutesLock, RwLockPolicy::Fair);
+    let (stop, completed) = (AtomicBool::new(false), AtomicUsize::new(0));
+    let (waits, _) = thread::scope(|scope| {
+        sustained_readers(scope, &routes, &stop, &completed);
+        thread::sleep(HOLD * 5);
+        let (waits, permission) = waits(OuterMutexPermission::get(), write(&routes));
+        stop.store(true, Ordering::Relaxed);
+        (waits, permission)
+    });
+    let p90 = waits[SAMPLES * 9 / 10 - 1];
+    assert!(p90 < HOLD * 5, "p90 write wait {p90:?}");
+    assert!(completed.load(Ordering::Relaxed) > 0);
+}
+
+#[test]
+fn new_prefers_writers() {
+    let routes = Routes::new(Vec::new(), RoutesLock);
+    assert_eq!(routes.policy(), RwLockPolicy::WriterPref);
+}
+
+#[cfg(not(feature = "no-poison"))]
+#[test]
+fn only_a_panicking_writer_poisons() {
+    let mut permission = OuterMutexPermission::get();
+    for policy in [RwLockPolicy::ReaderPref, RwLockPolicy::WriterPref, RwLockPolicy::Fair] {
+        let routes = Routes::with_policy(vec![1], RoutesLock, policy);
+        thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _table = routes.read(OuterMutexPermission::get());
+                    panic!("reader panicked");
+                })
+                .join()
+                .unwrap_err();
+        });
+        permission = routes.read(permission).guard().unlock();
+
+        thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let mut table = routes.write(OuterMutexPermission::get()).guard();
+                    table.push(2);
+                    panic!("writer panicked");
+                })
+                .join()
+                .unwrap_err();
+        });
+        let table = routes.read(permission).err().unwrap().into_inner();
+        assert_eq!(*table, [1, 2]);
+        // The permission is in the guard, as for any other read.
+        let poisoned = routes.write(table.unlock()).err().unwrap();
+        permission = poisoned.into_inner().unlock();
+    }
+}
@@ -0,0 +1,84 @@
+use std::thread;
+
+use deadlock_proof::{
+    DeadlockProofMutex, LockOutcome, NestedMutexPermission, OuterMutexPermission, WithScratch,
+};
+
+struct Ip;
+struct Device;
+
+#[test]
+fn scratch_keeps_its_capacity_across_threads() {
+    let ip = DeadlockProofMutex::new_with_scratch::<Vec<u8>>(0u64, Ip);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut guard = ip.lock(OuterMutexPermission::get()).guard();
+            let (packets, scratch) = guard.data_and_scratch();
+            scratch.extend_from_slice(&[0; 1024]);
+            *packets += 1;
+        });
+    });
+
+    let capacity = thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let mut guard = ip.lock(OuterMutexPermission::get()).guard();
+                assert_eq!(**guard, 1);
+                let scratch = guard.scratch();
+                assert_eq!(scratch.len(), 1024);
+                scratch.clear();
+                scratch.capacity()
+            })
+            .join()
+            .unwrap()
+    });
+    assert!(capacity >= 1024);
+
+    let mut guard = ip.lock(OuterMutexPermission::get()).guard();
+    assert!(guard.scratch().is_empty());
+    assert_eq!(guard.scratch().capacity(), capacity);
+}
+
+#[cfg(feature = "tracking")]
+#[test]
+fn only_data_access_counts_as_a_modification() {
+    let ip = DeadlockProofMutex::new_with_scratch::<String>(0u64, Ip);
+    let version = ip.version();
+
+    let mut guard = ip.lock(OuterMutexPermission::get()).guard();
+    guard.scratch().push_str("reused");
+    let permission = guard.unlock();
+    assert_eq!(ip.version(), version);
+
+    let mut guard = ip.lock(permission).guard();
+    *guard.data_and_scratch().0 += 1;
+    guard.unlock();
+    assert_ne!(ip.version(), version);
+}
+
+#[test]
+fn nested_guards_reach_the_scratch() {
+    let ip = DeadlockProofMutex::new_with_scratch::<Vec<u32>>(0u64, Ip);
+    let device: DeadlockProofMutex<
+        WithScratch<u32, Vec<u32>>,
+        NestedMutexPermission<OuterMutexPermission, Ip>,
+        Device,
+    > = DeadlockProofMutex::new_with_scratch(0u32, Device);
+
+    let mut permission = OuterMutexPermission::get();
+    for round in 1..=3 {
+        let (mut ip_guard, token) = ip.lock_for_nested(permission).guard();
+        let (mut device_guard, token) = device.lock_for_nested(token).guard();
+        device_guard.scratch().push(round);
+        ip_guard.scratch().push(round * 10);
+        **device_guard.get_mut() += 1;
+        permission = ip_guard.unlock(device_guard.unlock(token));
+    }
+
+    let (mut ip_guard, token) = ip.lock_for_nested(permission).guard();
+    let (mut device_guard, _token) = device.lock_for_nested(token).guard();
+    assert_eq!(**device_guard, 3);
+    assert_eq!(*device_guard.scratch(), [1, 2, 3]);
+    assert_eq!(*ip_guard.scratch(), [10, 20, 30]);
+}
@@ -0,0 +1,78 @@
+use deadlock_proof::{LockOutcome, NetworkStack, OuterMutexPermission};
+
+struct Packet {
+    destination: u32,
+    len: u64,
+    next_hop: Option<u32>,
+    port: Option<u16>,
+}
+
+#[test]
+fn packet_is_pipelined_through_all_layers() {
+    let stack = NetworkStack::new();
+    let packet = Packet {
+        destination: 0x0a00_0001,
+        len: 1500,
+        next_hop: None,
+        port: None,
+    };
+
+    let mut ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    ip.packets_processed += 1;
+    ip.routing_table_size = 1;
+    let packet = Packet {
+        next_hop: Some(packet.destination & 0xffff_ff00 | 1),
+        ..packet
+    };
+    let carry = ip.unlock_for_sequential_with(packet);
+    assert_eq!(carry.next_hop, Some(0x0a00_0001));
+
+    let carry = stack
+        .device_layer
+        .lock_with_carry(carry, |device, packet| {
+            assert!(packet.next_hop.is_some());
+            device.bytes_transmitted += packet.len;
+            packet
+        })
+        .guard();
+
+    let mut carry = stack
+        .transport_layer
+        .lock_with_carry(carry, |transport, mut packet| {
+            transport.tcp_connections += 1;
+            packet.port = Some(443);
+            packet
+        })
+        .guard();
+    carry.len = 0;
+
+    let (permission, packet) = carry.into_parts();
+    assert_eq!(packet.port, Some(443));
+    assert_eq!(packet.len, 0);
+
+    let ip = stack
+        .ip_layer
+        .lock(permission.to_earlier().to_earlier().to_earlier())
+        .guard();
+    assert_eq!(ip.packets_processed, 1);
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    assert_eq!(device.bytes_transmitted, 1500);
+    let transport = stack.transport_layer.lock(device.unlock_for_sequential()).guard();
+    assert_eq!(transport.tcp_connections, 1);
+}
+
+#[test]
+fn carry_can_change_type_between_layers() {
+    let stack = NetworkStack::new();
+    let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    let carry = ip.unlock_for_sequential_with(1500_u64);
+
+    let carry = stack
+        .device_layer
+        .lock_with_carry(carry, |device, len| {
+            device.bytes_transmitted += len;
+            device.bytes_transmitted.to_string()
+        })
+        .guard();
+    assert_eq!(*carry, "1500");
+}
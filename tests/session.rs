@@ -0,0 +1,109 @@
+use deadlock_proof::{
+    session::{Closed, Established, Listen, State, StateCell, SynReceived, TcpState, Transition},
+    DeadlockProofMutexGuard, LockOutcome, NetworkStack, OuterMutexPermission, Position, TcpSocket,
+    TransportLock, TransportState,
+};
+
+type TransportGuard<'a> =
+    DeadlockProofMutexGuard<'a, TransportState, Position<TransportLock>, TransportLock>;
+
+/// Runs `f` on the transport layer of `stack`, walking down to it.
+fn with_transport<R>(
+    stack: &NetworkStack,
+    permission: OuterMutexPermission,
+    f: impl FnOnce(&mut TransportGuard<'_>) -> R,
+) -> (OuterMutexPermission, R) {
+    let ip = stack.ip_layer.lock(permission).guard();
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    let mut transport = stack
+        .transport_layer
+        .lock(device.unlock_for_sequential())
+        .guard();
+    let result = f(&mut transport);
+    let permission = transport.unlock().to_earlier().to_earlier();
+    (permission, result)
+}
+
+#[test]
+fn connections_move_through_their_lifecycle_across_critical_sections() {
+    let stack = NetworkStack::new();
+    let (permission, ()) = with_transport(&stack, OuterMutexPermission::get(), |transport| {
+        transport.sockets.insert(443, TcpSocket::listening());
+        transport.socket_handle::<Listen>(443).unwrap().accept();
+    });
+    // The state outlives the handle, as a plain value.
+    let (permission, ()) = with_transport(&stack, permission, |transport| {
+        assert_eq!(transport.sockets[&443].state.get(), TcpState::SynReceived);
+        assert!(transport.socket_handle::<Listen>(443).is_none());
+        assert!(transport.socket_handle::<Established>(443).is_none());
+        transport
+            .socket_handle::<SynReceived>(443)
+            .unwrap()
+            .establish();
+    });
+    let (_permission, states) = with_transport(&stack, permission, |transport| {
+        transport.socket_handle::<Established>(443).unwrap().close();
+        assert!(transport.socket_handle::<Closed>(22).is_none());
+        transport
+            .sockets
+            .values()
+            .map(|socket| socket.state.get())
+            .collect::<Vec<_>>()
+    });
+    assert_eq!(states, [TcpState::Closed]);
+}
+
+#[test]
+fn every_legal_chain_ends_closed() {
+    let chains: [fn(&mut StateCell<TcpState>); 3] = [
+        |cell| {
+            cell.handle::<Listen>().unwrap().close();
+        },
+        |cell| {
+            cell.handle::<Listen>().unwrap().accept().reset();
+        },
+        |cell| {
+            cell.handle::<Listen>()
+                .unwrap()
+                .accept()
+                .establish()
+                .close();
+        },
+    ];
+    for chain in chains {
+        let socket = TcpSocket::listening();
+        let mut moved = socket.clone();
+        chain(&mut moved.state);
+        assert_eq!(moved.state.get(), TcpState::Closed);
+        // The copy taken before is untouched, as for any snapshot.
+        assert_eq!(socket.state.get(), TcpState::Listen);
+        assert_ne!(socket, moved);
+    }
+}
+
+#[test]
+fn other_state_machines_declare_their_own_transitions() {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Link {
+        Down,
+        Up,
+    }
+    struct Down;
+    struct Up;
+    impl State for Down {
+        type Machine = Link;
+        const STATE: Link = Link::Down;
+    }
+    impl State for Up {
+        type Machine = Link;
+        const STATE: Link = Link::Up;
+    }
+    impl Transition<Up> for Down {}
+    impl Transition<Down> for Up {}
+
+    let mut link = StateCell::new(Link::Down);
+    let up = link.handle::<Down>().unwrap().transition::<Up>();
+    up.transition::<Down>().transition::<Up>();
+    assert_eq!(link.get(), Link::Up);
+    assert!(link.handle::<Down>().is_none());
+}
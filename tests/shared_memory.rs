@@ -0,0 +1,191 @@
+#![cfg(all(feature = "shared-memory", target_os = "linux"))]
+
+use std::{
+    ffi::{c_int, c_void},
+    fs::{self, File},
+    mem,
+    os::fd::AsRawFd,
+    process, ptr, thread,
+};
+
+use deadlock_proof::{
+    shared_memory::{SharedDeadlockProofMutex, SharedLockError},
+    DeadlockProofMutex, LockOutcome, NestedMutexPermission, OuterMutexPermission,
+};
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_SHARED: c_int = 1;
+
+unsafe extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+/// One `MAP_SHARED` mapping of a file.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(file: &File, len: usize) -> Self {
+        // Safety: a fresh mapping of a file at least `len` bytes long.
+        let ptr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        assert_ne!(ptr as isize, -1, "mmap failed");
+        Self {
+            ptr: ptr.cast(),
+            len,
+        }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // Safety: mapped in `new`, and every mutex attached to it is gone.
+        unsafe { munmap(self.ptr.cast(), self.len) };
+    }
+}
+
+struct Ring;
+struct Stats;
+
+type Counter = SharedDeadlockProofMutex<u64, OuterMutexPermission, Ring>;
+
+/// A temporary file holding an initialised `Counter`, mapped twice.
+fn two_mappings(name: &str) -> (Mapping, Mapping) {
+    let path = std::env::temp_dir().join(format!("deadlock-proof-{}-{name}", process::id()));
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    file.set_len(Counter::SIZE as u64).unwrap();
+    let (first, second) = (
+        Mapping::new(&file, Counter::SIZE),
+        Mapping::new(&file, Counter::SIZE),
+    );
+    assert_ne!(first.ptr, second.ptr);
+    // Safety: the mapping is page-aligned, large enough and not attached yet.
+    unsafe { Counter::init(first.ptr, 0) }.unwrap();
+    (first, second)
+}
+
+fn attach(mapping: &Mapping) -> Counter {
+    // Safety: initialised by `two_mappings`, and outlived by the mapping.
+    unsafe { Counter::from_raw_parts(mapping.ptr, Ring) }
+}
+
+#[test]
+fn mappings_share_the_lock_and_the_data() {
+    let (first, second) = two_mappings("share");
+    let (a, b) = (attach(&first), attach(&second));
+    let stats: DeadlockProofMutex<u64, NestedMutexPermission<OuterMutexPermission, Ring>, Stats> =
+        DeadlockProofMutex::new(0, Stats);
+
+    let mut guard = a.lock(OuterMutexPermission::get()).unwrap();
+    *guard = 7;
+    let permission = guard.unlock();
+
+    let (guard, token) = b.lock_for_nested(permission).unwrap();
+    assert_eq!(*guard, 7);
+    let mut stats_guard = stats.lock(token).guard();
+    *stats_guard += *guard;
+    let _permission = guard.unlock(stats_guard.unlock());
+}
+
+#[test]
+fn contending_threads_exclude_each_other_across_mappings() {
+    let (first, second) = two_mappings("contend");
+    let (a, b) = (attach(&first), attach(&second));
+    const ROUNDS: u64 = 2000;
+
+    thread::scope(|scope| {
+        for mutex in [&a, &b, &a, &b] {
+            scope.spawn(move || {
+                let mut permission = OuterMutexPermission::get();
+                for _ in 0..ROUNDS {
+                    let mut guard = mutex.lock(permission).unwrap();
+                    let seen = *guard;
+                    thread::yield_now();
+                    *guard = seen + 1;
+                    permission = guard.unlock();
+                }
+            });
+        }
+    });
+
+    assert_eq!(*a.lock(OuterMutexPermission::get()).unwrap(), 4 * ROUNDS);
+}
+
+#[test]
+fn dead_owner_is_reported_and_can_be_recovered() {
+    let (first, second) = two_mappings("recover");
+    let (a, b) = (attach(&first), attach(&second));
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut guard = a.lock(OuterMutexPermission::get()).unwrap();
+            *guard = 41;
+            // Dies holding the lock.
+            mem::forget(guard);
+        });
+    });
+
+    let mut permission = OuterMutexPermission::get();
+    let died = match b.lock(permission) {
+        Err(SharedLockError::PreviousOwnerDied(died)) => died,
+        _ => panic!("expected an owner death"),
+    };
+    let mut guard = died.recover();
+    assert_eq!(*guard, 41);
+    *guard += 1;
+    permission = guard.unlock();
+
+    let guard = a.lock(permission).unwrap();
+    assert_eq!(*guard, 42);
+}
+
+#[test]
+fn unrecovered_mutex_gives_the_permission_back() {
+    let (first, second) = two_mappings("abandon");
+    let (a, b) = (attach(&first), attach(&second));
+
+    thread::scope(|scope| {
+        scope.spawn(|| mem::forget(a.lock(OuterMutexPermission::get()).unwrap()));
+    });
+
+    match b.lock(OuterMutexPermission::get()) {
+        // Released without being recovered, along with the permission.
+        Err(SharedLockError::PreviousOwnerDied(died)) => drop(died),
+        _ => panic!("expected an owner death"),
+    }
+    thread::scope(|scope| {
+        scope.spawn(|| match a.lock(OuterMutexPermission::get()) {
+            Err(SharedLockError::NotRecoverable(permission)) => assert!(matches!(
+                b.lock(permission),
+                Err(SharedLockError::NotRecoverable(_))
+            )),
+            _ => panic!("expected an unrecoverable mutex"),
+        });
+    });
+}
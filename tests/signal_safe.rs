@@ -0,0 +1,74 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+use deadlock_proof::{
+    DeadlockProofMutex, DeviceLock, DeviceState, LockOutcome, OuterMutexPermission, SignalSafe,
+};
+
+fn fold_bytes(state: &mut DeviceState, pending: &SignalSafe<u64>) {
+    state.bytes_transmitted += pending.swap(0, Ordering::Relaxed);
+}
+
+fn device_layer() -> deadlock_proof::SignalSafeMutex<DeviceState, u64, OuterMutexPermission, DeviceLock> {
+    let state = DeviceState {
+        interfaces_active: 1,
+        bytes_transmitted: 0,
+    };
+    DeadlockProofMutex::new(state, DeviceLock).with_signal_safe(0u64, fold_bytes)
+}
+
+#[test]
+fn handler_updates_are_folded_on_next_lock() {
+    let device = device_layer();
+    device.signal_safe().fetch_add(64, Ordering::Relaxed);
+    device.signal_safe().fetch_add(36, Ordering::Relaxed);
+
+    let guard = device.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(guard.bytes_transmitted, 100);
+    assert_eq!(device.signal_safe().load(Ordering::Relaxed), 0);
+    guard.unlock();
+}
+
+#[test]
+fn concurrent_handler_never_loses_updates() {
+    const BUMPS: u64 = 10_000;
+    let device = device_layer();
+    let done = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        // Stands in for the signal handler: no permission, no locks, only
+        // relaxed atomic updates.
+        scope.spawn(|| {
+            for _ in 0..BUMPS {
+                device.signal_safe().fetch_add(1, Ordering::Relaxed);
+            }
+            done.store(true, Ordering::Release);
+        });
+
+        let mut permission = OuterMutexPermission::get();
+        let mut last_seen = 0;
+        while !done.load(Ordering::Acquire) {
+            let guard = device.lock(permission).guard();
+            assert!(guard.bytes_transmitted >= last_seen);
+            last_seen = guard.bytes_transmitted;
+            permission = guard.unlock();
+        }
+        let guard = device.lock(permission).guard();
+        assert_eq!(guard.bytes_transmitted, BUMPS);
+        guard.unlock();
+    });
+}
+
+#[test]
+fn nested_lock_also_consolidates() {
+    let flagged = DeadlockProofMutex::new(false, DeviceLock).with_signal_safe(false, |seen, flag| {
+        *seen |= flag.swap(false, Ordering::Relaxed);
+    });
+    flagged.signal_safe().store(true, Ordering::Relaxed);
+
+    let (guard, nested) = flagged.lock_for_nested(OuterMutexPermission::get()).guard();
+    assert!(*guard);
+    guard.unlock(nested);
+}
@@ -0,0 +1,156 @@
+use std::{
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+use deadlock_proof::{
+    permission::live_claims, unique_type, DeadlockProofMutex, LockOutcome, NetworkStack,
+    OuterMutexPermission, SingleThreadedPhase,
+};
+
+/// Phases look at every claim in the process, so these tests must not
+/// overlap. Each body also runs on a fresh thread that is joined before the
+/// next test, so no finished test's claim is still counted.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn isolated(body: impl FnOnce() + Send) {
+    let _serial = SERIAL.lock().unwrap_or_else(|error| error.into_inner());
+    thread::scope(|scope| scope.spawn(body).join()).unwrap();
+}
+
+#[test]
+fn entering_needs_the_only_live_claim() {
+    isolated(|| {
+        let mut permission = OuterMutexPermission::get();
+        assert_eq!(live_claims(), 1);
+        let phase = SingleThreadedPhase::enter(&mut permission);
+        assert!(phase.is_some());
+        drop(phase);
+        // Can be entered again once left.
+        assert!(SingleThreadedPhase::enter(&mut permission).is_some());
+    });
+}
+
+#[test]
+fn entering_is_refused_while_another_thread_has_claimed() {
+    isolated(|| {
+        let mut permission = OuterMutexPermission::get();
+        let (claimed_tx, claimed_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let worker = thread::spawn(move || {
+            let _permission = OuterMutexPermission::get();
+            claimed_tx.send(()).unwrap();
+            done_rx.recv().unwrap();
+        });
+        claimed_rx.recv().unwrap();
+        assert_eq!(live_claims(), 2);
+        assert!(SingleThreadedPhase::enter(&mut permission).is_none());
+
+        done_tx.send(()).unwrap();
+        worker.join().unwrap();
+        assert!(SingleThreadedPhase::enter(&mut permission).is_some());
+    });
+}
+
+#[test]
+fn other_threads_cannot_claim_during_a_phase() {
+    isolated(|| {
+        let mut permission = OuterMutexPermission::get();
+        let phase = SingleThreadedPhase::enter(&mut permission).unwrap();
+        let refused = thread::spawn(|| {
+            let diagnostics = OuterMutexPermission::get_or_diagnose().err().unwrap();
+            assert!(diagnostics.blocked_by_phase);
+            assert!(diagnostics.to_string().contains("single-threaded phase"));
+        });
+        refused.join().unwrap();
+        drop(phase);
+
+        // The refused thread never claimed, so it counted for nothing.
+        assert_eq!(live_claims(), 1);
+        thread::spawn(|| assert!(OuterMutexPermission::get_or_diagnose().is_ok()))
+            .join()
+            .unwrap();
+    });
+}
+
+#[test]
+fn elided_guards_read_and_write_the_data() {
+    isolated(|| {
+        let stack = NetworkStack::new();
+        let mut permission = OuterMutexPermission::get();
+        {
+            let phase = SingleThreadedPhase::enter(&mut permission).unwrap();
+            let mut ip = stack.ip_layer.lock_elided(&phase);
+            ip.routing_table_size = 3;
+            drop(ip);
+            let mut device = stack.device_layer.lock_elided(&phase);
+            device.get_mut().interfaces_active = 2;
+            drop(device);
+            let transport = stack.transport_layer.lock_elided(&phase);
+            assert_eq!(transport.tcp_connections, 0);
+        }
+        #[cfg(feature = "tracking")]
+        assert_eq!(stack.ip_layer.version(), 1);
+        #[cfg(feature = "tracking")]
+        assert_eq!(stack.transport_layer.version(), 0);
+
+        // Workers see what the phase set up.
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+                assert_eq!(ip.routing_table_size, 3);
+                let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+                assert_eq!(device.interfaces_active, 2);
+            });
+        });
+    });
+}
+
+#[test]
+fn exclusive_access_needs_no_lock() {
+    isolated(|| {
+        let mut permission = OuterMutexPermission::get();
+        let mut mutex = DeadlockProofMutex::new(vec![1u32], unique_type!());
+        mutex.get_mut_exclusive(&mut permission).push(2);
+        assert_eq!(*mutex.lock(permission).guard(), [1, 2]);
+        #[cfg(feature = "tracking")]
+        assert_eq!(mutex.version(), 1);
+    });
+}
+
+#[test]
+fn elided_relock_of_a_held_mutex_panics() {
+    isolated(|| {
+        let result = std::panic::catch_unwind(|| {
+            let mut permission = OuterMutexPermission::get();
+            let mutex: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+                DeadlockProofMutex::new(0u32, unique_type!());
+            let phase = SingleThreadedPhase::enter(&mut permission).unwrap();
+            let _first = mutex.lock_elided(&phase);
+            let _second = mutex.lock_elided(&phase);
+        });
+        assert!(result.is_err());
+    });
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn locking_from_another_thread_during_a_phase_is_caught() {
+    isolated(|| {
+        use deadlock_proof::AsyncPermissionSlot;
+
+        // Async permissions are not root claims, so they can still reach a
+        // lock while the phase is held.
+        let mutex: DeadlockProofMutex<u32, deadlock_proof::AsyncPermission, _> =
+            DeadlockProofMutex::new(0u32, unique_type!());
+        let slot = AsyncPermissionSlot::new();
+        let mut permission = OuterMutexPermission::get();
+        let _phase = SingleThreadedPhase::enter(&mut permission).unwrap();
+        let result = thread::scope(|scope| {
+            scope
+                .spawn(|| mutex.lock(slot.claim().unwrap()).into_result().is_ok())
+                .join()
+        });
+        assert!(result.is_err());
+    });
+}
@@ -0,0 +1,64 @@
+use std::thread;
+
+use deadlock_proof::{LockOutcome, NetworkStack, OuterMutexPermission};
+
+#[test]
+fn skipping_the_device_layer_reaches_transport() {
+    let stack = NetworkStack::new();
+    let mut ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    ip.packets_processed += 1;
+    let permission = ip.unlock_for_sequential().skip_to();
+    let mut transport = stack.transport_layer.lock(permission).guard();
+    transport.udp_sockets += 1;
+
+    // Back up past the skipped level, as after a full walk.
+    let permission = transport.unlock().to_earlier().to_earlier();
+    let ip = stack.ip_layer.lock(permission).guard();
+    assert_eq!(ip.packets_processed, 1);
+    let transport = stack
+        .transport_layer
+        .lock(ip.unlock_for_sequential().skip_to());
+    assert_eq!(transport.guard().udp_sockets, 1);
+}
+
+#[test]
+fn skipping_and_walking_threads_do_not_deadlock() {
+    let stack = NetworkStack::new();
+    const ROUNDS: u32 = 1000;
+
+    thread::scope(|scope| {
+        for skip in [true, false, true, false] {
+            let stack = &stack;
+            scope.spawn(move || {
+                let mut permission = OuterMutexPermission::get();
+                for _ in 0..ROUNDS {
+                    let mut ip = stack.ip_layer.lock(permission).guard();
+                    ip.packets_processed += 1;
+                    let device = if skip {
+                        ip.unlock_for_sequential().skip_to()
+                    } else {
+                        let mut device =
+                            stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+                        device.bytes_transmitted += 1;
+                        device.unlock_for_sequential()
+                    };
+                    let mut transport = stack.transport_layer.lock(device).guard();
+                    transport.tcp_connections += 1;
+                    thread::yield_now();
+                    permission = transport.unlock().to_earlier().to_earlier();
+                }
+            });
+        }
+    });
+
+    let permission = OuterMutexPermission::get();
+    let ip = stack.ip_layer.lock(permission).guard();
+    assert_eq!(ip.packets_processed, u64::from(4 * ROUNDS));
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    assert_eq!(device.bytes_transmitted, u64::from(2 * ROUNDS));
+    let transport = stack
+        .transport_layer
+        .lock(device.unlock_for_sequential())
+        .guard();
+    assert_eq!(transport.tcp_connections, 4 * ROUNDS);
+}
@@ -0,0 +1,97 @@
+#![cfg(feature = "diff-log")]
+
+use std::{fmt, sync::Arc};
+
+use deadlock_proof::{
+    diff_log, snapshot::SnapshotStrategy, DeadlockProofMutex, LockOutcome, OuterMutexPermission,
+};
+
+/// Two megabytes of routes, printed as a summary so a logged change stays
+/// readable.
+#[derive(Clone, PartialEq)]
+struct Fib {
+    generation: u32,
+    entries: Vec<u8>,
+}
+
+impl Fib {
+    fn new() -> Self {
+        Self {
+            generation: 0,
+            entries: vec![0; 2 << 20],
+        }
+    }
+}
+
+impl fmt::Debug for Fib {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fib generation {} ({} bytes)",
+            self.generation,
+            self.entries.len()
+        )
+    }
+}
+
+/// Entries logged for the mutex named `name`.
+fn logged(name: &str) -> Vec<diff_log::DiffEntry> {
+    diff_log::recent()
+        .into_iter()
+        .filter(|entry| entry.identifier == name)
+        .collect()
+}
+
+#[test]
+fn a_shared_snapshot_copies_only_on_write() {
+    struct FibLock;
+    let table = Arc::new(Fib::new());
+    let original = Arc::downgrade(&table);
+    let fib: DeadlockProofMutex<Arc<Fib>, OuterMutexPermission, _> =
+        DeadlockProofMutex::new_with_diff_logging_by(table, FibLock, SnapshotStrategy::shared());
+
+    // Reading shares the data with the snapshot instead of copying it.
+    let guard = fib.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(Arc::strong_count(&guard), 2);
+    assert_eq!(Arc::as_ptr(&guard), original.as_ptr());
+    let mut guard = fib.lock(guard.unlock()).guard();
+    assert_eq!(Arc::strong_count(&guard), 2);
+    assert!(logged(std::any::type_name::<FibLock>()).is_empty());
+
+    // A write copies once, and the log sees the new data.
+    Arc::make_mut(&mut guard).generation += 1;
+    drop(guard);
+    let entries = logged(std::any::type_name::<FibLock>());
+    let [entry] = entries.as_slice() else {
+        panic!("expected one change, got {entries:?}");
+    };
+    assert_eq!(entry.before, "fib generation 0 (2097152 bytes)");
+    assert_eq!(entry.after, "fib generation 1 (2097152 bytes)");
+    // The snapshot was the last holder of the original.
+    assert_eq!(original.strong_count(), 0);
+}
+
+#[test]
+fn a_custom_snapshot_keeps_what_it_compares() {
+    struct HeaderLock;
+    // Only the generation is copied and compared.
+    let strategy = SnapshotStrategy::with(
+        |fib: &Fib| Fib {
+            generation: fib.generation,
+            entries: Vec::new(),
+        },
+        |before, after| before.generation == after.generation,
+    );
+    let fib: DeadlockProofMutex<Fib, OuterMutexPermission, _> =
+        DeadlockProofMutex::new_with_diff_logging_by(Fib::new(), HeaderLock, strategy);
+
+    let mut guard = fib.lock(OuterMutexPermission::get()).guard();
+    guard.entries[0] = 1;
+    let mut guard = fib.lock(guard.unlock()).guard();
+    assert!(logged(std::any::type_name::<HeaderLock>()).is_empty());
+    guard.generation = 7;
+    drop(guard);
+    let entries = logged(std::any::type_name::<HeaderLock>());
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].before, "fib generation 0 (0 bytes)");
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use deadlock_proof::soak::{self, Scenario, ScenarioMix, SoakConfig};
+
+#[test]
+fn short_soak_runs_every_scenario_cleanly() {
+    let report = soak::run(SoakConfig {
+        threads: 4,
+        duration: Duration::from_millis(200),
+        ..SoakConfig::default()
+    });
+
+    assert!(report.is_clean(), "{:?}", report.violations);
+    for scenario in Scenario::ALL {
+        assert!(report.operations(scenario) > 0, "{scenario:?} never ran");
+    }
+    assert!(report.elapsed >= Duration::from_millis(200));
+    assert!(report.throughput() > 0.0);
+    assert!(report.max_wait <= report.elapsed);
+}
+
+#[test]
+fn zero_weights_leave_scenarios_out() {
+    let report = soak::run(SoakConfig {
+        threads: 2,
+        duration: Duration::from_millis(50),
+        mix: ScenarioMix {
+            exclusive: 0,
+            nested: 0,
+            sequential: 0,
+            stack_walk: 1,
+        },
+        pool_size: 1,
+        ..SoakConfig::default()
+    });
+
+    assert!(report.is_clean(), "{:?}", report.violations);
+    assert_eq!(
+        report.operations(Scenario::StackWalk),
+        report.total_operations()
+    );
+    assert!(report.total_operations() > 0);
+}
+
+#[test]
+#[should_panic(expected = "every scenario has weight 0")]
+fn empty_mix_is_rejected() {
+    soak::run(SoakConfig {
+        mix: ScenarioMix {
+            exclusive: 0,
+            nested: 0,
+            sequential: 0,
+            stack_walk: 0,
+        },
+        ..SoakConfig::default()
+    });
+}
+
+#[cfg(feature = "diagnostics")]
+#[test]
+fn report_has_the_nesting_depth_of_every_worker() {
+    let report = soak::run(SoakConfig {
+        threads: 3,
+        duration: Duration::from_millis(50),
+        mix: ScenarioMix {
+            exclusive: 1,
+            nested: 1,
+            sequential: 0,
+            stack_walk: 0,
+        },
+        ..SoakConfig::default()
+    });
+
+    assert!(report.is_clean(), "{:?}", report.violations);
+    assert_eq!(report.thread_max_depths.len(), 3);
+    assert!(report.operations(Scenario::Nested) > 0);
+    // A pool mutex and the one nested in it.
+    assert_eq!(report.max_depth, 2);
+}
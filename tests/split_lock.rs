@@ -0,0 +1,132 @@
+use std::{sync::Barrier, thread, time::Duration};
+
+use deadlock_proof::{LockOutcome, OuterMutexPermission, SplitLock};
+
+struct InterfaceConfig {
+    active: u32,
+}
+
+struct TxCounters {
+    bytes: u64,
+}
+
+struct DeviceLock;
+
+fn device() -> SplitLock<InterfaceConfig, TxCounters, OuterMutexPermission, DeviceLock> {
+    SplitLock::new(
+        InterfaceConfig { active: 0 },
+        TxCounters { bytes: 0 },
+        DeviceLock,
+    )
+}
+
+#[test]
+fn the_two_halves_are_held_by_two_threads_at_once() {
+    let device = device();
+    // Both threads hold their half across the barrier, so it is only passed
+    // if the halves do not exclude each other.
+    let barrier = Barrier::new(2);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut config = device.lock_a(OuterMutexPermission::get()).guard();
+            config.active += 1;
+            barrier.wait();
+        });
+        scope.spawn(|| {
+            let mut counters = device.lock_b(OuterMutexPermission::get()).guard();
+            counters.bytes += 1500;
+            barrier.wait();
+        });
+    });
+
+    let both = device.lock_both(OuterMutexPermission::get()).guard();
+    assert_eq!(both.a().active, 1);
+    assert_eq!(both.b().bytes, 1500);
+}
+
+#[test]
+fn also_b_waits_for_the_thread_holding_b() {
+    let device = device();
+    let barrier = Barrier::new(2);
+    let permission = thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut counters = device.lock_b(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            thread::sleep(Duration::from_millis(10));
+            counters.bytes += 64;
+        });
+        barrier.wait();
+        // A is free while B is held.
+        let mut config = device.lock_a(OuterMutexPermission::get()).guard();
+        config.active = 2;
+        let mut both = config.also_b().guard();
+        let (config, counters) = both.parts_mut();
+        assert_eq!(counters.bytes, 64);
+        counters.bytes += u64::from(config.active);
+        both.unlock()
+    });
+
+    assert_eq!(device.lock_b(permission).guard().bytes, 66);
+}
+
+#[test]
+fn many_threads_on_each_half_keep_both_consistent() {
+    let device = device();
+    thread::scope(|scope| {
+        for thread in 0..8 {
+            let device = &device;
+            scope.spawn(move || {
+                let mut permission = OuterMutexPermission::get();
+                for _ in 0..100 {
+                    permission = match thread % 3 {
+                        0 => {
+                            let mut config = device.lock_a(permission).guard();
+                            config.active += 1;
+                            config.unlock()
+                        }
+                        1 => {
+                            let mut counters = device.lock_b(permission).guard();
+                            counters.bytes += 1;
+                            counters.unlock()
+                        }
+                        _ => {
+                            let mut both = device.lock_both(permission).guard();
+                            let (config, counters) = both.parts_mut();
+                            config.active += 1;
+                            counters.bytes += 1;
+                            both.unlock()
+                        }
+                    };
+                }
+            });
+        }
+    });
+
+    let both = device.lock_both(OuterMutexPermission::get()).guard();
+    // Threads 0, 3 and 6 lock A, 1, 4 and 7 lock B, 2 and 5 both.
+    assert_eq!(both.a().active, 500);
+    assert_eq!(both.b().bytes, 500);
+}
+
+#[cfg(not(feature = "no-poison"))]
+#[test]
+fn a_panic_poisons_only_its_half() {
+    let device = device();
+    thread::scope(|scope| {
+        let holder = scope.spawn(|| {
+            let _config = device.lock_a(OuterMutexPermission::get()).guard();
+            panic!("while configuring");
+        });
+        assert!(holder.join().is_err());
+    });
+
+    let permission = OuterMutexPermission::get();
+    let counters = device.lock_b(permission).guard();
+    let permission = counters.unlock();
+    let Err(poisoned) = device.lock_both(permission) else {
+        panic!("half A should be poisoned");
+    };
+    let both = poisoned.into_inner();
+    let permission = both.unlock();
+    assert!(device.lock_a(permission).is_err());
+}
@@ -0,0 +1,95 @@
+use deadlock_proof::{
+    construct_in_order, lock_hierarchy, DeadlockProofMutex, LockOutcome, NetworkStack,
+    OuterMutexPermission, Position,
+};
+
+#[test]
+fn stack_layers_are_initialized_from_each_other() {
+    let (permission, stack) = NetworkStack::new_with(OuterMutexPermission::get(), |walk| {
+        let stack = walk.stack();
+        walk.with_permission(|permission| {
+            let mut ip = stack.ip_layer.lock_in_context(permission).guard();
+            ip.routing_table_size = 3;
+            let routes = ip.routing_table_size;
+
+            let mut device = stack
+                .device_layer
+                .lock_in_context(ip.unlock_for_sequential())
+                .guard();
+            device.interfaces_active = routes as u32 * 2;
+            let interfaces = device.interfaces_active;
+
+            let mut transport = stack
+                .transport_layer
+                .lock_in_context(device.unlock_for_sequential())
+                .guard();
+            transport.udp_sockets = interfaces + 1;
+            (transport.unlock().to_earlier().to_earlier(), ())
+        })
+    });
+
+    let (_permission, views) = stack.views(permission);
+    assert_eq!(views.ip.routing_table_size, 3);
+    assert_eq!(views.device.interfaces_active, 6);
+    assert_eq!(views.transport.udp_sockets, 7);
+    assert_eq!(views.ip.packets_processed, 0);
+}
+
+struct ConfigLock;
+struct InterfaceLock;
+struct SocketLock;
+lock_hierarchy!(OuterMutexPermission => ConfigLock, InterfaceLock, SocketLock);
+
+struct Config {
+    ports_per_interface: u16,
+    interfaces: u16,
+}
+
+struct Layers {
+    config: DeadlockProofMutex<Config, Position<ConfigLock>, ConfigLock>,
+    interfaces: DeadlockProofMutex<Vec<u16>, Position<InterfaceLock>, InterfaceLock>,
+    sockets: DeadlockProofMutex<Vec<u16>, Position<SocketLock>, SocketLock>,
+}
+
+#[test]
+fn user_structs_are_built_in_hierarchy_order() {
+    let (permission, layers) = construct_in_order!(OuterMutexPermission::get() => Layers {
+        config: ConfigLock = Config {
+            ports_per_interface: 2,
+            interfaces: 3,
+        },
+        interfaces: InterfaceLock = |config| {
+            let first_port = |id| id * config.ports_per_interface;
+            (0..config.interfaces).map(first_port).collect::<Vec<_>>()
+        },
+        sockets: SocketLock = |first_ports| first_ports.iter().map(|port| port + 1).collect(),
+    });
+
+    // The permission is back at the root, so a normal walk follows.
+    let config = layers.config.lock(permission).guard();
+    let interfaces = layers
+        .interfaces
+        .lock(config.unlock_for_sequential())
+        .guard();
+    assert_eq!(*interfaces, [0, 2, 4]);
+    let sockets = layers
+        .sockets
+        .lock(interfaces.unlock_for_sequential())
+        .guard();
+    assert_eq!(*sockets, [1, 3, 5]);
+    // Reading a predecessor does not count as changing it.
+    #[cfg(feature = "tracking")]
+    assert_eq!(layers.config.version(), 0);
+}
+
+struct Single {
+    config: DeadlockProofMutex<u32, Position<ConfigLock>, ConfigLock>,
+}
+
+#[test]
+fn a_single_field_needs_no_walk() {
+    let (permission, single) = construct_in_order!(OuterMutexPermission::get() => Single {
+        config: ConfigLock = 7,
+    });
+    assert_eq!(*single.config.lock(permission).guard(), 7);
+}
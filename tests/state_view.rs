@@ -0,0 +1,125 @@
+use deadlock_proof::{
+    impl_state_view, DeadlockProofMutex, DeviceStateView, IcmpError, IpStateView, LockOutcome,
+    NetworkStack, OuterMutexPermission, Route, StackViews, TransportStateView,
+};
+use std::net::Ipv4Addr;
+
+fn assert_plain_data<T: Send + Sync + 'static>() {}
+
+#[test]
+fn views_are_plain_data() {
+    assert_plain_data::<IpStateView>();
+    assert_plain_data::<DeviceStateView>();
+    assert_plain_data::<TransportStateView>();
+    assert_plain_data::<StackViews>();
+}
+
+#[test]
+fn stack_views_copy_only_the_exposed_fields() {
+    let stack = NetworkStack::new();
+    let mut permission = OuterMutexPermission::get();
+    {
+        let mut ip = stack.ip_layer.lock(permission).guard();
+        ip.packets_processed = 7;
+        ip.routes
+            .add(Route {
+                destination: Ipv4Addr::new(10, 0, 0, 0),
+                prefix_len: 8,
+                next_hop: Ipv4Addr::new(192, 168, 1, 1),
+            })
+            .unwrap();
+        ip.routing_table_size = ip.routes.len();
+        let mut device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+        device.bytes_transmitted = 1500;
+        let mut transport = stack
+            .transport_layer
+            .lock(device.unlock_for_sequential())
+            .guard();
+        transport.udp_sockets = 2;
+        permission = transport
+            .unlock_for_sequential()
+            .to_earlier()
+            .to_earlier()
+            .to_earlier();
+    }
+    let mut walk = stack.begin_walk(permission);
+    walk.icmp_error_path(IcmpError::PortUnreachable);
+
+    let views = walk.views();
+    let (_permission, again) = stack.views(walk.finish());
+    assert_eq!(views, again);
+
+    // Exhaustive patterns, so an added field fails to compile here.
+    let IpStateView {
+        packets_processed,
+        routing_table_size,
+        icmp_errors_sent,
+        last_icmp_error,
+    } = views.ip;
+    assert_eq!(
+        (
+            packets_processed,
+            routing_table_size,
+            icmp_errors_sent,
+            last_icmp_error
+        ),
+        (7, 1, 1, Some(IcmpError::PortUnreachable))
+    );
+    let DeviceStateView {
+        interfaces_active,
+        bytes_transmitted,
+    } = views.device;
+    assert_eq!((interfaces_active, bytes_transmitted), (0, 1500));
+    let TransportStateView {
+        tcp_connections,
+        udp_sockets,
+        icmp_errors_sent,
+    } = views.transport;
+    assert_eq!((tcp_connections, udp_sockets, icmp_errors_sent), (0, 2, 1));
+
+    assert!(!stack.ip_layer.is_locked());
+}
+
+struct Queue {
+    pending: Vec<String>,
+    dropped: u64,
+    name: String,
+}
+
+impl_state_view!(
+    /// Everything but the queued items.
+    QueueView from Queue {
+        dropped: u64,
+        name: String,
+    }
+);
+
+#[test]
+fn guard_view_leaves_the_guard_usable() {
+    struct QueueLock;
+    let queue = DeadlockProofMutex::new(
+        Queue {
+            pending: vec!["a".into()],
+            dropped: 4,
+            name: "rx".into(),
+        },
+        QueueLock,
+    );
+    #[cfg(feature = "tracking")]
+    let version = queue.version();
+
+    let guard = queue.lock(OuterMutexPermission::get()).guard();
+    let view: QueueView = guard.view();
+    assert_eq!(guard.pending.len(), 1);
+    guard.unlock();
+
+    assert_eq!(
+        view,
+        QueueView {
+            dropped: 4,
+            name: "rx".into()
+        }
+    );
+    #[cfg(feature = "tracking")]
+    assert_eq!(queue.version(), version);
+}
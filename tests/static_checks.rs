@@ -0,0 +1,358 @@
+//! Pins the `Send`, `Sync` and `Unpin` status of every public type.
+//!
+//! Nothing here runs: each line is a compile-time assertion, so a change that
+//! flips an auto trait stops this file from building. A type that should
+//! implement a trait but does not fails with the usual unsatisfied-bound error
+//! on the line asserting it; one that should not but does fails with "type
+//! annotations needed" on that line.
+//!
+//! Generic types are pinned for a representative `T`: `u32` for data that is
+//! `Send + Sync`, `Cell<u32>` for data that is only `Send` and `Rc<u32>` for
+//! data that is neither.
+
+use std::{any::Any, cell::Cell, marker::PhantomPinned, rc::Rc};
+
+use deadlock_proof::{
+    backpressure::{BoundedUnderLock, Full, WaitForSpace},
+    concurrent::WorkerPanic,
+    declare_mutex_family,
+    fuzz_driver::{Execution, Op},
+    gate::{GateGuard, NestedGateGuard, OrderingGate},
+    budget::{Exhausted, LayerTiming, WalkAbandoned, WalkBudget, WalkOutcome},
+    lazy::InitPanicked,
+    lease::{Abandoned, Dropped, FinishToken, PermissionLease},
+    net_demo::{ConnectionLock, ConnectionRegistry, ConnectionStats, ShuttingDown},
+    notify::{AsyncNotify, Notified},
+    ordered_guards::{Leaf, Nested, Root},
+    permission::{ClaimDiagnostics, QuarantineToken, Quarantined, ThreadPermissionDebug},
+    poison::{NoPoison, Poisoning},
+    session::{Handle, Listen, StateCell, TcpState},
+    unclaimed::{AutoClaimGuard, ClaimError, Policy},
+    poll::PollLock,
+    reacquire::{Here, There},
+    retry::BackoffPolicy,
+    pool::{LeasedPermission, RetireReason, Retirement, WorkerPool},
+    soak::{Scenario, ScenarioMix, SoakConfig, SoakReport, Violation},
+    AsyncLock, AsyncMutexGuard, AsyncPermission, AsyncPermissionSlot, BlockingDelegate,
+    BlockingHandle, CancellableLockError, CompatGuard, Config, ContextGuard, ConfigureError, MisuseEvent, DeadlockProofAsyncMutex,
+    DeadlockProofMutex, DeadlockProofMutexGuard, DeadlockProofNestedMutexGuard,
+    DeadlockProofOwnedMutexGuard, DeadlockProofOwnedNestedMutexGuard, DeadlockProofPoisonError,
+    LockedOrGone,
+    WeakDeadlockProofMutex, DeadlockProofReadGuard, DeadlockProofRwLock, DeadlockProofWriteGuard, DeepSequentialPermission,
+    DelegationAbandoned, DeviceState, LazyDeadlockProofMutex, DeviceStateView, ElidedGuard, IcmpError, InNamespace, IpLock,
+    FamilyGuards, HierarchyGeneration, IpState, IpStateView, LockCancellation, MappedGuard, MaybeProofed, MutexConfig, MutexFamily,
+    NestedMutexPermission, NetworkStack, OrderedGuards, OwnedStates, OrderedLockMap, OuterMutexPermission,
+    PermissionCell, PermissionWith, PinnedLockError, RangeGuards, Region, RegionGuard, RejoinHandle, RootNamespace,
+    Route, RouteCache, RouteCacheStats, RoutingTable, RtHandle, ScopedGuard, SequentialCarry,
+    SequentialMutexPermission, SignalSafe, SignalSafeMutex, SingleThreadedPhase, SplitGuardA,
+    SplitGuardB, SplitGuardBoth, SplitLock, StackLayer, TcpSocket,
+    StackTransaction, StackViews, ThreadPinnedMutex, TransportState, TransportStateView,
+    TryLockError, TxAborted, VariantGuard, WalkCache, WalkToken, WithScratch,
+};
+
+/// Asserts that `$ty` implements every listed trait.
+macro_rules! assert_impl {
+    ($ty:ty: $($trait:path),+) => {
+        const _: fn() = || {
+            fn check<T: ?Sized $(+ $trait)+>() {}
+            check::<$ty>();
+        };
+    };
+}
+
+/// Asserts that `$ty` does not implement `$trait`. If it does, both impls of
+/// `AmbiguousIfImpl` apply and the call cannot be resolved.
+macro_rules! assert_not_impl {
+    ($ty:ty: $trait:path) => {
+        const _: fn() = || {
+            trait AmbiguousIfImpl<A> {
+                fn some_item() {}
+            }
+            impl<T: ?Sized> AmbiguousIfImpl<()> for T {}
+            struct Invalid;
+            impl<T: ?Sized + $trait> AmbiguousIfImpl<Invalid> for T {}
+            let _ = <$ty as AmbiguousIfImpl<_>>::some_item;
+        };
+    };
+}
+
+/// `auto_traits!(Ty: Send, !Sync, Unpin)` asserts each listed trait, or its
+/// absence when prefixed with `!`.
+macro_rules! auto_traits {
+    ($ty:ty: $($rest:tt)+) => {
+        auto_traits!(@each $ty; $($rest)+);
+    };
+    (@each $ty:ty; ! $trait:ident $(, $($rest:tt)+)?) => {
+        assert_not_impl!($ty: $trait);
+        $(auto_traits!(@each $ty; $($rest)+);)?
+    };
+    (@each $ty:ty; $trait:ident $(, $($rest:tt)+)?) => {
+        assert_impl!($ty: $trait);
+        $(auto_traits!(@each $ty; $($rest)+);)?
+    };
+}
+
+struct Id;
+
+type Outer = OuterMutexPermission;
+type Nest = NestedMutexPermission<Outer, Id>;
+
+declare_mutex_family!(Queue: Q0, Q1);
+
+// The macros themselves.
+auto_traits!(u32: Send, Sync, Unpin);
+auto_traits!(Rc<u32>: !Send, !Sync);
+auto_traits!(Cell<u32>: Send, !Sync);
+auto_traits!(PhantomPinned: !Unpin);
+
+// Permissions. Only the async ones may leave their thread.
+auto_traits!(Outer: !Send, !Sync, Unpin);
+auto_traits!(Nest: !Send, !Sync, Unpin);
+auto_traits!(SequentialMutexPermission<Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(DeepSequentialPermission<Outer, 3>: !Send, !Sync, Unpin);
+auto_traits!(SequentialCarry<Outer, Id, u32>: !Send, !Sync, Unpin);
+auto_traits!(PermissionWith<Outer, u32>: !Send, !Sync, Unpin);
+auto_traits!(PermissionCell<Outer>: !Send, !Sync, Unpin);
+auto_traits!(AsyncPermission: Send, Sync, Unpin);
+auto_traits!(PermissionCell<AsyncPermission>: Send, Sync, Unpin);
+auto_traits!(AsyncPermissionSlot: Send, Sync, Unpin);
+auto_traits!(SingleThreadedPhase<'static>: !Send, !Sync, Unpin);
+
+// Errors that hand a permission back are as thread-bound as it.
+auto_traits!(TryLockError<Outer>: !Send, !Sync, Unpin);
+auto_traits!(TryLockError<AsyncPermission>: Send, Sync, Unpin);
+auto_traits!(CancellableLockError<Outer>: !Send, !Sync, Unpin);
+auto_traits!(PinnedLockError<'static, u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(DeadlockProofPoisonError<'static, u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(TxAborted: Send, Sync, Unpin);
+auto_traits!(DelegationAbandoned: Send, Sync, Unpin);
+
+// Locks: shareable whenever `T: Send`, like `std::sync::Mutex`; the reader-
+// writer lock also needs `T: Sync`, like `std::sync::RwLock`.
+auto_traits!(DeadlockProofMutex<u32, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(DeadlockProofMutex<Cell<u32>, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(DeadlockProofMutex<Rc<u32>, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(DeadlockProofMutex<u32, Nest, Id>: Send, Sync, Unpin);
+auto_traits!(DeadlockProofMutex<WithScratch<u32, Vec<u8>>, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(DeadlockProofRwLock<u32, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(DeadlockProofRwLock<Cell<u32>, Outer, Id>: Send, !Sync, Unpin);
+auto_traits!(DeadlockProofRwLock<Rc<u32>, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(SplitLock<u32, u64, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(SplitLock<Cell<u32>, u64, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(SplitLock<Rc<u32>, u64, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(OrderingGate<Outer, Id>: Send, Sync, Unpin);
+auto_traits!(WeakDeadlockProofMutex<u32, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(DeadlockProofAsyncMutex<u32, AsyncPermission, Id>: Send, Sync, Unpin);
+auto_traits!(DeadlockProofAsyncMutex<Cell<u32>, AsyncPermission, Id>: Send, Sync, Unpin);
+auto_traits!(DeadlockProofAsyncMutex<Rc<u32>, AsyncPermission, Id>: !Send, !Sync, Unpin);
+auto_traits!(ThreadPinnedMutex<u32, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(ThreadPinnedMutex<Rc<u32>, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(SignalSafeMutex<u32, u64, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(SignalSafe<u64>: Send, Sync, Unpin);
+auto_traits!(SignalSafe<bool>: Send, Sync, Unpin);
+auto_traits!(OrderedLockMap<u32, u32, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(MutexFamily<u32, Outer, Queue, QueueId>: Send, Sync, Unpin);
+auto_traits!(LazyDeadlockProofMutex<u32, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(LazyDeadlockProofMutex<Cell<u32>, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(RtHandle<'static, u32, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(BlockingHandle<'static, u32, Outer, Id>: Send, Sync, Unpin);
+
+// Blocking guards hold the permission, so they stay on their thread whatever
+// `T` is.
+auto_traits!(DeadlockProofMutexGuard<'static, u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(DeadlockProofMutexGuard<'static, Cell<u32>, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(DeadlockProofNestedMutexGuard<'static, u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(DeadlockProofOwnedMutexGuard<u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(DeadlockProofOwnedNestedMutexGuard<u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(LockedOrGone<u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(DeadlockProofReadGuard<'static, u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(DeadlockProofWriteGuard<'static, u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(SplitGuardA<'static, u32, u64, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(SplitGuardB<'static, u64, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(SplitGuardBoth<'static, u32, u64, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(ScopedGuard<'static, u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(ContextGuard<'static, u32, Outer, Id, u32>: !Send, !Sync, Unpin);
+auto_traits!(GateGuard<'static, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(NestedGateGuard<'static, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(RegionGuard<'static, u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(Region<'static, 'static>: Send, Sync, Unpin);
+auto_traits!(RangeGuards<'static, u32, u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(FamilyGuards<'static, u32, Outer, QueueId>: !Send, !Sync, Unpin);
+auto_traits!(OrderedGuards<Root<Outer>>: !Send, !Sync, Unpin);
+auto_traits!(OrderedGuards<Nested<'static, Root<Outer>, u32, Id>>: !Send, !Sync, Unpin);
+auto_traits!(OrderedGuards<Leaf<'static, Root<Outer>, u32, Id>>: !Send, !Sync, Unpin);
+// Phase guards carry no permission and behave like `std::sync::MutexGuard`.
+auto_traits!(ElidedGuard<'static, u32>: !Send, Sync, Unpin);
+
+// Projected guards, including unsized targets.
+auto_traits!(MappedGuard<'static, [u8], Vec<u8>, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(MappedGuard<'static, str, String, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(MappedGuard<'static, dyn Any, u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(VariantGuard<'static, [u8], Vec<u8>, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(VariantGuard<'static, str, String, Outer, Id>: !Send, !Sync, Unpin);
+
+// Async guards and their futures may cross `.await` points on a
+// multi-threaded executor, with the auto traits of `&mut T`.
+auto_traits!(AsyncLock<'static, u32, AsyncPermission, Id>: Send, Sync, Unpin);
+auto_traits!(AsyncMutexGuard<'static, u32, AsyncPermission, Id>: Send, Sync, Unpin);
+auto_traits!(AsyncMutexGuard<'static, Cell<u32>, AsyncPermission, Id>: Send, !Sync, Unpin);
+auto_traits!(AsyncMutexGuard<'static, Rc<u32>, AsyncPermission, Id>: !Send, !Sync, Unpin);
+
+// Walks and transactions borrow the thread's permission.
+auto_traits!(WalkToken<'static>: !Send, !Sync, Unpin);
+auto_traits!(WalkCache: !Send, !Sync, Unpin);
+auto_traits!(StackTransaction<'static>: !Send, !Sync, Unpin);
+
+// Plain data.
+auto_traits!(NetworkStack: Send, Sync, Unpin);
+auto_traits!(IpState: Send, Sync, Unpin);
+auto_traits!(DeviceState: Send, Sync, Unpin);
+auto_traits!(TransportState: Send, Sync, Unpin);
+auto_traits!(Route: Send, Sync, Unpin);
+auto_traits!(RoutingTable: Send, Sync, Unpin);
+auto_traits!(IcmpError: Send, Sync, Unpin);
+auto_traits!(IpStateView: Send, Sync, Unpin);
+auto_traits!(DeviceStateView: Send, Sync, Unpin);
+auto_traits!(TransportStateView: Send, Sync, Unpin);
+auto_traits!(StackViews: Send, Sync, Unpin);
+auto_traits!(StackLayer: Send, Sync, Unpin);
+auto_traits!(IpLock: Send, Sync, Unpin);
+auto_traits!(RouteCache: Send, Sync, Unpin);
+auto_traits!(RouteCacheStats: Send, Sync, Unpin);
+auto_traits!(MutexConfig: Send, Sync, Unpin);
+auto_traits!(LockCancellation: Send, Sync, Unpin);
+auto_traits!(Config: Send, Sync, Unpin);
+auto_traits!(BackoffPolicy: Send, Sync, Unpin);
+auto_traits!(InitPanicked: Send, Sync, Unpin);
+auto_traits!(ConfigureError: Send, Sync, Unpin);
+auto_traits!(MisuseEvent: Send, Sync, Unpin);
+auto_traits!(HierarchyGeneration: Send, Sync, Unpin);
+auto_traits!(TcpState: Send, Sync, Unpin);
+auto_traits!(StateCell<TcpState>: Send, Sync, Unpin);
+auto_traits!(Handle<'static, Listen>: Send, Sync, Unpin);
+auto_traits!(TcpSocket: Send, Sync, Unpin);
+auto_traits!(OwnedStates: Send, Sync, Unpin);
+auto_traits!(Policy: Send, Sync, Unpin);
+auto_traits!(ClaimError: Send, Sync, Unpin);
+auto_traits!(AutoClaimGuard<'static, u32, Id>: !Send, !Sync, Unpin);
+auto_traits!(WalkBudget: Send, Sync, Unpin);
+auto_traits!(LayerTiming: Send, Sync, Unpin);
+auto_traits!(Exhausted: Send, Sync, Unpin);
+auto_traits!(WalkAbandoned: Send, Sync, Unpin);
+auto_traits!(WalkOutcome: Send, Sync, Unpin);
+auto_traits!(WithScratch<u32, Vec<u8>>: Send, Sync, Unpin);
+auto_traits!(RootNamespace: Send, Sync, Unpin);
+auto_traits!(InNamespace<RootNamespace, Id>: Send, Sync, Unpin);
+auto_traits!(Poisoning: Send, Sync, Unpin);
+auto_traits!(NoPoison: Send, Sync, Unpin);
+auto_traits!(ThreadPermissionDebug: Send, Sync, Unpin);
+auto_traits!(ClaimDiagnostics: Send, Sync, Unpin);
+auto_traits!(Quarantined: Send, Sync, Unpin);
+auto_traits!(QuarantineToken: !Send, !Sync, Unpin);
+auto_traits!(BlockingDelegate: Send, Sync, Unpin);
+auto_traits!(RejoinHandle: Send, Sync, Unpin);
+// Holds the panic payload, which is only `Send`.
+auto_traits!(WorkerPanic: Send, !Sync, Unpin);
+auto_traits!(Op: Send, Sync, Unpin);
+auto_traits!(Execution: Send, Sync, Unpin);
+auto_traits!(AsyncNotify: Send, Sync, Unpin);
+auto_traits!(BoundedUnderLock<u32>: Send, Sync, Unpin);
+auto_traits!(Full<u32>: Send, Sync, Unpin);
+auto_traits!(WaitForSpace: Send, Sync, Unpin);
+auto_traits!(MaybeProofed<'static, u32, Outer, Id>: Send, Sync, Unpin);
+auto_traits!(CompatGuard<'static, u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(Notified<'static>: Send, Sync, Unpin);
+auto_traits!(WorkerPool: Send, Sync, Unpin);
+auto_traits!(PermissionLease: !Send, !Sync, Unpin);
+auto_traits!(FinishToken: !Send, !Sync, Unpin);
+auto_traits!(Abandoned: Send, Sync, Unpin);
+auto_traits!(Dropped: Send, Sync, Unpin);
+auto_traits!(LeasedPermission: !Send, !Sync, Unpin);
+auto_traits!(Retirement: Send, Sync, Unpin);
+auto_traits!(RetireReason: Send, Sync, Unpin);
+auto_traits!(PollLock<'static, 'static, u32, AsyncPermission, Id>: Send, Sync, Unpin);
+auto_traits!(PollLock<'static, 'static, u32, Outer, Id>: !Send, !Sync, Unpin);
+auto_traits!(Here: Send, Sync, Unpin);
+auto_traits!(There<Here>: Send, Sync, Unpin);
+auto_traits!(ConnectionLock: Send, Sync, Unpin);
+auto_traits!(ConnectionRegistry: Send, Sync, Unpin);
+auto_traits!(ConnectionStats: Send, Sync, Unpin);
+auto_traits!(ShuttingDown: Send, Sync, Unpin);
+auto_traits!(Scenario: Send, Sync, Unpin);
+auto_traits!(ScenarioMix: Send, Sync, Unpin);
+auto_traits!(SoakConfig: Send, Sync, Unpin);
+auto_traits!(Violation: Send, Sync, Unpin);
+auto_traits!(SoakReport: Send, Sync, Unpin);
+
+// Type-erased handles for C callers. The mutex is shared between C threads;
+// the permission belongs to the thread that claimed it.
+#[cfg(feature = "ffi")]
+auto_traits!(deadlock_proof::ffi::DpmMutex: Send, Sync, Unpin);
+#[cfg(feature = "ffi")]
+auto_traits!(deadlock_proof::ffi::DpmPermission: !Send, !Sync, Unpin);
+
+#[cfg(feature = "tracking")]
+mod tracking {
+    use deadlock_proof::{
+        poison_watch::PoisonEvent, snapshot::SnapshotStrategy, version::ChangeCheck,
+        ContentionEvent,
+    };
+
+    use super::Rc;
+
+    // Plain function pointers, whatever the data.
+    auto_traits!(SnapshotStrategy<Rc<u32>>: Send, Sync, Unpin);
+    auto_traits!(PoisonEvent: Send, Sync, Unpin);
+    auto_traits!(ContentionEvent: Send, Sync, Unpin);
+    auto_traits!(ChangeCheck<'static, u32>: Send, Sync, Unpin);
+}
+
+#[cfg(feature = "metrics")]
+auto_traits!(deadlock_proof::metrics::Histogram: Send, Sync, Unpin);
+
+#[cfg(feature = "diff-log")]
+auto_traits!(deadlock_proof::diff_log::DiffEntry: Send, Sync, Unpin);
+
+#[cfg(feature = "diagnostics")]
+auto_traits!(deadlock_proof::diagnostics::HolderInfo: Send, Sync, Unpin);
+
+#[cfg(feature = "priority")]
+auto_traits!(deadlock_proof::priority::Priority: Send, Sync, Unpin);
+
+#[cfg(feature = "test-clock")]
+mod test_clock {
+    use deadlock_proof::clock::PausedClock;
+
+    // Holds the guard that keeps other tests from pausing the clock.
+    auto_traits!(PausedClock: !Send, Sync, Unpin);
+}
+
+#[cfg(feature = "strategy-bench")]
+mod strategy_bench {
+    use deadlock_proof::strategy_bench::{Strategy, StrategyReport, StrategyResult, Workload};
+
+    auto_traits!(Strategy: Send, Sync, Unpin);
+    auto_traits!(Workload: Send, Sync, Unpin);
+    auto_traits!(StrategyResult: Send, Sync, Unpin);
+    auto_traits!(StrategyReport: Send, Sync, Unpin);
+}
+
+// Holds the lock that runs configured tests one at a time.
+#[cfg(feature = "test-util")]
+auto_traits!(deadlock_proof::setup::TestConfiguration: !Send, Sync, Unpin);
+
+#[cfg(all(feature = "shared-memory", target_os = "linux"))]
+mod shared_memory {
+    use deadlock_proof::shared_memory::{
+        PreviousOwnerDied, SharedDeadlockProofMutex, SharedGuard, SharedNestedGuard,
+    };
+
+    use super::{Id, Outer};
+
+    auto_traits!(SharedDeadlockProofMutex<u64, Outer, Id>: Send, Sync, Unpin);
+    // A pthread mutex has to be unlocked by the thread that locked it, even
+    // when the permission could move.
+    auto_traits!(SharedGuard<'static, u64, deadlock_proof::AsyncPermission, Id>: !Send, !Sync, Unpin);
+    auto_traits!(SharedNestedGuard<'static, u64, Outer, Id>: !Send, !Sync, Unpin);
+    auto_traits!(PreviousOwnerDied<u32>: !Send, !Sync, Unpin);
+}
@@ -0,0 +1,47 @@
+#![cfg(feature = "strategy-bench")]
+
+use std::time::Duration;
+
+use deadlock_proof::strategy_bench::{self, Strategy, Workload};
+
+fn small(threads: usize) -> Workload {
+    Workload {
+        threads,
+        operations: 100,
+        first_hold: Duration::from_micros(2),
+        between: Duration::from_micros(2),
+        second_hold: Duration::ZERO,
+    }
+}
+
+#[test]
+fn both_strategies_complete_every_operation() {
+    let report = strategy_bench::run(&small(3));
+    assert_eq!(report.nested.strategy, Strategy::Nested);
+    assert_eq!(report.sequential.strategy, Strategy::Sequential);
+    for result in [&report.nested, &report.sequential] {
+        assert_eq!(result.operations, 300);
+        assert!(result.p50 <= result.p99 && result.p99 <= result.max);
+        assert!(result.throughput() > 0.0);
+    }
+    let printed = report.to_string();
+    assert!(
+        printed.contains("Nested") && printed.contains("Sequential"),
+        "{printed}"
+    );
+}
+
+#[test]
+fn the_latency_covers_the_work_of_an_operation() {
+    let result = strategy_bench::run_strategy(Strategy::Sequential, &small(1));
+    assert_eq!(result.operations, 100);
+    // Alone, an operation still has to do both phases of its work.
+    assert!(result.p50 >= Duration::from_micros(4), "{result:?}");
+    assert!(result.elapsed >= Duration::from_micros(400), "{result:?}");
+}
+
+#[test]
+#[should_panic = "at least one thread"]
+fn a_workload_needs_threads() {
+    strategy_bench::run_strategy(Strategy::Nested, &small(0));
+}
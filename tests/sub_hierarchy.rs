@@ -0,0 +1,116 @@
+//! Sequential chains inside a nested mutex: a host whose interface holds its
+//! own ordered queues, declared with `lock_hierarchy!(within ..)`.
+
+use deadlock_proof::{
+    concurrent, lock_hierarchy, DeadlockProofMutex, LockOutcome, OuterMutexPermission, Position,
+};
+
+struct HostLock;
+struct InterfaceLock;
+struct NeighborLock;
+struct TxQueueLock;
+struct RxQueueLock;
+
+lock_hierarchy!(OuterMutexPermission => HostLock);
+lock_hierarchy!(within HostLock => InterfaceLock, NeighborLock);
+lock_hierarchy!(within InterfaceLock => TxQueueLock, RxQueueLock);
+
+type Mutex<T, I> = DeadlockProofMutex<T, Position<I>, I>;
+
+struct Host {
+    packets: Mutex<u64, HostLock>,
+    interface_up: Mutex<bool, InterfaceLock>,
+    neighbors: Mutex<Vec<u8>, NeighborLock>,
+    tx_queue: Mutex<Vec<u64>, TxQueueLock>,
+    rx_queue: Mutex<Vec<u64>, RxQueueLock>,
+}
+
+impl Host {
+    fn new() -> Self {
+        Self {
+            packets: DeadlockProofMutex::new(0, HostLock),
+            interface_up: DeadlockProofMutex::new(true, InterfaceLock),
+            neighbors: DeadlockProofMutex::new(Vec::new(), NeighborLock),
+            tx_queue: DeadlockProofMutex::new(Vec::new(), TxQueueLock),
+            rx_queue: DeadlockProofMutex::new(Vec::new(), RxQueueLock),
+        }
+    }
+
+    /// Moves one packet from the transmit to the receive queue of the
+    /// interface, then notes the neighbor it came from, all under the host
+    /// lock.
+    fn loop_back(&self, permission: OuterMutexPermission, neighbor: u8) -> OuterMutexPermission {
+        let (mut packets, in_host) = self.packets.lock_for_nested(permission).guard();
+        *packets += 1;
+        let (interface, in_interface) = self.interface_up.lock_for_nested(in_host).guard();
+        assert!(*interface);
+
+        let mut tx = self.tx_queue.lock(in_interface).guard();
+        tx.push(*packets);
+        let packet = tx.pop().unwrap();
+        let mut rx = self.rx_queue.lock(tx.unlock_for_sequential()).guard();
+        rx.push(packet);
+        let in_interface = rx.unlock_for_sequential().to_earlier().to_earlier();
+
+        let mut neighbors = self
+            .neighbors
+            .lock(interface.unlock_for_sequential(in_interface))
+            .guard();
+        neighbors.push(neighbor);
+        let in_host = neighbors.unlock_for_sequential().to_earlier().to_earlier();
+        packets.unlock(in_host)
+    }
+}
+
+#[test]
+fn chain_inside_a_nested_mutex_composes_end_to_end() {
+    let host = Host::new();
+    let mut permission = OuterMutexPermission::get();
+    for neighbor in 0..3 {
+        permission = host.loop_back(permission, neighbor);
+    }
+
+    // Back out through the interface without visiting the neighbors.
+    let (packets, in_host) = host.packets.lock_for_nested(permission).guard();
+    let (interface, in_interface) = host.interface_up.lock_for_nested(in_host).guard();
+    let tx = host.tx_queue.lock(in_interface).guard();
+    let rx = host.rx_queue.lock(tx.unlock_for_sequential()).guard();
+    assert_eq!(*rx, [1, 2, 3]);
+    let in_interface = rx.unlock_for_sequential().to_earlier().to_earlier();
+    let _permission = packets.unlock(interface.unlock(in_interface));
+}
+
+#[test]
+fn sub_hierarchy_walks_from_four_threads() {
+    const WALKS: u64 = 250;
+    let host = Host::new();
+    let workers = (0..4u8)
+        .map(|thread| -> concurrent::Worker<'_> {
+            let host = &host;
+            Box::new(move |mut permission| {
+                for _ in 0..WALKS {
+                    permission = host.loop_back(permission, thread);
+                }
+                permission
+            })
+        })
+        .collect();
+    concurrent::run_all(workers).unwrap();
+
+    let (packets, in_host) = host
+        .packets
+        .lock_for_nested(OuterMutexPermission::get())
+        .guard();
+    assert_eq!(*packets, 4 * WALKS);
+    let (interface, in_interface) = host.interface_up.lock_for_nested(in_host).guard();
+    let tx = host.tx_queue.lock(in_interface).guard();
+    assert!(tx.is_empty());
+    let rx = host.rx_queue.lock(tx.unlock_for_sequential()).guard();
+    assert_eq!(rx.len() as u64, 4 * WALKS);
+    let in_interface = rx.unlock_for_sequential().to_earlier().to_earlier();
+    let neighbors = host
+        .neighbors
+        .lock(interface.unlock_for_sequential(in_interface))
+        .guard();
+    assert_eq!(neighbors.len() as u64, 4 * WALKS);
+}
@@ -0,0 +1,108 @@
+use std::{sync::Barrier, thread};
+
+use deadlock_proof::{
+    declare_mutex_family, sweep, DeadlockProofMutex, LockOutcome, MutexFamily, OrderedLockMap,
+    OuterMutexPermission,
+};
+
+struct SessionLock;
+
+type Session = DeadlockProofMutex<Vec<u32>, OuterMutexPermission, SessionLock>;
+
+fn sessions() -> Vec<Session> {
+    (0..5)
+        .map(|session| Session::new(vec![session, 100 + session], SessionLock))
+        .collect()
+}
+
+declare_mutex_family!(QueueLock: Rx, Tx, Control);
+
+#[test]
+fn sweeps_visit_every_mutex_in_order() {
+    let sessions = sessions();
+    let mut visited = Vec::new();
+    let permission = sweep::for_each_sequential(&sessions, OuterMutexPermission::get(), |idle| {
+        visited.push(idle[0]);
+        idle.retain(|&seconds| seconds < 100);
+    });
+    assert_eq!(visited, [0, 1, 2, 3, 4]);
+
+    let queues: MutexFamily<u32, OuterMutexPermission, QueueLock, QueueLockId> =
+        MutexFamily::new(QueueLock, |id| id as u32);
+    let permission = queues.sweep(permission, |queue| *queue += 10);
+    let permission = queues.lock_each_in_declared_order(permission, |id, queue| {
+        assert_eq!(*queue, id as u32 + 10);
+    });
+    assert_eq!(*sessions[3].lock(permission).guard(), [3]);
+}
+
+#[test]
+fn try_sweeps_skip_what_is_held_elsewhere() {
+    let sessions = sessions();
+    let queues: MutexFamily<u32, OuterMutexPermission, QueueLock, QueueLockId> =
+        MutexFamily::new(QueueLock, |_| 0);
+    let barrier = Barrier::new(3);
+    let permission = thread::scope(|scope| {
+        scope.spawn(|| {
+            let session = sessions[2].lock(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            barrier.wait();
+            session.unlock();
+        });
+        scope.spawn(|| {
+            let tx = queues.get(QueueLockId::Tx).lock(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            barrier.wait();
+            tx.unlock();
+        });
+        barrier.wait();
+        let (permission, skipped) =
+            sweep::try_for_each_sequential(&sessions, OuterMutexPermission::get(), Vec::clear);
+        let (permission, queues_skipped) = queues.try_sweep(permission, |queue| *queue += 1);
+        barrier.wait();
+        assert_eq!(skipped, [2]);
+        assert!(matches!(queues_skipped.as_slice(), [QueueLockId::Tx]));
+        permission
+    });
+
+    let mut lengths = Vec::new();
+    let permission = sweep::for_each_sequential(&sessions, permission, |idle| {
+        lengths.push(idle.len());
+    });
+    assert_eq!(lengths, [0, 0, 2, 0, 0]);
+    let mut queued = Vec::new();
+    queues.sweep(permission, |queue| queued.push(*queue));
+    assert_eq!(queued, [1, 0, 1]);
+}
+
+#[test]
+fn map_sweeps_take_one_entry_at_a_time_in_key_order() {
+    struct ConnectionLock;
+    let connections = OrderedLockMap::<u16, u32, OuterMutexPermission, _>::new(ConnectionLock);
+    let mut permission = OuterMutexPermission::get();
+    for port in [443, 22, 80, 8080] {
+        permission = connections.insert(permission, port, 0).0;
+    }
+    let barrier = Barrier::new(2);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let held = connections.lock_range(OuterMutexPermission::get(), 80..=80);
+            barrier.wait();
+            barrier.wait();
+            held.unlock_all();
+        });
+        barrier.wait();
+        let mut visited = Vec::new();
+        let (permission, skipped) = connections.try_sweep(permission, |&port, idle| {
+            visited.push(port);
+            *idle += 1;
+        });
+        barrier.wait();
+        assert_eq!(visited, [22, 443, 8080]);
+        assert_eq!(skipped, [80]);
+
+        let mut idle = Vec::new();
+        connections.sweep(permission, |&port, seconds| idle.push((port, *seconds)));
+        assert_eq!(idle, [(22, 1), (80, 0), (443, 1), (8080, 1)]);
+    });
+}
@@ -0,0 +1,37 @@
+#![cfg(feature = "diagnostics")]
+
+use std::thread;
+
+use deadlock_proof::{
+    declare_mutex_family, lock_stats, sweep, DeadlockProofMutex, MutexFamily, OuterMutexPermission,
+};
+
+struct SessionLock;
+
+declare_mutex_family!(QueueLock: Rx, Tx, Control);
+
+#[test]
+fn sweeps_never_hold_two_mutexes() {
+    // A thread of its own, so its maximum is this test's alone.
+    thread::spawn(|| {
+        let sessions: Vec<DeadlockProofMutex<u32, OuterMutexPermission, _>> = (0..8)
+            .map(|_| DeadlockProofMutex::new(0, SessionLock))
+            .collect();
+        let queues: MutexFamily<u32, OuterMutexPermission, QueueLock, QueueLockId> =
+            MutexFamily::new(QueueLock, |_| 0);
+
+        let permission =
+            sweep::for_each_sequential(&sessions, OuterMutexPermission::get(), |count| {
+                assert_eq!(lock_stats::thread_max_depth(), 1);
+                *count += 1;
+            });
+        let (permission, skipped) =
+            sweep::try_for_each_sequential(&sessions, permission, |count| *count += 1);
+        assert!(skipped.is_empty());
+        let permission = queues.sweep(permission, |count| *count += 1);
+        let (_permission, _) = queues.try_sweep(permission, |count| *count += 1);
+        assert_eq!(lock_stats::thread_max_depth(), 1);
+    })
+    .join()
+    .unwrap();
+}
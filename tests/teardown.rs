@@ -0,0 +1,79 @@
+use std::{
+    mem,
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use deadlock_proof::{
+    teardown, DeadlockProofMutex, LockOutcome, NetworkStack, OuterMutexPermission, TearDown,
+};
+
+#[test]
+fn an_unshared_idle_stack_comes_apart() {
+    let stack = Arc::new(NetworkStack::new());
+    stack
+        .ip_layer
+        .lock(OuterMutexPermission::get())
+        .guard()
+        .packets_processed = 7;
+    let stack = stack.try_teardown().ok().unwrap();
+    assert_eq!(stack.ip.packets_processed, 7);
+    assert!(stack.route_cache.is_empty());
+}
+
+#[test]
+fn a_shared_or_locked_stack_is_handed_back() {
+    let stack = Arc::new(NetworkStack::new());
+    let weak = Arc::downgrade(&stack);
+    let stack = stack.try_teardown().err().unwrap();
+    drop(weak);
+
+    // A leaked guard keeps its layer locked.
+    mem::forget(stack.ip_layer.lock(OuterMutexPermission::get()));
+    let stack = stack.try_teardown().err().unwrap();
+    assert!(stack.ip_layer.is_locked());
+    let deadline = Instant::now() + Duration::from_millis(20);
+    assert!(stack.teardown_when_idle(deadline).is_err());
+}
+
+#[test]
+fn teardown_waits_for_a_straggler() {
+    let stack = Arc::new(NetworkStack::new());
+    let (locked, until_locked) = mpsc::channel();
+    let straggler = Arc::clone(&stack);
+    let worker = thread::spawn(move || {
+        let ip = straggler.ip_layer.lock(OuterMutexPermission::get()).guard();
+        let device = straggler
+            .device_layer
+            .lock(ip.unlock_for_sequential())
+            .guard();
+        let mut transport = straggler
+            .transport_layer
+            .lock(device.unlock_for_sequential())
+            .guard();
+        locked.send(()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        transport.icmp_errors_sent += 1;
+    });
+    until_locked.recv().unwrap();
+    let stack = stack.try_teardown().err().unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let states = stack.teardown_when_idle(deadline).ok().unwrap();
+    assert_eq!(states.transport.icmp_errors_sent, 1);
+    worker.join().unwrap();
+}
+
+#[test]
+fn tuples_of_locks_come_apart_together() {
+    struct CountLock;
+    struct NameLock;
+    let count: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+        DeadlockProofMutex::new(3, CountLock);
+    let name: DeadlockProofMutex<&str, OuterMutexPermission, _> =
+        DeadlockProofMutex::new("eth0", NameLock);
+    let pair = Arc::new((count, name));
+    assert!(pair.is_idle());
+    assert_eq!(teardown::try_teardown(pair).ok(), Some((3, "eth0")));
+}
@@ -0,0 +1,144 @@
+//! The time-based tests again, on the paused clock, where they run in
+//! milliseconds and always see the same times. Every test here pauses the
+//! clock, so they run one at a time.
+#![cfg(feature = "test-clock")]
+
+use std::{
+    mem,
+    ops::ControlFlow,
+    sync::{mpsc, Arc, Barrier},
+    thread,
+    time::{Duration, Instant},
+};
+
+use deadlock_proof::{
+    budget::{Exhausted, WalkAbandoned, WalkBudget},
+    clock,
+    pool::{RetireReason, WorkerPool},
+    retry::{with_backoff, BackoffPolicy},
+    unique_type, DeadlockProofMutex, LockOutcome, NetworkStack, OuterMutexPermission,
+};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+#[test]
+fn a_slow_layer_uses_up_the_overall_budget_exactly() {
+    let _paused = clock::pause();
+    let stack = NetworkStack::new();
+    let budget = WalkBudget {
+        overall: Duration::from_millis(5),
+        per_layer: [MINUTE; 3],
+    };
+    let (_permission, outcome) = stack.process_packet_with_budget(
+        OuterMutexPermission::get(),
+        budget,
+        |_| clock::advance(Duration::from_millis(10)),
+        |_| unreachable!("the budget ran out at the IP layer"),
+        |_| {},
+    );
+
+    assert_eq!(
+        outcome.abandoned,
+        Some(WalkAbandoned {
+            layer: "DeviceLock",
+            exhausted: Exhausted::Overall,
+        })
+    );
+    assert_eq!(outcome.layers[0].waited, Duration::ZERO);
+    assert_eq!(outcome.layers[0].held, Duration::from_millis(10));
+}
+
+#[test]
+fn minute_long_waits_time_out_at_once() {
+    let _paused = clock::pause();
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    let barrier = Barrier::new(2);
+    let real_start = Instant::now();
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let guard = mutex.lock(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            barrier.wait();
+            guard.unlock();
+        });
+        barrier.wait();
+        let start = clock::now();
+        let error = mutex
+            .rt_handle()
+            .try_lock_for(OuterMutexPermission::get(), MINUTE)
+            .err()
+            .unwrap();
+        assert!(clock::now() - start >= MINUTE);
+
+        let policy = BackoffPolicy {
+            timeout: Some(MINUTE),
+            max_attempts: u32::MAX,
+            ..BackoffPolicy::default()
+        };
+        let start = clock::now();
+        let (_permission, gave) = with_backoff(&mutex, error.into_permission(), policy, |_| {
+            ControlFlow::Break(())
+        });
+        assert_eq!(gave, None);
+        let waited = clock::now() - start;
+        assert!(
+            waited <= MINUTE && waited >= MINUTE - policy.max,
+            "{waited:?}"
+        );
+        barrier.wait();
+    });
+    assert!(real_start.elapsed() < Duration::from_secs(30));
+}
+
+#[test]
+fn an_hour_long_lease_runs_out_when_the_clock_says() {
+    let _paused = clock::pause();
+    let pool = WorkerPool::new(1, Duration::from_secs(3600));
+    let leaked = Arc::new(DeadlockProofMutex::new(0u32, unique_type!()));
+    let (started, job_started) = mpsc::channel();
+    pool.submit("leaky", move |lease| {
+        lease.with_permission(|permission| {
+            mem::forget(leaked.lock(permission).guard());
+            started.send(()).unwrap();
+            loop {
+                thread::park();
+            }
+        })
+    });
+    job_started.recv().unwrap();
+    thread::sleep(Duration::from_millis(20));
+    assert!(pool.retirements().is_empty());
+
+    clock::advance(Duration::from_secs(3600));
+    let real_start = Instant::now();
+    while pool.retirements().is_empty() {
+        assert!(real_start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(pool.retirements()[0].reason, RetireReason::LeaseExpired);
+}
+
+#[test]
+fn auto_advance_moves_every_reading_on_until_resumed() {
+    let stack = Arc::new(NetworkStack::new());
+    let shared = Arc::clone(&stack);
+    {
+        let _paused = clock::pause();
+        let frozen = clock::now();
+        assert_eq!(clock::now(), frozen);
+
+        clock::set_auto_advance(Duration::from_millis(1));
+        let first = clock::now();
+        assert_eq!(clock::now() - first, Duration::from_millis(1));
+        // Still shared: the teardown waits out its deadline.
+        let stack = stack.teardown_when_idle(first + MINUTE).err().unwrap();
+        assert!(clock::now() >= first + MINUTE);
+        drop(stack);
+    }
+    // Resumed: the clock is the real one again.
+    let before = Instant::now();
+    let now = clock::now();
+    assert!(now >= before && now <= Instant::now());
+    drop(shared);
+}
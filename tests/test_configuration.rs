@@ -0,0 +1,34 @@
+#![cfg(feature = "test-util")]
+
+use deadlock_proof::{lease, setup};
+
+#[test]
+fn tears_down_lease_strictness() {
+    let configured = setup::configure_for_tests();
+    assert!(!lease::is_strict());
+    lease::set_strict(true);
+    drop(configured);
+    assert!(!lease::is_strict());
+}
+
+#[test]
+fn leaves_the_once_flag_alone() {
+    let _configured = setup::configure_for_tests();
+    assert!(!setup::is_configured());
+}
+
+#[cfg(feature = "diagnostics")]
+#[test]
+fn starts_a_new_depth_window() {
+    use deadlock_proof::{lock_stats, LockOutcome, NetworkStack, OuterMutexPermission};
+
+    let configured = setup::configure_for_tests();
+    let stack = NetworkStack::new();
+    let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+    ip.unlock();
+    assert_eq!(lock_stats::thread_max_depth(), 1);
+
+    drop(configured);
+    let _configured = setup::configure_for_tests();
+    assert_eq!(lock_stats::thread_max_depth(), 0);
+}
@@ -0,0 +1,59 @@
+use std::{sync::Arc, thread};
+
+use deadlock_proof::{
+    unique_type, LockOutcome, OuterMutexPermission, PinnedLockError, ThreadPinnedMutex,
+};
+
+#[test]
+fn owner_thread_can_lock() {
+    let permission = OuterMutexPermission::get();
+    let mutex = ThreadPinnedMutex::new(0u32, unique_type!(), &permission);
+    assert_eq!(mutex.owner(), thread::current().id());
+
+    let mut guard = mutex.lock(permission).unwrap();
+    *guard += 1;
+    let permission = guard.unlock();
+    assert_eq!(*mutex.lock(permission).unwrap(), 1);
+}
+
+#[test]
+fn other_thread_gets_its_permission_back() {
+    let permission = OuterMutexPermission::get();
+    let mutex = ThreadPinnedMutex::new(0u32, unique_type!(), &permission);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let permission = match mutex.lock(OuterMutexPermission::get()) {
+                Err(PinnedLockError::WrongThread(permission)) => permission,
+                Err(other) => panic!("expected WrongThread, got {other}"),
+                Ok(_) => panic!("locked from a foreign thread"),
+            };
+            // The recovered permission still works on ordinary mutexes.
+            let unpinned = deadlock_proof::DeadlockProofMutex::new(5u32, unique_type!());
+            assert_eq!(*unpinned.lock(permission).guard(), 5);
+        });
+    });
+
+    assert_eq!(*mutex.lock(permission).unwrap(), 0);
+}
+
+#[test]
+fn arc_shared_mutex_stays_pinned() {
+    let permission = OuterMutexPermission::get();
+    let mutex = Arc::new(ThreadPinnedMutex::new(Vec::new(), unique_type!(), &permission));
+
+    let remote = Arc::clone(&mutex);
+    let refused = thread::spawn(move || {
+        matches!(
+            remote.lock(OuterMutexPermission::get()),
+            Err(PinnedLockError::WrongThread(_))
+        )
+    })
+    .join()
+    .unwrap();
+    assert!(refused);
+
+    let mut guard = mutex.lock(permission).unwrap();
+    guard.push("owner");
+    assert_eq!(*guard, ["owner"]);
+}
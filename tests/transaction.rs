@@ -0,0 +1,126 @@
+use deadlock_proof::{
+    concurrent, LockOutcome, NetworkStack, OuterMutexPermission, StackLayer, TxAborted,
+};
+
+/// Reads (packets_processed, bytes_transmitted, tcp_connections) with a walk.
+fn counters(
+    stack: &NetworkStack,
+    permission: OuterMutexPermission,
+) -> (OuterMutexPermission, (u64, u64, u32)) {
+    let ip = stack.ip_layer.lock(permission).guard();
+    let packets = ip.packets_processed;
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    let bytes = device.bytes_transmitted;
+    let transport = stack
+        .transport_layer
+        .lock(device.unlock_for_sequential())
+        .guard();
+    let connections = transport.tcp_connections;
+    let permission = transport
+        .unlock_for_sequential()
+        .to_earlier()
+        .to_earlier()
+        .to_earlier();
+    (permission, (packets, bytes, connections))
+}
+
+#[test]
+fn commit_applies_every_layer() {
+    let stack = NetworkStack::new();
+    let (permission, result) = stack
+        .transaction()
+        .stage_ip(|ip| {
+            ip.packets_processed += 1;
+            Ok(())
+        })
+        .stage_device(|device| {
+            device.bytes_transmitted += 1500;
+            Ok(())
+        })
+        .stage_transport(|transport| {
+            transport.tcp_connections += 1;
+            Ok(())
+        })
+        .stage_ip(|ip| {
+            // Sees the earlier IP change.
+            assert_eq!(ip.packets_processed, 1);
+            ip.routing_table_size = 4;
+            Ok(())
+        })
+        .commit(OuterMutexPermission::get());
+    assert_eq!(result, Ok(()));
+
+    let (permission, totals) = counters(&stack, permission);
+    assert_eq!(totals, (1, 1500, 1));
+    assert_eq!(
+        stack.ip_layer.lock(permission).guard().routing_table_size,
+        4
+    );
+}
+
+#[test]
+fn middle_layer_rejection_leaves_stack_untouched() {
+    let stack = NetworkStack::new();
+    let (permission, result) = stack
+        .transaction()
+        .stage_ip(|ip| {
+            ip.packets_processed += 1;
+            Ok(())
+        })
+        .stage_device(|device| {
+            device.bytes_transmitted += 1500;
+            match device.interfaces_active {
+                0 => Err("no active interface".to_string()),
+                _ => Ok(()),
+            }
+        })
+        .stage_transport(|_| panic!("transport must not run after a rejection"))
+        .commit(OuterMutexPermission::get());
+
+    assert_eq!(
+        result,
+        Err(TxAborted::Rejected {
+            layer: StackLayer::Device,
+            reason: "no active interface".to_string(),
+        })
+    );
+    let (_permission, totals) = counters(&stack, permission);
+    assert_eq!(totals, (0, 0, 0));
+}
+
+#[test]
+fn concurrent_transactions_all_apply() {
+    const COMMITS: u64 = 500;
+    let stack = NetworkStack::new();
+
+    let workers = (0..2)
+        .map(|_| -> concurrent::Worker<'_> {
+            Box::new(|mut permission| {
+                for _ in 0..COMMITS {
+                    let result;
+                    (permission, result) = stack
+                        .transaction()
+                        .stage_ip(|ip| {
+                            ip.packets_processed += 1;
+                            Ok(())
+                        })
+                        .stage_device(|device| {
+                            device.bytes_transmitted += 1;
+                            Ok(())
+                        })
+                        .stage_transport(|transport| {
+                            transport.tcp_connections += 1;
+                            Ok(())
+                        })
+                        .commit(permission);
+                    result.unwrap();
+                }
+                permission
+            })
+        })
+        .collect();
+    concurrent::run_all(workers).unwrap();
+
+    let (_permission, totals) = counters(&stack, OuterMutexPermission::get());
+    assert_eq!(totals, (2 * COMMITS, 2 * COMMITS, 2 * COMMITS as u32));
+}
@@ -0,0 +1,97 @@
+use std::{sync::Barrier, thread};
+
+#[cfg(not(feature = "no-poison"))]
+use deadlock_proof::unique_type;
+use deadlock_proof::{
+    DeadlockProofMutex, LockOutcome, NestedMutexPermission, OuterMutexPermission, TryLockError,
+};
+
+struct DeviceQueueLock;
+struct StatsLock;
+struct PerQueueLock;
+
+type DeviceQueue = DeadlockProofMutex<Vec<u32>, OuterMutexPermission, DeviceQueueLock>;
+
+#[test]
+fn a_busy_lock_hands_the_permission_back_for_other_work() {
+    let queue: DeviceQueue = DeadlockProofMutex::new(Vec::new(), DeviceQueueLock);
+    let stats: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+        DeadlockProofMutex::new(0, StatsLock);
+    let barrier = Barrier::new(2);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let guard = queue.lock(OuterMutexPermission::get()).guard();
+            barrier.wait();
+            barrier.wait();
+            guard.unlock();
+        });
+        barrier.wait();
+        let permission = match queue.try_lock(OuterMutexPermission::get()) {
+            Err(TryLockError::WouldBlock(permission)) => permission,
+            _ => panic!("the other thread holds the queue"),
+        };
+        // Skip the queue and do something else with the same permission.
+        let mut skipped = stats.lock(permission).guard();
+        *skipped += 1;
+        let permission = skipped.unlock();
+        let permission = match queue.try_lock_for_nested(permission) {
+            Err(error) => error.into_permission(),
+            Ok(_) => panic!("the queue is still held"),
+        };
+        barrier.wait();
+        assert_eq!(*stats.lock(permission).guard(), 1);
+    });
+}
+
+#[test]
+fn try_lock_for_nested_hands_out_the_inner_token() {
+    let queue: DeviceQueue = DeadlockProofMutex::new(vec![1, 2], DeviceQueueLock);
+    let per_queue: DeadlockProofMutex<
+        u32,
+        NestedMutexPermission<OuterMutexPermission, DeviceQueueLock>,
+        _,
+    > = DeadlockProofMutex::new(0, PerQueueLock);
+
+    let (guard, inside) = queue
+        .try_lock_for_nested(OuterMutexPermission::get())
+        .unwrap();
+    assert_eq!(*guard, [1, 2]);
+    let mut counter = per_queue.try_lock(inside).unwrap();
+    *counter += 1;
+    let permission = guard.unlock(counter.unlock());
+    assert!(!queue.is_locked());
+    queue.lock(permission).guard().push(3);
+}
+
+#[cfg(not(feature = "no-poison"))]
+#[test]
+fn a_poisoned_lock_hands_the_permission_back_too() {
+    let poisoned = DeadlockProofMutex::new(0u32, unique_type!());
+    let healthy = DeadlockProofMutex::new(0u32, unique_type!());
+    thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let _guard = poisoned.lock(OuterMutexPermission::get());
+                panic!("poisoning on purpose");
+            })
+            .join()
+            .unwrap_err();
+    });
+
+    let permission = match poisoned.try_lock(OuterMutexPermission::get()) {
+        Err(TryLockError::Poisoned(permission)) => permission,
+        _ => panic!("the mutex is poisoned"),
+    };
+    let permission = match poisoned.try_lock_for_nested(permission) {
+        Err(TryLockError::Poisoned(permission)) => permission,
+        _ => panic!("the mutex is poisoned"),
+    };
+    // No guard came back, so the mutex was left unlocked.
+    assert!(!poisoned.is_locked());
+    let permission = healthy.lock(permission).guard().unlock();
+
+    // The data is still there for a blocking lock.
+    let recovered = poisoned.lock(permission).err().unwrap().into_guard();
+    assert_eq!(*recovered, 0);
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use deadlock_proof::{unique_type, DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+
+#[derive(Debug, Default, PartialEq)]
+struct TcpConnState {
+    bytes: u64,
+}
+
+fn connections() -> HashMap<u16, TcpConnState> {
+    HashMap::from([(80, TcpConnState { bytes: 10 })])
+}
+
+#[test]
+fn hit_maps_to_the_entry() {
+    let mutex = DeadlockProofMutex::new(connections(), unique_type!());
+    let guard = mutex.lock(OuterMutexPermission::get()).guard();
+
+    let mut entry = guard
+        .try_map_with(80, |map, port| map.get_mut(port))
+        .unwrap_or_else(|_| panic!("port 80 should be present"));
+    entry.bytes += 5;
+    let permission = entry.unlock();
+
+    let guard = mutex.lock(permission).guard();
+    assert_eq!(guard[&80], TcpConnState { bytes: 15 });
+}
+
+#[test]
+fn miss_returns_guard_and_key_for_insert_then_retry() {
+    let mutex = DeadlockProofMutex::new(connections(), unique_type!());
+    let guard = mutex.lock(OuterMutexPermission::get()).guard();
+
+    let (mut guard, port) = match guard.try_map_with(443, |map, port| map.get_mut(port)) {
+        Ok(_) => panic!("port 443 should be missing"),
+        Err(returned) => returned,
+    };
+    assert_eq!(port, 443);
+    guard.insert(port, TcpConnState::default());
+
+    let mut entry = guard
+        .try_map_with(port, |map, port| map.get_mut(port))
+        .unwrap_or_else(|_| panic!("port 443 was just inserted"));
+    entry.bytes = 1;
+    let permission = entry.unlock();
+
+    let guard = mutex.lock(permission).guard();
+    assert_eq!(guard.len(), 2);
+    assert_eq!(guard[&443], TcpConnState { bytes: 1 });
+}
+
+#[test]
+fn mapped_guard_keeps_the_lock() {
+    let mutex = DeadlockProofMutex::new(connections(), unique_type!());
+    let guard = mutex.lock(OuterMutexPermission::get()).guard();
+    let entry = guard.map(|map| map.get_mut(&80).unwrap());
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let permission = OuterMutexPermission::get();
+            assert!(
+                matches!(mutex.lock(permission).into_result(), Ok(guard) if guard[&80].bytes == 20)
+            );
+        });
+        let mut entry = entry;
+        entry.bytes = 20;
+        entry.unlock();
+    });
+}
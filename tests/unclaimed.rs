@@ -0,0 +1,96 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{Mutex, PoisonError},
+    thread,
+};
+
+use deadlock_proof::{
+    permission, unclaimed, ClaimError, DeadlockProofMutex, LockOutcome, OuterMutexPermission,
+    Policy,
+};
+
+struct CounterLock;
+struct OtherLock;
+
+type Counter = DeadlockProofMutex<u32, OuterMutexPermission, CounterLock>;
+
+/// The policy is process-wide, so these tests run one at a time, each on a
+/// fresh thread that never claimed its token.
+fn with_policy(policy: Policy, body: impl FnOnce() + Send) {
+    static SERIAL: Mutex<()> = Mutex::new(());
+    let _serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+    unclaimed::set_policy(policy);
+    let result = thread::scope(|scope| scope.spawn(body).join());
+    unclaimed::set_policy(Policy::default());
+    result.unwrap();
+}
+
+#[test]
+fn auto_claim_lends_the_token_for_the_length_of_the_guard() {
+    let counter = Counter::new(0, CounterLock);
+    with_policy(Policy::AutoClaim, || {
+        let mut guard = counter.lock_without_permission().unwrap().guard();
+        *guard += 1;
+        let state = permission::thread_debug_state();
+        if cfg!(debug_assertions) {
+            assert!(state.implicit);
+            assert_eq!(state.claimed_at.unwrap().file(), file!());
+        }
+        assert!(OuterMutexPermission::try_get().is_none());
+        guard.unlock();
+
+        // Given back: the thread can claim it for itself now.
+        let permission = OuterMutexPermission::get();
+        assert!(!permission::thread_debug_state().implicit);
+        assert_eq!(*counter.lock(permission).guard(), 1);
+    });
+}
+
+#[test]
+fn error_policy_reports_the_unclaimed_thread() {
+    let counter = Counter::new(0, CounterLock);
+    with_policy(Policy::Error, || {
+        let Err(ClaimError::Unclaimed { attempted_at }) = counter.lock_without_permission() else {
+            panic!("an unclaimed thread was let through");
+        };
+        assert_eq!(attempted_at.file(), file!());
+
+        // Once the thread claimed its token, it cannot lend it out again.
+        let _permission = OuterMutexPermission::get();
+        assert!(matches!(
+            counter.lock_without_permission(),
+            Err(ClaimError::Refused(_))
+        ));
+    });
+}
+
+#[test]
+fn panic_policy_panics_but_lock_autoclaim_still_claims() {
+    let counter = Counter::new(0, CounterLock);
+    with_policy(Policy::Panic, || {
+        let locked = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _ = counter.lock_without_permission();
+        }));
+        assert!(locked.is_err());
+
+        *counter.lock_autoclaim().unwrap().guard() += 1;
+        assert_eq!(*counter.lock(OuterMutexPermission::get()).guard(), 1);
+    });
+}
+
+#[test]
+fn a_held_implicit_claim_refuses_a_second_one() {
+    let counter = Counter::new(0, CounterLock);
+    let other: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+        DeadlockProofMutex::new(0, OtherLock);
+    with_policy(Policy::AutoClaim, || {
+        let _guard = counter.lock_without_permission().unwrap().guard();
+        let Err(ClaimError::Refused(diagnostics)) = other.lock_without_permission() else {
+            panic!("a second root was handed out");
+        };
+        assert!(!diagnostics.blocked_by_phase);
+        if cfg!(debug_assertions) {
+            assert!(diagnostics.to_string().contains("implicitly claimed at"));
+        }
+    });
+}
@@ -0,0 +1,96 @@
+use deadlock_proof::{unique_type, DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+
+/// The pre-refactor device state.
+#[derive(Debug, PartialEq)]
+struct LegacyDevice {
+    bytes_transmitted: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum Device {
+    Legacy(LegacyDevice),
+    Virtual { queues: u8 },
+}
+
+fn legacy(device: &mut Device) -> Option<&mut LegacyDevice> {
+    match device {
+        Device::Legacy(state) => Some(state),
+        Device::Virtual { .. } => None,
+    }
+}
+
+/// A helper written against the old struct.
+fn transmit(state: &mut LegacyDevice, bytes: u64) {
+    state.bytes_transmitted += bytes;
+}
+
+#[test]
+fn matching_variant_is_projected() {
+    let mutex = DeadlockProofMutex::new(
+        Device::Legacy(LegacyDevice {
+            bytes_transmitted: 0,
+        }),
+        unique_type!(),
+    );
+    let guard = mutex.lock(OuterMutexPermission::get()).guard();
+    #[cfg(feature = "tracking")]
+    let before = mutex.version();
+
+    let mut variant = guard
+        .try_into_variant(legacy)
+        .unwrap_or_else(|_| panic!("the device is legacy"));
+    transmit(&mut variant, 64);
+    let permission = variant.unlock();
+
+    #[cfg(feature = "tracking")]
+    assert!(mutex.version() > before);
+    let guard = mutex.lock(permission).guard();
+    assert_eq!(
+        *guard,
+        Device::Legacy(LegacyDevice {
+            bytes_transmitted: 64
+        })
+    );
+}
+
+#[test]
+fn other_variant_hands_the_guard_back() {
+    let mutex = DeadlockProofMutex::new(Device::Virtual { queues: 4 }, unique_type!());
+    let guard = mutex.lock(OuterMutexPermission::get()).guard();
+    #[cfg(feature = "tracking")]
+    let before = mutex.version();
+
+    let guard = match guard.try_into_variant(legacy) {
+        Ok(_) => panic!("the device is virtual"),
+        Err(guard) => guard,
+    };
+    assert_eq!(*guard, Device::Virtual { queues: 4 });
+    guard.unlock();
+    #[cfg(feature = "tracking")]
+    assert_eq!(mutex.version(), before);
+}
+
+#[test]
+fn round_trip_back_to_the_original_guard() {
+    let mutex = DeadlockProofMutex::new(
+        Device::Legacy(LegacyDevice {
+            bytes_transmitted: 10,
+        }),
+        unique_type!(),
+    );
+    let guard = mutex.lock(OuterMutexPermission::get()).guard();
+
+    let mut variant = guard
+        .try_into_variant(legacy)
+        .unwrap_or_else(|_| panic!("the device is legacy"));
+    transmit(&mut variant, 5);
+    let mut guard = variant.into_original();
+    assert!(mutex.is_locked());
+    // With the full guard back, the variant itself can change.
+    *guard = Device::Virtual { queues: 1 };
+    let permission = guard.unlock();
+
+    assert!(!mutex.is_locked());
+    let guard = mutex.lock(permission).guard();
+    assert_eq!(*guard, Device::Virtual { queues: 1 });
+}
@@ -0,0 +1,135 @@
+#![cfg(feature = "tracking")]
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use deadlock_proof::{
+    unique_type, DeadlockProofMutex, LockOutcome, NetworkStack, OuterMutexPermission,
+};
+
+#[test]
+fn read_only_guards_leave_the_version_alone() {
+    let mutex = DeadlockProofMutex::new(5u32, unique_type!());
+    let guard = mutex.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(*guard, 5);
+    let permission = guard.unlock();
+    assert_eq!(mutex.version(), 0);
+
+    let (guard, nested) = mutex.lock_for_nested(permission).guard();
+    assert_eq!(*guard, 5);
+    guard.unlock(nested);
+    assert_eq!(mutex.version(), 0);
+}
+
+#[test]
+fn mutable_access_bumps_the_version_once_per_guard() {
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    let mut guard = mutex.lock(OuterMutexPermission::get()).guard();
+    *guard.get_mut() += 1;
+    *guard += 1;
+    // Not bumped until the guard is released.
+    assert_eq!(mutex.version(), 0);
+    let permission = guard.unlock();
+    assert_eq!(mutex.version(), 1);
+
+    let (mut guard, nested) = mutex.lock_for_nested(permission).guard();
+    *guard.get_mut() += 1;
+    guard.unlock(nested);
+    assert_eq!(mutex.version(), 2);
+}
+
+#[test]
+fn mapped_guards_count_only_when_written() {
+    let mutex = DeadlockProofMutex::new(HashMap::from([(1u8, 10u32)]), unique_type!());
+    let mapped = mutex
+        .lock(OuterMutexPermission::get())
+        .guard()
+        .map(|map| map.get_mut(&1).unwrap());
+    assert_eq!(*mapped, 10);
+    let permission = mapped.unlock();
+    assert_eq!(mutex.version(), 0);
+
+    let mut mapped = mutex
+        .lock(permission)
+        .guard()
+        .map(|map| map.get_mut(&1).unwrap());
+    *mapped = 11;
+    mapped.unlock();
+    assert_eq!(mutex.version(), 1);
+}
+
+#[test]
+fn listeners_fire_after_release() {
+    let mutex = Arc::new(DeadlockProofMutex::new(0u32, unique_type!()));
+    let seen = Arc::new(AtomicU64::new(0));
+    {
+        let weak = Arc::downgrade(&mutex);
+        let seen = Arc::clone(&seen);
+        mutex.on_change(move |version| {
+            let mutex = weak.upgrade().unwrap();
+            assert!(!mutex.is_locked(), "listener runs once unlocked");
+            seen.store(version, Ordering::Relaxed);
+        });
+    }
+
+    let mut permission = OuterMutexPermission::get();
+    for expected in 1..=3 {
+        let mut guard = mutex.lock(permission).guard();
+        *guard.get_mut() += 1;
+        permission = guard.unlock();
+        assert_eq!(seen.load(Ordering::Relaxed), expected);
+    }
+    let guard = mutex.lock(permission).guard();
+    let _ = *guard;
+    guard.unlock();
+    assert_eq!(seen.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn stack_paths_that_write_bump_their_layers() {
+    let stack = NetworkStack::new();
+    let (permission, result) = stack
+        .transaction()
+        .stage_device(|device| {
+            device.interfaces_active += 1;
+            Ok(())
+        })
+        .commit(OuterMutexPermission::get());
+    result.unwrap();
+    assert_eq!(stack.ip_layer.version(), 0);
+    assert_eq!(stack.device_layer.version(), 1);
+    assert_eq!(stack.transport_layer.version(), 0);
+
+    let (permission, result) = stack
+        .transaction()
+        .stage_ip(|_| Err("rejected".into()))
+        .commit(permission);
+    assert!(result.is_err());
+    assert_eq!(stack.ip_layer.version(), 0);
+
+    let carry = stack
+        .ip_layer
+        .lock(permission)
+        .guard()
+        .unlock_for_sequential_with(());
+    let _carry = stack
+        .device_layer
+        .lock_with_carry(carry, |device, ()| device.bytes_transmitted += 1)
+        .guard();
+    assert_eq!(stack.ip_layer.version(), 0);
+    assert_eq!(stack.device_layer.version(), 2);
+}
+
+#[test]
+fn rcu_swaps_bump_but_reads_do_not() {
+    let mutex = DeadlockProofMutex::new(Arc::new(1u32), unique_type!());
+    let (permission, _) = mutex.clone_inner(OuterMutexPermission::get());
+    assert_eq!(mutex.version(), 0);
+    let (_permission, _) = mutex.swap_inner(permission, Arc::new(2));
+    assert_eq!(mutex.version(), 1);
+}
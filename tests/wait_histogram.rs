@@ -0,0 +1,103 @@
+#![cfg(feature = "metrics")]
+
+use std::{sync::Barrier, thread, time::Duration};
+
+use deadlock_proof::{
+    metrics::Histogram, unique_type, DeadlockProofMutex, LockOutcome, OuterMutexPermission,
+};
+
+#[test]
+fn uncontended_locks_land_in_low_buckets() {
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    let mut permission = OuterMutexPermission::get();
+    for _ in 0..10 {
+        permission = mutex.lock(permission).guard().unlock();
+    }
+
+    let histogram = mutex.wait_histogram();
+    assert_eq!(histogram.count(), 10);
+    // Far below a millisecond.
+    assert!(histogram.buckets()[20..].iter().all(|&count| count == 0));
+}
+
+#[test]
+fn contended_waits_land_in_higher_buckets() {
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    let barrier = Barrier::new(2);
+
+    thread::scope(|scope| {
+        let guard = mutex.lock(OuterMutexPermission::get()).guard();
+        scope.spawn(|| {
+            let permission = OuterMutexPermission::get();
+            barrier.wait();
+            mutex.lock(permission).guard().unlock();
+        });
+        barrier.wait();
+        thread::sleep(Duration::from_millis(20));
+        guard.unlock();
+    });
+
+    let histogram = mutex.wait_histogram();
+    assert_eq!(histogram.count(), 2);
+    // The waiter blocked for roughly 20ms, i.e. at least 2^23ns.
+    let slow: u64 = histogram.buckets()[23..].iter().sum();
+    assert_eq!(slow, 1);
+    assert!(Histogram::bucket_floor(23) >= Duration::from_millis(8));
+}
+
+#[cfg(feature = "metrics-exporter")]
+mod exporter {
+    use std::sync::Mutex;
+
+    use deadlock_proof::{
+        metrics::{set_recorder, Recorder, WAIT_HISTOGRAM},
+        DeadlockProofMutex, LockOutcome, NetworkStack, OuterMutexPermission,
+    };
+
+    struct ExporterLock;
+
+    #[derive(Default)]
+    struct Captured {
+        registered: Mutex<Vec<(&'static str, &'static str)>>,
+        recorded: Mutex<Vec<&'static str>>,
+    }
+
+    impl Recorder for Captured {
+        fn register_histogram(&self, name: &'static str, mutex: &'static str) {
+            self.registered.lock().unwrap().push((name, mutex));
+        }
+
+        fn record_histogram(&self, name: &'static str, mutex: &'static str, _nanos: u64) {
+            assert_eq!(name, WAIT_HISTOGRAM);
+            self.recorded.lock().unwrap().push(mutex);
+        }
+    }
+
+    #[test]
+    fn registers_one_histogram_per_mutex() {
+        let captured: &'static Captured = Box::leak(Box::default());
+        assert!(set_recorder(captured).is_ok());
+
+        let _stack = NetworkStack::new();
+        let standalone = DeadlockProofMutex::new((), ExporterLock);
+        let permission = standalone
+            .lock(OuterMutexPermission::get())
+            .guard()
+            .unlock();
+        let named = DeadlockProofMutex::new_named((), ExporterLock, "exporter-queue");
+        named.lock(permission).guard().unlock();
+
+        let registered = captured.registered.lock().unwrap();
+        let names: Vec<_> = registered.iter().map(|(_, mutex)| *mutex).collect();
+        assert!(registered.iter().all(|(name, _)| *name == WAIT_HISTOGRAM));
+        for layer in ["ip-layer", "device-layer", "transport-layer"] {
+            assert!(names.contains(&layer), "{names:?}");
+        }
+        assert!(names.contains(&std::any::type_name::<ExporterLock>()));
+        assert!(names.contains(&"exporter-queue"));
+        let recorded = captured.recorded.lock().unwrap();
+        let waits = |label| recorded.iter().filter(|&&mutex| mutex == label).count();
+        assert_eq!(waits(std::any::type_name::<ExporterLock>()), 1);
+        assert_eq!(waits("exporter-queue"), 1);
+    }
+}
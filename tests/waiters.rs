@@ -0,0 +1,69 @@
+#![cfg(feature = "tracking")]
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use deadlock_proof::{unique_type, DeadlockProofMutex, LockOutcome, OuterMutexPermission};
+
+fn wait_until(condition: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if condition() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    false
+}
+
+#[test]
+fn uncontended_mutex_has_no_waiters() {
+    let mutex = DeadlockProofMutex::new(0u32, unique_type!());
+    assert_eq!(mutex.waiters(), 0);
+
+    let guard = mutex.lock(OuterMutexPermission::get()).guard();
+    assert_eq!(mutex.waiters(), 0);
+    assert!(!mutex.is_contended());
+    guard.unlock();
+}
+
+#[test]
+fn threads_piling_onto_a_held_lock_are_counted() {
+    const THREADS: usize = 4;
+    let mutex = DeadlockProofMutex::new(0usize, unique_type!());
+
+    let permission = thread::scope(|scope| {
+        let guard = mutex.lock(OuterMutexPermission::get()).guard();
+        for _ in 0..THREADS {
+            scope.spawn(|| {
+                let mut guard = mutex.lock(OuterMutexPermission::get()).guard();
+                *guard += 1;
+            });
+        }
+        assert!(wait_until(|| mutex.waiters() == THREADS));
+        assert!(mutex.is_contended());
+        guard.unlock()
+    });
+
+    assert_eq!(mutex.waiters(), 0);
+    assert_eq!(*mutex.lock(permission).guard(), THREADS);
+}
+
+#[test]
+fn nested_acquisitions_are_counted_too() {
+    let mutex = DeadlockProofMutex::new((), unique_type!());
+
+    thread::scope(|scope| {
+        let (guard, nested) = mutex.lock_for_nested(OuterMutexPermission::get()).guard();
+        scope.spawn(|| {
+            let (guard, nested) = mutex.lock_for_nested(OuterMutexPermission::get()).guard();
+            guard.unlock(nested);
+        });
+        assert!(wait_until(|| mutex.waiters() == 1));
+        guard.unlock(nested);
+    });
+
+    assert!(!mutex.is_contended());
+}
@@ -0,0 +1,69 @@
+use std::{net::Ipv4Addr, panic};
+
+use deadlock_proof::{
+    IcmpError, LockOutcome, NetworkStack, OuterMutexPermission, Route, WalkPermission,
+};
+
+#[test]
+fn manual_steps_and_helpers_share_one_walk() {
+    let stack = NetworkStack::new();
+    let mut walk = stack.begin_walk(OuterMutexPermission::get());
+    walk.add_route(Route {
+        destination: Ipv4Addr::new(10, 0, 0, 0),
+        prefix_len: 8,
+        next_hop: Ipv4Addr::new(192, 168, 0, 1),
+    })
+    .unwrap();
+
+    let bytes = walk.with_permission(|permission| {
+        let mut ip = stack.ip_layer.lock_in_context(permission).guard();
+        ip.packets_processed += 1;
+        let mut device = stack
+            .device_layer
+            .lock_in_context(ip.unlock_for_sequential())
+            .guard();
+        device.bytes_transmitted += 64;
+        let bytes = device.bytes_transmitted;
+        (
+            device.unlock_for_sequential().to_earlier().to_earlier(),
+            bytes,
+        )
+    });
+    assert_eq!(bytes, 64);
+
+    walk.icmp_error_path(IcmpError::TimeExceeded);
+    assert_eq!(
+        walk.cached_route_lookup(Ipv4Addr::new(10, 9, 9, 9)),
+        Some(Ipv4Addr::new(192, 168, 0, 1))
+    );
+    assert!(std::ptr::eq(walk.stack(), &stack));
+
+    let ip = stack.ip_layer.lock(walk.finish()).guard();
+    assert_eq!(ip.packets_processed, 1);
+    assert_eq!(ip.icmp_errors_sent, 1);
+    assert_eq!(ip.routing_table_size, 1);
+}
+
+#[test]
+#[allow(deprecated)]
+fn permission_taking_shims_still_work() {
+    let stack = NetworkStack::new();
+    let mut walk = stack.begin_walk(OuterMutexPermission::get());
+    walk.icmp_error_path(IcmpError::HostUnreachable);
+    let permission = walk.finish();
+    let (permission, hop) = stack.cached_route_lookup(permission, Ipv4Addr::LOCALHOST);
+    assert_eq!(hop, None);
+    let (permission, fork) = stack.fork::<deadlock_proof::RootNamespace>(permission);
+    assert_eq!(fork.ip_layer.lock(permission).guard().icmp_errors_sent, 1);
+}
+
+#[test]
+fn a_panicking_step_loses_the_permission() {
+    let stack = NetworkStack::new();
+    let mut walk = stack.begin_walk(OuterMutexPermission::get());
+    let step = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        walk.with_permission(|_permission| -> (WalkPermission, ()) { panic!("step failed") })
+    }));
+    assert!(step.is_err());
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| walk.finish())).is_err());
+}
@@ -0,0 +1,109 @@
+use std::{
+    sync::Barrier,
+    thread,
+    time::{Duration, Instant},
+};
+
+use deadlock_proof::{
+    budget::{Exhausted, WalkAbandoned, WalkBudget},
+    network_stack::LOCK_ORDER,
+    LockOutcome, NetworkStack, OuterMutexPermission,
+};
+
+const GENEROUS: WalkBudget = WalkBudget {
+    overall: Duration::from_secs(10),
+    per_layer: [Duration::from_secs(5); 3],
+};
+
+#[test]
+fn an_uncontended_walk_visits_every_layer() {
+    let stack = NetworkStack::new();
+    let (permission, outcome) = stack.process_packet_with_budget(
+        OuterMutexPermission::get(),
+        GENEROUS,
+        |ip| ip.packets_processed += 1,
+        |device| device.bytes_transmitted += 64,
+        |transport| transport.udp_sockets += 1,
+    );
+
+    assert!(outcome.is_complete());
+    let layers: Vec<_> = outcome.layers.iter().map(|timing| timing.layer).collect();
+    assert_eq!(layers, LOCK_ORDER);
+    let ip = stack.ip_layer.lock(permission).guard();
+    let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+    assert_eq!(device.bytes_transmitted, 64);
+}
+
+#[test]
+fn a_held_middle_layer_abandons_the_walk_there() {
+    let stack = NetworkStack::new();
+    let barrier = Barrier::new(2);
+    let budget = WalkBudget {
+        per_layer: [
+            Duration::from_secs(5),
+            Duration::from_millis(5),
+            Duration::from_secs(5),
+        ],
+        ..GENEROUS
+    };
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let ip = stack.ip_layer.lock(OuterMutexPermission::get()).guard();
+            let device = stack.device_layer.lock(ip.unlock_for_sequential()).guard();
+            barrier.wait();
+            barrier.wait();
+            device.unlock();
+        });
+        barrier.wait();
+        let started = Instant::now();
+        let mut transport_ran = false;
+        let (permission, outcome) = stack.process_packet_with_budget(
+            OuterMutexPermission::get(),
+            budget,
+            |ip| ip.packets_processed += 1,
+            |_| unreachable!("the device layer is held"),
+            |_| transport_ran = true,
+        );
+        barrier.wait();
+
+        assert!(started.elapsed() >= Duration::from_millis(5));
+        assert_eq!(
+            outcome.abandoned,
+            Some(WalkAbandoned {
+                layer: "DeviceLock",
+                exhausted: Exhausted::Layer,
+            })
+        );
+        assert_eq!(outcome.layers.len(), 1);
+        assert!(!transport_ran);
+        // The walk was unwound to the root, so the permission locks again.
+        let ip = stack.ip_layer.lock(permission).guard();
+        assert_eq!(ip.packets_processed, 1);
+    });
+}
+
+#[test]
+fn a_slow_layer_uses_up_the_overall_budget() {
+    let stack = NetworkStack::new();
+    let budget = WalkBudget {
+        overall: Duration::from_millis(5),
+        ..GENEROUS
+    };
+    let (_permission, outcome) = stack.process_packet_with_budget(
+        OuterMutexPermission::get(),
+        budget,
+        |_| thread::sleep(Duration::from_millis(10)),
+        |_| unreachable!("the budget ran out at the IP layer"),
+        |_| {},
+    );
+
+    assert_eq!(
+        outcome.abandoned,
+        Some(WalkAbandoned {
+            layer: "DeviceLock",
+            exhausted: Exhausted::Overall,
+        })
+    );
+    assert!(outcome.layers[0].held >= Duration::from_millis(10));
+}
@@ -0,0 +1,101 @@
+use deadlock_proof::{LockOutcome, NetworkStack, OuterMutexPermission, WalkCache, WalkPermission};
+
+#[derive(Debug, PartialEq)]
+struct EffectiveMtu(u64);
+
+/// A made-up derivation from the IP and device layers, standing in for one
+/// that is too costly to repeat.
+fn effective_mtu(
+    stack: &NetworkStack,
+    permission: WalkPermission,
+) -> (WalkPermission, EffectiveMtu) {
+    let ip = stack.ip_layer.lock_in_context(permission).guard();
+    let routes = ip.routing_table_size as u64;
+    let device = stack
+        .device_layer
+        .lock_in_context(ip.unlock_for_sequential())
+        .guard();
+    let mtu = EffectiveMtu(1500 - 20 * routes + u64::from(device.interfaces_active));
+    (
+        device.unlock_for_sequential().to_earlier().to_earlier(),
+        mtu,
+    )
+}
+
+#[test]
+fn later_step_reads_what_an_earlier_one_put() {
+    let stack = NetworkStack::new();
+    let mut walk = stack.begin_walk(OuterMutexPermission::get());
+    walk.with_permission(|permission| {
+        let ip = stack.ip_layer.lock_in_context(permission).guard();
+        let mut device = stack
+            .device_layer
+            .lock_in_context(ip.unlock_for_sequential())
+            .guard();
+        device.interfaces_active = 2;
+        (device.unlock_for_sequential().to_earlier().to_earlier(), ())
+    });
+
+    walk.with_permission_and_cache(|permission, cache| {
+        let (permission, mtu) = effective_mtu(&stack, permission);
+        assert_eq!(cache.put(mtu), None);
+        (permission, ())
+    });
+    let segments = walk.with_permission_and_cache(|permission, cache| {
+        let mtu = cache.get::<EffectiveMtu>().expect("put by the first step");
+        let ip = stack.ip_layer.lock_in_context(permission).guard();
+        let device = stack
+            .device_layer
+            .lock_in_context(ip.unlock_for_sequential())
+            .guard();
+        let transport = stack
+            .transport_layer
+            .lock_in_context(device.unlock_for_sequential())
+            .guard();
+        let segments = mtu.0 * u64::from(transport.tcp_connections + 1);
+        let permission = transport.unlock_for_sequential();
+        (permission.to_earlier().to_earlier().to_earlier(), segments)
+    });
+
+    assert_eq!(segments, 1502);
+    assert_eq!(walk.cache().get(), Some(&EffectiveMtu(1502)));
+    walk.finish();
+}
+
+#[test]
+fn values_do_not_outlive_the_walk() {
+    let stack = NetworkStack::new();
+    let mut walk = stack.begin_walk(OuterMutexPermission::get());
+    walk.cache_mut().put(EffectiveMtu(9000));
+    let permission = walk.finish();
+
+    let walk = stack.begin_walk(permission);
+    assert!(walk.cache().is_empty());
+    assert_eq!(walk.cache().get::<EffectiveMtu>(), None);
+    walk.finish();
+}
+
+#[test]
+fn one_value_per_type() {
+    struct Hops(u8);
+
+    let mut cache = WalkCache::default();
+    assert_eq!(cache.put(EffectiveMtu(1500)), None);
+    assert_eq!(cache.put(EffectiveMtu(1400)), Some(EffectiveMtu(1500)));
+    cache.put(Hops(3));
+
+    let mut computed = 0;
+    let mut compute = || {
+        computed += 1;
+        Hops(9)
+    };
+    cache.get_or_insert_with(&mut compute).0 += 1;
+    assert_eq!(cache.get::<Hops>().map(|hops| hops.0), Some(4));
+    cache.remove::<Hops>();
+    assert_eq!(cache.get_or_insert_with(&mut compute).0, 9);
+    assert_eq!(computed, 1);
+
+    cache.get_mut::<EffectiveMtu>().unwrap().0 -= 100;
+    assert_eq!(cache.remove(), Some(EffectiveMtu(1300)));
+    assert!(cache.get::<EffectiveMtu>().is_none());
+}
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+#[cfg(feature = "tracking")]
+use std::{sync::mpsc, thread};
+
+use deadlock_proof::{
+    DeadlockProofMutex, LockedOrGone, OuterMutexPermission, WeakDeadlockProofMutex,
+};
+
+struct FilterLock;
+
+type Filter = DeadlockProofMutex<Vec<&'static str>, OuterMutexPermission, FilterLock>;
+
+#[test]
+fn an_alive_filter_is_locked_and_the_guard_keeps_it_alive() {
+    let filter: Arc<Filter> = Arc::new(DeadlockProofMutex::new(vec!["allow"], FilterLock));
+    let weak = filter.downgrade_proof();
+    assert!(weak.is_alive());
+
+    let LockedOrGone::Locked(mut rules) = weak.lock_if_alive(OuterMutexPermission::get()) else {
+        panic!("the filter is alive");
+    };
+    rules.push("drop");
+    // Disabling the filter while it is locked leaves the guard valid.
+    drop(filter);
+    assert!(weak.is_alive());
+    assert_eq!(*rules, ["allow", "drop"]);
+    let permission = rules.unlock();
+    assert!(!weak.is_alive());
+    assert!(weak.upgrade().is_none());
+    assert!(matches!(
+        weak.lock_if_alive(permission),
+        LockedOrGone::Gone(_)
+    ));
+}
+
+#[test]
+fn a_filter_dropped_before_the_call_hands_the_permission_back() {
+    struct CountersLock;
+    let weak: WeakDeadlockProofMutex<_, _, _> =
+        Arc::new(Filter::new(Vec::new(), FilterLock)).downgrade_proof();
+    let counters: DeadlockProofMutex<u32, OuterMutexPermission, _> =
+        DeadlockProofMutex::new(0, CountersLock);
+
+    let LockedOrGone::Gone(permission) = weak.lock_if_alive(OuterMutexPermission::get()) else {
+        panic!("the filter was dropped");
+    };
+    // The permission still locks everything else.
+    let mut dropped = counters.lock_or_return(permission).ok().unwrap();
+    *dropped += 1;
+    let permission = dropped.unlock();
+    assert!(matches!(
+        weak.lock_if_alive(permission),
+        LockedOrGone::Gone(_)
+    ));
+}
+
+#[cfg(feature = "tracking")]
+#[test]
+fn a_waiter_keeps_the_filter_its_holder_drops() {
+    let (send_weak, weak) = mpsc::channel();
+    let holder = thread::spawn(move || {
+        let (filter, mut guard) =
+            DeadlockProofMutex::new_locked(vec!["allow"], FilterLock, OuterMutexPermission::get());
+        send_weak.send(filter.downgrade_proof()).unwrap();
+        // Disable the filter once the other thread is waiting for it.
+        while filter.waiters() == 0 {
+            thread::yield_now();
+        }
+        guard.push("disabled");
+        drop(filter);
+        guard.unlock();
+    });
+    let weak: WeakDeadlockProofMutex<_, OuterMutexPermission, FilterLock> = weak.recv().unwrap();
+
+    let LockedOrGone::Locked(rules) = weak.lock_if_alive(OuterMutexPermission::get()) else {
+        panic!("the filter was alive when the wait began");
+    };
+    holder.join().unwrap();
+    assert_eq!(*rules, ["allow", "disabled"]);
+    assert_eq!(Arc::strong_count(rules.mutex()), 1);
+    let permission = rules.unlock();
+    assert!(matches!(
+        weak.lock_if_alive(permission),
+        LockedOrGone::Gone(_)
+    ));
+}